@@ -0,0 +1,407 @@
+//! Writing a snapshot out as a tar or zip file.
+//!
+//! An archive holds the files of a single commit, without any history, which
+//! is handy for releases and for handing the code to someone who doesn't use
+//! rat. Everything comes straight out of the object store, so the working
+//! directory is never touched, and every file gets the time of the commit, so
+//! archiving the same commit twice gives exactly the same file.
+//!
+//! There are three formats:
+//!
+//! - **tar**, in the POSIX format, with an extended header for any path too
+//!   long for the ordinary one.
+//! - **tar.gz**, which is the same thing compressed with gzip.
+//! - **zip**, where each file is compressed on its own.
+//!
+//! Both compress with [`deflate`](crate::deflate).
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::Path;
+
+use crate::compare::{Entry, FileMode};
+use crate::objects::{self, ObjectKind};
+use crate::{deflate, lfs, resolve, utils};
+
+/// The kinds of archive we can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Reads a format given by name, like `tar.gz`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tar" => Some(Self::Tar),
+            "tar.gz" | "tgz" => Some(Self::TarGz),
+            "zip" => Some(Self::Zip),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from the name of the file it's going to, which is
+    /// tar unless the name says otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        let name = path.to_string_lossy().to_ascii_lowercase();
+
+        if name.ends_with(".zip") {
+            Self::Zip
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Self::TarGz
+        } else {
+            Self::Tar
+        }
+    }
+}
+
+/// A file or directory going into an archive.
+struct Member {
+    /// The path inside the archive, with the prefix already added. Directories
+    /// end with a `/`.
+    path: String,
+    /// What the member is, or `None` for a directory.
+    entry: Option<Entry>,
+    data: Vec<u8>,
+}
+
+/// Builds an archive of the snapshot of `revision` in `format`, with `prefix`
+/// in front of every path, returning the archive and how many files are in
+/// it.
+///
+/// Just like git, the prefix is added exactly as it is, so `project/` puts
+/// everything in a `project` directory, while `project-` just starts every
+/// name with it.
+pub fn create(
+    revision: &str,
+    format: ArchiveFormat,
+    prefix: &str,
+) -> Result<(Vec<u8>, usize), Box<dyn Error>> {
+    let commit = objects::read_commit(&resolve::resolve_revision(revision)?)?;
+    let snapshot = objects::read_tree(&commit.tree)?;
+    let timestamp = commit.committer.timestamp;
+
+    let mut members = Vec::new();
+    let mut directories = BTreeSet::new();
+
+    // The prefix itself is a directory if it ends like one.
+    if let Some(directory) = prefix.strip_suffix('/') {
+        add_directories(directory, &mut directories, &mut members);
+    }
+
+    for (path, entry) in &snapshot {
+        // Every directory comes before anything in it, which some tools need.
+        if let Some((directory, _)) = format!("{prefix}{path}").rsplit_once('/') {
+            add_directories(directory, &mut directories, &mut members);
+        }
+
+        // Another nest's files aren't ours to archive, so it's left as an
+        // empty directory, just like it's checked out.
+        if entry.mode == FileMode::Nest {
+            add_directories(&format!("{prefix}{path}"), &mut directories, &mut members);
+            continue;
+        }
+
+        let data = objects::read_object_of_kind(&entry.hash, ObjectKind::Blob)?;
+
+        // A file kept out of the nest is archived with its real contents, just
+        // like it would be checked out, as long as we have them.
+        let data = match lfs::Pointer::parse(&data).filter(lfs::Pointer::has_content) {
+            Some(pointer) => std::fs::read(pointer.content_path())?,
+            None => data,
+        };
+
+        members.push(Member {
+            path: format!("{prefix}{path}"),
+            entry: Some(entry.clone()),
+            data,
+        });
+    }
+
+    let archive = match format {
+        ArchiveFormat::Tar => write_tar(&members, timestamp),
+        ArchiveFormat::TarGz => gzip(&write_tar(&members, timestamp), timestamp),
+        ArchiveFormat::Zip => write_zip(&members, timestamp)?,
+    };
+
+    Ok((archive, snapshot.len()))
+}
+
+/// Adds a member for `directory` and each of its parents that doesn't have
+/// one yet.
+fn add_directories(directory: &str, seen: &mut BTreeSet<String>, members: &mut Vec<Member>) {
+    let mut path = String::new();
+
+    for part in directory.split('/').filter(|part| !part.is_empty()) {
+        path.push_str(part);
+        path.push('/');
+
+        if seen.insert(path.clone()) {
+            members.push(Member {
+                path: path.clone(),
+                entry: None,
+                data: Vec::new(),
+            });
+        }
+    }
+}
+
+/// The Unix permissions each kind of member gets, including the bits that
+/// say what kind of file it is.
+fn unix_mode(entry: &Option<Entry>) -> u32 {
+    match entry.as_ref().map(|entry| entry.mode) {
+        None | Some(FileMode::Nest) => 0o040755,
+        Some(FileMode::Regular) => 0o100644,
+        Some(FileMode::Executable) => 0o100755,
+        Some(FileMode::Symlink) => 0o120777,
+    }
+}
+
+/// Tar files are made of 512-byte blocks.
+const BLOCK_SIZE: usize = 512;
+
+/// And the blocks are grouped into records of 20, which is what tar itself
+/// writes, so some tools expect the whole file to be a multiple of that.
+const RECORD_SIZE: usize = 20 * BLOCK_SIZE;
+
+fn write_tar(members: &[Member], timestamp: i64) -> Vec<u8> {
+    let mut tar = Vec::new();
+
+    for member in members {
+        let is_symlink = member
+            .entry
+            .as_ref()
+            .is_some_and(|entry| entry.mode == FileMode::Symlink);
+
+        let (type_flag, link_name, data): (u8, &[u8], &[u8]) = match &member.entry {
+            None => (b'5', b"", b""),
+            Some(_) if is_symlink => (b'2', &member.data, b""),
+            Some(_) => (b'0', b"", &member.data),
+        };
+
+        let path = member.path.as_bytes();
+
+        // The ordinary header only has room for 100 bytes of path or link
+        // target, so anything longer goes in an extended header first, which
+        // overrides the cut off one.
+        let mut extended = Vec::new();
+
+        if path.len() > 100 {
+            extended.extend(pax_record("path", path));
+        }
+
+        if link_name.len() > 100 {
+            extended.extend(pax_record("linkpath", link_name));
+        }
+
+        if !extended.is_empty() {
+            tar.extend(tar_header(
+                b"pax_header",
+                0o644,
+                extended.len(),
+                timestamp,
+                b'x',
+                b"",
+            ));
+            push_padded(&mut tar, &extended);
+        }
+
+        tar.extend(tar_header(
+            &path[..path.len().min(100)],
+            unix_mode(&member.entry) & 0o7777,
+            data.len(),
+            timestamp,
+            type_flag,
+            &link_name[..link_name.len().min(100)],
+        ));
+        push_padded(&mut tar, data);
+    }
+
+    // Two empty blocks mark the end.
+    tar.resize(tar.len() + 2 * BLOCK_SIZE, 0);
+    tar.resize(tar.len().next_multiple_of(RECORD_SIZE), 0);
+
+    tar
+}
+
+/// Adds `data` to `tar`, followed by enough zeroes to fill its last block.
+fn push_padded(tar: &mut Vec<u8>, data: &[u8]) {
+    tar.extend_from_slice(data);
+    tar.resize(tar.len().next_multiple_of(BLOCK_SIZE), 0);
+}
+
+/// Builds a line of an extended header, which starts with its own length.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    // The length includes the digits of the length itself, so adding them can
+    // make the length longer, which we keep trying until it settles.
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest;
+
+    while length != rest + length.to_string().len() {
+        length = rest + length.to_string().len();
+    }
+
+    let mut record = format!("{length} {key}=").into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+
+    record
+}
+
+fn tar_header(
+    name: &[u8],
+    mode: u32,
+    size: usize,
+    timestamp: i64,
+    type_flag: u8,
+    link_name: &[u8],
+) -> [u8; BLOCK_SIZE] {
+    let mut header = [0; BLOCK_SIZE];
+
+    // Numbers are written in octal, with a zero byte at the end.
+    let mut put_octal = |offset: usize, width: usize, value: u64| {
+        let digits = format!("{value:0width$o}", width = width - 1);
+        header[offset..offset + width - 1].copy_from_slice(&digits.as_bytes()[..width - 1]);
+    };
+
+    put_octal(100, 8, u64::from(mode));
+    put_octal(108, 8, 0);
+    put_octal(116, 8, 0);
+    put_octal(124, 12, size as u64);
+    put_octal(136, 12, timestamp.max(0) as u64);
+
+    header[..name.len()].copy_from_slice(name);
+    header[156] = type_flag;
+    header[157..157 + link_name.len()].copy_from_slice(link_name);
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // The checksum is the sum of every byte of the header, counting the
+    // checksum itself as spaces.
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..155].copy_from_slice(format!("{checksum:06o}\0").as_bytes());
+
+    header
+}
+
+/// Compresses `data` into a gzip file.
+fn gzip(data: &[u8], timestamp: i64) -> Vec<u8> {
+    // The magic number, DEFLATE, no flags, the time, no extra flags, and an
+    // unknown operating system.
+    let mut gzip = vec![0x1f, 0x8b, 8, 0];
+    gzip.extend_from_slice(&(timestamp.clamp(0, u32::MAX.into()) as u32).to_le_bytes());
+    gzip.extend_from_slice(&[0, 255]);
+
+    gzip.extend(deflate::compress(data));
+    gzip.extend_from_slice(&deflate::crc32(data).to_le_bytes());
+    gzip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+    gzip
+}
+
+fn write_zip(members: &[Member], timestamp: i64) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Without the extensions for huge files, everything has to fit in the
+    // fields of the original format.
+    if members.len() > usize::from(u16::MAX) {
+        Err("There are too many files to fit in a zip file.")?;
+    }
+
+    let (time, date) = dos_time(timestamp);
+
+    let mut zip = Vec::new();
+    let mut directory = Vec::new();
+
+    for member in members {
+        let compressed = deflate::compress(&member.data);
+
+        // Compressing doesn't always help, in which case it's stored as it is.
+        let (method, stored): (u16, &[u8]) = match compressed.len() < member.data.len() {
+            true => (8, &compressed),
+            false => (0, &member.data),
+        };
+
+        let offset =
+            u32::try_from(zip.len()).map_err(|_| "The archive is too big to be a zip file.")?;
+        let size = u32::try_from(member.data.len())
+            .map_err(|_| format!("{} is too big to go in a zip file.", member.path))?;
+        let crc = deflate::crc32(&member.data);
+        let name = member.path.as_bytes();
+
+        // Version 2.0, and bit 11 of the flags says the name is UTF-8.
+        let mut fields = Vec::new();
+        fields.extend_from_slice(&20u16.to_le_bytes());
+        fields.extend_from_slice(&0x0800u16.to_le_bytes());
+        fields.extend_from_slice(&method.to_le_bytes());
+        fields.extend_from_slice(&time.to_le_bytes());
+        fields.extend_from_slice(&date.to_le_bytes());
+        fields.extend_from_slice(&crc.to_le_bytes());
+        fields.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&size.to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+
+        zip.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        zip.extend_from_slice(&fields);
+        zip.extend_from_slice(name);
+        zip.extend_from_slice(stored);
+
+        // The central directory at the end repeats all of that, and adds the
+        // Unix permissions, which is how unzip knows about executables and
+        // symlinks, along with where to find the file.
+        let mut external = unix_mode(&member.entry) << 16;
+
+        if member.entry.is_none() {
+            // The MS-DOS directory attribute.
+            external |= 0x10;
+        }
+
+        directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        // Made by version 2.0 on Unix.
+        directory.extend_from_slice(&(3u16 << 8 | 20).to_le_bytes());
+        directory.extend_from_slice(&fields);
+        // No comment, on the first disk, with no internal attributes.
+        directory.extend_from_slice(&[0; 6]);
+        directory.extend_from_slice(&external.to_le_bytes());
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name);
+    }
+
+    let directory_offset =
+        u32::try_from(zip.len()).map_err(|_| "The archive is too big to be a zip file.")?;
+    let count = (members.len() as u16).to_le_bytes();
+
+    zip.extend_from_slice(&directory);
+
+    zip.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    // This is the only disk, and the central directory starts on it.
+    zip.extend_from_slice(&[0; 4]);
+    zip.extend_from_slice(&count);
+    zip.extend_from_slice(&count);
+    zip.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    zip.extend_from_slice(&directory_offset.to_le_bytes());
+    // No comment.
+    zip.extend_from_slice(&[0; 2]);
+
+    Ok(zip)
+}
+
+/// Converts a Unix timestamp into the MS-DOS time and date zip files use,
+/// which only go down to two seconds and can't be before 1980.
+fn dos_time(timestamp: i64) -> (u16, u16) {
+    let seconds_of_day = timestamp.rem_euclid(86400);
+    let (year, month, day) = utils::civil_from_days(timestamp.div_euclid(86400));
+
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+
+    let time = ((seconds_of_day / 3600) << 11)
+        | ((seconds_of_day % 3600 / 60) << 5)
+        | ((seconds_of_day % 60) / 2);
+    let date = ((year.min(2107) - 1980) << 9) | (month << 5) | day;
+
+    (time as u16, date as u16)
+}
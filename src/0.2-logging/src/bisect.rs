@@ -0,0 +1,249 @@
+//! Finding the commit that broke something, by binary search.
+//!
+//! Given a bad commit, where something's broken, and good ones from before it
+//! broke, the commit that broke it has to be somewhere in the history of the
+//! bad commit that isn't in the history of any good one. We check out the
+//! commit in the middle of that range for the user to test. Whichever way it
+//! goes, half of the range can be ruled out, so even thousands of commits only
+//! take a handful of tests.
+//!
+//! Unlike a cherry-pick or a rebase, a bisect doesn't stop anything else from
+//! happening in the meantime, so it isn't an [`Operation`](crate::state::Operation).
+//! What we know so far is kept in a few files in the nest:
+//!
+//! - `.rat/BISECT_START`, holding the branch HEAD was on before, or the commit
+//!   if it was detached, so we can go back there afterwards.
+//! - `.rat/BISECT_BAD`, holding the hash of the newest commit known to be bad.
+//! - `.rat/BISECT_GOOD` and `.rat/BISECT_SKIP`, listing the hashes of the
+//!   commits known to be good and the ones that couldn't be tested, one per
+//!   line.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::error::BisectError;
+use crate::{commit_graph, utils};
+
+fn start_path() -> PathBuf {
+    crate::nest_path("BISECT_START")
+}
+
+fn bad_path() -> PathBuf {
+    crate::nest_path("BISECT_BAD")
+}
+
+fn good_path() -> PathBuf {
+    crate::nest_path("BISECT_GOOD")
+}
+
+fn skip_path() -> PathBuf {
+    crate::nest_path("BISECT_SKIP")
+}
+
+/// Everything we know so far about a bisect.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BisectState {
+    /// The branch HEAD was on when the bisect started, or the commit if it
+    /// was detached.
+    pub start: String,
+    /// The newest commit known to be bad, if we know of one yet.
+    pub bad: Option<String>,
+    /// The commits known to be good.
+    pub good: Vec<String>,
+    /// The commits that couldn't be tested.
+    pub skipped: Vec<String>,
+}
+
+impl BisectState {
+    /// Checks whether a bisect is going on.
+    pub fn exists() -> bool {
+        start_path().exists()
+    }
+
+    /// Reads the state of the bisect that's going on.
+    pub fn read() -> Result<Self, BisectError> {
+        let read = |path: PathBuf| match fs::read_to_string(path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+        let read_list = |path: PathBuf| -> io::Result<Vec<String>> {
+            Ok(read(path)?
+                .unwrap_or_default()
+                .lines()
+                .map(str::to_string)
+                .collect())
+        };
+
+        let Some(start) = read(start_path())? else {
+            return Err(BisectError::NotStarted);
+        };
+
+        Ok(Self {
+            start: start.trim().to_string(),
+            bad: read(bad_path())?.map(|hash| hash.trim().to_string()),
+            good: read_list(good_path())?,
+            skipped: read_list(skip_path())?,
+        })
+    }
+
+    /// Saves the state, replacing whatever was saved before.
+    pub fn write(&self) -> Result<(), io::Error> {
+        let list = |hashes: &[String]| {
+            hashes
+                .iter()
+                .map(|hash| format!("{hash}\n"))
+                .collect::<String>()
+        };
+
+        utils::write_atomically(start_path(), &self.start)?;
+
+        match &self.bad {
+            Some(bad) => utils::write_atomically(bad_path(), bad)?,
+            None => remove_if_exists(bad_path())?,
+        }
+
+        utils::write_atomically(good_path(), list(&self.good))?;
+        utils::write_atomically(skip_path(), list(&self.skipped))
+    }
+
+    /// Forgets about the bisect entirely.
+    pub fn remove() -> Result<(), io::Error> {
+        for path in [start_path(), bad_path(), good_path(), skip_path()] {
+            remove_if_exists(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: PathBuf) -> Result<(), io::Error> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// What testing a commit showed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BisectMark {
+    /// The commit doesn't have the problem.
+    Good,
+    /// The commit has the problem.
+    Bad,
+    /// The commit couldn't be tested, like when it doesn't build at all.
+    Skip,
+}
+
+/// Where a bisect has got to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BisectStep {
+    /// We need both a bad commit and a good one before we can start.
+    Waiting,
+    /// The commit `commit` should be tested next. Whichever way it goes, at
+    /// most `remaining` commits will be left to test after it.
+    Test { commit: String, remaining: usize },
+    /// The first bad commit has been found.
+    Found(String),
+    /// Every commit that could be the first bad one is either the bad commit
+    /// or was skipped, so we can't narrow it down any further.
+    OnlySkipped(Vec<String>),
+}
+
+/// Works out what to do next, given what we know so far.
+pub fn next_step(state: &BisectState) -> Result<BisectStep, BisectError> {
+    let Some(bad) = &state.bad else {
+        return Ok(BisectStep::Waiting);
+    };
+
+    if state.good.is_empty() {
+        return Ok(BisectStep::Waiting);
+    }
+
+    // The good commits and everything before them can't be the one that
+    // broke things.
+    let mut ruled_out = HashSet::new();
+
+    for good in &state.good {
+        let mut to_visit = vec![good.clone()];
+
+        while let Some(hash) = to_visit.pop() {
+            if hash == *bad {
+                Err(BisectError::BadBeforeGood {
+                    bad: bad.clone(),
+                    good: good.clone(),
+                })?;
+            }
+
+            if ruled_out.insert(hash.clone()) {
+                to_visit.extend(commit_graph::read(&hash)?.parents);
+            }
+        }
+    }
+
+    // Whatever's left in the history of the bad commit is where the first bad
+    // commit must be, along with the parents of each one that are still in
+    // the running.
+    let mut candidates: HashMap<String, Vec<String>> = HashMap::new();
+    let mut to_visit = vec![bad.clone()];
+
+    while let Some(hash) = to_visit.pop() {
+        if ruled_out.contains(&hash) || candidates.contains_key(&hash) {
+            continue;
+        }
+
+        let parents: Vec<String> = commit_graph::read(&hash)?
+            .parents
+            .into_iter()
+            .filter(|parent| !ruled_out.contains(parent))
+            .collect();
+
+        to_visit.extend(parents.iter().cloned());
+        candidates.insert(hash, parents);
+    }
+
+    if candidates.len() == 1 {
+        return Ok(BisectStep::Found(bad.clone()));
+    }
+
+    // The best commit to test is the one that splits the candidates most
+    // evenly. If it's bad, only its own history is left, and if it's good,
+    // only everything else is. Ties go to the lowest hash, just so the
+    // answer never depends on the order we happened to find them in.
+    let total = candidates.len();
+    let best = candidates
+        .keys()
+        .filter(|hash| *hash != bad && !state.skipped.contains(hash))
+        .map(|hash| {
+            let below = history_within(hash, &candidates);
+
+            (below.max(total - below) - 1, hash.clone())
+        })
+        .min();
+
+    let Some((remaining, commit)) = best else {
+        let mut possible: Vec<String> = candidates.into_keys().collect();
+        possible.sort();
+
+        return Ok(BisectStep::OnlySkipped(possible));
+    };
+
+    Ok(BisectStep::Test { commit, remaining })
+}
+
+/// Counts the commits in the history of `start`, including itself, that are
+/// still candidates.
+fn history_within(start: &str, candidates: &HashMap<String, Vec<String>>) -> usize {
+    let mut seen = HashSet::new();
+    let mut to_visit = vec![start];
+
+    while let Some(hash) = to_visit.pop() {
+        if seen.insert(hash) {
+            to_visit.extend(candidates[hash].iter().map(String::as_str));
+        }
+    }
+
+    seen.len()
+}
@@ -0,0 +1,189 @@
+//! Working out which commit each line of a file came from.
+//!
+//! We start with every line of the file belonging to the newest commit, then
+//! walk back through the history. At each commit, we compare the file with
+//! how it was in each parent. Any line the parent already had can't have come
+//! from this commit, so it's handed down to the parent to keep looking. The
+//! lines that are left over are the ones this commit wrote.
+//!
+//! The log always hands out children before their parents, so by the time we
+//! get to a commit, every line that might have come from it has been handed
+//! down already. Once every line has been claimed, we can stop walking.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::rc::Rc;
+
+use crate::diff;
+use crate::metadata::Signature;
+use crate::objects::{self, ObjectKind};
+use crate::repository::LogIter;
+
+/// A line of a file, along with where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameLine {
+    /// The hash of the commit that last changed the line.
+    pub commit: String,
+    /// Who wrote the line, and when.
+    pub author: Signature,
+    /// Where the line was in the file in that commit, counting from one.
+    pub original_line: usize,
+    /// The line itself, without its line ending.
+    pub text: String,
+}
+
+/// Finds the commit that last changed each line of the file at `path`, as it
+/// is in the first commit in `log`.
+pub fn blame(mut log: LogIter, path: &str) -> Result<Vec<BlameLine>, Box<dyn Error>> {
+    let Some((start, start_metadata)) = log.next().transpose()? else {
+        Err("There are no commits yet.")?
+    };
+
+    let mut files = FileCache::default();
+    let (_, lines) = files
+        .read(&start_metadata.tree, path)?
+        .ok_or_else(|| format!("There's no file at {path} in that commit."))?;
+
+    let mut blamed: Vec<Option<BlameLine>> = vec![None; lines.len()];
+    let mut remaining = lines.len();
+
+    // The lines each commit still has to account for, as pairs of where the
+    // line ended up in the file we're blaming and where it is in the commit.
+    let mut pending: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+    pending.insert(start.clone(), (0..lines.len()).map(|i| (i, i)).collect());
+
+    for commit in [Ok((start, start_metadata))].into_iter().chain(log) {
+        if remaining == 0 {
+            break;
+        }
+
+        let (hash, metadata) = commit?;
+
+        let Some(mut unclaimed) = pending.remove(&hash) else {
+            continue;
+        };
+
+        let Some((our_blob, ours)) = files.read(&metadata.tree, path)? else {
+            continue;
+        };
+
+        for parent in &metadata.parents {
+            if unclaimed.is_empty() {
+                break;
+            }
+
+            let parent_tree = objects::read_commit(parent)?.tree;
+
+            let Some((their_blob, theirs)) = files.read(&parent_tree, path)? else {
+                continue;
+            };
+
+            // Most of the time the parent has exactly the same file, in which
+            // case all of the lines go straight to it.
+            if their_blob == our_blob {
+                pending
+                    .entry(parent.clone())
+                    .or_default()
+                    .append(&mut unclaimed);
+
+                break;
+            }
+
+            // Where each of our lines is in the parent's version, if it has
+            // it at all.
+            let mut in_parent = vec![None; ours.len()];
+
+            for (old, new) in diff::matching_lines(&theirs, &ours) {
+                in_parent[new] = Some(old);
+            }
+
+            let mut handed_down = Vec::new();
+
+            unclaimed.retain(|&(final_line, line)| match in_parent[line] {
+                Some(parent_line) => {
+                    handed_down.push((final_line, parent_line));
+                    false
+                }
+                None => true,
+            });
+
+            pending
+                .entry(parent.clone())
+                .or_default()
+                .extend(handed_down);
+        }
+
+        for (final_line, line) in unclaimed {
+            blamed[final_line] = Some(BlameLine {
+                commit: hash.clone(),
+                author: metadata.author.clone(),
+                original_line: line + 1,
+                text: ours[line].clone(),
+            });
+
+            remaining -= 1;
+        }
+    }
+
+    // A range like `v1.0..main` leaves out the older history, so some lines
+    // can be handed down to commits that never come up. Like git, we blame
+    // those on the commits at the edge of the range.
+    for (hash, unclaimed) in pending.into_iter().filter(|(_, lines)| !lines.is_empty()) {
+        let metadata = objects::read_commit(&hash)?;
+
+        let Some((_, ours)) = files.read(&metadata.tree, path)? else {
+            continue;
+        };
+
+        for (final_line, line) in unclaimed {
+            blamed[final_line] = Some(BlameLine {
+                commit: hash.clone(),
+                author: metadata.author.clone(),
+                original_line: line + 1,
+                text: ours[line].clone(),
+            });
+        }
+    }
+
+    // Otherwise, every line is claimed by the oldest commit that has it at
+    // the latest, since that has no parents to hand it down to.
+    Ok(blamed.into_iter().flatten().collect())
+}
+
+/// The lines of a version of the file, which are shared between every commit
+/// that has that version.
+type Lines = Rc<Vec<String>>;
+
+/// The lines of each version of the file we've read so far, keyed by the hash
+/// of its blob. Most commits don't touch any given file, so the same version
+/// comes up again and again.
+#[derive(Debug, Default)]
+struct FileCache {
+    files: HashMap<String, Lines>,
+}
+
+impl FileCache {
+    /// Reads the file at `path` in the tree with the hash `tree`, returning the
+    /// hash of its blob and its lines, or `None` if it's not there.
+    fn read(&mut self, tree: &str, path: &str) -> Result<Option<(String, Lines)>, Box<dyn Error>> {
+        let Some(entry) = objects::read_tree_entry(tree, path)? else {
+            return Ok(None);
+        };
+
+        if let Some(lines) = self.files.get(&entry.hash) {
+            return Ok(Some((entry.hash, lines.clone())));
+        }
+
+        let data = objects::read_object_of_kind(&entry.hash, ObjectKind::Blob)?;
+        let lines: Lines = Rc::new(
+            String::from_utf8_lossy(&data)
+                .lines()
+                .map(str::to_string)
+                .collect(),
+        );
+
+        self.files.insert(entry.hash.clone(), lines.clone());
+
+        Ok(Some((entry.hash, lines)))
+    }
+}
@@ -0,0 +1,325 @@
+//! Packing history into a single file.
+//!
+//! Sometimes there's no way for two nests to talk to each other directly, like
+//! when one of them is on a computer without a network connection. A bundle
+//! gets around that by putting everything a fetch would have copied into one
+//! file, which can be carried over however is convenient and then unpacked on
+//! the other side.
+//!
+//! A bundle starts with a few lines of text. The first says what it is, along
+//! with the [`hash`](crate::hash) function its objects are named with if
+//! that isn't SHA-256, like `# rat bundle v1 blake3`. Then
+//! there's a line for each ref it contains, and a line starting with `-` for
+//! each commit the other nest needs to have already, which we call
+//! prerequisites. A blank line ends the header:
+//!
+//! ```text
+//! # rat bundle v1
+//! 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08 refs/heads/main
+//! -2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae
+//!
+//! ```
+//!
+//! After that come the objects themselves, each one introduced by a line with
+//! its hash and the length of its stored form, followed by the stored form
+//! exactly as it is in the object store. Since we know every object's hash, we
+//! can check each one for corruption while unpacking it, just like when
+//! reading from the object store.
+//!
+//! The contents of any files kept out of the nest with [`lfs`](crate::lfs)
+//! come last, as long as we have them, each one introduced by a line with
+//! `lfs`, the SHA-256 hash of the contents and their length:
+//!
+//! ```text
+//! lfs 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08 1073741824
+//! ```
+//!
+//! Bundling `A..B` only includes what's needed for the commits reachable from
+//! `B` but not from `A`, and makes `A` a prerequisite. Bundling just `B`
+//! includes its entire history.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{BundleError, ObjectError};
+use crate::hash::{self, HashAlgorithm};
+use crate::objects;
+use crate::objects::ObjectKind;
+use crate::refs::{self, Head, Ref};
+use crate::transport::LocalTransport;
+use crate::{graph, lfs, remote, resolve, transfer};
+
+/// The first line of every bundle, which also says which version of the
+/// format it uses.
+const SIGNATURE: &str = "# rat bundle v1";
+
+/// Writes a bundle of everything in `range` to `path`, which is either a
+/// single revision or two separated by `..`. Returns how many objects were
+/// written.
+pub fn create(path: &Path, range: &str) -> Result<usize, Box<dyn Error>> {
+    let (from, to) = match range.split_once("..") {
+        Some((from, to)) => (Some(from), to),
+        None => (None, range),
+    };
+
+    // Just like git, either side of the range can be left out to mean HEAD.
+    let to = if to.is_empty() { "HEAD" } else { to };
+
+    // When the tip is a branch or a tag, we keep its name so that unbundling
+    // can recreate it. Anything else can only be given to the other side as
+    // a detached HEAD.
+    let (ref_name, tip) = if let Some(hash) = refs::read_branch(to)? {
+        (Ref::Branch(to.to_string()), hash)
+    } else if let Some(hash) = refs::read_tag(to)? {
+        (Ref::Tag(to.to_string()), hash)
+    } else {
+        (Ref::Head, resolve::resolve_revision(to)?)
+    };
+
+    let transport = LocalTransport::new(crate::nest_dir());
+
+    let mut header = match hash::current() {
+        HashAlgorithm::Sha256 => format!("{SIGNATURE}\n"),
+        algorithm => format!("{SIGNATURE} {algorithm}\n"),
+    };
+    header.push_str(&format!("{tip} {ref_name}\n"));
+    let mut have = HashSet::new();
+
+    if let Some(from) = from {
+        let from = resolve::resolve_revision(if from.is_empty() { "HEAD" } else { from })?;
+        header.push_str(&format!("-{from}\n"));
+
+        // The other side has everything reachable from the prerequisite, so
+        // none of that needs to go in the bundle.
+        transfer::walk_objects(
+            &transport,
+            std::slice::from_ref(&from),
+            |_| false,
+            |hash, _, _| {
+                have.insert(hash);
+
+                Ok(())
+            },
+        )?;
+    }
+
+    header.push('\n');
+
+    let mut contents = header.into_bytes();
+    let mut count = 0;
+    let mut pointers = Vec::new();
+
+    transfer::walk_objects(
+        &transport,
+        std::slice::from_ref(&tip),
+        |hash| have.contains(hash),
+        |hash, kind, data| {
+            if kind == ObjectKind::Blob {
+                pointers.extend(lfs::Pointer::parse(&data));
+            }
+
+            let encoded = objects::encode(kind, &data);
+
+            contents.extend_from_slice(format!("{hash} {}\n", encoded.len()).as_bytes());
+            contents.extend_from_slice(&encoded);
+            count += 1;
+
+            Ok(())
+        },
+    )?;
+
+    for pointer in pointers.iter().filter(|pointer| pointer.has_content()) {
+        let data = fs::read(pointer.content_path())?;
+
+        contents.extend_from_slice(format!("lfs {} {}\n", pointer.hash, data.len()).as_bytes());
+        contents.extend_from_slice(&data);
+    }
+
+    fs::write(path, contents)?;
+
+    Ok(count)
+}
+
+/// Reads the line of `contents` starting at `position`, without its newline.
+fn read_line(contents: &[u8], position: usize) -> Result<&str, BundleError> {
+    let length = contents[position..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or(BundleError::Truncated)?;
+
+    let line = &contents[position..position + length];
+
+    std::str::from_utf8(line).map_err(|_| invalid_line(&String::from_utf8_lossy(line)))
+}
+
+fn invalid_line(line: &str) -> BundleError {
+    BundleError::InvalidLine {
+        line: line.to_string(),
+    }
+}
+
+/// Unpacks the bundle at `path` into the nest, creating or updating the refs
+/// it contains. Returns a line describing each ref.
+///
+/// Branches are only ever moved forwards, and never while they're checked
+/// out, since that would leave the working directory behind. Tags we already
+/// have are left alone, just like when fetching.
+pub fn unbundle(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let contents = fs::read(path).map_err(|source| BundleError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let mut position = 0;
+
+    // Reads the next line of the header, without its newline.
+    let mut next_line = || -> Result<&str, BundleError> {
+        let line = read_line(&contents, position)?;
+        position += line.len() + 1;
+
+        Ok(line)
+    };
+
+    let algorithm = match next_line()?.strip_prefix(SIGNATURE) {
+        Some("") => HashAlgorithm::Sha256,
+        Some(algorithm) => algorithm.trim_start().parse()?,
+        None => Err(BundleError::NotABundle {
+            path: path.to_path_buf(),
+        })?,
+    };
+
+    if algorithm != hash::current() {
+        Err(BundleError::HashMismatch {
+            bundle: algorithm,
+            nest: hash::current(),
+        })?;
+    }
+
+    let mut bundle_refs = Vec::new();
+    let mut prerequisites = Vec::new();
+
+    loop {
+        let line = next_line()?;
+
+        if line.is_empty() {
+            break;
+        } else if let Some(hash) = line.strip_prefix('-') {
+            prerequisites.push(hash.to_string());
+        } else {
+            let (hash, name) = line.split_once(' ').ok_or_else(|| invalid_line(line))?;
+
+            // Ref names end up as file paths, so one that could lead outside
+            // the refs directory is a sign that something's very wrong.
+            if name != "HEAD" && !refs::is_valid_name(name) {
+                Err(BundleError::InvalidRefName {
+                    name: name.to_string(),
+                })?;
+            }
+
+            bundle_refs.push((Ref::parse(name), hash.to_string()));
+        }
+    }
+
+    let nest = crate::nest_dir();
+
+    let missing: Vec<String> = prerequisites
+        .into_iter()
+        .filter(|hash| !objects::has_object_in(nest, hash))
+        .collect();
+
+    if !missing.is_empty() {
+        Err(BundleError::MissingPrerequisites(missing))?;
+    }
+
+    while position < contents.len() {
+        let line = read_line(&contents, position)?;
+        let start = position + line.len() + 1;
+
+        // Each object is introduced by its hash and length, and the contents
+        // of each file kept out of the nest by `lfs` as well.
+        let (is_lfs, rest) = match line.strip_prefix("lfs ") {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        let (hash, length) = rest
+            .split_once(' ')
+            .and_then(|(hash, length)| Some((hash, length.parse::<usize>().ok()?)))
+            .ok_or_else(|| invalid_line(line))?;
+
+        let data = start
+            .checked_add(length)
+            .and_then(|end| contents.get(start..end))
+            .ok_or(BundleError::Truncated)?;
+
+        if is_lfs {
+            if lfs::store_in(lfs::storage(), data)?.hash != hash {
+                Err(ObjectError::Corrupt {
+                    hash: hash.to_string(),
+                })?;
+            }
+        } else {
+            let (kind, data) = objects::decode(hash, data)?;
+            objects::write_object(kind, &data)?;
+        }
+
+        position = start + length;
+    }
+
+    let mut updates = Vec::new();
+    let head = refs::read_head()?;
+
+    for (name, hash) in bundle_refs {
+        match &name {
+            Ref::Branch(branch) => {
+                let old = refs::read_branch(branch)?;
+
+                if old.as_ref() == Some(&hash) {
+                    continue;
+                }
+
+                if let Some(old) = &old {
+                    if head == Head::Branch(branch.clone()) {
+                        updates.push(format!(
+                            "    [skipped]  {branch} is checked out, merge {} into it instead",
+                            resolve::abbreviate(&hash)
+                        ));
+
+                        continue;
+                    }
+
+                    if !graph::is_ancestor(old, &objects::peel_to_commit(&hash)?)? {
+                        updates.push(format!(
+                            "    [rejected]  {branch} has commits the bundle doesn't"
+                        ));
+
+                        continue;
+                    }
+                }
+
+                refs::write_ref(&name, &hash)?;
+                updates.push(remote::describe_update(
+                    old.as_deref(),
+                    &hash,
+                    branch,
+                    branch,
+                ));
+            }
+            Ref::Tag(tag) => {
+                if refs::read_tag(tag)?.is_some() {
+                    continue;
+                }
+
+                refs::write_ref(&name, &hash)?;
+                updates.push(remote::describe_update(None, &hash, tag, tag));
+            }
+            // There's nowhere sensible to put a commit without a name, so we
+            // just say where it is.
+            _ => updates.push(format!("    {}  {name}", resolve::abbreviate(&hash))),
+        }
+    }
+
+    Ok(updates)
+}
@@ -0,0 +1,249 @@
+//! Remembering the hashes of files that haven't changed.
+//!
+//! Working out what's changed in the working directory means hashing every
+//! file in it, which gets slow in big nests. Most files haven't changed since
+//! the last time we looked, though, so like git, we keep a cache in
+//! `.rat/cache` of each file's size and modification time along with its hash.
+//! When both still match, we trust the hash instead of reading the file again.
+//!
+//! The first line of the cache holds the time we started hashing when it was
+//! written, and every other line holds one file:
+//!
+//! ```text
+//! 1700000000.123456789
+//! 1024 1699999990.000000000 blob 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08 notes/todo.txt
+//! ```
+//!
+//! Modification times are only so precise, so a file that changed in the same
+//! instant we hashed it could look like it hasn't changed since. To be safe,
+//! we only trust entries for files last modified before we started hashing.
+//!
+//! Symlinks are never cached, since hashing one only means reading where it
+//! points to, which is no slower than looking it up.
+
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::compare::{Entry, FileMode};
+use crate::error::ObjectError;
+use crate::{logging, objects, utils, worktree};
+
+/// Whether to use the cache at all, which `--no-cache` turns off.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Stops the cache from being used for the rest of the program, so that every
+/// file gets hashed from scratch.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// The parts of a file's metadata that change whenever its contents or mode
+/// do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    size: u64,
+    /// When the file was last modified, as the time since the Unix epoch.
+    modified: Duration,
+    /// Changing a file's permissions doesn't change when it was modified, so
+    /// we have to check those separately.
+    mode: FileMode,
+}
+
+impl Stat {
+    fn of(metadata: &Metadata) -> Option<Self> {
+        // A directory is only ever hashed when it's another nest, which can
+        // move to another commit without the directory itself changing.
+        if metadata.is_symlink() || metadata.is_dir() {
+            return None;
+        }
+
+        Some(Self {
+            size: metadata.len(),
+            modified: metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?,
+            mode: worktree::mode_of(metadata),
+        })
+    }
+}
+
+/// The hashes of files as of the last time we looked at them.
+#[derive(Debug)]
+pub struct StatCache {
+    /// When we started hashing the files in the cache.
+    started: Duration,
+    entries: HashMap<String, (Stat, Entry)>,
+}
+
+impl StatCache {
+    /// Reads the cache, or starts an empty one if there isn't one yet, it
+    /// can't be read, or it's been turned off.
+    ///
+    /// New entries should only be added once hashing has started, since the
+    /// start time is used to decide which ones can be trusted next time.
+    pub fn load() -> Self {
+        let mut entries = HashMap::new();
+
+        if ENABLED.load(Ordering::Relaxed) {
+            // A cache we can't make sense of is no worse than not having one,
+            // since we can always just hash everything again.
+            if let Some(cached) = fs::read_to_string(cache_path())
+                .ok()
+                .and_then(|data| parse(&data))
+            {
+                // Entries for files modified after we started hashing last
+                // time are the ones we can't trust, so there's no point
+                // keeping them.
+                entries = cached
+                    .entries
+                    .into_iter()
+                    .filter(|(_, (stat, _))| stat.modified < cached.started)
+                    .collect();
+            }
+        }
+
+        Self {
+            started: now(),
+            entries,
+        }
+    }
+
+    /// Hashes the file at `path`, unless it hasn't changed since we last did,
+    /// and remembers the hash for next time.
+    pub fn hash_file(&self, path: &str) -> io::Result<(Entry, Option<Stat>)> {
+        let stat = Stat::of(&fs::symlink_metadata(path)?);
+
+        match self.lookup(path, stat) {
+            Some(entry) => {
+                logging::trace(format_args!("Skipped {path}, which hasn't changed"));
+                Ok((entry.clone(), stat))
+            }
+            None => {
+                logging::verbose(format_args!("Hashed {path}"));
+                Ok((worktree::hash_working_file(path)?, stat))
+            }
+        }
+    }
+
+    /// Stores the file at `path` as a blob, unless it hasn't changed since we
+    /// last hashed it and we already have it stored.
+    pub fn write_file(&self, path: &str) -> Result<(Entry, Option<Stat>), ObjectError> {
+        let stat = fs::symlink_metadata(path)
+            .map(|metadata| Stat::of(&metadata))
+            .map_err(|source| ObjectError::Io {
+                path: path.into(),
+                source,
+            })?;
+
+        match self.lookup(path, stat) {
+            Some(entry) if objects::has_object_in(crate::nest_dir(), &entry.hash) => {
+                logging::trace(format_args!("Skipped {path}, which is already stored"));
+                Ok((entry.clone(), stat))
+            }
+            _ => {
+                logging::verbose(format_args!("Stored {path}"));
+                Ok((worktree::store_working_file(path)?, stat))
+            }
+        }
+    }
+
+    /// Remembers that the file at `path` was `entry` when it looked like
+    /// `stat`, as returned by [`StatCache::hash_file`] or
+    /// [`StatCache::write_file`].
+    pub fn insert(&mut self, path: String, stat: Option<Stat>, entry: &Entry) {
+        match stat {
+            Some(stat) => self.entries.insert(path, (stat, entry.clone())),
+            None => self.entries.remove(&path),
+        };
+    }
+
+    /// Forgets every entry whose path `keep` says no to, so that the cache
+    /// doesn't keep growing with files that are long gone.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.entries.retain(|path, _| keep(path));
+    }
+
+    /// Writes the cache back out, unless it's been turned off.
+    pub fn save(&self) -> io::Result<()> {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let mut data = format_time(self.started);
+        data.push('\n');
+
+        for (path, (stat, entry)) in &self.entries {
+            data.push_str(&format!(
+                "{} {} {} {} {path}\n",
+                stat.size,
+                format_time(stat.modified),
+                entry.mode,
+                entry.hash
+            ));
+        }
+
+        utils::write_atomically(cache_path(), data)
+    }
+
+    /// Finds the cached hash of the file at `path`, as long as it still looks
+    /// the same as when it was cached.
+    fn lookup(&self, path: &str, stat: Option<Stat>) -> Option<&Entry> {
+        let (cached_stat, entry) = self.entries.get(path)?;
+
+        (Some(*cached_stat) == stat).then_some(entry)
+    }
+}
+
+fn cache_path() -> PathBuf {
+    crate::nest_path("cache")
+}
+
+/// Parses the cache in the format described in the module documentation.
+fn parse(data: &str) -> Option<StatCache> {
+    let mut lines = data.lines();
+    let started = parse_time(lines.next()?)?;
+
+    let mut entries = HashMap::new();
+
+    for line in lines {
+        // The path goes last, since it's the only part that can have spaces.
+        let mut parts = line.splitn(5, ' ');
+
+        let size = parts.next()?.parse().ok()?;
+        let modified = parse_time(parts.next()?)?;
+        let mode = parts.next()?.parse().ok()?;
+        let hash = parts.next()?.to_string();
+        let path = parts.next()?.to_string();
+
+        let stat = Stat {
+            size,
+            modified,
+            mode,
+        };
+
+        entries.insert(path, (stat, Entry { mode, hash }));
+    }
+
+    Some(StatCache { started, entries })
+}
+
+/// Gets the current time as the time since the Unix epoch.
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Formats a time like `1700000000.123456789`.
+fn format_time(time: Duration) -> String {
+    format!("{}.{:09}", time.as_secs(), time.subsec_nanos())
+}
+
+/// Parses a time in the format written by [`format_time`].
+fn parse_time(s: &str) -> Option<Duration> {
+    let (seconds, nanos) = s.split_once('.')?;
+
+    Some(Duration::new(seconds.parse().ok()?, nanos.parse().ok()?))
+}
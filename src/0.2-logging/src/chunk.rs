@@ -0,0 +1,161 @@
+//! Splitting large files into chunks, so that versions of them can share most
+//! of their storage.
+//!
+//! Every version of a file is a blob of its own, which is fine for source
+//! code, but a 100 MB file that changes a little in every commit costs 100 MB
+//! every time. When `core.chunkThreshold` is set, files at least that big are
+//! split into chunks, each stored as a blob of its own, and the file's object
+//! just lists them. A version that only changes in a few places shares every
+//! other chunk with the version before it.
+//!
+//! The trick is where to cut. Cutting every 64 KiB would work until somebody
+//! inserts a byte near the start, which shifts every chunk after it. Instead,
+//! we cut wherever the bytes just before the cut look a certain way, so the
+//! cuts move along with the contents. This is how FastCDC does it: a rolling
+//! hash is updated with every byte, and wherever its top bits are all zero, we
+//! cut. To keep chunks close to the size we're aiming for, we never cut before
+//! [`MIN_SIZE`], ask for more zero bits until [`AVERAGE_SIZE`] so that early
+//! cuts are rarer, and fewer after it so that late ones are likelier, and
+//! always cut at [`MAX_SIZE`].
+//!
+//! Chunking is only about how an object is stored, not what it is. A chunked
+//! file has exactly the same hash as it would otherwise, and reading it gives
+//! back the whole thing, so nothing else needs to know.
+
+use std::io::{self, Read};
+use std::sync::OnceLock;
+
+use crate::config::Config;
+use crate::utils;
+
+/// No chunk is smaller than this, apart from the last one.
+pub const MIN_SIZE: usize = 16 * 1024;
+
+/// The size we aim for, on average.
+pub const AVERAGE_SIZE: usize = 64 * 1024;
+
+/// No chunk is bigger than this.
+pub const MAX_SIZE: usize = 256 * 1024;
+
+/// Cutting before [`AVERAGE_SIZE`] needs the top 18 bits of the hash to be
+/// zero, which is four times less likely than the 16 bits an average size of
+/// 2^16 would need.
+const MASK_SMALL: u64 = !0 << (64 - 18);
+
+/// Cutting after [`AVERAGE_SIZE`] only needs the top 14 bits to be zero, which
+/// is four times more likely.
+const MASK_LARGE: u64 = !0 << (64 - 14);
+
+/// A random number for each byte, which is what the rolling hash adds in. The
+/// numbers just have to be the same every time, so we make them with
+/// SplitMix64 rather than writing them all out.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x5241_5443_4843_4b53;
+    let mut i = 0;
+
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+
+        i += 1;
+    }
+
+    table
+};
+
+/// Finds the size above which files are split into chunks, from the
+/// `core.chunkThreshold` setting, which can end in `k`, `m` or `g`. Files are
+/// only ever chunked when it's set, and never when they'd fit in a single
+/// chunk anyway.
+pub fn threshold() -> Option<u64> {
+    // Every file we store needs to know, so we only read the config once.
+    static THRESHOLD: OnceLock<Option<u64>> = OnceLock::new();
+
+    *THRESHOLD.get_or_init(|| {
+        Config::load()
+            .ok()
+            .and_then(|config| {
+                config
+                    .get("core.chunkThreshold")
+                    .and_then(utils::parse_size)
+            })
+            .map(|threshold| threshold.max(MAX_SIZE as u64 + 1))
+    })
+}
+
+/// Checks whether a blob of `length` bytes should be stored in chunks.
+pub fn should_chunk(length: u64) -> bool {
+    threshold().is_some_and(|threshold| length >= threshold)
+}
+
+/// Finds where the first chunk of `data` ends. Since no chunk is bigger than
+/// [`MAX_SIZE`], there's no point passing in any more than that.
+pub fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_SIZE {
+        return data.len();
+    }
+
+    let end = data.len().min(MAX_SIZE);
+    let normal = end.min(AVERAGE_SIZE);
+    let mut hash = 0u64;
+
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+
+        let mask = match i < normal {
+            true => MASK_SMALL,
+            false => MASK_LARGE,
+        };
+
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+
+    end
+}
+
+/// Splits everything `reader` gives us into chunks, reading no more than
+/// [`MAX_SIZE`] bytes ahead of the chunk it's working on.
+pub fn chunks<R: Read>(reader: R) -> Chunks<R> {
+    Chunks {
+        reader,
+        buffer: Vec::with_capacity(MAX_SIZE),
+    }
+}
+
+/// The chunks of whatever a reader gives us. See [`chunks`].
+pub struct Chunks<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> Iterator for Chunks<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The next cut could be anywhere up to MAX_SIZE bytes along, so we
+        // need that many in the buffer before we can be sure where it goes.
+        let wanted = (MAX_SIZE - self.buffer.len()) as u64;
+
+        if let Err(e) = (&mut self.reader)
+            .take(wanted)
+            .read_to_end(&mut self.buffer)
+        {
+            return Some(Err(e));
+        }
+
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        let cut = cut_point(&self.buffer);
+
+        Some(Ok(self.buffer.drain(..cut).collect()))
+    }
+}
@@ -0,0 +1,569 @@
+//! Making sense of the command line.
+//!
+//! Each command describes the flags and arguments it takes with a [`Command`],
+//! and [`parse`] checks the command line against that description.
+//! That way every command gets its arguments by name instead of by position,
+//! mistakes like a missing argument or a misspelled flag are all caught in one
+//! place, and the same description doubles as the command's help.
+//!
+//! We follow the usual conventions: flags can go anywhere, a long flag takes
+//! its value either as `--flag=value` or as the next argument, a short flag as
+//! `-fvalue` or the next argument, and everything after `--` counts as an
+//! argument even if it starts with a dash. So does everything from a
+//! [trailing](Argument::trailing) argument on, which is how a command like
+//! `rat bisect run` can be handed another command along with its own flags.
+
+use std::slice;
+
+use crate::error::UsageError;
+
+/// Everything a command accepts on the command line.
+#[derive(Debug)]
+pub struct Command {
+    pub name: &'static str,
+    /// A few words on what the command does, for the list of every command.
+    pub summary: &'static str,
+    /// How the command is used, one line per form it takes, like
+    /// `rat commit [-m <message>]`.
+    pub usage: &'static [&'static str],
+    /// A longer explanation, shown in the command's help.
+    pub description: &'static str,
+    pub flags: &'static [Flag],
+    /// The arguments that aren't flags, in the order they're expected.
+    pub arguments: &'static [Argument],
+}
+
+/// A flag a command accepts, like `--ff-only` or `-m <message>`.
+#[derive(Debug)]
+pub struct Flag {
+    /// Every way of spelling the flag, like `-m` and `--message`. The first
+    /// one is the one we use when talking about it.
+    pub names: &'static [&'static str],
+    /// What to call the flag's value in the help, if it takes one.
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+impl Flag {
+    /// A flag that's either there or not.
+    pub const fn switch(names: &'static [&'static str], help: &'static str) -> Self {
+        Self {
+            names,
+            value: None,
+            help,
+        }
+    }
+
+    /// A flag that takes a value.
+    pub const fn value(
+        names: &'static [&'static str],
+        value: &'static str,
+        help: &'static str,
+    ) -> Self {
+        Self {
+            names,
+            value: Some(value),
+            help,
+        }
+    }
+}
+
+/// An argument that isn't a flag, like the commit given to `rat revert`.
+#[derive(Debug)]
+pub struct Argument {
+    pub name: &'static str,
+    pub required: bool,
+    /// Whether it soaks up every argument that's left, like the paths given
+    /// to `rat add`. Only the last argument can do this.
+    pub repeated: bool,
+    /// Whether everything from here on is taken as it is, flags and all.
+    pub trailing: bool,
+}
+
+impl Argument {
+    pub const fn required(name: &'static str) -> Self {
+        Self {
+            name,
+            required: true,
+            repeated: false,
+            trailing: false,
+        }
+    }
+
+    pub const fn optional(name: &'static str) -> Self {
+        Self {
+            name,
+            required: false,
+            repeated: false,
+            trailing: false,
+        }
+    }
+
+    /// One or more of the same argument.
+    pub const fn repeated(name: &'static str) -> Self {
+        Self {
+            name,
+            required: true,
+            repeated: true,
+            trailing: false,
+        }
+    }
+
+    /// Any number of the same argument, including none at all.
+    pub const fn optional_repeated(name: &'static str) -> Self {
+        Self {
+            name,
+            required: false,
+            repeated: true,
+            trailing: false,
+        }
+    }
+
+    /// Every argument that's left, including none at all, taken as it is
+    /// even if it looks like a flag, like the command `rat bisect run` runs.
+    /// A `--` in front of them is still left out.
+    pub const fn trailing(name: &'static str) -> Self {
+        Self {
+            name,
+            required: false,
+            repeated: true,
+            trailing: true,
+        }
+    }
+}
+
+/// The flag every command accepts to show its help.
+pub const HELP: Flag = Flag::switch(&["-h", "--help"], "Show this help.");
+
+/// What the command line asked rat to do.
+#[derive(Debug)]
+pub enum Invocation {
+    /// Show the list of every command.
+    Overview,
+    /// Show which version of rat this is.
+    Version,
+    /// Show the help for a command.
+    Help(&'static Command),
+    /// Run a command.
+    Run(Matches),
+}
+
+/// The flags and arguments given to a command, as found by [`parse`].
+#[derive(Debug)]
+pub struct Matches {
+    command: &'static Command,
+    /// Each flag that was given, in order, along with its value if it takes
+    /// one. A flag can be given more than once.
+    flags: Vec<(&'static Flag, Option<String>)>,
+    /// The arguments that weren't flags, in order.
+    positional: Vec<String>,
+}
+
+/// Works out what `arguments`, which shouldn't include the name of the program
+/// itself, ask for.
+///
+/// The command line starts with the name of one of `commands`, followed by its
+/// flags and arguments. The `globals` are flags that work with every command,
+/// so they can go either before or after the command's name.
+pub fn parse(
+    commands: &'static [Command],
+    globals: &'static [Flag],
+    arguments: &[String],
+) -> Result<Invocation, UsageError> {
+    let overview_error = |message: String| UsageError {
+        message,
+        usage: &["rat [<options>] <command> [<arguments>]"],
+    };
+
+    let mut flags = Vec::new();
+    let mut arguments = arguments.iter();
+
+    let name = loop {
+        let Some(argument) = arguments.next() else {
+            return Err(overview_error("No command provided.".to_string()));
+        };
+
+        if !argument.starts_with('-') {
+            break argument;
+        }
+
+        if argument == "--version" {
+            return Ok(Invocation::Version);
+        }
+
+        if HELP.names.contains(&argument.as_str()) {
+            return Ok(Invocation::Overview);
+        }
+
+        let globals: Vec<&'static Flag> = globals.iter().collect();
+
+        flags.push(take_flag(
+            argument,
+            &globals,
+            &mut arguments,
+            overview_error,
+        )?);
+    };
+
+    let command = find(commands, name)?;
+
+    let mut matches = Matches {
+        command,
+        flags,
+        positional: Vec::new(),
+    };
+
+    let trailing = command.arguments.iter().position(|a| a.trailing);
+
+    while let Some(argument) = arguments.next() {
+        if trailing == Some(matches.positional.len()) {
+            if argument != "--" {
+                matches.positional.push(argument.clone());
+            }
+
+            matches.positional.extend(arguments.by_ref().cloned());
+
+            break;
+        }
+
+        if argument == "--" {
+            matches.positional.extend(arguments.by_ref().cloned());
+
+            break;
+        }
+
+        // A lone dash usually means standard input, so it's an argument
+        // rather than a flag.
+        if !argument.starts_with('-') || argument == "-" {
+            matches.positional.push(argument.clone());
+
+            continue;
+        }
+
+        if HELP.names.contains(&split_flag(argument).0) {
+            return Ok(Invocation::Help(command));
+        }
+
+        let flags: Vec<&'static Flag> = command.flags.iter().chain(globals).collect();
+        let flag = take_flag(argument, &flags, &mut arguments, |message| {
+            matches.error(message)
+        })?;
+
+        matches.flags.push(flag);
+    }
+
+    let repeated = command.arguments.last().is_some_and(|a| a.repeated);
+
+    if let Some(missing) = command.arguments.get(matches.positional.len()) {
+        if missing.required {
+            Err(matches.error(format!("Missing <{}>.", missing.name)))?;
+        }
+    } else if !repeated && matches.positional.len() > command.arguments.len() {
+        let unexpected = &matches.positional[command.arguments.len()];
+
+        Err(matches.error(format!("Unexpected argument {unexpected}.")))?;
+    }
+
+    Ok(Invocation::Run(matches))
+}
+
+/// Looks up the command called `name` in `commands`.
+pub fn find(commands: &'static [Command], name: &str) -> Result<&'static Command, UsageError> {
+    commands
+        .iter()
+        .find(|command| command.name == name)
+        .ok_or_else(|| UsageError {
+            message: format!("{name} isn't a rat command. Run rat help to see every command."),
+            usage: &[],
+        })
+}
+
+/// Splits a flag into its name and the value stuck onto it, if there is one,
+/// like `--message=hi` or `-mhi`.
+fn split_flag(argument: &str) -> (&str, Option<&str>) {
+    if argument.starts_with("--") {
+        match argument.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (argument, None),
+        }
+    } else {
+        match argument.char_indices().nth(2) {
+            Some((index, _)) => (&argument[..index], Some(&argument[index..])),
+            None => (argument, None),
+        }
+    }
+}
+
+/// Looks up the flag `argument` in `flags`, and takes its value, if it needs
+/// one, from `rest` unless it's stuck onto the flag already. Any problems are
+/// reported with `error`.
+fn take_flag(
+    argument: &str,
+    flags: &[&'static Flag],
+    rest: &mut slice::Iter<String>,
+    error: impl Fn(String) -> UsageError,
+) -> Result<(&'static Flag, Option<String>), UsageError> {
+    // A flag named after the whole argument wins over splitting it up, so that
+    // -vv can be a flag of its own rather than -v with a value stuck on.
+    let (name, attached) = match flags.iter().any(|flag| flag.names.contains(&argument)) {
+        true => (argument, None),
+        false => split_flag(argument),
+    };
+
+    let flag = *flags
+        .iter()
+        .find(|flag| flag.names.contains(&name))
+        .ok_or_else(|| error(format!("Unknown flag {name}.")))?;
+
+    let value = match (flag.value, attached) {
+        (Some(_), Some(value)) => Some(value.to_string()),
+        (Some(value_name), None) => Some(
+            rest.next()
+                .ok_or_else(|| error(format!("{name} needs a <{value_name}>.")))?
+                .clone(),
+        ),
+        (None, None) => None,
+        // A switch can't take a value, so this was probably a typo, like -dx
+        // instead of -d x.
+        (None, Some(_)) => Err(error(format!("Unknown flag {argument}.")))?,
+    };
+
+    Ok((flag, value))
+}
+
+impl Command {
+    /// Writes out the command's help, with its usage, description and flags,
+    /// followed by the `globals` that work with every command.
+    pub fn help(&self, globals: &[Flag]) -> String {
+        let mut help = format!("Usage: {}\n", self.usage.join("\n       "));
+
+        if !self.description.is_empty() {
+            help.push_str(&format!("\n{}\n", wrap(self.description, 80)));
+        }
+
+        let flags: Vec<&Flag> = self.flags.iter().chain([&HELP]).collect();
+
+        help.push_str(&format!("\nOptions:\n{}", describe_flags(&flags)));
+
+        if !globals.is_empty() {
+            let globals: Vec<&Flag> = globals.iter().collect();
+
+            help.push_str(&format!("\nGlobal options:\n{}", describe_flags(&globals)));
+        }
+
+        help.trim_end().to_string()
+    }
+}
+
+/// Lists `flags`, one per line, along with what they do.
+fn describe_flags(flags: &[&Flag]) -> String {
+    // The flags' names all get lined up in a column, so that their help
+    // starts in the same place.
+    let names: Vec<(String, &str)> = flags
+        .iter()
+        .map(|flag| {
+            let mut names = flag.names.join(", ");
+
+            if let Some(value) = flag.value {
+                names.push_str(&format!(" <{value}>"));
+            }
+
+            (names, flag.help)
+        })
+        .collect();
+
+    let width = names
+        .iter()
+        .map(|(names, _)| names.len())
+        .max()
+        .unwrap_or(0);
+
+    names
+        .into_iter()
+        .map(|(names, help)| format!("    {names:<width$}  {help}\n"))
+        .collect()
+}
+
+/// Breaks `text` into lines no longer than `width`, wherever there are spaces.
+fn wrap(text: &str, width: usize) -> String {
+    let mut lines: Vec<String> = Vec::new();
+
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Lists every command in `commands`, along with a few words on what each one
+/// does, followed by the `globals` that work with every command.
+pub fn overview(commands: &[Command], globals: &[Flag]) -> String {
+    let width = commands.iter().map(|c| c.name.len()).max().unwrap_or(0);
+
+    let list = commands
+        .iter()
+        .map(|command| format!("    {:<width$}  {}\n", command.name, command.summary))
+        .collect::<String>();
+
+    let mut flags: Vec<&Flag> = globals.iter().collect();
+    let version = Flag::switch(&["--version"], "Show which version of rat this is.");
+    flags.extend([&HELP, &version]);
+
+    format!(
+        "Usage: rat [<options>] <command> [<arguments>]\n\nCommands:\n{list}\n\
+         Options:\n{}\n\
+         Run rat help <command> to see how to use one of them.",
+        describe_flags(&flags)
+    )
+}
+
+impl Matches {
+    /// Gets the command these are the flags and arguments of.
+    pub fn command(&self) -> &'static Command {
+        self.command
+    }
+
+    /// Checks whether the flag with any of the names of `name` was given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.find(name).next().is_some()
+    }
+
+    /// Gets the value of the flag `name`, or the last one if it was given
+    /// more than once.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.flags
+            .iter()
+            .rev()
+            .find(|(flag, _)| flag.names.contains(&name))
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    /// Gets every value given to the flag `name`, in order.
+    pub fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.find(name).filter_map(|(_, value)| value.as_deref())
+    }
+
+    /// Checks which of the mutually exclusive flags in `names` was given, if
+    /// any, returning its name as it appears in `names`.
+    pub fn one_of(&self, names: &[&'static str]) -> Result<Option<&'static str>, UsageError> {
+        let given: Vec<&'static str> = names
+            .iter()
+            .copied()
+            .filter(|name| self.flag(name))
+            .collect();
+
+        match given[..] {
+            [] => Ok(None),
+            [name] => Ok(Some(name)),
+            [first, second, ..] => {
+                Err(self.error(format!("{first} and {second} can't be used together.")))
+            }
+        }
+    }
+
+    /// Gets the argument called `name`, if it was given.
+    pub fn argument(&self, name: &str) -> Option<&str> {
+        self.arguments(name).first().map(String::as_str)
+    }
+
+    /// Gets the argument called `name`, or complains that it's missing. This
+    /// is for arguments that are only needed by some forms of a command, like
+    /// the branch name for `rat branch -d`.
+    pub fn required(&self, name: &str) -> Result<&str, UsageError> {
+        self.argument(name)
+            .ok_or_else(|| self.error(format!("Missing <{name}>.")))
+    }
+
+    /// Gets every value of the argument called `name`. Unless the argument is
+    /// repeated, that's at most one.
+    pub fn arguments(&self, name: &str) -> &[String] {
+        let Some(index) = self.command.arguments.iter().position(|a| a.name == name) else {
+            return &[];
+        };
+
+        let start = index.min(self.positional.len());
+        let end = if self.command.arguments[index].repeated {
+            self.positional.len()
+        } else {
+            (index + 1).min(self.positional.len())
+        };
+
+        &self.positional[start..end]
+    }
+
+    /// Gets every argument that wasn't a flag, in order, regardless of which
+    /// argument it was given as.
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    /// Makes an error about how the command was used, which shows how it
+    /// should be used as well.
+    pub fn error(&self, message: impl Into<String>) -> UsageError {
+        UsageError {
+            message: message.into(),
+            usage: self.command.usage,
+        }
+    }
+
+    fn find<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a (&'static Flag, Option<String>)> {
+        self.flags
+            .iter()
+            .filter(move |(flag, _)| flag.names.contains(&name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static COMMANDS: &[Command] = &[Command {
+        name: "run",
+        summary: "",
+        usage: &["rat run [-q] <action> [<command>...]"],
+        description: "",
+        flags: &[Flag::switch(&["-q"], "")],
+        arguments: &[Argument::required("action"), Argument::trailing("command")],
+    }];
+
+    fn parse_run(arguments: &[&str]) -> Result<Matches, UsageError> {
+        let arguments: Vec<String> = arguments.iter().map(|a| a.to_string()).collect();
+
+        match parse(COMMANDS, &[], &arguments)? {
+            Invocation::Run(matches) => Ok(matches),
+            invocation => panic!("{invocation:?}"),
+        }
+    }
+
+    #[test]
+    fn trailing_arguments_are_taken_as_they_are() {
+        let matches = parse_run(&["run", "-q", "go", "sh", "-c", "test -q", "--"]).unwrap();
+
+        assert!(matches.flag("-q"));
+        assert_eq!(matches.argument("action"), Some("go"));
+        assert_eq!(matches.arguments("command"), ["sh", "-c", "test -q", "--"]);
+    }
+
+    #[test]
+    fn a_dash_dash_before_trailing_arguments_is_left_out() {
+        let matches = parse_run(&["run", "go", "--", "-x"]).unwrap();
+
+        assert!(!matches.flag("-q"));
+        assert_eq!(matches.arguments("command"), ["-x"]);
+    }
+
+    #[test]
+    fn flags_before_trailing_arguments_are_still_checked() {
+        assert!(parse_run(&["run", "-x", "go"]).is_err());
+        assert!(parse_run(&["run"]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! A cache of the shape of history.
+//!
+//! Walking through history means following each commit to its parents, and
+//! reading a commit means finding its object, checking its hash and parsing
+//! all of it, just to get at a couple of hashes. For a long history that adds
+//! up, and things like `rat log main..feature` and `rat merge-base` have to
+//! walk all the way back to the very first commit.
+//!
+//! So `rat maintenance` writes down the parents and commit time of every
+//! commit reachable from a ref in `.rat/commit-graph`, one commit per line:
+//!
+//! ```text
+//! 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08 1700000000 60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752
+//! ```
+//!
+//! A commit can't change without its hash changing too, so the graph can
+//! never be wrong, only out of date. Commits made since it was written are
+//! just read the slow way.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::OnceLock;
+
+use crate::error::{ObjectError, RefError};
+use crate::refs::{self, Ref};
+use crate::{objects, utils};
+
+/// The parts of a commit that walking through history needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphCommit {
+    pub parents: Vec<String>,
+    /// When the commit was made, as a Unix timestamp.
+    pub timestamp: i64,
+}
+
+/// The graph as it was when we first needed it, keyed by commit hash.
+static GRAPH: OnceLock<HashMap<String, GraphCommit>> = OnceLock::new();
+
+/// Reads the graph, which is empty if it hasn't been written yet. It's only a
+/// cache, so a graph we can't read is treated the same way.
+fn load() -> &'static HashMap<String, GraphCommit> {
+    GRAPH.get_or_init(|| {
+        let Ok(contents) = fs::read_to_string(crate::nest_path("commit-graph")) else {
+            return HashMap::new();
+        };
+
+        contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(' ');
+                let hash = fields.next()?;
+                let timestamp = fields.next()?.parse().ok()?;
+
+                Some((
+                    hash.to_string(),
+                    GraphCommit {
+                        parents: fields.map(str::to_string).collect(),
+                        timestamp,
+                    },
+                ))
+            })
+            .collect()
+    })
+}
+
+/// Reads the parents and commit time of the commit `hash`, from the graph if
+/// it's there, and from the commit itself otherwise.
+pub fn read(hash: &str) -> Result<GraphCommit, ObjectError> {
+    if let Some(commit) = load().get(hash) {
+        return Ok(commit.clone());
+    }
+
+    let metadata = objects::read_commit(hash)?;
+
+    Ok(GraphCommit {
+        parents: metadata.parents,
+        timestamp: metadata.committer.timestamp,
+    })
+}
+
+/// Writes a new graph with every commit reachable from `HEAD` or any ref in
+/// it, returning how many commits that is.
+pub fn write() -> Result<usize, RefError> {
+    let mut to_visit = Vec::new();
+
+    for name in [Ref::Head].into_iter().chain(refs::list_all_refs()?) {
+        if let Some(hash) = refs::read_ref(&name)? {
+            to_visit.push(objects::peel_to_commit(&hash)?);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut lines = Vec::new();
+
+    while let Some(hash) = to_visit.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        // Anything that's already in the old graph is still right, so we only
+        // have to read the commits that are new since then.
+        let commit = read(&hash)?;
+
+        let mut line = format!("{hash} {}", commit.timestamp);
+
+        for parent in &commit.parents {
+            line.push(' ');
+            line.push_str(parent);
+        }
+
+        lines.push(line);
+        to_visit.extend(commit.parents);
+    }
+
+    lines.sort();
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+
+    utils::write_atomically(crate::nest_path("commit-graph"), contents)?;
+
+    Ok(lines.len())
+}
@@ -0,0 +1,219 @@
+//! Comparing snapshots of files against each other.
+//!
+//! A snapshot here is just a map from paths to the hashes of the blobs holding
+//! their contents, along with what sort of file each one is. Snapshots can
+//! come from a commit, the index, or the working directory. Since identical
+//! contents always have identical hashes, we never need to look at the
+//! contents themselves to figure out what changed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cache::StatCache;
+use crate::{ignore, objects, progress, submodules, utils, worktree};
+
+/// What sort of file a snapshot entry is, which decides what its blob holds
+/// and how it gets written back out to the working directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileMode {
+    /// An ordinary file, whose blob holds its contents.
+    Regular,
+    /// An ordinary file that can be run as a program. Like git, this is the
+    /// only permission we keep track of, since the rest are up to whoever
+    /// checks the file out.
+    Executable,
+    /// A symbolic link, whose blob holds the path it points to. Git stores
+    /// them the same way, which means a link is never followed, so it can't
+    /// lead us in circles or outside the nest.
+    Symlink,
+    /// Another nest kept inside this one, whose hash is the commit it's
+    /// pinned to. That commit lives in the other nest, so unlike everything
+    /// else, there's no blob for it here. See the
+    /// [`submodules`](crate::submodules) module.
+    Nest,
+}
+
+impl FileMode {
+    /// The number git uses for this sort of file, which is how modes are shown
+    /// in patches.
+    pub fn git_mode(&self) -> &'static str {
+        match self {
+            Self::Regular => "100644",
+            Self::Executable => "100755",
+            Self::Symlink => "120000",
+            Self::Nest => "160000",
+        }
+    }
+}
+
+impl Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Regular => "blob",
+            Self::Executable => "executable",
+            Self::Symlink => "symlink",
+            Self::Nest => "nest",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for FileMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blob" => Ok(Self::Regular),
+            "executable" => Ok(Self::Executable),
+            "symlink" => Ok(Self::Symlink),
+            "nest" => Ok(Self::Nest),
+            _ => Err(format!("Unknown file mode {s}.")),
+        }
+    }
+}
+
+/// A single file in a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub mode: FileMode,
+    /// The hash of the blob holding the file's contents, or its target for a
+    /// symlink.
+    pub hash: String,
+}
+
+/// A map from paths, relative to the root of the nest and using `/` as the
+/// separator, to the file at that path.
+pub type Snapshot = BTreeMap<String, Entry>;
+
+/// The ways in which a single file can differ between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    /// The file was moved here from somewhere else, which is found by
+    /// [`renames::detect`](crate::renames::detect) rather than here.
+    Renamed {
+        from: String,
+        /// How alike the two versions are, as a percentage.
+        similarity: u8,
+    },
+    /// The file was copied from one that's still there, which is also found
+    /// by [`renames::detect`](crate::renames::detect).
+    Copied {
+        from: String,
+        /// How alike the two versions are, as a percentage.
+        similarity: u8,
+    },
+}
+
+/// Reads the snapshot stored in the commit with the given hash. Not having a
+/// commit at all, which is the case before the first commit, is treated as an
+/// empty snapshot.
+pub fn read_commit(commit_hash: Option<&str>) -> Result<Snapshot, Box<dyn Error>> {
+    match commit_hash {
+        Some(commit_hash) => Ok(objects::read_tree(
+            &objects::read_commit(commit_hash)?.tree,
+        )?),
+        None => Ok(Snapshot::new()),
+    }
+}
+
+/// Reads the snapshot of everything currently in the working directory,
+/// ignoring the nest itself and anything excluded by `.ratignore` files. The
+/// files are hashed but not stored in the object store.
+///
+/// Files that are already tracked in `index` are always included as long as
+/// they exist, even if they match an ignore pattern. Ignoring a file only stops
+/// rat from picking it up as a new file, just like in git.
+pub fn read_working_directory(index: &Snapshot) -> Result<Snapshot, Box<dyn Error>> {
+    // A symlink counts as a file in its own right, even if what it points to
+    // is a directory or doesn't exist at all.
+    let tracked_files = index.keys().filter(|path| {
+        fs::symlink_metadata(path).is_ok_and(|metadata| !metadata.is_dir())
+            || submodules::is_nest(path)
+    });
+
+    // A tracked file that isn't ignored will show up twice, so we collect them
+    // into a set first to only hash each one once.
+    let files: Vec<String> = ignore::list_working_files("")?
+        .into_iter()
+        .chain(tracked_files.cloned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    // Hashing is by far the slowest part, and each file can be hashed on its
+    // own, so we spread them across every core. Files that haven't changed
+    // since last time don't need hashing at all.
+    let mut cache = StatCache::load();
+    let task = progress::start("Hashing files", files.len());
+    let hashes = utils::parallel_map(&files, |file| {
+        let result = cache.hash_file(file);
+        task.advance(utils::file_size(file));
+        result
+    });
+    drop(task);
+
+    let mut snapshot = Snapshot::new();
+
+    for (file, result) in files.into_iter().zip(hashes) {
+        let (mut entry, stat) = result?;
+        worktree::keep_tracked_mode(&mut entry, index.get(&file));
+
+        cache.insert(file.clone(), stat, &entry);
+        snapshot.insert(file, entry);
+    }
+
+    // Another nest that's only been checked out as an empty directory hasn't
+    // been cloned yet, which isn't the same as having been deleted.
+    for (path, entry) in index {
+        if entry.mode == FileMode::Nest && Path::new(path).is_dir() && !snapshot.contains_key(path)
+        {
+            snapshot.insert(path.clone(), entry.clone());
+        }
+    }
+
+    // Anything we didn't come across this time has been deleted, so there's
+    // no point remembering it.
+    cache.retain(|path| snapshot.contains_key(path));
+
+    // The cache only saves time, so failing to write it isn't worth failing
+    // over.
+    let _ = cache.save();
+
+    Ok(snapshot)
+}
+
+/// Classifies every path that differs between `old` and `new`. Paths whose
+/// contents and modes are identical in both are left out entirely.
+pub fn compare(old: &Snapshot, new: &Snapshot) -> BTreeMap<String, Change> {
+    let mut changes = BTreeMap::new();
+
+    for (path, old_entry) in old {
+        match new.get(path) {
+            None => {
+                changes.insert(path.clone(), Change::Deleted);
+            }
+            Some(new_entry) if new_entry != old_entry => {
+                changes.insert(path.clone(), Change::Modified);
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Anything in the new snapshot that we haven't seen in the old one must
+    // have been added.
+    for path in new.keys() {
+        if !old.contains_key(path) {
+            changes.insert(path.clone(), Change::Added);
+        }
+    }
+
+    changes
+}
@@ -0,0 +1,507 @@
+//! Scripts that teach shells to complete rat's commands and flags.
+//!
+//! Each script is worked out from the same list of commands and flags that
+//! the help is, so it never falls behind. Arguments that name a commit, like
+//! the branch given to `rat switch`, are completed with the names of branches
+//! and tags, which the script finds by running `rat branch` and `rat tag` each
+//! time, so that it always offers the ones in whichever nest it's used in.
+//! Arguments that name a file are completed with files.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::cli::{Command, Flag, HELP};
+
+/// The shells we can write a completion script for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            "powershell" => Ok(Self::PowerShell),
+            _ => Err(format!("{s} isn't one of bash, zsh, fish or powershell.")),
+        }
+    }
+}
+
+/// What a command's arguments get completed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Completes {
+    /// The names of branches and tags.
+    Refs,
+    Files,
+    /// The names of rat's commands, for `rat help`.
+    Commands,
+    Nothing,
+}
+
+impl Completes {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Refs => "refs",
+            Self::Files => "files",
+            Self::Commands => "commands",
+            Self::Nothing => "nothing",
+        }
+    }
+}
+
+/// Works out what the arguments of `command` should be completed with. Only
+/// one kind is offered for every argument, so a command that takes a commit
+/// at all gets refs, since paths can always be typed out.
+fn completes(command: &Command) -> Completes {
+    let names: Vec<&str> = command.arguments.iter().map(|a| a.name).collect();
+    let any = |candidates: &[&str]| names.iter().any(|name| candidates.contains(name));
+
+    if any(&[
+        "branch", "commit", "revision", "target", "upstream", "ref", "other", "range",
+    ]) {
+        Completes::Refs
+    } else if any(&["path", "file", "source", "destination", "directory"]) {
+        Completes::Files
+    } else if any(&["command"]) {
+        Completes::Commands
+    } else {
+        Completes::Nothing
+    }
+}
+
+/// Writes the completion script for `shell`, covering every one of
+/// `commands` along with the `globals` that work with all of them.
+pub fn script(shell: Shell, commands: &[Command], globals: &[Flag]) -> String {
+    // Help works everywhere, even though it isn't listed with the others.
+    let mut globals: Vec<&Flag> = globals.iter().collect();
+    globals.push(&HELP);
+
+    match shell {
+        Shell::Bash => bash(commands, &globals),
+        Shell::Zsh => zsh(commands, &globals),
+        Shell::Fish => fish(commands, &globals),
+        Shell::PowerShell => powershell(commands, &globals),
+    }
+}
+
+/// Lists every name of every flag in `flags`, separated by spaces.
+fn flag_names(flags: &[&Flag]) -> String {
+    flags
+        .iter()
+        .flat_map(|flag| flag.names.iter().copied())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lists every name of every global flag that takes a value, separated by
+/// `separator`. Whatever comes after one of these is its value, not the
+/// command, so the scripts need to know to skip it.
+fn value_flags(flags: &[&Flag], separator: &str) -> String {
+    flags
+        .iter()
+        .filter(|flag| flag.value.is_some())
+        .flat_map(|flag| flag.names.iter().copied())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Quotes `text` so that a POSIX shell reads it as a single word, exactly as
+/// it is.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+fn bash(commands: &[Command], globals: &[&Flag]) -> String {
+    let mut cases = String::new();
+
+    for command in commands {
+        let flags: Vec<&Flag> = command.flags.iter().collect();
+
+        let _ = writeln!(
+            cases,
+            "        {}) flags={}; completes={} ;;",
+            command.name,
+            quote(&flag_names(&flags)),
+            completes(command).name()
+        );
+    }
+
+    let names: Vec<&str> = commands.iter().map(|command| command.name).collect();
+
+    format!(
+        r#"# Completion for rat in bash. Add this to your .bashrc:
+#
+#     eval "$(rat completions bash)"
+
+_rat_refs() {{
+    rat branch 2>/dev/null | cut -c3-
+    rat tag 2>/dev/null
+}}
+
+_rat() {{
+    local cur=${{COMP_WORDS[COMP_CWORD]}}
+    local commands={commands}
+    local globals={globals}
+    local command="" flags="" completes="" i
+
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case ${{COMP_WORDS[i]}} in
+            {value_flags}) ((i++)) ;;
+            -*) ;;
+            *) command=${{COMP_WORDS[i]}}; break ;;
+        esac
+    done
+
+    if [[ -z $command ]]; then
+        if [[ $cur == -* ]]; then
+            COMPREPLY=($(compgen -W "$globals --version" -- "$cur"))
+        else
+            COMPREPLY=($(compgen -W "$commands" -- "$cur"))
+        fi
+        return
+    fi
+
+    case $command in
+{cases}    esac
+
+    if [[ $cur == -* ]]; then
+        COMPREPLY=($(compgen -W "$flags $globals" -- "$cur"))
+    elif [[ $completes == refs ]]; then
+        COMPREPLY=($(compgen -W "$(_rat_refs)" -- "$cur"))
+    elif [[ $completes == files ]]; then
+        COMPREPLY=($(compgen -f -- "$cur"))
+    elif [[ $completes == commands ]]; then
+        COMPREPLY=($(compgen -W "$commands" -- "$cur"))
+    fi
+}}
+
+complete -F _rat rat
+"#,
+        commands = quote(&names.join(" ")),
+        globals = quote(&flag_names(globals)),
+        value_flags = value_flags(globals, "|"),
+    )
+}
+
+/// Escapes `text` for one of zsh's `name:description` pairs, where a colon
+/// would otherwise end the name.
+fn zsh_pair(name: &str, description: &str) -> String {
+    quote(&format!("{}:{description}", name.replace(':', r"\:")))
+}
+
+/// Lists every flag in `flags` as zsh `name:description` pairs, one for each
+/// of its names.
+fn zsh_flags(flags: &[&Flag]) -> String {
+    flags
+        .iter()
+        .flat_map(|flag| flag.names.iter().map(|name| zsh_pair(name, flag.help)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn zsh(commands: &[Command], globals: &[&Flag]) -> String {
+    let mut cases = String::new();
+
+    for command in commands {
+        let flags: Vec<&Flag> = command.flags.iter().collect();
+
+        let _ = writeln!(
+            cases,
+            "        {}) flags=({}); completes={} ;;",
+            command.name,
+            zsh_flags(&flags),
+            completes(command).name()
+        );
+    }
+
+    let pairs: Vec<String> = commands
+        .iter()
+        .map(|command| zsh_pair(command.name, command.summary))
+        .collect();
+
+    format!(
+        r#"#compdef rat
+# Completion for rat in zsh. Add this to your .zshrc, after compinit:
+#
+#     eval "$(rat completions zsh)"
+
+_rat_refs() {{
+    rat branch 2>/dev/null | cut -c3-
+    rat tag 2>/dev/null
+}}
+
+_rat() {{
+    local -a commands globals flags
+    local command completes i
+    commands=({commands})
+    globals=({globals})
+
+    for ((i = 2; i < CURRENT; i++)); do
+        case ${{words[i]}} in
+            {value_flags}) ((i++)) ;;
+            -*) ;;
+            *) command=${{words[i]}}; break ;;
+        esac
+    done
+
+    if [[ -z $command ]]; then
+        if [[ $PREFIX == -* ]]; then
+            _describe option globals
+        else
+            _describe command commands
+        fi
+        return
+    fi
+
+    case $command in
+{cases}    esac
+
+    if [[ $PREFIX == -* ]]; then
+        _describe option flags -- globals
+    elif [[ $completes == refs ]]; then
+        compadd -- ${{(f)"$(_rat_refs)"}}
+    elif [[ $completes == files ]]; then
+        _files
+    elif [[ $completes == commands ]]; then
+        _describe command commands
+    fi
+}}
+
+compdef _rat rat
+"#,
+        commands = pairs.join(" "),
+        globals = zsh_flags(globals),
+        value_flags = value_flags(globals, "|"),
+    )
+}
+
+/// Writes the fish `complete` options that match every name of `flag`.
+fn fish_flag(flag: &Flag) -> String {
+    let mut options = String::new();
+
+    for name in flag.names {
+        // Fish tells long flags, single-letter ones and longer ones with a
+        // single dash, like -vv, apart.
+        let _ = match (name.strip_prefix("--"), name.strip_prefix('-')) {
+            (Some(long), _) => write!(options, " -l {long}"),
+            (None, Some(short)) if short.chars().count() == 1 => write!(options, " -s {short}"),
+            (None, Some(old)) => write!(options, " -o {old}"),
+            (None, None) => Ok(()),
+        };
+    }
+
+    if flag.value.is_some() {
+        options.push_str(" -r");
+    }
+
+    format!("{options} -d {}", quote(flag.help))
+}
+
+fn fish(commands: &[Command], globals: &[&Flag]) -> String {
+    let mut lines = String::new();
+
+    for command in commands {
+        let _ = writeln!(
+            lines,
+            "complete -c rat -n 'not __rat_command >/dev/null' -a {} -d {}",
+            command.name,
+            quote(command.summary)
+        );
+    }
+
+    for flag in globals {
+        let _ = writeln!(lines, "complete -c rat{}", fish_flag(flag));
+    }
+
+    for command in commands {
+        let condition = format!("-n '__rat_using {}'", command.name);
+
+        for flag in command.flags {
+            let _ = writeln!(lines, "complete -c rat {condition}{}", fish_flag(flag));
+        }
+
+        let _ = match completes(command) {
+            Completes::Refs => writeln!(lines, "complete -c rat {condition} -a '(__rat_refs)'"),
+            Completes::Files => writeln!(lines, "complete -c rat {condition} -F"),
+            Completes::Commands => writeln!(
+                lines,
+                "complete -c rat {condition} -a {}",
+                quote(
+                    &commands
+                        .iter()
+                        .map(|command| command.name)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                )
+            ),
+            Completes::Nothing => Ok(()),
+        };
+    }
+
+    format!(
+        r#"# Completion for rat in fish. Save this as
+# ~/.config/fish/completions/rat.fish, or run:
+#
+#     rat completions fish | source
+
+function __rat_command
+    set -l words (commandline -opc)
+    set -e words[1]
+    set -l skip 0
+
+    for word in $words
+        if test $skip = 1
+            set skip 0
+            continue
+        end
+
+        switch $word
+            case {value_flags}
+                set skip 1
+            case '-*'
+            case '*'
+                echo $word
+                return 0
+        end
+    end
+
+    return 1
+end
+
+function __rat_using
+    set -l command (__rat_command)
+    and test "$command" = $argv[1]
+end
+
+function __rat_refs
+    rat branch 2>/dev/null | string sub -s 3
+    rat tag 2>/dev/null
+end
+
+complete -c rat -f
+complete -c rat -n 'not __rat_command >/dev/null' -l version -d 'Show which version of rat this is.'
+{lines}"#,
+        value_flags = value_flags(globals, " "),
+    )
+}
+
+/// Quotes `text` as a PowerShell string that's read exactly as it is.
+fn powershell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+/// Lists `names` as a PowerShell array.
+fn powershell_array<'a>(names: impl IntoIterator<Item = &'a str>) -> String {
+    let quoted: Vec<String> = names.into_iter().map(powershell_quote).collect();
+
+    format!("@({})", quoted.join(", "))
+}
+
+fn powershell(commands: &[Command], globals: &[&Flag]) -> String {
+    let mut flag_cases = String::new();
+    let mut completes_cases = String::new();
+
+    for command in commands {
+        let names = command
+            .flags
+            .iter()
+            .flat_map(|flag| flag.names.iter().copied());
+
+        let _ = writeln!(
+            flag_cases,
+            "        {} {{ {} }}",
+            powershell_quote(command.name),
+            powershell_array(names)
+        );
+        let _ = writeln!(
+            completes_cases,
+            "        {} {{ {} }}",
+            powershell_quote(command.name),
+            powershell_quote(completes(command).name())
+        );
+    }
+
+    let global_names = globals.iter().flat_map(|flag| flag.names.iter().copied());
+    let value_names = globals
+        .iter()
+        .filter(|flag| flag.value.is_some())
+        .flat_map(|flag| flag.names.iter().copied());
+
+    format!(
+        r#"# Completion for rat in PowerShell. Add this to your $PROFILE:
+#
+#     rat completions powershell | Out-String | Invoke-Expression
+
+Register-ArgumentCompleter -Native -CommandName rat -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+
+    $commands = {commands}
+    $globals = {globals}
+    $valueFlags = {value_flags}
+
+    # Only the words before the one being completed say where we are.
+    $words = $commandAst.CommandElements |
+        Select-Object -Skip 1 |
+        Where-Object {{ $_.Extent.EndOffset -lt $cursorPosition }} |
+        ForEach-Object {{ $_.ToString() }}
+
+    $command = $null
+    $skip = $false
+
+    foreach ($word in $words) {{
+        if ($skip) {{ $skip = $false; continue }}
+        if ($valueFlags -contains $word) {{ $skip = $true; continue }}
+        if ($word.StartsWith('-')) {{ continue }}
+
+        $command = $word
+        break
+    }}
+
+    if (-not $command) {{
+        if ($wordToComplete.StartsWith('-')) {{
+            $candidates = $globals + @('--version')
+        }} else {{
+            $candidates = $commands
+        }}
+    }} else {{
+        $flags = switch ($command) {{
+{flag_cases}            default {{ @() }}
+        }}
+        $completes = switch ($command) {{
+{completes_cases}            default {{ 'nothing' }}
+        }}
+
+        if ($wordToComplete.StartsWith('-')) {{
+            $candidates = $flags + $globals
+        }} elseif ($completes -eq 'refs') {{
+            $branches = rat branch 2>$null | ForEach-Object {{ $_.Substring(2) }}
+            $tags = rat tag 2>$null
+            $candidates = @($branches) + @($tags)
+        }} elseif ($completes -eq 'commands') {{
+            $candidates = $commands
+        }} else {{
+            # Returning nothing leaves it to PowerShell to complete files.
+            return
+        }}
+    }}
+
+    $candidates |
+        Where-Object {{ $_ -like "$wordToComplete*" }} |
+        ForEach-Object {{
+            [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+        }}
+}}
+"#,
+        commands = powershell_array(commands.iter().map(|command| command.name)),
+        globals = powershell_array(global_names),
+        value_flags = powershell_array(value_names),
+    )
+}
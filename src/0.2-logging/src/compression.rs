@@ -0,0 +1,111 @@
+//! Compressing objects before they're stored, to trade time for disk space.
+//!
+//! Objects are stored as they are unless `core.compression` is set, which is
+//! quickest, and fine for source code. For nests full of big binary files,
+//! it can be set to `zlib` or `zstd` to compress every object written from
+//! then on, whether it's loose or in a [`pack`](crate::pack). Either can be
+//! followed by a level, like `zstd:19`, from 1 for fastest up to 9 for zlib
+//! or 19 for zstd. A number on its own is a zlib level, like in git, and
+//! `none` or `0` turns compression back off.
+//!
+//! Changing the setting never touches objects that are already stored, and
+//! they can always be read, since how an object is stored can be told from
+//! its first few bytes. An object stored as it is starts with its kind, a
+//! zlib stream always starts with `x`, and a zstd frame starts with a magic
+//! number that isn't text at all. `rat repack -a` rewrites everything in the
+//! packs with the current setting.
+
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::config::Config;
+use crate::{deflate, zstd};
+
+/// How objects are compressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Zlib(u32),
+    Zstd(u32),
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{s} isn't a compression setting.");
+
+        let (algorithm, level) = match s.split_once(':') {
+            Some((algorithm, level)) => {
+                let level: u32 = level.parse().map_err(|_| invalid())?;
+                (algorithm, Some(level))
+            }
+            // Like git, a number on its own is a zlib level.
+            None => match s.parse::<u32>() {
+                Ok(level) => ("zlib", Some(level)),
+                Err(_) => (s, None),
+            },
+        };
+
+        match (algorithm, level) {
+            ("none", None) | ("zlib", Some(0)) => Ok(Self::None),
+            ("zlib", None) => Ok(Self::Zlib(deflate::DEFAULT_LEVEL)),
+            ("zlib", Some(level @ 1..=deflate::MAX_LEVEL)) => Ok(Self::Zlib(level)),
+            ("zstd", None) => Ok(Self::Zstd(zstd::DEFAULT_LEVEL)),
+            ("zstd", Some(level @ 1..=zstd::MAX_LEVEL)) => Ok(Self::Zstd(level)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Compression {
+    /// Compresses `data`, returning `None` if this doesn't compress anything
+    /// or compressing didn't make it any smaller, in which case it should be
+    /// stored as it is.
+    pub fn compress(self, data: &[u8]) -> Option<Vec<u8>> {
+        let compressed = match self {
+            Self::None => return None,
+            Self::Zlib(level) => deflate::compress_zlib(data, level),
+            Self::Zstd(level) => zstd::compress(data, level),
+        };
+
+        (compressed.len() < data.len()).then_some(compressed)
+    }
+}
+
+/// Finds how objects should be compressed, from the `core.compression`
+/// setting. A setting we don't understand is the same as none at all.
+pub fn current() -> Compression {
+    // Every object we store needs to know, so we only read the config once.
+    static COMPRESSION: OnceLock<Compression> = OnceLock::new();
+
+    *COMPRESSION.get_or_init(|| {
+        Config::load()
+            .ok()
+            .and_then(|config| config.get("core.compression")?.parse().ok())
+            .unwrap_or_default()
+    })
+}
+
+/// What every zstd frame starts with.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Checks whether `stored` was compressed, which its first few bytes are
+/// enough to tell.
+pub fn is_compressed(stored: &[u8]) -> bool {
+    stored.starts_with(&ZSTD_MAGIC) || stored.first() == Some(&0x78)
+}
+
+/// Undoes whatever compression `stored` was stored with, returning it as it
+/// is if it wasn't compressed, or `None` if it was and it's damaged.
+pub fn decompress(stored: &[u8]) -> Option<Cow<'_, [u8]>> {
+    if stored.starts_with(&ZSTD_MAGIC) {
+        zstd::decompress(stored).map(Cow::Owned)
+    } else if is_compressed(stored) {
+        deflate::decompress_zlib(stored).map(Cow::Owned)
+    } else {
+        Some(Cow::Borrowed(stored))
+    }
+}
@@ -0,0 +1,491 @@
+//! Reading and writing configuration files.
+//!
+//! Configuration lives in two places: `~/.ratconfig` holds settings for the
+//! current user across every nest, and `.rat/config` holds settings for one
+//! particular nest, which take priority. Both use the same INI-style format as
+//! git's config files:
+//!
+//! ```text
+//! [user]
+//!     name = Ada Lovelace
+//!     email = ada@example.com
+//!
+//! [remote "origin"]
+//!     url = ../thesis
+//! ```
+//!
+//! Settings are referred to by joining the section, the optional subsection in
+//! quotes, and the key with dots, like `user.name` or `remote.origin.url`.
+//! Section and key names are case-insensitive, but subsection names aren't.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::ConfigError;
+use crate::utils;
+
+/// A single `[section]` or `[section "subsection"]` along with the settings
+/// inside it, in the order they appear in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Section {
+    name: String,
+    subsection: Option<String>,
+    entries: Vec<(String, String)>,
+}
+
+impl Section {
+    /// The prefix that keys in this section start with, like `user.` or
+    /// `remote.origin.`.
+    fn prefix(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!("{}.{subsection}.", self.name),
+            None => format!("{}.", self.name),
+        }
+    }
+}
+
+/// The contents of a single config file.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    sections: Vec<Section>,
+}
+
+impl ConfigFile {
+    /// Reads and parses the config file at `path`. A file that doesn't exist is
+    /// treated as empty, since having no configuration is perfectly normal.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(source) => {
+                return Err(ConfigError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+
+        let mut sections: Vec<Section> = Vec::new();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            let invalid = || ConfigError::InvalidLine {
+                path: path.to_path_buf(),
+                number: line_number + 1,
+                line: line.to_string(),
+            };
+
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header.strip_suffix(']').ok_or_else(invalid)?;
+
+                let (name, subsection) = match header.split_once(' ') {
+                    Some((name, subsection)) => {
+                        let subsection = subsection
+                            .trim()
+                            .strip_prefix('"')
+                            .and_then(|s| s.strip_suffix('"'))
+                            .ok_or_else(invalid)?;
+
+                        (name, Some(subsection.to_string()))
+                    }
+                    None => (header, None),
+                };
+
+                sections.push(Section {
+                    name: name.to_lowercase(),
+                    subsection,
+                    entries: Vec::new(),
+                });
+
+                continue;
+            }
+
+            // Every setting has to be inside a section.
+            let section = sections.last_mut().ok_or_else(invalid)?;
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+
+            section
+                .entries
+                .push((key.trim().to_lowercase(), unquote(value.trim())));
+        }
+
+        Ok(Self { sections })
+    }
+
+    /// Serializes the config and writes it to `path`.
+    ///
+    /// Note that since we only keep track of the sections and settings, any
+    /// comments in the original file are lost.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), io::Error> {
+        let mut contents = String::new();
+
+        for section in &self.sections {
+            match &section.subsection {
+                Some(subsection) => {
+                    contents.push_str(&format!("[{} \"{subsection}\"]\n", section.name))
+                }
+                None => contents.push_str(&format!("[{}]\n", section.name)),
+            }
+
+            for (key, value) in &section.entries {
+                contents.push_str(&format!("\t{key} = {}\n", quote(value)));
+            }
+        }
+
+        utils::write_atomically(path, contents)
+    }
+
+    /// Lists every setting in the file as a full key and its value, in the
+    /// order they appear.
+    pub fn entries(&self) -> impl Iterator<Item = (String, &str)> {
+        self.sections.iter().flat_map(|section| {
+            let prefix = section.prefix();
+
+            section
+                .entries
+                .iter()
+                .map(move |(key, value)| (format!("{prefix}{key}"), value.as_str()))
+        })
+    }
+
+    /// Sets `key` to `value`, replacing the last existing value for that key if
+    /// there is one, and adding it to the end of its section (creating the
+    /// section if necessary) otherwise.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let (name, subsection, key) = split_key(key)?;
+
+        let existing = self.sections.iter().rposition(|section| {
+            section.name == name && section.subsection.as_deref() == subsection
+        });
+
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                self.sections.push(Section {
+                    name: name.clone(),
+                    subsection: subsection.map(str::to_string),
+                    entries: Vec::new(),
+                });
+
+                self.sections.len() - 1
+            }
+        };
+
+        let section = &mut self.sections[index];
+
+        match section.entries.iter_mut().rev().find(|(k, _)| *k == key) {
+            Some((_, existing_value)) => *existing_value = value.to_string(),
+            None => section.entries.push((key, value.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Adds another value for `key`, keeping any it already has, for settings
+    /// that can have more than one value, like `remote.<name>.pushurl`.
+    pub fn add(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let (name, subsection, key) = split_key(key)?;
+
+        let existing =
+            self.sections.iter_mut().rev().find(|section| {
+                section.name == name && section.subsection.as_deref() == subsection
+            });
+
+        match existing {
+            Some(section) => section.entries.push((key, value.to_string())),
+            None => self.sections.push(Section {
+                name,
+                subsection: subsection.map(str::to_string),
+                entries: vec![(key, value.to_string())],
+            }),
+        }
+
+        Ok(())
+    }
+
+    /// Removes every value of `key`, returning whether it had any.
+    pub fn unset_all(&mut self, key: &str) -> Result<bool, ConfigError> {
+        let (name, subsection, key) = split_key(key)?;
+        let mut removed = false;
+
+        for section in &mut self.sections {
+            if section.name == name && section.subsection.as_deref() == subsection {
+                let before = section.entries.len();
+                section.entries.retain(|(k, _)| *k != key);
+                removed |= section.entries.len() != before;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes every `[name "subsection"]` section, along with all of the
+    /// settings inside it, returning whether there were any.
+    pub fn remove_section(&mut self, name: &str, subsection: Option<&str>) -> bool {
+        let before = self.sections.len();
+        let name = name.to_lowercase();
+
+        self.sections
+            .retain(|section| section.name != name || section.subsection.as_deref() != subsection);
+
+        self.sections.len() != before
+    }
+}
+
+/// Splits a key like `remote.origin.url` into its section, optional
+/// subsection, and the final key name, normalizing the case of the parts that
+/// are case-insensitive.
+fn split_key(key: &str) -> Result<(String, Option<&str>, String), ConfigError> {
+    let invalid = || ConfigError::InvalidKey {
+        key: key.to_string(),
+    };
+
+    let (section, rest) = key.split_once('.').ok_or_else(invalid)?;
+
+    // Subsections can themselves contain dots, so the key name is whatever
+    // comes after the last one.
+    let (subsection, name) = match rest.rsplit_once('.') {
+        Some((subsection, name)) => (Some(subsection), name),
+        None => (None, rest),
+    };
+
+    if section.is_empty() || name.is_empty() {
+        Err(invalid())?;
+    }
+
+    Ok((section.to_lowercase(), subsection, name.to_lowercase()))
+}
+
+/// Normalizes a key given by the user so it can be compared with the keys
+/// produced by [`ConfigFile::entries`].
+fn normalize_key(key: &str) -> Result<String, ConfigError> {
+    let (section, subsection, name) = split_key(key)?;
+
+    Ok(match subsection {
+        Some(subsection) => format!("{section}.{subsection}.{name}"),
+        None => format!("{section}.{name}"),
+    })
+}
+
+/// Removes the surrounding quotes from a value, if it has them.
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+/// Adds quotes around a value if it would otherwise lose leading or trailing
+/// whitespace when read back in.
+fn quote(value: &str) -> String {
+    if value.trim() != value {
+        format!("\"{value}\"")
+    } else {
+        value.to_string()
+    }
+}
+
+/// The location of the config file for the current user, which lives in their
+/// home directory.
+pub fn user_config_path() -> Option<PathBuf> {
+    // Unix systems store the home directory in $HOME, while Windows uses
+    // $USERPROFILE instead.
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".ratconfig"))
+}
+
+/// The location of the config file for the current nest.
+pub fn nest_config_path() -> PathBuf {
+    crate::nest_path("config")
+}
+
+/// The combined configuration from every config file that applies to the
+/// current nest.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// The files in increasing order of priority.
+    files: Vec<ConfigFile>,
+}
+
+impl Config {
+    /// Loads the user config and then the nest config, if there is one.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut files = Vec::new();
+
+        if let Some(path) = user_config_path() {
+            files.push(ConfigFile::read(path)?);
+        }
+
+        files.push(ConfigFile::read(nest_config_path())?);
+
+        Ok(Self { files })
+    }
+
+    /// Combines the given files, which should be in increasing order of
+    /// priority.
+    pub fn from_files(files: Vec<ConfigFile>) -> Self {
+        Self { files }
+    }
+
+    /// Lists every setting from every file, in increasing order of priority.
+    pub fn entries(&self) -> impl Iterator<Item = (String, &str)> {
+        self.files.iter().flat_map(ConfigFile::entries)
+    }
+
+    /// Gets the value of `key`. If it's set more than once, the last value
+    /// wins, which means the nest config overrides the user config.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key).ok()?;
+
+        self.entries()
+            .filter(|(k, _)| *k == key)
+            .last()
+            .map(|(_, value)| value)
+    }
+
+    /// Gets every value of `key`, from every file, in increasing order of
+    /// priority.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let Ok(key) = normalize_key(key) else {
+            return Vec::new();
+        };
+
+        self.entries()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    /// A path in the temporary directory that no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rat-test-{}-config-{name}", process::id()))
+    }
+
+    /// Parses `contents` as a config file.
+    fn parse(name: &str, contents: &str) -> Result<ConfigFile, ConfigError> {
+        let path = temp_path(name);
+        fs::write(&path, contents).unwrap();
+
+        let file = ConfigFile::read(&path);
+        fs::remove_file(&path).unwrap();
+
+        file
+    }
+
+    #[test]
+    fn parses_sections_subsections_and_comments() {
+        let file = parse(
+            "parse",
+            "# Who I am\n\
+             [User]\n\
+             \tName = Ada Lovelace\n\
+             \temail=ada@example.com\n\
+             ; Where things go\n\
+             [remote \"Origin.v2\"]\n\
+             \turl = \" ../thesis \"\n",
+        )
+        .unwrap();
+
+        let entries: Vec<(String, &str)> = file.entries().collect();
+
+        assert_eq!(
+            entries,
+            [
+                ("user.name".to_string(), "Ada Lovelace"),
+                ("user.email".to_string(), "ada@example.com"),
+                ("remote.Origin.v2.url".to_string(), " ../thesis "),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        for contents in [
+            "name = outside a section\n",
+            "[user\n",
+            "[user]\nno equals sign\n",
+        ] {
+            assert!(
+                matches!(
+                    parse("invalid", contents),
+                    Err(ConfigError::InvalidLine { number: 1 | 2, .. })
+                ),
+                "{contents}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_missing_file_is_empty() {
+        let file = ConfigFile::read(temp_path("missing")).unwrap();
+
+        assert_eq!(file.entries().count(), 0);
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let path = temp_path("round-trip");
+        let mut file = ConfigFile::default();
+        file.set("user.name", "Ada").unwrap();
+        file.set("remote.origin.url", "  spaced out  ").unwrap();
+        file.add("remote.origin.pushurl", "one").unwrap();
+        file.add("remote.origin.pushurl", "two").unwrap();
+
+        file.write(&path).unwrap();
+        let read = ConfigFile::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.sections, file.sections);
+    }
+
+    #[test]
+    fn setting_replaces_and_unsetting_removes() {
+        let mut file = ConfigFile::default();
+        file.set("core.editor", "vi").unwrap();
+        file.set("CORE.Editor", "nano").unwrap();
+
+        assert_eq!(
+            file.entries().collect::<Vec<_>>(),
+            [("core.editor".to_string(), "nano")]
+        );
+        assert!(file.unset_all("core.editor").unwrap());
+        assert!(!file.unset_all("core.editor").unwrap());
+        assert!(file.set("nodot", "x").is_err());
+    }
+
+    #[test]
+    fn later_files_win() {
+        let mut user = ConfigFile::default();
+        user.set("user.name", "Ada").unwrap();
+        user.set("user.email", "ada@example.com").unwrap();
+        user.add("remote.origin.pushurl", "one").unwrap();
+
+        let mut nest = ConfigFile::default();
+        nest.set("user.name", "Charles").unwrap();
+        nest.add("remote.origin.pushurl", "two").unwrap();
+
+        let config = Config::from_files(vec![user, nest]);
+
+        assert_eq!(config.get("User.Name"), Some("Charles"));
+        assert_eq!(config.get("user.email"), Some("ada@example.com"));
+        assert_eq!(config.get_all("remote.origin.pushurl"), ["one", "two"]);
+        assert_eq!(config.get("user.missing"), None);
+    }
+}
@@ -0,0 +1,751 @@
+//! DEFLATE, the compression used by gzip and zip files, and by git for every
+//! object it stores.
+//!
+//! DEFLATE works in two steps. First, anything that's already appeared in
+//! the last 32 KiB is replaced by a note saying how far back it was and how
+//! long it is. Then everything is written with Huffman codes, so that common
+//! bytes and lengths take fewer bits.
+//!
+//! We find repeats with a hash table keyed by the next three bytes, which
+//! points at the last place those three bytes appeared, with a chain back to
+//! every place before that. For the codes, we use the fixed ones that the
+//! format defines, rather than working out the best ones for each file.
+//! That compresses a little less, but it's much simpler, and every
+//! decompressor understands it.
+//!
+//! Decompressing has to understand everything the format allows, since we
+//! don't get to choose how other programs compress things. That includes
+//! blocks that aren't compressed at all, and blocks that start by describing
+//! their own Huffman codes.
+
+/// How far back a repeat can be.
+const WINDOW_SIZE: usize = 32 * 1024;
+
+/// The shortest and longest repeats the format can describe.
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// The level we compress at unless asked otherwise, which is zlib's default
+/// too.
+pub const DEFAULT_LEVEL: u32 = 6;
+
+/// The highest level there is.
+pub const MAX_LEVEL: u32 = 9;
+
+/// How many earlier places with the same three bytes we try before settling
+/// for the best repeat so far, at each level from 1 up. More compresses
+/// slightly better, but more slowly. Level 0 doesn't look for repeats at
+/// all, and stores everything as it is.
+const MAX_CHAINS: [usize; MAX_LEVEL as usize] = [4, 8, 16, 32, 48, 64, 128, 256, 1024];
+
+/// The most a stored block can hold, since its length is two bytes.
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+/// The first length for each length code from 257, and how many extra bits
+/// follow it to say exactly which length it is.
+const LENGTH_BASES: [(u16, u8); 29] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 1),
+    (13, 1),
+    (15, 1),
+    (17, 1),
+    (19, 2),
+    (23, 2),
+    (27, 2),
+    (31, 2),
+    (35, 3),
+    (43, 3),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 4),
+    (115, 4),
+    (131, 5),
+    (163, 5),
+    (195, 5),
+    (227, 5),
+    (258, 0),
+];
+
+/// The first distance for each distance code, and how many extra bits follow.
+const DISTANCE_BASES: [(u16, u8); 30] = [
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 1),
+    (7, 1),
+    (9, 2),
+    (13, 2),
+    (17, 3),
+    (25, 3),
+    (33, 4),
+    (49, 4),
+    (65, 5),
+    (97, 5),
+    (129, 6),
+    (193, 6),
+    (257, 7),
+    (385, 7),
+    (513, 8),
+    (769, 8),
+    (1025, 9),
+    (1537, 9),
+    (2049, 10),
+    (3073, 10),
+    (4097, 11),
+    (6145, 11),
+    (8193, 12),
+    (12289, 12),
+    (16385, 13),
+    (24577, 13),
+];
+
+/// Writes bits into bytes starting from the lowest bit, which is the order
+/// DEFLATE uses.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest `count` bits of `value`, lowest first.
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= u64::from(value) << self.count;
+        self.count += count;
+
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which unlike everything else goes highest bit
+    /// first.
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// Skips to the start of the next byte.
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+            self.bits = 0;
+            self.count = 0;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+
+        self.out
+    }
+}
+
+/// Writes a literal byte or the end of the block (256) or a length code
+/// (257 and up) with the fixed codes.
+fn write_symbol(writer: &mut BitWriter, symbol: u16) {
+    let symbol = u32::from(symbol);
+
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+/// Writes a repeat of `length` bytes from `distance` bytes back.
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    // Each table is sorted, so the code we want is the last one that starts
+    // at or before the number we're writing.
+    let code = LENGTH_BASES.partition_point(|&(base, _)| usize::from(base) <= length) - 1;
+    let (base, extra) = LENGTH_BASES[code];
+
+    write_symbol(writer, 257 + code as u16);
+    writer.write((length - usize::from(base)) as u32, u32::from(extra));
+
+    let code = DISTANCE_BASES.partition_point(|&(base, _)| usize::from(base) <= distance) - 1;
+    let (base, extra) = DISTANCE_BASES[code];
+
+    // Distance codes are all five bits long.
+    writer.write_code(code as u32, 5);
+    writer.write((distance - usize::from(base)) as u32, u32::from(extra));
+}
+
+/// Compresses `data` into a raw DEFLATE stream, as it goes inside a gzip or
+/// zip file.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    compress_level(data, DEFAULT_LEVEL)
+}
+
+/// Compresses `data` into a raw DEFLATE stream at `level`, from 0 for not
+/// compressing at all up to [`MAX_LEVEL`] for trying hardest.
+pub fn compress_level(data: &[u8], level: u32) -> Vec<u8> {
+    let mut writer = BitWriter {
+        out: Vec::new(),
+        bits: 0,
+        count: 0,
+    };
+
+    if level == 0 {
+        // Even nothing at all needs a block to say so.
+        let blocks: Vec<&[u8]> = match data.is_empty() {
+            true => vec![&[]],
+            false => data.chunks(MAX_STORED_BLOCK).collect(),
+        };
+
+        for (i, block) in blocks.iter().enumerate() {
+            writer.write(u32::from(i + 1 == blocks.len()), 1);
+            writer.write(0, 2);
+            writer.align();
+
+            let length = block.len() as u16;
+            writer.out.extend_from_slice(&length.to_le_bytes());
+            writer.out.extend_from_slice(&(!length).to_le_bytes());
+            writer.out.extend_from_slice(block);
+        }
+
+        return writer.finish();
+    }
+
+    let max_chain = MAX_CHAINS[level.min(MAX_LEVEL) as usize - 1];
+
+    // Everything goes in a single final block using the fixed codes.
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    let mut chains = Chains {
+        head: vec![usize::MAX; HASH_SIZE],
+        previous: vec![usize::MAX; data.len()],
+    };
+
+    let mut position = 0;
+
+    while position < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+
+        if position + MIN_MATCH <= data.len() {
+            let mut candidate = chains.head[hash_at(data, position)];
+            let mut chain = 0;
+
+            while candidate != usize::MAX
+                && position - candidate <= WINDOW_SIZE
+                && chain < max_chain
+            {
+                let longest = (data.len() - position).min(MAX_MATCH);
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[position..position + longest])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if length > best_length {
+                    best_length = length;
+                    best_distance = position - candidate;
+
+                    if length == longest {
+                        break;
+                    }
+                }
+
+                candidate = chains.previous[candidate];
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_distance);
+
+            for skipped in position..position + best_length {
+                chains.insert(data, skipped);
+            }
+
+            position += best_length;
+        } else {
+            write_symbol(&mut writer, u16::from(data[position]));
+            chains.insert(data, position);
+            position += 1;
+        }
+    }
+
+    write_symbol(&mut writer, 256);
+
+    writer.finish()
+}
+
+/// How many different hashes of three bytes there are.
+const HASH_SIZE: usize = 1 << 15;
+
+fn hash_at(data: &[u8], position: usize) -> usize {
+    let bytes = &data[position..position + MIN_MATCH];
+    let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+
+    (value.wrapping_mul(2_654_435_761) >> 17) as usize % HASH_SIZE
+}
+
+/// Where each hash of three bytes has been seen so far.
+struct Chains {
+    /// The last place each hash was seen, or `usize::MAX` if it hasn't been.
+    head: Vec<usize>,
+    /// For each place, the place before it with the same hash.
+    previous: Vec<usize>,
+}
+
+impl Chains {
+    fn insert(&mut self, data: &[u8], position: usize) {
+        if position + MIN_MATCH <= data.len() {
+            let hash = hash_at(data, position);
+            self.previous[position] = self.head[hash];
+            self.head[hash] = position;
+        }
+    }
+}
+
+/// The CRC-32 of `data`, which gzip and zip files use to check that nothing
+/// got damaged.
+pub fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+
+        while i < 256 {
+            let mut value = i as u32;
+            let mut bit = 0;
+
+            while bit < 8 {
+                value = match value & 1 {
+                    1 => 0xedb8_8320 ^ (value >> 1),
+                    _ => value >> 1,
+                };
+                bit += 1;
+            }
+
+            table[i] = value;
+            i += 1;
+        }
+
+        table
+    };
+
+    !data.iter().fold(!0, |crc, &byte| {
+        TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Reads bits out of bytes starting from the lowest bit, which is the order
+/// DEFLATE uses.
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// The next byte to read into `bits`.
+    position: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    /// Reads `count` bits, lowest first, or `None` if the data runs out.
+    fn read(&mut self, count: u32) -> Option<u32> {
+        while self.count < count {
+            self.bits |= u64::from(*self.data.get(self.position)?) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+
+        let value = (self.bits & ((1 << count) - 1)) as u32;
+        self.bits >>= count;
+        self.count -= count;
+
+        Some(value)
+    }
+
+    /// Skips to the start of the next byte. We only ever read a byte when we
+    /// need some of its bits, so whatever's left is part of the current one.
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+/// A set of Huffman codes, stored as how many codes there are of each
+/// length, and which symbol each one stands for in order.
+///
+/// That's all we need because DEFLATE codes are canonical: the codes of each
+/// length are consecutive numbers, given to their symbols in order, and
+/// every length starts where the one before it left off.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the codes from the length of the code for each symbol, where a
+    /// length of zero means the symbol doesn't have one.
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+
+        counts[0] = 0;
+
+        // Where the symbols with each length start in the list.
+        let mut offsets = [0; 16];
+
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + usize::from(counts[length]);
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[usize::from(length)]] = symbol as u16;
+                offsets[usize::from(length)] += 1;
+            }
+        }
+
+        Self { counts, symbols }
+    }
+
+    /// Reads one code, a bit at a time, until it's long enough to be one of
+    /// ours.
+    fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        // The code read so far, the first code of the current length, and
+        // where the symbols of the current length start.
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+
+        for &count in &self.counts[1..] {
+            code |= reader.read(1)? as usize;
+            let count = usize::from(count);
+
+            if code - first < count {
+                return self.symbols.get(index + code - first).copied();
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        None
+    }
+}
+
+/// The fixed codes, which are used by blocks that don't bring their own.
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/// Reads the codes a block brings with it. They're described by their
+/// lengths, which are themselves Huffman coded, with a few extra symbols to
+/// say that a length is repeated.
+fn read_dynamic_codes(reader: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    /// The order the lengths of the codes for lengths come in, from the most
+    /// to the least likely to be used.
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    let literals = reader.read(5)? as usize + 257;
+    let distances = reader.read(5)? as usize + 1;
+    let length_codes = reader.read(4)? as usize + 4;
+
+    let mut lengths = [0; 19];
+
+    for &symbol in &ORDER[..length_codes] {
+        lengths[symbol] = reader.read(3)? as u8;
+    }
+
+    let length_code = Huffman::new(&lengths);
+    let mut lengths = Vec::with_capacity(literals + distances);
+
+    while lengths.len() < literals + distances {
+        let (length, repeat) = match length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + reader.read(2)?),
+            17 => (0, 3 + reader.read(3)?),
+            _ => (0, 11 + reader.read(7)?),
+        };
+
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+
+    if lengths.len() > literals + distances {
+        return None;
+    }
+
+    Some((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+/// Decompresses one block that uses Huffman codes, up to its end.
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Option<()> {
+    loop {
+        let symbol = literals.decode(reader)?;
+
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Some(()),
+            _ => {
+                let (base, extra) = *LENGTH_BASES.get(usize::from(symbol - 257))?;
+                let length = usize::from(base) + reader.read(u32::from(extra))? as usize;
+
+                let (base, extra) = *DISTANCE_BASES.get(usize::from(distances.decode(reader)?))?;
+                let distance = usize::from(base) + reader.read(u32::from(extra))? as usize;
+
+                let start = out.len().checked_sub(distance)?;
+
+                // The repeat can overlap what it's writing, like a distance of
+                // one repeating the last byte over and over, so it has to be
+                // copied a byte at a time.
+                for i in start..start + length {
+                    out.push(out[i]);
+                }
+            }
+        }
+    }
+}
+
+/// Decompresses a raw DEFLATE stream, returning the data along with how many
+/// bytes of `data` the stream took up, or `None` if it isn't valid.
+pub fn decompress(data: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut reader = BitReader {
+        data,
+        position: 0,
+        bits: 0,
+        count: 0,
+    };
+
+    let mut out = Vec::new();
+
+    loop {
+        let last = reader.read(1)? == 1;
+
+        match reader.read(2)? {
+            // A stored block is just its length, the same length with every
+            // bit flipped, and then that many bytes.
+            0 => {
+                reader.align();
+
+                let start = reader.position;
+                let header = data.get(start..start + 4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return None;
+                }
+
+                let end = start + 4 + usize::from(length);
+                out.extend_from_slice(data.get(start + 4..end)?);
+                reader.position = end;
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            _ => return None,
+        }
+
+        if last {
+            return Some((out, reader.position));
+        }
+    }
+}
+
+/// Decompresses a zlib stream, which is a DEFLATE stream with a two byte
+/// header and a checksum at the end. This is how git compresses its objects.
+pub fn decompress_zlib(data: &[u8]) -> Option<Vec<u8>> {
+    let &[method, flags, ..] = data else {
+        return None;
+    };
+
+    // The header has to say it's DEFLATE, must be a multiple of 31 when read
+    // as a number, and can't ask for a preset dictionary, which nobody uses.
+    if method & 0x0f != 8
+        || ((u16::from(method) << 8) | u16::from(flags)) % 31 != 0
+        || flags & 0x20 != 0
+    {
+        return None;
+    }
+
+    let (out, used) = decompress(&data[2..])?;
+    let checksum = data.get(2 + used..2 + used + 4)?;
+
+    (u32::from_be_bytes(checksum.try_into().ok()?) == adler32(&out)).then_some(out)
+}
+
+/// Compresses `data` into a zlib stream at `level`, which is how
+/// [`decompress_zlib`] expects it.
+pub fn compress_zlib(data: &[u8], level: u32) -> Vec<u8> {
+    // The header says it's DEFLATE with the biggest window, and roughly how
+    // hard we tried, and then has to be made a multiple of 31.
+    let method = 0x78;
+    let effort = match level {
+        0 | 1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    };
+    let flags = effort << 6;
+    let flags = flags + (31 - ((method << 8) | flags) % 31) % 31;
+
+    let mut out = vec![method as u8, flags as u8];
+    out.extend(compress_level(data, level));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+
+    out
+}
+
+/// The Adler-32 checksum zlib streams end with.
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+
+    let mut a = 1;
+    let mut b = 0;
+
+    // This is the most bytes we can add up before the sums could overflow.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+
+        a %= MODULUS;
+        b %= MODULUS;
+    }
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text with plenty of repeats, some of them further back than others.
+    fn sample() -> Vec<u8> {
+        let mut data = Vec::new();
+
+        for i in 0..200 {
+            data.extend_from_slice(format!("line {} of {}\n", i % 17, i * 31).as_bytes());
+        }
+
+        data.extend((0..1000).map(|i| (i * i % 256) as u8));
+        data
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn adler32_matches_the_check_value() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn decompresses_a_stored_block() {
+        let stream = [120, 1, 1, 3, 0, 252, 255, 97, 98, 99, 2, 77, 1, 39];
+
+        assert_eq!(decompress_zlib(&stream).as_deref(), Some(&b"abc"[..]));
+    }
+
+    #[test]
+    fn decompresses_a_block_with_fixed_codes() {
+        let stream = [
+            120, 156, 203, 72, 205, 201, 201, 87, 200, 64, 39, 1, 104, 3, 8, 177,
+        ];
+
+        assert_eq!(
+            decompress_zlib(&stream).as_deref(),
+            Some(&b"hello hello hello hello"[..])
+        );
+    }
+
+    #[test]
+    fn decompresses_a_block_with_its_own_codes() {
+        // What zlib itself makes of this at level 9.
+        let stream = [
+            120, 218, 117, 203, 219, 9, 128, 48, 12, 70, 225, 85, 50, 128, 184, 135, 99, 244, 242,
+            215, 6, 106, 35, 77, 164, 184, 189, 244, 73, 132, 250, 252, 157, 179, 25, 117, 167,
+            100, 25, 228, 161, 70, 146, 200, 248, 128, 46, 196, 175, 116, 105, 127, 228, 118, 12,
+            232, 172, 81, 142, 153, 36, 145, 194, 154, 43, 244, 59, 226, 148, 144, 71, 224, 81, 24,
+            105, 110, 92, 67, 67, 188, 10, 219, 189, 62, 201, 173, 59, 187,
+        ];
+        let text = "It was the best of times, it was the worst of times, it was the age of \
+                    wisdom, it was the age of foolishness, it was the epoch of belief, it was \
+                    the epoch of incredulity.";
+
+        assert_eq!(decompress_zlib(&stream).as_deref(), Some(text.as_bytes()));
+    }
+
+    #[test]
+    fn round_trips_at_every_level() {
+        let data = sample();
+
+        for level in 0..=MAX_LEVEL {
+            let compressed = compress_level(&data, level);
+
+            assert_eq!(
+                decompress(&compressed),
+                Some((data.clone(), compressed.len())),
+                "level {level}"
+            );
+        }
+    }
+
+    #[test]
+    fn zlib_round_trips() {
+        for data in [Vec::new(), b"a".to_vec(), sample()] {
+            let compressed = compress_zlib(&data, DEFAULT_LEVEL);
+
+            assert_eq!(decompress_zlib(&compressed), Some(data));
+        }
+    }
+
+    #[test]
+    fn zlib_rejects_a_damaged_checksum() {
+        let mut compressed = compress_zlib(&sample(), DEFAULT_LEVEL);
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+
+        assert_eq!(decompress_zlib(&compressed), None);
+    }
+}
@@ -0,0 +1,274 @@
+//! Describing one piece of data in terms of another.
+//!
+//! Two versions of the same file are usually almost identical, so rather than
+//! storing both in full, a [`pack`](crate::pack) can store one of them along
+//! with a *delta*: instructions for building the other one out of it. Each
+//! instruction either copies a run of bytes from the base, or inserts bytes
+//! that the base doesn't have.
+//!
+//! A delta starts with the lengths of the base and of the result, followed by
+//! the instructions:
+//!
+//! - `0`, a length and that many bytes, to insert them.
+//! - `1`, an offset and a length, to copy that many bytes of the base from
+//!   that offset.
+//!
+//! Every number is written as a varint, seven bits at a time with the lowest
+//! bits first, where the top bit of each byte says whether any more follow.
+//!
+//! To find what can be copied, we note where every [`BLOCK_SIZE`]-byte block
+//! of the base is, then slide along the result looking for those blocks. Each
+//! one we find is stretched out for as long as the two keep matching.
+
+use std::collections::HashMap;
+
+/// How long a run of bytes has to be before we go looking for it in the base.
+/// Shorter runs are cheaper to insert than to copy anyway.
+const BLOCK_SIZE: usize = 16;
+
+const INSERT: u8 = 0;
+const COPY: u8 = 1;
+
+/// Works out a delta that turns `base` into `target`.
+pub fn compute(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_varint(&mut delta, base.len() as u64);
+    write_varint(&mut delta, target.len() as u64);
+
+    // Where each block of the base starts. If a block appears more than once,
+    // any of them will do, so we keep the first.
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+
+    for start in (0..base.len() / BLOCK_SIZE).map(|i| i * BLOCK_SIZE) {
+        blocks
+            .entry(&base[start..start + BLOCK_SIZE])
+            .or_insert(start);
+    }
+
+    // Everything from pending up to the position we've got to will have to be
+    // inserted, unless it turns out to be part of a match.
+    let mut pending = 0;
+    let mut position = 0;
+
+    while position + BLOCK_SIZE <= target.len() {
+        let Some(&found) = blocks.get(&target[position..position + BLOCK_SIZE]) else {
+            position += 1;
+            continue;
+        };
+
+        // The match can stretch backwards into what we were going to insert,
+        // and forwards for as long as the two keep agreeing.
+        let (mut base_start, mut target_start) = (found, position);
+
+        while base_start > 0
+            && target_start > pending
+            && base[base_start - 1] == target[target_start - 1]
+        {
+            base_start -= 1;
+            target_start -= 1;
+        }
+
+        let (mut base_end, mut target_end) = (found + BLOCK_SIZE, position + BLOCK_SIZE);
+
+        while base_end < base.len()
+            && target_end < target.len()
+            && base[base_end] == target[target_end]
+        {
+            base_end += 1;
+            target_end += 1;
+        }
+
+        write_insert(&mut delta, &target[pending..target_start]);
+
+        delta.push(COPY);
+        write_varint(&mut delta, base_start as u64);
+        write_varint(&mut delta, (base_end - base_start) as u64);
+
+        position = target_end;
+        pending = target_end;
+    }
+
+    write_insert(&mut delta, &target[pending..]);
+
+    delta
+}
+
+/// Builds the result of applying `delta` to `base`, or `None` if the delta
+/// doesn't make sense for this base.
+pub fn apply(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+
+    let base_length = read_varint(delta, &mut position)?;
+    let target_length = read_varint(delta, &mut position)?;
+
+    if base_length != base.len() as u64 {
+        return None;
+    }
+
+    // The length comes from the pack, so we don't trust it enough to reserve
+    // that much memory before we've seen that the instructions add up to it.
+    let mut target = Vec::new();
+
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+
+        match instruction {
+            INSERT => {
+                let length = usize::try_from(read_varint(delta, &mut position)?).ok()?;
+                target.extend_from_slice(delta.get(position..position.checked_add(length)?)?);
+                position += length;
+            }
+            COPY => {
+                let offset = usize::try_from(read_varint(delta, &mut position)?).ok()?;
+                let length = usize::try_from(read_varint(delta, &mut position)?).ok()?;
+                target.extend_from_slice(base.get(offset..offset.checked_add(length)?)?);
+            }
+            _ => return None,
+        }
+    }
+
+    (target.len() as u64 == target_length).then_some(target)
+}
+
+/// Adds an instruction to insert `bytes`, unless there aren't any.
+fn write_insert(delta: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+
+    delta.push(INSERT);
+    write_varint(delta, bytes.len() as u64);
+    delta.extend_from_slice(bytes);
+}
+
+/// Writes `value` to `out` as a varint.
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+/// Reads a varint from `data`, starting at `position` and moving it past the
+/// end of the varint. Returns `None` if it runs off the end or doesn't fit in
+/// 64 bits.
+pub fn read_varint(data: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*position)?;
+        *position += 1;
+
+        value |= u64::from(byte & 0x7f).checked_shl(shift)?;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(edits: &[(usize, &str)]) -> Vec<u8> {
+        let mut lines: Vec<String> = (0..300).map(|i| format!("line number {i}\n")).collect();
+
+        for &(line, replacement) in edits {
+            lines[line] = replacement.to_string();
+        }
+
+        lines.concat().into_bytes()
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        let values = [
+            0,
+            1,
+            127,
+            128,
+            300,
+            16_383,
+            16_384,
+            u64::from(u32::MAX),
+            u64::MAX,
+        ];
+        let mut data = Vec::new();
+
+        for value in values {
+            write_varint(&mut data, value);
+        }
+
+        let mut position = 0;
+
+        for value in values {
+            assert_eq!(read_varint(&data, &mut position), Some(value));
+        }
+
+        assert_eq!(position, data.len());
+        assert_eq!(read_varint(&data, &mut position), None);
+    }
+
+    #[test]
+    fn varints_are_written_lowest_bits_first() {
+        let mut data = Vec::new();
+        write_varint(&mut data, 300);
+
+        assert_eq!(data, [0xac, 0x02]);
+    }
+
+    #[test]
+    fn a_small_change_makes_a_small_delta() {
+        let base = document(&[]);
+        let target = document(&[(10, "something else\n"), (200, "")]);
+        let delta = compute(&base, &target);
+
+        assert!(delta.len() < 100, "{} bytes", delta.len());
+        assert_eq!(apply(&base, &delta), Some(target));
+    }
+
+    #[test]
+    fn round_trips_unrelated_and_empty_data() {
+        let cases: [(&[u8], &[u8]); 4] = [
+            (b"", b""),
+            (b"", b"new"),
+            (b"old", b""),
+            (
+                b"nothing alike at all",
+                b"0123456789abcdefghijklmnopqrstuvwxyz",
+            ),
+        ];
+
+        for (base, target) in cases {
+            assert_eq!(apply(base, &compute(base, target)).as_deref(), Some(target));
+        }
+    }
+
+    #[test]
+    fn rejects_the_wrong_base() {
+        let delta = compute(&document(&[]), &document(&[(0, "first\n")]));
+
+        assert_eq!(apply(b"something else entirely", &delta), None);
+    }
+
+    #[test]
+    fn rejects_a_copy_past_the_end_of_the_base() {
+        let mut delta = Vec::new();
+        write_varint(&mut delta, 3);
+        write_varint(&mut delta, 4);
+        delta.extend_from_slice(&[COPY, 0, 4]);
+
+        assert_eq!(apply(b"abc", &delta), None);
+    }
+}
@@ -0,0 +1,136 @@
+//! Working out which lines changed between two versions of a file.
+//!
+//! We use Eugene Myers' algorithm from "An O(ND) Difference Algorithm and Its
+//! Variations", which is also what git uses by default. It finds the shortest
+//! edit script, meaning the smallest number of lines that have to be inserted
+//! or deleted to turn one file into the other. Its running time depends on how
+//! many lines changed rather than on how long the files are, which suits us,
+//! since most changes are small compared to the files they're in.
+
+/// A run of lines that differ between the old and new versions of a file.
+///
+/// The ranges are half-open, so a hunk that only inserts lines has an empty
+/// old range, and one that only deletes lines has an empty new range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_end: usize,
+    pub new_start: usize,
+    pub new_end: usize,
+}
+
+/// Splits `text` into lines, keeping the line endings attached so that joining
+/// the lines back together gives exactly the original text. This means a
+/// missing newline at the end of a file counts as a change, just like in git.
+pub fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// Finds the hunks that turn `old` into `new`, in order. Lines that aren't part
+/// of any hunk are the same in both.
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut old_index, mut new_index) = (0, 0);
+
+    for (old_match, new_match) in matching_lines(old, new)
+        .into_iter()
+        .chain([(old.len(), new.len())])
+    {
+        // Everything between two matching lines has changed.
+        if old_match > old_index || new_match > new_index {
+            hunks.push(Hunk {
+                old_start: old_index,
+                old_end: old_match,
+                new_start: new_index,
+                new_end: new_match,
+            });
+        }
+
+        (old_index, new_index) = (old_match + 1, new_match + 1);
+    }
+
+    hunks
+}
+
+/// Finds the longest sequence of lines that appear in both `old` and `new` in
+/// the same order, returned as pairs of indices into each.
+pub fn matching_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<(usize, usize)> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m) as usize;
+
+    // The algorithm considers the edit graph, where moving right deletes a
+    // line, moving down inserts one, and moving diagonally keeps a line that
+    // both files share. Diagonal k holds the points where x - y = k. For each
+    // number of edits d, `furthest[k]` is the furthest x we can reach on
+    // diagonal k using d edits. Diagonals can be negative, so they're offset by
+    // `max` to index the vector.
+    let offset = max as isize;
+    let mut furthest = vec![0isize; 2 * max + 2];
+
+    // We keep a copy of `furthest` from each round, so we can retrace our
+    // steps once we've reached the end. Round d only ever looks at diagonals
+    // -d - 1 to d + 1, so that's all we need to keep.
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        let start = (offset - d - 1).max(0) as usize;
+        let end = ((offset + d + 2) as usize).min(furthest.len());
+        trace.push((start, furthest[start..end].to_vec()));
+
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+
+            // We either get here by moving down from diagonal k + 1 or right
+            // from diagonal k - 1, and we pick whichever got further.
+            let mut x = if k == -d || (k != d && furthest[index - 1] < furthest[index + 1]) {
+                furthest[index + 1]
+            } else {
+                furthest[index - 1] + 1
+            };
+            let mut y = x - k;
+
+            // Then we follow the diagonal for as long as the lines match.
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            furthest[index] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk backwards through the rounds, recording the diagonal moves we made.
+    let mut matches = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, (start, furthest)) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let index = (k + offset) as usize - start;
+
+        let previous_k = if k == -d || (k != d && furthest[index - 1] < furthest[index + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = furthest[(previous_k + offset) as usize - start];
+        let previous_y = previous_x - previous_k;
+
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            matches.push((x as usize, y as usize));
+        }
+
+        if d > 0 {
+            (x, y) = (previous_x, previous_y);
+        }
+    }
+
+    matches.reverse();
+    matches
+}
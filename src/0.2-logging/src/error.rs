@@ -0,0 +1,1521 @@
+//! The ways things can go wrong.
+//!
+//! Each part of rat has its own error type, listing everything that can go
+//! wrong in it along with the paths and hashes involved, so that programs
+//! using rat as a library can tell the cases apart instead of having to pick
+//! through error messages. Errors from one part that bubble up through
+//! another are wrapped rather than flattened into text, so nothing is lost on
+//! the way up. Code that only glues other parts together, like fetching or
+//! importing from git, returns a `Box<dyn Error>` instead, which still holds
+//! the original error and can be downcast back to it.
+//!
+//! Every error also maps to an exit code, so that scripts running the `rat`
+//! command can tell what kind of thing went wrong without parsing its output:
+//!
+//! | Code | Meaning                                               |
+//! |------|-------------------------------------------------------|
+//! | 1    | Anything not covered below                            |
+//! | 2    | The command line or a pattern in it didn't make sense |
+//! | 3    | There's no nest, or there already is one              |
+//! | 4    | A branch, tag or revision couldn't be used            |
+//! | 5    | An object is missing or corrupt                       |
+//! | 6    | An operation is in progress, or isn't                 |
+//! | 7    | The working directory has changes that would be lost  |
+//! | 8    | A merge couldn't be done cleanly                      |
+//! | 9    | A commit couldn't be made                             |
+//! | 10   | A path couldn't be staged                             |
+//! | 11   | Another rat process is changing the nest              |
+//! | 12   | A hook refused to let the command go ahead            |
+//! | 13   | A remote couldn't be found or read from               |
+//! | 14   | A remote refused a push                               |
+//! | 15   | A config file or key didn't make sense                |
+//! | 16   | A bundle couldn't be read                             |
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::PathBuf;
+
+use crate::hash::HashAlgorithm;
+use crate::merge;
+use crate::objects::ObjectKind;
+use crate::resolve;
+use crate::state::Operation;
+
+/// Implements `From` for each listed variant of an error type that just wraps
+/// another error, so that `?` can do the wrapping.
+macro_rules! wrap_errors {
+    ($error:ident { $($variant:ident($inner:ty)),* $(,)? }) => {
+        $(
+            impl From<$inner> for $error {
+                fn from(error: $inner) -> Self {
+                    Self::$variant(error)
+                }
+            }
+        )*
+    };
+}
+
+/// Something went wrong reading or writing the object store.
+#[derive(Debug)]
+pub enum ObjectError {
+    /// There's no object with this hash.
+    NotFound { hash: String },
+    /// The object's contents don't match its hash.
+    Corrupt { hash: String },
+    /// The object matches its hash, but can't be understood.
+    Malformed { hash: String, reason: String },
+    /// A tree can't have an entry with this name, like one with a line break
+    /// in it.
+    InvalidName { name: String },
+    /// The object isn't the kind we needed.
+    WrongKind {
+        hash: String,
+        expected: ObjectKind,
+        found: ObjectKind,
+    },
+    /// Reading or writing a file in the object store failed.
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl ObjectError {
+    pub fn exit_code(&self) -> u8 {
+        5
+    }
+}
+
+impl Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { hash } => write!(f, "Object {hash} doesn't exist."),
+            Self::Corrupt { hash } => write!(f, "Object {hash} is corrupt."),
+            Self::Malformed { hash, reason } => write!(f, "Object {hash} is malformed: {reason}"),
+            Self::InvalidName { name } => write!(f, "A tree can't have an entry named {name:?}."),
+            Self::WrongKind {
+                hash,
+                expected,
+                found,
+            } => write!(f, "Object {hash} is a {found}, not a {expected}."),
+            Self::Io { path, source } => {
+                write!(f, "Failed to access {}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl Error for ObjectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Something went wrong with a ref, or turning a revision into a commit.
+#[derive(Debug)]
+pub enum RefError {
+    /// The name isn't allowed for a ref of this kind, like `branch`.
+    InvalidName {
+        kind: &'static str,
+        name: String,
+    },
+    /// There's no ref of this kind with this name.
+    NotFound {
+        kind: &'static str,
+        name: String,
+    },
+    /// There's already a ref of this kind with this name.
+    AlreadyExists {
+        kind: &'static str,
+        name: String,
+    },
+    /// The branch can't be changed like this while it's checked out.
+    CurrentBranch {
+        name: String,
+    },
+    /// The branch is checked out in another working directory, at this path.
+    CheckedOutElsewhere {
+        name: String,
+        path: PathBuf,
+    },
+    /// Deleting the branch would lose commits.
+    NotMerged {
+        name: String,
+    },
+    /// HEAD is on a branch that doesn't have any commits yet.
+    NoCommits,
+    /// HEAD points at a ref that isn't a branch.
+    InvalidHead {
+        target: String,
+    },
+    /// The ref doesn't point at another ref.
+    NotSymbolic {
+        name: String,
+    },
+    /// Following the symbolic refs starting at this one went on for too long,
+    /// which almost certainly means some of them point at each other.
+    SymbolicLoop {
+        name: String,
+    },
+    /// HEAD was asked to point at a ref that isn't a branch.
+    NotABranch {
+        target: String,
+    },
+    /// The revision doesn't name anything.
+    UnknownRevision {
+        revision: String,
+        reason: String,
+    },
+    /// The revision names something, but its suffixes lead nowhere.
+    InvalidRevision {
+        revision: String,
+        reason: String,
+    },
+    /// The hash prefix matches more than one commit.
+    AmbiguousRevision {
+        prefix: String,
+        candidates: Vec<String>,
+    },
+    Object(ObjectError),
+    Io(io::Error),
+}
+
+wrap_errors!(RefError {
+    Object(ObjectError),
+    Io(io::Error),
+});
+
+impl RefError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Object(e) => e.exit_code(),
+            Self::Io(_) => 1,
+            _ => 4,
+        }
+    }
+}
+
+impl Display for RefError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName { kind, name } => write!(f, "{name} isn't a valid {kind} name."),
+            Self::NotFound { kind, name } => write!(f, "No {kind} named {name}."),
+            Self::AlreadyExists { kind, name } => {
+                write!(f, "A {kind} named {name} already exists.")
+            }
+            Self::CurrentBranch { name } => {
+                write!(f, "Can't delete {name}, since it's the current branch.")
+            }
+            Self::CheckedOutElsewhere { name, path } => {
+                write!(f, "{name} is already checked out in {}.", path.display())
+            }
+            Self::NotMerged { name } => write!(
+                f,
+                "Branch {name} has commits that aren't part of the current branch. \
+                 Use -D to delete it anyway."
+            ),
+            Self::NoCommits => write!(f, "HEAD doesn't point at a commit yet."),
+            Self::InvalidHead { target } => {
+                write!(f, "HEAD points at {target}, which isn't a branch.")
+            }
+            Self::NotSymbolic { name } => write!(f, "{name} isn't a symbolic ref."),
+            Self::SymbolicLoop { name } => write!(
+                f,
+                "{name} leads to too many symbolic refs in a row, which probably point at \
+                 each other."
+            ),
+            Self::NotABranch { target } => write!(
+                f,
+                "HEAD can only point at a branch, like refs/heads/main, and {target} isn't one."
+            ),
+            Self::UnknownRevision { revision, reason } => {
+                write!(f, "Unknown revision {revision}: {reason}.")
+            }
+            Self::InvalidRevision { revision, reason } => {
+                write!(f, "Invalid revision {revision}: {reason}.")
+            }
+            Self::AmbiguousRevision { prefix, candidates } => {
+                write!(f, "{prefix} is ambiguous. It could be any of:")?;
+
+                for candidate in candidates {
+                    write!(f, "\n    {}", resolve::abbreviate(candidate))?;
+                }
+
+                Ok(())
+            }
+            Self::Object(e) => e.fmt(f),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for RefError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Object(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Something went wrong keeping track of an operation that stopped halfway.
+#[derive(Debug)]
+pub enum StateError {
+    /// Something else can't start until this operation is finished.
+    InProgress {
+        operation: Operation,
+        hash: String,
+    },
+    /// There's nothing to continue or abort.
+    NotInProgress(Operation),
+    /// Nothing at all is in progress, so there aren't any conflicts.
+    NothingInProgress,
+    /// The path doesn't have any conflicts to resolve.
+    NoConflicts(PathBuf),
+    /// These paths still have conflicts, so the operation can't continue.
+    Unresolved(BTreeSet<String>),
+    Io(io::Error),
+}
+
+wrap_errors!(StateError { Io(io::Error) });
+
+impl StateError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Io(_) => 1,
+            _ => 6,
+        }
+    }
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InProgress { operation, .. } => write!(
+                f,
+                "A {operation} is in progress. Finish it with rat {operation} --continue \
+                 or cancel it with rat {operation} --abort."
+            ),
+            Self::NotInProgress(operation) => write!(f, "There's no {operation} in progress."),
+            Self::NothingInProgress => write!(
+                f,
+                "Nothing is in progress, so there aren't any conflicts to resolve."
+            ),
+            Self::NoConflicts(path) => write!(f, "{} doesn't have any conflicts.", path.display()),
+            Self::Unresolved(paths) => write!(
+                f,
+                "These files still have conflicts:\n{}\nStage them with rat add once they're fixed.",
+                merge::describe_conflicts(paths)
+            ),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for StateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Something went wrong creating or opening a nest.
+#[derive(Debug)]
+pub enum InitError {
+    /// There's no nest in this directory.
+    NotANest {
+        path: PathBuf,
+    },
+    /// The command needs a working directory, but the nest is bare.
+    Bare {
+        command: &'static str,
+    },
+    /// The command changes the nest, but the nest is really a git
+    /// repository, which we can only read.
+    GitRepository {
+        command: &'static str,
+    },
+    /// The nest's `format` file says something we don't understand, most
+    /// likely because it was made by a newer version of rat.
+    UnknownFormat {
+        reason: String,
+    },
+    /// The nest was made by an older version of rat, and has to be upgraded
+    /// with `rat migrate` first.
+    OldFormat {
+        version: u32,
+    },
+    /// The nest was made by a newer version of rat, with a layout we don't
+    /// know about.
+    NewerFormat {
+        version: u32,
+    },
+    /// The nest already exists, but names its objects with a different hash
+    /// function than the one asked for.
+    HashMismatch {
+        existing: HashAlgorithm,
+    },
+    /// There's no way to work out a directory to clone into from the URL.
+    NoDirectoryName {
+        url: String,
+    },
+    /// There's already something in the directory we'd clone into.
+    NotEmpty {
+        path: PathBuf,
+    },
+    Ref(RefError),
+    Io(io::Error),
+}
+
+wrap_errors!(InitError {
+    Ref(RefError),
+    Io(io::Error),
+});
+
+impl InitError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Ref(e) => e.exit_code(),
+            Self::Io(_) => 1,
+            _ => 3,
+        }
+    }
+}
+
+impl Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotANest { path } => write!(
+                f,
+                "There's no rat nest in {}. Run rat init to create one.",
+                path.display()
+            ),
+            Self::Bare { command } => write!(
+                f,
+                "rat {command} needs a working directory, but this nest is bare."
+            ),
+            Self::GitRepository { command } => write!(
+                f,
+                "rat {command} doesn't work on git repositories. Only commands that read \
+                 history do, like rat log and rat show."
+            ),
+            Self::UnknownFormat { reason } => write!(
+                f,
+                "This nest has a format rat doesn't understand: {reason} It may have been \
+                 made by a newer version of rat."
+            ),
+            Self::OldFormat { version } => write!(
+                f,
+                "This nest uses the layout of an older version of rat (format version \
+                 {version}). Run rat migrate to upgrade it."
+            ),
+            Self::NewerFormat { version } => write!(
+                f,
+                "This nest uses format version {version}, but this version of rat only \
+                 understands up to version {}. Upgrade rat to use it.",
+                crate::format::VERSION
+            ),
+            Self::HashMismatch { existing } => write!(
+                f,
+                "This nest already uses {}, and its hash function can't be changed.",
+                existing.display_name()
+            ),
+            Self::NoDirectoryName { url } => {
+                write!(f, "Can't work out a directory name from {url}.")
+            }
+            Self::NotEmpty { path } => {
+                write!(f, "{} already exists and isn't empty.", path.display())
+            }
+            Self::Ref(e) => e.fmt(f),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for InitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Ref(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The nest couldn't be locked.
+#[derive(Debug)]
+pub enum LockError {
+    /// Another rat process is holding the lock, which is the file at `path`.
+    Held {
+        pid: Option<u32>,
+        path: PathBuf,
+    },
+    Io(io::Error),
+}
+
+wrap_errors!(LockError { Io(io::Error) });
+
+impl LockError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Held { .. } => 11,
+            Self::Io(_) => 1,
+        }
+    }
+}
+
+impl Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Held { pid, path } => {
+                match pid {
+                    Some(pid) => write!(
+                        f,
+                        "Another rat process (process {pid}) is running in this nest."
+                    )?,
+                    None => write!(f, "Another rat process is running in this nest.")?,
+                }
+
+                write!(
+                    f,
+                    " It's holding the lock on {}, so try again once it's finished.",
+                    path.display()
+                )
+            }
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for LockError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// One of the user's hooks stopped a command, or couldn't be run at all.
+#[derive(Debug)]
+pub enum HookError {
+    /// The hook exited with an error, with the code `code` unless it was
+    /// killed.
+    Rejected { hook: String, code: Option<i32> },
+    /// Starting the hook or talking to it failed.
+    Io { hook: String, source: io::Error },
+}
+
+impl HookError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Rejected { .. } => 12,
+            Self::Io { .. } => 1,
+        }
+    }
+}
+
+impl Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rejected {
+                hook,
+                code: Some(code),
+            } => write!(f, "The {hook} hook failed with exit code {code}."),
+            Self::Rejected { hook, code: None } => {
+                write!(f, "The {hook} hook was stopped by a signal.")
+            }
+            Self::Io { hook, source } => write!(f, "Failed to run the {hook} hook: {source}"),
+        }
+    }
+}
+
+impl Error for HookError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Rejected { .. } => None,
+            Self::Io { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Something went wrong staging files.
+#[derive(Debug)]
+pub enum AddError {
+    /// The path leads outside of the nest.
+    OutsideNest {
+        path: PathBuf,
+    },
+    /// The path is ignored, so staging it was probably a mistake.
+    Ignored {
+        path: PathBuf,
+    },
+    /// Nothing exists at the path, and nothing was staged there either.
+    NoMatch {
+        path: PathBuf,
+    },
+    /// The path is a directory, which needs asking for explicitly.
+    IsDirectory {
+        path: PathBuf,
+    },
+    /// The file has changes that haven't been committed, which would be lost.
+    HasChanges {
+        path: String,
+    },
+    /// There's already something at the path we'd be moving a file to.
+    DestinationExists {
+        path: PathBuf,
+    },
+    /// The file isn't in the index.
+    NotTracked {
+        path: String,
+    },
+    /// The path has a line break in it, which the index can't hold.
+    LineBreak {
+        path: PathBuf,
+    },
+    Ref(RefError),
+    Object(ObjectError),
+    Io(io::Error),
+    Other(Box<dyn Error>),
+}
+
+wrap_errors!(AddError {
+    Ref(RefError),
+    Object(ObjectError),
+    Io(io::Error),
+    Other(Box<dyn Error>),
+});
+
+impl AddError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Ref(e) => e.exit_code(),
+            Self::Object(e) => e.exit_code(),
+            Self::Io(_) | Self::Other(_) => 1,
+            _ => 10,
+        }
+    }
+}
+
+impl Display for AddError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutsideNest { path } => {
+                write!(f, "Path {} is outside of the nest.", path.display())
+            }
+            Self::Ignored { path } => write!(
+                f,
+                "Path {} is ignored by a {} file.",
+                path.display(),
+                crate::ignore::IGNORE_FILE
+            ),
+            Self::NoMatch { path } => {
+                write!(f, "Path {} did not match any files.", path.display())
+            }
+            Self::IsDirectory { path } => write!(
+                f,
+                "Path {} is a directory. Use -r to remove everything in it.",
+                path.display()
+            ),
+            Self::HasChanges { path } => write!(
+                f,
+                "{path} has changes that haven't been committed. Use -f to remove it anyway."
+            ),
+            Self::DestinationExists { path } => write!(
+                f,
+                "There's already something at {}. Use -f to replace it.",
+                path.display()
+            ),
+            Self::NotTracked { path } => write!(f, "{path} isn't being tracked."),
+            Self::LineBreak { path } => write!(
+                f,
+                "Path {path:?} has a line break in it, which rat can't track."
+            ),
+            Self::Ref(e) => e.fmt(f),
+            Self::Object(e) => e.fmt(f),
+            Self::Io(e) => e.fmt(f),
+            Self::Other(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for AddError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Ref(e) => Some(e),
+            Self::Object(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::Other(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Something went wrong making a commit, or an annotated tag.
+#[derive(Debug)]
+pub enum CommitError {
+    /// We couldn't work out the name or email of whoever is acting in `role`,
+    /// which is either `AUTHOR` or `COMMITTER`.
+    NoIdentity {
+        role: String,
+        field: &'static str,
+    },
+    /// The commit would have exactly the same snapshot as its parent.
+    NothingToCommit,
+    State(StateError),
+    Ref(RefError),
+    Object(ObjectError),
+    Config(ConfigError),
+    Hook(HookError),
+    Io(io::Error),
+    Other(Box<dyn Error>),
+}
+
+wrap_errors!(CommitError {
+    State(StateError),
+    Ref(RefError),
+    Object(ObjectError),
+    Config(ConfigError),
+    Hook(HookError),
+    Io(io::Error),
+    Other(Box<dyn Error>),
+});
+
+impl CommitError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::NoIdentity { .. } | Self::NothingToCommit => 9,
+            Self::State(e) => e.exit_code(),
+            Self::Ref(e) => e.exit_code(),
+            Self::Object(e) => e.exit_code(),
+            Self::Config(e) => e.exit_code(),
+            Self::Hook(e) => e.exit_code(),
+            Self::Io(_) | Self::Other(_) => 1,
+        }
+    }
+}
+
+impl Display for CommitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoIdentity { role, field } => write!(
+                f,
+                "No {field} set. Set it with rat config --set user.{field} <{field}>, or with the \
+                 RAT_{role}_{} environment variable. Failing both, it comes from your username, \
+                 but the USER environment variable isn't set either.",
+                field.to_uppercase()
+            ),
+            Self::NothingToCommit => write!(
+                f,
+                "Nothing to commit, since nothing has changed. Use --allow-empty to commit \
+                 anyway."
+            ),
+            Self::State(e) => e.fmt(f),
+            Self::Ref(e) => e.fmt(f),
+            Self::Object(e) => e.fmt(f),
+            Self::Config(e) => e.fmt(f),
+            Self::Hook(e) => e.fmt(f),
+            Self::Io(e) => e.fmt(f),
+            Self::Other(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for CommitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::NoIdentity { .. } | Self::NothingToCommit => None,
+            Self::State(e) => Some(e),
+            Self::Ref(e) => Some(e),
+            Self::Object(e) => Some(e),
+            Self::Config(e) => Some(e),
+            Self::Hook(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::Other(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+/// Something went wrong changing the working directory to match a commit,
+/// whether by checking it out, resetting to it, or anything else.
+#[derive(Debug)]
+pub enum CheckoutError {
+    /// There are changes that haven't been committed, which `action`, like
+    /// `checking out`, would lose.
+    UncommittedChanges {
+        action: &'static str,
+    },
+    /// An untracked file is in the way of a file `action` needs to write.
+    WouldOverwrite {
+        action: &'static str,
+        path: String,
+    },
+    /// One of the paths to restore couldn't be used.
+    Path(AddError),
+    State(StateError),
+    Ref(RefError),
+    Object(ObjectError),
+    Io(io::Error),
+    Other(Box<dyn Error>),
+}
+
+wrap_errors!(CheckoutError {
+    Path(AddError),
+    State(StateError),
+    Ref(RefError),
+    Object(ObjectError),
+    Io(io::Error),
+    Other(Box<dyn Error>),
+});
+
+impl CheckoutError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::UncommittedChanges { .. } | Self::WouldOverwrite { .. } => 7,
+            Self::Path(e) => e.exit_code(),
+            Self::State(e) => e.exit_code(),
+            Self::Ref(e) => e.exit_code(),
+            Self::Object(e) => e.exit_code(),
+            Self::Io(_) | Self::Other(_) => 1,
+        }
+    }
+}
+
+impl Display for CheckoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UncommittedChanges { action } => write!(
+                f,
+                "You have uncommitted changes. Commit them before {action}."
+            ),
+            Self::WouldOverwrite { action, path } => write!(
+                f,
+                "{} would overwrite the untracked file {path}.",
+                capitalize(action)
+            ),
+            Self::Path(e) => e.fmt(f),
+            Self::State(e) => e.fmt(f),
+            Self::Ref(e) => e.fmt(f),
+            Self::Object(e) => e.fmt(f),
+            Self::Io(e) => e.fmt(f),
+            Self::Other(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for CheckoutError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Path(e) => Some(e),
+            Self::State(e) => Some(e),
+            Self::Ref(e) => Some(e),
+            Self::Object(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::Other(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Something went wrong searching through history with `rat bisect`.
+#[derive(Debug)]
+pub enum BisectError {
+    /// There's no bisect going on to mark commits in or finish.
+    NotStarted,
+    /// A bisect is already going on, so another one can't start.
+    AlreadyStarted,
+    /// The bad commit is in the history of a good one, so there's nothing
+    /// between them to search.
+    BadBeforeGood {
+        bad: String,
+        good: String,
+    },
+    Checkout(CheckoutError),
+    Ref(RefError),
+    Object(ObjectError),
+    State(StateError),
+    Io(io::Error),
+}
+
+wrap_errors!(BisectError {
+    Checkout(CheckoutError),
+    Ref(RefError),
+    Object(ObjectError),
+    State(StateError),
+    Io(io::Error),
+});
+
+impl BisectError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Checkout(e) => e.exit_code(),
+            Self::Ref(e) => e.exit_code(),
+            Self::Object(e) => e.exit_code(),
+            Self::State(e) => e.exit_code(),
+            Self::Io(_) => 1,
+            _ => 6,
+        }
+    }
+}
+
+impl Display for BisectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotStarted => write!(
+                f,
+                "There's no bisect in progress. Start one with rat bisect start."
+            ),
+            Self::AlreadyStarted => write!(
+                f,
+                "A bisect is already in progress. Finish it with rat bisect reset first."
+            ),
+            Self::BadBeforeGood { bad, good } => write!(
+                f,
+                "The bad commit {} is in the history of the good commit {}, so there's \
+                 nothing between them to search.",
+                resolve::abbreviate(bad),
+                resolve::abbreviate(good)
+            ),
+            Self::Checkout(e) => e.fmt(f),
+            Self::Ref(e) => e.fmt(f),
+            Self::Object(e) => e.fmt(f),
+            Self::State(e) => e.fmt(f),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for BisectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Checkout(e) => Some(e),
+            Self::Ref(e) => Some(e),
+            Self::Object(e) => Some(e),
+            Self::State(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Something went wrong combining changes, whether by merging, reverting or
+/// cherry-picking.
+#[derive(Debug)]
+pub enum MergeError {
+    /// `action`, like `Merging branch main`, would cause conflicts in these
+    /// paths, so we didn't change anything.
+    Conflicts {
+        action: String,
+        paths: BTreeSet<String>,
+    },
+    /// `operation` stopped while applying the commit `hash`, leaving
+    /// conflicts in these paths for the user to resolve.
+    Stopped {
+        operation: Operation,
+        hash: String,
+        paths: BTreeSet<String>,
+    },
+    /// Both sides have new commits, but only a fast-forward was allowed.
+    CannotFastForward {
+        label: String,
+    },
+    /// The commit is a merge, so it's not clear which of its parents to
+    /// compare it against for `action`, like `reverted`.
+    IsMerge {
+        hash: String,
+        action: &'static str,
+    },
+    /// A line of the todo list for an interactive rebase doesn't make sense.
+    InvalidTodo {
+        line: String,
+        reason: String,
+    },
+    /// Every commit was left out of an interactive rebase.
+    NothingToDo,
+    /// The message for a commit was left empty, so `operation` stopped.
+    EmptyMessage {
+        operation: Operation,
+    },
+    Checkout(CheckoutError),
+    Commit(CommitError),
+    State(StateError),
+    Ref(RefError),
+    Object(ObjectError),
+    Io(io::Error),
+    Other(Box<dyn Error>),
+}
+
+wrap_errors!(MergeError {
+    Checkout(CheckoutError),
+    Commit(CommitError),
+    State(StateError),
+    Ref(RefError),
+    Object(ObjectError),
+    Io(io::Error),
+    Other(Box<dyn Error>),
+});
+
+impl MergeError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Conflicts { .. }
+            | Self::Stopped { .. }
+            | Self::CannotFastForward { .. }
+            | Self::IsMerge { .. }
+            | Self::InvalidTodo { .. }
+            | Self::NothingToDo
+            | Self::EmptyMessage { .. } => 8,
+            Self::Checkout(e) => e.exit_code(),
+            Self::Commit(e) => e.exit_code(),
+            Self::State(e) => e.exit_code(),
+            Self::Ref(e) => e.exit_code(),
+            Self::Object(e) => e.exit_code(),
+            Self::Io(_) | Self::Other(_) => 1,
+        }
+    }
+}
+
+impl Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflicts { action, paths } => write!(
+                f,
+                "{action} would cause conflicts in:\n{}",
+                merge::describe_conflicts(paths)
+            ),
+            Self::Stopped {
+                operation,
+                hash,
+                paths,
+            } => write!(
+                f,
+                "The {operation} of {} stopped because of conflicts in:\n{}\n\
+                 Fix them, stage them with rat add, then run rat {operation} --continue.",
+                resolve::abbreviate(hash),
+                merge::describe_conflicts(paths)
+            ),
+            Self::CannotFastForward { label } => write!(
+                f,
+                "Can't fast-forward to {label}, since both sides have new commits."
+            ),
+            Self::IsMerge { hash, action } => write!(
+                f,
+                "Commit {} is a merge, so it can't be {action}.",
+                resolve::abbreviate(hash)
+            ),
+            Self::InvalidTodo { line, reason } => {
+                write!(f, "Invalid line in the todo list, {reason}: {line}")
+            }
+            Self::NothingToDo => write!(f, "Nothing to do, so the rebase was cancelled."),
+            Self::EmptyMessage { operation } => write!(
+                f,
+                "Stopped, since the message was empty. Run rat {operation} --continue to \
+                 write it again, or rat {operation} --abort to give up."
+            ),
+            Self::Checkout(e) => e.fmt(f),
+            Self::Commit(e) => e.fmt(f),
+            Self::State(e) => e.fmt(f),
+            Self::Ref(e) => e.fmt(f),
+            Self::Object(e) => e.fmt(f),
+            Self::Io(e) => e.fmt(f),
+            Self::Other(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for MergeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Checkout(e) => Some(e),
+            Self::Commit(e) => Some(e),
+            Self::State(e) => Some(e),
+            Self::Ref(e) => Some(e),
+            Self::Object(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::Other(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Something went wrong reading another nest, whether it's on this computer
+/// or on a web server.
+#[derive(Debug)]
+pub enum TransportError {
+    /// There's no nest at the URL.
+    NotANest {
+        url: String,
+    },
+    /// The URL isn't one we can talk to, like an `https://` one.
+    InvalidUrl {
+        url: String,
+        reason: String,
+    },
+    /// Connecting to the server at `host` failed.
+    Connect {
+        host: String,
+        source: io::Error,
+    },
+    /// Listening for requests on `address` failed.
+    Listen {
+        address: String,
+        source: io::Error,
+    },
+    /// The server's answer didn't make sense.
+    InvalidResponse {
+        url: String,
+        reason: String,
+    },
+    /// The server answered with an error, given by its status line.
+    RequestFailed {
+        url: String,
+        status: String,
+    },
+    /// The server doesn't have a file we needed.
+    Missing {
+        url: String,
+    },
+    /// The two nests name their objects with different hash functions, so
+    /// history can't be copied between them.
+    HashMismatch {
+        from: HashAlgorithm,
+        to: HashAlgorithm,
+    },
+    Io(io::Error),
+}
+
+wrap_errors!(TransportError { Io(io::Error) });
+
+impl TransportError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Io(_) => 1,
+            _ => 13,
+        }
+    }
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotANest { url } => write!(f, "{url} isn't a rat nest."),
+            Self::InvalidUrl { url, reason } => write!(f, "Invalid URL {url}: {reason}"),
+            Self::Connect { host, source } => write!(f, "Failed to connect to {host}: {source}"),
+            Self::Listen { address, source } => {
+                write!(f, "Failed to listen on {address}: {source}")
+            }
+            Self::InvalidResponse { url, reason } => {
+                write!(f, "Received an invalid response for {url}: {reason}")
+            }
+            Self::RequestFailed { url, status } => write!(f, "Request for {url} failed: {status}"),
+            Self::Missing { url } => write!(f, "{url} doesn't exist."),
+            Self::HashMismatch { from, to } => write!(
+                f,
+                "Can't copy history between a nest that uses {} and one that uses {}.",
+                from.display_name(),
+                to.display_name()
+            ),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for TransportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Connect { source, .. } | Self::Listen { source, .. } => Some(source),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Something went wrong with a remote, or the remote refused what we asked
+/// of it.
+#[derive(Debug)]
+pub enum RemoteError {
+    /// There's no remote with this name.
+    NotFound {
+        name: String,
+    },
+    /// There's already a remote with this name.
+    AlreadyExists {
+        name: String,
+    },
+    /// The name can't be used for a remote.
+    InvalidName {
+        name: String,
+    },
+    /// The remote is at `url`, which isn't on this computer, so it can't be
+    /// pushed to.
+    NotLocal {
+        name: String,
+        url: String,
+    },
+    /// The remote's branch has commits that ours doesn't, which moving it
+    /// would lose.
+    NotFastForward {
+        remote: String,
+        branch: String,
+    },
+    /// The branch is checked out in the remote, so moving it would leave the
+    /// remote's working directory out of sync.
+    CheckedOut {
+        remote: String,
+        branch: String,
+    },
+    /// Pushing to `failed` of the remote's `total` URLs didn't work.
+    PushFailed {
+        failed: usize,
+        total: usize,
+    },
+    Transport(TransportError),
+}
+
+wrap_errors!(RemoteError {
+    Transport(TransportError),
+});
+
+impl RemoteError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Transport(e) => e.exit_code(),
+            Self::NotFastForward { .. } | Self::CheckedOut { .. } | Self::PushFailed { .. } => 14,
+            _ => 13,
+        }
+    }
+}
+
+impl Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { name } => write!(f, "No remote named {name}."),
+            Self::AlreadyExists { name } => write!(f, "A remote named {name} already exists."),
+            Self::InvalidName { name } => write!(f, "{name} isn't a valid remote name."),
+            Self::NotLocal { name, url } => write!(
+                f,
+                "Remote {name} is at {url}, but we can only write to nests on this computer."
+            ),
+            Self::NotFastForward { remote, branch } => write!(
+                f,
+                "The {branch} branch of {remote} has commits that yours doesn't. \
+                 Fetch them and combine them with yours before pushing."
+            ),
+            Self::CheckedOut { remote, branch } => {
+                write!(
+                    f,
+                    "Can't push to {branch}, since it's checked out in {remote}."
+                )
+            }
+            Self::PushFailed { failed, total } => {
+                write!(f, "Failed to push to {failed} of {total} URLs.")
+            }
+            Self::Transport(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for RemoteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A config file or a key in it couldn't be understood.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Line `number` of the config file at `path`, counting from 1, isn't a
+    /// section header or a setting.
+    InvalidLine {
+        path: PathBuf,
+        number: usize,
+        line: String,
+    },
+    /// The key doesn't have a section and a name.
+    InvalidKey { key: String },
+    /// Reading the config file at `path` failed.
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl ConfigError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Io { .. } => 1,
+            _ => 15,
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLine { path, number, line } => {
+                write!(f, "Invalid line {number} in {}: {line}", path.display())
+            }
+            Self::InvalidKey { key } => {
+                write!(f, "Invalid key {key}. Keys look like section.name.")
+            }
+            Self::Io { path, source } => write!(f, "Failed to read {}: {source}", path.display()),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A bundle couldn't be read.
+#[derive(Debug)]
+pub enum BundleError {
+    /// The file at `path` isn't a bundle at all.
+    NotABundle {
+        path: PathBuf,
+    },
+    /// The bundle ends partway through.
+    Truncated,
+    /// A line of the bundle doesn't make sense.
+    InvalidLine {
+        line: String,
+    },
+    /// The bundle names a ref with a name we'd never give one.
+    InvalidRefName {
+        name: String,
+    },
+    /// The bundle's objects are named with a different hash function than
+    /// the nest's.
+    HashMismatch {
+        bundle: HashAlgorithm,
+        nest: HashAlgorithm,
+    },
+    /// The bundle builds on these commits, which the nest doesn't have.
+    MissingPrerequisites(Vec<String>),
+    Object(ObjectError),
+    /// Reading or writing the bundle at `path` failed.
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+}
+
+wrap_errors!(BundleError {
+    Object(ObjectError),
+});
+
+impl BundleError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Object(e) => e.exit_code(),
+            Self::Io { .. } => 1,
+            _ => 16,
+        }
+    }
+}
+
+impl Display for BundleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotABundle { path } => write!(f, "{} isn't a rat bundle.", path.display()),
+            Self::Truncated => write!(f, "The bundle is truncated."),
+            Self::InvalidLine { line } => write!(f, "Invalid line in bundle: {line}"),
+            Self::InvalidRefName { name } => write!(f, "Invalid ref name in bundle: {name}"),
+            Self::HashMismatch { bundle, nest } => write!(
+                f,
+                "The bundle's objects are named with {}, but the nest uses {}.",
+                bundle.display_name(),
+                nest.display_name()
+            ),
+            Self::MissingPrerequisites(hashes) => {
+                write!(
+                    f,
+                    "The bundle needs these commits, which aren't in the nest:"
+                )?;
+
+                for hash in hashes {
+                    write!(f, "\n    {hash}")?;
+                }
+
+                Ok(())
+            }
+            Self::Object(e) => e.fmt(f),
+            Self::Io { path, source } => write!(f, "Failed to access {}: {source}", path.display()),
+        }
+    }
+}
+
+impl Error for BundleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Object(e) => Some(e),
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Something went wrong reading a git repository.
+#[derive(Debug)]
+pub enum GitError {
+    /// There's no git repository at the path.
+    NotARepository { path: PathBuf },
+    /// The repository names its objects with SHA-256, which we can't read.
+    UnsupportedHash { path: PathBuf },
+    /// The pack or pack index at the path can't be understood.
+    DamagedPack { path: PathBuf },
+    /// The pack index at the path is in a version we can't read.
+    UnsupportedPackIndex { path: PathBuf },
+}
+
+impl GitError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::NotARepository { .. } | Self::UnsupportedHash { .. } => 3,
+            Self::DamagedPack { .. } | Self::UnsupportedPackIndex { .. } => 5,
+        }
+    }
+}
+
+impl Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotARepository { path } => {
+                write!(f, "{} isn't a git repository.", path.display())
+            }
+            Self::UnsupportedHash { path } => write!(
+                f,
+                "{} doesn't use SHA-1 hashes, which is the only kind we can read.",
+                path.display()
+            ),
+            Self::DamagedPack { path } => write!(f, "Git pack {} is damaged.", path.display()),
+            Self::UnsupportedPackIndex { path } => write!(
+                f,
+                "Git pack index {} is in a version we can't read.",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for GitError {}
+
+/// The command line didn't make sense, like when an argument is missing or a
+/// flag doesn't exist.
+#[derive(Debug)]
+pub struct UsageError {
+    pub message: String,
+    /// How the command should have been used, one line per form it takes, if
+    /// we know which command it was.
+    pub usage: &'static [&'static str],
+}
+
+impl UsageError {
+    pub fn exit_code(&self) -> u8 {
+        2
+    }
+}
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        for (i, line) in self.usage.iter().enumerate() {
+            let label = if i == 0 { "\n\nUsage:" } else { "\n      " };
+
+            write!(f, "{label} {line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for UsageError {}
+
+/// A regular expression couldn't be understood.
+#[derive(Debug)]
+pub struct RegexError {
+    pub pattern: String,
+    /// What's wrong with it, like `there's a ( without a matching )`.
+    pub reason: String,
+}
+
+impl RegexError {
+    pub fn exit_code(&self) -> u8 {
+        2
+    }
+}
+
+impl Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid pattern {}: {}.", self.pattern, self.reason)
+    }
+}
+
+impl Error for RegexError {}
+
+/// Works out the exit code for any error, using the one its type maps to if
+/// it's one of ours, and 1 otherwise.
+pub fn exit_code(error: &(dyn Error + 'static)) -> u8 {
+    if let Some(e) = error.downcast_ref::<ObjectError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<RefError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<StateError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<InitError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<LockError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<HookError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<AddError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<CommitError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<CheckoutError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<BisectError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<MergeError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<TransportError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<RemoteError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<ConfigError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<BundleError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<GitError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<UsageError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<RegexError>() {
+        e.exit_code()
+    } else {
+        1
+    }
+}
+
+/// Uppercases the first letter of `s`, so an action like `checking out` can
+/// start a sentence.
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,297 @@
+//! Turning a nest into a git repository, for anyone who outgrows rat.
+//!
+//! Git can build a repository from a stream of commands describing it, which
+//! is what `git fast-import` reads, so that's what we write. The stream is
+//! plain text apart from the contents of each file, and it looks like this:
+//!
+//! ```text
+//! blob
+//! mark :1
+//! data 6
+//! hello
+//!
+//! commit refs/heads/main
+//! mark :2
+//! author Ada Lovelace <ada@example.com> 1700000000 +0100
+//! committer Ada Lovelace <ada@example.com> 1700000000 +0100
+//! data 14
+//! First draft.
+//!
+//! M 100644 :1 notes.txt
+//! ```
+//!
+//! Each file and commit gets a mark, a number that later commands use to
+//! refer back to it, since git is going to give everything different hashes
+//! from ours. Every commit only lists how its files differ from its first
+//! parent, and comes after all of its parents, so git can build each one on
+//! top of what came before.
+//!
+//! Git has no idea what a rat LFS pointer is, so files kept out of the nest
+//! are written with their real contents whenever we have them.
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::compare::{self, Change, FileMode, Snapshot};
+use crate::lfs;
+use crate::metadata::CommitMetadata;
+use crate::objects::{self, ObjectKind};
+use crate::refs::{self, Head, Ref};
+
+/// What went into an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub commits: usize,
+    pub files: usize,
+    pub refs: usize,
+}
+
+/// Writes a `git fast-import` stream for every branch and tag in the nest,
+/// along with all of their history.
+pub fn export_to_git() -> Result<(Vec<u8>, ExportSummary), Box<dyn Error>> {
+    let mut tips = Vec::new();
+
+    for name in refs::list_all_refs()? {
+        if matches!(name, Ref::Branch(_) | Ref::Tag(_)) {
+            if let Some(hash) = refs::read_ref(&name)? {
+                tips.push((name, hash));
+            }
+        }
+    }
+
+    let mut exporter = Exporter {
+        out: Vec::new(),
+        marks: HashMap::new(),
+        next_mark: 1,
+        summary: ExportSummary {
+            commits: 0,
+            files: 0,
+            refs: 0,
+        },
+    };
+
+    // Git needs a ref to build each commit on, so we use the first one we
+    // find it from. Every ref is put back where it belongs at the end.
+    for (name, hash) in &tips {
+        let commit = objects::peel_to_commit(hash)?;
+        exporter.export_history(&commit, &name.full_name())?;
+    }
+
+    // A detached HEAD can be the only way to reach some commits, in which
+    // case it gets a branch of its own so that they aren't lost.
+    if let Head::Detached(hash) = refs::read_head()? {
+        if !exporter.marks.contains_key(&hash) {
+            let name = Ref::Branch("detached-head".to_string());
+            exporter.export_history(&hash, &name.full_name())?;
+            tips.push((name, hash));
+        }
+    }
+
+    for (name, hash) in &tips {
+        exporter.export_ref(name, hash)?;
+    }
+
+    Ok((exporter.out, exporter.summary))
+}
+
+struct Exporter {
+    out: Vec<u8>,
+    /// The mark of every object written so far, by hash.
+    marks: HashMap<String, usize>,
+    next_mark: usize,
+    summary: ExportSummary,
+}
+
+impl Exporter {
+    /// Gives the object `hash` the next mark, writing the line for it.
+    fn mark(&mut self, hash: &str) {
+        self.marks.insert(hash.to_string(), self.next_mark);
+        self.write(&format!("mark :{}\n", self.next_mark));
+        self.next_mark += 1;
+    }
+
+    fn write(&mut self, text: &str) {
+        self.out.extend_from_slice(text.as_bytes());
+    }
+
+    /// Writes `data` the way fast-import takes it, as its length and then
+    /// the bytes themselves.
+    fn write_data(&mut self, data: &[u8]) {
+        self.write(&format!("data {}\n", data.len()));
+        self.out.extend_from_slice(data);
+        self.out.push(b'\n');
+    }
+
+    /// Writes every commit in the history of `tip` that hasn't been written
+    /// yet, building them on the ref `ref_name`.
+    fn export_history(&mut self, tip: &str, ref_name: &str) -> Result<(), Box<dyn Error>> {
+        // Each entry is a commit, along with whether its parents have been
+        // dealt with already, so that we only write it after all of them.
+        let mut stack = vec![(tip.to_string(), false)];
+
+        while let Some((hash, parents_done)) = stack.pop() {
+            if self.marks.contains_key(&hash) {
+                continue;
+            }
+
+            let metadata = objects::read_commit(&hash)?;
+
+            if parents_done {
+                self.export_commit(&hash, &metadata, ref_name)?;
+                continue;
+            }
+
+            stack.push((hash, true));
+
+            for parent in metadata.parents.iter().rev() {
+                // A parent that's already further down the stack has to be
+                // pushed again anyway, so that it's written before this one.
+                if !self.marks.contains_key(parent) {
+                    stack.push((parent.clone(), false));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn export_commit(
+        &mut self,
+        hash: &str,
+        metadata: &CommitMetadata,
+        ref_name: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let snapshot = objects::read_tree(&metadata.tree)?;
+
+        // The changes are relative to the first parent, since that's what git
+        // starts each commit from.
+        let base = match metadata.parents.first() {
+            Some(parent) => objects::read_tree(&objects::read_commit(parent)?.tree)?,
+            None => Snapshot::new(),
+        };
+
+        // Git pins nests inside this one by their git commit hashes, which we
+        // don't have, so those are left out.
+        let is_nest = |path: &String| {
+            [&base, &snapshot].iter().any(|side| {
+                side.get(path)
+                    .is_some_and(|entry| entry.mode == FileMode::Nest)
+            })
+        };
+
+        let mut changes = compare::compare(&base, &snapshot);
+        changes.retain(|path, _| !is_nest(path));
+
+        // Every file has to be written before the commit that uses it.
+        for (path, change) in &changes {
+            if *change != Change::Deleted {
+                self.export_file(&snapshot[path].hash)?;
+            }
+        }
+
+        // A commit with no parents would otherwise be built on top of whatever
+        // the ref already has.
+        if metadata.parents.is_empty() {
+            self.write(&format!("reset {ref_name}\n"));
+        }
+
+        self.write(&format!("commit {ref_name}\n"));
+        self.mark(hash);
+        self.write(&format!("author {}\n", metadata.author));
+        self.write(&format!("committer {}\n", metadata.committer));
+        self.write_data(metadata.message.as_bytes());
+
+        for (i, parent) in metadata.parents.iter().enumerate() {
+            let command = if i == 0 { "from" } else { "merge" };
+            self.write(&format!("{command} :{}\n", self.marks[parent]));
+        }
+
+        for (path, change) in changes {
+            let quoted = quote_path(&path);
+
+            match change {
+                Change::Deleted => self.write(&format!("D {quoted}\n")),
+                _ => {
+                    let entry = &snapshot[&path];
+                    let mode = entry.mode.git_mode();
+
+                    let mark = self.marks[&entry.hash];
+                    self.write(&format!("M {mode} :{mark} {quoted}\n"));
+                }
+            }
+        }
+
+        self.write("\n");
+        self.summary.commits += 1;
+
+        Ok(())
+    }
+
+    /// Writes the blob `hash`, unless it's already been written.
+    fn export_file(&mut self, hash: &str) -> Result<(), Box<dyn Error>> {
+        if self.marks.contains_key(hash) {
+            return Ok(());
+        }
+
+        let data = objects::read_object_of_kind(hash, ObjectKind::Blob)?;
+
+        let data = match lfs::Pointer::parse(&data).filter(lfs::Pointer::has_content) {
+            Some(pointer) => std::fs::read(pointer.content_path())?,
+            None => data,
+        };
+
+        self.write("blob\n");
+        self.mark(hash);
+        self.write_data(&data);
+        self.summary.files += 1;
+
+        Ok(())
+    }
+
+    /// Points `name` at what it pointed to in the nest, which for an
+    /// annotated tag means writing the tag itself.
+    fn export_ref(&mut self, name: &Ref, hash: &str) -> Result<(), Box<dyn Error>> {
+        self.summary.refs += 1;
+
+        if let (Ref::Tag(tag), ObjectKind::Tag) = (name, objects::read_object(hash)?.0) {
+            let metadata = objects::read_tag(hash)?;
+            let commit = objects::peel_to_commit(hash)?;
+
+            self.write(&format!("tag {tag}\n"));
+            self.write(&format!("from :{}\n", self.marks[&commit]));
+            self.write(&format!("tagger {}\n", metadata.tagger));
+            self.write_data(metadata.message.as_bytes());
+
+            return Ok(());
+        }
+
+        let commit = objects::peel_to_commit(hash)?;
+
+        self.write(&format!("reset {}\n", name.full_name()));
+        self.write(&format!("from :{}\n\n", self.marks[&commit]));
+
+        Ok(())
+    }
+}
+
+/// Quotes `path` if fast-import would misread it otherwise, which is when it
+/// starts with a quote or has a line break in it.
+fn quote_path(path: &str) -> String {
+    if !path.starts_with('"') && !path.contains('\n') {
+        return path.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+
+    for c in path.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,140 @@
+//! Telling apart the different layouts a nest can have.
+//!
+//! The very first version of rat kept each commit as a complete copy of the
+//! working directory, in `.rat/commit-1`, `.rat/commit-2` and so on, with
+//! `HEAD` holding the number of the latest one. Everything since keeps
+//! [`objects`](crate::objects) named after their hashes instead, along with
+//! branches, the index and everything else. We call these format versions 1
+//! and 2, and `rat migrate` turns a nest of the first kind into the second.
+//!
+//! Every nest made now has a `format` file saying which version it is, along
+//! with the [`hash`](crate::hash) function it names its objects with:
+//!
+//! ```text
+//! version 2
+//! hash sha256
+//! ```
+//!
+//! Nests made before there was a `format` file can still be told apart by
+//! their `HEAD`, which is just a number in the first version. A nest with a
+//! newer version than we know about is refused outright, since there's no
+//! telling what we'd break by reading or writing it.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::InitError;
+use crate::hash::HashAlgorithm;
+use crate::utils;
+
+/// The version of the layout this version of rat makes and understands.
+pub const VERSION: u32 = 2;
+
+/// What a nest's `format` file says about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub version: u32,
+    pub hash: HashAlgorithm,
+}
+
+impl Format {
+    /// The format of a new nest whose objects are named with `hash`.
+    pub fn new(hash: HashAlgorithm) -> Self {
+        Self {
+            version: VERSION,
+            hash,
+        }
+    }
+
+    /// Parses the contents of a `format` file. Lines we don't recognise are
+    /// skipped, so that newer versions of rat can add to them.
+    pub fn parse(text: &str) -> Result<Self, InitError> {
+        // The first format files only said which hash function the nest
+        // used, and were all version 2.
+        let mut version = 2;
+        let mut hash = None;
+
+        for line in text.lines() {
+            match line.split_once(' ') {
+                Some(("version", number)) => {
+                    version = number
+                        .trim()
+                        .parse()
+                        .map_err(|_| InitError::UnknownFormat {
+                            reason: format!("{number} isn't a version number."),
+                        })?;
+                }
+                Some(("hash", name)) => hash = Some(name.trim()),
+                _ => {}
+            }
+        }
+
+        // A newer version could mean anything at all, so there's no point
+        // looking any further.
+        if version > VERSION {
+            return Err(InitError::NewerFormat { version });
+        }
+
+        let hash = hash
+            .map(str::parse)
+            .transpose()
+            .map_err(|reason| InitError::UnknownFormat { reason })?
+            .unwrap_or_default();
+
+        Ok(Self { version, hash })
+    }
+
+    pub fn serialize(&self) -> String {
+        format!("version {}\nhash {}\n", self.version, self.hash)
+    }
+}
+
+fn format_path(nest: &Path) -> PathBuf {
+    nest.join("format")
+}
+
+/// Works out the format of the nest directory `nest`, from its `format` file
+/// if it has one, or otherwise from how it's laid out.
+pub fn read(nest: &Path) -> Result<Format, InitError> {
+    match fs::read_to_string(format_path(nest)) {
+        Ok(text) => Format::parse(&text),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let version = match is_snapshot_nest(nest) {
+                true => 1,
+                false => 2,
+            };
+
+            Ok(Format {
+                version,
+                hash: HashAlgorithm::default(),
+            })
+        }
+        Err(e) => Err(e)?,
+    }
+}
+
+/// Writes the `format` file of the nest directory `nest`.
+pub fn write(nest: &Path, format: &Format) -> io::Result<()> {
+    utils::write_atomically(format_path(nest), format.serialize())
+}
+
+/// Checks that we can work with the nest directory `nest`, returning its
+/// format if we can.
+pub fn check(nest: &Path) -> Result<Format, InitError> {
+    let format = read(nest)?;
+
+    if format.version < VERSION {
+        Err(InitError::OldFormat {
+            version: format.version,
+        })?;
+    }
+
+    Ok(format)
+}
+
+/// Checks whether the nest directory `nest` is laid out the way the very
+/// first version of rat did it, with a number in `HEAD`.
+fn is_snapshot_nest(nest: &Path) -> bool {
+    fs::read_to_string(nest.join("HEAD")).is_ok_and(|head| head.trim().parse::<i64>().is_ok())
+}
@@ -0,0 +1,781 @@
+//! Reading real git repositories.
+//!
+//! Git stores the same four kinds of object we do, with the same header in
+//! front of each one, so most of what's here is about the ways it differs:
+//!
+//! - Objects are named by their SHA-1 hash rather than SHA-256, which makes
+//!   every hash 40 characters long instead of 64.
+//! - Every object is compressed with zlib, including the header.
+//! - Most objects live in packs rather than files of their own. Git's packs
+//!   work like [ours](crate::pack), except that a delta can name its base
+//!   either by hash or by how far back in the pack it is.
+//! - Tree entries are binary: the mode as an octal number, a space, the name,
+//!   a null byte and then the 20 bytes of the hash, with nothing in between
+//!   entries.
+//! - Commits and tags can be in an encoding other than UTF-8, which their
+//!   `encoding` header names. We understand ISO-8859-1, which is the only
+//!   other one that turns up in practice.
+//!
+//! Refs are laid out just like ours, in files under `refs/` and in
+//! `packed-refs`. We only ever read a git repository, never change it.
+//!
+//! Pointing rat at a `.git` directory with `--rat-dir` makes the object store
+//! read from it through here, with trees translated into our format on the
+//! way, so that commands which only read history work on a real repository
+//! just as they do on a nest.
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::compare::FileMode;
+use crate::deflate;
+use crate::error::{GitError, ObjectError};
+use crate::metadata::{CommitMetadata, TagMetadata};
+use crate::objects::{self, ObjectKind};
+use crate::refs::{self, Head};
+use crate::utils;
+
+/// How many bytes of objects read out of packs we hang on to, so that deltas
+/// built on the same base don't each have to rebuild it.
+const CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The mode git gives directories in trees.
+pub const MODE_TREE: u32 = 0o40000;
+
+/// The mode git gives submodules in trees, whose hash names a commit in some
+/// other repository.
+pub const MODE_SUBMODULE: u32 = 0o160000;
+
+/// An object's kind and its data, which can be shared with the cache.
+type SharedObject = (ObjectKind, Rc<Vec<u8>>);
+
+/// A git repository opened for reading.
+pub struct GitRepository {
+    /// The `.git` directory, or the repository itself if it's bare.
+    dir: PathBuf,
+    packs: Vec<GitPack>,
+    /// Objects read out of packs so far, by which pack they're in and where.
+    cache: RefCell<HashMap<(usize, usize), SharedObject>>,
+    /// How many bytes of objects are in `cache`.
+    cached_size: Cell<usize>,
+}
+
+/// A pack file along with its index, all read into memory.
+struct GitPack {
+    /// Where the pack is, so we can say which one is damaged.
+    path: PathBuf,
+    /// The hash of every object in the pack, sorted, as raw bytes.
+    hashes: Vec<[u8; 20]>,
+    /// Where each object in `hashes` starts in `data`.
+    offsets: Vec<usize>,
+    data: Vec<u8>,
+}
+
+/// One line of a git tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitTreeEntry {
+    /// The mode, like `0o100644` for an ordinary file or [`MODE_TREE`] for a
+    /// directory.
+    pub mode: u32,
+    pub name: String,
+    pub hash: String,
+}
+
+impl GitTreeEntry {
+    /// What sort of file the entry is, assuming it isn't a directory or a
+    /// submodule. Git only has a couple of modes for files, but very old
+    /// repositories can have others, like `0o100664`, which are just ordinary
+    /// files.
+    pub fn file_mode(&self) -> FileMode {
+        match self.mode {
+            0o120000 => FileMode::Symlink,
+            mode if mode & 0o111 != 0 => FileMode::Executable,
+            _ => FileMode::Regular,
+        }
+    }
+}
+
+thread_local! {
+    /// Every nest directory we've looked at, along with the git repository
+    /// it turned out to be, if it was one.
+    static OPENED: RefCell<HashMap<PathBuf, Option<Rc<GitRepository>>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Checks whether `dir` is the directory of a git repository, like `.git`,
+/// rather than a nest. Git always writes down the version of its format in
+/// the config, which we never do.
+pub fn is_git_dir(dir: &Path) -> bool {
+    fs::read_to_string(dir.join("config"))
+        .is_ok_and(|config| config.contains("repositoryformatversion"))
+}
+
+/// Opens the nest directory `nest` as a git repository, if that's what it
+/// is. Reading the packs takes a while, so each one is only opened once.
+pub fn open_nest(nest: &Path) -> Option<Rc<GitRepository>> {
+    OPENED.with(|opened| {
+        if let Some(repository) = opened.borrow().get(nest) {
+            return repository.clone();
+        }
+
+        let repository = is_git_dir(nest)
+            .then(|| GitRepository::open(nest).ok().map(Rc::new))
+            .flatten();
+
+        opened
+            .borrow_mut()
+            .insert(nest.to_path_buf(), repository.clone());
+
+        repository
+    })
+}
+
+impl GitRepository {
+    /// Opens the git repository at `path`, which can be a working directory
+    /// with a `.git` directory in it, or the repository itself.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let dot_git = path.join(".git");
+
+        let dir = if dot_git.is_dir() {
+            dot_git
+        } else if dot_git.is_file() {
+            // Linked worktrees and submodules have a file pointing at the
+            // real repository instead.
+            let contents = fs::read_to_string(&dot_git)?;
+            let target = contents.trim().strip_prefix("gitdir: ").ok_or_else(|| {
+                GitError::NotARepository {
+                    path: path.to_path_buf(),
+                }
+            })?;
+
+            path.join(target)
+        } else {
+            path.to_path_buf()
+        };
+
+        if !dir.join("objects").is_dir() || !dir.join("HEAD").is_file() {
+            Err(GitError::NotARepository {
+                path: path.to_path_buf(),
+            })?;
+        }
+
+        // Newer versions of git can use SHA-256 instead, which would need
+        // everything here to be a little different.
+        let config = fs::read_to_string(dir.join("config")).unwrap_or_default();
+
+        if config.lines().any(|line| {
+            let line = line.trim().to_ascii_lowercase();
+            line.starts_with("objectformat") && !line.ends_with("sha1")
+        }) {
+            Err(GitError::UnsupportedHash {
+                path: path.to_path_buf(),
+            })?;
+        }
+
+        let mut packs = Vec::new();
+
+        if let Ok(entries) = fs::read_dir(dir.join("objects/pack")) {
+            let mut paths: Vec<_> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == "idx"))
+                .collect();
+
+            paths.sort();
+
+            for path in paths {
+                packs.push(GitPack::read(&path)?);
+            }
+        }
+
+        Ok(Self {
+            dir,
+            packs,
+            cache: RefCell::new(HashMap::new()),
+            cached_size: Cell::new(0),
+        })
+    }
+
+    /// Reads the object `hash`, whether it's loose or in a pack.
+    pub fn read_object(&self, hash: &str) -> Result<(ObjectKind, Vec<u8>), Box<dyn Error>> {
+        let (kind, data) = self.read_shared_object(hash)?;
+
+        Ok((kind, data.to_vec()))
+    }
+
+    /// Reads the object `hash`, checking that it's a `expected`.
+    pub fn read_object_of_kind(
+        &self,
+        hash: &str,
+        expected: ObjectKind,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (kind, data) = self.read_object(hash)?;
+
+        if kind != expected {
+            Err(ObjectError::WrongKind {
+                hash: hash.to_string(),
+                expected,
+                found: kind,
+            })?;
+        }
+
+        Ok(data)
+    }
+
+    /// Reads the commit `hash`. The tree and parents it names are git hashes.
+    pub fn read_commit(&self, hash: &str) -> Result<CommitMetadata, Box<dyn Error>> {
+        let data = self.read_object_of_kind(hash, ObjectKind::Commit)?;
+
+        // Signatures take up more than one line, but each line after the
+        // first starts with a space, so they look like a header with an empty
+        // key, which gets skipped, as does the encoding once it's been used.
+        CommitMetadata::parse(&decode_text(&data))
+    }
+
+    /// Reads the annotated tag `hash`.
+    pub fn read_tag(&self, hash: &str) -> Result<TagMetadata, Box<dyn Error>> {
+        let data = self.read_object_of_kind(hash, ObjectKind::Tag)?;
+
+        TagMetadata::parse(&decode_text(&data))
+    }
+
+    /// Reads the entries of the tree `hash`.
+    pub fn read_tree(&self, hash: &str) -> Result<Vec<GitTreeEntry>, Box<dyn Error>> {
+        let data = self.read_object_of_kind(hash, ObjectKind::Tree)?;
+        let invalid = || ObjectError::Corrupt {
+            hash: hash.to_string(),
+        };
+
+        let mut entries = Vec::new();
+        let mut rest = &data[..];
+
+        while !rest.is_empty() {
+            let space = rest.iter().position(|&b| b == b' ').ok_or_else(invalid)?;
+            let null = rest.iter().position(|&b| b == 0).ok_or_else(invalid)?;
+
+            let mode = std::str::from_utf8(&rest[..space])
+                .ok()
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                .filter(|_| space < null)
+                .ok_or_else(invalid)?;
+
+            let hash = rest.get(null + 1..null + 21).ok_or_else(invalid)?;
+
+            entries.push(GitTreeEntry {
+                mode,
+                name: String::from_utf8_lossy(&rest[space + 1..null]).into_owned(),
+                hash: utils::to_hex(hash),
+            });
+
+            rest = &rest[null + 21..];
+        }
+
+        Ok(entries)
+    }
+
+    /// Reads the object `hash` as it would be stored in a nest, which only
+    /// makes a difference for trees. Submodules are left out, since we have
+    /// nothing like them, as are names our trees don't allow, like ones with
+    /// line breaks, which they have no way of writing down.
+    pub fn read_object_as_nest(&self, hash: &str) -> Result<(ObjectKind, Vec<u8>), Box<dyn Error>> {
+        let (kind, data) = self.read_object(hash)?;
+
+        if kind != ObjectKind::Tree {
+            return Ok((kind, data));
+        }
+
+        let mut lines = Vec::new();
+
+        for entry in self.read_tree(hash)? {
+            let kind = match entry.mode {
+                MODE_TREE => ObjectKind::Tree.to_string(),
+                MODE_SUBMODULE => continue,
+                _ => entry.file_mode().to_string(),
+            };
+
+            if objects::is_valid_tree_name(&entry.name) {
+                lines.push((entry.name, kind, entry.hash));
+            }
+        }
+
+        // Git sorts directories as if their names ended in a slash, whereas
+        // we just sort by name.
+        lines.sort();
+
+        let data: String = lines
+            .into_iter()
+            .map(|(name, kind, hash)| format!("{kind} {hash} {name}\n"))
+            .collect();
+
+        Ok((kind, data.into_bytes()))
+    }
+
+    /// Checks whether the repository has the object `hash`.
+    pub fn has_object(&self, hash: &str) -> bool {
+        let Some(raw) = utils::from_hex(hash).and_then(|raw| <[u8; 20]>::try_from(raw).ok()) else {
+            return false;
+        };
+
+        let (directory, file) = hash.split_at(2);
+
+        self.dir
+            .join("objects")
+            .join(directory)
+            .join(file)
+            .is_file()
+            || self
+                .packs
+                .iter()
+                .any(|pack| pack.hashes.binary_search(&raw).is_ok())
+    }
+
+    /// Lists the hashes of every object starting with `prefix`, which has to
+    /// be at least two characters long.
+    pub fn find_objects_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let mut hashes: Vec<String> = self
+            .packs
+            .iter()
+            .flat_map(|pack| &pack.hashes)
+            .map(|hash| utils::to_hex(hash))
+            .filter(|hash| hash.starts_with(prefix))
+            .collect();
+
+        let (directory, file_prefix) = prefix.split_at(2);
+
+        if let Ok(entries) = fs::read_dir(self.dir.join("objects").join(directory)) {
+            for entry in entries.flatten() {
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+
+                if file_name.starts_with(file_prefix) {
+                    hashes.push(format!("{directory}{file_name}"));
+                }
+            }
+        }
+
+        hashes.sort();
+        hashes.dedup();
+
+        hashes
+    }
+
+    /// Follows the object `hash` through any annotated tags to whatever they
+    /// eventually point at.
+    pub fn peel(&self, hash: &str) -> Result<(ObjectKind, String), Box<dyn Error>> {
+        let mut hash = hash.to_string();
+
+        loop {
+            match self.read_shared_object(&hash)?.0 {
+                ObjectKind::Tag => hash = self.read_tag(&hash)?.object,
+                kind => return Ok((kind, hash)),
+            }
+        }
+    }
+
+    /// Reads what `HEAD` points at.
+    pub fn read_head(&self) -> Result<Head, Box<dyn Error>> {
+        Ok(refs::parse_head(&fs::read_to_string(
+            self.dir.join("HEAD"),
+        )?)?)
+    }
+
+    /// Lists every ref that points straight at an object, by full name, along
+    /// with the hash it points at.
+    pub fn list_refs(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+        let mut refs = BTreeMap::new();
+
+        // Packed refs come first, so that a loose ref with the same name,
+        // which is always newer, replaces it.
+        if let Ok(packed) = fs::read_to_string(self.dir.join("packed-refs")) {
+            for line in packed.lines() {
+                // Comments start with #, and lines starting with ^ say what the
+                // annotated tag on the line before points at.
+                if line.starts_with('#') || line.starts_with('^') {
+                    continue;
+                }
+
+                if let Some((hash, name)) = line.split_once(' ') {
+                    refs.insert(name.to_string(), hash.to_string());
+                }
+            }
+        }
+
+        let mut directories = vec![self.dir.join("refs")];
+
+        while let Some(directory) = directories.pop() {
+            let Ok(entries) = fs::read_dir(&directory) else {
+                continue;
+            };
+
+            for entry in entries {
+                let path = entry?.path();
+
+                if path.is_dir() {
+                    directories.push(path);
+                    continue;
+                }
+
+                let contents = fs::read_to_string(&path)?;
+                let hash = contents.trim();
+
+                // Symbolic refs like refs/remotes/origin/HEAD just repeat
+                // another ref, so they're left out.
+                if hash.len() != 40 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    continue;
+                }
+
+                let name = path
+                    .strip_prefix(&self.dir)?
+                    .to_string_lossy()
+                    .replace('\\', "/");
+
+                refs.insert(name, hash.to_ascii_lowercase());
+            }
+        }
+
+        Ok(refs)
+    }
+
+    /// Reads the object `hash`, sharing its data with the cache if it came
+    /// out of a pack.
+    fn read_shared_object(&self, hash: &str) -> Result<SharedObject, Box<dyn Error>> {
+        let not_found = || ObjectError::NotFound {
+            hash: hash.to_string(),
+        };
+        let raw: [u8; 20] = utils::from_hex(hash)
+            .and_then(|raw| raw.try_into().ok())
+            .ok_or_else(not_found)?;
+
+        let (directory, file) = hash.split_at(2);
+        let loose_path = self.dir.join("objects").join(directory).join(file);
+
+        if loose_path.is_file() {
+            let (kind, data) =
+                read_loose_object(&loose_path).ok_or_else(|| ObjectError::Corrupt {
+                    hash: hash.to_string(),
+                })?;
+
+            return Ok((kind, Rc::new(data)));
+        }
+
+        for (pack, contents) in self.packs.iter().enumerate() {
+            if let Ok(index) = contents.hashes.binary_search(&raw) {
+                return self.read_packed_object(pack, contents.offsets[index]);
+            }
+        }
+
+        Err(not_found())?
+    }
+
+    /// Reads the object starting at `offset` in the pack `pack`, applying
+    /// whatever deltas it's made of.
+    fn read_packed_object(
+        &self,
+        pack: usize,
+        offset: usize,
+    ) -> Result<SharedObject, Box<dyn Error>> {
+        if let Some(cached) = self.cache.borrow().get(&(pack, offset)) {
+            return Ok(cached.clone());
+        }
+
+        let damaged = || GitError::DamagedPack {
+            path: self.packs[pack].path.clone(),
+        };
+        let data = &self.packs[pack].data;
+
+        // Each entry starts with its type and uncompressed size, packed into
+        // as many bytes as they need, seven bits at a time.
+        let mut position = offset;
+        let mut byte = *data.get(position).ok_or_else(damaged)?;
+        let entry_type = (byte >> 4) & 7;
+        let mut size = usize::from(byte & 0x0f);
+        let mut shift = 4;
+
+        while byte & 0x80 != 0 {
+            position += 1;
+            byte = *data.get(position).ok_or_else(damaged)?;
+            size |= usize::from(byte & 0x7f) << shift;
+            shift += 7;
+        }
+
+        position += 1;
+
+        let (kind, contents) = match entry_type {
+            1..=4 => {
+                let kind = [
+                    ObjectKind::Commit,
+                    ObjectKind::Tree,
+                    ObjectKind::Blob,
+                    ObjectKind::Tag,
+                ][usize::from(entry_type - 1)];
+
+                let contents = deflate::decompress_zlib(&data[position..]).ok_or_else(damaged)?;
+
+                (kind, contents)
+            }
+            // A delta whose base is further back in the same pack. How far back
+            // is written big end first, and each byte after the first adds one
+            // before shifting, so that no distance has two ways to write it.
+            6 => {
+                let mut byte = *data.get(position).ok_or_else(damaged)?;
+                let mut distance = usize::from(byte & 0x7f);
+
+                while byte & 0x80 != 0 {
+                    position += 1;
+                    byte = *data.get(position).ok_or_else(damaged)?;
+                    distance = ((distance + 1) << 7) | usize::from(byte & 0x7f);
+                }
+
+                let base_offset = offset.checked_sub(distance).ok_or_else(damaged)?;
+                let (kind, base) = self.read_packed_object(pack, base_offset)?;
+                let delta = deflate::decompress_zlib(&data[position + 1..]).ok_or_else(damaged)?;
+
+                (kind, apply_delta(&base, &delta).ok_or_else(damaged)?)
+            }
+            // A delta whose base is named by its hash.
+            7 => {
+                let base_hash = data.get(position..position + 20).ok_or_else(damaged)?;
+                let (kind, base) = self.read_shared_object(&utils::to_hex(base_hash))?;
+                let delta = deflate::decompress_zlib(&data[position + 20..]).ok_or_else(damaged)?;
+
+                (kind, apply_delta(&base, &delta).ok_or_else(damaged)?)
+            }
+            _ => Err(damaged())?,
+        };
+
+        if contents.len() != size && entry_type <= 4 {
+            Err(damaged())?;
+        }
+
+        let contents = Rc::new(contents);
+        let mut cache = self.cache.borrow_mut();
+
+        // Rather than keeping track of which objects were used last, we just
+        // start over whenever the cache gets full.
+        if self.cached_size.get() + contents.len() > CACHE_SIZE {
+            cache.clear();
+            self.cached_size.set(0);
+        }
+
+        self.cached_size
+            .set(self.cached_size.get() + contents.len());
+
+        cache.insert((pack, offset), (kind, contents.clone()));
+
+        Ok((kind, contents))
+    }
+}
+
+impl GitPack {
+    /// Reads the pack index at `index_path`, and the pack next to it.
+    ///
+    /// An index starts with a magic number and its version, then how many
+    /// objects have hashes starting with each byte or less, then every hash in
+    /// order, a checksum of each entry, and where each one starts in the
+    /// pack. Packs over 2 GiB need more than 31 bits for that, so offsets with
+    /// the top bit set point into a table of bigger ones at the end.
+    fn read(index_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let damaged = || GitError::DamagedPack {
+            path: index_path.to_path_buf(),
+        };
+        let index = fs::read(index_path)?;
+
+        if index.get(..8) != Some(b"\xfftOc\0\0\0\x02".as_slice()) {
+            Err(GitError::UnsupportedPackIndex {
+                path: index_path.to_path_buf(),
+            })?;
+        }
+
+        let read_u32 = |position: usize| {
+            index
+                .get(position..position + 4)
+                .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+        };
+
+        let count = read_u32(8 + 255 * 4).ok_or_else(damaged)?;
+        let hashes_start = 8 + 256 * 4;
+        let offsets_start = hashes_start + count * 24;
+        let large_offsets_start = offsets_start + count * 4;
+
+        let mut hashes = Vec::with_capacity(count);
+        let mut offsets = Vec::with_capacity(count);
+
+        for i in 0..count {
+            let hash = index
+                .get(hashes_start + i * 20..hashes_start + i * 20 + 20)
+                .ok_or_else(damaged)?;
+
+            hashes.push(hash.try_into()?);
+
+            let offset = read_u32(offsets_start + i * 4).ok_or_else(damaged)?;
+
+            offsets.push(if offset & 0x8000_0000 == 0 {
+                offset
+            } else {
+                let position = large_offsets_start + (offset & 0x7fff_ffff) * 8;
+                let bytes = index.get(position..position + 8).ok_or_else(damaged)?;
+
+                u64::from_be_bytes(bytes.try_into()?) as usize
+            });
+        }
+
+        let path = index_path.with_extension("pack");
+        let data = fs::read(&path)?;
+
+        if data.get(..4) != Some(b"PACK".as_slice()) {
+            return Err(GitError::DamagedPack { path }.into());
+        }
+
+        Ok(Self {
+            path,
+            hashes,
+            offsets,
+            data,
+        })
+    }
+}
+
+/// Reads the loose object in the file at `path`, which is compressed along
+/// with its header.
+fn read_loose_object(path: &Path) -> Option<(ObjectKind, Vec<u8>)> {
+    let mut data = deflate::decompress_zlib(&fs::read(path).ok()?)?;
+
+    let null = data.iter().position(|&b| b == 0)?;
+    let header = std::str::from_utf8(&data[..null]).ok()?;
+    let (kind, length) = header.split_once(' ')?;
+
+    if length.parse::<usize>().ok()? != data.len() - null - 1 {
+        return None;
+    }
+
+    let kind = kind.parse().ok()?;
+    data.drain(..=null);
+
+    Some((kind, data))
+}
+
+/// Builds an object from its base and a git delta.
+///
+/// A delta starts with the sizes of the base and the result, each written
+/// seven bits at a time. Then come instructions, each starting with a byte
+/// whose top bit says what it is. If it's set, the instruction copies from
+/// the base, and the other bits say which of the four offset bytes and three
+/// size bytes follow, lowest first, with the rest being zero. Otherwise, the
+/// byte is how many bytes that follow should be copied in as they are.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+
+    let mut read_size = || {
+        let mut size = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = *delta.get(position)?;
+            position += 1;
+            size |= usize::from(byte & 0x7f) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                return Some(size);
+            }
+        }
+    };
+
+    let base_size = read_size()?;
+    let result_size = read_size()?;
+
+    if base_size != base.len() {
+        return None;
+    }
+
+    let mut result = Vec::with_capacity(result_size);
+
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+
+        if instruction & 0x80 != 0 {
+            let mut values = [0usize; 2];
+
+            for (bit, shift) in (0..7).zip([0, 8, 16, 24, 0, 8, 16]) {
+                if instruction & (1 << bit) != 0 {
+                    values[usize::from(bit >= 4)] |= usize::from(*delta.get(position)?) << shift;
+                    position += 1;
+                }
+            }
+
+            // A size of zero can't be useful, so it means 64 KiB instead.
+            let [offset, size] = values;
+            let size = if size == 0 { 0x10000 } else { size };
+
+            result.extend_from_slice(base.get(offset..offset + size)?);
+        } else if instruction != 0 {
+            let length = usize::from(instruction);
+
+            result.extend_from_slice(delta.get(position..position + length)?);
+            position += length;
+        } else {
+            return None;
+        }
+    }
+
+    (result.len() == result_size).then_some(result)
+}
+
+/// Turns the data of a git commit or tag into text, going by the encoding
+/// its `encoding` header names, or UTF-8 if it doesn't have one.
+///
+/// Git leaves it to whoever wrote the commit to say what encoding it's in,
+/// and nearly everybody uses UTF-8. The one other encoding we understand is
+/// ISO-8859-1, where every byte is the character with the same number. Any
+/// other encoding is read as UTF-8, with whatever doesn't fit replaced.
+pub fn decode_text(data: &[u8]) -> Cow<'_, str> {
+    // The headers end at the first blank line.
+    let header_end = data
+        .windows(2)
+        .position(|pair| pair == b"\n\n")
+        .unwrap_or(data.len());
+
+    let encoding = data[..header_end]
+        .split(|&byte| byte == b'\n')
+        .find_map(|line| line.strip_prefix(b"encoding "))
+        .map(|encoding| {
+            String::from_utf8_lossy(encoding)
+                .to_lowercase()
+                .replace(['-', '_'], "")
+        });
+
+    match encoding.as_deref() {
+        Some("iso88591" | "latin1" | "l1") => data.iter().map(|&byte| char::from(byte)).collect(),
+        _ => String::from_utf8_lossy(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_text_without_an_encoding_as_utf8() {
+        let data = "tree 0\nauthor Zoë <z@example.com> 0 +0000\n\nCafé".as_bytes();
+
+        assert_eq!(decode_text(data), String::from_utf8_lossy(data));
+    }
+
+    #[test]
+    fn decodes_iso_8859_1() {
+        let data = b"tree 0\nencoding ISO-8859-1\n\nCaf\xe9 \xa3";
+
+        assert_eq!(decode_text(data), "tree 0\nencoding ISO-8859-1\n\nCafé £");
+    }
+
+    #[test]
+    fn only_looks_for_the_encoding_in_the_headers() {
+        let data = b"tree 0\n\nencoding latin1\nCaf\xe9";
+
+        assert_eq!(decode_text(data), "tree 0\n\nencoding latin1\nCaf\u{fffd}");
+    }
+}
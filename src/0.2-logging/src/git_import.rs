@@ -0,0 +1,328 @@
+//! Bringing the history of a git repository into a nest.
+//!
+//! We read git's objects with the [`git`](crate::git) module and write each of
+//! them again as one of ours. Every object ends up with a different hash, so a
+//! commit can only be written once all of its parents have been, since it has
+//! to name their new hashes. Messages, authors, committers and dates all
+//! carry over exactly, so the history looks just the same apart from the
+//! hashes. Commits written in ISO-8859-1 are turned into UTF-8 on the way,
+//! since that's the only encoding our commits are ever in.
+//!
+//! Branches and tags come across, but remote branches, stashes and notes
+//! don't. Rat has nothing like a submodule, so those are left out of the
+//! trees they're in.
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::path::Path;
+
+use crate::compare::{self, Snapshot};
+use crate::git::{self, GitRepository};
+use crate::index::Index;
+use crate::metadata::{CommitMetadata, TagMetadata};
+use crate::objects::{self, ObjectKind};
+use crate::refs::{self, Head, Ref};
+use crate::worktree::{check_untracked_files, restore_snapshot};
+
+/// What an import brought into the nest.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub commits: usize,
+    pub branches: usize,
+    pub tags: usize,
+    /// The paths of the submodules that were left out.
+    pub submodules: BTreeSet<String>,
+    /// Anything else we couldn't bring across, and why.
+    pub skipped: Vec<String>,
+    /// The branch we checked out, if the nest didn't have anything checked
+    /// out yet.
+    pub checked_out: Option<String>,
+}
+
+/// Copies every branch and tag of the git repository at `path` into the
+/// nest, along with all of their history.
+///
+/// If nothing has been committed on the current branch yet, we also switch to
+/// whichever branch the git repository is on and check it out, so that an
+/// import into a brand new nest leaves it ready to use.
+pub fn import_git(path: &Path) -> Result<ImportSummary, Box<dyn Error>> {
+    let git = GitRepository::open(path)?;
+    let was_empty = refs::resolve_head()?.is_none();
+
+    let mut importer = Importer {
+        git,
+        commits: HashMap::new(),
+        blobs: HashMap::new(),
+        trees: HashMap::new(),
+        summary: ImportSummary::default(),
+    };
+
+    let git_refs = importer.git.list_refs()?;
+
+    for (name, hash) in &git_refs {
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            let (kind, target) = importer.git.peel(hash)?;
+
+            if kind != ObjectKind::Commit {
+                importer.skip(format!("branch {branch}, which isn't a commit"));
+                continue;
+            }
+
+            let commit = importer.import_history(&target)?;
+
+            if importer.write_ref(Ref::Branch(branch.to_string()), &commit)? {
+                importer.summary.branches += 1;
+            }
+        } else if let Some(tag) = name.strip_prefix("refs/tags/") {
+            importer.import_tag(tag, hash)?;
+        }
+    }
+
+    let mut summary = importer.summary;
+
+    if was_empty {
+        if let Head::Branch(branch) = importer.git.read_head()? {
+            if git_refs.contains_key(&format!("refs/heads/{branch}")) {
+                refs::set_head_branch(&branch)?;
+            }
+        }
+
+        if let Some(commit) = refs::resolve_head()? {
+            check_out(&commit)?;
+
+            if let Head::Branch(branch) = refs::read_head()? {
+                summary.checked_out = Some(branch);
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Fills the working directory and the index from `commit`, without touching
+/// anything that isn't tracked.
+fn check_out(commit: &str) -> Result<(), Box<dyn Error>> {
+    let index = Index::read(crate::nest_path("index"))?;
+    let working_snapshot = compare::read_working_directory(&index.entries)?;
+    let target_snapshot = compare::read_commit(Some(commit))?;
+
+    check_untracked_files(
+        &index.entries,
+        &working_snapshot,
+        &target_snapshot,
+        "importing",
+    )?;
+
+    restore_snapshot(&index.entries, &working_snapshot, target_snapshot)?;
+
+    Ok(())
+}
+
+struct Importer {
+    git: GitRepository,
+    /// Our hash for every git commit imported so far, by its git hash.
+    commits: HashMap<String, String>,
+    /// The same for blobs.
+    blobs: HashMap<String, String>,
+    /// The same for trees, which is `None` for a tree that ended up empty
+    /// because it only held submodules.
+    trees: HashMap<String, Option<String>>,
+    summary: ImportSummary,
+}
+
+impl Importer {
+    fn skip(&mut self, what: String) {
+        self.summary.skipped.push(what);
+    }
+
+    /// Points `name` at `hash`, unless it already points somewhere else, in
+    /// which case we leave it alone. Returns whether the ref now points at
+    /// `hash`.
+    fn write_ref(&mut self, name: Ref, hash: &str) -> Result<bool, Box<dyn Error>> {
+        match refs::read_ref(&name)? {
+            Some(existing) if existing != hash => {
+                self.skip(format!("{name}, which already exists in the nest"));
+
+                Ok(false)
+            }
+            _ => {
+                refs::write_ref(&name, hash)?;
+
+                Ok(true)
+            }
+        }
+    }
+
+    /// Imports every commit in the history of the git commit `tip` that
+    /// hasn't been imported yet, returning our hash for `tip`.
+    fn import_history(&mut self, tip: &str) -> Result<String, Box<dyn Error>> {
+        // Each entry is a commit, along with whether its parents have been
+        // dealt with already, so that we only write it after all of them.
+        let mut stack = vec![(tip.to_string(), false)];
+
+        while let Some((hash, parents_done)) = stack.pop() {
+            if self.commits.contains_key(&hash) {
+                continue;
+            }
+
+            let metadata = self.git.read_commit(&hash)?;
+
+            if parents_done {
+                self.import_commit(&hash, metadata)?;
+                continue;
+            }
+
+            stack.push((hash, true));
+
+            for parent in metadata.parents.iter().rev() {
+                if !self.commits.contains_key(parent) {
+                    stack.push((parent.clone(), false));
+                }
+            }
+        }
+
+        Ok(self.commits[tip].clone())
+    }
+
+    fn import_commit(
+        &mut self,
+        hash: &str,
+        metadata: CommitMetadata,
+    ) -> Result<(), Box<dyn Error>> {
+        let tree = match self.import_tree(&metadata.tree, "")? {
+            Some(tree) => tree,
+            None => objects::write_tree(&Snapshot::new())?,
+        };
+
+        let parents = metadata
+            .parents
+            .iter()
+            .map(|parent| self.commits[parent].clone())
+            .collect();
+
+        let imported = CommitMetadata {
+            tree,
+            parents,
+            ..metadata
+        };
+
+        let new_hash = objects::write_object(ObjectKind::Commit, imported.serialize().as_bytes())?;
+
+        self.commits.insert(hash.to_string(), new_hash);
+        self.summary.commits += 1;
+
+        Ok(())
+    }
+
+    /// Imports the git tree `hash`, which is at `path` in the commit, and
+    /// returns our hash for it, or `None` if nothing in it could be imported.
+    fn import_tree(&mut self, hash: &str, path: &str) -> Result<Option<String>, Box<dyn Error>> {
+        if let Some(tree) = self.trees.get(hash) {
+            return Ok(tree.clone());
+        }
+
+        let mut lines = Vec::new();
+
+        for entry in self.git.read_tree(hash)? {
+            let entry_path = match path {
+                "" => entry.name.clone(),
+                _ => format!("{path}/{}", entry.name),
+            };
+
+            // Each line of one of our trees is a single entry, so a name with a
+            // line break in it can't be written down, and names like `.rat`
+            // aren't allowed in one at all.
+            if !objects::is_valid_tree_name(&entry.name) {
+                self.skip(format!(
+                    "{entry_path:?}, since a nest can't have a file by that name"
+                ));
+                continue;
+            }
+
+            let (kind, new_hash) = match entry.mode {
+                git::MODE_TREE => match self.import_tree(&entry.hash, &entry_path)? {
+                    Some(tree) => (ObjectKind::Tree.to_string(), tree),
+                    None => continue,
+                },
+                git::MODE_SUBMODULE => {
+                    self.summary.submodules.insert(entry_path);
+                    continue;
+                }
+                _ => (
+                    entry.file_mode().to_string(),
+                    self.import_blob(&entry.hash)?,
+                ),
+            };
+
+            lines.push((entry.name, kind, new_hash));
+        }
+
+        // Git sorts directories as if their names ended in a slash, whereas
+        // we just sort by name.
+        lines.sort();
+
+        let tree = match lines.is_empty() {
+            true => None,
+            false => {
+                let data: String = lines
+                    .into_iter()
+                    .map(|(name, kind, hash)| format!("{kind} {hash} {name}\n"))
+                    .collect();
+
+                Some(objects::write_object(ObjectKind::Tree, data.as_bytes())?)
+            }
+        };
+
+        self.trees.insert(hash.to_string(), tree.clone());
+
+        Ok(tree)
+    }
+
+    fn import_blob(&mut self, hash: &str) -> Result<String, Box<dyn Error>> {
+        if let Some(blob) = self.blobs.get(hash) {
+            return Ok(blob.clone());
+        }
+
+        let data = self.git.read_object_of_kind(hash, ObjectKind::Blob)?;
+        let blob = objects::write_object(ObjectKind::Blob, &data)?;
+
+        self.blobs.insert(hash.to_string(), blob.clone());
+
+        Ok(blob)
+    }
+
+    /// Imports the tag `name`, which points at the git object `hash`.
+    fn import_tag(&mut self, name: &str, hash: &str) -> Result<(), Box<dyn Error>> {
+        let (kind, target) = self.git.peel(hash)?;
+
+        // Our tags can only ever point at commits, in the end.
+        if kind != ObjectKind::Commit {
+            self.skip(format!("tag {name}, which points at a {kind}"));
+            return Ok(());
+        }
+
+        let commit = self.import_history(&target)?;
+
+        // Very old annotated tags have no tagger, which ours need, so those
+        // become lightweight tags instead. A tag of a tag ends up pointing
+        // straight at the commit.
+        let tag_hash = match self.git.read_tag(hash) {
+            Ok(metadata) => {
+                let imported = TagMetadata {
+                    object: commit,
+                    kind: ObjectKind::Commit,
+                    ..metadata
+                };
+
+                objects::write_object(ObjectKind::Tag, imported.serialize().as_bytes())?
+            }
+            Err(_) => commit,
+        };
+
+        if self.write_ref(Ref::Tag(name.to_string()), &tag_hash)? {
+            self.summary.tags += 1;
+        }
+
+        Ok(())
+    }
+}
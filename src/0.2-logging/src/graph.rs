@@ -0,0 +1,89 @@
+//! Walking the commit graph.
+//!
+//! Every commit points at its parents, so the whole history forms what's
+//! called a directed acyclic graph: commits are the nodes, and each one has an
+//! edge to each of its parents. Lots of questions about history, like whether
+//! one commit is contained in another's history, boil down to walking along
+//! those edges.
+//!
+//! Only the parents and commit time of each commit matter here, so we read
+//! them through the [`commit_graph`] cache.
+
+use std::collections::HashSet;
+
+use crate::commit_graph;
+use crate::error::ObjectError;
+
+/// Finds every commit reachable from `start` by following parents, including
+/// `start` itself.
+pub fn reachable(start: &str) -> Result<HashSet<String>, ObjectError> {
+    let mut seen = HashSet::new();
+    let mut to_visit = vec![start.to_string()];
+
+    while let Some(hash) = to_visit.pop() {
+        // Two parents can easily share an ancestor, so we need to make sure we
+        // don't walk the same part of the history twice.
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        to_visit.extend(commit_graph::read(&hash)?.parents);
+    }
+
+    Ok(seen)
+}
+
+/// Checks whether `ancestor` is part of the history of `descendant`. A commit
+/// counts as its own ancestor.
+pub fn is_ancestor(ancestor: &str, descendant: &str) -> Result<bool, ObjectError> {
+    Ok(reachable(descendant)?.contains(ancestor))
+}
+
+/// Finds the best common ancestor of `a` and `b`, which is where their
+/// histories split apart, or `None` if they don't share any history at all.
+/// If there's more than one, we pick the most recent.
+pub fn merge_base(a: &str, b: &str) -> Result<Option<String>, ObjectError> {
+    Ok(merge_bases(a, b)?.into_iter().next())
+}
+
+/// Finds every best common ancestor of `a` and `b`, most recent first.
+///
+/// Every commit that's an ancestor of both is a common ancestor, but most of
+/// them are also ancestors of other common ancestors, which makes them less
+/// useful. We're after the ones that aren't. There's usually just one, but
+/// there can be several when histories have been merged back and forth.
+pub fn merge_bases(a: &str, b: &str) -> Result<Vec<String>, ObjectError> {
+    let from_a = reachable(a)?;
+    let common: HashSet<String> = reachable(b)?
+        .into_iter()
+        .filter(|hash| from_a.contains(hash))
+        .collect();
+
+    // Anything reachable from the parents of a common ancestor is an ancestor
+    // of another common ancestor, so it can't be one of the best ones.
+    let mut seen = HashSet::new();
+    let mut to_visit = Vec::new();
+
+    for hash in &common {
+        to_visit.extend(commit_graph::read(hash)?.parents);
+    }
+
+    while let Some(hash) = to_visit.pop() {
+        if seen.insert(hash.clone()) {
+            to_visit.extend(commit_graph::read(&hash)?.parents);
+        }
+    }
+
+    let mut best = Vec::new();
+
+    for hash in common.into_iter().filter(|hash| !seen.contains(hash)) {
+        let timestamp = commit_graph::read(&hash)?.timestamp;
+        best.push((timestamp, hash));
+    }
+
+    // Ties are broken by the hash, just so the answer never depends on the
+    // order we happened to find them in.
+    best.sort_by(|x, y| y.cmp(x));
+
+    Ok(best.into_iter().map(|(_, hash)| hash).collect())
+}
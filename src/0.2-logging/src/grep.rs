@@ -0,0 +1,127 @@
+//! Searching the contents of files for a pattern.
+//!
+//! Like `git grep`, we only search the files rat knows about: the ones being
+//! tracked in the working directory, or every file in a commit. Untracked
+//! files can be searched too, as long as they aren't ignored.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io;
+
+use crate::compare::{self, FileMode};
+use crate::error::ObjectError;
+use crate::index::Index;
+use crate::objects::{self, ObjectKind};
+use crate::regex::Regex;
+use crate::{ignore, utils};
+
+/// Somewhere a pattern was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrepMatch {
+    /// A line that matches.
+    Line {
+        path: String,
+        /// The number of the line, counting from one.
+        number: usize,
+        /// The line itself, without its line ending.
+        text: String,
+        /// Where in the line each match is, as byte ranges.
+        matches: Vec<(usize, usize)>,
+    },
+    /// A binary file that matches somewhere. Its lines wouldn't mean much to
+    /// anyone, so we don't show them.
+    Binary { path: String },
+}
+
+/// Searches the files being tracked in the working directory for `regex`,
+/// along with every untracked file that isn't ignored if `untracked` is set.
+pub fn search_working_directory(
+    regex: &Regex,
+    untracked: bool,
+) -> Result<Vec<GrepMatch>, Box<dyn Error>> {
+    let mut paths: BTreeSet<String> = Index::read(crate::nest_path("index"))?
+        .entries
+        .into_keys()
+        .collect();
+
+    if untracked {
+        paths.extend(ignore::list_working_files("")?);
+    }
+
+    let paths: Vec<String> = paths.into_iter().collect();
+
+    let results = utils::parallel_map(&paths, |path| -> io::Result<Vec<GrepMatch>> {
+        // Files that have been deleted but not staged yet have nothing to
+        // search, and neither do symlinks, which could lead anywhere.
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => return Ok(Vec::new()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        }
+
+        Ok(search_file(regex, path, &fs::read(path)?))
+    });
+
+    let mut found = Vec::new();
+
+    for result in results {
+        found.extend(result?);
+    }
+
+    Ok(found)
+}
+
+/// Searches every file in the commit with the hash `commit` for `regex`.
+pub fn search_commit(regex: &Regex, commit: &str) -> Result<Vec<GrepMatch>, Box<dyn Error>> {
+    // Other nests inside this one have nothing of theirs stored here.
+    let snapshot: Vec<_> = compare::read_commit(Some(commit))?
+        .into_iter()
+        .filter(|(_, entry)| entry.mode != FileMode::Nest)
+        .collect();
+
+    let results = utils::parallel_map(&snapshot, |(path, entry)| -> Result<_, ObjectError> {
+        let data = objects::read_object_of_kind(&entry.hash, ObjectKind::Blob)?;
+
+        Ok(search_file(regex, path, &data))
+    });
+
+    let mut found = Vec::new();
+
+    for result in results {
+        found.extend(result?);
+    }
+
+    Ok(found)
+}
+
+/// Searches the file at `path`, which holds `data`, for `regex`.
+fn search_file(regex: &Regex, path: &str, data: &[u8]) -> Vec<GrepMatch> {
+    if utils::is_binary(data) {
+        let text = String::from_utf8_lossy(data);
+
+        return if text.lines().any(|line| regex.is_match(line)) {
+            vec![GrepMatch::Binary {
+                path: path.to_string(),
+            }]
+        } else {
+            Vec::new()
+        };
+    }
+
+    String::from_utf8_lossy(data)
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let matches = regex.find_all(line);
+
+            (!matches.is_empty()).then(|| GrepMatch::Line {
+                path: path.to_string(),
+                number: i + 1,
+                text: line.to_string(),
+                matches,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,536 @@
+//! Choosing the hash function that names objects.
+//!
+//! Every object is named after a hash of its contents, which is normally
+//! SHA-256. A nest can use [BLAKE3](https://github.com/BLAKE3-team/BLAKE3)
+//! instead, which is just as hard to fool but a good deal faster, so big
+//! commits take less time to hash. Which one a nest uses is picked when it's
+//! created, with `rat init --hash blake3`, and can never change afterwards,
+//! since that would change the name of every object in it.
+//!
+//! The choice is written down in the nest's [`format`](crate::format) file.
+//! Nests made before there was a choice don't say, and use SHA-256. Both hash
+//! functions give 32-byte hashes, so hashes look the same whichever one made
+//! them, and packs and the commit graph don't need to care.
+//! Two nests that use different ones can't share history, though, since the
+//! same commit would have a different hash in each.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+
+use crate::format;
+use crate::utils::{self, Sha256};
+
+/// Something that works out the hash of an object, fed a piece at a time.
+///
+/// Data can also be fed in by writing it, so that anything that writes, like
+/// `io::copy`, can send it straight to the hasher.
+pub trait ObjectHasher: io::Write + Send {
+    /// Feeds more data into the hash.
+    fn update(&mut self, data: &[u8]);
+
+    /// Finishes the hash, returning the 32-byte digest.
+    fn finish(self: Box<Self>) -> [u8; 32];
+
+    /// Finishes the hash, returning it as hexadecimal, which is how we write
+    /// hashes everywhere else.
+    fn finish_hex(self: Box<Self>) -> String {
+        utils::to_hex(&self.finish())
+    }
+}
+
+impl ObjectHasher for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Sha256::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> [u8; 32] {
+        self.finalize()
+    }
+}
+
+impl ObjectHasher for Blake3 {
+    fn update(&mut self, data: &[u8]) {
+        Blake3::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> [u8; 32] {
+        self.finalize()
+    }
+}
+
+/// The hash functions a nest can name its objects with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Starts a new hash.
+    pub fn hasher(self) -> Box<dyn ObjectHasher> {
+        match self {
+            Self::Sha256 => Box::new(Sha256::new()),
+            Self::Blake3 => Box::new(Blake3::new()),
+        }
+    }
+
+    /// Hashes `data` in one go, returning the hash as hexadecimal.
+    pub fn hash(self, data: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finish_hex()
+    }
+
+    /// The name people know the hash function by, for messages.
+    pub fn display_name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "SHA-256",
+            Self::Blake3 => "BLAKE3",
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            _ => Err(format!(
+                "Unknown hash function {s}. It has to be sha256 or blake3."
+            )),
+        }
+    }
+}
+
+/// The hash function of each nest directory we've looked at so far, since
+/// we need it for every object we read or write.
+fn cache() -> std::sync::MutexGuard<'static, HashMap<PathBuf, HashAlgorithm>> {
+    static ALGORITHMS: OnceLock<Mutex<HashMap<PathBuf, HashAlgorithm>>> = OnceLock::new();
+
+    ALGORITHMS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Finds the hash function the nest directory `nest` uses.
+///
+/// A `format` file we can't make sense of counts as SHA-256 here, which is
+/// safe because [`Repository::open`](crate::repository::Repository::open)
+/// refuses to work with such a nest in the first place.
+pub fn algorithm_in(nest: &Path) -> HashAlgorithm {
+    if let Some(algorithm) = cache().get(nest) {
+        return *algorithm;
+    }
+
+    let algorithm = format::read(nest).map_or_else(|_| HashAlgorithm::default(), |f| f.hash);
+    cache().insert(nest.to_path_buf(), algorithm);
+
+    algorithm
+}
+
+/// Finds the hash function our own nest uses.
+pub fn current() -> HashAlgorithm {
+    algorithm_in(crate::nest_dir())
+}
+
+/// The words BLAKE3 starts from, which are the same as SHA-256's.
+const BLAKE3_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// The order the words of a block are shuffled into between rounds.
+const BLAKE3_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+/// Flags that say what a block is a part of.
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+
+const BLOCK_LENGTH: usize = 64;
+const CHUNK_LENGTH: usize = 1024;
+
+/// Mixes two words of a block into four words of the state.
+fn mix(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(x);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(y);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+/// Squeezes a 64-byte block into the chaining value `chaining_value`, which
+/// is how BLAKE3 takes in everything it hashes.
+fn compress(
+    chaining_value: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    block_length: u32,
+    flags: u32,
+) -> [u32; 16] {
+    let mut state = [
+        chaining_value[0],
+        chaining_value[1],
+        chaining_value[2],
+        chaining_value[3],
+        chaining_value[4],
+        chaining_value[5],
+        chaining_value[6],
+        chaining_value[7],
+        BLAKE3_IV[0],
+        BLAKE3_IV[1],
+        BLAKE3_IV[2],
+        BLAKE3_IV[3],
+        counter as u32,
+        (counter >> 32) as u32,
+        block_length,
+        flags,
+    ];
+    let mut block = *block;
+
+    for round in 0..7 {
+        // The columns first, and then the diagonals.
+        mix(&mut state, 0, 4, 8, 12, block[0], block[1]);
+        mix(&mut state, 1, 5, 9, 13, block[2], block[3]);
+        mix(&mut state, 2, 6, 10, 14, block[4], block[5]);
+        mix(&mut state, 3, 7, 11, 15, block[6], block[7]);
+        mix(&mut state, 0, 5, 10, 15, block[8], block[9]);
+        mix(&mut state, 1, 6, 11, 12, block[10], block[11]);
+        mix(&mut state, 2, 7, 8, 13, block[12], block[13]);
+        mix(&mut state, 3, 4, 9, 14, block[14], block[15]);
+
+        if round < 6 {
+            block = BLAKE3_PERMUTATION.map(|i| block[i]);
+        }
+    }
+
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= chaining_value[i];
+    }
+
+    state
+}
+
+/// Reads a block as the little-endian words BLAKE3 works on. Blocks shorter
+/// than 64 bytes are padded with zeroes.
+fn block_words(block: &[u8]) -> [u32; 16] {
+    let mut padded = [0; BLOCK_LENGTH];
+    padded[..block.len()].copy_from_slice(block);
+
+    let mut words = [0; 16];
+
+    for (word, bytes) in words.iter_mut().zip(padded.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+
+    words
+}
+
+fn first_eight(words: [u32; 16]) -> [u32; 8] {
+    let mut first = [0; 8];
+    first.copy_from_slice(&words[..8]);
+    first
+}
+
+/// The last block of a chunk or a parent, which isn't compressed until we
+/// know whether it's the root of the whole tree.
+struct Output {
+    chaining_value: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_length: u32,
+    flags: u32,
+}
+
+impl Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        first_eight(compress(
+            &self.chaining_value,
+            &self.block,
+            self.counter,
+            self.block_length,
+            self.flags,
+        ))
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        let words = compress(
+            &self.chaining_value,
+            &self.block,
+            0,
+            self.block_length,
+            self.flags | ROOT,
+        );
+
+        let mut hash = [0; 32];
+
+        for (bytes, word) in hash.chunks_exact_mut(4).zip(words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+
+        hash
+    }
+}
+
+/// Joins the chaining values of two neighbouring parts of the tree.
+fn parent_output(left: [u32; 8], right: [u32; 8]) -> Output {
+    let mut block = [0; 16];
+    block[..8].copy_from_slice(&left);
+    block[8..].copy_from_slice(&right);
+
+    Output {
+        chaining_value: BLAKE3_IV,
+        block,
+        counter: 0,
+        block_length: BLOCK_LENGTH as u32,
+        flags: PARENT,
+    }
+}
+
+/// A minimal implementation of the BLAKE3 hash function, following the
+/// reference implementation in the
+/// [specification](https://github.com/BLAKE3-team/BLAKE3-specs).
+///
+/// BLAKE3 splits its input into 1 KiB chunks and hashes each one on its own,
+/// then joins them up in pairs, like the branches of a tree, until there's a
+/// single hash left. We hash the chunks one after another, but the tree is
+/// what would let a faster implementation hash them all at once.
+#[derive(Debug, Clone)]
+pub struct Blake3 {
+    /// The chaining value of the chunk we're in the middle of.
+    chaining_value: [u32; 8],
+    /// How many whole chunks came before this one.
+    chunk_counter: u64,
+    /// Data in the current chunk that hasn't been compressed yet. The last
+    /// block of a chunk is always kept back, since it's compressed
+    /// differently.
+    block: Vec<u8>,
+    /// How many blocks of the current chunk have been compressed.
+    blocks_compressed: usize,
+    /// The chaining values of the parts of the tree that are waiting for a
+    /// neighbour to be joined with.
+    stack: Vec<[u32; 8]>,
+}
+
+impl Default for Blake3 {
+    fn default() -> Self {
+        Self {
+            chaining_value: BLAKE3_IV,
+            chunk_counter: 0,
+            block: Vec::with_capacity(BLOCK_LENGTH),
+            blocks_compressed: 0,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl Blake3 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds more data into the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // A full chunk is only finished once we know more data follows,
+            // since the very last one is finished differently.
+            if self.chunk_length() == CHUNK_LENGTH {
+                let chaining_value = self.chunk_output().chaining_value();
+                self.chunk_counter += 1;
+                self.push_chunk(chaining_value);
+
+                self.chaining_value = BLAKE3_IV;
+                self.blocks_compressed = 0;
+                self.block.clear();
+            }
+
+            // Likewise, a full block is only compressed once more follows.
+            if self.block.len() == BLOCK_LENGTH {
+                let flags = self.start_flag();
+
+                self.chaining_value = first_eight(compress(
+                    &self.chaining_value,
+                    &block_words(&self.block),
+                    self.chunk_counter,
+                    BLOCK_LENGTH as u32,
+                    flags,
+                ));
+
+                self.blocks_compressed += 1;
+                self.block.clear();
+            }
+
+            let wanted = (BLOCK_LENGTH - self.block.len())
+                .min(CHUNK_LENGTH - self.chunk_length())
+                .min(data.len());
+
+            self.block.extend_from_slice(&data[..wanted]);
+            data = &data[wanted..];
+        }
+    }
+
+    /// Finishes the hash, returning the 32-byte digest.
+    pub fn finalize(self) -> [u8; 32] {
+        let mut output = self.chunk_output();
+
+        for chaining_value in self.stack.iter().rev() {
+            output = parent_output(*chaining_value, output.chaining_value());
+        }
+
+        output.root_hash()
+    }
+
+    fn chunk_length(&self) -> usize {
+        self.blocks_compressed * BLOCK_LENGTH + self.block.len()
+    }
+
+    fn start_flag(&self) -> u32 {
+        match self.blocks_compressed {
+            0 => CHUNK_START,
+            _ => 0,
+        }
+    }
+
+    fn chunk_output(&self) -> Output {
+        Output {
+            chaining_value: self.chaining_value,
+            block: block_words(&self.block),
+            counter: self.chunk_counter,
+            block_length: self.block.len() as u32,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+
+    /// Adds the chaining value of a finished chunk to the tree, joining it
+    /// with its neighbours for as long as that makes a complete subtree.
+    /// `chunk_counter` already counts it.
+    fn push_chunk(&mut self, mut chaining_value: [u32; 8]) {
+        let mut chunks = self.chunk_counter;
+
+        // Each 0 bit at the bottom of the count is a subtree that's now
+        // complete.
+        while chunks & 1 == 0 {
+            let Some(left) = self.stack.pop() else {
+                break;
+            };
+
+            chaining_value = parent_output(left, chaining_value).chaining_value();
+            chunks >>= 1;
+        }
+
+        self.stack.push(chaining_value);
+    }
+}
+
+/// Lets data be fed into the hash by anything that writes, like `io::copy`.
+impl io::Write for Blake3 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The input the official BLAKE3 test vectors hash: the bytes 0 to 250,
+    /// over and over.
+    fn input(length: usize) -> Vec<u8> {
+        (0..length).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn blake3_matches_the_test_vectors() {
+        let vectors = [
+            (
+                0,
+                "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+            ),
+            (
+                1,
+                "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213",
+            ),
+            (
+                1024,
+                "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7",
+            ),
+            (
+                1025,
+                "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444",
+            ),
+            (
+                2049,
+                "5f4d72f40d7a5f82b15ca2b2e44b1de3c2ef86c426c95c1af0b6879522563030",
+            ),
+            (
+                3072,
+                "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2",
+            ),
+            (
+                5000,
+                "ee78d92070de3df1c57c37002abf0a6b1a6589acdeef4d8ffac7cf3d9e8f2836",
+            ),
+        ];
+
+        for (length, expected) in vectors {
+            assert_eq!(
+                HashAlgorithm::Blake3.hash(&input(length)),
+                expected,
+                "{length} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn blake3_gives_the_same_hash_however_the_data_is_split() {
+        let data = input(5000);
+        let mut hasher = Blake3::new();
+
+        for piece in data.chunks(333) {
+            hasher.update(piece);
+        }
+
+        assert_eq!(
+            utils::to_hex(&hasher.finalize()),
+            HashAlgorithm::Blake3.hash(&data)
+        );
+    }
+
+    #[test]
+    fn algorithms_parse_from_their_names() {
+        for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+            assert_eq!(algorithm.to_string().parse(), Ok(algorithm));
+        }
+
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+}
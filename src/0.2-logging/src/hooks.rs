@@ -0,0 +1,128 @@
+//! Running the user's own scripts at certain points, called hooks.
+//!
+//! Any executable file in `.rat/hooks` named after one of the points below is
+//! run when rat gets there. The hooks that run before something happens can
+//! stop it by exiting with an error, which is handy for things like refusing
+//! to commit code that doesn't pass its tests. The hooks are the same as
+//! git's:
+//!
+//! - `pre-commit` runs before a commit is made, before its message has even
+//!   been written, so that nobody writes one for a commit that won't be made.
+//! - `commit-msg` runs once the message has been written, and gets the path
+//!   of the file holding it, which it can change.
+//! - `post-commit` runs after a commit has been made. It can't stop anything,
+//!   so its exit status is ignored.
+//! - `pre-push` runs before a push, and gets the name and URL of the remote.
+//!   Each ref being pushed is given to it on standard input as a line like
+//!   `<local ref> <local hash> <remote ref> <remote hash>`.
+//!
+//! The commit hooks run for every commit, whether it's made by `rat commit` or
+//! by merging, cherry-picking, reverting or rebasing. `--no-verify` skips
+//! `pre-commit` and `commit-msg`, but never `post-commit`.
+//!
+//! Hooks run in the working directory, and anything they print ends up in
+//! standard error, so it doesn't get mixed up with rat's own output.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::compare::FileMode;
+use crate::error::HookError;
+use crate::worktree;
+
+/// Finds the hook called `name`, or `None` if there isn't one we can run.
+fn find(name: &str) -> Option<PathBuf> {
+    let path = crate::nest_path(&format!("hooks/{name}"));
+    let metadata = fs::metadata(&path).ok()?;
+
+    // Like git, we skip hooks that aren't executable, which makes for an easy
+    // way to turn one off without deleting it. Other platforms have no way to
+    // say, so every hook counts.
+    let executable = !cfg!(unix) || worktree::mode_of(&metadata) == FileMode::Executable;
+
+    (metadata.is_file() && executable).then_some(path)
+}
+
+/// Runs the hook called `name`, if there is one, with `arguments` and with
+/// `input` on its standard input. Fails if the hook exits with an error,
+/// which is how it says that whatever was about to happen shouldn't.
+pub fn run(name: &str, arguments: &[&str], input: &str) -> Result<(), HookError> {
+    let Some(path) = find(name) else {
+        return Ok(());
+    };
+
+    let io_error = |source| HookError::Io {
+        hook: name.to_string(),
+        source,
+    };
+
+    let mut child = Command::new(&path)
+        .args(arguments)
+        .stdin(Stdio::piped())
+        .stdout(io::stderr())
+        .spawn()
+        .map_err(io_error)?;
+
+    // A hook doesn't have to read its input, and if it exits without reading
+    // it, writing gets a broken pipe, which isn't a problem at all.
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(input.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(io_error(e))?,
+            _ => {}
+        }
+    }
+
+    let status = child.wait().map_err(io_error)?;
+
+    if !status.success() {
+        Err(HookError::Rejected {
+            hook: name.to_string(),
+            code: status.code(),
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Whether `pre-commit` and `commit-msg` are skipped, for `--no-verify`.
+static SKIP_COMMIT_CHECKS: AtomicBool = AtomicBool::new(false);
+
+/// Skips the hooks that can stop a commit for the rest of the program.
+pub fn skip_commit_checks() {
+    SKIP_COMMIT_CHECKS.store(true, Ordering::Relaxed);
+}
+
+/// Runs the `pre-commit` hook, which can stop a commit from being made before
+/// its message has been written.
+pub fn pre_commit() -> Result<(), HookError> {
+    if SKIP_COMMIT_CHECKS.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    run("pre-commit", &[], "")
+}
+
+/// Runs the `commit-msg` hook, which can stop a commit with `message` from
+/// being made, returning the message as the hook left it.
+pub fn check_message(message: &str) -> Result<String, HookError> {
+    if SKIP_COMMIT_CHECKS.load(Ordering::Relaxed) || find("commit-msg").is_none() {
+        return Ok(message.to_string());
+    }
+
+    // The hook can change the message, so it's handed the message in a file
+    // rather than as an argument.
+    let io_error = |source| HookError::Io {
+        hook: "commit-msg".to_string(),
+        source,
+    };
+
+    let message_file = crate::nest_path("COMMIT_EDITMSG");
+
+    fs::write(&message_file, message).map_err(io_error)?;
+    run("commit-msg", &[&message_file.to_string_lossy()], "")?;
+
+    fs::read_to_string(&message_file).map_err(io_error)
+}
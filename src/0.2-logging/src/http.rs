@@ -0,0 +1,211 @@
+//! Just enough HTTP to fetch files from a web server.
+//!
+//! Rat talks to other nests over HTTP using nothing but plain GET requests for
+//! individual files, which is what git calls the "dumb" protocol. That means
+//! we don't need a full HTTP client, and writing the few lines it takes
+//! ourselves saves us a dependency. We speak HTTP/1.0 and ask the server to
+//! close the connection when it's done, so the response simply ends when the
+//! connection does, and we don't have to deal with chunked responses or
+//! keeping connections alive.
+//!
+//! The other side is just as simple: a server that answers GET requests for
+//! files, one connection at a time per thread, and closes each connection
+//! after answering it.
+//!
+//! There's no support for HTTPS, since that would mean implementing TLS.
+
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use crate::error::TransportError;
+use crate::logging;
+
+/// The parts of an `http://` URL we need to make a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Url {
+    /// The host and port to connect to, like `example.com:80`.
+    address: String,
+    /// The host as it should be sent in the `Host` header.
+    host: String,
+    /// The path on the server, always starting with `/`.
+    path: String,
+}
+
+impl Url {
+    /// Parses a URL like `http://example.com:8080/thesis`.
+    fn parse(url: &str) -> Result<Self, TransportError> {
+        let invalid = |reason: &str| TransportError::InvalidUrl {
+            url: url.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("only http:// URLs work."))?;
+
+        let (host, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+
+        if host.is_empty() {
+            Err(invalid("it has no host."))?;
+        }
+
+        // The port is optional, and defaults to the standard one for HTTP.
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{host}:80")
+        };
+
+        Ok(Self {
+            address,
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Checks whether `url` looks like something we'd fetch over HTTP rather than
+/// a path on the filesystem.
+pub fn is_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Downloads the file at `url`, returning `None` if the server says it
+/// doesn't exist.
+pub fn get(url: &str) -> Result<Option<Vec<u8>>, TransportError> {
+    let full_url = url;
+    let url = Url::parse(url)?;
+
+    let mut stream =
+        TcpStream::connect(&url.address).map_err(|source| TransportError::Connect {
+            host: url.host.clone(),
+            source,
+        })?;
+
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: rat\r\nConnection: close\r\n\r\n",
+        url.path, url.host
+    )?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let invalid = |reason: String| TransportError::InvalidResponse {
+        url: full_url.to_string(),
+        reason,
+    };
+
+    // The headers are separated from the body by an empty line.
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("it has no end to its headers.".to_string()))?;
+
+    let headers = String::from_utf8_lossy(&response[..header_end]);
+    let status_line = headers.lines().next().unwrap_or_default();
+
+    // The status line looks like "HTTP/1.0 200 OK", and all we care about is
+    // the number in the middle.
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| invalid(format!("its status line is {status_line}")))?;
+
+    match status {
+        "200" => Ok(Some(response[header_end + 4..].to_vec())),
+        "404" => Ok(None),
+        _ => Err(TransportError::RequestFailed {
+            url: full_url.to_string(),
+            status: status_line.to_string(),
+        }),
+    }
+}
+
+/// Works out the response to a GET request for the given path, returning
+/// `None` if there's nothing there.
+pub type Handler = fn(&str) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
+
+/// Starts listening for connections on `address`, which is a host and port
+/// like `127.0.0.1:8080`, ready to [`serve`] them.
+pub fn listen(address: &str) -> Result<TcpListener, TransportError> {
+    TcpListener::bind(address).map_err(|source| TransportError::Listen {
+        address: address.to_string(),
+        source,
+    })
+}
+
+/// Answers every request that comes in on `listener` with `handler`,
+/// forever. Each connection is handled on its own thread, so one slow client
+/// can't hold up everybody else.
+pub fn serve(listener: TcpListener, handler: Handler) {
+    for stream in listener.incoming() {
+        // A connection that fails before we've even accepted it isn't worth
+        // stopping the server for.
+        let Ok(stream) = stream else {
+            continue;
+        };
+
+        thread::spawn(move || {
+            if let Err(e) = respond(stream, handler) {
+                logging::warning(format_args!("Failed to answer a request: {e}"));
+            }
+        });
+    }
+}
+
+/// Reads a single request from `stream` and answers it.
+fn respond(mut stream: TcpStream, handler: Handler) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+
+    // The request line looks like "GET /info/refs HTTP/1.0".
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // We don't need any of the headers, but we have to read past them before
+    // answering, otherwise some clients get upset.
+    let mut header = String::new();
+
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or("/"),
+    );
+
+    // Anything after a question mark is a query string, which we ignore.
+    let path = target.split('?').next().unwrap_or_default();
+
+    let (status, body) = if method != "GET" {
+        (
+            "405 Method Not Allowed",
+            b"Only GET requests are supported.\n".to_vec(),
+        )
+    } else {
+        match handler(path) {
+            Ok(Some(body)) => ("200 OK", body),
+            Ok(None) => ("404 Not Found", b"Not found.\n".to_vec()),
+            Err(e) => ("500 Internal Server Error", format!("{e}\n").into_bytes()),
+        }
+    };
+
+    logging::verbose(format_args!("{method} {path} {status}"));
+
+    write!(
+        stream,
+        "HTTP/1.0 {status}\r\nContent-Length: {}\r\nContent-Type: application/octet-stream\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+
+    Ok(())
+}
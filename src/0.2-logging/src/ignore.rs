@@ -0,0 +1,557 @@
+//! Support for `.ratignore` files.
+//!
+//! Much like `.gitignore`, a `.ratignore` file contains one pattern per line,
+//! and any file or directory matching one of those patterns is left out when
+//! rat looks through the working directory for files. A `.ratignore` file can
+//! be placed in any directory, and its patterns only apply to the contents of
+//! that directory.
+//!
+//! The pattern syntax is a subset of git's:
+//!
+//! - Blank lines and lines starting with `#` are skipped.
+//! - `*` matches anything except `/`, `?` matches any single character except
+//!   `/`, and `[a-z]` or `[!a-z]` match a single character in (or not in) a
+//!   set.
+//! - `**` matches across directories, so `**/build` matches `build` anywhere
+//!   and `logs/**` matches everything inside `logs`.
+//! - A pattern starting with `!` re-includes anything a previous pattern
+//!   excluded. The last matching pattern always wins.
+//! - A pattern ending with `/` only matches directories.
+//! - A pattern containing a `/` anywhere except at the end is matched against
+//!   the whole path relative to the `.ratignore` file. Otherwise, it's matched
+//!   against just the name of each file or directory, at any depth.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::{index, logging, submodules};
+
+/// The name of the file that ignore patterns are read from.
+pub const IGNORE_FILE: &str = ".ratignore";
+
+/// A single pattern read from a `.ratignore` file.
+#[derive(Debug, Clone)]
+struct Rule {
+    /// The directory containing the `.ratignore` file this rule came from,
+    /// relative to the root of the nest. Empty for the root directory itself.
+    base: String,
+    pattern: Vec<char>,
+    negated: bool,
+    directory_only: bool,
+    /// Whether the pattern should be matched against the whole path rather
+    /// than just the final component.
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str, base: &str) -> Option<Self> {
+        let line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        let (directory_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        // A leading slash only serves to anchor the pattern, so once we know
+        // that we can drop it.
+        let (anchored, line) = match line.strip_prefix('/') {
+            Some(rest) => (true, rest),
+            None => (line.contains('/'), line),
+        };
+
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            base: base.to_string(),
+            pattern: line.chars().collect(),
+            negated,
+            directory_only,
+            anchored,
+        })
+    }
+
+    /// Checks whether this rule applies to `path`, which is relative to the
+    /// root of the nest.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+
+        // Rules only apply to things inside the directory they came from.
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path.strip_prefix(&format!("{}/", self.base)) {
+                Some(relative) => relative,
+                None => return false,
+            }
+        };
+
+        let target = if self.anchored {
+            relative
+        } else {
+            // rsplit always produces at least one item, so this can't fail.
+            relative.rsplit('/').next().unwrap_or(relative)
+        };
+
+        glob_match(&self.pattern, &target.chars().collect::<Vec<_>>())
+    }
+}
+
+/// A set of ignore rules, collected from any number of `.ratignore` files.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// Reads the `.ratignore` file inside `dir`, if there is one, and adds its
+    /// rules to the set. `dir` is relative to the root of the nest.
+    fn load(&mut self, dir: &str) -> Result<(), io::Error> {
+        let ignore_file = if dir.is_empty() {
+            IGNORE_FILE.to_string()
+        } else {
+            format!("{dir}/{IGNORE_FILE}")
+        };
+
+        let contents = match fs::read_to_string(ignore_file) {
+            Ok(contents) => contents,
+            // Not having an ignore file is perfectly normal.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        self.rules
+            .extend(contents.lines().filter_map(|line| Rule::parse(line, dir)));
+
+        Ok(())
+    }
+
+    /// Builds a set of rules from `patterns`, each written just like a line of
+    /// a `.ratignore` file at the root of the nest. This is how settings that
+    /// pick out files by name, like `lfs.paths`, get the same syntax.
+    pub fn from_patterns<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            rules: patterns
+                .into_iter()
+                .filter_map(|pattern| Rule::parse(pattern, ""))
+                .collect(),
+        }
+    }
+
+    /// Checks whether the file at `path`, relative to the root of the nest,
+    /// matches the rules.
+    pub fn matches_file(&self, path: &str) -> bool {
+        self.matches(path, false)
+    }
+
+    /// Checks whether `path` itself matches the rules. Note that this doesn't
+    /// take into account whether one of the parent directories of `path` is
+    /// ignored, see [`is_ignored`] for that.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        // Since later rules override earlier ones, we look for the last rule
+        // that matches and let it decide.
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+/// Checks whether `path`, relative to the root of the nest, is ignored. This
+/// is the case if it or any of its parent directories match the rules in any
+/// of the applicable `.ratignore` files.
+pub fn is_ignored(path: &str) -> Result<bool, io::Error> {
+    if path == crate::RAT_NEST {
+        return Ok(true);
+    }
+
+    let mut rules = IgnoreRules::default();
+    rules.load("")?;
+
+    let components: Vec<&str> = path.split('/').collect();
+
+    for depth in 1..=components.len() {
+        let prefix = components[..depth].join("/");
+        let is_dir = depth < components.len() || Path::new(&prefix).is_dir();
+
+        if rules.matches(&prefix, is_dir) {
+            return Ok(true);
+        }
+
+        if is_dir {
+            rules.load(&prefix)?;
+        }
+    }
+
+    Ok(false)
+}
+
+/// Recursively lists every file inside `dir`, which is relative to the root of
+/// the nest, skipping the nest itself and anything ignored by a `.ratignore`
+/// file. The returned paths are relative to the root of the nest and always use
+/// `/` as the separator.
+pub fn list_working_files(dir: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    // Rules from every directory above the one we start in still apply, so we
+    // have to load those first.
+    let mut rules = IgnoreRules::default();
+    rules.load("")?;
+
+    if !dir.is_empty() {
+        let components: Vec<&str> = dir.split('/').collect();
+
+        for depth in 1..components.len() {
+            rules.load(&components[..depth].join("/"))?;
+        }
+    }
+
+    let mut files = Vec::new();
+    list_working_files_into(dir, &rules, &mut files)?;
+
+    files.sort();
+
+    Ok(files)
+}
+
+fn list_working_files_into(
+    dir: &str,
+    parent_rules: &IgnoreRules,
+    files: &mut Vec<String>,
+) -> Result<(), io::Error> {
+    // Each directory can add its own rules, which only apply within it, so we
+    // extend a copy of the rules rather than the ones we were given.
+    let mut rules = parent_rules.clone();
+    rules.load(dir)?;
+
+    let read_path = if dir.is_empty() { "." } else { dir };
+
+    for dir_entry in fs::read_dir(read_path)? {
+        let dir_entry = dir_entry?;
+        let entry_name = dir_entry.file_name().to_string_lossy().into_owned();
+
+        let path = if dir.is_empty() {
+            entry_name
+        } else {
+            format!("{dir}/{entry_name}")
+        };
+
+        if path == crate::RAT_NEST {
+            continue;
+        }
+
+        // The index holds one file per line, so there's no way to track a
+        // file with a line break in its name, or anything inside a directory
+        // with one.
+        if !index::is_valid_path(&path) {
+            logging::warning(format_args!(
+                "Skipping {path:?}, since rat can't track a path with a line break in it."
+            ));
+            continue;
+        }
+
+        let is_dir = dir_entry.file_type()?.is_dir();
+
+        // Once a directory is ignored we never look inside it, which means
+        // a negated pattern can't re-include a file inside an ignored
+        // directory. Git works the same way.
+        if rules.matches(&path, is_dir) {
+            continue;
+        }
+
+        // Another nest inside this one counts as a single entry, pointing at
+        // the commit it's on, unless it doesn't have any commits to point at.
+        if is_dir && submodules::is_nest(&path) {
+            if submodules::head(&path)?.is_some() {
+                files.push(path);
+            }
+        } else if is_dir {
+            list_working_files_into(&path, &rules, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Recursively lists every file in the working directory that's ignored by a
+/// `.ratignore` file, including everything inside ignored directories. This
+/// is the other half of what [`list_working_files`] leaves out, apart from the
+/// nest itself. Some of these might be tracked anyway, if they were added
+/// before they were ignored.
+pub fn list_ignored_files() -> Result<Vec<String>, io::Error> {
+    let mut files = Vec::new();
+    list_ignored_files_into("", &IgnoreRules::default(), false, &mut files)?;
+
+    files.sort();
+
+    Ok(files)
+}
+
+fn list_ignored_files_into(
+    dir: &str,
+    parent_rules: &IgnoreRules,
+    ignored: bool,
+    files: &mut Vec<String>,
+) -> Result<(), io::Error> {
+    let mut rules = parent_rules.clone();
+    rules.load(dir)?;
+
+    let read_path = if dir.is_empty() { "." } else { dir };
+
+    for dir_entry in fs::read_dir(read_path)? {
+        let dir_entry = dir_entry?;
+        let entry_name = dir_entry.file_name().to_string_lossy().into_owned();
+
+        let path = if dir.is_empty() {
+            entry_name
+        } else {
+            format!("{dir}/{entry_name}")
+        };
+
+        if path == crate::RAT_NEST {
+            continue;
+        }
+
+        let is_dir = dir_entry.file_type()?.is_dir();
+
+        // Everything inside an ignored directory is ignored too, whatever the
+        // rules say about it on its own.
+        let ignored = ignored || rules.matches(&path, is_dir);
+
+        if is_dir && submodules::is_nest(&path) {
+            continue;
+        } else if is_dir {
+            list_ignored_files_into(&path, &rules, ignored, files)?;
+        } else if ignored {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists everything in the working directory that isn't in `tracked`, for
+/// `rat clean`. Ignored files are left out unless `include_ignored` is set.
+///
+/// Directories that have nothing tracked inside are left out too, unless
+/// `directories` is set. Then they're listed as a whole, with a `/` on the end,
+/// as long as everything inside them would be listed. Otherwise, we list the
+/// files inside that would be instead, just like git.
+pub fn list_untracked(
+    tracked: &BTreeSet<String>,
+    directories: bool,
+    include_ignored: bool,
+) -> Result<Vec<String>, io::Error> {
+    // Every directory with something tracked inside, which we always have to
+    // look through rather than treating as a whole.
+    let tracked_dirs: BTreeSet<&str> = tracked
+        .iter()
+        .flat_map(|path| path.match_indices('/').map(|(i, _)| &path[..i]))
+        .collect();
+
+    let mut untracked = Vec::new();
+    let options = UntrackedOptions {
+        tracked,
+        tracked_dirs: &tracked_dirs,
+        directories,
+        include_ignored,
+    };
+
+    list_untracked_into("", &IgnoreRules::default(), &options, &mut untracked)?;
+
+    untracked.sort();
+
+    Ok(untracked)
+}
+
+/// Everything [`list_untracked_into`] needs to know that stays the same as it
+/// looks through each directory.
+struct UntrackedOptions<'a> {
+    tracked: &'a BTreeSet<String>,
+    tracked_dirs: &'a BTreeSet<&'a str>,
+    directories: bool,
+    include_ignored: bool,
+}
+
+/// Adds everything untracked inside `dir` to `untracked`, returning whether
+/// that was everything inside it.
+fn list_untracked_into(
+    dir: &str,
+    parent_rules: &IgnoreRules,
+    options: &UntrackedOptions,
+    untracked: &mut Vec<String>,
+) -> Result<bool, io::Error> {
+    let mut rules = parent_rules.clone();
+    rules.load(dir)?;
+
+    let read_path = if dir.is_empty() { "." } else { dir };
+    let mut everything = true;
+
+    for dir_entry in fs::read_dir(read_path)? {
+        let dir_entry = dir_entry?;
+        let entry_name = dir_entry.file_name().to_string_lossy().into_owned();
+
+        let path = if dir.is_empty() {
+            entry_name
+        } else {
+            format!("{dir}/{entry_name}")
+        };
+
+        if path == crate::RAT_NEST || options.tracked.contains(&path) {
+            everything = false;
+            continue;
+        }
+
+        let is_dir = dir_entry.file_type()?.is_dir();
+
+        // Another nest has history of its own, which cleaning up after this
+        // one should never throw away.
+        if is_dir && submodules::is_nest(&path) {
+            everything = false;
+            continue;
+        }
+
+        // Anything tracked has to be kept, even inside an ignored directory,
+        // so we have to look through those.
+        if is_dir && options.tracked_dirs.contains(path.as_str()) {
+            list_untracked_into(&path, &rules, options, untracked)?;
+            everything = false;
+            continue;
+        }
+
+        if rules.matches(&path, is_dir) && !options.include_ignored {
+            everything = false;
+            continue;
+        }
+
+        if !is_dir {
+            untracked.push(path);
+            continue;
+        }
+
+        if !options.directories {
+            everything = false;
+            continue;
+        }
+
+        // We only know whether the whole directory can go once we've looked
+        // at everything inside, so we collect that separately first.
+        let mut inside = Vec::new();
+
+        if list_untracked_into(&path, &rules, options, &mut inside)? {
+            untracked.push(format!("{path}/"));
+        } else {
+            untracked.extend(inside);
+            everything = false;
+        }
+    }
+
+    Ok(everything)
+}
+
+/// Matches `text` against the glob `pattern`, using the syntax described in
+/// the module documentation.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // "**/" can match zero or more whole directories, so we try the
+            // rest of the pattern at the start and after every slash.
+            if let ['/', rest @ ..] = rest {
+                return (0..=text.len())
+                    .filter(|&i| i == 0 || text[i - 1] == '/')
+                    .any(|i| glob_match(rest, &text[i..]));
+            }
+
+            // Otherwise "**" behaves like a "*" that can match slashes too.
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => {
+            // A single "*" can consume any number of characters, but never a
+            // slash, so we stop trying once we reach one.
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+
+            false
+        }
+        ['?', rest @ ..] => match text {
+            [c, text_rest @ ..] if *c != '/' => glob_match(rest, text_rest),
+            _ => false,
+        },
+        ['[', rest @ ..] => {
+            let Some((c, text_rest)) = text.split_first() else {
+                return false;
+            };
+
+            match match_class(rest, *c) {
+                Some((true, pattern_rest)) => glob_match(pattern_rest, text_rest),
+                Some((false, _)) => false,
+                // An unterminated bracket is treated as a literal "[".
+                None => *c == '[' && glob_match(rest, text_rest),
+            }
+        }
+        ['\\', escaped, rest @ ..] => match text {
+            [c, text_rest @ ..] if c == escaped => glob_match(rest, text_rest),
+            _ => false,
+        },
+        [literal, rest @ ..] => match text {
+            [c, text_rest @ ..] if c == literal => glob_match(rest, text_rest),
+            _ => false,
+        },
+    }
+}
+
+/// Matches `c` against a character class like `[a-z]`, where `pattern` starts
+/// just after the opening bracket. Returns whether it matched along with the
+/// rest of the pattern after the closing bracket, or `None` if there isn't a
+/// closing bracket.
+fn match_class(pattern: &[char], c: char) -> Option<(bool, &[char])> {
+    let (negated, mut pattern) = match pattern {
+        ['!' | '^', rest @ ..] => (true, rest),
+        _ => (false, pattern),
+    };
+
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        match pattern {
+            [] => return None,
+            // A "]" right at the start is part of the set rather than the end.
+            [']', rest @ ..] if !first => return Some((matched != negated, rest)),
+            [start, '-', end, rest @ ..] if *end != ']' => {
+                matched |= (*start..=*end).contains(&c);
+                pattern = rest;
+            }
+            [single, rest @ ..] => {
+                matched |= *single == c;
+                pattern = rest;
+            }
+        }
+
+        first = false;
+    }
+}
@@ -0,0 +1,272 @@
+//! The index, otherwise known as the staging area.
+//!
+//! Instead of snapshotting whatever happens to be in the working directory,
+//! `commit` builds its snapshot out of the index, which is a complete
+//! description of what the *next* commit is going to look like. `rat add`
+//! stores the current contents of a file in the object store and records the
+//! hash of that blob in the index, so you can carry on editing the file
+//! without affecting what gets committed.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::compare::{Entry, FileMode, Snapshot};
+use crate::utils;
+
+/// An in-memory representation of the `.rat/index` file.
+///
+/// Entries are keyed by their path relative to the root of the nest, always
+/// using `/` as the separator, and map to the blob that will be committed.
+/// We use a `BTreeMap` so that iterating over the entries always produces
+/// them in the same sorted order, which keeps the file on disk stable.
+#[derive(Debug, Default)]
+pub struct Index {
+    pub entries: Snapshot,
+}
+
+impl Index {
+    /// Reads and parses the index file at `path`.
+    ///
+    /// The format is deliberately simple: each line contains the hash of a
+    /// blob and the path it should be committed at, separated by a space.
+    /// Anything other than an ordinary file has its mode in front, too.
+    ///
+    /// ```text
+    /// 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08 notes/todo.txt
+    /// symlink 2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae latest
+    /// ```
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let mut entries = BTreeMap::new();
+
+        for line in fs::read_to_string(path)?.lines() {
+            let corrupt = || format!("Corrupt index: invalid entry {line}.");
+            let (first, rest) = line.split_once(' ').ok_or_else(corrupt)?;
+
+            // A hash is never a valid mode, so there's no mistaking the two.
+            let (mode, hash, entry_path) = match first.parse() {
+                Ok(mode) => {
+                    let (hash, entry_path) = rest.split_once(' ').ok_or_else(corrupt)?;
+
+                    (mode, hash, entry_path)
+                }
+                Err(_) => (FileMode::Regular, first, rest),
+            };
+
+            entries.insert(
+                entry_path.to_string(),
+                Entry {
+                    mode,
+                    hash: hash.to_string(),
+                },
+            );
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Serializes the index into the format described in [`Index::read`] and
+    /// writes it to `path`.
+    ///
+    /// A path with a line break in it would read back as two broken entries,
+    /// leaving the whole index unreadable, so we refuse to write one at all.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        if let Some(entry_path) = self.entries.keys().find(|path| !is_valid_path(path)) {
+            Err(format!(
+                "Can't stage {entry_path:?}, since rat can't track a path with a line break in it."
+            ))?;
+        }
+
+        let data = self
+            .entries
+            .iter()
+            .map(|(entry_path, entry)| match entry.mode {
+                FileMode::Regular => format!("{} {entry_path}\n", entry.hash),
+                mode => format!("{mode} {} {entry_path}\n", entry.hash),
+            })
+            .collect::<String>();
+
+        utils::write_atomically(path, data)?;
+
+        Ok(())
+    }
+
+    /// Stages `entry` for the file at `entry_path`, replacing anything
+    /// previously staged there.
+    pub fn stage(&mut self, entry_path: String, entry: Entry) {
+        self.entries.insert(entry_path, entry);
+    }
+
+    /// Removes every entry that is either `entry_path` itself or lies inside
+    /// the directory `entry_path`, returning how many entries were removed.
+    pub fn unstage(&mut self, entry_path: &str) -> usize {
+        let before = self.entries.len();
+        let directory_prefix = format!("{entry_path}/");
+
+        self.entries
+            .retain(|path, _| path != entry_path && !path.starts_with(&directory_prefix));
+
+        before - self.entries.len()
+    }
+}
+
+/// Checks whether `path` can be staged, which it can't if it has a line break
+/// in it, since each entry in the index is a single line.
+pub fn is_valid_path(path: &str) -> bool {
+    !path.contains('\n')
+}
+
+/// Reads the renames `rat mv` has staged since the last commit from the file
+/// at `path`, as a map from each new path to the path it used to have.
+///
+/// Snapshots only say which files exist, not where they came from, so these
+/// are kept alongside the index as hints for telling renames apart from a
+/// deletion and an unrelated new file. Each line holds the old path and the
+/// new one, separated by a tab.
+pub fn read_renames(path: impl AsRef<Path>) -> io::Result<BTreeMap<String, String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(old, new)| (new.to_string(), old.to_string()))
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes the renames in `renames`, in the format described in
+/// [`read_renames`], to the file at `path`, removing it if there aren't any.
+pub fn write_renames(path: impl AsRef<Path>, renames: &BTreeMap<String, String>) -> io::Result<()> {
+    if renames.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+
+    let contents = renames
+        .iter()
+        .map(|(new, old)| format!("{old}\t{new}\n"))
+        .collect::<String>();
+
+    utils::write_atomically(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::*;
+
+    /// A path in the temporary directory that no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rat-test-{}-index-{name}", process::id()))
+    }
+
+    fn entry(mode: FileMode, hash: &str) -> Entry {
+        Entry {
+            mode,
+            hash: hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_the_documented_format() {
+        let path = temp_path("format");
+        let hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        fs::write(
+            &path,
+            format!("{hash} notes/my todo.txt\nsymlink {hash} latest\n"),
+        )
+        .unwrap();
+
+        let index = Index::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(index.entries.len(), 2);
+        assert_eq!(
+            index.entries["notes/my todo.txt"],
+            entry(FileMode::Regular, hash)
+        );
+        assert_eq!(index.entries["latest"], entry(FileMode::Symlink, hash));
+    }
+
+    #[test]
+    fn round_trips_every_mode() {
+        let path = temp_path("round-trip");
+        let mut index = Index::default();
+
+        for (i, mode) in [
+            FileMode::Regular,
+            FileMode::Executable,
+            FileMode::Symlink,
+            FileMode::Nest,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            index.stage(format!("dir/file {i}"), entry(mode, &format!("{i:064x}")));
+        }
+
+        index.write(&path).unwrap();
+        let read = Index::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.entries, index.entries);
+    }
+
+    #[test]
+    fn rejects_a_line_without_a_path() {
+        let path = temp_path("corrupt");
+        fs::write(&path, "9f86d081884c7d659a2feaa0c55ad015\n").unwrap();
+
+        let result = Index::read(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn refuses_to_write_a_path_with_a_line_break() {
+        let path = temp_path("line-break");
+        let mut index = Index::default();
+        index.stage("a\nb".to_string(), entry(FileMode::Regular, "00"));
+
+        assert!(index.write(&path).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unstaging_a_directory_unstages_everything_in_it() {
+        let mut index = Index::default();
+
+        for path in ["src", "src/main.rs", "src/lib/mod.rs", "srcs/other.rs"] {
+            index.stage(path.to_string(), entry(FileMode::Regular, "00"));
+        }
+
+        assert_eq!(index.unstage("src"), 3);
+        assert_eq!(index.entries.keys().collect::<Vec<_>>(), ["srcs/other.rs"]);
+    }
+
+    #[test]
+    fn renames_round_trip() {
+        let path = temp_path("renames");
+        let renames = BTreeMap::from([
+            ("new.txt".to_string(), "old.txt".to_string()),
+            ("b/c d".to_string(), "a/c d".to_string()),
+        ]);
+
+        write_renames(&path, &renames).unwrap();
+        assert_eq!(read_renames(&path).unwrap(), renames);
+
+        // No renames at all means no file, which reads back as none.
+        write_renames(&path, &BTreeMap::new()).unwrap();
+        assert!(!path.exists());
+        assert!(read_renames(&path).unwrap().is_empty());
+    }
+}
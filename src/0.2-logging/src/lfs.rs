@@ -0,0 +1,287 @@
+//! Keeping huge files out of the nest, like Git LFS does.
+//!
+//! Everything in the history gets copied by every clone, forever, which is a
+//! problem for things like videos and datasets that nobody needs every
+//! version of. Files picked out by the `lfs.paths` and `lfs.threshold`
+//! settings are kept in a separate store instead, and what gets committed is
+//! a small pointer file that names the real contents by their hash:
+//!
+//! ```text
+//! version rat-lfs/1
+//! sha256 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//! size 1073741824
+//! ```
+//!
+//! The store is `.rat/lfs/objects` unless `lfs.storage` points somewhere else,
+//! like a directory that several nests share, laid out just like the object
+//! store. Checking out a pointer puts the real contents in the working
+//! directory if the store has them, and the pointer itself if it doesn't, so
+//! the rest of the nest still works without them.
+//!
+//! Cloning, fetching, pushing and bundling copy the contents of every
+//! pointer in the history they copy, as long as the other side has them, so
+//! each nest's store has what its history needs.
+//!
+//! The settings are:
+//!
+//! - `lfs.paths`, a list of patterns separated by spaces, written like the
+//!   lines of a `.ratignore` file, such as `*.psd assets/**`.
+//! - `lfs.threshold`, a size like `50m`. Files at least that big are kept
+//!   out of the nest wherever they are.
+//! - `lfs.storage`, the directory to keep the contents in.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use crate::config::{Config, ConfigFile};
+use crate::ignore::IgnoreRules;
+use crate::utils::{self, Sha256};
+
+/// The first line of every pointer file.
+const VERSION_LINE: &str = "version rat-lfs/1";
+
+/// Pointer files are tiny, so anything bigger than this can't be one, and we
+/// don't need to read it to find out.
+const MAX_POINTER_SIZE: u64 = 1024;
+
+/// Which files are kept out of the nest, and where their contents go instead.
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    paths: IgnoreRules,
+    threshold: Option<u64>,
+    storage: PathBuf,
+    /// Whether anything at all is kept out of the nest.
+    enabled: bool,
+}
+
+fn settings() -> &'static Settings {
+    // Every file we hash needs to know, so we only read the config once.
+    static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+    SETTINGS.get_or_init(|| {
+        let config = Config::load().ok();
+        let get = |key: &str| config.as_ref().and_then(|config| config.get(key));
+
+        let paths = get("lfs.paths").unwrap_or_default();
+        let threshold = get("lfs.threshold").and_then(utils::parse_size);
+
+        Settings {
+            paths: IgnoreRules::from_patterns(paths.split_whitespace()),
+            threshold,
+            storage: get("lfs.storage")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| crate::nest_path("lfs/objects")),
+            enabled: !paths.trim().is_empty() || threshold.is_some(),
+        }
+    })
+}
+
+/// What gets committed in place of a file kept out of the nest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    /// The SHA-256 hash of the file's contents, just as they are.
+    pub hash: String,
+    pub size: u64,
+}
+
+impl Pointer {
+    /// Reads a pointer file, or returns `None` if `data` isn't one.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() as u64 > MAX_POINTER_SIZE {
+            return None;
+        }
+
+        let text = std::str::from_utf8(data).ok()?;
+        let mut lines = text.strip_suffix('\n')?.split('\n');
+
+        if lines.next()? != VERSION_LINE {
+            return None;
+        }
+
+        let hash = lines.next()?.strip_prefix("sha256 ")?;
+        let size = lines.next()?.strip_prefix("size ")?.parse().ok()?;
+
+        if lines.next().is_some()
+            || hash.len() != 64
+            || !hash.bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            return None;
+        }
+
+        Some(Self {
+            hash: hash.to_ascii_lowercase(),
+            size,
+        })
+    }
+
+    /// Writes the pointer file out.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        format!("{VERSION_LINE}\nsha256 {}\nsize {}\n", self.hash, self.size).into_bytes()
+    }
+
+    /// Finds where the store keeps the contents the pointer points to.
+    pub fn content_path(&self) -> PathBuf {
+        content_path_in(storage(), &self.hash)
+    }
+
+    /// Checks whether the store has the contents the pointer points to.
+    pub fn has_content(&self) -> bool {
+        self.content_path().is_file()
+    }
+}
+
+/// Finds the directory the contents of files kept out of the nest are stored
+/// in.
+pub fn storage() -> &'static Path {
+    &settings().storage
+}
+
+/// Finds the directory the nest at `nest` stores the contents of files kept
+/// out of it in, which might not be our own.
+pub fn storage_in(nest: &Path) -> PathBuf {
+    if nest == crate::nest_dir() {
+        return storage().to_path_buf();
+    }
+
+    ConfigFile::read(nest.join("config"))
+        .ok()
+        .and_then(|file| {
+            Config::from_files(vec![file])
+                .get("lfs.storage")
+                .map(PathBuf::from)
+        })
+        .unwrap_or_else(|| nest.join("lfs/objects"))
+}
+
+/// Finds where the store at `storage` keeps the contents with the SHA-256
+/// hash `hash`.
+pub fn content_path_in(storage: &Path, hash: &str) -> PathBuf {
+    let (directory, file) = hash.split_at(2.min(hash.len()));
+
+    storage.join(directory).join(file)
+}
+
+/// Checks whether the file at `path`, which is `size` bytes long, should be
+/// kept out of the nest.
+pub fn applies_to(path: &str, size: u64) -> bool {
+    let settings = settings();
+
+    settings.enabled
+        && (settings
+            .threshold
+            .is_some_and(|threshold| size >= threshold)
+            || settings.paths.matches_file(path))
+}
+
+/// Works out the pointer for the file at `path`, without storing anything.
+///
+/// A file that's already a pointer, which is what checking out a pointer
+/// leaves behind when the store doesn't have its contents, is its own pointer.
+pub fn pointer_for(path: &str) -> io::Result<Pointer> {
+    if let Some(pointer) = read_pointer(path)? {
+        return Ok(pointer);
+    }
+
+    let mut hasher = Sha256::new();
+    let size = copy_hashing(File::open(path)?, &mut hasher, &mut io::sink())?;
+
+    Ok(Pointer {
+        hash: utils::to_hex(&hasher.finalize()),
+        size,
+    })
+}
+
+/// Copies the contents of the file at `path` into the store, returning the
+/// pointer that should be committed in its place.
+pub fn store(path: &str) -> io::Result<Pointer> {
+    if let Some(pointer) = read_pointer(path)? {
+        return Ok(pointer);
+    }
+
+    store_in(storage(), File::open(path)?)
+}
+
+/// Copies everything `reader` reads into the store at `storage`, returning
+/// the pointer to it.
+pub fn store_in(storage: &Path, reader: impl Read) -> io::Result<Pointer> {
+    // Several files can be stored at once, so every temporary file needs a
+    // name of its own.
+    static NEXT_TEMPORARY: AtomicUsize = AtomicUsize::new(0);
+
+    fs::create_dir_all(storage)?;
+
+    // Just like a blob, we don't know where the contents go until we've read
+    // all of them.
+    let temporary_path = storage.join(format!(
+        "incoming-{}-{}",
+        process::id(),
+        NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let mut hasher = Sha256::new();
+
+    let size = File::create(&temporary_path)
+        .and_then(|mut file| {
+            let size = copy_hashing(reader, &mut hasher, &mut file)?;
+            file.sync_all()?;
+
+            Ok(size)
+        })
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temporary_path);
+        })?;
+
+    let pointer = Pointer {
+        hash: utils::to_hex(&hasher.finalize()),
+        size,
+    };
+
+    let content_path = content_path_in(storage, &pointer.hash);
+
+    if content_path.is_file() {
+        fs::remove_file(&temporary_path)?;
+    } else {
+        // The parent of a content path is always its subdirectory.
+        if let Some(parent) = content_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::rename(&temporary_path, &content_path)?;
+    }
+
+    Ok(pointer)
+}
+
+/// Reads the file at `path` as a pointer, if it's small enough to be one and
+/// is one.
+fn read_pointer(path: &str) -> io::Result<Option<Pointer>> {
+    if fs::metadata(path)?.len() > MAX_POINTER_SIZE {
+        return Ok(None);
+    }
+
+    Ok(Pointer::parse(&fs::read(path)?))
+}
+
+/// Copies everything `reader` reads to `out`, hashing it along the way, and
+/// returns how long it was.
+fn copy_hashing(reader: impl Read, hasher: &mut Sha256, out: &mut impl Write) -> io::Result<u64> {
+    let mut reader = BufReader::new(reader);
+    let mut buffer = vec![0; 64 * 1024];
+    let mut size = 0;
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+
+        if read == 0 {
+            return Ok(size);
+        }
+
+        hasher.update(&buffer[..read]);
+        out.write_all(&buffer[..read])?;
+        size += read as u64;
+    }
+}
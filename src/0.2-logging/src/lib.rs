@@ -0,0 +1,162 @@
+//! Rat is a tiny version control system in the spirit of git.
+//!
+//! This crate holds everything rat knows how to do, so that other programs
+//! can use it too. The `rat` command itself is a thin layer on top that turns
+//! the command line into calls to [`Repository`] and prints the results.
+
+pub mod archive;
+pub mod bisect;
+pub mod blame;
+pub mod bundle;
+pub mod cache;
+pub mod chunk;
+pub mod cli;
+pub mod commit_graph;
+pub mod compare;
+pub mod completion;
+pub mod compression;
+pub mod config;
+pub mod deflate;
+pub mod delta;
+pub mod diff;
+pub mod error;
+pub mod fast_export;
+pub mod format;
+pub mod git;
+pub mod git_import;
+pub mod graph;
+pub mod grep;
+pub mod hash;
+pub mod hooks;
+pub mod http;
+pub mod ignore;
+pub mod index;
+pub mod json;
+pub mod lfs;
+pub mod lock;
+pub mod logging;
+pub mod merge;
+pub mod metadata;
+pub mod notes;
+pub mod objects;
+pub mod pack;
+pub mod pager;
+pub mod patch;
+pub mod pretty;
+pub mod progress;
+pub mod refs;
+pub mod regex;
+pub mod remote;
+pub mod renames;
+pub mod repository;
+pub mod resolve;
+pub mod signing;
+pub mod state;
+pub mod stats;
+pub mod submodules;
+pub mod tools;
+pub mod transfer;
+pub mod transport;
+pub mod utils;
+pub mod worktree;
+pub mod worktrees;
+pub mod zstd;
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use config::{Config, ConfigFile};
+
+pub use repository::Repository;
+
+// Akin to the hidden .git directory, this is the directory where rat will store
+// the history of the nest. The real .git directory is a bit more complicated
+// than we're going to make it, but the concept is the same - everything that
+// git stores is nothing magical, it's all just files stored in a directory.
+pub const RAT_NEST: &str = ".rat";
+
+/// Where the nest is, if it's somewhere other than `.rat` in the current
+/// directory.
+static NEST_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// The directory of the linked working directory we're in's own files, if
+/// we're in one. See the [`worktrees`] module.
+static WORKTREE_NEST: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Points rat at the nest directory `dir` instead of `.rat` in the current
+/// directory, which stays the working directory either way.
+///
+/// This has to happen before anything looks at the nest, since the nest
+/// can't move once it's being used. Later calls are ignored.
+pub fn set_nest_dir(dir: impl Into<PathBuf>) {
+    let _ = NEST_DIR.set(dir.into());
+}
+
+/// Finds the nest directory, which is `.rat` unless [`set_nest_dir`] said
+/// otherwise, the current directory is a bare nest, or `.rat` is a file
+/// leading to the nest of a linked working directory.
+pub fn nest_dir() -> &'static Path {
+    NEST_DIR.get_or_init(|| {
+        let linked = Path::new(RAT_NEST)
+            .is_file()
+            .then(|| worktrees::read_link(Path::new(RAT_NEST)))
+            .flatten();
+
+        if let Some((own, shared)) = linked {
+            let _ = WORKTREE_NEST.set(Some(own));
+            shared
+        } else if !Path::new(RAT_NEST).is_dir() && looks_like_nest(Path::new(".")) {
+            PathBuf::from(".")
+        } else {
+            PathBuf::from(RAT_NEST)
+        }
+    })
+}
+
+/// Finds the directory holding the own files of the linked working directory
+/// we're in, like its `HEAD` and index, or `None` if we're in the main one.
+pub fn worktree_nest() -> Option<&'static Path> {
+    nest_dir();
+    WORKTREE_NEST.get_or_init(|| None).as_deref()
+}
+
+/// Finds the nest that belongs to the directory `dir`. That's usually `.rat`
+/// inside it, but a bare nest has no working directory to hide its contents
+/// from, so they're right there in `dir` instead.
+pub fn find_nest(dir: &Path) -> Option<PathBuf> {
+    let nest = dir.join(RAT_NEST);
+
+    if nest.is_dir() {
+        Some(nest)
+    } else if nest.is_file() {
+        worktrees::read_link(&nest).map(|(_, shared)| shared)
+    } else if looks_like_nest(dir) {
+        Some(dir.to_path_buf())
+    } else {
+        None
+    }
+}
+
+/// Checks whether the nest directory `nest` is bare, meaning it has no working
+/// directory at all. Bare nests are handy for sharing, since nobody works in
+/// them directly, so there's never a checked out branch in the way of a push.
+pub fn is_bare(nest: &Path) -> bool {
+    ConfigFile::read(nest.join("config"))
+        .map(|file| Config::from_files(vec![file]))
+        .is_ok_and(|config| config.get("core.bare") == Some("true"))
+}
+
+/// Checks whether `dir` has the contents of a nest in it directly.
+fn looks_like_nest(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir()
+}
+
+/// Finds the file `name` inside the nest, like `index` or `refs/heads/main`.
+/// In a linked working directory, files that belong to it alone are found
+/// among its own files instead.
+pub fn nest_path(name: &str) -> PathBuf {
+    match worktree_nest() {
+        Some(own) if worktrees::is_own_file(name) => utils::join_path(own, name),
+        _ => utils::join_path(nest_dir(), name),
+    }
+}
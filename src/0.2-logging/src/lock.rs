@@ -0,0 +1,161 @@
+//! Making sure only one rat process changes the nest at a time.
+//!
+//! Two commits running at once could each read HEAD, make a commit on top of
+//! it, and then write HEAD, leaving one of the commits lost. So before changing
+//! anything, a command takes the nest's lock, and anybody else gets told to
+//! wait their turn.
+//!
+//! The lock is the operating system's lock on a file called `LOCK` in the nest.
+//! Taking it either succeeds or fails in one step, so only one process can
+//! ever hold it, and the operating system gives it back when the process
+//! exits, however that happens. That matters, because a process that gets
+//! killed never gets the chance to give it back itself, and there's no safe
+//! way to tell that a lock has been left behind and take it over: two
+//! processes could both decide it had been, and both end up holding it.
+//!
+//! The file holds the ID of the process holding the lock, so we can say who it
+//! is.
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::error::LockError;
+
+/// The nest's lock, which is held until this is dropped.
+#[derive(Debug)]
+pub struct NestLock {
+    // The lock belongs to the open file, so it's given back when this closes.
+    _file: File,
+}
+
+impl NestLock {
+    /// Takes the lock, failing if another rat process is holding it.
+    pub fn acquire() -> Result<Self, LockError> {
+        Self::acquire_path(crate::nest_path("LOCK"))
+    }
+
+    /// Takes the lock of the nest directory `nest`, which doesn't have to be
+    /// ours, like a remote we're about to push to.
+    pub fn acquire_in(nest: &Path) -> Result<Self, LockError> {
+        Self::acquire_path(nest.join("LOCK"))
+    }
+
+    fn acquire_path(path: PathBuf) -> Result<Self, LockError> {
+        // The file is left behind once the lock is given back, since deleting
+        // it would let somebody who had just opened it lock a file nobody
+        // else can see any more. The next process to come along reuses it.
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|contents| contents.trim().parse().ok());
+
+                return Err(LockError::Held { pid, path });
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        // Whoever held the lock last left their ID behind, which isn't ours.
+        file.set_len(0)?;
+        writeln!(file, "{}", process::id())?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    use super::*;
+
+    /// An empty directory in the temporary directory that no other test uses,
+    /// to stand in for a nest.
+    fn temp_nest(name: &str) -> PathBuf {
+        let nest = env::temp_dir().join(format!("rat-test-{}-lock-{name}", process::id()));
+        let _ = fs::remove_dir_all(&nest);
+        fs::create_dir_all(&nest).unwrap();
+
+        nest
+    }
+
+    #[test]
+    fn only_one_holder_at_a_time() {
+        let nest = temp_nest("held");
+        let lock = NestLock::acquire_in(&nest).unwrap();
+
+        match NestLock::acquire_in(&nest) {
+            Err(LockError::Held { pid, .. }) => assert_eq!(pid, Some(process::id())),
+            other => panic!("{other:?}"),
+        }
+
+        drop(lock);
+        NestLock::acquire_in(&nest).unwrap();
+
+        fs::remove_dir_all(&nest).unwrap();
+    }
+
+    #[test]
+    fn takes_over_a_lock_left_behind() {
+        let nest = temp_nest("left-behind");
+        fs::write(nest.join("LOCK"), format!("{}\n", u32::MAX)).unwrap();
+
+        let lock = NestLock::acquire_in(&nest).unwrap();
+        assert_eq!(
+            fs::read_to_string(nest.join("LOCK")).unwrap(),
+            format!("{}\n", process::id())
+        );
+
+        drop(lock);
+        fs::remove_dir_all(&nest).unwrap();
+    }
+
+    #[test]
+    fn exactly_one_of_many_at_once_gets_it() {
+        let nest = temp_nest("race");
+
+        // Races only go wrong now and again, so we run plenty of them.
+        for _ in 0..200 {
+            fs::write(nest.join("LOCK"), format!("{}\n", u32::MAX)).unwrap();
+
+            // Everybody tries at the same moment, and whoever gets the lock
+            // holds on to it until everybody else has tried too.
+            let (tried, done) = (Arc::new(Barrier::new(8)), Arc::new(Barrier::new(8)));
+            let attempts: Vec<_> = (0..8)
+                .map(|_| {
+                    let (nest, tried, done) = (nest.clone(), tried.clone(), done.clone());
+
+                    thread::spawn(move || {
+                        tried.wait();
+                        let lock = NestLock::acquire_in(&nest);
+                        done.wait();
+
+                        lock.is_ok()
+                    })
+                })
+                .collect();
+
+            let holders = attempts
+                .into_iter()
+                .map(|attempt| attempt.join().unwrap())
+                .filter(|&held| held)
+                .count();
+
+            assert_eq!(holders, 1);
+        }
+
+        fs::remove_dir_all(&nest).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+//! Deciding how much rat says about what it's doing.
+//!
+//! Normally a command only prints its result, like the changes it staged or
+//! the commits it found. With `-v`, rat also says which files it hashed,
+//! stored or wrote out and how long each slow step took, and with `-vv` it
+//! goes further still, saying which refs every revision resolved to and which
+//! files it could skip. With `-q`, it doesn't even report on what it did,
+//! only printing what was asked for.
+//!
+//! All of these extra messages go to standard error, so they never get mixed
+//! up with output that's meant to be read by another program.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much to print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Only what the command was asked for, set with `-q`.
+    Quiet,
+    /// The command's results, which is the default.
+    Normal,
+    /// What's being done to which files, and how long it took, set with `-v`.
+    Verbose,
+    /// Every little step along the way, set with `-vv`.
+    Trace,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+/// Prints as much as `level` says for the rest of the program.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Gets how much we're printing.
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Quiet,
+        1 => Level::Normal,
+        2 => Level::Verbose,
+        _ => Level::Trace,
+    }
+}
+
+/// Prints `message` as a warning, unless we were asked to be quiet.
+pub fn warning(message: fmt::Arguments) {
+    log(Level::Normal, format_args!("warning: {message}"));
+}
+
+/// Prints `message` if we were asked to be verbose.
+pub fn verbose(message: fmt::Arguments) {
+    log(Level::Verbose, message);
+}
+
+/// Prints `message` if we were asked to say everything.
+pub fn trace(message: fmt::Arguments) {
+    log(Level::Trace, message);
+}
+
+fn log(level: Level, message: fmt::Arguments) {
+    if self::level() >= level {
+        eprintln!("{message}");
+    }
+}
@@ -1,156 +1,4882 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::env;
 use std::error::Error;
 use std::fs;
-use std::process::Command;
-use std::{env, io};
-
-mod utils;
-
-// Akin to the hidden .git directory, this is the directory where rat will store
-// the history of the nest. The real .git directory is a bit more complicated
-// than we're going to make it, but the concept is the same - everything that
-// git stores is nothing magical, it's all just files stored in a directory.
-const RAT_NEST: &str = ".rat";
-
-// We're going to be using Box<dyn Error> to make some aspects of error handling
-// less explicit for simplicity. It allows us to use any type that implements
-// the Error trait as an error, including types known only at runtime thanks
-// to "dyn". In an completely idiomatic application, you would likely create
-// custom error types that you bubble up to the surface and then report in a
-// user-friendly way.
-fn main() -> Result<(), Box<dyn Error>> {
-    let command_line_arguments: Vec<String> = env::args().collect();
-
-    let subcommand = command_line_arguments
-        .get(1)
-        .ok_or_else(|| "No command provided.".to_string())?;
-
-    let output = match subcommand.as_str() {
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+
+use rat::archive::{self, ArchiveFormat};
+use rat::bisect::{self, BisectMark, BisectState, BisectStep};
+use rat::cli::{self, Argument, Command, Flag, Invocation, Matches};
+use rat::compare::{self, Change, Entry, FileMode};
+use rat::completion::{self, Shell};
+use rat::config::{self, Config, ConfigFile};
+use rat::error::{CommitError, InitError, RefError, RemoteError, UsageError};
+use rat::grep::{self, GrepMatch};
+use rat::http;
+use rat::index::Index;
+use rat::json::Json;
+use rat::lock::NestLock;
+use rat::logging::{self, Level};
+use rat::metadata::{CommitMetadata, Signature, TagMetadata};
+use rat::objects::{self, ObjectKind};
+use rat::patch::{FilePatch, PatchContent, PatchHunk, PatchLine};
+use rat::pretty::{Commit, Graph, LogFormat};
+use rat::progress::{self, TerminalBar};
+use rat::refs::{self, Head, Ref};
+use rat::regex::Regex;
+use rat::renames::Detection;
+use rat::repository::{
+    ConflictSide, DiffSide, FastForwardMode, InitOutcome, LogFilter, MergeOutcome, MigrateOutcome,
+    RebaseOutcome, Repository, ResetMode, Status, DEFAULT_BRANCH, MIGRATED_DIR,
+};
+use rat::resolve::RevisionRange;
+use rat::signing::{self, SignatureStatus};
+use rat::stats::{self, NestStats};
+use rat::submodules::{self, Submodule};
+use rat::tools::{Tool, ToolKind};
+use rat::utils::{self, Color, ColorChoice, TerminalFormat};
+use rat::worktree::RestorePlan;
+use rat::worktrees::{self, Worktree};
+use rat::{
+    blame, bundle, cache, commit_graph, fast_export, git, git_import, graph, hooks, nest_dir,
+    nest_path, notes, pack, pager, patch, remote, resolve, state,
+};
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+
+            // Each kind of failure gets its own exit code, so that scripts can
+            // tell them apart without having to read the message.
+            ExitCode::from(rat::error::exit_code(e.as_ref()))
+        }
+    }
+}
+
+/// The flags that work with every command.
+static GLOBAL_FLAGS: &[Flag] = &[
+    Flag::switch(
+        &["--json"],
+        "Print the output of log, blame, status, branch, tag and count-objects as JSON.",
+    ),
+    Flag::value(
+        &["--color"],
+        "when",
+        "Color the output: auto, which is the default, always or never.",
+    ),
+    Flag::switch(
+        &["--no-pager"],
+        "Print everything at once instead of through a pager.",
+    ),
+    Flag::value(
+        &["--rat-dir"],
+        "dir",
+        "Use the nest in this directory instead of .rat. RAT_DIR does the same. A git \
+         repository's .git directory works too, for commands that only read history.",
+    ),
+    Flag::value(
+        &["--work-tree"],
+        "dir",
+        "Use this directory as the working directory. RAT_WORK_TREE does the same.",
+    ),
+    Flag::switch(
+        &["--no-cache"],
+        "Hash every file again instead of trusting the ones that look unchanged.",
+    ),
+    Flag::switch(
+        &["-q", "--quiet"],
+        "Only print what was asked for, without progress bars or messages about what was done.",
+    ),
+    Flag::switch(
+        &["-v", "--verbose"],
+        "Say which files are hashed, stored and written, and how long each step takes.",
+    ),
+    Flag::switch(
+        &["-vv", "--trace"],
+        "Say even more than -v, like which commit each revision resolves to.",
+    ),
+];
+
+/// The commands whose output can easily run longer than a screen, and so is
+/// shown through a pager when it's going to a terminal.
+const PAGED_COMMANDS: &[&str] = &["log", "shortlog", "show", "reflog", "blame", "grep", "diff"];
+
+/// The commands whose output only ever reports what they did, which -q leaves
+/// out. Commands that can stop partway with conflicts aren't here, since what
+/// they print then is something the user needs to know.
+const REPORTING_COMMANDS: &[&str] = &[
+    "init",
+    "clone",
+    "migrate",
+    "add",
+    "rm",
+    "mv",
+    "commit",
+    "fetch",
+    "push",
+    "reset",
+    "switch",
+    "restore",
+    "checkout",
+    "pack-refs",
+    "maintenance",
+    "repack",
+];
+
+/// The commands that change the nest, which only one rat process should be
+/// doing at a time.
+const LOCKED_COMMANDS: &[&str] = &[
+    "migrate",
+    "add",
+    "rm",
+    "mv",
+    "clean",
+    "commit",
+    "branch",
+    "tag",
+    "cherry-pick",
+    "remote",
+    "fetch",
+    "push",
+    "merge",
+    "rebase",
+    "pull",
+    "bundle",
+    "revert",
+    "reset",
+    "switch",
+    "restore",
+    "bisect",
+    "checkout",
+    "mergetool",
+    "symbolic-ref",
+    "pack-refs",
+    "maintenance",
+    "repack",
+    "import-git",
+    "notes",
+    "worktree",
+    "submodule",
+];
+
+/// The commands that make a new nest rather than using the one we're in, so
+/// which nest directory to use isn't known until they've started.
+const NEW_NEST_COMMANDS: &[&str] = &["init", "clone"];
+
+/// The commands that work with the files in the working directory, which a
+/// bare nest doesn't have.
+const WORK_TREE_COMMANDS: &[&str] = &[
+    "add",
+    "rm",
+    "mv",
+    "clean",
+    "commit",
+    "status",
+    "cherry-pick",
+    "merge",
+    "rebase",
+    "pull",
+    "revert",
+    "reset",
+    "switch",
+    "restore",
+    "bisect",
+    "checkout",
+    "import-git",
+    "mergetool",
+    "submodule",
+];
+
+/// The commands that only read history, which are the ones that work when
+/// the nest is really a git repository.
+const GIT_COMMANDS: &[&str] = &[
+    "log",
+    "shortlog",
+    "show",
+    "blame",
+    "describe",
+    "merge-base",
+    "cat-file",
+    "ls-tree",
+    "show-ref",
+];
+
+/// Every command rat knows, in the order they're listed in the help.
+static COMMANDS: &[Command] = &[
+    Command {
+        name: "init",
+        summary: "Create an empty nest",
+        usage: &["rat init [--bare] [--initial-branch <name>] [--hash <algorithm>] [<directory>]"],
+        description: "Creates a new rat nest in <directory>, or the current directory, with no \
+                      commits and HEAD on the main branch. The directory is created if it \
+                      doesn't exist yet. Running it where there's already a nest is safe, and \
+                      leaves the nest as it was. Objects are named with SHA-256 hashes, unless \
+                      --hash picks BLAKE3, which is faster. This can't be changed later, and \
+                      only nests that use the same one can share history.",
+        flags: &[
+            Flag::switch(
+                &["--bare"],
+                "Make a nest with no working directory, for others to push to.",
+            ),
+            Flag::value(
+                &["-b", "--initial-branch"],
+                "name",
+                "Start out on this branch instead of init.defaultBranch, or main.",
+            ),
+            Flag::value(
+                &["--hash"],
+                "algorithm",
+                "Name objects with sha256 or blake3 hashes.",
+            ),
+        ],
+        arguments: &[Argument::optional("directory")],
+    },
+    Command {
+        name: "clone",
+        summary: "Copy a nest and all of its history",
+        usage: &["rat clone [--mirror] <source> [<directory>]"],
+        description: "Copies the nest at <source>, which can be a path or an http:// URL, into \
+                      <directory>, and checks out the branch it was on. Without a directory, \
+                      the copy is named after the source.",
+        flags: &[Flag::switch(
+            &["--mirror"],
+            "Make a bare copy with exactly the same branches, tags and notes, which rat \
+             fetch keeps exactly the same, deleting whatever the source deletes.",
+        )],
+        arguments: &[
+            Argument::required("source"),
+            Argument::optional("directory"),
+        ],
+    },
+    Command {
+        name: "migrate",
+        summary: "Upgrade a nest made by an older version of rat",
+        usage: &["rat migrate"],
+        description: "Upgrades the nest to the layout this version of rat uses, in place. The \
+                      snapshots made by the very first version of rat become commits on the \
+                      main branch, one for each snapshot with the message it was made with, \
+                      and the working directory is left as it is. The snapshots themselves \
+                      are moved into .rat/migrated rather than deleted.",
+        flags: &[],
+        arguments: &[],
+    },
+    Command {
+        name: "add",
+        summary: "Stage changes to be committed",
+        usage: &["rat add [-p] <path>..."],
+        description: "Stages the files at each <path>, or everything inside it if it's a \
+                      directory, so that they're part of the next commit. Paths that have been \
+                      deleted are staged as deletions. With -p, each change to a tracked file \
+                      is shown in turn, and only the ones you say yes to are staged.",
+        flags: &[Flag::switch(
+            &["-p", "--patch"],
+            "Pick which changes to stage one hunk at a time.",
+        )],
+        arguments: &[Argument::repeated("path")],
+    },
+    Command {
+        name: "rm",
+        summary: "Remove files from the working directory and the index",
+        usage: &["rat rm [--cached] [-r] [-f] <path>..."],
+        description: "Deletes the files at each <path> and stages their deletion, so that \
+                      they're no longer part of the next commit. Files with changes that \
+                      haven't been committed are left alone unless -f is given.",
+        flags: &[
+            Flag::switch(
+                &["--cached"],
+                "Only stop tracking the files, leaving them in the working directory.",
+            ),
+            Flag::switch(&["-r"], "Remove everything inside directories too."),
+            Flag::switch(
+                &["-f", "--force"],
+                "Remove files even if they have changes that haven't been committed.",
+            ),
+        ],
+        arguments: &[Argument::repeated("path")],
+    },
+    Command {
+        name: "mv",
+        summary: "Move or rename a file or directory",
+        usage: &["rat mv [-f] <source> <destination>"],
+        description: "Moves <source> to <destination>, or inside it if it's a directory, and \
+                      stages the move, noting it down as a rename.",
+        flags: &[Flag::switch(
+            &["-f", "--force"],
+            "Replace whatever is already at <destination>.",
+        )],
+        arguments: &[
+            Argument::required("source"),
+            Argument::required("destination"),
+        ],
+    },
+    Command {
+        name: "clean",
+        summary: "Delete files that aren't being tracked",
+        usage: &["rat clean [-n | -f] [-d] [-x]"],
+        description: "Lists the files in the working directory that aren't being tracked, \
+                      leaving out ignored ones. Nothing is deleted unless -f is given, since \
+                      untracked files can't be gotten back afterwards.",
+        flags: &[
+            Flag::switch(
+                &["-n", "--dry-run"],
+                "Only list what would be deleted, which is the default.",
+            ),
+            Flag::switch(&["-f", "--force"], "Actually delete the files."),
+            Flag::switch(
+                &["-d"],
+                "Delete directories with nothing tracked inside as well.",
+            ),
+            Flag::switch(&["-x"], "Delete ignored files as well."),
+        ],
+        arguments: &[],
+    },
+    Command {
+        name: "commit",
+        summary: "Record the staged changes",
+        usage: &[
+            "rat commit [--amend] [--allow-empty] [--no-verify] [-S] [-m <message>... | -F <file>] \
+             [--] [<path>...]",
+        ],
+        description: "Records everything that's staged as a new commit on the current branch. \
+                      Without -m or -F, your editor is opened to write the message, starting \
+                      with a summary of what's being committed. Lines starting with # are \
+                      left out of the message. With --amend, \
+                      the last commit is replaced instead, and the one it replaces can still \
+                      be found with rat reflog. A commit that doesn't change anything is \
+                      refused unless --allow-empty is given. With -S, or the setting \
+                      commit.sign set to true, the commit is signed with the SSH key in \
+                      user.signingKey. Given <path>s, only the files there are committed, \
+                      straight from the working directory, and anything else that's staged \
+                      stays staged.",
+        flags: &[
+            Flag::value(
+                &["-m", "--message"],
+                "message",
+                "Use <message> as the commit message. Given more than once, each one \
+                 becomes a paragraph.",
+            ),
+            Flag::value(
+                &["-F", "--file"],
+                "file",
+                "Read the commit message from <file>, or standard input if it's -.",
+            ),
+            Flag::switch(
+                &["--amend"],
+                "Replace the last commit, starting from its message.",
+            ),
+            Flag::switch(&["--allow-empty"], "Commit even if nothing has changed."),
+            Flag::switch(
+                &["--no-verify", "-n"],
+                "Don't run the pre-commit and commit-msg hooks.",
+            ),
+            Flag::switch(&["-S", "--sign"], "Sign the commit with your SSH key."),
+        ],
+        arguments: &[Argument::optional_repeated("path")],
+    },
+    Command {
+        name: "log",
+        summary: "Show the history",
+        usage: &[
+            "rat log [--oneline | --format <format>] [--graph] [--show-signature] \
+             [--stat | --name-status] [-n <count>] [--author <text>] [--grep <text>] \
+             [--since <date>] [--until <date>] [--follow <path>] [--all | <revision>]",
+        ],
+        description: "Lists every commit in the history of <revision>, or HEAD if it isn't \
+                      given, newest first. The options narrow down which commits are shown. \
+                      Dates can be like 2023-11-14, 2023-11-14 22:13:20 or 2 weeks ago. \
+                      Formats can use placeholders like %h for the abbreviated hash, %an for \
+                      the author's name, %ad for the date and %s for the first line of the \
+                      message. With --stat or --name-status, each commit is followed by the \
+                      files it changed, compared to its parent. Merges are left without, the \
+                      same as in rat show.",
+        flags: &[
+            Flag::switch(
+                &["--oneline"],
+                "Show each commit on one line, with just its hash and subject.",
+            ),
+            Flag::switch(
+                &["--stat"],
+                "Show how many lines were added and removed in each file a commit changed.",
+            ),
+            Flag::switch(
+                &["--name-status"],
+                "Show each file a commit changed, with a letter for how it changed.",
+            ),
+            Flag::value(
+                &["--format", "--pretty"],
+                "format",
+                "Show each commit using <format>, like \"%h %an %s\".",
+            ),
+            Flag::switch(
+                &["--graph"],
+                "Draw the lines of history next to the commits.",
+            ),
+            Flag::switch(
+                &["--show-signature"],
+                "Check each commit's signature, and show whether it's good.",
+            ),
+            Flag::switch(
+                &["--all"],
+                "Show the history of every branch and tag, not just one revision.",
+            ),
+            Flag::value(
+                &["-n", "--max-count"],
+                "count",
+                "Show at most <count> commits.",
+            ),
+            Flag::value(
+                &["--author"],
+                "text",
+                "Only show commits whose author's name or email contains <text>.",
+            ),
+            Flag::value(
+                &["--grep"],
+                "text",
+                "Only show commits whose message contains <text>.",
+            ),
+            Flag::value(
+                &["--follow"],
+                "path",
+                "Only show commits that changed the file at <path>, following it back \
+                 through renames.",
+            ),
+            Flag::value(
+                &["--since", "--after"],
+                "date",
+                "Only show commits made on or after <date>.",
+            ),
+            Flag::value(
+                &["--until", "--before"],
+                "date",
+                "Only show commits made on or before <date>.",
+            ),
+        ],
+        arguments: &[Argument::optional("revision")],
+    },
+    Command {
+        name: "shortlog",
+        summary: "Summarize the history by author",
+        usage: &["rat shortlog [-s] [-n] [-e] [--all | <revision>]"],
+        description: "Groups every commit in the history of <revision>, or HEAD if it isn't \
+                      given, by who wrote it, listing the first line of each one's message, \
+                      oldest first. Authors are sorted by name, unless -n is given.",
+        flags: &[
+            Flag::switch(
+                &["-s", "--summary"],
+                "Only show how many commits each author made, not their messages.",
+            ),
+            Flag::switch(
+                &["-n", "--numbered"],
+                "Sort authors by how many commits they made, most first.",
+            ),
+            Flag::switch(&["-e", "--email"], "Show each author's email too."),
+            Flag::switch(
+                &["--all"],
+                "Summarize the history of every branch and tag, not just one revision.",
+            ),
+        ],
+        arguments: &[Argument::optional("revision")],
+    },
+    Command {
+        name: "show",
+        summary: "Show a commit and what it changed",
+        usage: &["rat show [<revision>]"],
+        description: "Shows the commit <revision> names, or HEAD if it isn't given, along \
+                      with how it changed each file. If <revision> is an annotated tag, the \
+                      tag is shown first. Merges are shown without their changes, since they \
+                      have more than one parent to compare them with.",
+        flags: &[],
+        arguments: &[Argument::optional("revision")],
+    },
+    Command {
+        name: "verify-commit",
+        summary: "Check a commit's signature",
+        usage: &["rat verify-commit [<revision>]"],
+        description: "Checks that the commit <revision> names, or HEAD if it isn't given, \
+                      was signed by its committer, against the keys listed in the file the \
+                      setting signing.allowedSigners names. Fails if the signature is bad or \
+                      missing.",
+        flags: &[],
+        arguments: &[Argument::optional("revision")],
+    },
+    Command {
+        name: "verify-tag",
+        summary: "Check a tag's signature",
+        usage: &["rat verify-tag <tag>"],
+        description: "Checks that <tag> was signed by whoever made it, against the keys \
+                      listed in the file the setting signing.allowedSigners names. Fails if \
+                      the signature is bad or missing.",
+        flags: &[],
+        arguments: &[Argument::required("tag")],
+    },
+    Command {
+        name: "notes",
+        summary: "Attach notes to commits",
+        usage: &[
+            "rat notes [list]",
+            "rat notes add [-f] [-m <text>...] [<commit>]",
+            "rat notes show [<commit>]",
+            "rat notes remove [<commit>]",
+        ],
+        description: "Adds, shows or removes the note on <commit>, which defaults to HEAD. A \
+                      note is extra text shown under the commit's message in the log, which \
+                      can be added at any time without changing the commit itself. Without \
+                      -m, your editor is opened to write the note in. Listing shows every \
+                      commit that has a note.",
+        flags: &[
+            Flag::value(
+                &["-m", "--message"],
+                "text",
+                "Use <text> as the note. Given more than once, each one becomes a paragraph.",
+            ),
+            Flag::switch(
+                &["-f", "--force"],
+                "Replace the note the commit already has.",
+            ),
+        ],
+        arguments: &[Argument::optional("action"), Argument::optional("commit")],
+    },
+    Command {
+        name: "reflog",
+        summary: "Show where HEAD or a branch has been",
+        usage: &["rat reflog [<ref>]"],
+        description: "Lists every move of <ref>, or HEAD if it isn't given, newest first, \
+                      along with what moved it. Each one can be used as a revision, like \
+                      HEAD@{2} for where HEAD was two moves ago, which is how to get back to \
+                      commits that no branch points at any more.",
+        flags: &[],
+        arguments: &[Argument::optional("ref")],
+    },
+    Command {
+        name: "blame",
+        summary: "Show which commit each line of a file came from",
+        usage: &["rat blame <path> [<revision>]"],
+        description: "Shows every line of the file at <path> as it is in <revision>, or HEAD \
+                      if it isn't given, next to the commit that last changed it, along with \
+                      who wrote it and when.",
+        flags: &[],
+        arguments: &[Argument::required("path"), Argument::optional("revision")],
+    },
+    Command {
+        name: "grep",
+        summary: "Search the contents of files",
+        usage: &["rat grep [-i] [-l] [--untracked] <pattern> [<revision>]"],
+        description: "Shows every line matching the regular expression <pattern> in the files \
+                      being tracked in the working directory, or in every file in <revision> \
+                      if it's given. Patterns can use ., [a-z], \\d, ^, $, *, +, ?, {n,m}, | \
+                      and groups in brackets.",
+        flags: &[
+            Flag::switch(
+                &["-i", "--ignore-case"],
+                "Match regardless of upper and lower case.",
+            ),
+            Flag::switch(
+                &["-l", "--files-with-matches"],
+                "Only show the paths of the files that match.",
+            ),
+            Flag::switch(
+                &["--untracked"],
+                "Search untracked files as well, unless they're ignored.",
+            ),
+        ],
+        arguments: &[
+            Argument::required("pattern"),
+            Argument::optional("revision"),
+        ],
+    },
+    Command {
+        name: "status",
+        summary: "Show what's changed since the last commit",
+        usage: &["rat status [--porcelain] [-z]"],
+        description: "Shows which changes are staged, which aren't, and which files aren't \
+                      tracked at all. With --porcelain, shows one line per file instead, \
+                      meant for scripts, which stays the same between versions of rat: two \
+                      letters for the change staged and the change not staged, a space, and \
+                      the path. The letters are M for modified, A for added, D for deleted, \
+                      R for renamed and C for copied, or a space for no change, with UU for a \
+                      conflict and ?? for a file that isn't tracked. A rename or copy is \
+                      shown as `R  old -> new`. With -z, each line ends with a zero byte \
+                      instead of a newline, and a rename or copy is shown as `R  new`, a zero \
+                      byte, then `old`, so that any path can be told apart.",
+        flags: &[
+            Flag::switch(
+                &["--porcelain"],
+                "Show one line per file, in a format that won't change.",
+            ),
+            Flag::switch(
+                &["-z"],
+                "End each line with a zero byte rather than a newline. Implies \
+                 --porcelain.",
+            ),
+        ],
+        arguments: &[],
+    },
+    Command {
+        name: "diff",
+        summary: "Show changes line by line",
+        usage: &["rat diff [--staged] [--no-renames | -C] [<revision>]"],
+        description: "Shows the changes in the working directory that aren't staged yet. With \
+                      --staged, shows the changes that are staged instead, compared to \
+                      <revision> or HEAD. Given just <revision>, shows every change in the \
+                      working directory since that commit. <revision> can also be a range: \
+                      a..b compares a with b, and a...b compares b with where it split off \
+                      from a. Files that were moved are shown as renames, as long as \
+                      they're still at least half the same.",
+        flags: &[
+            Flag::switch(
+                &["--staged", "--cached"],
+                "Show the changes that are staged to be committed.",
+            ),
+            Flag::switch(&["--no-renames"], "Show moved files as deleted and added."),
+            Flag::switch(
+                &["-C", "--find-copies"],
+                "Look for files that were copied as well as renamed.",
+            ),
+        ],
+        arguments: &[Argument::optional("revision")],
+    },
+    Command {
+        name: "difftool",
+        summary: "Show changes in another program",
+        usage: &["rat difftool [--staged] [-t <tool>] [<revision>]"],
+        description: "Opens each file that rat diff would show in the program set with \
+                      diff.tool, one at a time. Tools rat doesn't know about can be set up \
+                      with difftool.<tool>.cmd, a command using $LOCAL and $REMOTE for the \
+                      old and new versions of the file. Changes made to files in the working \
+                      directory are kept.",
+        flags: &[
+            Flag::switch(
+                &["--staged", "--cached"],
+                "Show the changes that are staged to be committed.",
+            ),
+            Flag::value(
+                &["-t", "--tool"],
+                "tool",
+                "Use <tool> instead of diff.tool.",
+            ),
+        ],
+        arguments: &[Argument::optional("revision")],
+    },
+    Command {
+        name: "mergetool",
+        summary: "Resolve conflicts in another program",
+        usage: &["rat mergetool [-t <tool>] [<path>...]"],
+        description: "Opens each conflicted file, or only the ones at <path>, in the program \
+                      set with merge.tool, and stages the ones it merges to mark them as \
+                      resolved. Tools rat doesn't know about can be set up with \
+                      mergetool.<tool>.cmd, a command using $LOCAL, $REMOTE, $BASE and \
+                      $MERGED. Unless mergetool.<tool>.trustExitCode is true, a file only \
+                      counts as merged if the tool changed it.",
+        flags: &[Flag::value(
+            &["-t", "--tool"],
+            "tool",
+            "Use <tool> instead of merge.tool.",
+        )],
+        arguments: &[Argument::optional_repeated("path")],
+    },
+    Command {
+        name: "config",
+        summary: "Read and change settings",
+        usage: &[
+            "rat config [--global] --list",
+            "rat config [--global] --get <key>",
+            "rat config [--global] --set <key> <value>",
+        ],
+        description: "Reads or changes settings, like core.editor. Changes go to the nest's \
+                      own config file, unless --global is given.",
+        flags: &[
+            Flag::switch(
+                &["--global"],
+                "Use your own config file instead of the nest's.",
+            ),
+            Flag::switch(&["--list"], "Show every setting."),
+            Flag::switch(&["--get"], "Show the value of <key>."),
+            Flag::switch(&["--set"], "Set <key> to <value>."),
+        ],
+        arguments: &[Argument::optional("key"), Argument::optional("value")],
+    },
+    Command {
+        name: "branch",
+        summary: "List, create, delete or rename branches",
+        usage: &[
+            "rat branch",
+            "rat branch <name> [<commit>]",
+            "rat branch (-d | -D) <name>",
+            "rat branch -m <old> <new>",
+        ],
+        description: "Without any arguments, lists every branch. Otherwise creates a branch \
+                      called <name> at <commit>, or HEAD if it isn't given.",
+        flags: &[
+            Flag::switch(
+                &["-d", "--delete"],
+                "Delete a branch whose commits are all part of the current branch.",
+            ),
+            Flag::switch(&["-D"], "Delete a branch even if that would lose commits."),
+            Flag::switch(&["-m", "--move"], "Rename a branch."),
+        ],
+        arguments: &[Argument::optional("name"), Argument::optional("commit")],
+    },
+    Command {
+        name: "tag",
+        summary: "List, create or delete tags",
+        usage: &[
+            "rat tag",
+            "rat tag [-a | -s] [-m <message>] <name> [<commit>]",
+            "rat tag -d <name>",
+        ],
+        description: "Without any arguments, lists every tag. Otherwise creates a tag called \
+                      <name> at <commit>, or HEAD if it isn't given. Giving a message makes \
+                      the tag annotated, which records who made it and why. Signed tags can \
+                      be checked with rat verify-tag.",
+        flags: &[
+            Flag::switch(
+                &["-a", "--annotate"],
+                "Make an annotated tag, writing its message in your editor.",
+            ),
+            Flag::switch(
+                &["-s", "--sign"],
+                "Make an annotated tag signed with the SSH key in user.signingKey.",
+            ),
+            Flag::value(
+                &["-m", "--message"],
+                "message",
+                "Make an annotated tag with <message>.",
+            ),
+            Flag::switch(&["-d", "--delete"], "Delete a tag."),
+        ],
+        arguments: &[Argument::optional("name"), Argument::optional("commit")],
+    },
+    Command {
+        name: "describe",
+        summary: "Name a commit after the nearest tag",
+        usage: &["rat describe [--tags] [--long] [--dirty] [<commit>]"],
+        description: "Names <commit>, or HEAD, after the nearest annotated tag in its history, \
+                      like v1.2-14-gabc1234 for the 14th commit since v1.2, or just v1.2 for \
+                      the tagged commit itself. Commits without a tag in their history are \
+                      named by their abbreviated hash instead, which makes this handy for \
+                      putting a version in builds.",
+        flags: &[
+            Flag::switch(
+                &["--tags"],
+                "Use lightweight tags as well as annotated ones.",
+            ),
+            Flag::switch(
+                &["--long"],
+                "Always show how many commits since the tag and the hash.",
+            ),
+            Flag::switch(
+                &["--dirty"],
+                "Add -dirty on the end if there are uncommitted changes.",
+            ),
+        ],
+        arguments: &[Argument::optional("commit")],
+    },
+    Command {
+        name: "cherry-pick",
+        summary: "Apply the changes from a commit",
+        usage: &[
+            "rat cherry-pick <commit>",
+            "rat cherry-pick (--continue | --abort)",
+        ],
+        description: "Makes the same changes as <commit> on top of HEAD, as a new commit. If \
+                      that causes conflicts, fix them, stage them, and then run \
+                      rat cherry-pick --continue.",
+        flags: &[
+            Flag::switch(
+                &["--continue"],
+                "Finish a cherry-pick once conflicts are fixed.",
+            ),
+            Flag::switch(&["--abort"], "Give up on a cherry-pick that stopped."),
+        ],
+        arguments: &[Argument::optional("commit")],
+    },
+    Command {
+        name: "worktree",
+        summary: "Check out branches in working directories of their own",
+        usage: &[
+            "rat worktree [list]",
+            "rat worktree add <path> <branch>",
+            "rat worktree remove [--force] <path>",
+        ],
+        description: "Lists, adds or removes working directories linked to this nest. Each \
+                      has its own HEAD and index but shares the history, branches and \
+                      config, so different branches can be worked on side by side. A branch \
+                      can only be checked out in one of them at a time.",
+        flags: &[Flag::switch(
+            &["-f", "--force"],
+            "Remove a working directory even if it has uncommitted changes.",
+        )],
+        arguments: &[
+            Argument::optional("action"),
+            Argument::optional("path"),
+            Argument::optional("branch"),
+        ],
+    },
+    Command {
+        name: "submodule",
+        summary: "Keep other nests inside this one",
+        usage: &[
+            "rat submodule [status]",
+            "rat submodule add <source> <path>",
+            "rat submodule update",
+        ],
+        description: "Adding clones the nest <source> into <path>, records where it came from \
+                      in .ratmodules and stages it as a pointer to the commit it's on, so \
+                      committing pins it there. Updating clones any that are missing and \
+                      checks out the commit each one is pinned to. The status lists them, \
+                      with - in front of ones that haven't been cloned yet and + in front of \
+                      ones that are on a different commit.",
+        flags: &[],
+        arguments: &[
+            Argument::optional("action"),
+            Argument::optional("source"),
+            Argument::optional("path"),
+        ],
+    },
+    Command {
+        name: "remote",
+        summary: "Manage the nests you share history with",
+        usage: &[
+            "rat remote [list]",
+            "rat remote add <name> <url>",
+            "rat remote remove <name>",
+            "rat remote set-url [--push [--add]] <name> <url>",
+            "rat remote prune <name>",
+        ],
+        description: "Lists, adds or removes remotes, which are other nests you fetch from \
+                      and push to by name. Set-url changes where a remote is, or with --push, \
+                      where it's pushed to, which can be more than one place with --add. \
+                      Prune deletes the remote branches for branches that are gone from the \
+                      remote, without fetching anything.",
+        flags: &[
+            Flag::switch(
+                &["--push"],
+                "Change where the remote is pushed to, rather than where it's fetched from.",
+            ),
+            Flag::switch(
+                &["--add"],
+                "Push to the new URL as well as the others, rather than instead of them.",
+            ),
+        ],
+        arguments: &[
+            Argument::optional("action"),
+            Argument::optional("name"),
+            Argument::optional("url"),
+        ],
+    },
+    Command {
+        name: "fetch",
+        summary: "Download history from a remote",
+        usage: &["rat fetch [--prune] [<remote>]"],
+        description: "Copies every commit <remote> has that this nest doesn't, and updates \
+                      its remote branches and tags. The remote defaults to origin. Remote \
+                      branches for branches that were deleted from the remote are kept unless \
+                      --prune is given, or remote.<remote>.prune or fetch.prune is true. A \
+                      nest made by rat clone --mirror has its branches, tags and notes made \
+                      exactly the same as the remote's instead.",
+        flags: &[Flag::switch(
+            &["-p", "--prune"],
+            "Delete remote branches whose branches are gone from the remote.",
+        )],
+        arguments: &[Argument::optional("remote")],
+    },
+    Command {
+        name: "push",
+        summary: "Upload a branch to a remote",
+        usage: &["rat push [--no-verify] [<remote> [--all | --mirror | <branch>]]"],
+        description: "Copies <branch> and its history to <remote>, as long as that's a \
+                      fast-forward. They default to origin and the current branch. If the \
+                      remote has push URLs, set with rat remote set-url --push, it's pushed \
+                      to at each of them, and what happened at each one is shown.",
+        flags: &[
+            Flag::switch(&["--all"], "Push every branch, rather than just one."),
+            Flag::switch(
+                &["--mirror"],
+                "Make the remote's branches, tags and notes exactly the same as ours, even \
+                 if that loses commits, deleting whatever we don't have.",
+            ),
+            Flag::switch(&["--no-verify"], "Don't run the pre-push hook."),
+        ],
+        arguments: &[Argument::optional("remote"), Argument::optional("branch")],
+    },
+    Command {
+        name: "merge",
+        summary: "Join another line of history into the current branch",
+        usage: &[
+            "rat merge [--ff-only | --no-ff] [--no-verify] <commit>",
+            "rat merge (--continue | --abort)",
+        ],
+        description: "Brings the changes from <commit>, which is usually a branch, into the \
+                      current branch, fast-forwarding if possible and making a merge commit \
+                      otherwise. If that causes conflicts, fix them, stage them, and then run \
+                      rat merge --continue.",
+        flags: &[
+            Flag::switch(
+                &["--ff-only"],
+                "Refuse to make a merge commit, and only fast-forward.",
+            ),
+            Flag::switch(
+                &["--no-ff"],
+                "Always make a merge commit, even when fast-forwarding would do.",
+            ),
+            Flag::switch(
+                &["--no-verify"],
+                "Don't run the pre-commit and commit-msg hooks.",
+            ),
+            Flag::switch(&["--continue"], "Finish a merge once conflicts are fixed."),
+            Flag::switch(
+                &["--abort"],
+                "Give up on a merge and put everything back the way it was.",
+            ),
+        ],
+        arguments: &[Argument::optional("commit")],
+    },
+    Command {
+        name: "rebase",
+        summary: "Move the current branch's commits on top of another",
+        usage: &[
+            "rat rebase [-i] <upstream>",
+            "rat rebase (--continue | --abort)",
+        ],
+        description: "Replays each commit on the current branch that <upstream> doesn't have \
+                      on top of <upstream>, then moves the branch to the last of them. With \
+                      -i, your editor is opened on the list of commits first, so you can \
+                      reorder, reword, squash or drop them. If a commit causes conflicts, fix \
+                      them, stage them, and then run rat rebase --continue.",
+        flags: &[
+            Flag::switch(
+                &["-i", "--interactive"],
+                "Edit the list of commits before replaying them.",
+            ),
+            Flag::switch(
+                &["--continue"],
+                "Carry on with a rebase once conflicts are fixed.",
+            ),
+            Flag::switch(
+                &["--abort"],
+                "Give up on a rebase and put the branch back where it was.",
+            ),
+        ],
+        arguments: &[Argument::optional("upstream")],
+    },
+    Command {
+        name: "pull",
+        summary: "Fetch from a remote and merge",
+        usage: &["rat pull [--ff-only | --no-ff] [<remote> [<branch>]]"],
+        description: "Fetches from <remote> and merges its <branch> into the current branch. \
+                      They default to origin and the branch with the same name as the \
+                      current one.",
+        flags: &[
+            Flag::switch(
+                &["--ff-only"],
+                "Refuse to make a merge commit, and only fast-forward.",
+            ),
+            Flag::switch(
+                &["--no-ff"],
+                "Always make a merge commit, even when fast-forwarding would do.",
+            ),
+        ],
+        arguments: &[Argument::optional("remote"), Argument::optional("branch")],
+    },
+    Command {
+        name: "merge-base",
+        summary: "Find where two histories split apart",
+        usage: &["rat merge-base [--all] <commit> <other>"],
+        description: "Shows the best common ancestor of <commit> and <other>, which is the \
+                      most recent commit in both of their histories, and what merging them \
+                      would start from.",
+        flags: &[Flag::switch(
+            &["--all"],
+            "Show every best common ancestor, if there's more than one.",
+        )],
+        arguments: &[Argument::required("commit"), Argument::required("other")],
+    },
+    Command {
+        name: "archive",
+        summary: "Write the files of a commit to a tar or zip file",
+        usage: &["rat archive [--format <format>] [--prefix <prefix>] [-o <file>] [<revision>]"],
+        description: "Writes the files of <revision>, or HEAD if it isn't given, to <file> or \
+                      to the standard output, without touching the working directory. The \
+                      format is tar, tar.gz or zip, which is guessed from the name of <file> \
+                      if it isn't given, and is tar otherwise.",
+        flags: &[
+            Flag::value(&["--format"], "format", "Write this kind of archive."),
+            Flag::value(
+                &["--prefix"],
+                "prefix",
+                "Put this in front of every path, like project/ to put everything in a \
+                 project directory.",
+            ),
+            Flag::value(
+                &["-o", "--output"],
+                "file",
+                "Write the archive to this file.",
+            ),
+        ],
+        arguments: &[Argument::optional("revision")],
+    },
+    Command {
+        name: "export",
+        summary: "Convert the nest into a git repository",
+        usage: &["rat export --to-git [-o <file>]"],
+        description: "Writes every branch and tag, along with all of their history, as a \
+                      stream for git fast-import. Feeding it to git fast-import in a new, \
+                      empty git repository turns that into a copy of the nest.",
+        flags: &[
+            Flag::switch(&["--to-git"], "Write a git fast-import stream."),
+            Flag::value(
+                &["-o", "--output"],
+                "file",
+                "Write the stream to this file.",
+            ),
+        ],
+        arguments: &[],
+    },
+    Command {
+        name: "import-git",
+        summary: "Copy the history of a git repository into the nest",
+        usage: &["rat import-git <path>"],
+        description: "Reads the git repository at <path>, which can be a working directory or \
+                      the repository itself, and recreates every branch and tag along with all \
+                      of their history, keeping every message, author and date. Branches and \
+                      tags that already exist in the nest are left alone. If nothing has been \
+                      committed in the nest yet, the branch the git repository is on gets \
+                      checked out.",
+        flags: &[],
+        arguments: &[Argument::required("path")],
+    },
+    Command {
+        name: "bundle",
+        summary: "Move history around in a single file",
+        usage: &[
+            "rat bundle create <file> <range>",
+            "rat bundle unbundle <file>",
+        ],
+        description: "Writes the commits in <range>, like main or v1..main, to <file>, or \
+                      reads them back in, so that history can be shared without a network.",
+        flags: &[],
+        arguments: &[
+            Argument::required("action"),
+            Argument::required("file"),
+            Argument::optional("range"),
+        ],
+    },
+    Command {
+        name: "serve",
+        summary: "Share the nest over HTTP",
+        usage: &["rat serve [<port> | <host>:<port>]"],
+        description: "Serves the nest over HTTP, so that it can be cloned and fetched from. \
+                      A port on its own, which is 8080 if it isn't given, only takes \
+                      connections from this computer. To let other computers connect, give \
+                      the address to listen on as well, like 0.0.0.0:8080 for every network \
+                      interface. With -v, every request is listed as it's answered.",
+        flags: &[],
+        arguments: &[Argument::optional("address")],
+    },
+    Command {
+        name: "revert",
+        summary: "Undo the changes from a commit",
+        usage: &["rat revert <commit>"],
+        description: "Makes a new commit that undoes the changes made by <commit>.",
+        flags: &[],
+        arguments: &[Argument::required("commit")],
+    },
+    Command {
+        name: "reset",
+        summary: "Move the current branch to another commit",
+        usage: &[
+            "rat reset [--soft | --mixed] [<commit>]",
+            "rat reset --hard [-n] [<commit>]",
+        ],
+        description: "Moves the current branch to <commit>, or HEAD if it isn't given. By \
+                      default the index is reset too, but the working directory is left \
+                      alone.",
+        flags: &[
+            Flag::switch(&["--soft"], "Leave the index alone as well."),
+            Flag::switch(&["--mixed"], "Reset the index, which is the default."),
+            Flag::switch(
+                &["--hard"],
+                "Reset the working directory as well, losing any uncommitted changes.",
+            ),
+            Flag::switch(
+                &["-n", "--dry-run"],
+                "With --hard, only list the files that would be overwritten or deleted.",
+            ),
+        ],
+        arguments: &[Argument::optional("commit")],
+    },
+    Command {
+        name: "switch",
+        summary: "Switch to another branch",
+        usage: &[
+            "rat switch <branch>",
+            "rat switch -c <new-branch> [<start>]",
+            "rat switch --detach <commit>",
+        ],
+        description: "Updates the working directory to match <branch> and switches to it. \
+                      With -c, makes a new branch at <start>, or HEAD, and switches to that. \
+                      With --detach, detaches HEAD at <commit> instead.",
+        flags: &[
+            Flag::value(
+                &["-c", "--create"],
+                "new-branch",
+                "Make a new branch and switch to it.",
+            ),
+            Flag::switch(&["--detach"], "Detach HEAD at a commit."),
+        ],
+        arguments: &[Argument::optional("target")],
+    },
+    Command {
+        name: "restore",
+        summary: "Bring files back to how they were committed",
+        usage: &[
+            "rat restore [--source <revision>] [--staged] <path>...",
+            "rat restore (--ours | --theirs) <path>...",
+        ],
+        description: "Replaces each <path> in the index and the working directory with how it \
+                      is in <revision>, which defaults to HEAD, throwing away any changes to \
+                      it. HEAD and the current branch stay where they are. During a conflict, \
+                      --ours and --theirs replace each conflicted file in the working directory \
+                      with one side's version instead, and it stays conflicted until you add it.",
+        flags: &[
+            Flag::value(
+                &["-s", "--source"],
+                "revision",
+                "Restore the files from this commit instead of HEAD.",
+            ),
+            Flag::switch(
+                &["--staged"],
+                "Only restore the index, keeping the changes in the working directory.",
+            ),
+            Flag::switch(
+                &["--ours"],
+                "Take our version of each conflicted file, from the branch we're on.",
+            ),
+            Flag::switch(
+                &["--theirs"],
+                "Take their version of each conflicted file, from the commit being merged in.",
+            ),
+        ],
+        arguments: &[Argument::repeated("path")],
+    },
+    Command {
+        name: "bisect",
+        summary: "Search through history for the commit that broke something",
+        usage: &[
+            "rat bisect start [<bad> [<good>...]]",
+            "rat bisect (good | bad | skip) [<commit>...]",
+            "rat bisect run [--] <command> [<arguments>...]",
+            "rat bisect reset",
+        ],
+        description: "Finds the first bad commit by binary search. Mark a bad commit and a \
+                      good one from before it broke, and rat checks out the commit halfway \
+                      between them for you to test and mark in turn, until only one is \
+                      left. <commit> defaults to HEAD. run tests each commit with \
+                      <command> instead: exiting with 0 means good, 125 means it can't be \
+                      tested, and anything else up to 127 means bad. reset goes back to \
+                      where you started.",
+        flags: &[],
+        arguments: &[
+            Argument::required("action"),
+            Argument::trailing("arguments"),
+        ],
+    },
+    Command {
+        name: "checkout",
+        summary: "Switch to a branch or commit",
+        usage: &["rat checkout [-n] (<branch> | <commit>)"],
+        description: "Updates the working directory to match <branch> and switches to it, or \
+                      detaches HEAD at <commit>. This is the older way of doing what switch \
+                      does, kept for anyone used to it.",
+        flags: &[Flag::switch(
+            &["-n", "--dry-run"],
+            "Only list the files that would be created, overwritten or deleted.",
+        )],
+        arguments: &[Argument::required("target")],
+    },
+    Command {
+        name: "hash-object",
+        summary: "Work out the hash a file would have as a blob",
+        usage: &["rat hash-object [-w] <file>..."],
+        description: "Prints the hash each <file> would be stored under, which is the same \
+                      wherever the file is, since it only depends on the contents.",
+        flags: &[Flag::switch(
+            &["-w", "--write"],
+            "Store the files in the nest as well.",
+        )],
+        arguments: &[Argument::repeated("file")],
+    },
+    Command {
+        name: "cat-file",
+        summary: "Show an object from the object store",
+        usage: &["rat cat-file [-t | -s | -p] <object>"],
+        description: "Prints the contents of <object>, which is any kind of object: the \
+                      contents of a blob, the listing of a tree, or the metadata of a commit or \
+                      tag. <object> can be a revision, an object's hash, or revision:path for \
+                      a file or directory in a commit.",
+        flags: &[
+            Flag::switch(&["-t"], "Show what kind of object it is instead."),
+            Flag::switch(&["-s"], "Show the size of its contents in bytes instead."),
+            Flag::switch(&["-p"], "Show its contents, which is the default."),
+        ],
+        arguments: &[Argument::required("object")],
+    },
+    Command {
+        name: "ls-files",
+        summary: "List the files rat knows about",
+        usage: &["rat ls-files [-c] [-o] [-i] [-z] [<path>...]"],
+        description: "Lists the files being tracked, which are the ones in the index, or just \
+                      the ones inside each <path>. Untracked and ignored files can be listed \
+                      as well, or instead.",
+        flags: &[
+            Flag::switch(
+                &["-c", "--cached"],
+                "List tracked files, which is the default without -o or -i.",
+            ),
+            Flag::switch(
+                &["-o", "--others"],
+                "List untracked files that aren't ignored.",
+            ),
+            Flag::switch(
+                &["-i", "--ignored"],
+                "List ignored files that aren't tracked.",
+            ),
+            Flag::switch(
+                &["-z"],
+                "End each path with a zero byte rather than a newline, so that any path can \
+                 be told apart.",
+            ),
+        ],
+        arguments: &[Argument::optional_repeated("path")],
+    },
+    Command {
+        name: "ls-tree",
+        summary: "List the files in a commit or tree",
+        usage: &["rat ls-tree [-r] [--name-only] <revision> [<path>]"],
+        description: "Lists what's in the root directory of <revision>, or in <path>, with \
+                      the mode, kind and hash of each entry. <revision> can also be a tree, \
+                      like revision:dir.",
+        flags: &[
+            Flag::switch(&["-r"], "List the files inside directories too."),
+            Flag::switch(&["--name-only"], "Only list the paths."),
+        ],
+        arguments: &[Argument::required("revision"), Argument::optional("path")],
+    },
+    Command {
+        name: "show-ref",
+        summary: "List refs and the hashes they point at",
+        usage: &["rat show-ref [--heads] [--tags] [--head] [-s] [-d] [<pattern>...]"],
+        description: "Lists every ref, like refs/heads/main, along with the hash it points at. \
+                      With a <pattern>, only the refs whose names end with it are listed, so \
+                      main matches both refs/heads/main and refs/remotes/origin/main.",
+        flags: &[
+            Flag::switch(&["--heads"], "Only list branches."),
+            Flag::switch(&["--tags"], "Only list tags."),
+            Flag::switch(&["--head"], "List HEAD as well."),
+            Flag::switch(&["-s", "--hash"], "Only list the hashes."),
+            Flag::switch(
+                &["-d", "--dereference"],
+                "List the commit each annotated tag points at too, as tag^{}.",
+            ),
+        ],
+        arguments: &[Argument::optional_repeated("pattern")],
+    },
+    Command {
+        name: "count-objects",
+        summary: "Show what's in the nest and how much room it takes up",
+        usage: &["rat count-objects"],
+        description: "Counts the objects in the nest by kind, how many are loose and how many \
+                      are packed, and the branches and tags. Then it compares how big the \
+                      history would be if every commit kept a copy of every file with how big \
+                      it is storing each version of a file once, and how much room the nest \
+                      really takes up on disk, and lists the biggest files.",
+        flags: &[],
+        arguments: &[],
+    },
+    Command {
+        name: "symbolic-ref",
+        summary: "Show or change which branch HEAD points at",
+        usage: &["rat symbolic-ref [--short] <name> [<ref>]"],
+        description: "Prints the full name of the ref that the symbolic ref <name> points at, \
+                      like refs/heads/main for HEAD. With <ref>, <name> is pointed at that ref \
+                      instead. For HEAD, <ref> has to be a branch, and the index and the \
+                      working directory are left as they are.",
+        flags: &[Flag::switch(
+            &["--short"],
+            "Print the short name of the ref, like main, rather than the full name.",
+        )],
+        arguments: &[Argument::required("name"), Argument::optional("ref")],
+    },
+    Command {
+        name: "pack-refs",
+        summary: "Pack refs into a single file",
+        usage: &["rat pack-refs [--all]"],
+        description: "Moves tags, which hardly ever change, out of their own files and into \
+                      .rat/packed-refs, which is much quicker to read when there are lots of \
+                      them. Refs that were packed before and have changed since are packed \
+                      again too.",
+        flags: &[Flag::switch(
+            &["--all"],
+            "Pack branches and every other ref as well.",
+        )],
+        arguments: &[],
+    },
+    Command {
+        name: "maintenance",
+        summary: "Tidy up the nest so that rat stays quick",
+        usage: &["rat maintenance"],
+        description: "Writes down the shape of the history in .rat/commit-graph, so that \
+                      walking through it doesn't mean reading every commit along the way, and \
+                      packs tags like pack-refs does. It's always safe to run, and worth \
+                      running again every so often as the history grows.",
+        flags: &[],
+        arguments: &[],
+    },
+    Command {
+        name: "repack",
+        summary: "Move objects into a pack to save space",
+        usage: &["rat repack [-a]"],
+        description: "Moves every object stored in a file of its own into a single new pack in \
+                      .rat/objects/pack. Objects that are similar to each other, like two \
+                      versions of the same file, are stored as the differences between them, \
+                      which usually takes a fraction of the space. Reading an object works the \
+                      same either way. Objects are compressed if core.compression says to, \
+                      which with -a applies to everything in the nest.",
+        flags: &[Flag::switch(
+            &["-a", "--all"],
+            "Put the objects from existing packs into the new one too, so that everything \
+             ends up in a single pack.",
+        )],
+        arguments: &[],
+    },
+    Command {
+        name: "completions",
+        summary: "Print a script that completes rat's commands in a shell",
+        usage: &["rat completions (bash | zsh | fish | powershell)"],
+        description: "Prints a script that teaches <shell> to complete rat's commands, flags \
+                      and the names of branches and tags, which it asks rat for each time. \
+                      The top of the script says where to put it.",
+        flags: &[],
+        arguments: &[Argument::required("shell")],
+    },
+    Command {
+        name: "help",
+        summary: "Show how to use rat or one of its commands",
+        usage: &["rat help [<command>]"],
+        description: "",
+        flags: &[],
+        arguments: &[Argument::optional("command")],
+    },
+];
+
+// The library returns its own error types, but here we're going to be using
+// Box<dyn Error> so that every command can bubble up whichever one it runs
+// into with ?. It allows us to use any type that implements the Error trait as
+// an error, including types known only at runtime thanks to "dyn", and main
+// can still work out which one it was to pick an exit code.
+fn run() -> Result<(), Box<dyn Error>> {
+    let command_line_arguments: Vec<String> = env::args().skip(1).collect();
+
+    let matches = match cli::parse(COMMANDS, GLOBAL_FLAGS, &command_line_arguments)? {
+        Invocation::Overview => {
+            println!("{}", cli::overview(COMMANDS, GLOBAL_FLAGS));
+
+            return Ok(());
+        }
+        Invocation::Version => {
+            println!("rat {}", env!("CARGO_PKG_VERSION"));
+
+            return Ok(());
+        }
+        Invocation::Help(command) => {
+            println!("{}", command.help(GLOBAL_FLAGS));
+
+            return Ok(());
+        }
+        Invocation::Run(matches) => matches,
+    };
+
+    locate_nest(&matches)?;
+
+    let name = matches.command().name;
+
+    if WORK_TREE_COMMANDS.contains(&name) && Repository::open()?.is_bare() {
+        Err(InitError::Bare { command: name })?;
+    }
+
+    // Looking for the nest settles where it is, so we mustn't for commands
+    // that are yet to decide.
+    if !GIT_COMMANDS.contains(&name)
+        && !NEW_NEST_COMMANDS.contains(&name)
+        && git::is_git_dir(nest_dir())
+    {
+        Err(InitError::GitRepository { command: name })?;
+    }
+
+    // The lock is given back when this goes out of scope at the end.
+    let _lock = if LOCKED_COMMANDS.contains(&name) {
+        // A nest laid out the old way can't be opened until it's been
+        // migrated, so migrating only needs there to be a nest at all.
+        if name != "migrate" {
+            Repository::open()?;
+        } else if !nest_dir().is_dir() {
+            Err(InitError::NotANest {
+                path: env::current_dir()?,
+            })?;
+        }
+
+        Some(NestLock::acquire()?)
+    } else {
+        None
+    };
+
+    // Commands that list things can print them as JSON instead, for other
+    // programs to read.
+    let json = matches.flag("--json");
+
+    let color = match matches.value("--color") {
+        Some(choice) => choice.parse().map_err(|e| matches.error(e))?,
+        None => ColorChoice::Auto,
+    };
+    let format = utils::terminal_format(color);
+
+    if matches.flag("--no-cache") {
+        cache::disable();
+    }
+
+    let level = match matches.one_of(&["--quiet", "--verbose", "--trace"])? {
+        Some("--quiet") => Level::Quiet,
+        Some("--verbose") => Level::Verbose,
+        Some(_) => Level::Trace,
+        None => Level::Normal,
+    };
+    logging::set_level(level);
+
+    // A progress bar is only any use to someone watching it, so it's left out
+    // when standard error is going somewhere else. It would get in the way of
+    // the extra messages -v prints there too.
+    if level == Level::Normal && io::stderr().is_terminal() {
+        progress::set_reporter(Box::new(TerminalBar::new()));
+    }
+
+    let output = match matches.command().name {
         "init" => {
-            init()?;
-
-            "Initialized new rat nest.".to_string()
-        }
-        "commit" => {
-            // The user can specify the commit message either through the -m
-            // option in the command itself or by opening their default editor
-            // to edit a commit message.
-            let message = if let Some("-m") = command_line_arguments.get(2).map(String::as_str) {
-                // If they specified -m, we attempt to get the message from the
-                // next argument.
-                command_line_arguments
-                    .get(3)
-                    .ok_or_else(|| "No commit message provided.".to_string())?
-                    .to_owned()
+            let directory = matches.argument("directory");
+
+            if let Some(directory) = directory {
+                fs::create_dir_all(directory)?;
+                env::set_current_dir(directory)?;
+            }
+
+            let bare = matches.flag("--bare");
+
+            // A bare nest is the directory itself, unless we've been told to
+            // put it somewhere else.
+            if bare {
+                rat::set_nest_dir(".");
+            }
+
+            // Like git, the user can pick a different initial branch for all
+            // of their nests in their config.
+            let config = Config::load()?;
+            let initial_branch = matches
+                .value("--initial-branch")
+                .or_else(|| config.get("init.defaultBranch"))
+                .unwrap_or(DEFAULT_BRANCH);
+
+            let algorithm = matches
+                .value("--hash")
+                .map(str::parse)
+                .transpose()
+                .map_err(|e| matches.error(e))?;
+
+            let (_, outcome) = Repository::init(bare, initial_branch, algorithm)?;
+
+            let action = match outcome {
+                InitOutcome::Created => "Initialized new",
+                InitOutcome::Reinitialized => "Reinitialized existing",
+            };
+
+            match directory {
+                Some(directory) => format!("{action} rat nest in {directory}."),
+                None => format!("{action} rat nest."),
+            }
+        }
+        "clone" => {
+            let source = matches.required("source")?;
+
+            let (_, destination) = Repository::clone(
+                source,
+                matches.argument("directory"),
+                matches.flag("--mirror"),
+            )?;
+
+            format!("Cloned {source} into {destination}.")
+        }
+        "migrate" => match Repository::migrate()? {
+            MigrateOutcome::UpToDate => "The nest is already up to date.".to_string(),
+            MigrateOutcome::Recorded => "Recorded the nest's format.".to_string(),
+            MigrateOutcome::Snapshots { commits } => {
+                format!(
+                    "Upgraded the nest, turning {commits} snapshot(s) into commits on \
+                     {DEFAULT_BRANCH}. The old snapshots are kept in .rat/{MIGRATED_DIR}, and \
+                     can be deleted once you're happy with the result."
+                )
+            }
+        },
+        "add" if matches.flag("--patch") => add_patch(&Repository::open()?, &matches, format)?,
+        "add" => {
+            let count = Repository::open()?.add(matches.arguments("path"))?;
+
+            format!("Staged {count} change(s).")
+        }
+        "rm" => Repository::open()?
+            .remove(
+                matches.arguments("path"),
+                matches.flag("--cached"),
+                matches.flag("-r"),
+                matches.flag("-f"),
+            )?
+            .into_iter()
+            .map(|path| format!("Removed {path}."))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        "mv" => Repository::open()?
+            .move_path(
+                matches.required("source")?,
+                matches.required("destination")?,
+                matches.flag("-f"),
+            )?
+            .into_iter()
+            .map(|(old, new)| format!("Moved {old} to {new}."))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        "clean" => {
+            let force = matches.one_of(&["-n", "-f"])? == Some("-f");
+            let verb = if force { "Removed" } else { "Would remove" };
+
+            Repository::open()?
+                .clean(matches.flag("-d"), matches.flag("-x"), force)?
+                .into_iter()
+                .map(|path| format!("{verb} {path}."))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        "commit" => commit(&Repository::open()?, &matches)?,
+        "log" => log(&Repository::open()?, &matches, json, format)?,
+        "shortlog" => shortlog(&Repository::open()?, &matches)?,
+        "show" => show(&Repository::open()?, &matches, format)?,
+        "verify-commit" => {
+            Repository::open()?;
+
+            verify_commit(&matches)?
+        }
+        "verify-tag" => {
+            Repository::open()?;
+
+            verify_tag(&matches)?
+        }
+        "notes" => {
+            Repository::open()?;
+
+            notes(&matches)?
+        }
+        "reflog" => {
+            Repository::open()?;
+
+            reflog(&matches, format)?
+        }
+        "blame" => blame(&Repository::open()?, &matches, json, format)?,
+        "grep" => grep(&matches, format)?,
+        "diff" => diff(&Repository::open()?, &matches, format)?,
+        "difftool" => return difftool(&Repository::open()?, &matches),
+        "mergetool" => mergetool(&Repository::open()?, &matches)?,
+        "status" => {
+            let status = Repository::open()?.status()?;
+
+            if matches.flag("--porcelain") || matches.flag("-z") {
+                let output = format_porcelain_status(status, matches.flag("-z"));
+                return Ok(pager::print_raw(output.as_bytes())?);
+            }
+
+            if json {
+                status_json(status).to_string()
+            } else {
+                format_status(status, format)
+            }
+        }
+        "config" => config(&matches)?,
+        "branch" => branch(&Repository::open()?, &matches, json, format)?,
+        "tag" => tag(&Repository::open()?, &matches, json)?,
+        "describe" => describe(&Repository::open()?, &matches)?,
+        "cherry-pick" => {
+            let repository = Repository::open()?;
+            let action = matches.one_of(&["--continue", "--abort"])?;
+
+            if action.is_some() && !matches.positional().is_empty() {
+                Err(matches.error("--continue and --abort don't take a commit."))?;
+            }
+
+            if matches.flag("--no-verify") {
+                hooks::skip_commit_checks();
+            }
+
+            match action {
+                Some("--continue") => {
+                    let hash = repository.cherry_pick_continue()?;
+
+                    format!("Created commit {}.", resolve::abbreviate(&hash))
+                }
+                Some(_) => {
+                    repository.cherry_pick_abort()?;
+
+                    "Cancelled cherry-pick.".to_string()
+                }
+                None => {
+                    let revision = matches.required("commit")?;
+                    let hash = repository.cherry_pick(&resolve::resolve_revision(revision)?)?;
+
+                    format!("Created commit {}.", resolve::abbreviate(&hash))
+                }
+            }
+        }
+        "remote" => {
+            Repository::open()?;
+
+            // We convert the arguments into string slices so we can match on
+            // them.
+            let arguments: Vec<&str> = matches.positional().iter().map(String::as_str).collect();
+
+            if arguments.first() != Some(&"set-url")
+                && (matches.flag("--push") || matches.flag("--add"))
+            {
+                Err(matches.error("--push and --add only go with set-url."))?;
+            }
+
+            match arguments[..] {
+                [] | ["list"] => {
+                    let mut lines = Vec::new();
+
+                    for (name, url) in remote::list()? {
+                        let push_urls = remote::push_urls(&name)?;
+
+                        lines.push(format!("{name}\t{url}"));
+
+                        if push_urls != [url] {
+                            lines.extend(
+                                push_urls
+                                    .into_iter()
+                                    .map(|push_url| format!("{name}\t{push_url} (push)")),
+                            );
+                        }
+                    }
+
+                    lines.join("\n")
+                }
+                ["add", name, url] => {
+                    remote::add(name, url)?;
+
+                    format!("Added remote {name}.")
+                }
+                ["remove", name] => {
+                    remote::remove(name)?;
+
+                    format!("Removed remote {name}.")
+                }
+                ["set-url", name, url] => {
+                    let push = matches.flag("--push");
+
+                    if matches.flag("--add") && !push {
+                        Err(matches.error("--add only goes with --push."))?;
+                    }
+
+                    remote::set_url(name, url, push, matches.flag("--add"))?;
+
+                    match push {
+                        true => format!("Set a push URL for {name}."),
+                        false => format!("Set the URL of {name}."),
+                    }
+                }
+                ["prune", name] => {
+                    let deleted = remote::prune(name)?;
+
+                    if deleted.is_empty() {
+                        format!("{name} has no stale remote branches.")
+                    } else {
+                        format!("Pruned {name}:\n{}", deleted.join("\n"))
+                    }
+                }
+                ["add" | "remove" | "set-url" | "prune", ..] => Err(matches.error(format!(
+                    "Wrong number of arguments for rat remote {}.",
+                    arguments[0]
+                )))?,
+                [action, ..] => Err(matches.error(format!("Unknown action {action}.")))?,
+            }
+        }
+        "worktree" => {
+            Repository::open()?;
+
+            let arguments: Vec<&str> = matches.positional().iter().map(String::as_str).collect();
+
+            match arguments[..] {
+                [] | ["list"] => format_worktrees(worktrees::list()?),
+                ["add", path, branch] => {
+                    let worktree = worktrees::add(Path::new(path), branch)?;
+
+                    format!("Checked out {branch} in {}.", worktree.path.display())
+                }
+                ["remove", path] => {
+                    let worktree = worktrees::remove(Path::new(path), matches.flag("-f"))?;
+
+                    format!("Removed {}.", worktree.path.display())
+                }
+                ["add" | "remove", ..] => Err(matches.error(format!(
+                    "Wrong number of arguments for rat worktree {}.",
+                    arguments[0]
+                )))?,
+                [action, ..] => Err(matches.error(format!("Unknown action {action}.")))?,
+            }
+        }
+        "submodule" => {
+            let repository = Repository::open()?;
+
+            let arguments: Vec<&str> = matches.positional().iter().map(String::as_str).collect();
+
+            match arguments[..] {
+                [] | ["status"] => format_submodules(submodules::list()?),
+                ["add", source, path] => add_submodule(&repository, source, path)?,
+                ["update"] => update_submodules()?,
+                ["add" | "update", ..] => Err(matches.error(format!(
+                    "Wrong number of arguments for rat submodule {}.",
+                    arguments[0]
+                )))?,
+                [action, ..] => Err(matches.error(format!("Unknown action {action}.")))?,
+            }
+        }
+        "fetch" => {
+            Repository::open()?;
+
+            let name = matches.argument("remote").unwrap_or("origin");
+            let prune = matches.flag("--prune") || remote::prunes_by_default(name)?;
+            let updates = remote::fetch(name, prune)?;
+
+            if updates.is_empty() {
+                format!("{name} has nothing new.")
+            } else {
+                format!("Fetched from {name}:\n{}", updates.join("\n"))
+            }
+        }
+        "push" => {
+            Repository::open()?;
+
+            push(&matches)?
+        }
+        "merge" => {
+            let repository = Repository::open()?;
+            let action = matches.one_of(&["--continue", "--abort"])?;
+
+            if action.is_some() && !matches.positional().is_empty() {
+                Err(matches.error("--continue and --abort don't take a commit."))?;
+            }
+
+            if matches.flag("--no-verify") {
+                hooks::skip_commit_checks();
+            }
+
+            match action {
+                Some("--continue") => {
+                    let hash = repository.merge_continue()?;
+
+                    format!("Created merge commit {}.", resolve::abbreviate(&hash))
+                }
+                Some(_) => {
+                    repository.merge_abort()?;
+
+                    "Cancelled merge.".to_string()
+                }
+                None => {
+                    let revision = matches.required("commit")?;
+                    let their_hash = resolve::resolve_revision(revision)?;
+
+                    // The label ends up in the message of the merge commit, so
+                    // it's worth making clear when it's the name of a branch.
+                    let label = match refs::read_branch(revision)? {
+                        Some(_) => format!("branch {revision}"),
+                        None => revision.to_string(),
+                    };
+
+                    describe_merge(repository.merge(
+                        &their_hash,
+                        &label,
+                        fast_forward_mode(&matches)?,
+                    )?)
+                }
+            }
+        }
+        "rebase" => {
+            let repository = Repository::open()?;
+            let action = matches.one_of(&["--continue", "--abort"])?;
+
+            if action.is_some() && !matches.positional().is_empty() {
+                Err(matches.error("--continue and --abort don't take an upstream."))?;
+            }
+
+            match action {
+                Some("--continue") => {
+                    describe_rebase(repository.rebase_continue(&mut edit_message)?)
+                }
+                Some(_) => {
+                    repository.rebase_abort()?;
+
+                    "Cancelled rebase.".to_string()
+                }
+                None => {
+                    let upstream = resolve::resolve_revision(matches.required("upstream")?)?;
+
+                    describe_rebase(repository.rebase(
+                        &upstream,
+                        matches.flag("-i"),
+                        &mut edit_message,
+                    )?)
+                }
+            }
+        }
+        "pull" => {
+            let repository = Repository::open()?;
+
+            // By default we pull the branch with the same name as the current
+            // one from origin.
+            let name = matches.argument("remote").unwrap_or("origin");
+            let branch = match matches.argument("branch") {
+                Some(branch) => branch.to_string(),
+                None => match refs::read_head()? {
+                    Head::Branch(branch) => branch,
+                    Head::Detached(_) => Err("HEAD is detached, so there's no branch to pull.")?,
+                },
+            };
+
+            describe_merge(repository.pull(name, &branch, fast_forward_mode(&matches)?)?)
+        }
+        "merge-base" => {
+            let commit = resolve::resolve_revision(matches.required("commit")?)?;
+            let other = resolve::resolve_revision(matches.required("other")?)?;
+
+            let mut bases = graph::merge_bases(&commit, &other)?;
+
+            if bases.is_empty() {
+                Err("Those commits don't have any history in common.")?;
+            }
+
+            if !matches.flag("--all") {
+                bases.truncate(1);
+            }
+
+            bases.join("\n")
+        }
+        "bundle" => {
+            Repository::open()?;
+
+            let file = matches.required("file")?;
+
+            match (matches.required("action")?, matches.argument("range")) {
+                ("create", Some(range)) => {
+                    let count = bundle::create(Path::new(file), range)?;
+
+                    format!("Wrote {count} object(s) to {file}.")
+                }
+                ("create", None) => Err(matches.error("Missing <range>."))?,
+                ("unbundle", None) => {
+                    let updates = bundle::unbundle(Path::new(file))?;
+
+                    if updates.is_empty() {
+                        format!("{file} has nothing new.")
+                    } else {
+                        format!("Unbundled {file}:\n{}", updates.join("\n"))
+                    }
+                }
+                ("unbundle", Some(range)) => {
+                    Err(matches.error(format!("Unexpected argument {range}.")))?
+                }
+                (action, _) => Err(matches.error(format!("Unknown action {action}.")))?,
+            }
+        }
+        "serve" => {
+            let address = serve_address(&matches)?;
+            let repository = Repository::open()?;
+            let listener = http::listen(&address)?;
+
+            println!(
+                "Serving the nest on http://{}. Press Ctrl+C to stop.",
+                listener.local_addr()?
+            );
+            repository.serve(listener);
+
+            String::new()
+        }
+        "revert" => {
+            let repository = Repository::open()?;
+            let revision = matches.required("commit")?;
+            let hash = repository.revert(&resolve::resolve_revision(revision)?)?;
+
+            format!("Created commit {}.", resolve::abbreviate(&hash))
+        }
+        "reset" => {
+            let repository = Repository::open()?;
+
+            // The mode is optional and defaults to --mixed, just like in git.
+            let mode = match matches.one_of(&["--soft", "--mixed", "--hard"])? {
+                Some("--soft") => ResetMode::Soft,
+                Some("--hard") => ResetMode::Hard,
+                _ => ResetMode::Mixed,
+            };
+
+            // Without a commit, we reset to HEAD itself, which is a handy way
+            // to unstage everything, or with --hard, to throw away every
+            // uncommitted change.
+            let commit_hash = match matches.argument("commit") {
+                Some(revision) => resolve::resolve_revision(revision)?,
+                None => refs::resolve_head()?
+                    .ok_or_else(|| "There are no commits yet to reset to.".to_string())?,
+            };
+
+            if matches.flag("--dry-run") {
+                if mode != ResetMode::Hard {
+                    Err(matches.error("--dry-run only works with --hard."))?;
+                }
+
+                describe_plan(repository.preview_hard_reset(&commit_hash)?)
             } else {
-                // Otherwise, we open their editor to a special file and use the
-                // contents of that file as the commit message instead.
-                let commit_file = format!("{RAT_NEST}/COMMIT_EDITMSG");
+                repository.reset(mode, &commit_hash)?;
 
-                // Empty the file first
-                fs::write(&commit_file, "")?;
+                format!("HEAD is now at {}.", resolve::abbreviate(&commit_hash))
+            }
+        }
+        "switch" => switch(&Repository::open()?, &matches)?,
+        "bisect" => bisect(&Repository::open()?, &matches)?,
+        "restore" => {
+            let repository = Repository::open()?;
 
-                // By convention, the default editor is usually in the $EDITOR
-                // environment variable, but sometimes in $VISUAL.
-                let editor = env::var("EDITOR")
-                    .or_else(|_| env::var("VISUAL"))
-                    .map_err(|_| "No editor set.".to_string())?;
+            let side = match matches.one_of(&["--ours", "--theirs"])? {
+                Some("--ours") => Some(ConflictSide::Ours),
+                Some(_) => Some(ConflictSide::Theirs),
+                None => None,
+            };
 
-                Command::new(editor)
-                    // We pass in the special commit file to the editor
-                    // through the Command interface.
-                    .arg(&commit_file)
-                    .status()?;
+            let restored = match side {
+                Some(_) if matches.value("-s").is_some() || matches.flag("--staged") => {
+                    Err(matches
+                        .error("--ours and --theirs can't be used with --source or --staged."))?
+                }
+                Some(side) => repository.restore_side(matches.arguments("path"), side)?,
+                None => {
+                    let source = match matches.value("-s") {
+                        Some(revision) => resolve::resolve_revision(revision)?,
+                        None => refs::resolve_head()?.ok_or(RefError::NoCommits)?,
+                    };
 
-                fs::read_to_string(commit_file)
-                    .map_err(|e| format!("Failed to read commit message: {e}"))?
+                    repository.restore(
+                        matches.arguments("path"),
+                        &source,
+                        matches.flag("--staged"),
+                    )?
+                }
             };
 
-            if message.trim().is_empty() {
-                Err("Cancelled commit.")?;
+            restored
+                .into_iter()
+                .map(|path| format!("Restored {path}."))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        "checkout" => {
+            let repository = Repository::open()?;
+            let target = matches.required("target")?;
+
+            match matches.flag("--dry-run") {
+                true => describe_plan(repository.preview_checkout(target)?),
+                false => describe_head(repository.checkout(target)?),
+            }
+        }
+        "hash-object" => {
+            let write = matches.flag("-w");
+
+            if write {
+                Repository::open()?;
+            }
+
+            let mut hashes = Vec::new();
+
+            for file in matches.arguments("file") {
+                let path = Path::new(file);
+
+                hashes.push(match write {
+                    true => objects::write_file(path)?,
+                    false => objects::hash_file(path)?,
+                });
+            }
+
+            hashes.join("\n")
+        }
+        // Blobs can hold anything at all, so they're written out exactly as
+        // they are rather than as text.
+        "cat-file" => return cat_file(&matches),
+        "archive" => return write_archive(&matches),
+        "export" => return export(&matches),
+        "import-git" => {
+            Repository::open()?;
+
+            let summary = git_import::import_git(Path::new(matches.required("path")?))?;
+
+            let mut lines = vec![format!(
+                "Imported {} commit(s), {} branch(es) and {} tag(s).",
+                summary.commits, summary.branches, summary.tags
+            )];
+
+            for path in &summary.submodules {
+                lines.push(format!("Left out the submodule {path}."));
+            }
+
+            for skipped in &summary.skipped {
+                lines.push(format!("Skipped {skipped}."));
+            }
+
+            if let Some(branch) = summary.checked_out {
+                lines.push(format!("Checked out {branch}."));
+            }
+
+            lines.join("\n")
+        }
+        "ls-files" => return ls_files(&matches),
+        "ls-tree" => ls_tree(&matches)?,
+        "show-ref" => show_ref(&matches)?,
+        "pack-refs" => {
+            Repository::open()?;
+
+            let count = refs::pack_refs(matches.flag("--all"))?;
+
+            format!("Packed {count} ref(s).")
+        }
+        "maintenance" => {
+            Repository::open()?;
+
+            let commits = commit_graph::write()?;
+            let packed = refs::pack_refs(false)?;
+
+            format!("Wrote a commit graph of {commits} commit(s).\nPacked {packed} ref(s).")
+        }
+        "repack" => {
+            Repository::open()?;
+
+            match pack::repack(matches.flag("--all"))? {
+                Some(summary) => format!(
+                    "Packed {} object(s) into pack-{}, {} of them as deltas.",
+                    summary.objects, summary.name, summary.deltas
+                ),
+                None => "Nothing to pack.".to_string(),
+            }
+        }
+        "count-objects" => {
+            let repository = Repository::open()?;
+
+            let history = repository
+                .log_all()?
+                .map(|commit| commit.map(|(hash, _)| hash))
+                .collect::<Result<Vec<_>, _>>()?;
+            let stats = stats::collect(&history)?;
+
+            if json {
+                stats_json(stats).to_string()
+            } else {
+                format_stats(stats)
             }
+        }
+        "symbolic-ref" => {
+            Repository::open()?;
+
+            let name = Ref::parse(matches.required("name")?);
+
+            match matches.argument("ref") {
+                Some(target) => {
+                    let target = Ref::parse(target);
+                    refs::write_symbolic_ref(&name, &target)?;
+
+                    format!("{name} now points at {target}.")
+                }
+                None => {
+                    let target = refs::read_symbolic_ref(&name)?.ok_or(RefError::NotSymbolic {
+                        name: name.full_name(),
+                    })?;
 
-            let number = commit(&message)?;
+                    match matches.flag("--short") {
+                        true => target.short_name(),
+                        false => target.full_name(),
+                    }
+                }
+            }
+        }
+        "completions" => {
+            let shell: Shell = matches
+                .required("shell")?
+                .parse()
+                .map_err(|e| matches.error(e))?;
 
-            format!("Created commit number {number}.")
+            completion::script(shell, COMMANDS, GLOBAL_FLAGS)
         }
-        "log" => log()?,
-        _ => Err("Invalid subcommand.")?,
+        "help" => match matches.argument("command") {
+            Some(name) => cli::find(COMMANDS, name)?.help(GLOBAL_FLAGS),
+            None => cli::overview(COMMANDS, GLOBAL_FLAGS),
+        },
+        _ => unreachable!("every command in COMMANDS is handled above"),
     };
 
-    println!("{}", output);
+    // Saying what would happen is the whole point of a dry run.
+    if level == Level::Quiet
+        && REPORTING_COMMANDS.contains(&matches.command().name)
+        && !matches.flag("--dry-run")
+    {
+        return Ok(());
+    }
+
+    if PAGED_COMMANDS.contains(&matches.command().name) && !matches.flag("--no-pager") {
+        pager::page(&output)?;
+    } else {
+        pager::print(&output)?;
+    }
 
     // We need to explicitly return an empty Ok here, since our return value is
     // a Result, not a plain "void".
     Ok(())
 }
 
-/// Initializes a new rat nest in the current directory.
-fn init() -> Result<(), io::Error> {
-    fs::create_dir(RAT_NEST)?;
-    fs::write(format!("{RAT_NEST}/HEAD"), "-1")?;
+/// Names the commit picked out by `matches` after the nearest tag.
+fn describe(repository: &Repository, matches: &Matches) -> Result<String, Box<dyn Error>> {
+    let commit = match matches.argument("commit") {
+        Some(_) if matches.flag("--dirty") => {
+            Err(matches.error("--dirty can't be used with a commit."))?
+        }
+        Some(revision) => resolve::resolve_revision(revision)?,
+        None => refs::resolve_head()?.ok_or(RefError::NoCommits)?,
+    };
 
-    Ok(())
-}
+    let mut name = match repository.describe(&commit, matches.flag("--tags"))? {
+        Some(description) if matches.flag("--long") => format!(
+            "{}-{}-g{}",
+            description.tag,
+            description.distance,
+            resolve::abbreviate(&commit)
+        ),
+        Some(description) => description.to_string(),
+        None => resolve::abbreviate(&commit),
+    };
 
-/// Commits the contents of the current directory to the nest.
-fn commit(message: &str) -> Result<i32, Box<dyn Error>> {
-    let head_file = format!("{RAT_NEST}/HEAD");
+    // Untracked files don't count, since they aren't part of any build that
+    // came from the nest.
+    if matches.flag("--dirty") && !repository.is_bare() {
+        let status = repository.status()?;
+
+        if !status.staged.is_empty() || !status.unstaged.is_empty() {
+            name.push_str("-dirty");
+        }
+    }
 
-    let head_string = fs::read_to_string(&head_file)?;
-    let head_number: i32 = head_string.parse()?;
+    Ok(name)
+}
 
-    let new_head_number = head_number + 1;
+/// Starts, carries on or finishes a bisect, as `matches` asks.
+fn bisect(repository: &Repository, matches: &Matches) -> Result<String, Box<dyn Error>> {
+    let arguments = matches.arguments("arguments");
 
-    // Create a new directory for the new commit inside the nest.
-    let commit_dir = format!("{RAT_NEST}/commit-{new_head_number}");
-    fs::create_dir(&commit_dir)?;
+    let resolve_all = |revisions: &[String]| -> Result<Vec<String>, Box<dyn Error>> {
+        revisions
+            .iter()
+            .map(|revision| Ok(resolve::resolve_revision(revision)?))
+            .collect()
+    };
 
-    // Copy the current working directory into the commit directory, ignoring
-    // the nest itself.
-    utils::copy_dir_deep(env::current_dir()?, &commit_dir, &[RAT_NEST])?;
+    // Commits to mark default to the one that's checked out.
+    let marked = || -> Result<Vec<String>, Box<dyn Error>> {
+        match arguments {
+            [] => Ok(vec![refs::resolve_head()?.ok_or(RefError::NoCommits)?]),
+            revisions => resolve_all(revisions),
+        }
+    };
 
-    // Write a message into the .message file in that directory
-    fs::write(format!("{commit_dir}/.message"), message)?;
+    let mark = |mark: BisectMark| -> Result<String, Box<dyn Error>> {
+        describe_bisect_step(repository.bisect_mark(mark, &marked()?)?)
+    };
 
-    // Update the HEAD file with the new commit that we just created.
-    fs::write(head_file, new_head_number.to_string())?;
+    match matches.required("action")? {
+        "start" => {
+            let bad = match arguments.first() {
+                Some(revision) => Some(resolve::resolve_revision(revision)?),
+                None => None,
+            };
+            let good = resolve_all(arguments.get(1..).unwrap_or_default())?;
 
-    Ok(new_head_number)
+            describe_bisect_step(repository.bisect_start(bad.as_deref(), &good)?)
+        }
+        "good" => mark(BisectMark::Good),
+        "bad" if arguments.len() > 1 => Err(matches.error("Only one commit can be marked bad."))?,
+        "bad" => mark(BisectMark::Bad),
+        "skip" => mark(BisectMark::Skip),
+        "run" => bisect_run(repository, matches, arguments),
+        "reset" if !arguments.is_empty() => {
+            Err(matches.error("reset doesn't take any arguments."))?
+        }
+        "reset" => Ok(describe_head(repository.bisect_reset()?)),
+        action => Err(matches.error(format!("Unknown bisect action {action}.")))?,
+    }
 }
 
-fn log() -> Result<String, Box<dyn Error>> {
-    // First we obtain the current head pointer. We wrap it in an Option because
-    // we're going to be digging into its parents and need a way to bail out
-    // once we get to the root.
-    let head_file = format!("{RAT_NEST}/HEAD");
-    let current_head: i32 = fs::read_to_string(head_file)?.parse()?;
-
-    // We initialize the string we're eventually going to return.
-    let mut logs = String::new();
+/// Runs `command` on commit after commit, marking each one by its exit code,
+/// until the first bad commit turns up.
+fn bisect_run(
+    repository: &Repository,
+    matches: &Matches,
+    command: &[String],
+) -> Result<String, Box<dyn Error>> {
+    let [program, arguments @ ..] = command else {
+        Err(matches.error("Missing <command>."))?
+    };
 
-    for commit_num in (0..=current_head).rev() {
-        // This is the header, which is simply the commit number itself
-        logs.push_str(&format!("commit {commit_num}\n\n"));
+    if bisect::next_step(&BisectState::read()?)? == BisectStep::Waiting {
+        Err("Mark a good commit and a bad one before running a bisect.")?;
+    }
 
-        // We retrieve the message string from the .message file
-        let message = fs::read_to_string(format!("{RAT_NEST}/commit-{commit_num}/.message"))?;
+    loop {
+        let status = process::Command::new(program)
+            .args(arguments)
+            .status()
+            .map_err(|e| format!("Failed to run {program}: {e}"))?;
 
-        // for each line
-        // prepend 4 spaces to that line
-        let indented_message = message
-            .lines()
-            .map(|s| format!("    {s}\n"))
-            .collect::<String>();
+        let mark = match status.code() {
+            Some(0) => BisectMark::Good,
+            Some(125) => BisectMark::Skip,
+            Some(1..=127) => BisectMark::Bad,
+            Some(code) => Err(format!(
+                "{program} exited with code {code}, which doesn't say whether the commit is \
+                 good or bad, so the bisect stopped here."
+            ))?,
+            None => Err(format!(
+                "{program} was stopped by a signal, so the bisect stopped here."
+            ))?,
+        };
 
-        // We append the message to our logs
-        logs.push_str(&indented_message);
+        let head = refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+        let step = repository.bisect_mark(mark, &[head])?;
+        let finished = !matches!(step, BisectStep::Test { .. });
+        let description = describe_bisect_step(step)?;
 
-        // We check this condition down here because we don't want these
-        // separators to be printed after the last commit.
-        if commit_num != 0 {
-            logs.push_str("\n\n");
+        if finished {
+            return Ok(description);
         }
+
+        // There might be a long way to go, so we show how it's going as we go
+        // rather than all at the end.
+        println!("{description}");
     }
+}
+
+/// Describes where a bisect has got to.
+fn describe_bisect_step(step: BisectStep) -> Result<String, Box<dyn Error>> {
+    let summary = |hash: &str| -> Result<String, Box<dyn Error>> {
+        let metadata = objects::read_commit(hash)?;
+        let subject = metadata.message.lines().next().unwrap_or("");
+
+        Ok(format!("{} {subject}", resolve::abbreviate(hash)))
+    };
+
+    Ok(match step {
+        BisectStep::Waiting => "Waiting for both a good and a bad commit.".to_string(),
+        BisectStep::Test { commit, remaining } => {
+            // Each test halves what's left, give or take.
+            let steps = usize::BITS - remaining.leading_zeros();
+            let plural = |count: usize, word: &str| match count {
+                1 => format!("{count} {word}"),
+                _ => format!("{count} {word}s"),
+            };
+
+            format!(
+                "Bisecting: {} left to test after this (roughly {}).\nHEAD is now at {}.",
+                plural(remaining, "commit"),
+                plural(steps as usize, "step"),
+                summary(&commit)?
+            )
+        }
+        BisectStep::Found(hash) => format!("The first bad commit is {}.", summary(&hash)?),
+        BisectStep::OnlySkipped(hashes) => {
+            let mut lines = vec![
+                "Some commits were skipped, so the first bad commit could be any of:".to_string(),
+            ];
+
+            for hash in hashes {
+                lines.push(format!("    {}", summary(&hash)?));
+            }
+
+            lines.join("\n")
+        }
+    })
+}
+
+/// Shows the object picked out by `matches`, or what kind it is or its size.
+fn cat_file(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    Repository::open()?;
+
+    let hash = resolve::resolve_object(matches.required("object")?)?;
+    let (kind, data) = objects::read_object(&hash)?;
+
+    let output = match matches.one_of(&["-t", "-s", "-p"])? {
+        Some("-t") => format!("{kind}\n").into_bytes(),
+        Some("-s") => format!("{}\n", data.len()).into_bytes(),
+        _ => data,
+    };
+
+    Ok(pager::print_raw(&output)?)
+}
+
+/// Writes the archive that `matches` asks for.
+fn write_archive(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    Repository::open()?;
+
+    let output = matches.value("--output");
+
+    let format = match (matches.value("--format"), output) {
+        (Some(name), _) => ArchiveFormat::from_name(name)
+            .ok_or_else(|| matches.error(format!("Unknown archive format {name}.")))?,
+        (None, Some(output)) => ArchiveFormat::from_path(Path::new(output)),
+        (None, None) => ArchiveFormat::Tar,
+    };
+
+    let revision = matches.argument("revision").unwrap_or("HEAD");
+    let prefix = matches.value("--prefix").unwrap_or_default();
+
+    let (archive, count) = archive::create(revision, format, prefix)?;
+
+    match output {
+        Some(output) => {
+            fs::write(output, archive)?;
+            println!("Wrote {count} file(s) to {output}.");
+
+            Ok(())
+        }
+        None => Ok(pager::print_raw(&archive)?),
+    }
+}
+
+/// Writes the export that `matches` asks for.
+fn export(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    Repository::open()?;
+
+    // Git is the only thing we can export to so far, but there's no reason
+    // it has to stay that way.
+    if !matches.flag("--to-git") {
+        Err(matches.error("Missing --to-git."))?;
+    }
+
+    let (stream, summary) = fast_export::export_to_git()?;
+
+    match matches.value("--output") {
+        Some(output) => {
+            fs::write(output, stream)?;
+            println!(
+                "Exported {} commit(s), {} file(s) and {} ref(s) to {output}.",
+                summary.commits, summary.files, summary.refs
+            );
+
+            Ok(())
+        }
+        None => Ok(pager::print_raw(&stream)?),
+    }
+}
+
+/// Lists the files in the working directory that `matches` asks about.
+fn ls_files(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let (others, ignored) = (matches.flag("-o"), matches.flag("-i"));
+    let tracked = matches.flag("-c") || !(others || ignored);
+
+    let files = Repository::open()?.list_files(tracked, others, ignored)?;
+
+    let mut prefixes = Vec::new();
+
+    for path in matches.arguments("path") {
+        prefixes.push(
+            utils::normalize_path(path)
+                .ok_or_else(|| format!("Path {path} is outside of the nest."))?,
+        );
+    }
+
+    let wanted = |file: &String| {
+        prefixes.is_empty()
+            || prefixes.iter().any(|prefix| {
+                prefix.is_empty() || file == prefix || file.starts_with(&format!("{prefix}/"))
+            })
+    };
+
+    // Paths can have newlines in them, so scripts that need to be sure can
+    // ask for zero bytes instead, which paths never have.
+    let terminator = if matches.flag("-z") { '\0' } else { '\n' };
+
+    let output: String = files
+        .iter()
+        .filter(|file| wanted(file))
+        .map(|file| format!("{file}{terminator}"))
+        .collect();
+
+    Ok(pager::print_raw(output.as_bytes())?)
+}
+
+/// Lists the entries of the tree picked out by `matches`.
+fn ls_tree(matches: &Matches) -> Result<String, Box<dyn Error>> {
+    Repository::open()?;
+
+    let mut hash = resolve::resolve_object(matches.required("revision")?)?;
+
+    // Commits and tags stand for the tree of the commit.
+    if objects::read_object(&hash)?.0 != ObjectKind::Tree {
+        hash = objects::read_commit(&objects::peel_to_commit(&hash)?)?.tree;
+    }
+
+    let path = match matches.argument("path") {
+        Some(path) => utils::normalize_path(path)
+            .ok_or_else(|| format!("Path {path} is outside of the nest."))?,
+        None => String::new(),
+    };
+
+    let mut lines = Vec::new();
+    list_tree(
+        &hash,
+        "",
+        &path,
+        matches.flag("-r"),
+        matches.flag("--name-only"),
+        &mut lines,
+    )?;
+
+    Ok(lines.join("\n"))
+}
+
+/// Lists the refs picked out by `matches`, each with the hash it points at.
+fn show_ref(matches: &Matches) -> Result<String, Box<dyn Error>> {
+    Repository::open()?;
+
+    let patterns = matches.arguments("pattern");
+    let (heads, tags) = (matches.flag("--heads"), matches.flag("--tags"));
+
+    let mut names = Vec::new();
+
+    if matches.flag("--head") {
+        names.push(Ref::Head);
+    }
+
+    names.extend(
+        refs::list_all_refs()?
+            .into_iter()
+            .filter(|name| match name {
+                Ref::Branch(_) => heads || !tags,
+                Ref::Tag(_) => tags || !heads,
+                _ => !heads && !tags,
+            }),
+    );
+
+    let mut lines = Vec::new();
+
+    for name in names {
+        let full_name = name.full_name();
+
+        // Like git, a pattern has to match whole parts of the name, so main
+        // matches refs/heads/main but not refs/heads/domain.
+        let matched = patterns.is_empty()
+            || patterns.iter().any(|pattern| {
+                full_name == *pattern || full_name.ends_with(&format!("/{pattern}"))
+            });
+
+        if !matched {
+            continue;
+        }
+
+        // HEAD doesn't point at anything yet if its branch has no commits.
+        let Some(hash) = refs::read_ref(&name)? else {
+            continue;
+        };
+
+        let mut entries = vec![(hash.clone(), full_name.clone())];
+
+        if matches.flag("-d")
+            && matches!(name, Ref::Tag(_))
+            && objects::read_object(&hash)?.0 == ObjectKind::Tag
+        {
+            entries.push((objects::peel_to_commit(&hash)?, format!("{full_name}^{{}}")));
+        }
+
+        for (hash, full_name) in entries {
+            lines.push(match matches.flag("-s") {
+                true => hash,
+                false => format!("{hash} {full_name}"),
+            });
+        }
+    }
+
+    // Like git, finding nothing at all counts as failing, so that scripts can
+    // check whether a ref exists.
+    if lines.is_empty() {
+        Err(match patterns.is_empty() {
+            true => "There aren't any refs yet.".to_string(),
+            false => format!("No refs match {}.", patterns.join(" ")),
+        })?;
+    }
+
+    Ok(lines.join("\n"))
+}
+
+/// Adds a line to `lines` for each entry of the tree `hash`, whose path starts
+/// with `prefix`, that's at or inside `path`, looking inside subtrees if
+/// `recursive` is set.
+fn list_tree(
+    hash: &str,
+    prefix: &str,
+    path: &str,
+    recursive: bool,
+    name_only: bool,
+    lines: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+    for entry in objects::read_tree_entries(hash)? {
+        let entry_path = format!("{prefix}{}", entry.name);
+        let is_tree = entry.kind == ObjectKind::Tree;
+
+        let wanted =
+            path.is_empty() || entry_path == path || entry_path.starts_with(&format!("{path}/"));
+
+        // Directories above the path we're after have to be looked inside
+        // to get there, but aren't listed themselves.
+        if !wanted {
+            if is_tree && path.starts_with(&format!("{entry_path}/")) {
+                list_tree(
+                    &entry.hash,
+                    &format!("{entry_path}/"),
+                    path,
+                    recursive,
+                    name_only,
+                    lines,
+                )?;
+            }
+
+            continue;
+        }
+
+        if is_tree && recursive {
+            list_tree(
+                &entry.hash,
+                &format!("{entry_path}/"),
+                path,
+                recursive,
+                name_only,
+                lines,
+            )?;
+            continue;
+        }
+
+        lines.push(match (name_only, is_tree) {
+            (true, _) => entry_path,
+            (false, true) => format!("040000 tree {}\t{entry_path}", entry.hash),
+            // Like git, another nest is listed as the commit it's pinned to.
+            (false, false) => format!(
+                "{} {} {}\t{entry_path}",
+                entry.mode.git_mode(),
+                match entry.mode {
+                    FileMode::Nest => "commit",
+                    _ => "blob",
+                },
+                entry.hash
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// Switches branches as `matches` asks, making a new one first if need be.
+fn switch(repository: &Repository, matches: &Matches) -> Result<String, Box<dyn Error>> {
+    let target = matches.argument("target");
+
+    let head = match matches.one_of(&["-c", "--detach"])? {
+        Some("-c") => {
+            let name = matches.value("-c").unwrap_or_default();
+            let start = match target {
+                Some(start) => resolve::resolve_revision(start)?,
+                None => refs::resolve_head()?.ok_or(RefError::NoCommits)?,
+            };
+
+            repository.branch(name, &start)?;
+
+            // The new branch isn't any use if we can't switch to it, so we
+            // take it away again rather than leaving it behind.
+            match repository.checkout(name) {
+                Ok(head) => head,
+                Err(e) => {
+                    repository.delete_branch(name, true)?;
+                    Err(e)?
+                }
+            }
+        }
+        Some(_) => {
+            let commit = matches.required("target")?;
+
+            repository.checkout(&resolve::resolve_revision(commit)?)?
+        }
+        None => {
+            let branch = matches.required("target")?;
+
+            // Unlike checkout, switch only ever ends up on a branch, unless
+            // it's been asked to detach.
+            if refs::read_branch(branch)?.is_none() {
+                Err(RefError::NotFound {
+                    kind: "branch",
+                    name: branch.to_string(),
+                })?;
+            }
+
+            repository.checkout(branch)?
+        }
+    };
+
+    Ok(describe_head(head))
+}
+
+/// Describes where HEAD ended up after switching branches or commits.
+fn describe_head(head: Head) -> String {
+    match head {
+        Head::Branch(branch) => format!("Switched to branch {branch}."),
+        Head::Detached(hash) => format!("HEAD is now detached at {}.", resolve::abbreviate(&hash)),
+    }
+}
+
+/// Lists the files a checkout or hard reset would change, for --dry-run.
+fn describe_plan(plan: RestorePlan) -> String {
+    if plan.is_empty() {
+        return "No files would change.".to_string();
+    }
+
+    let lines = [
+        ("Would create", plan.created),
+        ("Would overwrite", plan.overwritten),
+        ("Would remove", plan.removed),
+    ];
+
+    let mut lines: Vec<(String, &str)> = lines
+        .into_iter()
+        .flat_map(|(verb, paths)| paths.into_iter().map(move |path| (path, verb)))
+        .collect();
+
+    lines.sort();
+
+    lines
+        .into_iter()
+        .map(|(path, verb)| format!("{verb} {path}."))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Points rat at the nest and working directory picked with --rat-dir and
+/// --work-tree, or the RAT_DIR and RAT_WORK_TREE environment variables, so
+/// that scripts can use a nest without having to move into it first.
+fn locate_nest(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let pick = |flag, variable| {
+        matches
+            .value(flag)
+            .map(PathBuf::from)
+            .or_else(|| env::var_os(variable).map(PathBuf::from))
+            .filter(|path| !path.as_os_str().is_empty())
+    };
+
+    let nest_dir = pick("--rat-dir", "RAT_DIR");
+    let work_tree = pick("--work-tree", "RAT_WORK_TREE");
+
+    // A relative nest directory is relative to where we started, so we have to
+    // pin it down before moving into the working directory.
+    if let Some(nest_dir) = nest_dir {
+        rat::set_nest_dir(env::current_dir()?.join(nest_dir));
+    }
+
+    if let Some(work_tree) = work_tree {
+        env::set_current_dir(&work_tree).map_err(|e| {
+            format!(
+                "Failed to use {} as the working directory: {e}",
+                work_tree.display()
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Opens the user's editor on the file `file_name` inside the nest, starting
+/// out with `initial` in it, and returns whatever they wrote into it once they
+/// close the editor, without any lines starting with `#`. That leaves us
+/// somewhere to explain things to the user without it ending up in what they
+/// write.
+fn edit_message(file_name: &str, initial: &str) -> Result<String, Box<dyn Error>> {
+    let message_file = nest_path(file_name);
+
+    fs::write(&message_file, initial)?;
+
+    // The user can pick an editor specifically for rat with the core.editor
+    // setting. Otherwise, by convention, the default editor is usually in the
+    // $EDITOR environment variable, but sometimes in $VISUAL. Every copy of
+    // Windows comes with Notepad, so there we can always fall back to that.
+    let editor = Config::load()?
+        .get("core.editor")
+        .map(str::to_string)
+        .or_else(|| env::var("EDITOR").ok())
+        .or_else(|| env::var("VISUAL").ok())
+        .or_else(|| cfg!(windows).then(|| "notepad".to_string()))
+        .ok_or_else(|| "No editor set.".to_string())?;
+
+    // We pass in the special message file to the editor through the Command
+    // interface.
+    let status = editor_command(&editor)?
+        .arg(&message_file)
+        .status()
+        .map_err(|e| format!("Failed to start the editor {editor}: {e}"))?;
+
+    // Quitting an editor with an error, like :cq in vim, is the usual way to
+    // say "never mind", so we don't use whatever is in the file.
+    if !status.success() {
+        let how = match status.code() {
+            Some(code) => format!("exited with code {code}"),
+            None => "was stopped by a signal".to_string(),
+        };
+
+        Err(format!(
+            "The editor {editor} {how}, so nothing was changed."
+        ))?;
+    }
+
+    let message =
+        fs::read_to_string(message_file).map_err(|e| format!("Failed to read message: {e}"))?;
+
+    Ok(strip_comments(&message))
+}
+
+/// Removes every line starting with `#` from `text`, along with any blank
+/// lines that leaves at the start or end.
+fn strip_comments(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+
+    let text = lines.join("\n");
+    let text = text.trim_matches('\n');
+
+    if text.is_empty() {
+        String::new()
+    } else {
+        format!("{text}\n")
+    }
+}
+
+/// What the editor starts out with when writing a commit message, which is
+/// `message` followed by a summary of what's about to be committed, like:
+///
+/// ```text
+///
+/// # Lines starting with # are left out, and an empty message cancels the
+/// # commit.
+/// #
+/// # On branch main
+/// # Changes to be committed:
+/// #     modified:   cheese.txt
+/// ```
+fn commit_template(
+    message: &str,
+    changes: BTreeMap<String, Change>,
+) -> Result<String, Box<dyn Error>> {
+    let branch = match refs::read_head()? {
+        Head::Branch(branch) => format!("On branch {branch}"),
+        Head::Detached(hash) => format!("HEAD detached at {}", resolve::abbreviate(&hash)),
+    };
+
+    // The summary is laid out like the output of rat status, just without the
+    // colours, which would end up in the file as escape codes.
+    let summary = format_changes(changes, TerminalFormat::default(), Color::Green);
+
+    let mut template = format!(
+        "{message}\n\
+         # Lines starting with # are left out, and an empty message cancels the\n\
+         # commit.\n\
+         #\n\
+         # {branch}\n"
+    );
+
+    if summary.is_empty() {
+        template.push_str("# Nothing has changed.\n");
+    } else {
+        template.push_str("# Changes to be committed:\n");
+
+        for line in summary.lines() {
+            template.push_str(&format!("#{line}\n"));
+        }
+    }
+
+    Ok(template)
+}
+
+/// Builds the command that runs `editor`, which can include arguments of its
+/// own, like `code --wait`.
+///
+/// On Windows, editors are often batch files, which can only be run through
+/// `cmd`, so we go through it the same way typing the editor's name at a
+/// prompt would, which also takes care of the arguments. Anywhere else, we
+/// split them up the way a shell would.
+fn editor_command(editor: &str) -> Result<process::Command, Box<dyn Error>> {
+    if cfg!(windows) {
+        let mut command = process::Command::new("cmd");
+        command.arg("/C").arg(editor);
+
+        return Ok(command);
+    }
+
+    let parts = utils::split_command_line(editor)
+        .ok_or_else(|| format!("The editor {editor} has a quote that's never closed."))?;
+    let (program, arguments) = parts
+        .split_first()
+        .ok_or_else(|| "The editor setting is empty.".to_string())?;
+
+    let mut command = process::Command::new(program);
+    command.args(arguments);
+
+    Ok(command)
+}
+
+/// Makes a commit, or replaces the last one with --amend, working out the
+/// message from the flags in `matches` and running any hooks along the way.
+fn commit(repository: &Repository, matches: &Matches) -> Result<String, Box<dyn Error>> {
+    let paths = matches.arguments("path");
+
+    if paths.is_empty() {
+        return commit_staged(repository, matches, None);
+    }
+
+    let entry_paths = paths
+        .iter()
+        .map(|path| {
+            utils::normalize_path(path).ok_or_else(|| format!("{path} is outside the nest."))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Committing particular paths takes them from the working directory, so
+    // they're staged first, which keeps the index in step with the commit. If
+    // the commit isn't made after all, the index goes back to how it was, so
+    // that nothing is left staged that wasn't before.
+    let index_file = nest_path("index");
+    let index = Index::read(&index_file)?;
+
+    repository.add(paths)?;
+
+    commit_staged(repository, matches, Some(&entry_paths)).or_else(|e| {
+        index.write(&index_file)?;
+
+        Err(e)
+    })
+}
+
+/// Does the rest of [`commit`] once any paths given are staged, committing
+/// only `only` if it's set.
+fn commit_staged(
+    repository: &Repository,
+    matches: &Matches,
+    only: Option<&[String]>,
+) -> Result<String, Box<dyn Error>> {
+    let amend = matches.flag("--amend");
+    let allow_empty = matches.flag("--allow-empty");
+
+    // The message isn't looked at until later, but asking for it two ways at
+    // once is a mistake worth pointing out straight away.
+    matches.one_of(&["-m", "-F"])?;
+
+    if matches.flag("-S") {
+        signing::enable();
+    }
+
+    if matches.flag("--no-verify") {
+        hooks::skip_commit_checks();
+    }
+
+    // There's no point writing a message for a commit we're going to refuse
+    // to make.
+    let changes = repository.changes_to_commit(amend, only)?;
+
+    if !allow_empty && changes.is_empty() {
+        Err(CommitError::NothingToCommit)?;
+    }
+
+    // The message is only asked for once the pre-commit hook has passed, so
+    // nobody writes one in their editor for nothing.
+    let message = || Ok(commit_message(matches, amend, changes)?);
+
+    let hash = if amend {
+        repository.amend(message, allow_empty, only)?
+    } else {
+        repository.commit(message, allow_empty, only)?
+    };
+
+    Ok(format!("Created commit {}.", resolve::abbreviate(&hash)))
+}
+
+/// Works out the message for [`commit`] from `matches`, asking for it in the
+/// editor if it wasn't given, which starts out listing `changes`, along with
+/// the message being replaced when we're going to `amend`.
+fn commit_message(
+    matches: &Matches,
+    amend: bool,
+    changes: BTreeMap<String, Change>,
+) -> Result<String, Box<dyn Error>> {
+    // The user can specify the commit message either through the -m option in
+    // the command itself, where each one is a paragraph, or in a file with -F.
+    let message = match (matches.one_of(&["-m", "-F"])?, matches.value("-F")) {
+        (Some("-m"), _) => {
+            let paragraphs: Vec<&str> = matches.values("-m").collect();
+
+            format!("{}\n", paragraphs.join("\n\n"))
+        }
+        (_, Some("-")) => io::read_to_string(io::stdin())?,
+        (_, Some(file)) => fs::read_to_string(file)
+            .map_err(|e| format!("Failed to read message from {file}: {e}"))?,
+        // Otherwise, we open their editor to a special file and use the
+        // contents of that file as the commit message instead. When amending,
+        // it starts out with the message being replaced.
+        (_, None) => {
+            let old_message = if amend {
+                let head = refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+
+                objects::read_commit(&head)?.message
+            } else {
+                String::new()
+            };
+
+            edit_message("COMMIT_EDITMSG", &commit_template(&old_message, changes)?)?
+        }
+    };
+
+    if message.trim().is_empty() {
+        Err("Cancelled commit.")?;
+    }
+
+    Ok(message)
+}
+
+/// Lists, creates, deletes or renames branches, depending on the flags in
+/// `matches`. Branches are listed as JSON if `json` is set, and otherwise
+/// formatted with `format`.
+fn branch(
+    repository: &Repository,
+    matches: &Matches,
+    json: bool,
+    format: TerminalFormat,
+) -> Result<String, Box<dyn Error>> {
+    match matches.one_of(&["-d", "-D", "-m"])? {
+        Some(flag @ ("-d" | "-D")) => {
+            let [name] = matches.positional() else {
+                Err(matches.error(format!("{flag} needs the name of a branch.")))?
+            };
+
+            // -D is the forceful version of -d, which deletes the branch even
+            // if that would lose commits.
+            let hash = repository.delete_branch(name, flag == "-D")?;
+
+            Ok(format!(
+                "Deleted branch {name} (was {}).",
+                resolve::abbreviate(&hash)
+            ))
+        }
+        Some(_) => {
+            let [old_name, new_name] = matches.positional() else {
+                Err(matches.error("-m needs the old and new names of a branch."))?
+            };
+
+            repository.rename_branch(old_name, new_name)?;
+
+            Ok(format!("Renamed branch {old_name} to {new_name}."))
+        }
+        None => {
+            // With no arguments at all, we just list the branches.
+            let Some(name) = matches.argument("name") else {
+                return list_branches(repository, json, format);
+            };
+
+            // Most of the time you want a new branch to start from wherever
+            // you are right now, so the commit is optional and defaults to
+            // whatever HEAD resolves to.
+            let commit_hash = match matches.argument("commit") {
+                Some(revision) => resolve::resolve_revision(revision)?,
+                None => refs::resolve_head()?.ok_or_else(|| {
+                    "There are no commits yet to create a branch from.".to_string()
+                })?,
+            };
+
+            repository.branch(name, &commit_hash)?;
+
+            Ok(format!(
+                "Created branch {name} at {}.",
+                resolve::abbreviate(&commit_hash)
+            ))
+        }
+    }
+}
+
+/// Lists every branch, one per line, with an asterisk next to the current one,
+/// or as JSON if `json` is set.
+fn list_branches(
+    repository: &Repository,
+    json: bool,
+    format: TerminalFormat,
+) -> Result<String, Box<dyn Error>> {
+    let branches = repository.branches()?;
+
+    if json {
+        let branches: Json = branches
+            .into_iter()
+            .map(|branch| {
+                Json::object([
+                    ("name", branch.name.into()),
+                    ("commit", branch.commit.into()),
+                    ("current", branch.current.into()),
+                ])
+            })
+            .collect();
+
+        return Ok(branches.to_string());
+    }
+
+    let lines = branches
+        .into_iter()
+        .map(|branch| {
+            // The current branch is painted green, as well as being marked,
+            // so that it stands out in a long list.
+            if branch.current {
+                format!("* {}", format.paint(&branch.name, Color::Green))
+            } else {
+                format!("  {}", branch.name)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(lines.join("\n"))
+}
+
+/// Lists, creates or deletes tags, depending on the flags in `matches`. Tags
+/// are listed as JSON if `json` is set.
+///
+/// A tag made with just a name is lightweight, which means it's just a ref
+/// pointing at the commit. With `-a` or `-m`, it's annotated instead, which
+/// also records who made it and a message. If `-a` is given without `-m`, the
+/// message is written in the user's editor, just like for `commit`.
+fn tag(repository: &Repository, matches: &Matches, json: bool) -> Result<String, Box<dyn Error>> {
+    if matches.flag("-d") {
+        let [name] = matches.positional() else {
+            Err(matches.error("-d needs the name of a tag."))?
+        };
+
+        let hash = repository.delete_tag(name)?;
+
+        return Ok(format!(
+            "Deleted tag {name} (was {}).",
+            resolve::abbreviate(&hash)
+        ));
+    }
+
+    // Only a tag object has somewhere to put a signature, so signing makes
+    // the tag annotated too.
+    let sign = matches.flag("-s");
+    let annotate = matches.flag("-a") || sign;
+    let mut message = matches.value("-m").map(str::to_string);
+
+    let Some(name) = matches.argument("name") else {
+        if annotate || message.is_some() {
+            Err(matches.error("Missing <name>."))?;
+        }
+
+        let tags = repository.tags()?;
+
+        if json {
+            let tags: Json = tags
+                .into_iter()
+                .map(|tag| {
+                    Json::object([
+                        ("name", tag.name.into()),
+                        ("commit", tag.commit.into()),
+                        (
+                            "tagger",
+                            tag.annotation
+                                .as_ref()
+                                .map(|a| signature_json(&a.tagger))
+                                .into(),
+                        ),
+                        ("message", tag.annotation.map(|a| a.message).into()),
+                    ])
+                })
+                .collect();
+
+            return Ok(tags.to_string());
+        }
+
+        return Ok(tags
+            .into_iter()
+            .map(|tag| tag.name)
+            .collect::<Vec<_>>()
+            .join("\n"));
+    };
+
+    let commit_hash = match matches.argument("commit") {
+        Some(revision) => resolve::resolve_revision(revision)?,
+        None => {
+            refs::resolve_head()?.ok_or_else(|| "There are no commits yet to tag.".to_string())?
+        }
+    };
+
+    // Like git, giving a message is enough to make the tag annotated, since a
+    // lightweight tag has nowhere to keep it.
+    if annotate && message.is_none() {
+        message = Some(edit_message("TAG_EDITMSG", "")?);
+    }
+
+    if message
+        .as_ref()
+        .is_some_and(|message| message.trim().is_empty())
+    {
+        Err("Cancelled tag.")?;
+    }
+
+    repository.create_tag(name, &commit_hash, message.as_deref(), sign)?;
+
+    Ok(format!(
+        "Created tag {name} at {}.",
+        resolve::abbreviate(&commit_hash)
+    ))
+}
+
+/// Turns the outcome of a merge into something to show the user.
+fn describe_merge(outcome: MergeOutcome) -> String {
+    match outcome {
+        MergeOutcome::UpToDate => "Already up to date.".to_string(),
+        MergeOutcome::FastForward(hash) => {
+            format!("Fast-forwarded to {}.", resolve::abbreviate(&hash))
+        }
+        MergeOutcome::Merged(hash) => {
+            format!("Created merge commit {}.", resolve::abbreviate(&hash))
+        }
+    }
+}
+
+/// Works out whether a merge is allowed to fast-forward from the flags in
+/// `matches`.
+fn fast_forward_mode(matches: &Matches) -> Result<FastForwardMode, UsageError> {
+    Ok(match matches.one_of(&["--ff-only", "--no-ff"])? {
+        Some("--ff-only") => FastForwardMode::Only,
+        Some(_) => FastForwardMode::Never,
+        None => FastForwardMode::Allowed,
+    })
+}
+
+/// Describes what happened when rebasing, for showing to the user.
+fn describe_rebase(outcome: RebaseOutcome) -> String {
+    match outcome {
+        RebaseOutcome::UpToDate => "Already up to date.".to_string(),
+        RebaseOutcome::FastForward(hash) => {
+            format!("Fast-forwarded to {}.", resolve::abbreviate(&hash))
+        }
+        RebaseOutcome::Rebased(hash) => {
+            format!("Rebased. HEAD is now at {}.", resolve::abbreviate(&hash))
+        }
+    }
+}
+
+/// Lists every commit reachable from the revision in `matches`, or HEAD if
+/// it's not given, newest first, or as JSON if `json` is set. With `--all`,
+/// every branch and tag is included instead.
+fn log(
+    repository: &Repository,
+    matches: &Matches,
+    json: bool,
+    format: TerminalFormat,
+) -> Result<String, Box<dyn Error>> {
+    let revision = matches.argument("revision");
+    let filter = log_filter(matches)?;
+    let log_format = log_format(matches)?;
+    let decorations = repository.decorations()?;
+
+    let history = if matches.flag("--all") {
+        if revision.is_some() {
+            Err(matches.error("--all and <revision> can't be used together."))?;
+        }
+
+        repository.log_all()?
+    } else {
+        repository.log_iter(revision)?
+    };
+
+    let notes = notes::read_all()?;
+
+    // Checking a signature means running ssh-keygen, so we only do it when
+    // it's going to be shown.
+    let check_signatures = matches.flag("--show-signature") || log_format.shows_signatures();
+    let check_signature = |metadata: &CommitMetadata| match check_signatures {
+        true => signing::verify(metadata).map(Some),
+        false => Ok(None),
+    };
+
+    let files = matches.one_of(&["--stat", "--name-status"])?;
+
+    let render = |hash: &String, metadata: &CommitMetadata| -> Result<String, Box<dyn Error>> {
+        let commit = Commit {
+            hash,
+            metadata,
+            decorations: decorations.get(hash).map_or(&[], Vec::as_slice),
+            note: notes.get(hash).map(String::as_str),
+            signature: check_signature(metadata)?,
+        };
+
+        let text = log_format.render(&commit, format);
+
+        // Each commit is only compared with its parent once it's about to be
+        // shown, so that commits that are filtered out, or past -n, cost
+        // nothing.
+        let Some(files) = files else {
+            return Ok(text);
+        };
+
+        let patches = commit_patches(hash, metadata)?;
+
+        if patches.is_empty() {
+            return Ok(text);
+        }
+
+        let listing = match files {
+            "--stat" => format_stat(&patches, format),
+            _ => format_name_status(&patches),
+        };
+
+        Ok(match log_format {
+            LogFormat::Medium => format!("{}\n\n{listing}", text.trim_end()),
+            _ => format!("{}\n{listing}", text.trim_end()),
+        })
+    };
+
+    if matches.flag("--graph") && !json {
+        let mut graph = Graph::default();
+        let mut rows = Vec::new();
+
+        // Commits that are filtered out aren't drawn, so the ones that are
+        // get connected to each other instead.
+        for (hash, metadata, parents) in filter.simplify(history)? {
+            // Full commits need a blank line after them, which the graph is
+            // drawn next to as well.
+            let mut text = render(&hash, &metadata)?;
+
+            if log_format == LogFormat::Medium {
+                text.push('\n');
+            }
+
+            rows.push(graph.draw(&hash, &parents, &text));
+        }
+
+        return Ok(rows.join("\n").trim_end().to_string());
+    }
+
+    let commits = filter.apply(history);
+
+    if json {
+        let commits = commits
+            .map(|commit| {
+                let (hash, metadata) = commit?;
+                let labels: Json = decorations
+                    .get(&hash)
+                    .into_iter()
+                    .flatten()
+                    .map(|label| label.to_string())
+                    .collect();
+                let note = notes.get(&hash).cloned();
+                let signature = check_signature(&metadata)?.map(|status| status.to_string());
+
+                Ok(Json::object([
+                    ("hash", hash.into()),
+                    ("tree", metadata.tree.into()),
+                    ("parents", metadata.parents.into()),
+                    ("author", signature_json(&metadata.author)),
+                    ("committer", signature_json(&metadata.committer)),
+                    ("message", metadata.message.into()),
+                    ("note", note.into()),
+                    ("signature", signature.into()),
+                    ("refs", labels),
+                ]))
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        return Ok(Json::Array(commits).to_string());
+    }
+
+    // We collect each commit's entry and join them up at the end, so the
+    // separators only go between commits and not after the last one.
+    let entries = commits
+        .map(|commit| {
+            let (hash, metadata) = commit?;
+
+            render(&hash, &metadata)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Commits with their files listed after them take up several lines, even
+    // on one line each, so they get a blank line between them to tell them
+    // apart.
+    let separator = match files {
+        Some(_) => "\n\n",
+        None => log_format.separator(),
+    };
+
+    Ok(entries.join(separator))
+}
+
+/// Groups the commits in the history picked out by `matches` by author,
+/// with either the subject of each one or just how many there are.
+fn shortlog(repository: &Repository, matches: &Matches) -> Result<String, Box<dyn Error>> {
+    let revision = matches.argument("revision");
+
+    let history = if matches.flag("--all") {
+        if revision.is_some() {
+            Err(matches.error("--all and <revision> can't be used together."))?;
+        }
+
+        repository.log_all()?
+    } else {
+        repository.log_iter(revision)?
+    };
+
+    // Each author's subjects are kept in the order we find them, which is
+    // newest first, and turned around when they're shown.
+    let mut authors: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for commit in history {
+        let (_, metadata) = commit?;
+        let author = match matches.flag("--email") {
+            true => format!("{} <{}>", metadata.author.name, metadata.author.email),
+            false => metadata.author.name.clone(),
+        };
+        let subject = metadata.message.lines().next().unwrap_or("").to_string();
+
+        authors.entry(author).or_default().push(subject);
+    }
+
+    let mut authors: Vec<(String, Vec<String>)> = authors.into_iter().collect();
+
+    // Sorting is stable, so authors with as many commits as each other stay
+    // in order by name.
+    if matches.flag("--numbered") {
+        authors.sort_by_key(|(_, subjects)| Reverse(subjects.len()));
+    }
+
+    if matches.flag("--summary") {
+        return Ok(authors
+            .into_iter()
+            .map(|(author, subjects)| format!("{:>6}\t{author}", subjects.len()))
+            .collect::<Vec<_>>()
+            .join("\n"));
+    }
+
+    Ok(authors
+        .into_iter()
+        .map(|(author, subjects)| {
+            let count = subjects.len();
+            let subjects: String = subjects
+                .iter()
+                .rev()
+                .map(|subject| format!("      {subject}\n"))
+                .collect();
+
+            format!("{author} ({count}):\n{subjects}")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string())
+}
+
+/// Shows the commit picked out by `matches` with everything it changed,
+/// after the tag itself if it's an annotated tag.
+fn show(
+    repository: &Repository,
+    matches: &Matches,
+    format: TerminalFormat,
+) -> Result<String, Box<dyn Error>> {
+    let revision = matches.argument("revision").unwrap_or("HEAD");
+    let hash = resolve::resolve_revision(revision)?;
+    let mut sections = Vec::new();
+
+    if let Some(tag_hash) = refs::read_tag(revision)? {
+        if objects::read_object(&tag_hash)?.0 == ObjectKind::Tag {
+            let tag = objects::read_tag(&tag_hash)?;
+
+            sections.push(format!(
+                "{}\nTagger: {} <{}>\nDate:   {}\n\n{}",
+                format.paint(&format!("tag {}", tag.name), Color::Yellow),
+                tag.tagger.name,
+                tag.tagger.email,
+                tag.tagger.format_date(),
+                tag.message.trim_end()
+            ));
+        }
+    }
+
+    let metadata = objects::read_commit(&hash)?;
+    let decorations = repository.decorations()?;
+    let note = notes::read(&hash)?;
+
+    let commit = Commit {
+        hash: &hash,
+        metadata: &metadata,
+        decorations: decorations.get(&hash).map_or(&[], Vec::as_slice),
+        note: note.as_deref(),
+        signature: None,
+    };
+
+    sections.push(
+        LogFormat::Medium
+            .render(&commit, format)
+            .trim_end()
+            .to_string(),
+    );
+
+    let patches = commit_patches(&hash, &metadata)?;
+
+    if !patches.is_empty() {
+        sections.push(format_patches(patches, format));
+    }
+
+    Ok(sections.join("\n\n"))
+}
+
+/// Works out what the commit `hash` changed compared to its parent. A merge
+/// has more than one parent to compare with, so we leave those out and give
+/// nothing for them.
+fn commit_patches(hash: &str, metadata: &CommitMetadata) -> Result<Vec<FilePatch>, Box<dyn Error>> {
+    if metadata.parents.len() > 1 {
+        return Ok(Vec::new());
+    }
+
+    let old = compare::read_commit(metadata.parents.first().map(String::as_str))?;
+    let new = compare::read_commit(Some(hash))?;
+
+    patch::diff_snapshots(&old, &new, false, Detection::Renames, &BTreeMap::new())
+}
+
+/// How wide the bars of pluses and minuses can get in `--stat`, so that one
+/// big change doesn't push the rest off the screen.
+const STAT_BAR_WIDTH: usize = 40;
+
+/// Describes how many lines were added and removed in each of `patches`, with
+/// a bar of pluses and minuses to make them easy to compare, and a total at
+/// the end.
+fn format_stat(patches: &[FilePatch], format: TerminalFormat) -> String {
+    let counts: Vec<(String, Option<(usize, usize)>)> = patches
+        .iter()
+        .map(|patch| {
+            let name = match &patch.change {
+                Change::Renamed { from, .. } | Change::Copied { from, .. } => {
+                    format!("{from} => {}", patch.path)
+                }
+                _ => patch.path.clone(),
+            };
+
+            // Binary files don't have lines to count.
+            let counts = match &patch.content {
+                PatchContent::Binary => None,
+                PatchContent::Text(hunks) => {
+                    let lines = hunks.iter().flat_map(|hunk| &hunk.lines);
+                    let count = |wanted: fn(&PatchLine) -> bool| {
+                        lines.clone().filter(|line| wanted(line)).count()
+                    };
+
+                    Some((
+                        count(|line| matches!(line, PatchLine::Added(_))),
+                        count(|line| matches!(line, PatchLine::Removed(_))),
+                    ))
+                }
+            };
+
+            (name, counts)
+        })
+        .collect();
+
+    let name_width = counts
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let most = counts
+        .iter()
+        .filter_map(|(_, counts)| counts.map(|(added, removed)| added + removed))
+        .max()
+        .unwrap_or(0);
+    let count_width = most.to_string().len();
+
+    let (mut insertions, mut deletions) = (0, 0);
+    let mut lines = Vec::new();
+
+    for (name, counts) in &counts {
+        let Some((added, removed)) = *counts else {
+            lines.push(format!(" {name:<name_width$} | {:>count_width$}", "Bin"));
+            continue;
+        };
+
+        insertions += added;
+        deletions += removed;
+
+        // Bars are only scaled down when the biggest one wouldn't fit, and
+        // never so far that a change disappears altogether.
+        let scale = |count: usize| match most > STAT_BAR_WIDTH {
+            true if count > 0 => (count * STAT_BAR_WIDTH / most).max(1),
+            true => 0,
+            false => count,
+        };
+
+        // Painting nothing would still leave color codes behind, so empty
+        // bars are left empty.
+        let bar = |count: usize, symbol: &str, color: Color| match scale(count) {
+            0 => String::new(),
+            width => format.paint(&symbol.repeat(width), color),
+        };
+
+        let line = format!(
+            " {name:<name_width$} | {:>count_width$} {}{}",
+            added + removed,
+            bar(added, "+", Color::Green),
+            bar(removed, "-", Color::Red)
+        );
+
+        lines.push(line.trim_end().to_string());
+    }
+
+    let plural = |count: usize, word: &str| match count {
+        1 => format!("{count} {word}"),
+        _ => format!("{count} {word}s"),
+    };
+
+    lines.push(format!(
+        " {} changed, {}(+), {}(-)",
+        plural(patches.len(), "file"),
+        plural(insertions, "insertion"),
+        plural(deletions, "deletion")
+    ));
+
+    lines.join("\n")
+}
+
+/// Lists each of `patches` with a letter for how the file changed: A for
+/// added, M for modified, D for deleted, R for renamed and C for copied. Like
+/// git, R and C are followed by how alike the two files are as a percentage,
+/// like R086, and the file is listed with where it came from first.
+fn format_name_status(patches: &[FilePatch]) -> String {
+    patches
+        .iter()
+        .map(|patch| match &patch.change {
+            Change::Added => format!("A\t{}", patch.path),
+            Change::Modified => format!("M\t{}", patch.path),
+            Change::Deleted => format!("D\t{}", patch.path),
+            Change::Renamed { from, similarity } => {
+                format!("R{similarity:03}\t{from}\t{}", patch.path)
+            }
+            Change::Copied { from, similarity } => {
+                format!("C{similarity:03}\t{from}\t{}", patch.path)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Checks the signature on the commit picked out by `matches`, failing
+/// unless it's good.
+fn verify_commit(matches: &Matches) -> Result<String, Box<dyn Error>> {
+    let revision = matches.argument("revision").unwrap_or("HEAD");
+    let hash = resolve::resolve_revision(revision)?;
+    let metadata = objects::read_commit(&hash)?;
+    let abbreviated = resolve::abbreviate(&hash);
+
+    match signing::verify(&metadata)? {
+        SignatureStatus::Good => Ok(format!(
+            "Good signature on {abbreviated} from {} <{}>.",
+            metadata.committer.name, metadata.committer.email
+        )),
+        SignatureStatus::Bad => Err(format!(
+            "Bad signature on {abbreviated}. Either it was changed after it was signed, or \
+             it wasn't signed with a key allowed for {}.",
+            metadata.committer.email
+        ))?,
+        SignatureStatus::Missing => Err(format!("Commit {abbreviated} isn't signed."))?,
+    }
+}
+
+/// Checks the signature on the tag named in `matches`, failing unless it's
+/// good.
+fn verify_tag(matches: &Matches) -> Result<String, Box<dyn Error>> {
+    let name = matches.required("tag")?;
+    let hash = refs::read_tag(name)?.ok_or_else(|| RefError::NotFound {
+        kind: "tag",
+        name: name.to_string(),
+    })?;
+
+    let (kind, data) = objects::read_object(&hash)?;
+
+    if kind != ObjectKind::Tag {
+        Err(format!(
+            "Tag {name} isn't annotated, so it can't have a signature."
+        ))?;
+    }
+
+    let metadata = TagMetadata::parse(&String::from_utf8(data)?)?;
+
+    match signing::verify_tag(&metadata)? {
+        SignatureStatus::Good => Ok(format!(
+            "Good signature on tag {name} from {} <{}>.",
+            metadata.tagger.name, metadata.tagger.email
+        )),
+        SignatureStatus::Bad => Err(format!(
+            "Bad signature on tag {name}. Either it was changed after it was signed, or it \
+             wasn't signed with a key allowed for {}.",
+            metadata.tagger.email
+        ))?,
+        SignatureStatus::Missing => Err(format!("Tag {name} isn't signed."))?,
+    }
+}
+
+/// Lists, adds, shows or removes notes, depending on the action in
+/// `matches`.
+fn notes(matches: &Matches) -> Result<String, Box<dyn Error>> {
+    let action = matches.argument("action").unwrap_or("list");
+    let commit = || resolve::resolve_revision(matches.argument("commit").unwrap_or("HEAD"));
+
+    if action != "add" && (matches.flag("-m") || matches.flag("-f")) {
+        Err(matches.error(format!("-m and -f only go with add, not {action}.")))?;
+    }
+
+    match action {
+        "list" => {
+            if let Some(commit) = matches.argument("commit") {
+                Err(matches.error(format!("Unexpected argument {commit}.")))?;
+            }
+
+            let mut commits: Vec<String> = notes::read_all()?.into_keys().collect();
+            commits.sort();
+
+            Ok(commits.join("\n"))
+        }
+        "add" => {
+            let commit = commit()?;
+            let abbreviated_hash = resolve::abbreviate(&commit);
+
+            let text = if matches.flag("-m") {
+                matches.values("-m").collect::<Vec<_>>().join("\n\n")
+            } else {
+                let old_note = notes::read(&commit)?.unwrap_or_default();
+
+                edit_message(
+                    "NOTES_EDITMSG",
+                    &format!(
+                        "{old_note}\n# Write the note for commit {abbreviated_hash}. Lines \
+                         starting with # will be left out,\n# and an empty note leaves the \
+                         commit as it was.\n"
+                    ),
+                )?
+            };
+
+            if text.trim().is_empty() {
+                Err("Cancelled adding the note, since it's empty.")?;
+            }
+
+            notes::add(&commit, &text, matches.flag("-f"))?;
+
+            Ok(format!("Added a note to {abbreviated_hash}."))
+        }
+        "show" => {
+            let commit = commit()?;
+
+            notes::read(&commit)?
+                .map(|note| note.trim_end().to_string())
+                .ok_or_else(|| {
+                    format!(
+                        "Commit {} doesn't have a note.",
+                        resolve::abbreviate(&commit)
+                    )
+                    .into()
+                })
+        }
+        "remove" => {
+            let commit = commit()?;
+            let abbreviated_hash = resolve::abbreviate(&commit);
+
+            if !notes::remove(&commit)? {
+                Err(format!("Commit {abbreviated_hash} doesn't have a note."))?;
+            }
+
+            Ok(format!("Removed the note from {abbreviated_hash}."))
+        }
+        _ => Err(matches.error(format!("Unknown action {action}.")))?,
+    }
+}
+
+/// Shows which commit each line of the file in `matches` came from, or as
+/// JSON if `json` is set.
+fn blame(
+    repository: &Repository,
+    matches: &Matches,
+    json: bool,
+    format: TerminalFormat,
+) -> Result<String, Box<dyn Error>> {
+    let path = matches.required("path")?;
+    let path = utils::normalize_path(path)
+        .ok_or_else(|| matches.error(format!("{path} is outside of the nest.")))?;
+
+    let lines = blame::blame(repository.log_iter(matches.argument("revision"))?, &path)?;
+
+    if json {
+        let lines: Json = lines
+            .into_iter()
+            .map(|line| {
+                Json::object([
+                    ("commit", line.commit.into()),
+                    ("author", signature_json(&line.author)),
+                    ("original_line", line.original_line.into()),
+                    ("text", line.text.into()),
+                ])
+            })
+            .collect();
+
+        return Ok(lines.to_string());
+    }
+
+    // Everything before the text is lined up in columns, so that the text
+    // itself lines up too.
+    let name_width = lines
+        .iter()
+        .map(|line| line.author.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let number_width = lines.len().to_string().len();
+
+    // The same few commits usually come up over and over, and abbreviating a
+    // hash means looking through the objects, so we only do it once each.
+    let mut abbreviations = HashMap::new();
+
+    let output = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let abbreviation = abbreviations
+                .entry(&line.commit)
+                .or_insert_with(|| resolve::abbreviate(&line.commit));
+
+            format!(
+                "{} ({:name_width$} {} {:>number_width$}) {}",
+                format.paint(abbreviation, Color::Yellow),
+                line.author.name,
+                utils::format_short_date(line.author.timestamp, line.author.utc_offset),
+                i + 1,
+                line.text
+            )
+        })
+        .collect::<Vec<_>>();
+
+    Ok(output.join("\n"))
+}
+
+/// Searches files for the pattern in `matches`, showing each line that
+/// matches as `path:number:line`, with the matches themselves highlighted.
+fn grep(matches: &Matches, format: TerminalFormat) -> Result<String, Box<dyn Error>> {
+    let regex = Regex::new(matches.required("pattern")?, matches.flag("-i"))?;
+
+    let found = match matches.argument("revision") {
+        Some(revision) => {
+            if matches.flag("--untracked") {
+                Err(matches.error("--untracked and <revision> can't be used together."))?;
+            }
+
+            grep::search_commit(&regex, &resolve::resolve_revision(revision)?)?
+        }
+        None => grep::search_working_directory(&regex, matches.flag("--untracked"))?,
+    };
+
+    if matches.flag("-l") {
+        let paths: BTreeSet<&str> = found
+            .iter()
+            .map(|found| match found {
+                GrepMatch::Line { path, .. } | GrepMatch::Binary { path } => path.as_str(),
+            })
+            .collect();
+
+        return Ok(paths
+            .into_iter()
+            .map(|path| format.paint(path, Color::Magenta))
+            .collect::<Vec<_>>()
+            .join("\n"));
+    }
+
+    let lines = found
+        .iter()
+        .map(|found| match found {
+            GrepMatch::Line {
+                path,
+                number,
+                text,
+                matches,
+            } => {
+                // The matches are painted one by one, with the text between
+                // them left as it is.
+                let mut line = String::new();
+                let mut end_of_last = 0;
+
+                for &(start, end) in matches {
+                    line.push_str(&text[end_of_last..start]);
+                    line.push_str(&format.paint(&text[start..end], Color::Red));
+                    end_of_last = end;
+                }
+
+                line.push_str(&text[end_of_last..]);
+
+                format!(
+                    "{}:{}:{line}",
+                    format.paint(path, Color::Magenta),
+                    format.paint(&number.to_string(), Color::Green)
+                )
+            }
+            GrepMatch::Binary { path } => format!("Binary file {path} matches"),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(lines.join("\n"))
+}
+
+/// Lists the moves in the reflog of the ref `rat reflog` was asked about,
+/// newest first, like `abc1234 HEAD@{0}: commit: Add the cheese`.
+fn reflog(matches: &Matches, format: TerminalFormat) -> Result<String, Box<dyn Error>> {
+    let name = matches.argument("ref").unwrap_or("HEAD");
+    let ref_name = refs::reflog_ref_name(name).ok_or_else(|| RefError::InvalidName {
+        kind: "ref",
+        name: name.to_string(),
+    })?;
+
+    let lines: Vec<String> = refs::read_reflog(&ref_name)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            format!(
+                "{} {name}@{{{i}}}: {}",
+                format.paint(&resolve::abbreviate(&entry.new), Color::Yellow),
+                entry.message
+            )
+        })
+        .collect();
+
+    Ok(lines.join("\n"))
+}
+
+/// Works out which commits `rat log` should show from the options in
+/// `matches`.
+fn log_filter(matches: &Matches) -> Result<LogFilter, Box<dyn Error>> {
+    let max_count = match matches.value("-n") {
+        Some(count) => Some(
+            count
+                .parse()
+                .map_err(|_| matches.error(format!("Invalid count {count}.")))?,
+        ),
+        None => None,
+    };
+
+    let now = Signature::now(String::new(), String::new());
+    let date = |flag| match matches.value(flag) {
+        Some(date) => utils::parse_date(date, now.timestamp, now.utc_offset)
+            .map(Some)
+            .ok_or_else(|| matches.error(format!("Invalid date {date}."))),
+        None => Ok(None),
+    };
+
+    Ok(LogFilter {
+        max_count,
+        author: matches.value("--author").map(str::to_string),
+        grep: matches.value("--grep").map(str::to_string),
+        since: date("--since")?,
+        until: date("--until")?,
+        path: match matches.value("--follow") {
+            Some(path) => Some(
+                utils::normalize_path(path)
+                    .ok_or_else(|| matches.error(format!("{path} is outside of the nest.")))?,
+            ),
+            None => None,
+        },
+    })
+}
+
+/// Works out how `rat log` should show each commit from the options in
+/// `matches`.
+fn log_format(matches: &Matches) -> Result<LogFormat, Box<dyn Error>> {
+    if let Some(template) = matches.value("--format") {
+        if matches.flag("--oneline") {
+            Err(matches.error("--oneline and --format can't be used together."))?;
+        }
+
+        return Ok(template.parse().map_err(|e| matches.error(e))?);
+    }
+
+    Ok(if matches.flag("--oneline") {
+        LogFormat::Oneline
+    } else {
+        LogFormat::Medium
+    })
+}
+
+/// Reads or changes settings, depending on the flags in `matches`:
+///
+/// - `--list` shows every setting from every config file.
+/// - `--get <key>` shows the value of a single setting.
+/// - `--set <key> <value>` changes a setting.
+///
+/// Changes are made to the nest's config file, unless `--global` is given,
+/// in which case they're made to the user's config file instead.
+fn config(matches: &Matches) -> Result<String, Box<dyn Error>> {
+    let global = matches.flag("--global");
+
+    let path = if global {
+        config::user_config_path().ok_or("Couldn't find your home directory.")?
+    } else {
+        config::nest_config_path()
+    };
+
+    // With --global, we only want to look at the user's settings, but
+    // otherwise we show everything that applies to the nest.
+    let settings = if global {
+        Config::from_files(vec![ConfigFile::read(&path)?])
+    } else {
+        Config::load()?
+    };
+
+    // We convert the arguments into string slices so we can match on them.
+    let arguments: Vec<&str> = matches.positional().iter().map(String::as_str).collect();
+
+    match (
+        matches.one_of(&["--list", "--get", "--set"])?,
+        &arguments[..],
+    ) {
+        (Some("--list"), []) => Ok(settings
+            .entries()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join("\n")),
+        (Some("--get"), [key]) => Ok(settings
+            .get(key)
+            .ok_or_else(|| format!("Setting {key} isn't set."))?
+            .to_string()),
+        (Some("--set"), [key, value]) => {
+            let mut file = ConfigFile::read(&path)?;
+            file.set(key, value)?;
+            file.write(&path)?;
+
+            Ok(format!("Set {key} to {value}."))
+        }
+        (Some(flag), _) => Err(matches.error(format!("Wrong number of arguments for {flag}.")))?,
+        (None, _) => Err(matches.error("One of --list, --get or --set is needed."))?,
+    }
+}
+
+/// Shows how the files picked out by `matches` changed, line by line.
+fn diff(
+    repository: &Repository,
+    matches: &Matches,
+    format: TerminalFormat,
+) -> Result<String, Box<dyn Error>> {
+    let mut base = String::new();
+    let (old, new) = diff_sides(repository, matches, &mut base)?;
+
+    let detection = match matches.one_of(&["--no-renames", "-C"])? {
+        Some("--no-renames") => Detection::Off,
+        Some(_) => Detection::Copies,
+        None => Detection::Renames,
+    };
+
+    Ok(format_patches(
+        repository.diff(old, new, detection)?,
+        format,
+    ))
+}
+
+/// The answers to the question `rat add -p` asks about each hunk.
+const PATCH_HELP: &str = "y - stage this hunk
+n - don't stage this hunk
+a - stage this hunk and the rest of the hunks in this file
+d - don't stage this hunk or any of the rest in this file
+s - split this hunk into smaller ones
+q - stop here, staging only what's been picked so far
+? - show this help";
+
+/// Asks about each unstaged hunk of the files picked out by `matches`, and
+/// stages the ones the user says yes to.
+fn add_patch(
+    repository: &Repository,
+    matches: &Matches,
+    format: TerminalFormat,
+) -> Result<String, Box<dyn Error>> {
+    let patches = repository.unstaged_patches(matches.arguments("path"))?;
+
+    if patches.is_empty() {
+        return Ok("No changes to stage.".to_string());
+    }
+
+    let mut stdout = io::stdout();
+    let mut answers = io::stdin().lines();
+    let mut staged = 0;
+
+    for patch in patches {
+        let PatchContent::Text(hunks) = patch.content else {
+            continue;
+        };
+
+        writeln!(
+            stdout,
+            "{}",
+            format.paint(&format!("diff --git a/{0} b/{0}", patch.path), Color::Cyan)
+        )?;
+
+        let mut remaining: VecDeque<PatchHunk> = hunks.into();
+        let mut chosen = Vec::new();
+        let mut quit = false;
+
+        while let Some(hunk) = remaining.pop_front() {
+            let pieces = hunk.split();
+
+            writeln!(stdout, "{}", format_hunk(&hunk, format).join("\n"))?;
+
+            let options = match pieces.len() {
+                1 => "y,n,a,d,q,?",
+                _ => "y,n,a,d,s,q,?",
+            };
+            write!(stdout, "Stage this hunk [{options}]? ")?;
+            stdout.flush()?;
+
+            // Running out of answers is the same as being told to stop.
+            let answer = match answers.next() {
+                Some(answer) => answer?,
+                None => "q".to_string(),
+            };
+
+            match answer.trim() {
+                "y" => chosen.push(hunk),
+                "n" => {}
+                "a" => {
+                    chosen.push(hunk);
+                    chosen.extend(remaining.drain(..));
+                }
+                "d" => remaining.clear(),
+                "s" if pieces.len() > 1 => {
+                    writeln!(stdout, "Split into {} hunks.", pieces.len())?;
+
+                    for piece in pieces.into_iter().rev() {
+                        remaining.push_front(piece);
+                    }
+                }
+                "q" => {
+                    remaining.clear();
+                    quit = true;
+                }
+                _ => {
+                    writeln!(stdout, "{PATCH_HELP}")?;
+                    remaining.push_front(hunk);
+                }
+            }
+        }
+
+        if !chosen.is_empty() {
+            repository.stage_hunks(&patch.path, &chosen)?;
+            staged += chosen.len();
+        }
+
+        if quit {
+            break;
+        }
+    }
+
+    Ok(format!("Staged {staged} hunk(s)."))
+}
+
+/// Works out which two sides `rat diff` or `rat difftool` should compare,
+/// from the flags and revision in `matches`. Comparing b with where it split
+/// off from a needs the hash of that commit to live somewhere, so it's put in
+/// `base`.
+fn diff_sides<'a>(
+    repository: &Repository,
+    matches: &'a Matches,
+    base: &'a mut String,
+) -> Result<(DiffSide<'a>, DiffSide<'a>), Box<dyn Error>> {
+    let revision = matches.argument("revision");
+    let staged = matches.flag("--staged");
+
+    // Ranges compare two commits, so we only need the working directory and
+    // the index for everything else.
+    Ok(match revision.map(RevisionRange::parse) {
+        Some(RevisionRange::Difference(from, to)) if !staged => {
+            (DiffSide::Revision(from), DiffSide::Revision(to))
+        }
+        Some(RevisionRange::Symmetric(a, b)) if !staged => {
+            let (a_hash, b_hash) = (resolve::resolve_revision(a)?, resolve::resolve_revision(b)?);
+            *base = graph::merge_base(&a_hash, &b_hash)?
+                .ok_or_else(|| format!("{a} and {b} don't have any history in common."))?;
+
+            (DiffSide::Revision(base), DiffSide::Revision(b))
+        }
+        Some(RevisionRange::Single(_)) | None if repository.is_bare() => Err(InitError::Bare {
+            command: matches.command().name,
+        })?,
+        Some(RevisionRange::Single(revision)) if staged => {
+            (DiffSide::Revision(revision), DiffSide::Index)
+        }
+        Some(RevisionRange::Single(revision)) => {
+            (DiffSide::Revision(revision), DiffSide::WorkingDirectory)
+        }
+        None if staged => (DiffSide::Revision("HEAD"), DiffSide::Index),
+        None => (DiffSide::Index, DiffSide::WorkingDirectory),
+        Some(_) => Err(matches.error("--staged can't be used with a range."))?,
+    })
+}
+
+/// Shows each file that differs between the sides picked out by `matches` in
+/// the diff tool the user has set up, one after the other.
+fn difftool(repository: &Repository, matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let tool = Tool::configured(ToolKind::Diff, matches.value("--tool"))?;
+
+    let mut base = String::new();
+    let (old, new) = diff_sides(repository, matches, &mut base)?;
+
+    let directory = env::temp_dir().join(format!("rat-difftool-{}", process::id()));
+
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        for patch in repository.diff(old, new, Detection::Off)? {
+            let local = export_version(&directory, &patch.path, "LOCAL", patch.old.as_ref())?;
+
+            // Files in the working directory are shown as they are, so that
+            // any changes made in the tool are kept.
+            let remote = match &patch.new {
+                Some(_) if new == DiffSide::WorkingDirectory => PathBuf::from(&patch.path),
+                entry => export_version(&directory, &patch.path, "REMOTE", entry.as_ref())?,
+            };
+
+            tool.run(&[
+                ("LOCAL", &local),
+                ("REMOTE", &remote),
+                ("MERGED", &remote),
+                ("BASE", &local),
+            ])?;
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+
+    result
+}
+
+/// Opens each conflicted file picked out by `matches` in the merge tool the
+/// user has set up, and marks the ones it merges as resolved.
+fn mergetool(repository: &Repository, matches: &Matches) -> Result<String, Box<dyn Error>> {
+    let tool = Tool::configured(ToolKind::Merge, matches.value("--tool"))?;
+    let paths = matches.arguments("path");
+
+    let mut conflicts = state::read_conflicts()?;
+
+    if !paths.is_empty() {
+        let chosen: Vec<String> = paths.iter().filter_map(utils::normalize_path).collect();
+
+        conflicts.retain(|conflict| {
+            chosen.iter().any(|path| {
+                path.is_empty() || conflict == path || conflict.starts_with(&format!("{path}/"))
+            })
+        });
+    }
+
+    if conflicts.is_empty() {
+        return Ok("There aren't any conflicts to resolve.".to_string());
+    }
+
+    let sides = repository.conflict_sides()?;
+    let directory = env::temp_dir().join(format!("rat-mergetool-{}", process::id()));
+
+    let result = (|| -> Result<Vec<String>, Box<dyn Error>> {
+        let mut lines = Vec::new();
+
+        for path in &conflicts {
+            let base = export_version(&directory, path, "BASE", sides.base.get(path))?;
+            let local = export_version(&directory, path, "LOCAL", sides.ours.get(path))?;
+            let remote = export_version(&directory, path, "REMOTE", sides.theirs.get(path))?;
+
+            let before = fs::read(path).ok();
+            let succeeded = tool.run(&[
+                ("LOCAL", &local),
+                ("REMOTE", &remote),
+                ("BASE", &base),
+                ("MERGED", Path::new(path)),
+            ])?;
+            let changed = fs::read(path).ok() != before;
+
+            // Staging a file is how we mark it as resolved.
+            if tool.merged(succeeded, changed) {
+                repository.add(&[path])?;
+                lines.push(format!("Resolved {path}."));
+            } else {
+                lines.push(format!("{path} still has conflicts."));
+            }
+        }
+
+        Ok(lines)
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+
+    Ok(result?.join("\n"))
+}
+
+/// Writes the version of the file at `path` given by `entry` into
+/// `directory`, for handing to a diff or merge tool, and returns where it
+/// went. It's named like `main.BASE.rs`, so that it's clear which version it
+/// is while keeping its extension. A file that doesn't exist in that version
+/// is written out empty.
+fn export_version(
+    directory: &Path,
+    path: &str,
+    label: &str,
+    entry: Option<&Entry>,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let original = Path::new(path);
+    let stem = original.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match original.extension() {
+        Some(extension) => format!("{stem}.{label}.{}", extension.to_string_lossy()),
+        None => format!("{stem}.{label}"),
+    };
+
+    let exported = directory.join(original).with_file_name(name);
+
+    if let Some(parent) = exported.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let data = match entry {
+        Some(entry) => objects::read_object_of_kind(&entry.hash, ObjectKind::Blob)?,
+        None => Vec::new(),
+    };
+    fs::write(&exported, data)?;
+
+    Ok(exported)
+}
+
+/// Describes each of `patches` in the unified format git uses, so that the
+/// output can be given to other tools as well as read.
+fn format_patches(patches: Vec<FilePatch>, format: TerminalFormat) -> String {
+    let mut lines = Vec::new();
+
+    for patch in patches {
+        let path = &patch.path;
+
+        // A file that was renamed or copied has a different name on each
+        // side.
+        let (from, similarity, verb) = match &patch.change {
+            Change::Renamed { from, similarity } => (from, Some(similarity), "rename"),
+            Change::Copied { from, similarity } => (from, Some(similarity), "copy"),
+            _ => (path, None, ""),
+        };
+
+        lines.push(format!("diff --git a/{from} b/{path}"));
+
+        if let Some(similarity) = similarity {
+            lines.push(format!("similarity index {similarity}%"));
+            lines.push(format!("{verb} from {from}"));
+            lines.push(format!("{verb} to {path}"));
+        }
+
+        match (&patch.old, &patch.new) {
+            (None, Some(new)) => lines.push(format!("new file mode {}", new.mode.git_mode())),
+            (Some(old), None) => lines.push(format!("deleted file mode {}", old.mode.git_mode())),
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                lines.push(format!("old mode {}", old.mode.git_mode()));
+                lines.push(format!("new mode {}", new.mode.git_mode()));
+            }
+            _ => {}
+        }
+
+        let hunks = match patch.content {
+            PatchContent::Binary => {
+                lines.push(format!("Binary files a/{from} and b/{path} differ"));
+                continue;
+            }
+            PatchContent::Text(hunks) if hunks.is_empty() => continue,
+            PatchContent::Text(hunks) => hunks,
+        };
+
+        // A file that doesn't exist on one side is compared with nothing.
+        let old_name = match patch.old {
+            Some(_) => format!("a/{from}"),
+            None => "/dev/null".to_string(),
+        };
+        let new_name = match patch.new {
+            Some(_) => format!("b/{path}"),
+            None => "/dev/null".to_string(),
+        };
+
+        lines.push(format!("--- {old_name}"));
+        lines.push(format!("+++ {new_name}"));
+
+        for hunk in &hunks {
+            lines.extend(format_hunk(hunk, format));
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Describes `hunk` the way it's shown in a patch, one line at a time.
+fn format_hunk(hunk: &PatchHunk, format: TerminalFormat) -> Vec<String> {
+    let mut lines = vec![format.paint(&hunk.header(), Color::Cyan)];
+
+    for line in &hunk.lines {
+        let (text, prefix, color) = match line {
+            PatchLine::Context(text) => (text, " ", None),
+            PatchLine::Added(text) => (text, "+", Some(Color::Green)),
+            PatchLine::Removed(text) => (text, "-", Some(Color::Red)),
+        };
+
+        let body = format!("{prefix}{}", text.trim_end_matches('\n'));
+
+        lines.push(match color {
+            Some(color) => format.paint(&body, color),
+            None => body,
+        });
+
+        if !text.ends_with('\n') {
+            lines.push("\\ No newline at end of file".to_string());
+        }
+    }
+
+    lines
+}
+
+/// Clones the nest `source` into `path` and stages it as another nest inside
+/// this one, along with where it came from.
+fn add_submodule(
+    repository: &Repository,
+    source: &str,
+    path: &str,
+) -> Result<String, Box<dyn Error>> {
+    let path = utils::normalize_path(path)
+        .filter(|path| !path.is_empty())
+        .ok_or_else(|| format!("{path} isn't inside the nest."))?;
+
+    if submodules::list()?
+        .iter()
+        .any(|submodule| submodule.path == path)
+    {
+        Err(format!("{path} is already a submodule."))?;
+    }
+
+    run_rat(Path::new("."), &["clone", source, &path])?;
+    submodules::record(&path, source)?;
+    repository.add(&[submodules::MODULES_FILE, path.as_str()])?;
+
+    Ok(format!("Added {source} in {path}."))
+}
+
+/// Clones every submodule that's missing and checks out the commit each one is
+/// pinned to, returning what was done.
+fn update_submodules() -> Result<String, Box<dyn Error>> {
+    let mut done = Vec::new();
+
+    for submodule in submodules::list()? {
+        let Some(pinned) = &submodule.pinned else {
+            continue;
+        };
+
+        if !submodules::is_nest(&submodule.path) {
+            run_rat(Path::new("."), &["clone", &submodule.url, &submodule.path])?;
+            done.push(format!("Cloned {} into {}.", submodule.url, submodule.path));
+        }
+
+        if submodules::head(&submodule.path)?.as_ref() != Some(pinned) {
+            let dir = Path::new(&submodule.path);
+
+            // The commit might be newer than anything the nest has seen yet.
+            if !submodules::has_commit(&submodule.path, pinned) {
+                run_rat(dir, &["fetch"])?;
+            }
+
+            run_rat(dir, &["checkout", pinned])?;
+            done.push(format!(
+                "Checked out {} in {}.",
+                resolve::abbreviate(pinned),
+                submodule.path
+            ));
+        }
+    }
+
+    match done.is_empty() {
+        true => Ok("Every submodule is up to date.".to_string()),
+        false => Ok(done.join("\n")),
+    }
+}
+
+/// Runs rat again in `dir`, for working in another nest, which this process
+/// can't do since it's settled on this one. It only reports errors.
+fn run_rat(dir: &Path, arguments: &[&str]) -> Result<(), Box<dyn Error>> {
+    let status = process::Command::new(env::current_exe()?)
+        .arg("--quiet")
+        .args(arguments)
+        .current_dir(dir)
+        // These would point it straight back at this nest.
+        .env_remove("RAT_DIR")
+        .env_remove("RAT_WORK_TREE")
+        .status()?;
+
+    if !status.success() {
+        Err(format!(
+            "rat {} failed in {}.",
+            arguments.join(" "),
+            dir.display()
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Lists `submodules` one per line, with the commit each is pinned to and a
+/// mark in front saying whether it's on that commit.
+fn format_submodules(submodules: Vec<Submodule>) -> String {
+    submodules
+        .into_iter()
+        .map(|submodule| {
+            let mark = match (&submodule.pinned, &submodule.checked_out) {
+                (_, None) => '-',
+                (pinned, checked_out) if pinned != checked_out => '+',
+                _ => ' ',
+            };
+
+            let commit = match &submodule.pinned {
+                Some(hash) => resolve::abbreviate(hash),
+                None => "(not staged)".to_string(),
+            };
+
+            format!("{mark}{commit} {}", submodule.path)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Lists `worktrees` one per line, with the commit each is on and its branch.
+fn format_worktrees(worktrees: Vec<Worktree>) -> String {
+    let width = worktrees
+        .iter()
+        .map(|worktree| worktree.path.to_string_lossy().chars().count())
+        .max()
+        .unwrap_or(0);
+
+    worktrees
+        .into_iter()
+        .map(|worktree| {
+            let commit = match &worktree.commit {
+                Some(hash) => resolve::abbreviate(hash),
+                None => "(no commits)".to_string(),
+            };
+
+            let head = match &worktree.head {
+                Head::Branch(branch) => format!("[{branch}]"),
+                Head::Detached(_) => "(detached HEAD)".to_string(),
+            };
+
+            let mut line = format!(
+                "{:width$}  {commit} {head}",
+                worktree.path.to_string_lossy()
+            );
+
+            if worktree.missing {
+                line.push_str(" (missing)");
+            }
+
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Describes how the index differs from the last commit, how the working
+/// directory differs from the index, and which files aren't tracked at all,
+/// for showing to the user.
+fn format_status(status: Status, format: TerminalFormat) -> String {
+    if status.is_clean() {
+        return "Nothing to commit, working directory clean.".to_string();
+    }
+
+    let mut sections = Vec::new();
+
+    if let Some((operation, hash)) = status.operation {
+        sections.push(format!(
+            "You are in the middle of a {operation} of {}.\n",
+            resolve::abbreviate(&hash)
+        ));
+    }
+
+    if !status.conflicts.is_empty() {
+        let conflict_list = status
+            .conflicts
+            .iter()
+            .map(|path| format!("    {}\n", format.paint(path, Color::Red)))
+            .collect::<String>();
+
+        sections.push(format!(
+            "Unmerged paths:\n  (fix them and rat add them, or pick a side with rat restore \
+             --ours or --theirs)\n{conflict_list}"
+        ));
+    }
+
+    if !status.staged.is_empty() {
+        sections.push(format!(
+            "Changes to be committed:\n{}",
+            format_changes(status.staged, format, Color::Green)
+        ));
+    }
+
+    if !status.unstaged.is_empty() {
+        sections.push(format!(
+            "Changes not staged for commit:\n{}",
+            format_changes(status.unstaged, format, Color::Red)
+        ));
+    }
+
+    if !status.untracked.is_empty() {
+        let untracked_list = status
+            .untracked
+            .iter()
+            .map(|path| format!("    {}\n", format.paint(path, Color::Red)))
+            .collect::<String>();
+
+        sections.push(format!("Untracked files:\n{untracked_list}"));
+    }
+
+    // We trim the final newline, since the output gets printed with one anyway.
+    sections.join("\n").trim_end().to_string()
+}
+
+/// Describes the same things as [`format_status`], one line per file in a
+/// format that scripts can rely on, with each line ending in a zero byte
+/// rather than a newline if `null` is set.
+fn format_porcelain_status(status: Status, null: bool) -> String {
+    let code = |change: &Change| match change {
+        Change::Added => 'A',
+        Change::Modified => 'M',
+        Change::Deleted => 'D',
+        Change::Renamed { .. } => 'R',
+        Change::Copied { .. } => 'C',
+    };
+
+    let from = |change: Option<&Change>| match change {
+        Some(Change::Renamed { from, .. } | Change::Copied { from, .. }) => Some(from.clone()),
+        _ => None,
+    };
+
+    let mut lines: BTreeMap<String, (String, Option<String>)> = status
+        .conflicts
+        .into_iter()
+        .map(|path| (path, ("UU".to_string(), None)))
+        .collect();
+
+    let paths: BTreeSet<&String> = status.staged.keys().chain(status.unstaged.keys()).collect();
+
+    for path in paths {
+        let staged = status.staged.get(path);
+        let unstaged = status.unstaged.get(path);
+
+        let codes = format!("{}{}", staged.map_or(' ', code), unstaged.map_or(' ', code));
+
+        lines.insert(path.clone(), (codes, from(staged).or(from(unstaged))));
+    }
+
+    let terminator = if null { '\0' } else { '\n' };
+
+    let mut output: String = lines
+        .into_iter()
+        .map(|(path, (codes, from))| match from {
+            // Without zero bytes to split them, the old path goes first, the
+            // way it reads. With them, it goes after, as a path of its own.
+            Some(from) if null => format!("{codes} {path}\0{from}\0"),
+            Some(from) => format!("{codes} {from} -> {path}\n"),
+            None => format!("{codes} {path}{terminator}"),
+        })
+        .collect();
+
+    for path in status.untracked {
+        output.push_str(&format!("?? {path}{terminator}"));
+    }
+
+    output
+}
+
+/// Describes the same things as [`format_status`], but as JSON.
+fn status_json(status: Status) -> Json {
+    let operation = status.operation.map(|(operation, hash)| {
+        Json::object([
+            ("name", operation.to_string().into()),
+            ("commit", hash.into()),
+        ])
+    });
+
+    let changes = |changes: BTreeMap<String, Change>| -> Json {
+        changes
+            .into_iter()
+            .map(|(path, change)| {
+                let (change, from) = match change {
+                    Change::Added => ("added", None),
+                    Change::Modified => ("modified", None),
+                    Change::Deleted => ("deleted", None),
+                    Change::Renamed { from, .. } => ("renamed", Some(from)),
+                    Change::Copied { from, .. } => ("copied", Some(from)),
+                };
+
+                // Only renames and copies come from somewhere else, so the
+                // other changes have null here.
+                Json::object([
+                    ("path", path.into()),
+                    ("change", change.into()),
+                    ("from", from.into()),
+                ])
+            })
+            .collect()
+    };
+
+    Json::object([
+        ("operation", operation.into()),
+        ("conflicts", status.conflicts.into_iter().collect()),
+        ("staged", changes(status.staged)),
+        ("unstaged", changes(status.unstaged)),
+        ("untracked", status.untracked.into()),
+    ])
+}
+
+/// Works out the address `rat serve` should listen on from `matches`.
+///
+/// A port on its own only listens on this computer, since serving the nest to
+/// the whole network should be something you ask for.
+fn serve_address(matches: &Matches) -> Result<String, UsageError> {
+    let address = matches.argument("address").unwrap_or("8080");
+    let invalid = || matches.error(format!("{address} isn't a port or a <host>:<port>."));
+
+    if address.bytes().all(|b| b.is_ascii_digit()) {
+        let port: u16 = address.parse().map_err(|_| invalid())?;
+
+        return Ok(format!("127.0.0.1:{port}"));
+    }
+
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok(address.to_string())
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Pushes the branches picked out by `matches` to each URL of the remote,
+/// describing what happened at each one.
+fn push(matches: &Matches) -> Result<String, Box<dyn Error>> {
+    // By default we push the current branch to origin.
+    let name = matches.argument("remote").unwrap_or("origin");
+    let verify = !matches.flag("--no-verify");
+
+    let mut outcomes = if matches.flag("--mirror") {
+        if matches.flag("--all") || matches.argument("branch").is_some() {
+            Err(matches.error("--mirror can't be used with --all or <branch>."))?;
+        }
+
+        remote::push_mirror(name, verify)?
+    } else {
+        let branches = match (matches.flag("--all"), matches.argument("branch")) {
+            (true, Some(_)) => Err(matches.error("--all and <branch> can't be used together."))?,
+            (true, None) => refs::list_branches()?,
+            (false, Some(branch)) => vec![branch.to_string()],
+            (false, None) => match refs::read_head()? {
+                Head::Branch(branch) => vec![branch],
+                Head::Detached(_) => Err("HEAD is detached, so there's no branch to push.")?,
+            },
+        };
+
+        remote::push(name, &branches, verify)?
+    };
+
+    // With only one URL, which is almost always the case, a failure is just
+    // an error like any other.
+    if let [_] = outcomes[..] {
+        let updates = outcomes.remove(0).result?;
+
+        return Ok(match updates.is_empty() {
+            true => "Everything up to date.".to_string(),
+            false => format_push(name, &updates),
+        });
+    }
+
+    let report = outcomes
+        .iter()
+        .map(|outcome| match &outcome.result {
+            Ok(updates) => format_push(&outcome.url, updates),
+            Err(e) => format!("Failed to push to {}: {e}", outcome.url),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let failures = outcomes
+        .iter()
+        .filter(|outcome| outcome.result.is_err())
+        .count();
+
+    // Which URLs worked is worth knowing even when some of them didn't, so
+    // the report is shown before the error.
+    if failures > 0 {
+        pager::print(&report)?;
+
+        Err(RemoteError::PushFailed {
+            failed: failures,
+            total: outcomes.len(),
+        })?;
+    }
+
+    Ok(report)
+}
+
+/// Describes the branches that moved when pushing to `destination`, which is
+/// the name or URL of a remote.
+fn format_push(destination: &str, updates: &[String]) -> String {
+    match updates {
+        [] => format!("{destination} is up to date."),
+        _ => format!("Pushed to {destination}:\n{}", updates.join("\n")),
+    }
+}
+
+/// Describes what's in the nest and how much room it takes up, for showing
+/// to the user.
+fn format_stats(stats: NestStats) -> String {
+    let size = |bytes: u64| utils::format_bytes(bytes);
+
+    // Chunks only come up in nests with big files, so they're only mentioned
+    // when there are some.
+    let chunks = match stats.chunks {
+        0 => String::new(),
+        chunks => format!(", {chunks} chunks"),
+    };
+
+    let mut lines = vec![
+        format!(
+            "Objects:  {} ({} commits, {} trees, {} blobs{chunks}, {} tags)",
+            stats.commits + stats.trees + stats.blobs + stats.chunks + stats.tags,
+            stats.commits,
+            stats.trees,
+            stats.blobs,
+            stats.tags
+        ),
+        format!("Loose:    {}", stats.loose_objects),
+        format!(
+            "Packed:   {} in {} pack(s)",
+            stats.packed_objects, stats.packs
+        ),
+        format!("Branches: {}", stats.branches),
+        format!("Tags:     {}", stats.tag_refs),
+        String::new(),
+        format!("Every file in every commit: {}", size(stats.history_size)),
+        format!("Each version stored once:   {}", size(stats.stored_size)),
+        format!("The nest on disk:           {}", size(stats.disk_size)),
+    ];
+
+    if !stats.largest.is_empty() {
+        let width = stats
+            .largest
+            .iter()
+            .map(|file| size(file.size).len())
+            .max()
+            .unwrap_or(0);
+
+        lines.push(String::new());
+        lines.push("Largest files:".to_string());
+
+        for file in &stats.largest {
+            lines.push(format!("    {:>width$}  {}", size(file.size), file.path));
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Describes the same things as [`format_stats`], but as JSON, with sizes in
+/// bytes.
+fn stats_json(stats: NestStats) -> Json {
+    let largest: Json = stats
+        .largest
+        .into_iter()
+        .map(|file| {
+            Json::object([
+                ("path", file.path.into()),
+                ("hash", file.hash.into()),
+                ("size", file.size.into()),
+            ])
+        })
+        .collect();
+
+    Json::object([
+        ("commits", stats.commits.into()),
+        ("trees", stats.trees.into()),
+        ("blobs", stats.blobs.into()),
+        ("chunks", stats.chunks.into()),
+        ("tags", stats.tags.into()),
+        ("loose_objects", stats.loose_objects.into()),
+        ("packed_objects", stats.packed_objects.into()),
+        ("packs", stats.packs.into()),
+        ("branches", stats.branches.into()),
+        ("tag_refs", stats.tag_refs.into()),
+        ("history_size", stats.history_size.into()),
+        ("stored_size", stats.stored_size.into()),
+        ("disk_size", stats.disk_size.into()),
+        ("largest", largest),
+    ])
+}
+
+/// Describes who made a commit or tag, and when, as JSON.
+fn signature_json(signature: &Signature) -> Json {
+    Json::object([
+        ("name", signature.name.as_str().into()),
+        ("email", signature.email.as_str().into()),
+        ("timestamp", signature.timestamp.into()),
+        ("utc_offset", signature.utc_offset.into()),
+    ])
+}
+
+/// Formats a list of changes with one indented line per file, like
+/// `    modified:   src/main.rs`, painted with `color`.
+fn format_changes(
+    changes: impl IntoIterator<Item = (String, Change)>,
+    format: TerminalFormat,
+    color: Color,
+) -> String {
+    changes
+        .into_iter()
+        .map(|(path, change)| {
+            let (label, path) = match change {
+                Change::Added => ("new file:", path),
+                Change::Modified => ("modified:", path),
+                Change::Deleted => ("deleted:", path),
+                Change::Renamed { from, .. } => ("renamed:", format!("{from} -> {path}")),
+                Change::Copied { from, .. } => ("copied:", format!("{from} -> {path}")),
+            };
 
-    Ok(logs)
+            format!(
+                "    {}\n",
+                format.paint(&format!("{label:<12}{path}"), color)
+            )
+        })
+        .collect()
 }
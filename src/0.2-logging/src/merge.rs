@@ -0,0 +1,316 @@
+//! Combining two sets of changes made on top of the same starting point.
+//!
+//! A three-way merge takes a common *base* version and two versions derived
+//! from it, which we call *ours* and *theirs*. Anything that only one side
+//! changed is taken from that side, and anything both sides changed in the
+//! same way is taken from either. When both sides changed the same part in
+//! different ways, there's no way to tell which is right, so we keep both and
+//! surround them with conflict markers for the user to sort out:
+//!
+//! ```text
+//! <<<<<<< ours
+//! what we have
+//! =======
+//! what they have
+//! >>>>>>> theirs
+//! ```
+//!
+//! The same idea works on whole snapshots, where each file is merged on its
+//! own. This is what powers `revert`, `cherry-pick` and `merge`, which only
+//! differ in which versions they pass in as the base, ours and theirs.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+
+use crate::compare::{Entry, FileMode, Snapshot};
+use crate::diff::{self, Hunk};
+use crate::objects::{self, ObjectKind};
+use crate::utils;
+
+/// The outcome of merging a single file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextMerge {
+    /// Every change could be combined automatically.
+    Clean(String),
+    /// At least one part of the file was changed differently on each side, so
+    /// the text contains conflict markers.
+    Conflicted(String),
+}
+
+/// Merges the changes that took `base` to `ours` with the ones that took it to
+/// `theirs`. The labels are shown next to the conflict markers so the user can
+/// tell which side is which.
+pub fn merge_text(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    our_label: &str,
+    their_label: &str,
+) -> TextMerge {
+    let base_lines = diff::split_lines(base);
+    let our_lines = diff::split_lines(ours);
+    let their_lines = diff::split_lines(theirs);
+
+    // Tag each hunk with which side it came from, and sort them by where they
+    // start in the base, so we can find the ones that overlap.
+    let mut hunks: Vec<(bool, Hunk)> = diff::diff(&base_lines, &our_lines)
+        .into_iter()
+        .map(|hunk| (true, hunk))
+        .chain(
+            diff::diff(&base_lines, &their_lines)
+                .into_iter()
+                .map(|hunk| (false, hunk)),
+        )
+        .collect();
+    hunks.sort_by_key(|(_, hunk)| (hunk.old_start, hunk.old_end));
+
+    let mut merged = String::new();
+    let mut conflicted = false;
+
+    // Everything outside the hunks is the same on all three sides, so the
+    // position in each side only differs from the position in the base by how
+    // many lines the hunks before it added or removed.
+    let mut base_index = 0;
+    let (mut our_shift, mut their_shift) = (0isize, 0isize);
+    let mut hunks = hunks.into_iter().peekable();
+
+    while let Some((from_ours, first)) = hunks.next() {
+        // Hunks from different sides that overlap, or even just touch, have
+        // to be dealt with together.
+        let mut group = vec![(from_ours, first)];
+        let mut group_end = first.old_end;
+
+        while let Some((_, next)) = hunks.peek() {
+            if next.old_start > group_end {
+                break;
+            }
+
+            group_end = group_end.max(next.old_end);
+            group.extend(hunks.next());
+        }
+
+        let group_start = first.old_start;
+        merged.push_str(&base_lines[base_index..group_start].concat());
+        base_index = group_end;
+
+        let our_start = shifted(group_start, our_shift);
+        let their_start = shifted(group_start, their_shift);
+
+        for (from_ours, hunk) in &group {
+            let change =
+                (hunk.new_end - hunk.new_start) as isize - (hunk.old_end - hunk.old_start) as isize;
+
+            if *from_ours {
+                our_shift += change;
+            } else {
+                their_shift += change;
+            }
+        }
+
+        let our_part = &our_lines[our_start..shifted(group_end, our_shift)];
+        let their_part = &their_lines[their_start..shifted(group_end, their_shift)];
+
+        let ours_changed = group.iter().any(|(from_ours, _)| *from_ours);
+        let theirs_changed = group.iter().any(|(from_ours, _)| !*from_ours);
+
+        if !theirs_changed || our_part == their_part {
+            merged.push_str(&our_part.concat());
+        } else if !ours_changed {
+            merged.push_str(&their_part.concat());
+        } else {
+            conflicted = true;
+
+            merged.push_str(&format!("<<<<<<< {our_label}\n"));
+            push_lines(&mut merged, our_part);
+            merged.push_str("=======\n");
+            push_lines(&mut merged, their_part);
+            merged.push_str(&format!(">>>>>>> {their_label}\n"));
+        }
+    }
+
+    merged.push_str(&base_lines[base_index..].concat());
+
+    if conflicted {
+        TextMerge::Conflicted(merged)
+    } else {
+        TextMerge::Clean(merged)
+    }
+}
+
+fn shifted(index: usize, shift: isize) -> usize {
+    (index as isize + shift) as usize
+}
+
+/// Adds `lines` to `text`, making sure it ends with a newline afterwards so
+/// that a conflict marker can't end up on the same line as the last of them.
+fn push_lines(text: &mut String, lines: &[&str]) {
+    text.push_str(&lines.concat());
+
+    if !lines.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+/// The outcome of merging two snapshots.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotMerge {
+    /// The merged snapshot. Conflicted files are included too, holding whatever
+    /// we think is the most helpful thing to leave in the working directory.
+    pub snapshot: Snapshot,
+    /// The paths of every file that couldn't be merged automatically.
+    pub conflicts: BTreeSet<String>,
+}
+
+/// Merges the snapshots `ours` and `theirs` using `base` as their common
+/// starting point, one file at a time. Merged files that didn't exist before
+/// are stored in the object store along the way.
+pub fn merge_snapshots(
+    base: &Snapshot,
+    ours: &Snapshot,
+    theirs: &Snapshot,
+    our_label: &str,
+    their_label: &str,
+) -> Result<SnapshotMerge, Box<dyn Error>> {
+    let paths: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    let mut result = SnapshotMerge::default();
+
+    for path in paths {
+        let base_entry = base.get(path);
+        let our_entry = ours.get(path);
+        let their_entry = theirs.get(path);
+
+        // Most files are only touched by one side, if at all, in which case we
+        // can skip looking at their contents.
+        let merged_entry = if our_entry == their_entry || base_entry == their_entry {
+            our_entry.cloned()
+        } else if base_entry == our_entry {
+            their_entry.cloned()
+        } else {
+            let is_file = |entry: Option<&Entry>| {
+                entry.is_none_or(|entry| {
+                    matches!(entry.mode, FileMode::Regular | FileMode::Executable)
+                })
+            };
+
+            match (our_entry, their_entry) {
+                // Only the contents of ordinary files can be merged. Where a
+                // symlink points, or which commit another nest is on, is all
+                // or nothing.
+                (Some(our_entry), Some(their_entry))
+                    if is_file(base_entry)
+                        && is_file(Some(our_entry))
+                        && is_file(Some(their_entry)) =>
+                {
+                    let base_hash = base_entry.map(|entry| entry.hash.as_str());
+
+                    // One side might only have changed whether the file is
+                    // executable, in which case the contents are easy.
+                    let hash = if our_entry.hash == their_entry.hash
+                        || base_hash == Some(&their_entry.hash)
+                    {
+                        our_entry.hash.clone()
+                    } else if base_hash == Some(&our_entry.hash) {
+                        their_entry.hash.clone()
+                    } else {
+                        let (hash, conflicted) = merge_blobs(
+                            base_hash,
+                            &our_entry.hash,
+                            &their_entry.hash,
+                            our_label,
+                            their_label,
+                        )?;
+
+                        if conflicted {
+                            result.conflicts.insert(path.clone());
+                        }
+
+                        hash
+                    };
+
+                    // The mode gets merged the same way, where changing it
+                    // on our side wins if both sides did.
+                    let mode = if base_entry.map(|entry| entry.mode) == Some(our_entry.mode) {
+                        their_entry.mode
+                    } else {
+                        our_entry.mode
+                    };
+
+                    Some(Entry { mode, hash })
+                }
+                // Either one side deleted the file while the other changed
+                // it, or we can't merge it at all. We keep the changed version
+                // around, since it's much easier to delete it again than to
+                // get it back.
+                (our_entry, their_entry) => {
+                    result.conflicts.insert(path.clone());
+
+                    our_entry.or(their_entry).cloned()
+                }
+            }
+        };
+
+        if let Some(entry) = merged_entry {
+            result.snapshot.insert(path.clone(), entry);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Merges the contents of three blobs, returning the hash of the merged blob
+/// and whether it's conflicted. A file that didn't exist in the base is
+/// treated as if it were empty. Files that aren't text can't be merged line by
+/// line, so those are always a conflict, and we keep our version.
+fn merge_blobs(
+    base_hash: Option<&str>,
+    our_hash: &str,
+    their_hash: &str,
+    our_label: &str,
+    their_label: &str,
+) -> Result<(String, bool), Box<dyn Error>> {
+    let base = match base_hash {
+        Some(hash) => objects::read_object_of_kind(hash, ObjectKind::Blob)?,
+        None => Vec::new(),
+    };
+    let ours = objects::read_object_of_kind(our_hash, ObjectKind::Blob)?;
+    let theirs = objects::read_object_of_kind(their_hash, ObjectKind::Blob)?;
+
+    match (as_text(&base), as_text(&ours), as_text(&theirs)) {
+        (Some(base), Some(ours), Some(theirs)) => {
+            match merge_text(base, ours, theirs, our_label, their_label) {
+                TextMerge::Clean(text) => Ok((
+                    objects::write_object(ObjectKind::Blob, text.as_bytes())?,
+                    false,
+                )),
+                TextMerge::Conflicted(text) => Ok((
+                    objects::write_object(ObjectKind::Blob, text.as_bytes())?,
+                    true,
+                )),
+            }
+        }
+        _ => Ok((our_hash.to_string(), true)),
+    }
+}
+
+/// Interprets `data` as text, unless it looks like a binary file.
+fn as_text(data: &[u8]) -> Option<&str> {
+    if utils::is_binary(data) {
+        return None;
+    }
+
+    std::str::from_utf8(data).ok()
+}
+
+/// Summarizes which files couldn't be merged, for use in error messages.
+pub fn describe_conflicts(conflicts: &BTreeSet<String>) -> String {
+    conflicts
+        .iter()
+        .map(|path| format!("    {path}\n"))
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
@@ -0,0 +1,311 @@
+//! Reading and writing the metadata stored in each commit and tag object.
+//!
+//! The metadata consists of a header with one `key value` pair per line, then
+//! a blank line, then the commit message:
+//!
+//! ```text
+//! tree 2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae
+//! parent fcde2b2edba56bf408601fb721fe9b5c338d10ee429ea04fae5511b68fbf8fb9
+//! parent 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//! author Ada Lovelace <ada@example.com> 1700000000 +0100
+//! committer Ada Lovelace <ada@example.com> 1700000000 +0100
+//!
+//! Combine the two drafts.
+//! ```
+//!
+//! The `tree` line names the tree object holding the snapshot of the nest at
+//! the time of the commit. The author is whoever originally wrote the changes,
+//! and the committer is whoever actually created the commit. These are usually
+//! the same person, but not always, for example when someone applies a patch
+//! that somebody else sent them.
+//!
+//! A commit can have any number of parents. The very first commit has none,
+//! a normal commit has exactly one, and a commit that brings two lines of
+//! history together has one for each of them.
+//!
+//! A [signed](crate::signing) commit has a `signature` line after the
+//! committer. Signatures take up several lines, so every line after the first
+//! starts with a space, just like in git:
+//!
+//! ```text
+//! signature -----BEGIN SSH SIGNATURE-----
+//!  U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAg...
+//!  -----END SSH SIGNATURE-----
+//! ```
+//!
+//! Tag objects use the same layout, with a header naming the object being
+//! tagged, its kind, the name of the tag and who created it:
+//!
+//! ```text
+//! object 2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae
+//! type commit
+//! tag v1.0
+//! tagger Ada Lovelace <ada@example.com> 1700000000 +0100
+//!
+//! The first finished draft.
+//! ```
+//!
+//! A signed tag has a `signature` line after the tagger, just like a signed
+//! commit.
+
+use std::error::Error;
+use std::fmt::Display;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::objects::ObjectKind;
+use crate::utils;
+
+/// Identifies who did something and when, like the `author` and `committer`
+/// lines in a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// The number of seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// The offset of the timezone the signature was made in from UTC, in
+    /// minutes. We need to record this separately, since the timestamp itself
+    /// is always in UTC.
+    pub utc_offset: i32,
+}
+
+impl Signature {
+    /// Creates a signature for the current moment in the local timezone.
+    pub fn now(name: String, email: String) -> Self {
+        // The system clock being set before 1970 is strange enough that we can
+        // just treat it as the epoch itself.
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64);
+
+        Self {
+            name,
+            email,
+            timestamp,
+            utc_offset: utils::local_utc_offset(),
+        }
+    }
+
+    /// Parses a signature in the form `Name <email> timestamp +hhmm`.
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = || format!("Invalid signature: {s}");
+
+        // The name can contain almost anything, so we find the email by its
+        // angle brackets and work outwards from there.
+        let (name, rest) = s.split_once(" <").ok_or_else(invalid)?;
+        let (email, rest) = rest.split_once("> ").ok_or_else(invalid)?;
+        let (timestamp, offset) = rest.split_once(' ').ok_or_else(invalid)?;
+
+        Ok(Self {
+            name: name.to_string(),
+            email: email.to_string(),
+            timestamp: timestamp.parse()?,
+            utc_offset: utils::parse_utc_offset(offset).ok_or_else(invalid)?,
+        })
+    }
+
+    /// Formats the time of the signature in the style git uses for `log`, like
+    /// `Thu Nov 14 22:13:20 2023 +0100`.
+    pub fn format_date(&self) -> String {
+        utils::format_timestamp(self.timestamp, self.utc_offset)
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.timestamp,
+            utils::format_utc_offset(self.utc_offset)
+        )
+    }
+}
+
+/// The parsed contents of a commit object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitMetadata {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub message: String,
+    /// The signature over everything else in the commit, if it's signed.
+    pub signature: Option<String>,
+}
+
+impl CommitMetadata {
+    /// Parses metadata in the format described in the module documentation.
+    pub fn parse(data: &str) -> Result<Self, Box<dyn Error>> {
+        // The header is everything up to the first blank line. If there isn't
+        // one, the commit has an empty message.
+        let (header, message) = data.split_once("\n\n").unwrap_or((data, ""));
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut signature: Option<String> = None;
+        let mut key = "";
+
+        for line in header.lines().filter(|line| !line.is_empty()) {
+            // A line starting with a space carries on the line before it.
+            if let Some(rest) = line.strip_prefix(' ') {
+                if let ("signature", Some(signature)) = (key, signature.as_mut()) {
+                    signature.push('\n');
+                    signature.push_str(rest);
+                }
+
+                continue;
+            }
+
+            let value;
+            (key, value) = line
+                .split_once(' ')
+                .ok_or_else(|| format!("Invalid metadata line: {line}"))?;
+
+            // Skipping keys we don't know about means older versions of rat can
+            // still read metadata written by newer ones.
+            match key {
+                "tree" => tree = Some(value.to_string()),
+                "parent" => parents.push(value.to_string()),
+                "author" => author = Some(Signature::parse(value)?),
+                "committer" => committer = Some(Signature::parse(value)?),
+                "signature" => signature = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            tree: tree.ok_or("Commit has no tree.")?,
+            parents,
+            author: author.ok_or("Commit has no author.")?,
+            committer: committer.ok_or("Commit has no committer.")?,
+            message: message.to_string(),
+            signature,
+        })
+    }
+
+    /// Serializes the metadata into the format described in the module
+    /// documentation.
+    pub fn serialize(&self) -> String {
+        let mut data = format!("tree {}\n", self.tree);
+
+        for parent in &self.parents {
+            data.push_str(&format!("parent {parent}\n"));
+        }
+
+        data.push_str(&format!("author {}\n", self.author));
+        data.push_str(&format!("committer {}\n", self.committer));
+
+        if let Some(signature) = &self.signature {
+            data.push_str(&format!("signature {}\n", signature.replace('\n', "\n ")));
+        }
+
+        data.push('\n');
+        data.push_str(&self.message);
+
+        data
+    }
+
+    /// Serializes everything apart from the signature, which is what the
+    /// signature is over.
+    pub fn signed_data(&self) -> String {
+        Self {
+            signature: None,
+            ..self.clone()
+        }
+        .serialize()
+    }
+}
+
+/// The parsed contents of a tag object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagMetadata {
+    /// The hash of the object being tagged, which is almost always a commit.
+    pub object: String,
+    pub kind: ObjectKind,
+    pub name: String,
+    pub tagger: Signature,
+    pub message: String,
+    /// The signature over everything else in the tag, if it's signed.
+    pub signature: Option<String>,
+}
+
+impl TagMetadata {
+    /// Parses metadata in the format described in the module documentation.
+    pub fn parse(data: &str) -> Result<Self, Box<dyn Error>> {
+        let (header, message) = data.split_once("\n\n").unwrap_or((data, ""));
+
+        let mut object = None;
+        let mut kind = None;
+        let mut name = None;
+        let mut tagger = None;
+        let mut signature: Option<String> = None;
+        let mut key = "";
+
+        for line in header.lines().filter(|line| !line.is_empty()) {
+            if let Some(rest) = line.strip_prefix(' ') {
+                if let ("signature", Some(signature)) = (key, signature.as_mut()) {
+                    signature.push('\n');
+                    signature.push_str(rest);
+                }
+
+                continue;
+            }
+
+            let value;
+            (key, value) = line
+                .split_once(' ')
+                .ok_or_else(|| format!("Invalid metadata line: {line}"))?;
+
+            match key {
+                "object" => object = Some(value.to_string()),
+                "type" => kind = Some(value.parse()?),
+                "tag" => name = Some(value.to_string()),
+                "tagger" => tagger = Some(Signature::parse(value)?),
+                "signature" => signature = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            object: object.ok_or("Tag has no object.")?,
+            kind: kind.ok_or("Tag has no type.")?,
+            name: name.ok_or("Tag has no name.")?,
+            tagger: tagger.ok_or("Tag has no tagger.")?,
+            message: message.to_string(),
+            signature,
+        })
+    }
+
+    /// Serializes the metadata into the format described in the module
+    /// documentation.
+    pub fn serialize(&self) -> String {
+        let mut data = format!(
+            "object {}\ntype {}\ntag {}\ntagger {}\n",
+            self.object, self.kind, self.name, self.tagger
+        );
+
+        if let Some(signature) = &self.signature {
+            data.push_str(&format!("signature {}\n", signature.replace('\n', "\n ")));
+        }
+
+        data.push('\n');
+        data.push_str(&self.message);
+
+        data
+    }
+
+    /// Serializes everything apart from the signature, which is what the
+    /// signature is over.
+    pub fn signed_data(&self) -> String {
+        Self {
+            signature: None,
+            ..self.clone()
+        }
+        .serialize()
+    }
+}
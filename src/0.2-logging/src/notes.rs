@@ -0,0 +1,113 @@
+//! Notes, which attach extra text to a commit without changing it.
+//!
+//! A commit's message is part of what gets hashed, so changing it would give
+//! the commit a new hash, along with every commit after it. Notes are kept
+//! outside of the history instead, so they can be added to any commit at any
+//! time, which makes them handy for things like review comments or the
+//! results of a test run.
+//!
+//! Each note is a file in `.rat/notes` named after the full hash of the
+//! commit it belongs to, holding nothing but the text of the note. Notes stay
+//! in the nest they were written in, since fetching and pushing only copy
+//! history, unless the nest is being mirrored, in which case they're copied
+//! along with everything else.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{resolve, utils};
+
+fn note_path(commit: &str) -> PathBuf {
+    crate::nest_path("notes").join(commit)
+}
+
+/// Reads the note on `commit`, if it has one.
+pub fn read(commit: &str) -> io::Result<Option<String>> {
+    match fs::read_to_string(note_path(commit)) {
+        Ok(note) => Ok(Some(note)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads every note there is, by the hash of the commit it belongs to.
+pub fn read_all() -> io::Result<HashMap<String, String>> {
+    read_all_in(crate::nest_dir())
+}
+
+/// Reads every note in the nest directory `nest`, like [`read_all`].
+pub fn read_all_in(nest: &Path) -> io::Result<HashMap<String, String>> {
+    let entries = match fs::read_dir(nest.join("notes")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut notes = HashMap::new();
+
+    for entry in entries {
+        let entry = entry?;
+        let commit = entry.file_name().to_string_lossy().into_owned();
+
+        notes.insert(commit, fs::read_to_string(entry.path())?);
+    }
+
+    Ok(notes)
+}
+
+/// Gives `commit` the note `text`. A commit can only have one note, so if it
+/// already has one, it's only replaced if `force` is set.
+pub fn add(commit: &str, text: &str, force: bool) -> Result<(), Box<dyn Error>> {
+    if !force && read(commit)?.is_some() {
+        Err(format!(
+            "Commit {} already has a note. Use -f to replace it.",
+            resolve::abbreviate(commit)
+        ))?;
+    }
+
+    // Like a commit message, a note always ends with exactly one line break,
+    // however it was typed.
+    let text = format!("{}\n", text.trim_end());
+
+    fs::create_dir_all(crate::nest_path("notes"))?;
+    utils::write_atomically(note_path(commit), text)?;
+
+    Ok(())
+}
+
+/// Removes the note on `commit`, returning whether there was one.
+pub fn remove(commit: &str) -> io::Result<bool> {
+    match fs::remove_file(note_path(commit)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Makes the notes in the nest directory `nest` exactly `notes`, adding and
+/// changing notes to match and removing every other one, which is how notes
+/// are mirrored.
+pub fn replace_all_in(nest: &Path, notes: &HashMap<String, String>) -> io::Result<()> {
+    let directory = nest.join("notes");
+
+    for commit in read_all_in(nest)?.into_keys() {
+        if !notes.contains_key(&commit) {
+            fs::remove_file(directory.join(commit))?;
+        }
+    }
+
+    if notes.is_empty() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(&directory)?;
+
+    for (commit, text) in notes {
+        utils::write_atomically(directory.join(commit), text)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1154 @@
+//! The object store, which is where all of the actual history lives.
+//!
+//! Rather than copying the whole working directory for every commit, we store
+//! each piece of data exactly once in `.rat/objects`, named after the hash of
+//! its contents. Since identical contents always produce the same hash,
+//! a file that doesn't change between commits is only ever stored once. This
+//! is called content-addressed storage, and it's the core idea behind git.
+//!
+//! There are four kinds of objects:
+//!
+//! - A **blob** holds the contents of a single file, and nothing else. Not even
+//!   its name.
+//! - A **tree** holds a directory listing. Each line names a blob or another
+//!   tree, which is how names and nested directories get attached to blobs.
+//!   Blobs that hold symlinks or executable files are marked as such, since
+//!   that's something the blob itself has no way of saying.
+//! - A **commit** points at the tree for the root of the nest, along with its
+//!   parents and a message. See the [`metadata`](crate::metadata) module for
+//!   its format.
+//! - A **tag** gives a commit a permanent name along with a message and who
+//!   created it. Most tags are just refs, so these are only used for what git
+//!   calls annotated tags.
+//!
+//! The hash is SHA-256 unless the nest was set up to use another
+//! [`hash`](crate::hash) function, which only matters here.
+//!
+//! Every object is stored with a small header containing its kind and length,
+//! followed by a null byte and then the data itself, exactly like git does.
+//! The hash is computed over the header and the data together, so a blob and a
+//! tree with the same data still get different hashes.
+//!
+//! Objects can also be stored compressed, depending on the
+//! [`compression`](crate::compression) setting, in which case the stored form
+//! is compressed as a whole, and the hash is still that of the uncompressed
+//! form.
+//!
+//! Objects start out in files of their own, which we call loose objects, until
+//! `rat repack` moves them into a [`pack`](crate::pack). Reading an object
+//! looks in both places, so nothing outside this module needs to know which.
+//! Large files can also be split into [`chunk`](crate::chunk)s, which is just
+//! as invisible. So is reading from a real [`git`](crate::git) repository
+//! instead of a nest.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::chunk;
+use crate::compare::{Entry, FileMode, Snapshot};
+use crate::compression::{self, Compression};
+use crate::error::ObjectError;
+use crate::git;
+use crate::hash::{self, ObjectHasher};
+use crate::metadata::{CommitMetadata, TagMetadata};
+use crate::pack;
+use crate::utils;
+
+/// The different kinds of object the store can hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl Display for ObjectKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Blob => "blob",
+            Self::Tree => "tree",
+            Self::Commit => "commit",
+            Self::Tag => "tag",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for ObjectKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blob" => Ok(Self::Blob),
+            "tree" => Ok(Self::Tree),
+            "commit" => Ok(Self::Commit),
+            "tag" => Ok(Self::Tag),
+            _ => Err(format!("Unknown object kind {s}.")),
+        }
+    }
+}
+
+/// What an object's header says about it, as read by [`read_header`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectHeader {
+    pub kind: ObjectKind,
+    /// How big the object's data is.
+    pub size: u64,
+    /// The hashes of the chunks a blob has been split into, in order, or
+    /// nothing if it hasn't been.
+    pub chunks: Vec<String>,
+}
+
+/// Builds the full stored form of an object: the header followed by the data.
+pub fn encode(kind: ObjectKind, data: &[u8]) -> Vec<u8> {
+    let mut encoded = format!("{kind} {}\0", data.len()).into_bytes();
+    encoded.extend_from_slice(data);
+    encoded
+}
+
+/// Computes the hash an object would have, without storing it.
+pub fn hash_object(kind: ObjectKind, data: &[u8]) -> String {
+    hash_object_in(crate::nest_dir(), kind, data)
+}
+
+/// Computes the hash an object would have in the nest directory `nest`.
+pub fn hash_object_in(nest: &Path, kind: ObjectKind, data: &[u8]) -> String {
+    hash::algorithm_in(nest).hash(&encode(kind, data))
+}
+
+/// How much of a file we read at a time when streaming it through the hasher.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Computes the hash the file at `path` would have as a blob, without storing
+/// it. The file is read a piece at a time, so even huge files never have to
+/// fit in memory.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = hash::current().hasher();
+    encode_file(path, &mut hasher)?;
+
+    Ok(hasher.finish_hex())
+}
+
+/// Stores the file at `path` as a blob, returning its hash. Like
+/// [`hash_file`], this streams the file rather than reading it all at once.
+pub fn write_file(path: &Path) -> Result<String, ObjectError> {
+    write_file_in(crate::nest_dir(), path)
+}
+
+/// Stores the file at `path` as a blob in the nest directory `nest`.
+pub fn write_file_in(nest: &Path, path: &Path) -> Result<String, ObjectError> {
+    let io_error = |path: &Path| {
+        let path = path.to_path_buf();
+
+        move |source| ObjectError::Io { path, source }
+    };
+
+    let file = File::open(path).map_err(io_error(path))?;
+    let length = file.metadata().map_err(io_error(path))?.len();
+
+    if chunk::should_chunk(length) {
+        return write_chunked_in(nest, path, file.take(length), length);
+    }
+
+    // Compressing needs the whole file at once, so there's no streaming it.
+    if compression::current() != Compression::None {
+        let data = fs::read(path).map_err(io_error(path))?;
+
+        return write_object_in(nest, ObjectKind::Blob, &data);
+    }
+
+    // Several files can be written at once, so every temporary file needs a
+    // name of its own.
+    static NEXT_TEMPORARY: AtomicUsize = AtomicUsize::new(0);
+
+    // We don't know the hash, and so where the object goes, until we've read
+    // the whole file. So we write it somewhere temporary as we go, then move
+    // it into place at the end.
+    let temporary_path = nest.join("objects").join(format!(
+        "incoming-{}-{}",
+        process::id(),
+        NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let hash = write_temporary(nest, path, &temporary_path).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary_path);
+    })?;
+
+    let object_path = object_path_in(nest, &hash);
+
+    if has_object_in(nest, &hash) {
+        fs::remove_file(&temporary_path).map_err(io_error(&temporary_path))?;
+    } else {
+        // The parent of an object path is always its subdirectory.
+        if let Some(parent) = object_path.parent() {
+            fs::create_dir_all(parent).map_err(io_error(parent))?;
+        }
+
+        fs::rename(&temporary_path, &object_path).map_err(io_error(&object_path))?;
+    }
+
+    Ok(hash)
+}
+
+/// Stores the `length` bytes that `reader` gives us as a blob split into
+/// chunks, returning its hash. `path` is where they're coming from, for when
+/// something goes wrong.
+///
+/// Each chunk is a blob of its own, and the stored form of the whole blob
+/// lists their hashes in order, like this:
+///
+/// ```text
+/// chunked 1048576\09f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+/// 60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752
+/// ```
+fn write_chunked_in(
+    nest: &Path,
+    path: &Path,
+    reader: impl Read,
+    length: u64,
+) -> Result<String, ObjectError> {
+    // The hash is the same as it would be for the blob stored in one piece, so
+    // we work it out as the chunks go by.
+    let mut hasher = hash::algorithm_in(nest).hasher();
+    hasher.update(format!("{} {length}\0", ObjectKind::Blob).as_bytes());
+
+    let mut chunk_hashes = Vec::new();
+    let mut read = 0;
+
+    for chunk in chunk::chunks(reader) {
+        let chunk = chunk.map_err(|source| ObjectError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        hasher.update(&chunk);
+        read += chunk.len() as u64;
+
+        // A chunk is never big enough to be split up itself.
+        chunk_hashes.push(write_object_in(nest, ObjectKind::Blob, &chunk)?);
+    }
+
+    if read != length {
+        return Err(ObjectError::Io {
+            path: path.to_path_buf(),
+            source: io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} got shorter while it was being read.", path.display()),
+            ),
+        });
+    }
+
+    let hash = hasher.finish_hex();
+
+    if !has_object_in(nest, &hash) {
+        let object_path = object_path_in(nest, &hash);
+        let contents = format!("{CHUNKED_KIND} {length}\0{}", chunk_hashes.join("\n"));
+
+        // The parent of an object path is always its subdirectory.
+        if let Some(parent) = object_path.parent() {
+            fs::create_dir_all(parent).map_err(|source| ObjectError::Io {
+                path: parent.to_path_buf(),
+                source,
+            })?;
+        }
+
+        // The chunks are all written already, so the list never points at
+        // anything that's missing.
+        utils::write_atomically(&object_path, contents).map_err(|source| ObjectError::Io {
+            path: object_path,
+            source,
+        })?;
+    }
+
+    Ok(hash)
+}
+
+/// What the stored form of a blob split into chunks starts with, in place of
+/// its kind.
+const CHUNKED_KIND: &str = "chunked";
+
+/// Checks whether the object with the given hash in the nest directory `nest`
+/// is a blob split into chunks.
+pub fn is_chunked_in(nest: &Path, hash: &str) -> bool {
+    let mut start = [0; CHUNKED_KIND.len() + 1];
+
+    File::open(object_path_in(nest, hash))
+        .and_then(|mut file| file.read_exact(&mut start))
+        .is_ok_and(|()| start == *format!("{CHUNKED_KIND} ").as_bytes())
+}
+
+/// Puts a blob split into chunks back together from the stored form that
+/// lists them, checking that the result really does have the given hash.
+fn read_chunked_in(
+    nest: &Path,
+    hash: &str,
+    encoded: &[u8],
+) -> Result<(ObjectKind, Vec<u8>), ObjectError> {
+    let malformed = |reason: &str| ObjectError::Malformed {
+        hash: hash.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let (length, chunk_hashes) = split_chunk_list(hash, encoded)?;
+    let length = length as usize;
+    let mut data = Vec::new();
+
+    for chunk_hash in chunk_hashes {
+        match read_object_in(nest, chunk_hash)? {
+            (ObjectKind::Blob, chunk) => data.extend(chunk),
+            _ => Err(malformed("one of its chunks isn't a blob"))?,
+        }
+
+        if data.len() > length {
+            break;
+        }
+    }
+
+    if data.len() != length {
+        Err(malformed("its chunks have the wrong length"))?;
+    }
+
+    if hash_object_in(nest, ObjectKind::Blob, &data) != hash {
+        Err(ObjectError::Corrupt {
+            hash: hash.to_string(),
+        })?;
+    }
+
+    Ok((ObjectKind::Blob, data))
+}
+
+/// Splits the stored form of a blob split into chunks into its length and
+/// the hashes of its chunks.
+fn split_chunk_list<'a>(hash: &str, encoded: &'a [u8]) -> Result<(u64, Vec<&'a str>), ObjectError> {
+    let malformed = |reason: &str| ObjectError::Malformed {
+        hash: hash.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let (length, chunk_hashes) = std::str::from_utf8(encoded)
+        .ok()
+        .and_then(|encoded| encoded.strip_prefix(CHUNKED_KIND)?.strip_prefix(' '))
+        .and_then(|rest| rest.split_once('\0'))
+        .ok_or_else(|| malformed("it has an invalid list of chunks"))?;
+
+    let length = length
+        .parse()
+        .map_err(|_| malformed("it has an invalid length"))?;
+
+    Ok((length, chunk_hashes.lines().collect()))
+}
+
+/// Writes the stored form of the file at `path` as a blob to
+/// `temporary_path`, returning the hash it has in the nest directory `nest`.
+fn write_temporary(nest: &Path, path: &Path, temporary_path: &Path) -> Result<String, ObjectError> {
+    let file = File::create(temporary_path).map_err(|source| ObjectError::Io {
+        path: temporary_path.to_path_buf(),
+        source,
+    })?;
+
+    let mut writer = HashingWriter {
+        inner: BufWriter::with_capacity(BUFFER_SIZE, file),
+        hasher: hash::algorithm_in(nest).hasher(),
+    };
+
+    // Like every other object, the blob has to be safely on the disk before
+    // anything can refer to it.
+    encode_file(path, &mut writer)
+        .and_then(|()| writer.inner.flush())
+        .and_then(|()| writer.inner.get_ref().sync_all())
+        .map_err(|source| ObjectError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+    Ok(writer.hasher.finish_hex())
+}
+
+/// Writes the stored form of the file at `path` as a blob to `out`, which is
+/// exactly what [`encode`] would give us, just a piece at a time.
+fn encode_file(path: &Path, out: &mut impl Write) -> io::Result<()> {
+    let file = File::open(path)?;
+    let length = file.metadata()?.len();
+
+    // The header needs the length up front, so if the file grows while we're
+    // reading it, we stop at the length we started with.
+    write!(out, "{} {length}\0", ObjectKind::Blob)?;
+
+    let copied = io::copy(
+        &mut BufReader::with_capacity(BUFFER_SIZE, file.take(length)),
+        out,
+    )?;
+
+    if copied != length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} got shorter while it was being read.", path.display()),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Passes everything written to it on to `inner`, hashing it along the way.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Box<dyn ObjectHasher>,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Finds where the object with the given hash is stored. Just like git, we
+/// use the first two characters of the hash as a subdirectory, so that we don't
+/// end up with a single directory containing a huge number of files, which
+/// many filesystems struggle with.
+///
+/// `nest` is the nest directory to look in, which is usually our own `.rat`,
+/// but can be somebody else's when we're sharing history between nests.
+fn object_path_in(nest: &Path, hash: &str) -> PathBuf {
+    let (directory, file) = hash.split_at(2.min(hash.len()));
+
+    nest.join("objects").join(directory).join(file)
+}
+
+/// Checks whether the nest directory `nest` has the object with the given
+/// hash, either loose or packed.
+pub fn has_object_in(nest: &Path, hash: &str) -> bool {
+    if let Some(git) = git::open_nest(nest) {
+        return git.has_object(hash);
+    }
+
+    object_path_in(nest, hash).is_file() || pack::has_packed_object_in(nest, hash)
+}
+
+/// Lists the hashes of every loose object in the nest directory `nest`.
+pub fn list_loose_objects_in(nest: &Path) -> Result<Vec<String>, ObjectError> {
+    let objects_dir = nest.join("objects");
+    let mut hashes = Vec::new();
+
+    let io_error = |path: &Path| {
+        let path = path.to_path_buf();
+
+        move |source| ObjectError::Io { path, source }
+    };
+
+    for dir_entry in fs::read_dir(&objects_dir).map_err(io_error(&objects_dir))? {
+        let directory = dir_entry.map_err(io_error(&objects_dir))?.path();
+
+        // Besides the subdirectories for each pair of characters, there's
+        // also the pack directory and any temporary files, which we skip.
+        let Some(prefix) = directory
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|name| name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()))
+        else {
+            continue;
+        };
+
+        if !directory.is_dir() {
+            continue;
+        }
+
+        for dir_entry in fs::read_dir(&directory).map_err(io_error(&directory))? {
+            let file_name = dir_entry
+                .map_err(io_error(&directory))?
+                .file_name()
+                .to_string_lossy()
+                .into_owned();
+
+            hashes.push(format!("{prefix}{file_name}"));
+        }
+    }
+
+    hashes.sort();
+
+    Ok(hashes)
+}
+
+/// Deletes the loose copy of the object with the given hash from the nest
+/// directory `nest`, once it's safely in a pack.
+pub fn remove_loose_object_in(nest: &Path, hash: &str) -> Result<(), ObjectError> {
+    let path = object_path_in(nest, hash);
+
+    fs::remove_file(&path).map_err(|source| ObjectError::Io {
+        path: path.clone(),
+        source,
+    })?;
+
+    // Once the subdirectory is empty, there's no reason to keep it around. If
+    // it isn't, removing it fails, which is fine.
+    if let Some(parent) = path.parent() {
+        let _ = fs::remove_dir(parent);
+    }
+
+    Ok(())
+}
+
+/// Lists the hashes of every stored object whose hash starts with `prefix`.
+/// Prefixes shorter than two characters never match anything.
+pub fn find_objects_with_prefix(prefix: &str) -> Result<Vec<String>, ObjectError> {
+    if prefix.len() < 2 {
+        return Ok(Vec::new());
+    }
+
+    if let Some(git) = git::open_nest(crate::nest_dir()) {
+        return Ok(git.find_objects_with_prefix(prefix));
+    }
+
+    // Because objects are stored in subdirectories named after the first two
+    // characters of their hash, we only ever need to look in one of them.
+    let (directory, file_prefix) = prefix.split_at(2);
+    let directory = crate::nest_path("objects").join(directory);
+
+    // Packed objects are sorted by hash too, but there are never so many of
+    // them that it's worth being clever.
+    let mut hashes: Vec<String> = pack::list_packed_objects_in(crate::nest_dir())?
+        .into_iter()
+        .filter(|hash| hash.starts_with(prefix))
+        .collect();
+
+    if !directory.is_dir() {
+        return Ok(hashes);
+    }
+
+    let io_error = |source| ObjectError::Io {
+        path: directory.clone(),
+        source,
+    };
+
+    for dir_entry in fs::read_dir(&directory).map_err(io_error)? {
+        let file_name = dir_entry
+            .map_err(io_error)?
+            .file_name()
+            .to_string_lossy()
+            .into_owned();
+
+        if file_name.starts_with(file_prefix) {
+            hashes.push(format!("{}{file_name}", &prefix[..2]));
+        }
+    }
+
+    hashes.sort();
+    hashes.dedup();
+
+    Ok(hashes)
+}
+
+/// Stores an object, returning its hash. If an object with the same hash
+/// already exists, there's nothing to do, since it must have the same contents.
+pub fn write_object(kind: ObjectKind, data: &[u8]) -> Result<String, ObjectError> {
+    write_object_in(crate::nest_dir(), kind, data)
+}
+
+/// Stores an object in the nest directory `nest`, returning its hash.
+pub fn write_object_in(nest: &Path, kind: ObjectKind, data: &[u8]) -> Result<String, ObjectError> {
+    if kind == ObjectKind::Blob && chunk::should_chunk(data.len() as u64) {
+        // Reading from memory can't go wrong, so there's no path to blame.
+        return write_chunked_in(nest, Path::new(""), data, data.len() as u64);
+    }
+
+    let encoded = encode(kind, data);
+    let hash = hash::algorithm_in(nest).hash(&encoded);
+
+    if !has_object_in(nest, &hash) {
+        let path = object_path_in(nest, &hash);
+
+        // The parent of an object path is always its subdirectory.
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|source| ObjectError::Io {
+                path: parent.to_path_buf(),
+                source,
+            })?;
+        }
+
+        let stored = compression::current().compress(&encoded).unwrap_or(encoded);
+
+        // Objects are written before anything refers to them, so that a crash
+        // can never leave a branch pointing at a half-written commit.
+        utils::write_atomically(&path, stored)
+            .map_err(|source| ObjectError::Io { path, source })?;
+    }
+
+    Ok(hash)
+}
+
+/// Reads the object with the given hash, returning its kind and data.
+pub fn read_object(hash: &str) -> Result<(ObjectKind, Vec<u8>), ObjectError> {
+    read_object_in(crate::nest_dir(), hash)
+}
+
+/// Reads the object with the given hash from the nest directory `nest`.
+pub fn read_object_in(nest: &Path, hash: &str) -> Result<(ObjectKind, Vec<u8>), ObjectError> {
+    if let Some(git) = git::open_nest(nest) {
+        if !git.has_object(hash) {
+            return Err(ObjectError::NotFound {
+                hash: hash.to_string(),
+            });
+        }
+
+        return git
+            .read_object_as_nest(hash)
+            .map_err(|e| ObjectError::Malformed {
+                hash: hash.to_string(),
+                reason: e.to_string(),
+            });
+    }
+
+    let path = object_path_in(nest, hash);
+
+    match fs::read(&path) {
+        // The list of chunks is always stored as it is, since it's nothing
+        // but hashes, which don't compress.
+        Ok(stored) if stored.starts_with(format!("{CHUNKED_KIND} ").as_bytes()) => {
+            read_chunked_in(nest, hash, &stored)
+        }
+        Ok(stored) => decode_in(nest, hash, &stored),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => pack::read_packed_object_in(nest, hash)?
+            .ok_or_else(|| ObjectError::NotFound {
+                hash: hash.to_string(),
+            }),
+        Err(source) => Err(ObjectError::Io { path, source }),
+    }
+}
+
+/// Splits the stored form of the object with the given hash back into its kind
+/// and data, checking that it really does have that hash.
+pub fn decode(hash: &str, stored: &[u8]) -> Result<(ObjectKind, Vec<u8>), ObjectError> {
+    decode_in(crate::nest_dir(), hash, stored)
+}
+
+/// Does the same as [`decode`] for an object from the nest directory `nest`,
+/// whose hash function might not be the same as ours.
+pub fn decode_in(
+    nest: &Path,
+    hash: &str,
+    stored: &[u8],
+) -> Result<(ObjectKind, Vec<u8>), ObjectError> {
+    let malformed = |reason: &str| ObjectError::Malformed {
+        hash: hash.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let encoded =
+        compression::decompress(stored).ok_or_else(|| malformed("it can't be decompressed"))?;
+    let encoded = &encoded[..];
+
+    // Since the name of an object is the hash of its contents, we can easily
+    // check that it hasn't been corrupted or tampered with.
+    if hash::algorithm_in(nest).hash(encoded) != hash {
+        return Err(ObjectError::Corrupt {
+            hash: hash.to_string(),
+        });
+    }
+
+    let (kind, length, data) = split_header(hash, encoded)?;
+
+    if length != data.len() as u64 {
+        Err(malformed("it has the wrong length"))?;
+    }
+
+    Ok((kind, data.to_vec()))
+}
+
+/// Splits the encoded form of an object into the kind and length its header
+/// gives, and the data after it.
+fn split_header<'a>(
+    hash: &str,
+    encoded: &'a [u8],
+) -> Result<(ObjectKind, u64, &'a [u8]), ObjectError> {
+    let malformed = |reason: &str| ObjectError::Malformed {
+        hash: hash.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let header_end = encoded
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| malformed("it has no header"))?;
+
+    let (kind, length) = std::str::from_utf8(&encoded[..header_end])
+        .ok()
+        .and_then(|header| header.split_once(' '))
+        .ok_or_else(|| malformed("it has an invalid header"))?;
+
+    let kind: ObjectKind = kind.parse().map_err(|e: String| malformed(&e))?;
+    let length = length
+        .parse()
+        .map_err(|_| malformed("it has an invalid length"))?;
+
+    Ok((kind, length, &encoded[header_end + 1..]))
+}
+
+/// The most a header can take up: the longest kind, a space, the longest
+/// length and the zero byte after it.
+const MAX_HEADER_SIZE: u64 = 32;
+
+/// Reads what the header of the object with the given hash says about it,
+/// without reading the rest of it where we can help it.
+pub fn read_header(hash: &str) -> Result<ObjectHeader, ObjectError> {
+    read_header_in(crate::nest_dir(), hash)
+}
+
+/// Does the same as [`read_header`] for the nest directory `nest`.
+///
+/// A blob split into chunks isn't put back together, and a packed delta isn't
+/// applied, so unlike [`read_object_in`], nothing here checks the object
+/// against its hash.
+pub fn read_header_in(nest: &Path, hash: &str) -> Result<ObjectHeader, ObjectError> {
+    let header = |kind, size| ObjectHeader {
+        kind,
+        size,
+        chunks: Vec::new(),
+    };
+
+    if git::open_nest(nest).is_some() {
+        let (kind, data) = read_object_in(nest, hash)?;
+
+        return Ok(header(kind, data.len() as u64));
+    }
+
+    let path = object_path_in(nest, hash);
+    let io_error = |source| ObjectError::Io {
+        path: path.clone(),
+        source,
+    };
+
+    let mut file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return pack::read_packed_header_in(nest, hash)?
+                .map(|(kind, size)| header(kind, size))
+                .ok_or_else(|| ObjectError::NotFound {
+                    hash: hash.to_string(),
+                });
+        }
+        Err(source) => return Err(io_error(source)),
+    };
+
+    let mut stored = Vec::new();
+    Read::by_ref(&mut file)
+        .take(MAX_HEADER_SIZE)
+        .read_to_end(&mut stored)
+        .map_err(io_error)?;
+
+    // A list of chunks is nothing but hashes, and a compressed object has to
+    // be decompressed as a whole, so both need the rest of the file.
+    let chunked = stored.starts_with(format!("{CHUNKED_KIND} ").as_bytes());
+
+    if chunked || compression::is_compressed(&stored) {
+        file.read_to_end(&mut stored).map_err(io_error)?;
+    }
+
+    if chunked {
+        let (size, chunks) = split_chunk_list(hash, &stored)?;
+
+        return Ok(ObjectHeader {
+            kind: ObjectKind::Blob,
+            size,
+            chunks: chunks.into_iter().map(str::to_string).collect(),
+        });
+    }
+
+    let encoded = compression::decompress(&stored).ok_or_else(|| ObjectError::Malformed {
+        hash: hash.to_string(),
+        reason: "it can't be decompressed".to_string(),
+    })?;
+    let (kind, size, _) = split_header(hash, &encoded)?;
+
+    Ok(header(kind, size))
+}
+
+/// Reads an object, making sure it's of the expected kind.
+pub fn read_object_of_kind(hash: &str, expected: ObjectKind) -> Result<Vec<u8>, ObjectError> {
+    let (found, data) = read_object(hash)?;
+
+    if found != expected {
+        Err(ObjectError::WrongKind {
+            hash: hash.to_string(),
+            expected,
+            found,
+        })?;
+    }
+
+    Ok(data)
+}
+
+/// Reads and parses the commit object with the given hash.
+pub fn read_commit(hash: &str) -> Result<CommitMetadata, ObjectError> {
+    let data = read_object_of_kind(hash, ObjectKind::Commit)?;
+
+    parse_text(hash, data, CommitMetadata::parse)
+}
+
+/// Reads and parses the tag object with the given hash.
+pub fn read_tag(hash: &str) -> Result<TagMetadata, ObjectError> {
+    let data = read_object_of_kind(hash, ObjectKind::Tag)?;
+
+    parse_text(hash, data, TagMetadata::parse)
+}
+
+/// Parses the data of the object with the given hash with `parse`, which
+/// expects text.
+fn parse_text<T>(
+    hash: &str,
+    data: Vec<u8>,
+    parse: impl FnOnce(&str) -> Result<T, Box<dyn Error>>,
+) -> Result<T, ObjectError> {
+    // A git repository's commits and tags can be in another encoding, which
+    // they say in a header of their own.
+    let text = match git::open_nest(crate::nest_dir()) {
+        Some(_) => Ok(git::decode_text(&data).into_owned()),
+        None => String::from_utf8(data).map_err(Box::from),
+    };
+
+    text.and_then(|text| parse(&text))
+        .map_err(|e| ObjectError::Malformed {
+            hash: hash.to_string(),
+            reason: e.to_string(),
+        })
+}
+
+/// Follows tag objects starting from `hash` until it reaches a commit, and
+/// returns the hash of that commit. If `hash` is already a commit, it's
+/// returned as it is.
+pub fn peel_to_commit(hash: &str) -> Result<String, ObjectError> {
+    let mut hash = hash.to_string();
+
+    loop {
+        match read_object(&hash)?.0 {
+            ObjectKind::Commit => return Ok(hash),
+            // A tag can point at another tag, so we keep going until we find
+            // something that isn't one.
+            ObjectKind::Tag => hash = read_tag(&hash)?.object,
+            found => {
+                return Err(ObjectError::WrongKind {
+                    hash,
+                    expected: ObjectKind::Commit,
+                    found,
+                })
+            }
+        }
+    }
+}
+
+/// A single line in a tree object, naming either a blob or another tree.
+///
+/// Trees are stored as text, with one entry per line:
+///
+/// ```text
+/// blob 9f86d08... notes.txt
+/// executable 2cf24db... build.sh
+/// symlink fcde2b2... latest
+/// nest 4e07408... vendor/parser
+/// tree 2c26b46... src
+/// ```
+///
+/// Anything other than `tree` is the [`FileMode`] of a blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub kind: ObjectKind,
+    /// Only means anything for blobs.
+    pub mode: FileMode,
+    pub hash: String,
+    pub name: String,
+}
+
+/// Lists the hashes of every object a tree refers to directly. The commits
+/// nests inside this one are pinned to are left out, since they're in those
+/// nests instead.
+pub fn tree_children(data: &[u8]) -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(parse_tree(data)?
+        .into_iter()
+        .filter(|entry| entry.mode != FileMode::Nest)
+        .map(|entry| entry.hash)
+        .collect())
+}
+
+/// Reads the entries of the tree with the given hash, without looking inside
+/// any of its subtrees.
+pub fn read_tree_entries(hash: &str) -> Result<Vec<TreeEntry>, ObjectError> {
+    let data = read_object_of_kind(hash, ObjectKind::Tree)?;
+
+    parse_tree(&data).map_err(|e| ObjectError::Malformed {
+        hash: hash.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+/// Checks whether `name` can be the name of an entry in a tree.
+///
+/// Trees come from other nests too, and their names end up as paths in the
+/// working directory, so a name like `..` or `a/b` could have a checkout write
+/// files outside of it, and `.rat` would let it replace the nest itself,
+/// hooks and all. Names with line breaks can't be written down in a tree at
+/// all, and null bytes can't be in a path.
+pub fn is_valid_tree_name(name: &str) -> bool {
+    // Windows takes backslashes as separators too.
+    let is_separator = |c| c == '/' || (cfg!(windows) && c == '\\');
+
+    !matches!(name, "" | "." | "..")
+        && !name.eq_ignore_ascii_case(crate::RAT_NEST)
+        && !name.contains(|c| is_separator(c) || c == '\0' || c == '\n')
+}
+
+fn parse_tree(data: &[u8]) -> Result<Vec<TreeEntry>, Box<dyn Error>> {
+    let mut names = HashSet::new();
+
+    std::str::from_utf8(data)?
+        .lines()
+        .map(|line| {
+            // We only split twice so that names containing spaces survive.
+            let mut parts = line.splitn(3, ' ');
+
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(kind), Some(hash), Some(name)) => {
+                    if !is_valid_tree_name(name) {
+                        Err(format!("Invalid name in tree entry: {line:?}"))?;
+                    }
+
+                    // Two entries with the same name could make a symlink and
+                    // a directory of the same name, and have files written
+                    // through the symlink.
+                    if !names.insert(name) {
+                        Err(format!("Duplicate name in tree entry: {line:?}"))?;
+                    }
+
+                    let (kind, mode) = match kind.parse() {
+                        Ok(mode) => (ObjectKind::Blob, mode),
+                        Err(_) => (kind.parse()?, FileMode::Regular),
+                    };
+
+                    Ok(TreeEntry {
+                        kind,
+                        mode,
+                        hash: hash.to_string(),
+                        name: name.to_string(),
+                    })
+                }
+                _ => Err(format!("Invalid tree entry: {line}").into()),
+            }
+        })
+        .collect()
+}
+
+/// Stores the tree objects needed to represent `snapshot`, which maps paths to
+/// blobs, and returns the hash of the root tree.
+///
+/// Since trees can only describe a single directory, we need a separate tree
+/// for every subdirectory, each of which is referenced by its parent.
+pub fn write_tree(snapshot: &Snapshot) -> Result<String, ObjectError> {
+    let entries = snapshot
+        .iter()
+        .map(|(path, entry)| (path.as_str(), entry))
+        .collect();
+
+    write_subtree(entries)
+}
+
+fn write_subtree(entries: Vec<(&str, &Entry)>) -> Result<String, ObjectError> {
+    // Split the entries into the files directly inside this directory and the
+    // ones inside subdirectories, grouped by which subdirectory they're in.
+    let mut files = BTreeMap::new();
+    let mut directories: BTreeMap<&str, Vec<(&str, &Entry)>> = BTreeMap::new();
+
+    for (path, entry) in entries {
+        // Whatever we write has to read back the same, so a name that reading
+        // would refuse mustn't be written in the first place.
+        let name = path.split('/').next().unwrap_or(path);
+
+        if !is_valid_tree_name(name) {
+            Err(ObjectError::InvalidName {
+                name: name.to_string(),
+            })?;
+        }
+
+        match path.split_once('/') {
+            Some((directory, rest)) => directories
+                .entry(directory)
+                .or_default()
+                .push((rest, entry)),
+            None => {
+                files.insert(path, entry);
+            }
+        }
+    }
+
+    // Each subdirectory becomes its own tree, which we have to write first so
+    // we know its hash. Files are written with their mode, which is just
+    // `blob` for ordinary ones.
+    let mut tree_entries = BTreeMap::new();
+
+    for (name, entry) in files {
+        tree_entries.insert(name, (entry.mode.to_string(), entry.hash.clone()));
+    }
+
+    for (name, subentries) in directories {
+        tree_entries.insert(
+            name,
+            (ObjectKind::Tree.to_string(), write_subtree(subentries)?),
+        );
+    }
+
+    let data = tree_entries
+        .into_iter()
+        .map(|(name, (kind, hash))| format!("{kind} {hash} {name}\n"))
+        .collect::<String>();
+
+    write_object(ObjectKind::Tree, data.as_bytes())
+}
+
+/// Reads the tree with the given hash, along with all of its subtrees, and
+/// flattens it back into a snapshot mapping paths to blobs.
+pub fn read_tree(hash: &str) -> Result<Snapshot, ObjectError> {
+    let mut snapshot = Snapshot::new();
+    read_tree_into(hash, "", &mut snapshot)?;
+
+    Ok(snapshot)
+}
+
+/// Finds the file at `path` in the tree with the given hash, without reading
+/// any more of the tree than it takes to get there.
+pub fn read_tree_entry(hash: &str, path: &str) -> Result<Option<Entry>, ObjectError> {
+    let mut tree = hash.to_string();
+    let mut names = path.split('/').peekable();
+
+    while let Some(name) = names.next() {
+        let Some(entry) = read_tree_entries(&tree)?
+            .into_iter()
+            .find(|entry| entry.name == name)
+        else {
+            return Ok(None);
+        };
+
+        match (entry.kind, names.peek()) {
+            (ObjectKind::Blob, None) => {
+                return Ok(Some(Entry {
+                    mode: entry.mode,
+                    hash: entry.hash,
+                }))
+            }
+            (ObjectKind::Tree, Some(_)) => tree = entry.hash,
+            // Either we've found a directory where we wanted a file, or a
+            // file where we wanted a directory, and neither is what we're
+            // looking for.
+            _ => return Ok(None),
+        }
+    }
+
+    Ok(None)
+}
+
+fn read_tree_into(hash: &str, prefix: &str, snapshot: &mut Snapshot) -> Result<(), ObjectError> {
+    let data = read_object_of_kind(hash, ObjectKind::Tree)?;
+    let malformed = |reason: String| ObjectError::Malformed {
+        hash: hash.to_string(),
+        reason,
+    };
+
+    for entry in parse_tree(&data).map_err(|e| malformed(e.to_string()))? {
+        let path = format!("{prefix}{}", entry.name);
+
+        match entry.kind {
+            ObjectKind::Blob => {
+                snapshot.insert(
+                    path,
+                    Entry {
+                        mode: entry.mode,
+                        hash: entry.hash,
+                    },
+                );
+            }
+            ObjectKind::Tree => read_tree_into(&entry.hash, &format!("{path}/"), snapshot)?,
+            kind @ (ObjectKind::Commit | ObjectKind::Tag) => {
+                Err(malformed(format!("it contains a {kind}")))?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tree_entries() {
+        let entries = parse_tree(b"executable 2cf24db build.sh\ntree 2c26b46 my src\n").unwrap();
+
+        assert_eq!(
+            entries,
+            [
+                TreeEntry {
+                    kind: ObjectKind::Blob,
+                    mode: FileMode::Executable,
+                    hash: "2cf24db".to_string(),
+                    name: "build.sh".to_string(),
+                },
+                TreeEntry {
+                    kind: ObjectKind::Tree,
+                    mode: FileMode::Regular,
+                    hash: "2c26b46".to_string(),
+                    name: "my src".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_names_that_would_escape_the_working_directory() {
+        for name in ["", ".", "..", "../pwned", "a/b", ".rat", ".RAT", "a\0b"] {
+            assert!(!is_valid_tree_name(name), "{name:?}");
+            assert!(
+                parse_tree(format!("blob 9f86d08 {name}\n").as_bytes()).is_err(),
+                "{name:?}"
+            );
+        }
+
+        for name in ["...", ".rats", "a b", "..a"] {
+            assert!(is_valid_tree_name(name), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        assert!(parse_tree(b"symlink 9f86d08 a\ntree 2c26b46 a\n").is_err());
+    }
+
+    #[test]
+    fn refuses_to_write_names_it_could_not_read_back() {
+        for path in ["a\nb", "dir/a\nb", "a//b"] {
+            let snapshot = Snapshot::from([(
+                path.to_string(),
+                Entry {
+                    mode: FileMode::Regular,
+                    hash: "9f86d08".to_string(),
+                },
+            )]);
+
+            assert!(
+                matches!(write_tree(&snapshot), Err(ObjectError::InvalidName { .. })),
+                "{path:?}"
+            );
+        }
+    }
+}
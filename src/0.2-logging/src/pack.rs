@@ -0,0 +1,735 @@
+//! Packs, which store lots of objects together in a single file.
+//!
+//! Every object starts out as a file of its own in `.rat/objects`, which is
+//! simple, but wasteful: each version of a file is stored in full, even when
+//! it only differs from the last one by a line. `rat repack` gathers the
+//! objects up into a pack in `.rat/objects/pack`, where an object can be
+//! stored as a [`delta`](crate::delta) against a similar one instead.
+//!
+//! A pack is two files, named after the hash of the pack itself:
+//!
+//! - `pack-<hash>.pack` holds the objects. It starts with `RATPACK`, a version
+//!   byte and the number of objects as four bytes. Each object is then a byte
+//!   for its kind, a varint length and its data. If the top bit of the kind is
+//!   set, the data is a delta instead, and the 32-byte hash of its base comes
+//!   before the length. The base is always in the same pack. If the next bit
+//!   down is set, the data, delta or not, is
+//!   [`compress`](crate::compression)ed, and the length is of what's stored.
+//!   Last comes the SHA-256 hash of everything before it.
+//! - `pack-<hash>.idx` lets us find an object in the pack without reading the
+//!   whole thing. It starts with `RATIDX`, a version byte and the number of
+//!   objects, then has the hash of each object and where it starts in the
+//!   pack, as eight bytes, sorted by hash.
+//!
+//! Reading an object looks for its own file first and then in the packs, so
+//! nothing else has to care where it's stored.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::compression;
+use crate::delta;
+use crate::error::{ObjectError, RefError};
+use crate::objects::{self, ObjectKind};
+use crate::refs::{self, Ref};
+use crate::utils::{self, Sha256};
+
+const PACK_SIGNATURE: &[u8] = b"RATPACK\x01";
+const INDEX_SIGNATURE: &[u8] = b"RATIDX\x01";
+
+/// The top bit of an object's kind byte, which is set for deltas.
+const DELTA_FLAG: u8 = 0x80;
+
+/// The next bit of an object's kind byte, which is set if its data is
+/// compressed.
+const COMPRESSED_FLAG: u8 = 0x40;
+
+/// How many of the objects before each one we try as its delta base. Similar
+/// objects end up next to each other, so there's not much point looking
+/// further back than this.
+const DELTA_WINDOW: usize = 10;
+
+/// The longest chain of deltas we allow, since reading an object means
+/// reading every base along the chain first.
+const MAX_DELTA_DEPTH: usize = 10;
+
+/// Objects smaller than this are stored in full, since a delta can't save much.
+const MIN_DELTA_SIZE: usize = 64;
+
+/// An object about to go into a pack.
+#[derive(Debug, Clone)]
+pub struct PackObject {
+    pub hash: String,
+    pub kind: ObjectKind,
+    pub data: Vec<u8>,
+    /// The path the object was last seen at, if it's a file or a directory.
+    /// Versions of the same file are usually the most similar, so objects
+    /// with the same path are tried as each other's bases first.
+    pub path_hint: String,
+}
+
+/// What went into a newly written pack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackSummary {
+    /// The hash the pack is named after.
+    pub name: String,
+    pub objects: usize,
+    /// How many of the objects were stored as deltas.
+    pub deltas: usize,
+}
+
+/// The index of a pack, which says where each of its objects is.
+#[derive(Debug)]
+struct Pack {
+    name: String,
+    path: PathBuf,
+    /// The hash of each object and where it starts, sorted by hash.
+    entries: Vec<(String, u64)>,
+}
+
+impl Pack {
+    fn offset(&self, hash: &str) -> Option<u64> {
+        let index = self
+            .entries
+            .binary_search_by(|(entry, _)| entry.as_str().cmp(hash))
+            .ok()?;
+
+        Some(self.entries[index].1)
+    }
+}
+
+/// The packs we've read the indexes of so far, for each nest directory.
+static PACKS: OnceLock<Mutex<HashMap<PathBuf, Arc<Vec<Pack>>>>> = OnceLock::new();
+
+fn pack_dir_in(nest: &Path) -> PathBuf {
+    nest.join("objects").join("pack")
+}
+
+/// Finds the packs in the nest directory `nest`. Their indexes are only read
+/// the first time, unless `rescan` is set, in which case we look again for any
+/// that have appeared since.
+fn packs_in(nest: &Path, rescan: bool) -> Result<Arc<Vec<Pack>>, ObjectError> {
+    let mut cache = PACKS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    if let Some(packs) = cache.get(nest) {
+        if !rescan {
+            return Ok(packs.clone());
+        }
+    }
+
+    let dir = pack_dir_in(nest);
+    let mut names = Vec::new();
+
+    match fs::read_dir(&dir) {
+        Ok(entries) => {
+            for entry in entries {
+                let entry = entry.map_err(|source| ObjectError::Io {
+                    path: dir.clone(),
+                    source,
+                })?;
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+
+                if let Some(name) = file_name
+                    .strip_prefix("pack-")
+                    .and_then(|rest| rest.strip_suffix(".idx"))
+                {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(source) => return Err(ObjectError::Io { path: dir, source }),
+    }
+
+    names.sort();
+
+    let old = cache.remove(nest).unwrap_or_default();
+    let mut packs = Vec::new();
+
+    for name in names {
+        // A pack we've already read can't have changed, since it's named
+        // after its contents.
+        match old.iter().find(|pack| pack.name == name) {
+            Some(pack) => packs.push(Pack {
+                name,
+                path: pack.path.clone(),
+                entries: pack.entries.clone(),
+            }),
+            None => packs.push(read_index(&dir, name)?),
+        }
+    }
+
+    let packs = Arc::new(packs);
+    cache.insert(nest.to_path_buf(), packs.clone());
+
+    Ok(packs)
+}
+
+/// Reads the index of the pack called `name` in `dir`.
+fn read_index(dir: &Path, name: String) -> Result<Pack, ObjectError> {
+    let index_path = dir.join(format!("pack-{name}.idx"));
+
+    let contents = fs::read(&index_path).map_err(|source| ObjectError::Io {
+        path: index_path.clone(),
+        source,
+    })?;
+
+    let damaged = || ObjectError::Io {
+        path: index_path.clone(),
+        source: io::Error::new(io::ErrorKind::InvalidData, "the pack index is damaged"),
+    };
+
+    let rest = contents.strip_prefix(INDEX_SIGNATURE).ok_or_else(damaged)?;
+    let (count, rest) = rest.split_first_chunk::<4>().ok_or_else(damaged)?;
+    let count = u32::from_be_bytes(*count) as usize;
+
+    if rest.len() != count * 40 {
+        return Err(damaged());
+    }
+
+    let entries = rest
+        .chunks_exact(40)
+        .map(|entry| {
+            let (hash, offset) = entry.split_at(32);
+
+            (
+                utils::to_hex(hash),
+                u64::from_be_bytes(offset.try_into().expect("the offset is eight bytes")),
+            )
+        })
+        .collect();
+
+    Ok(Pack {
+        path: dir.join(format!("pack-{name}.pack")),
+        name,
+        entries,
+    })
+}
+
+/// Checks whether any pack in the nest directory `nest` has the object with
+/// the given hash.
+pub fn has_packed_object_in(nest: &Path, hash: &str) -> bool {
+    packs_in(nest, false).is_ok_and(|packs| packs.iter().any(|pack| pack.offset(hash).is_some()))
+}
+
+/// Reads the object with the given hash from whichever pack in the nest
+/// directory `nest` has it, or returns `None` if none of them do.
+pub fn read_packed_object_in(
+    nest: &Path,
+    hash: &str,
+) -> Result<Option<(ObjectKind, Vec<u8>)>, ObjectError> {
+    // If we can't find it, a pack might have been written since we last
+    // looked, so it's worth looking once more.
+    for rescan in [false, true] {
+        for pack in packs_in(nest, rescan)?.iter() {
+            if pack.offset(hash).is_some() {
+                return read_from(nest, pack, hash, 0).map(Some);
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Lists the hashes of every packed object in the nest directory `nest`.
+pub fn list_packed_objects_in(nest: &Path) -> Result<Vec<String>, ObjectError> {
+    let mut hashes: Vec<String> = packs_in(nest, true)?
+        .iter()
+        .flat_map(|pack| pack.entries.iter().map(|(hash, _)| hash.clone()))
+        .collect();
+
+    hashes.sort();
+    hashes.dedup();
+
+    Ok(hashes)
+}
+
+/// Lists the names of every pack in the nest directory `nest`.
+pub fn list_packs_in(nest: &Path) -> Result<Vec<String>, ObjectError> {
+    Ok(packs_in(nest, true)?
+        .iter()
+        .map(|pack| pack.name.clone())
+        .collect())
+}
+
+/// Reads the object with the given hash out of `pack`, which is in the nest
+/// directory `nest`, where `depth` is how many deltas we've already followed
+/// to get here.
+fn read_from(
+    nest: &Path,
+    pack: &Pack,
+    hash: &str,
+    depth: usize,
+) -> Result<(ObjectKind, Vec<u8>), ObjectError> {
+    let entry = open_entry(pack, hash)?;
+    let (kind, base) = (entry.kind, entry.base.clone());
+    let mut data = entry.read_data()?;
+
+    if let Some(base) = base {
+        if depth >= MAX_DELTA_DEPTH {
+            return Err(damaged(pack, hash, "has too many deltas in a row"));
+        }
+
+        let (base_kind, base_data) = read_from(nest, pack, &base, depth + 1)?;
+
+        if base_kind != kind {
+            return Err(damaged(
+                pack,
+                hash,
+                "is a delta against a different kind of object",
+            ));
+        }
+
+        data = delta::apply(&base_data, &data)
+            .ok_or_else(|| damaged(pack, hash, "has an invalid delta"))?;
+    }
+
+    // Just like a loose object, a packed one is named after its contents, so
+    // we can check that it hasn't been damaged.
+    if objects::hash_object_in(nest, kind, &data) != hash {
+        return Err(ObjectError::Corrupt {
+            hash: hash.to_string(),
+        });
+    }
+
+    Ok((kind, data))
+}
+
+/// Finds the kind and size of the object with the given hash in whichever
+/// pack in the nest directory `nest` has it, or returns `None` if none of
+/// them do.
+///
+/// Unlike reading the object, this never follows a delta back to its base,
+/// since a delta starts with the size of what it makes. An object that's
+/// stored as it is doesn't even need its data read.
+pub fn read_packed_header_in(
+    nest: &Path,
+    hash: &str,
+) -> Result<Option<(ObjectKind, u64)>, ObjectError> {
+    for rescan in [false, true] {
+        for pack in packs_in(nest, rescan)?.iter() {
+            if pack.offset(hash).is_none() {
+                continue;
+            }
+
+            let entry = open_entry(pack, hash)?;
+            let kind = entry.kind;
+
+            if entry.base.is_none() && !entry.compressed {
+                return Ok(Some((kind, entry.length)));
+            }
+
+            let is_delta = entry.base.is_some();
+            let data = entry.read_data()?;
+
+            let size = match is_delta {
+                // The size of the base comes first, then the size of the
+                // result.
+                true => {
+                    let mut position = 0;
+                    delta::read_varint(&data, &mut position)
+                        .and_then(|_| delta::read_varint(&data, &mut position))
+                        .ok_or_else(|| damaged(pack, hash, "has an invalid delta"))?
+                }
+                false => data.len() as u64,
+            };
+
+            return Ok(Some((kind, size)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// An object's entry in a pack, read up to the start of its data.
+struct PackEntry<'a> {
+    pack: &'a Pack,
+    hash: &'a str,
+    kind: ObjectKind,
+    /// The hash of the object its data is a delta against, if it's a delta.
+    base: Option<String>,
+    compressed: bool,
+    /// How long the data is, as it's stored.
+    length: u64,
+    reader: BufReader<File>,
+}
+
+impl PackEntry<'_> {
+    /// Reads the entry's data, decompressing it if it's compressed. For a
+    /// delta, that's still the delta.
+    fn read_data(self) -> Result<Vec<u8>, ObjectError> {
+        let mut data = Vec::new();
+        self.reader
+            .take(self.length)
+            .read_to_end(&mut data)
+            .map_err(|source| ObjectError::Io {
+                path: self.pack.path.clone(),
+                source,
+            })?;
+
+        if data.len() as u64 != self.length {
+            return Err(damaged(self.pack, self.hash, "is cut short"));
+        }
+
+        if self.compressed {
+            data = compression::decompress(&data)
+                .ok_or_else(|| damaged(self.pack, self.hash, "can't be decompressed"))?
+                .into_owned();
+        }
+
+        Ok(data)
+    }
+}
+
+/// Reads the start of the entry for the object with the given hash in
+/// `pack`, up to where its data begins.
+fn open_entry<'a>(pack: &'a Pack, hash: &'a str) -> Result<PackEntry<'a>, ObjectError> {
+    let offset = pack
+        .offset(hash)
+        .ok_or_else(|| damaged(pack, hash, "points at a base that isn't in the pack"))?;
+
+    let io_error = |source| ObjectError::Io {
+        path: pack.path.clone(),
+        source,
+    };
+
+    let mut file = File::open(&pack.path).map_err(io_error)?;
+    file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+    let mut reader = BufReader::new(file);
+
+    let mut kind_byte = [0];
+    reader.read_exact(&mut kind_byte).map_err(io_error)?;
+    let kind = kind_from_byte(kind_byte[0] & !(DELTA_FLAG | COMPRESSED_FLAG))
+        .ok_or_else(|| damaged(pack, hash, "has an unknown kind"))?;
+
+    let base = if kind_byte[0] & DELTA_FLAG != 0 {
+        let mut base = [0; 32];
+        reader.read_exact(&mut base).map_err(io_error)?;
+        Some(utils::to_hex(&base))
+    } else {
+        None
+    };
+
+    let length = read_varint_from(&mut reader)
+        .map_err(io_error)?
+        .ok_or_else(|| damaged(pack, hash, "has an invalid length"))?;
+
+    Ok(PackEntry {
+        pack,
+        hash,
+        kind,
+        base,
+        compressed: kind_byte[0] & COMPRESSED_FLAG != 0,
+        length,
+        reader,
+    })
+}
+
+/// The error for an object whose entry in `pack` is damaged, as `reason`
+/// says.
+fn damaged(pack: &Pack, hash: &str, reason: &str) -> ObjectError {
+    ObjectError::Malformed {
+        hash: hash.to_string(),
+        reason: format!("its entry in pack-{} {reason}", pack.name),
+    }
+}
+
+/// Reads a varint a byte at a time, returning `None` if it doesn't fit in 64
+/// bits.
+fn read_varint_from(reader: &mut impl Read) -> io::Result<Option<u64>> {
+    let mut bytes = Vec::new();
+
+    loop {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        bytes.push(byte[0]);
+
+        if byte[0] & 0x80 == 0 || bytes.len() > 10 {
+            return Ok(delta::read_varint(&bytes, &mut 0));
+        }
+    }
+}
+
+fn kind_to_byte(kind: ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Blob => 1,
+        ObjectKind::Tree => 2,
+        ObjectKind::Commit => 3,
+        ObjectKind::Tag => 4,
+    }
+}
+
+fn kind_from_byte(byte: u8) -> Option<ObjectKind> {
+    match byte {
+        1 => Some(ObjectKind::Blob),
+        2 => Some(ObjectKind::Tree),
+        3 => Some(ObjectKind::Commit),
+        4 => Some(ObjectKind::Tag),
+        _ => None,
+    }
+}
+
+/// Writes `objects` into a new pack in the nest directory `nest`, storing as
+/// many of them as deltas as is worthwhile.
+pub fn write_pack_in(
+    nest: &Path,
+    mut objects: Vec<PackObject>,
+) -> Result<PackSummary, ObjectError> {
+    // Putting objects of the same kind and path next to each other means each
+    // one's best base is almost always within the window. Bigger objects go
+    // first, since deleting from a file makes a smaller delta than adding to
+    // it.
+    objects.sort_by(|a, b| {
+        (
+            kind_to_byte(a.kind),
+            &a.path_hint,
+            std::cmp::Reverse(a.data.len()),
+        )
+            .cmp(&(
+                kind_to_byte(b.kind),
+                &b.path_hint,
+                std::cmp::Reverse(b.data.len()),
+            ))
+    });
+    objects.dedup_by(|a, b| a.hash == b.hash);
+
+    let mut pack = PACK_SIGNATURE.to_vec();
+    pack.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+
+    let compression = compression::current();
+
+    let mut entries = Vec::new();
+    let mut depths = vec![0; objects.len()];
+    let mut deltas = 0;
+
+    for i in 0..objects.len() {
+        let object = &objects[i];
+        let mut best: Option<(usize, Vec<u8>)> = None;
+
+        if object.data.len() >= MIN_DELTA_SIZE {
+            for j in i.saturating_sub(DELTA_WINDOW)..i {
+                if objects[j].kind != object.kind || depths[j] >= MAX_DELTA_DEPTH {
+                    continue;
+                }
+
+                let delta = delta::compute(&objects[j].data, &object.data);
+
+                // A delta that doesn't save at least half isn't worth having
+                // to read the base for.
+                if delta.len() < object.data.len() / 2
+                    && best
+                        .as_ref()
+                        .is_none_or(|(_, best)| delta.len() < best.len())
+                {
+                    best = Some((j, delta));
+                }
+            }
+        }
+
+        entries.push((object.hash.clone(), pack.len() as u64));
+
+        let hash_bytes = |hash: &str| {
+            utils::from_hex(hash).ok_or_else(|| ObjectError::Malformed {
+                hash: hash.to_string(),
+                reason: "its hash isn't valid".to_string(),
+            })
+        };
+
+        let mut kind = kind_to_byte(object.kind);
+
+        let (base, data) = match best {
+            Some((base, delta)) => {
+                kind |= DELTA_FLAG;
+                depths[i] = depths[base] + 1;
+                deltas += 1;
+
+                (Some(hash_bytes(&objects[base].hash)?), Cow::Owned(delta))
+            }
+            None => (None, Cow::Borrowed(&object.data[..])),
+        };
+
+        let data = match compression.compress(&data) {
+            Some(compressed) => {
+                kind |= COMPRESSED_FLAG;
+                Cow::Owned(compressed)
+            }
+            None => data,
+        };
+
+        pack.push(kind);
+        pack.extend(base.unwrap_or_default());
+        delta::write_varint(&mut pack, data.len() as u64);
+        pack.extend_from_slice(&data);
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(&pack);
+    let checksum = hasher.finalize();
+    pack.extend_from_slice(&checksum);
+
+    let name = utils::to_hex(&checksum);
+
+    entries.sort();
+
+    let mut index = INDEX_SIGNATURE.to_vec();
+    index.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    for (hash, offset) in &entries {
+        index.extend_from_slice(&utils::from_hex(hash).unwrap_or_default());
+        index.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    let dir = pack_dir_in(nest);
+    let io_error = |path: PathBuf| move |source| ObjectError::Io { path, source };
+
+    fs::create_dir_all(&dir).map_err(io_error(dir.clone()))?;
+
+    // The pack has to be there before its index, since finding the index is
+    // how we know the pack exists.
+    let pack_path = dir.join(format!("pack-{name}.pack"));
+    utils::write_atomically(&pack_path, pack).map_err(io_error(pack_path.clone()))?;
+
+    let index_path = dir.join(format!("pack-{name}.idx"));
+    utils::write_atomically(&index_path, index).map_err(io_error(index_path.clone()))?;
+
+    Ok(PackSummary {
+        name,
+        objects: objects.len(),
+        deltas,
+    })
+}
+
+/// Deletes the pack called `name` from the nest directory `nest`, index first,
+/// so that it's never found without its objects.
+pub fn remove_pack_in(nest: &Path, name: &str) -> Result<(), ObjectError> {
+    let dir = pack_dir_in(nest);
+
+    for extension in ["idx", "pack"] {
+        let path = dir.join(format!("pack-{name}.{extension}"));
+        fs::remove_file(&path).map_err(|source| ObjectError::Io { path, source })?;
+    }
+
+    Ok(())
+}
+
+/// Moves every loose object in the nest into a new pack, along with the
+/// objects in every existing pack if `all` is set, so that they end up in a
+/// single one. Returns what went into the pack, or `None` if there was nothing
+/// to pack.
+pub fn repack(all: bool) -> Result<Option<PackSummary>, RefError> {
+    let nest = crate::nest_dir();
+
+    // A blob split into chunks is already sharing what it can with other
+    // versions of it, so it stays as it is, and only its chunks get packed.
+    let loose: Vec<String> = objects::list_loose_objects_in(nest)?
+        .into_iter()
+        .filter(|hash| !objects::is_chunked_in(nest, hash))
+        .collect();
+    let old_packs = match all {
+        true => list_packs_in(nest)?,
+        false => Vec::new(),
+    };
+
+    let mut hashes = loose.clone();
+
+    if all {
+        hashes.extend(list_packed_objects_in(nest)?);
+    }
+
+    hashes.sort();
+    hashes.dedup();
+
+    if hashes.is_empty() {
+        return Ok(None);
+    }
+
+    let path_hints = find_path_hints()?;
+
+    let mut to_pack = Vec::new();
+
+    for hash in hashes {
+        let (kind, data) = objects::read_object_in(nest, &hash)?;
+
+        to_pack.push(PackObject {
+            path_hint: path_hints.get(&hash).cloned().unwrap_or_default(),
+            hash,
+            kind,
+            data,
+        });
+    }
+
+    let summary = write_pack_in(nest, to_pack)?;
+
+    // Everything is safely in the new pack now, so the old copies can go.
+    for hash in &loose {
+        objects::remove_loose_object_in(nest, hash)?;
+    }
+
+    for name in old_packs {
+        // Packing exactly the same objects again gives exactly the same pack.
+        if name != summary.name {
+            remove_pack_in(nest, &name)?;
+        }
+    }
+
+    packs_in(nest, true)?;
+
+    Ok(Some(summary))
+}
+
+/// Finds a path for every file and directory in the history of `HEAD` or any
+/// ref, so that versions of the same file can be packed next to each other.
+fn find_path_hints() -> Result<HashMap<String, String>, RefError> {
+    let mut commits = Vec::new();
+
+    for name in [Ref::Head].into_iter().chain(refs::list_all_refs()?) {
+        if let Some(hash) = refs::read_ref(&name)? {
+            commits.push(objects::peel_to_commit(&hash)?);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut hints = HashMap::new();
+
+    while let Some(hash) = commits.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        let metadata = objects::read_commit(&hash)?;
+        let mut trees = vec![(metadata.tree, String::new())];
+        commits.extend(metadata.parents);
+
+        while let Some((tree, path)) = trees.pop() {
+            // Once we've seen a tree, we've seen everything in it too.
+            if hints.contains_key(&tree) {
+                continue;
+            }
+
+            for entry in objects::read_tree_entries(&tree)? {
+                let entry_path = match path.is_empty() {
+                    true => entry.name,
+                    false => format!("{path}/{}", entry.name),
+                };
+
+                match entry.kind {
+                    ObjectKind::Tree => trees.push((entry.hash, entry_path)),
+                    _ => {
+                        hints.entry(entry.hash).or_insert(entry_path);
+                    }
+                }
+            }
+
+            hints.insert(tree, path);
+        }
+    }
+
+    Ok(hints)
+}
@@ -0,0 +1,114 @@
+//! Showing long output a page at a time.
+//!
+//! Commands like `log` can easily print more than fits on the screen, so when
+//! they're writing to a terminal we hand their output to a pager like `less`
+//! instead, which lets the user scroll through it. Anywhere else, like a file
+//! or another program, paging would just get in the way, so the output goes
+//! straight through.
+
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+use crate::config::Config;
+use crate::utils;
+
+/// The pager we use if the user hasn't picked one. The `-R` flag makes it
+/// show colors rather than the escape sequences behind them.
+const DEFAULT_PAGER: &str = "less -R";
+
+/// Works out which pager to use, if any.
+///
+/// Like the editor, the pager can be picked specifically for rat with the
+/// `RAT_PAGER` environment variable or the `core.pager` setting, and otherwise
+/// comes from `$PAGER`. Setting any of them to nothing, or to `cat`, turns
+/// paging off.
+pub fn pager_command() -> Option<String> {
+    let pager = env::var("RAT_PAGER")
+        .ok()
+        .or_else(|| {
+            Config::load()
+                .ok()
+                .and_then(|config| config.get("core.pager").map(str::to_string))
+        })
+        .or_else(|| env::var("PAGER").ok())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+
+    match pager.trim() {
+        "" | "cat" => None,
+        pager => Some(pager.to_string()),
+    }
+}
+
+/// Writes `output` followed by a newline to standard output, through the
+/// user's pager if standard output is a terminal and there's a pager to be
+/// had.
+///
+/// If the pager can't be started, say because it isn't installed, we just
+/// write the output directly instead.
+pub fn page(output: &str) -> io::Result<()> {
+    if io::stdout().is_terminal() {
+        if let Some(pager) = pager_command() {
+            if let Some(result) = page_with(&pager, output) {
+                return result;
+            }
+        }
+    }
+
+    print(output)
+}
+
+/// Writes `output` followed by a newline to standard output.
+///
+/// Unlike `println!`, this doesn't panic if whoever is reading our output
+/// stops early, like `head` does. That's not an error as far as we're
+/// concerned, since they've got everything they wanted.
+pub fn print(output: &str) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+
+    ignore_broken_pipe(writeln!(stdout, "{output}").and_then(|()| stdout.flush()))
+}
+
+/// Writes `output` to standard output exactly as it is, without a newline on
+/// the end, for output that isn't necessarily text. Just like [`print`], it's
+/// fine for the reader to stop early.
+pub fn print_raw(output: &[u8]) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+
+    ignore_broken_pipe(stdout.write_all(output).and_then(|()| stdout.flush()))
+}
+
+/// Shows `output` in `pager`, returning `None` if it couldn't be started.
+fn page_with(pager: &str, output: &str) -> Option<io::Result<()>> {
+    // The pager can have arguments of its own, like -R.
+    let parts = utils::split_command_line(pager)?;
+    let (program, arguments) = parts.split_first()?;
+    let mut command = Command::new(program);
+    command.args(arguments).stdin(Stdio::piped());
+
+    // These make less quit straight away when everything fits on one screen,
+    // keep colors, and leave the output on the screen afterwards, which is
+    // what git asks for too. Anyone who has their own preferences already
+    // will have set LESS.
+    if env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+
+    let mut child = command.spawn().ok()?;
+
+    let mut stdin = child.stdin.take()?;
+    let written = ignore_broken_pipe(writeln!(stdin, "{output}"));
+
+    // Closing the pager's input lets it know there's nothing more coming.
+    drop(stdin);
+
+    Some(written.and_then(|()| child.wait().map(drop)))
+}
+
+/// Treats the reader of our output going away as success.
+fn ignore_broken_pipe(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,353 @@
+//! Showing how files changed, line by line.
+//!
+//! [`compare`](crate::compare) tells us which files changed between two
+//! snapshots, and [`diff`](crate::diff) tells us which lines changed between
+//! two versions of a file. Here we put the two together into patches in the
+//! unified format that `diff -u` and git use, where each run of changed lines
+//! is shown along with a few unchanged lines around it, so that you can see
+//! where it is.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::compare::{self, Change, Entry, FileMode, Snapshot};
+use crate::diff;
+use crate::error::ObjectError;
+use crate::objects::{self, ObjectKind};
+use crate::renames::{self, Detection};
+use crate::{utils, worktree};
+
+/// How many unchanged lines are shown before and after each change.
+const CONTEXT_LINES: usize = 3;
+
+/// A single line of a hunk. Each one keeps its line ending, if it has one, so
+/// that a missing newline at the end of a file can be pointed out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchLine {
+    /// A line that's the same in both versions.
+    Context(String),
+    /// A line that's only in the new version.
+    Added(String),
+    /// A line that's only in the old version.
+    Removed(String),
+}
+
+/// A run of changes, along with the unchanged lines around it. Line numbers
+/// count from zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchHunk {
+    pub old_start: usize,
+    pub old_count: usize,
+    pub new_start: usize,
+    pub new_count: usize,
+    pub lines: Vec<PatchLine>,
+}
+
+impl PatchHunk {
+    /// The `@@ -1,3 +1,4 @@` line that starts the hunk, which says which lines
+    /// it covers in each version. Line numbers count from one, except that an
+    /// empty range gives the line just before it.
+    pub fn header(&self) -> String {
+        let range = |start: usize, count: usize| {
+            let start = if count == 0 { start } else { start + 1 };
+
+            match count {
+                1 => format!("{start}"),
+                _ => format!("{start},{count}"),
+            }
+        };
+
+        format!(
+            "@@ -{} +{} @@",
+            range(self.old_start, self.old_count),
+            range(self.new_start, self.new_count)
+        )
+    }
+
+    /// Splits the hunk into smaller ones wherever there are unchanged lines
+    /// between its changes, so that each change can be picked on its own. The
+    /// unchanged lines between two changes are context for both of them. A
+    /// hunk with only one change comes back just as it is.
+    pub fn split(&self) -> Vec<PatchHunk> {
+        // We need to know where every line is in each version, to know where
+        // each of the smaller hunks starts.
+        let mut positions = Vec::new();
+        let (mut old_line, mut new_line) = (self.old_start, self.new_start);
+
+        for line in &self.lines {
+            positions.push((old_line, new_line));
+
+            match line {
+                PatchLine::Context(_) => (old_line, new_line) = (old_line + 1, new_line + 1),
+                PatchLine::Added(_) => new_line += 1,
+                PatchLine::Removed(_) => old_line += 1,
+            }
+        }
+
+        // Each change is a run of added and removed lines, which we find as
+        // ranges of indices into the lines.
+        let mut changes = Vec::new();
+        let mut index = 0;
+
+        while index < self.lines.len() {
+            if matches!(self.lines[index], PatchLine::Context(_)) {
+                index += 1;
+                continue;
+            }
+
+            let start = index;
+
+            while index < self.lines.len() && !matches!(self.lines[index], PatchLine::Context(_)) {
+                index += 1;
+            }
+
+            changes.push((start, index));
+        }
+
+        if changes.len() <= 1 {
+            return vec![self.clone()];
+        }
+
+        (0..changes.len())
+            .map(|change| {
+                let start = match change {
+                    0 => 0,
+                    _ => changes[change - 1].1,
+                };
+                let end = changes
+                    .get(change + 1)
+                    .map_or(self.lines.len(), |(next_start, _)| *next_start);
+
+                let lines = self.lines[start..end].to_vec();
+                let (old_start, new_start) = positions[start];
+
+                PatchHunk {
+                    old_start,
+                    old_count: lines
+                        .iter()
+                        .filter(|line| !matches!(line, PatchLine::Added(_)))
+                        .count(),
+                    new_start,
+                    new_count: lines
+                        .iter()
+                        .filter(|line| !matches!(line, PatchLine::Removed(_)))
+                        .count(),
+                    lines,
+                }
+            })
+            .collect()
+    }
+}
+
+/// What changed inside a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchContent {
+    /// The hunks that turn the old version of the file into the new one,
+    /// which are empty if only the file's mode changed.
+    Text(Vec<PatchHunk>),
+    /// One of the versions is binary, so there aren't any lines to compare.
+    Binary,
+}
+
+/// How a single file changed between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePatch {
+    pub path: String,
+    pub change: Change,
+    /// The file in the old snapshot, unless it was added. For a file that was
+    /// renamed or copied, this is the file it came from.
+    pub old: Option<Entry>,
+    /// The file in the new snapshot, unless it was deleted.
+    pub new: Option<Entry>,
+    pub content: PatchContent,
+}
+
+/// Works out how every file that differs between `old` and `new` changed. The
+/// files in `old` are always read from the object store, while the ones in
+/// `new` are read from the working directory if `new_is_working_directory` is
+/// set, since its files are hashed without being stored. Renames and copies
+/// are looked for as `detection` says, using any `hints` from
+/// [`renames::detect`].
+pub fn diff_snapshots(
+    old: &Snapshot,
+    new: &Snapshot,
+    new_is_working_directory: bool,
+    detection: Detection,
+    hints: &BTreeMap<String, String>,
+) -> Result<Vec<FilePatch>, Box<dyn Error>> {
+    let changes = renames::detect(
+        old,
+        new,
+        compare::compare(old, new),
+        detection,
+        hints,
+        new_is_working_directory,
+    )?;
+
+    let mut patches = Vec::new();
+
+    for (path, change) in changes {
+        let old_path = match &change {
+            Change::Renamed { from, .. } | Change::Copied { from, .. } => from,
+            _ => &path,
+        };
+
+        let old_entry = old.get(old_path).cloned();
+        let new_entry = new.get(&path).cloned();
+
+        let old_data = match &old_entry {
+            Some(entry) => read_blob(entry)?,
+            None => Vec::new(),
+        };
+        let new_data = match &new_entry {
+            Some(entry) if new_is_working_directory && entry.mode != FileMode::Nest => {
+                worktree::read_working_file(&path)?
+            }
+            Some(entry) => read_blob(entry)?,
+            None => Vec::new(),
+        };
+
+        let content = if utils::is_binary(&old_data) || utils::is_binary(&new_data) {
+            PatchContent::Binary
+        } else {
+            let old_text = String::from_utf8_lossy(&old_data);
+            let new_text = String::from_utf8_lossy(&new_data);
+
+            PatchContent::Text(hunks(
+                &diff::split_lines(&old_text),
+                &diff::split_lines(&new_text),
+            ))
+        };
+
+        patches.push(FilePatch {
+            path,
+            change,
+            old: old_entry,
+            new: new_entry,
+            content,
+        });
+    }
+
+    Ok(patches)
+}
+
+/// Finds the hunks that turn `old` into `new`, with up to [`CONTEXT_LINES`]
+/// unchanged lines around each change. Changes that are close enough for their
+/// context to touch are put in the same hunk.
+pub fn hunks(old: &[&str], new: &[&str]) -> Vec<PatchHunk> {
+    let changes = diff::diff(old, new);
+    let mut groups: Vec<Vec<diff::Hunk>> = Vec::new();
+
+    for change in changes {
+        match groups.last_mut() {
+            Some(group)
+                if change.old_start - group[group.len() - 1].old_end <= 2 * CONTEXT_LINES =>
+            {
+                group.push(change)
+            }
+            _ => groups.push(vec![change]),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|group| {
+            let first = group[0];
+            let last = group[group.len() - 1];
+
+            // The lines before the first change are the same in both
+            // versions, so the context starts the same distance back in each.
+            let leading = first.old_start.min(CONTEXT_LINES);
+            let (old_start, new_start) = (first.old_start - leading, first.new_start - leading);
+            let old_end = (last.old_end + CONTEXT_LINES).min(old.len());
+
+            let mut lines = Vec::new();
+            let mut position = old_start;
+
+            for change in &group {
+                lines.extend(context(&old[position..change.old_start]));
+                lines.extend(
+                    old[change.old_start..change.old_end]
+                        .iter()
+                        .map(|line| PatchLine::Removed(line.to_string())),
+                );
+                lines.extend(
+                    new[change.new_start..change.new_end]
+                        .iter()
+                        .map(|line| PatchLine::Added(line.to_string())),
+                );
+
+                position = change.old_end;
+            }
+
+            lines.extend(context(&old[position..old_end]));
+
+            let old_count = lines
+                .iter()
+                .filter(|line| !matches!(line, PatchLine::Added(_)))
+                .count();
+            let new_count = lines
+                .iter()
+                .filter(|line| !matches!(line, PatchLine::Removed(_)))
+                .count();
+
+            PatchHunk {
+                old_start,
+                old_count,
+                new_start,
+                new_count,
+                lines,
+            }
+        })
+        .collect()
+}
+
+/// Makes the changes in `hunks` to `old`, the lines of the version they were
+/// worked out from, leaving out any other changes there were. The hunks have
+/// to be in order, as given by [`hunks`] or [`PatchHunk::split`].
+pub fn apply_hunks(old: &[&str], hunks: &[PatchHunk]) -> String {
+    let mut result = String::new();
+    let mut position = 0;
+
+    for hunk in hunks {
+        if hunk.old_start > position {
+            result.extend(old[position..hunk.old_start].iter().copied());
+        }
+
+        // Two pieces of a split hunk share the context between them, which
+        // the first one has already put in by the time we get to the second.
+        let mut overlap = position.saturating_sub(hunk.old_start);
+
+        for line in &hunk.lines {
+            match line {
+                PatchLine::Context(_) if overlap > 0 => overlap -= 1,
+                PatchLine::Context(text) | PatchLine::Added(text) => result.push_str(text),
+                PatchLine::Removed(_) => {}
+            }
+        }
+
+        position = position.max(hunk.old_start + hunk.old_count);
+    }
+
+    result.extend(old[position.min(old.len())..].iter().copied());
+
+    result
+}
+
+/// Turns unchanged lines into context lines.
+fn context(lines: &[&str]) -> Vec<PatchLine> {
+    lines
+        .iter()
+        .map(|line| PatchLine::Context(line.to_string()))
+        .collect()
+}
+
+/// Reads the contents of the blob a snapshot entry points at. For a symlink,
+/// that's the path it points to, and for another nest, which has no blob, we
+/// make up a line naming the commit it's pinned to, just like git does.
+fn read_blob(entry: &Entry) -> Result<Vec<u8>, ObjectError> {
+    match entry.mode {
+        FileMode::Nest => Ok(format!("Subproject commit {}\n", entry.hash).into_bytes()),
+        _ => objects::read_object_of_kind(&entry.hash, ObjectKind::Blob),
+    }
+}
@@ -0,0 +1,563 @@
+//! Showing commits in the log.
+//!
+//! By default, `rat log` shows each commit in full, the same way git does:
+//!
+//! ```text
+//! commit 3d5d346 (HEAD -> main, tag: v1.0)
+//! Author: Ada Lovelace <ada@example.com>
+//! Date:   Thu Nov 14 22:13:20 2023 +0100
+//!
+//!     Combine the two drafts.
+//! ```
+//!
+//! A commit with a [note](crate::notes) has it shown under the message, after
+//! a `Notes:` line. With `--show-signature`, each commit also gets a
+//! `Signature:` line saying whether its [signature](crate::signing) is good.
+//!
+//! `--oneline` squeezes each commit onto a single line instead, and
+//! `--format` lets you lay them out however you like, with placeholders that
+//! get replaced with each commit's details. These are the same ones git uses:
+//!
+//! | Placeholder | Replaced with                                      |
+//! |-------------|----------------------------------------------------|
+//! | `%H`, `%h`  | the commit's hash, in full or abbreviated          |
+//! | `%T`, `%t`  | the hash of its tree, in full or abbreviated       |
+//! | `%P`, `%p`  | the hashes of its parents, in full or abbreviated  |
+//! | `%an`       | the author's name                                  |
+//! | `%ae`       | the author's email                                 |
+//! | `%ad`       | when it was written                                |
+//! | `%at`       | when it was written, as a Unix timestamp           |
+//! | `%cn`, `%ce`, `%cd`, `%ct` | the same for the committer          |
+//! | `%s`        | the first line of the message                      |
+//! | `%b`        | the rest of the message                            |
+//! | `%B`        | the whole message                                  |
+//! | `%d`        | the branches and tags pointing at it, in brackets  |
+//! | `%N`        | its note                                           |
+//! | `%G?`       | `G`, `B` or `N` for a good, bad or no signature    |
+//! | `%n`        | a new line                                         |
+//! | `%%`        | a plain `%`                                        |
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::metadata::{CommitMetadata, Signature};
+use crate::repository::Decoration;
+use crate::resolve;
+use crate::signing::SignatureStatus;
+use crate::utils::{Color, TerminalFormat};
+
+/// A commit along with everything we might want to show about it.
+#[derive(Debug, Clone, Copy)]
+pub struct Commit<'a> {
+    pub hash: &'a str,
+    pub metadata: &'a CommitMetadata,
+    /// Whatever points at the commit, like branches and tags.
+    pub decorations: &'a [Decoration],
+    /// The commit's note, if it has one.
+    pub note: Option<&'a str>,
+    /// What checking the commit's signature found, if it's been checked.
+    pub signature: Option<SignatureStatus>,
+}
+
+impl Commit<'_> {
+    /// The first line of the message, which says what the commit does.
+    fn subject(&self) -> &str {
+        self.metadata.message.lines().next().unwrap_or("")
+    }
+
+    /// Everything in the message after the subject and the blank line
+    /// following it.
+    fn body(&self) -> &str {
+        match self.metadata.message.split_once('\n') {
+            Some((_, body)) => body.trim_start_matches('\n'),
+            None => "",
+        }
+    }
+
+    /// The decorations, joined together and painted according to `format`.
+    fn labels(&self, format: TerminalFormat) -> String {
+        self.decorations
+            .iter()
+            .map(|label| {
+                // Each kind of label gets its own color, like in git.
+                let color = match label {
+                    Decoration::Head(_) => Color::Cyan,
+                    Decoration::Branch(_) => Color::Green,
+                    Decoration::RemoteBranch(_) => Color::Red,
+                    Decoration::Tag(_) => Color::Yellow,
+                };
+
+                format.paint(&label.to_string(), color)
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The decorations in brackets, with a space before them, or nothing at
+    /// all if there aren't any.
+    fn decoration_suffix(&self, format: TerminalFormat) -> String {
+        if self.decorations.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", self.labels(format))
+        }
+    }
+}
+
+/// A `--format` template, split into the pieces it's made of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<Piece>);
+
+/// One piece of a `--format` template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    /// Text that's shown exactly as it is.
+    Literal(String),
+    /// A placeholder, named by whatever comes after the `%`.
+    Placeholder(&'static str),
+}
+
+/// Every placeholder we know about, with the longer ones first so that `%an`
+/// isn't mistaken for something else followed by an `n`.
+const PLACEHOLDERS: &[&str] = &[
+    "an", "ae", "ad", "at", "cn", "ce", "cd", "ct", "G?", "H", "h", "T", "t", "P", "p", "s", "b",
+    "B", "d", "N", "n", "%",
+];
+
+/// How to show each commit in the log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Everything about the commit, over several lines.
+    #[default]
+    Medium,
+    /// The abbreviated hash, decorations and subject, on one line.
+    Oneline,
+    /// A template given with `--format`.
+    Custom(Template),
+}
+
+impl LogFormat {
+    /// Shows `commit` in this format, with colors if `format` says so.
+    /// Templates given with `--format` never have colors added, since the
+    /// user has decided exactly what they want to see.
+    pub fn render(&self, commit: &Commit, format: TerminalFormat) -> String {
+        match self {
+            Self::Medium => render_medium(commit, format),
+            Self::Oneline => format!(
+                "{}{} {}",
+                format.paint(&resolve::abbreviate(commit.hash), Color::Yellow),
+                commit.decoration_suffix(format),
+                commit.subject()
+            ),
+            Self::Custom(Template(pieces)) => {
+                let mut output = String::new();
+
+                for piece in pieces {
+                    match piece {
+                        Piece::Literal(text) => output.push_str(text),
+                        Piece::Placeholder(name) => {
+                            output.push_str(&expand(name, commit));
+                        }
+                    }
+                }
+
+                output
+            }
+        }
+    }
+
+    /// Whether showing a commit in this format needs its signature checked.
+    pub fn shows_signatures(&self) -> bool {
+        match self {
+            Self::Custom(Template(pieces)) => pieces.contains(&Piece::Placeholder("G?")),
+            _ => false,
+        }
+    }
+
+    /// What goes between one commit and the next. Full commits take up
+    /// several lines, so they get a blank line between them to keep them
+    /// apart.
+    pub fn separator(&self) -> &'static str {
+        match self {
+            Self::Medium => "\n\n",
+            Self::Oneline | Self::Custom(_) => "\n",
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    /// Parses a `--format` template, as described in the module
+    /// documentation. Like git, the names of its built-in formats can be used
+    /// too.
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        match template {
+            "medium" => return Ok(Self::Medium),
+            "oneline" => return Ok(Self::Oneline),
+            _ => {}
+        }
+
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+
+        while let Some(index) = rest.find('%') {
+            literal.push_str(&rest[..index]);
+            rest = &rest[index + 1..];
+
+            let name = PLACEHOLDERS
+                .iter()
+                .find(|name| rest.starts_with(*name))
+                .ok_or_else(|| {
+                    let found: String = rest.chars().take(2).collect();
+                    format!("Unknown placeholder %{found} in format.")
+                })?;
+
+            rest = &rest[name.len()..];
+
+            // The simplest placeholders are really just literal text, so we
+            // may as well treat them that way.
+            match *name {
+                "n" => literal.push('\n'),
+                "%" => literal.push('%'),
+                name => {
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    pieces.push(Piece::Placeholder(name));
+                }
+            }
+        }
+
+        literal.push_str(rest);
+
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+
+        Ok(Self::Custom(Template(pieces)))
+    }
+}
+
+/// Works out what the placeholder `name` stands for in `commit`.
+fn expand(name: &str, commit: &Commit) -> String {
+    let metadata = commit.metadata;
+    let plain = TerminalFormat::default();
+
+    // The author and committer placeholders come in the same four flavors,
+    // so we pick the signature first and then the detail.
+    let signature_detail = |signature: &Signature, detail: char| match detail {
+        'n' => signature.name.clone(),
+        'e' => signature.email.clone(),
+        'd' => signature.format_date(),
+        _ => signature.timestamp.to_string(),
+    };
+
+    match name {
+        "H" => commit.hash.to_string(),
+        "h" => resolve::abbreviate(commit.hash),
+        "T" => metadata.tree.clone(),
+        "t" => resolve::abbreviate(&metadata.tree),
+        "P" => metadata.parents.join(" "),
+        "p" => metadata
+            .parents
+            .iter()
+            .map(|parent| resolve::abbreviate(parent))
+            .collect::<Vec<_>>()
+            .join(" "),
+        "s" => commit.subject().to_string(),
+        "b" => commit.body().to_string(),
+        "B" => metadata.message.clone(),
+        "d" => commit.decoration_suffix(plain),
+        "N" => commit.note.unwrap_or_default().to_string(),
+        "G?" => commit
+            .signature
+            .map_or(String::new(), |status| status.letter().to_string()),
+        _ => {
+            let mut chars = name.chars();
+            let role = chars.next();
+            let detail = chars.next().unwrap_or('t');
+
+            match role {
+                Some('a') => signature_detail(&metadata.author, detail),
+                _ => signature_detail(&metadata.committer, detail),
+            }
+        }
+    }
+}
+
+/// Shows everything about `commit`, the way `rat log` does by default.
+fn render_medium(commit: &Commit, format: TerminalFormat) -> String {
+    let metadata = commit.metadata;
+
+    // This is the header, which is simply the hash of the commit. We
+    // abbreviate it, since the full hash is a lot to take in and the
+    // abbreviation works just as well anywhere rat accepts a hash.
+    let mut entry = format.paint(
+        &format!("commit {}", resolve::abbreviate(commit.hash)),
+        Color::Yellow,
+    );
+
+    // If anything points at this commit, we list it next to the hash, so
+    // it's easy to see where each branch and tag is in the history.
+    entry.push_str(&commit.decoration_suffix(format));
+    entry.push('\n');
+
+    // Commits with more than one parent get an extra line listing them,
+    // since that's not obvious from the order alone.
+    if metadata.parents.len() > 1 {
+        let parent_list = metadata
+            .parents
+            .iter()
+            .map(|parent| resolve::abbreviate(parent))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let _ = writeln!(entry, "Merge: {parent_list}");
+    }
+
+    if let Some(status) = commit.signature {
+        let color = match status {
+            SignatureStatus::Good => Color::Green,
+            SignatureStatus::Bad => Color::Red,
+            SignatureStatus::Missing => Color::Yellow,
+        };
+
+        let _ = writeln!(
+            entry,
+            "Signature: {}",
+            format.paint(&status.to_string(), color)
+        );
+    }
+
+    // Like git, we only show the author here, since that's who actually
+    // wrote the changes.
+    let _ = writeln!(
+        entry,
+        "Author: {} <{}>\nDate:   {}\n",
+        metadata.author.name,
+        metadata.author.email,
+        metadata.author.format_date()
+    );
+
+    // The message is indented, so that it stands apart from the header.
+    for line in metadata.message.lines() {
+        let _ = writeln!(entry, "    {line}");
+    }
+
+    if let Some(note) = commit.note {
+        entry.push_str("\nNotes:\n");
+
+        for line in note.lines() {
+            let _ = writeln!(entry, "    {line}");
+        }
+    }
+
+    entry
+}
+
+/// Draws the lines of history next to each commit, for `rat log --graph`:
+///
+/// ```text
+/// *   commit 3d5d346 (HEAD -> main)
+/// |\  Merge: a1b2c3d e4f5a6b
+/// | * commit e4f5a6b (feature)
+/// * | commit a1b2c3d
+/// |/
+/// * commit 9f86d08
+/// ```
+///
+/// Each line heads down towards the commit it's waiting for, and commits are
+/// drawn as `*` on their line. A commit with several parents splits its line
+/// into one for each of them, and lines waiting for the same commit join back
+/// together.
+#[derive(Debug, Default)]
+pub struct Graph {
+    /// The commit each line is waiting for, from left to right.
+    columns: Vec<String>,
+}
+
+impl Graph {
+    /// Draws the commit `hash` with `parents` on its line, with `text` next to
+    /// it. The commits have to be given children first, the same order the
+    /// log shows them in.
+    pub fn draw(&mut self, hash: &str, parents: &[String], text: &str) -> String {
+        let column = self.column_of(hash);
+
+        let commit_row = (0..self.columns.len())
+            .map(|i| if i == column { "*" } else { "|" })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut rows = vec![commit_row];
+        rows.extend(self.advance(column, parents));
+
+        // The text starts next to the commit itself, then carries on down
+        // past any lines that are splitting apart or joining together, and
+        // then next to the lines as they are after this commit.
+        let padding = vec!["|"; self.columns.len()].join(" ");
+        let lines: Vec<&str> = text.lines().collect();
+
+        // The text is lined up past the widest row, so that it doesn't shift
+        // about as lines join together.
+        let width = rows.iter().chain([&padding]).map(String::len).max();
+        let width = width.unwrap_or(0);
+
+        (0..rows.len().max(lines.len()))
+            .map(|i| {
+                let row = rows.get(i).unwrap_or(&padding);
+                let line = lines.get(i).unwrap_or(&"");
+
+                format!("{row:width$} {line}").trim_end().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Finds the line waiting for `hash`, or starts a new one on the right if
+    /// nothing's waiting for it, which happens for the first commit on each
+    /// branch we start from.
+    fn column_of(&mut self, hash: &str) -> usize {
+        match self.columns.iter().position(|column| column == hash) {
+            Some(column) => column,
+            None => {
+                self.columns.push(hash.to_string());
+                self.columns.len() - 1
+            }
+        }
+    }
+
+    /// Replaces the line in `column` with one for each of `parents`, returning
+    /// the rows that show every line moving to where it ends up.
+    fn advance(&mut self, column: usize, parents: &[String]) -> Vec<String> {
+        let old = std::mem::take(&mut self.columns);
+
+        // Like git, every line keeps its place, with the commit's parents in
+        // the place of its line. A line waiting for a commit that a line to
+        // its left is already waiting for joins that line, rather than
+        // keeping one of its own.
+        for (i, waiting_for) in old.iter().enumerate() {
+            let hashes = match i == column {
+                true => parents,
+                false => std::slice::from_ref(waiting_for),
+            };
+
+            for hash in hashes {
+                if !self.columns.contains(hash) {
+                    self.columns.push(hash.clone());
+                }
+            }
+        }
+
+        let position = |hash: &String| self.columns.iter().position(|c| c == hash);
+
+        // Each edge goes from where a line was to where it's going next.
+        let mut edges = Vec::new();
+
+        for (i, waiting_for) in old.iter().enumerate() {
+            let hashes = match i == column {
+                true => parents,
+                false => std::slice::from_ref(waiting_for),
+            };
+
+            edges.extend(hashes.iter().filter_map(position).map(|to| (i, to)));
+        }
+
+        transition_rows(edges)
+    }
+}
+
+/// Draws the rows that move every line from where it starts to where it
+/// ends, as given by `edges`. Lines can only move one place each row, so a
+/// line going further than that gets a diagonal spanning several rows.
+///
+/// Lines heading in opposite directions have to cross, and a line heading
+/// left and one heading right can want the same place in a row. The one
+/// heading left always gets it, and the other waits a row where it is, so
+/// that neither is ever drawn over.
+fn transition_rows(mut edges: Vec<(usize, usize)>) -> Vec<String> {
+    let mut rows = Vec::new();
+
+    while edges.iter().any(|(from, to)| from != to) {
+        let width = edges
+            .iter()
+            .map(|(from, to)| from.max(to))
+            .max()
+            .map_or(0, |w| w * 2 + 2);
+        let mut row = vec![' '; width];
+
+        for (from, to) in &edges {
+            if from > to {
+                row[from * 2 - 1] = '/';
+            }
+        }
+
+        for (from, to) in &mut edges {
+            if from > to {
+                *from -= 1;
+            } else if from < to && row[*from * 2 + 1] == ' ' {
+                row[*from * 2 + 1] = '\\';
+                *from += 1;
+            } else {
+                row[*from * 2] = '|';
+            }
+        }
+
+        rows.push(row.into_iter().collect::<String>().trim_end().to_string());
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws each of `commits`, given as a hash and its parents, with the
+    /// hash as its text.
+    fn draw(commits: &[(&str, &[&str])]) -> String {
+        let mut graph = Graph::default();
+
+        commits
+            .iter()
+            .map(|(hash, parents)| {
+                let parents: Vec<String> = parents.iter().map(|p| p.to_string()).collect();
+                graph.draw(hash, &parents, hash)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn draws_a_straight_line() {
+        assert_eq!(
+            draw(&[("c", &["b"]), ("b", &["a"]), ("a", &[])]),
+            "* c\n* b\n* a"
+        );
+    }
+
+    #[test]
+    fn draws_a_merge_splitting_and_joining() {
+        let drawn = draw(&[("m", &["a", "e"]), ("e", &["r"]), ("a", &["r"]), ("r", &[])]);
+
+        assert_eq!(drawn, "*   m\n|\\\n| * e\n* | a\n|/\n* r");
+    }
+
+    #[test]
+    fn a_line_joining_across_another_waits_its_turn() {
+        let drawn = draw(&[
+            ("m", &["a", "b"]),
+            ("n", &["b", "a"]),
+            ("a", &["r"]),
+            ("b", &["r"]),
+            ("r", &[]),
+        ]);
+
+        assert_eq!(
+            drawn,
+            "*   m\n|\\\n| | * n\n| |/\n|/|\n* | a\n| * b\n|/\n* r"
+        );
+    }
+}
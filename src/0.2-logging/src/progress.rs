@@ -0,0 +1,210 @@
+//! Reporting how far along slow operations are.
+//!
+//! Hashing every file in a big working directory, or writing them all out
+//! again when checking out a commit, can take long enough that it looks like
+//! nothing is happening. Those operations report each file they finish to
+//! whatever [`Progress`] has been set with [`set_reporter`], which does
+//! nothing at all unless a program asks for something else.
+//!
+//! The command line uses a [`TerminalBar`], which draws a line on standard
+//! error like this one, as long as that's a terminal:
+//!
+//! ```text
+//! Hashing files: 45% (450/1000), 12.3 MiB, about 3s left
+//! ```
+//!
+//! With `-v`, how long each task took is [`logged`](crate::logging) too once
+//! it's done.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::{logging, utils};
+
+/// Something that gets told how a slow operation is going.
+///
+/// Files can be finished on several threads at once, so every method can be
+/// called from any of them.
+pub trait Progress: Send + Sync {
+    /// Called when `task` starts, with the number of files it's going to go
+    /// through.
+    fn start(&self, task: &str, total: usize);
+
+    /// Called each time a file is done, with the number of bytes in it.
+    fn advance(&self, bytes: u64);
+
+    /// Called once the task has gone through every file, or given up.
+    fn finish(&self);
+}
+
+static REPORTER: OnceLock<Box<dyn Progress>> = OnceLock::new();
+
+/// Reports the progress of every slow operation from now on to `reporter`.
+/// Only the first reporter set counts.
+pub fn set_reporter(reporter: Box<dyn Progress>) {
+    let _ = REPORTER.set(reporter);
+}
+
+/// Starts reporting the progress of `task`, which is going to go through
+/// `total` files. The task finishes when the returned [`Task`] is dropped.
+pub fn start(task: &str, total: usize) -> Task {
+    let reporter = REPORTER.get().map(Box::as_ref);
+
+    if let Some(reporter) = reporter {
+        reporter.start(task, total);
+    }
+
+    Task {
+        name: task.to_string(),
+        reporter,
+        started: Instant::now(),
+        done: AtomicUsize::new(0),
+        bytes: AtomicU64::new(0),
+    }
+}
+
+/// A slow operation that's underway.
+pub struct Task {
+    name: String,
+    reporter: Option<&'static dyn Progress>,
+    started: Instant,
+    done: AtomicUsize,
+    bytes: AtomicU64,
+}
+
+impl Task {
+    /// Reports that another file holding `bytes` bytes is done.
+    pub fn advance(&self, bytes: u64) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+
+        if let Some(reporter) = self.reporter {
+            reporter.advance(bytes);
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        if let Some(reporter) = self.reporter {
+            reporter.finish();
+        }
+
+        logging::verbose(format_args!(
+            "{}: {} files, {} in {:.2}s",
+            self.name,
+            self.done.load(Ordering::Relaxed),
+            utils::format_bytes(self.bytes.load(Ordering::Relaxed)),
+            self.started.elapsed().as_secs_f64()
+        ));
+    }
+}
+
+/// How long a task has to run before we bother drawing anything, so that fast
+/// ones don't flash a bar up for no reason.
+const DELAY: Duration = Duration::from_millis(500);
+
+/// How often the bar is drawn again at most, since drawing it for every file
+/// would take longer than the files themselves.
+const REFRESH: Duration = Duration::from_millis(100);
+
+/// A progress bar drawn on standard error.
+#[derive(Default)]
+pub struct TerminalBar {
+    state: Mutex<Option<BarState>>,
+}
+
+struct BarState {
+    task: String,
+    total: usize,
+    done: usize,
+    bytes: u64,
+    started: Instant,
+    /// When we last drew the bar, if we have at all.
+    drawn: Option<Instant>,
+}
+
+impl TerminalBar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BarState {
+    fn draw(&mut self, finished: bool) {
+        let percent = match self.total {
+            0 => 100,
+            total => self.done * 100 / total,
+        };
+
+        let mut line = format!(
+            "{}: {percent}% ({}/{}), {}",
+            self.task,
+            self.done,
+            self.total,
+            utils::format_bytes(self.bytes)
+        );
+
+        if finished {
+            line.push_str(", done.\n");
+        } else if self.done > 0 {
+            // We guess at how long is left by assuming the files still to go
+            // will take as long as the ones so far did on average.
+            let elapsed = self.started.elapsed();
+            let left = elapsed.mul_f64((self.total - self.done) as f64 / self.done as f64);
+            line.push_str(&format!(", about {}s left", left.as_secs() + 1));
+        }
+
+        // Going back to the start of the line and clearing it lets each draw
+        // replace the last one.
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[K{line}");
+        let _ = stderr.flush();
+
+        self.drawn = Some(Instant::now());
+    }
+}
+
+impl Progress for TerminalBar {
+    fn start(&self, task: &str, total: usize) {
+        *self.state.lock().unwrap() = Some(BarState {
+            task: task.to_string(),
+            total,
+            done: 0,
+            bytes: 0,
+            started: Instant::now(),
+            drawn: None,
+        });
+    }
+
+    fn advance(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+
+        state.done += 1;
+        state.bytes += bytes;
+
+        let due = match state.drawn {
+            Some(drawn) => drawn.elapsed() >= REFRESH,
+            None => state.started.elapsed() >= DELAY,
+        };
+
+        if due {
+            state.draw(false);
+        }
+    }
+
+    fn finish(&self) {
+        // A task that finished before we drew anything is left out entirely.
+        if let Some(mut state) = self.state.lock().unwrap().take() {
+            if state.drawn.is_some() {
+                state.draw(true);
+            }
+        }
+    }
+}
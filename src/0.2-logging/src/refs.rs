@@ -0,0 +1,802 @@
+//! References, which give human-friendly names to commits.
+//!
+//! Hashes are great for identifying commits, but not for remembering them. A
+//! reference, or "ref", is just a file inside `.rat/refs` containing the hash
+//! of a commit. Branches are refs living in `.rat/refs/heads`, so the branch
+//! `main` is the file `.rat/refs/heads/main`. Making a commit on a branch just
+//! means writing the new hash into that file.
+//!
+//! `HEAD` is special. Usually, it doesn't contain a hash at all, but instead
+//! points at another ref, like `ref: refs/heads/main`. This is called a
+//! symbolic ref, and it's how rat knows which branch you're on. If you check
+//! out a commit directly instead of a branch, `HEAD` contains that hash
+//! instead, and you're in what git calls "detached HEAD" mode.
+//!
+//! Tags live in `.rat/refs/tags`, and the branches of remotes we've talked to
+//! live in `.rat/refs/remotes`, in a subdirectory for each remote.
+//!
+//! Any other ref can be symbolic too, although that's rare. A symbolic ref
+//! can even point at another symbolic ref, so we only follow a few of them
+//! before giving up, in case some of them point at each other in a loop.
+//!
+//! A nest with thousands of tags would need thousands of tiny files, which
+//! is slow to list and wastes space, so refs can also be packed together into
+//! `.rat/packed-refs`, with a line like `hash refs/tags/v1.0` for each one.
+//! A ref that has its own file always wins over a packed one, so updating a
+//! packed ref just means writing its file as usual.
+//!
+//! Every time `HEAD` or a branch moves, we note down where it was and where it
+//! went in its *reflog*, in `.rat/logs`. Commits that no branch points at any
+//! more, like the one replaced by `rat commit --amend`, are then still easy to
+//! find, as something like `HEAD@{1}`, meaning "where HEAD was one move ago".
+//!
+//! Everything else in rat reads and writes refs through here rather than
+//! touching the files itself, so this is the only place that needs to know
+//! how they're stored.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::RefError;
+use crate::utils;
+
+/// The prefix of every branch ref.
+pub const HEADS_PREFIX: &str = "refs/heads/";
+
+/// The prefix of every tag ref.
+pub const TAGS_PREFIX: &str = "refs/tags/";
+
+/// The prefix of every ref tracking a branch in a remote.
+pub const REMOTES_PREFIX: &str = "refs/remotes/";
+
+/// What stands in for the hash of a ref that doesn't exist, like the old hash
+/// of a ref in its reflog before it was created.
+pub const NO_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How many symbolic refs we follow in a row before deciding they must point
+/// at each other in a loop. Git uses the same limit.
+const MAX_SYMBOLIC_DEPTH: usize = 5;
+
+/// A ref, named by what kind of ref it is rather than by where its file
+/// lives, so that nothing outside this module has to put together names like
+/// `refs/heads/main` itself.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Ref {
+    /// `HEAD` itself.
+    Head,
+    /// The branch with the given name, like `main` for `refs/heads/main`.
+    Branch(String),
+    /// The tag with the given name.
+    Tag(String),
+    /// Where the branch `branch` of the remote `remote` was when we last
+    /// talked to it.
+    Remote { remote: String, branch: String },
+    /// Any other ref, by its full name.
+    Other(String),
+}
+
+impl Ref {
+    /// Works out which ref has the full name `name`, like `refs/heads/main`.
+    pub fn parse(name: &str) -> Self {
+        if name == "HEAD" {
+            return Self::Head;
+        }
+
+        if let Some(branch) = name.strip_prefix(HEADS_PREFIX) {
+            return Self::Branch(branch.to_string());
+        }
+
+        if let Some(tag) = name.strip_prefix(TAGS_PREFIX) {
+            return Self::Tag(tag.to_string());
+        }
+
+        // Remote names can't contain slashes, so the first one is always
+        // where the branch name starts.
+        if let Some((remote, branch)) = name
+            .strip_prefix(REMOTES_PREFIX)
+            .and_then(|rest| rest.split_once('/'))
+        {
+            return Self::Remote {
+                remote: remote.to_string(),
+                branch: branch.to_string(),
+            };
+        }
+
+        Self::Other(name.to_string())
+    }
+
+    /// The full name of the ref, which is also the path of its file inside the
+    /// nest directory.
+    pub fn full_name(&self) -> String {
+        match self {
+            Self::Head => "HEAD".to_string(),
+            Self::Branch(branch) => format!("{HEADS_PREFIX}{branch}"),
+            Self::Tag(tag) => format!("{TAGS_PREFIX}{tag}"),
+            Self::Remote { remote, branch } => format!("{REMOTES_PREFIX}{remote}/{branch}"),
+            Self::Other(name) => name.clone(),
+        }
+    }
+
+    /// The name people usually call the ref by, like `main` for a branch or
+    /// `origin/main` for a remote branch.
+    pub fn short_name(&self) -> String {
+        match self {
+            Self::Branch(name) | Self::Tag(name) => name.clone(),
+            Self::Remote { remote, branch } => format!("{remote}/{branch}"),
+            Self::Head | Self::Other(_) => self.full_name(),
+        }
+    }
+}
+
+impl Display for Ref {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.full_name())
+    }
+}
+
+/// What's stored for a ref, before following it anywhere.
+enum Stored {
+    /// The ref points straight at this hash.
+    Hash(String),
+    /// The ref points at the ref with this full name.
+    Symbolic(String),
+}
+
+/// What `HEAD` is currently pointing at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Head {
+    /// We're on the branch with the given name. The branch might not actually
+    /// exist yet, which is the case in a brand new nest before the first
+    /// commit.
+    Branch(String),
+    /// We're not on any branch, and `HEAD` points straight at this commit.
+    Detached(String),
+}
+
+/// Finds the file a ref like `refs/heads/main` is stored in.
+fn ref_path(name: &str) -> PathBuf {
+    crate::nest_path(name)
+}
+
+/// Reads the hash the given ref points at, following it through any symbolic
+/// refs, or `None` if it doesn't exist.
+pub fn read_ref(name: &Ref) -> Result<Option<String>, RefError> {
+    // HEAD might belong to a linked working directory rather than being in
+    // the nest itself.
+    if *name == Ref::Head {
+        return resolve_head();
+    }
+
+    read_ref_in(crate::nest_dir(), name)
+}
+
+/// Reads the hash the given ref points at in the nest directory `nest`, which
+/// is usually somebody else's `.rat`.
+pub fn read_ref_in(nest: &Path, name: &Ref) -> Result<Option<String>, RefError> {
+    let mut full_name = name.full_name();
+
+    for _ in 0..=MAX_SYMBOLIC_DEPTH {
+        match read_stored_in(nest, &full_name)? {
+            Some(Stored::Hash(hash)) => return Ok(Some(hash)),
+            Some(Stored::Symbolic(target)) => full_name = target,
+            None => return Ok(None),
+        }
+    }
+
+    Err(RefError::SymbolicLoop {
+        name: name.full_name(),
+    })
+}
+
+/// Reads what's stored for the ref with the full name `name` in the nest
+/// directory `nest`, from its own file if it has one, and otherwise from
+/// `packed-refs`.
+fn read_stored_in(nest: &Path, name: &str) -> Result<Option<Stored>, RefError> {
+    let path = utils::join_path(nest, name);
+
+    // A directory is where refs with longer names live, like refs/heads/feature
+    // for refs/heads/feature/cheese, so there's no ref here either.
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound || path.is_dir() => {
+            return Ok(read_packed_refs_in(nest)?.remove(name).map(Stored::Hash));
+        }
+        Err(e) => Err(e)?,
+    };
+
+    let contents = contents.trim();
+
+    match contents.strip_prefix("ref: ") {
+        // The target ends up as a file path too, so it mustn't be able to lead
+        // anywhere outside the refs directory.
+        Some(target) if !target.starts_with("refs/") || !is_valid_name(target) => {
+            Err(RefError::InvalidName {
+                kind: "ref",
+                name: target.to_string(),
+            })
+        }
+        Some(target) => Ok(Some(Stored::Symbolic(target.to_string()))),
+        None => Ok(Some(Stored::Hash(contents.to_string()))),
+    }
+}
+
+/// Points the given ref at `hash`, creating it if necessary. A symbolic ref
+/// stops being symbolic and points straight at `hash` instead.
+pub fn write_ref(name: &Ref, hash: &str) -> Result<(), io::Error> {
+    write_ref_in(crate::nest_dir(), name, hash)
+}
+
+/// Points the given ref in the nest directory `nest` at `hash`.
+pub fn write_ref_in(nest: &Path, name: &Ref, hash: &str) -> Result<(), io::Error> {
+    let path = utils::join_path(nest, &name.full_name());
+
+    // Branch names can contain slashes, like feature/cheese, which means they
+    // can live in subdirectories that might not exist yet.
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    utils::write_atomically(path, hash)
+}
+
+/// Reads what `HEAD` is pointing at.
+pub fn read_head() -> Result<Head, RefError> {
+    parse_head(&fs::read_to_string(ref_path("HEAD"))?)
+}
+
+/// Reads what `HEAD` is pointing at in the nest directory `nest`.
+pub fn read_head_in(nest: &Path) -> Result<Head, RefError> {
+    parse_head(&fs::read_to_string(nest.join("HEAD"))?)
+}
+
+/// Parses the contents of a `HEAD` file.
+pub fn parse_head(head: &str) -> Result<Head, RefError> {
+    let head = head.trim();
+
+    match head.strip_prefix("ref: ") {
+        Some(target) => {
+            let branch =
+                target
+                    .strip_prefix(HEADS_PREFIX)
+                    .ok_or_else(|| RefError::InvalidHead {
+                        target: target.to_string(),
+                    })?;
+
+            Ok(Head::Branch(branch.to_string()))
+        }
+        None => Ok(Head::Detached(head.to_string())),
+    }
+}
+
+/// Formats what `HEAD` is pointing at the way it's stored in the `HEAD` file,
+/// which [`parse_head`] reads back.
+pub fn format_head(head: &Head) -> String {
+    match head {
+        Head::Branch(branch) => format!("ref: {HEADS_PREFIX}{branch}"),
+        Head::Detached(hash) => hash.clone(),
+    }
+}
+
+/// Resolves `HEAD` all the way down to a commit hash, or `None` if the current
+/// branch doesn't have any commits yet.
+pub fn resolve_head() -> Result<Option<String>, RefError> {
+    match read_head()? {
+        Head::Branch(branch) => read_ref(&Ref::Branch(branch)),
+        Head::Detached(hash) => Ok(Some(hash)),
+    }
+}
+
+/// Points `HEAD` at the given branch, making it the current branch.
+pub fn set_head_branch(branch: &str) -> Result<(), io::Error> {
+    utils::write_atomically(
+        ref_path("HEAD"),
+        format_head(&Head::Branch(branch.to_string())),
+    )
+}
+
+/// Points `HEAD` directly at a commit, detaching it from any branch.
+pub fn set_head_detached(hash: &str) -> Result<(), io::Error> {
+    utils::write_atomically(
+        ref_path("HEAD"),
+        format_head(&Head::Detached(hash.to_string())),
+    )
+}
+
+/// Reads the ref that the symbolic ref `name` points at, like
+/// `refs/heads/main` for `HEAD`, or `None` if it points straight at a commit.
+pub fn read_symbolic_ref(name: &Ref) -> Result<Option<Ref>, RefError> {
+    if *name == Ref::Head {
+        return match read_head()? {
+            Head::Branch(branch) => Ok(Some(Ref::Branch(branch))),
+            Head::Detached(_) => Ok(None),
+        };
+    }
+
+    match read_stored_in(crate::nest_dir(), &name.full_name())? {
+        Some(Stored::Symbolic(target)) => Ok(Some(Ref::parse(&target))),
+        Some(Stored::Hash(_)) => Ok(None),
+        None => Err(RefError::NotFound {
+            kind: "ref",
+            name: name.full_name(),
+        }),
+    }
+}
+
+/// Points the symbolic ref `name` at the ref `target`, creating it if
+/// necessary. `target` doesn't have to exist yet, in which case `name` won't
+/// point at anything until it does.
+///
+/// `HEAD` can only point at a branch, and nothing can end up pointing back at
+/// itself through `target`.
+pub fn write_symbolic_ref(name: &Ref, target: &Ref) -> Result<(), RefError> {
+    let full_name = name.full_name();
+
+    if *name != Ref::Head && (!full_name.starts_with("refs/") || !is_valid_name(&full_name)) {
+        Err(RefError::InvalidName {
+            kind: "ref",
+            name: full_name.clone(),
+        })?;
+    }
+
+    let target_name = target.full_name();
+
+    match target {
+        Ref::Branch(branch) if !is_valid_name(branch) => Err(RefError::InvalidName {
+            kind: "branch",
+            name: branch.clone(),
+        })?,
+        Ref::Branch(_) => {}
+        _ if *name == Ref::Head => Err(RefError::NotABranch {
+            target: target_name.clone(),
+        })?,
+        Ref::Head => Err(RefError::InvalidName {
+            kind: "ref",
+            name: target_name.clone(),
+        })?,
+        _ if !target_name.starts_with("refs/") || !is_valid_name(&target_name) => {
+            Err(RefError::InvalidName {
+                kind: "ref",
+                name: target_name.clone(),
+            })?
+        }
+        _ => {}
+    }
+
+    // If the target leads back here, reading either of them would go round
+    // in circles.
+    let mut next = Some(target_name.clone());
+
+    for _ in 0..=MAX_SYMBOLIC_DEPTH {
+        let Some(current) = next.take() else {
+            break;
+        };
+
+        if current == full_name {
+            Err(RefError::SymbolicLoop {
+                name: full_name.clone(),
+            })?;
+        }
+
+        if let Some(Stored::Symbolic(following)) = read_stored_in(crate::nest_dir(), &current)? {
+            next = Some(following);
+        }
+    }
+
+    if next.is_some() {
+        Err(RefError::SymbolicLoop { name: target_name })?;
+    }
+
+    let path = ref_path(&full_name);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    Ok(utils::write_atomically(
+        path,
+        format!("ref: {}", target.full_name()),
+    )?)
+}
+
+/// Moves whatever `HEAD` is pointing at to the commit `hash`. If we're on a
+/// branch, that means updating the branch, and otherwise it means updating
+/// `HEAD` itself. This is what happens when you make a new commit.
+///
+/// The move is recorded in the reflogs of both `HEAD` and the branch, along
+/// with `message`, which says why it happened.
+pub fn advance_head(hash: &str, message: &str) -> Result<(), RefError> {
+    let old = resolve_head()?;
+
+    match read_head()? {
+        Head::Branch(branch) => {
+            let name = Ref::Branch(branch);
+
+            write_ref(&name, hash)?;
+            append_reflog(&name, old.as_deref(), hash, message)?;
+        }
+        Head::Detached(_) => set_head_detached(hash)?,
+    }
+
+    append_reflog(&Ref::Head, old.as_deref(), hash, message)?;
+
+    Ok(())
+}
+
+/// Deletes the given ref, along with its reflog and any directories that are
+/// left empty as a result, stopping at `.rat/refs` itself. The ref is taken
+/// out of `packed-refs` too, otherwise the packed one would take its place.
+pub fn delete_ref(name: &Ref) -> Result<(), io::Error> {
+    delete_ref_in(crate::nest_dir(), name)
+}
+
+/// Deletes the given ref from the nest directory `nest`, the same way as
+/// [`delete_ref`].
+pub fn delete_ref_in(nest: &Path, name: &Ref) -> Result<(), io::Error> {
+    let full_name = name.full_name();
+    let path = utils::join_path(nest, &full_name);
+
+    let had_file = match fs::remove_file(&path) {
+        Ok(()) => {
+            remove_empty_parents(&path, &nest.join("refs"));
+            true
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e),
+    };
+
+    let mut packed = read_packed_refs_in(nest)?;
+    let was_packed = packed.remove(&full_name).is_some();
+
+    if was_packed {
+        write_packed_refs_in(nest, &packed)?;
+    }
+
+    if !had_file && !was_packed {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{full_name} doesn't exist"),
+        ));
+    }
+
+    // A ref made before reflogs existed won't have one.
+    let log_path = utils::join_path(nest, &format!("logs/{full_name}"));
+
+    match fs::remove_file(&log_path) {
+        Ok(()) => remove_empty_parents(&log_path, &nest.join("logs/refs")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(())
+}
+
+/// Removes the directories above `path` that are empty, stopping at `root`.
+fn remove_empty_parents(path: &Path, root: &Path) {
+    for parent in path.ancestors().skip(1) {
+        // remove_dir only succeeds on empty directories, so we stop at the
+        // first one that still has something in it.
+        if parent == root || fs::remove_dir(parent).is_err() {
+            break;
+        }
+    }
+}
+
+/// One move of a ref, as recorded in its reflog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflogEntry {
+    /// Where the ref pointed before, or `None` if it didn't exist yet.
+    pub old: Option<String>,
+    /// Where the ref pointed afterwards.
+    pub new: String,
+    /// When it moved, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// Why it moved, like `commit: Add the cheese`.
+    pub message: String,
+}
+
+/// Works out which ref the user means by `name` when asking about a reflog:
+/// `HEAD`, a full ref name like `refs/heads/main`, or otherwise the name of a
+/// branch. Returns `None` if it can't be the name of a ref at all.
+pub fn reflog_ref_name(name: &str) -> Option<Ref> {
+    match name {
+        "" | "@" | "HEAD" => Some(Ref::Head),
+        name if !is_valid_name(name) => None,
+        name if name.starts_with("refs/") => Some(Ref::parse(name)),
+        name => Some(Ref::Branch(name.to_string())),
+    }
+}
+
+/// Finds the file the reflog of a ref like `refs/heads/main` is stored in.
+fn reflog_path(name: &str) -> PathBuf {
+    crate::nest_path(&format!("logs/{name}"))
+}
+
+/// Records in the reflog of the given ref that it moved from `old` to `new`,
+/// because of `message`.
+///
+/// Each entry is a line like `old new timestamp\tmessage`, and new entries go
+/// on the end, so the file reads from oldest to newest.
+pub fn append_reflog(
+    name: &Ref,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> Result<(), io::Error> {
+    let path = reflog_path(&name.full_name());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // The system clock being set before 1970 is strange enough that we can
+    // just treat it as the epoch itself.
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    // A message is only ever a single line, since each line is an entry.
+    let message = message.lines().next().unwrap_or_default();
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(
+        file,
+        "{} {new} {timestamp}\t{message}",
+        old.unwrap_or(NO_HASH)
+    )
+}
+
+/// Reads the reflog of the given ref, newest first, so that the entry for
+/// `name@{n}` is at index `n`. A ref that has never moved has an empty one.
+pub fn read_reflog(name: &Ref) -> Result<Vec<ReflogEntry>, io::Error> {
+    let contents = match fs::read_to_string(reflog_path(&name.full_name())) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    // A line we can't make sense of is most likely one that was only half
+    // written, so we skip it rather than refusing to read the rest.
+    let mut entries: Vec<ReflogEntry> = contents
+        .lines()
+        .filter_map(|line| {
+            let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+            let mut fields = header.split(' ');
+            let old = fields.next()?;
+            let new = fields.next()?;
+            let timestamp = fields.next()?.parse().ok()?;
+
+            Some(ReflogEntry {
+                old: (old != NO_HASH).then(|| old.to_string()),
+                new: new.to_string(),
+                timestamp,
+                message: message.to_string(),
+            })
+        })
+        .collect();
+
+    entries.reverse();
+
+    Ok(entries)
+}
+
+/// Moves the reflog of the ref `old_name` over to `new_name`, for when a
+/// branch is renamed.
+pub fn rename_reflog(old_name: &Ref, new_name: &Ref) -> Result<(), io::Error> {
+    let old_path = reflog_path(&old_name.full_name());
+    let new_path = reflog_path(&new_name.full_name());
+
+    if !old_path.exists() {
+        return Ok(());
+    }
+
+    if let Some(parent) = new_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::rename(&old_path, new_path)?;
+    remove_empty_parents(&old_path, &reflog_path("refs"));
+
+    Ok(())
+}
+
+/// Lists the names of every branch, in sorted order.
+pub fn list_branches() -> Result<Vec<String>, io::Error> {
+    list_refs(HEADS_PREFIX)
+}
+
+/// Lists the names of every tag, in sorted order.
+pub fn list_tags() -> Result<Vec<String>, io::Error> {
+    list_refs(TAGS_PREFIX)
+}
+
+/// Lists the names of every branch of the remote called `remote` that we know
+/// about, in sorted order.
+pub fn list_remote_branches(remote: &str) -> Result<Vec<String>, io::Error> {
+    list_refs(&format!("{REMOTES_PREFIX}{remote}/"))
+}
+
+/// Lists every ref except `HEAD`, sorted by full name.
+pub fn list_all_refs() -> Result<Vec<Ref>, io::Error> {
+    list_all_refs_in(crate::nest_dir())
+}
+
+/// Lists every ref except `HEAD` in the nest directory `nest`, sorted by full
+/// name.
+pub fn list_all_refs_in(nest: &Path) -> Result<Vec<Ref>, io::Error> {
+    Ok(list_refs_in(nest, "refs/")?
+        .into_iter()
+        .map(|name| Ref::parse(&format!("refs/{name}")))
+        .collect())
+}
+
+/// Lists the names of every ref starting with `prefix`, with the prefix itself
+/// removed, in sorted order.
+fn list_refs(prefix: &str) -> Result<Vec<String>, io::Error> {
+    list_refs_in(crate::nest_dir(), prefix)
+}
+
+/// Lists the names of every ref starting with `prefix` in the nest directory
+/// `nest`, whether it has its own file or is packed, with the prefix itself
+/// removed, in sorted order.
+fn list_refs_in(nest: &Path, prefix: &str) -> Result<Vec<String>, io::Error> {
+    let mut refs: BTreeSet<String> = read_packed_refs_in(nest)?
+        .into_keys()
+        .filter_map(|name| name.strip_prefix(prefix).map(str::to_string))
+        .collect();
+
+    let mut loose = Vec::new();
+    let dir = utils::join_path(nest, prefix);
+
+    // A nest that has never had a commit might not have the directory yet, and
+    // the tags directory only appears once the first tag is made.
+    if dir.is_dir() {
+        list_refs_into(&dir, "", &mut loose)?;
+    }
+
+    refs.extend(loose);
+
+    Ok(refs.into_iter().collect())
+}
+
+fn list_refs_into(dir: &Path, prefix: &str, refs: &mut Vec<String>) -> Result<(), io::Error> {
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+
+        // No ref's name starts with a dot, so these can only be temporary
+        // files from a ref that's being written right now.
+        if file_name.starts_with('.') {
+            continue;
+        }
+
+        let name = format!("{prefix}{file_name}");
+
+        // Refs with slashes in their names are stored in subdirectories.
+        if dir_entry.file_type()?.is_dir() {
+            list_refs_into(&dir_entry.path(), &format!("{name}/"), refs)?;
+        } else {
+            refs.push(name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads every ref in the `packed-refs` file of the nest directory `nest`,
+/// as a map from full names to hashes.
+fn read_packed_refs_in(nest: &Path) -> Result<BTreeMap<String, String>, io::Error> {
+    let contents = match fs::read_to_string(nest.join("packed-refs")) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    // Git's packed-refs files look the same, except that they can have a
+    // header line starting with # and lines starting with ^ after annotated
+    // tags, both of which we can skip. Names we wouldn't accept from a file
+    // are skipped too, since they could end up as paths later on.
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let (hash, name) = line.split_once(' ')?;
+
+            (!hash.starts_with('#') && name.starts_with("refs/") && is_valid_name(name))
+                .then(|| (name.to_string(), hash.to_string()))
+        })
+        .collect())
+}
+
+/// Replaces the `packed-refs` file with `packed`, a map from full names to
+/// hashes.
+fn write_packed_refs(packed: &BTreeMap<String, String>) -> Result<(), io::Error> {
+    write_packed_refs_in(crate::nest_dir(), packed)
+}
+
+/// Replaces the `packed-refs` file in the nest directory `nest`.
+fn write_packed_refs_in(nest: &Path, packed: &BTreeMap<String, String>) -> Result<(), io::Error> {
+    let mut contents = String::from("# pack-refs with: sorted\n");
+
+    for (name, hash) in packed {
+        contents.push_str(&format!("{hash} {name}\n"));
+    }
+
+    utils::write_atomically(nest.join("packed-refs"), contents)
+}
+
+/// Moves refs out of their own files and into `packed-refs`, returning how
+/// many were moved.
+///
+/// Tags hardly ever change once they're made, so normally only they're
+/// packed, along with refs that were packed before and have been updated
+/// since. If `all` is set, every other ref is packed too. Symbolic refs never
+/// are, since `packed-refs` can only hold hashes.
+pub fn pack_refs(all: bool) -> Result<usize, RefError> {
+    let nest = crate::nest_dir();
+    let mut packed = read_packed_refs_in(nest)?;
+    let mut loose = Vec::new();
+
+    let refs_dir = ref_path("refs");
+
+    if refs_dir.is_dir() {
+        list_refs_into(&refs_dir, "refs/", &mut loose)?;
+    }
+
+    let mut moved = Vec::new();
+
+    for name in loose {
+        if !all && !name.starts_with(TAGS_PREFIX) && !packed.contains_key(&name) {
+            continue;
+        }
+
+        if let Some(Stored::Hash(hash)) = read_stored_in(nest, &name)? {
+            packed.insert(name.clone(), hash);
+            moved.push(name);
+        }
+    }
+
+    // The packed refs have to be safely written before their files go, so
+    // that they never disappear, even for a moment.
+    write_packed_refs(&packed)?;
+
+    for name in &moved {
+        let path = ref_path(name);
+        fs::remove_file(&path)?;
+        remove_empty_parents(&path, &refs_dir);
+    }
+
+    Ok(moved.len())
+}
+
+/// Reads the commit the branch with the given name points at, or `None` if
+/// there is no such branch.
+pub fn read_branch(branch: &str) -> Result<Option<String>, RefError> {
+    read_ref(&Ref::Branch(branch.to_string()))
+}
+
+/// Reads the object the tag with the given name points at, or `None` if there
+/// is no such tag.
+pub fn read_tag(tag: &str) -> Result<Option<String>, RefError> {
+    read_ref(&Ref::Tag(tag.to_string()))
+}
+
+/// Checks whether `name` is allowed as the name of a branch or tag. We follow a
+/// simplified version of git's rules, which mostly exist to make sure names
+/// can't be confused with other syntax or escape the refs directory.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c))
+        && name.split('/').all(|part| !part.starts_with('.'))
+}
@@ -0,0 +1,713 @@
+//! Matching text against regular expressions, for searching with `rat grep`.
+//!
+//! Patterns use the usual syntax:
+//!
+//! | Syntax           | Matches                                              |
+//! |------------------|------------------------------------------------------|
+//! | `a`              | the character `a`                                    |
+//! | `.`              | any character                                        |
+//! | `[abc]`, `[a-z]` | any of the characters listed, or in the range        |
+//! | `[^abc]`         | any character except the ones listed                 |
+//! | `\d`, `\w`, `\s` | a digit, a word character, or whitespace             |
+//! | `\D`, `\W`, `\S` | anything else                                        |
+//! | `^`, `$`         | the start and end of the line                        |
+//! | `x*`, `x+`, `x?` | zero or more, one or more, or zero or one `x`        |
+//! | `x{n}`, `x{n,m}` | `x` exactly `n` times, or between `n` and `m` times  |
+//! | `x\|y`           | either `x` or `y`                                    |
+//! | `(x)`            | `x`, grouped together                                |
+//! | `\.`, `\*`, ...  | the character itself, without its special meaning    |
+//!
+//! The obvious way to match, trying each possibility in turn and backing up
+//! when it fails, can take longer than the age of the universe on patterns
+//! like `(a*)*b`. Instead, we use Ken Thompson's approach: the pattern is
+//! compiled into a little program, and we run every possible path through it
+//! at the same time, one character at a time. That never takes more than the
+//! length of the text times the length of the program.
+
+use std::mem;
+
+use crate::error::RegexError;
+
+/// The most times a counted repetition like `x{n}` can repeat, since each
+/// repetition makes the program longer.
+const MAX_REPETITIONS: u32 = 1000;
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Instruction>,
+    ignore_case: bool,
+}
+
+impl Regex {
+    /// Compiles `pattern`, which matches regardless of case if `ignore_case`
+    /// is set.
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self, RegexError> {
+        let mut parser = Parser {
+            pattern,
+            chars: pattern.chars().collect(),
+            position: 0,
+        };
+
+        let node = parser.parse_alternation()?;
+
+        // The only thing that can stop the parser before the end is a bracket
+        // that was never opened.
+        if parser.position < parser.chars.len() {
+            return Err(parser.error("there's a ) without a matching ("));
+        }
+
+        let mut program = Vec::new();
+        compile(&node, &mut program);
+        program.push(Instruction::Match);
+
+        Ok(Self {
+            program,
+            ignore_case,
+        })
+    }
+
+    /// Checks whether the pattern matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+
+        self.find_in(&chars, 0).is_some()
+    }
+
+    /// Finds every place the pattern matches in `text`, from left to right,
+    /// as byte ranges. Matches don't overlap.
+    pub fn find_all(&self, text: &str) -> Vec<(usize, usize)> {
+        let (offsets, chars): (Vec<usize>, Vec<char>) = text.char_indices().unzip();
+        let byte_offset = |i: usize| offsets.get(i).copied().unwrap_or(text.len());
+
+        let mut matches = Vec::new();
+        let mut from = 0;
+
+        while from <= chars.len() {
+            let Some((start, end)) = self.find_in(&chars, from) else {
+                break;
+            };
+
+            matches.push((byte_offset(start), byte_offset(end)));
+
+            // An empty match would be found again at the same place forever,
+            // so we skip ahead a character after one.
+            from = if end == start { end + 1 } else { end };
+        }
+
+        matches
+    }
+
+    /// Finds the leftmost match in `chars` that starts at or after `from`,
+    /// returning where it starts and ends.
+    fn find_in(&self, chars: &[char], from: usize) -> Option<(usize, usize)> {
+        // Each thread is a place in the program, along with where in the text
+        // its match started. They're kept in order of priority, so that the
+        // first thread to match is the one we want.
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut found = None;
+
+        for position in from..=chars.len() {
+            // Until we've found a match, a new one could start anywhere. It
+            // has the lowest priority, since it starts furthest to the right.
+            if found.is_none() {
+                self.add_thread(&mut current, 0, position, position, chars.len());
+            }
+
+            if current.list.is_empty() {
+                break;
+            }
+
+            for &(pc, start) in &current.list {
+                match &self.program[pc] {
+                    Instruction::Match => {
+                        found = Some((start, position));
+
+                        // Every thread after this one has a lower priority,
+                        // so there's no point carrying on with them.
+                        break;
+                    }
+                    instruction => {
+                        if chars
+                            .get(position)
+                            .is_some_and(|&c| instruction.matches(c, self.ignore_case))
+                        {
+                            self.add_thread(&mut next, pc + 1, position + 1, start, chars.len());
+                        }
+                    }
+                }
+            }
+
+            mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        found
+    }
+
+    /// Adds a thread at `pc` to `threads`, following any jumps and checking
+    /// any assertions straight away, so that every thread in the list is
+    /// waiting on a character.
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        pc: usize,
+        position: usize,
+        start: usize,
+        length: usize,
+    ) {
+        // Two threads at the same place will do exactly the same thing from
+        // now on, so we only need the first one, which has priority.
+        if threads.seen[pc] {
+            return;
+        }
+
+        threads.seen[pc] = true;
+
+        match self.program[pc] {
+            Instruction::Jump(target) => self.add_thread(threads, target, position, start, length),
+            Instruction::Split(first, second) => {
+                self.add_thread(threads, first, position, start, length);
+                self.add_thread(threads, second, position, start, length);
+            }
+            Instruction::Start => {
+                if position == 0 {
+                    self.add_thread(threads, pc + 1, position, start, length);
+                }
+            }
+            Instruction::End => {
+                if position == length {
+                    self.add_thread(threads, pc + 1, position, start, length);
+                }
+            }
+            _ => threads.list.push((pc, start)),
+        }
+    }
+}
+
+/// The threads running through the program at one place in the text.
+#[derive(Debug)]
+struct Threads {
+    list: Vec<(usize, usize)>,
+    /// Which instructions already have a thread.
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(length: usize) -> Self {
+        Self {
+            list: Vec::new(),
+            seen: vec![false; length],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.fill(false);
+    }
+}
+
+/// A set of characters, like `[a-z]` or `\d`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl Class {
+    fn digit() -> Vec<(char, char)> {
+        vec![('0', '9')]
+    }
+
+    fn word() -> Vec<(char, char)> {
+        vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')]
+    }
+
+    fn space() -> Vec<(char, char)> {
+        vec![(' ', ' '), ('\t', '\r')]
+    }
+
+    fn contains(&self, c: char, ignore_case: bool) -> bool {
+        let in_ranges = |c: char| self.ranges.iter().any(|&(low, high)| low <= c && c <= high);
+
+        let found = in_ranges(c)
+            || ignore_case && (c.to_lowercase().any(in_ranges) || c.to_uppercase().any(in_ranges));
+
+        found != self.negated
+    }
+}
+
+/// The pattern as it's written, broken down into its parts.
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+/// A single step in a compiled pattern.
+#[derive(Debug, Clone)]
+enum Instruction {
+    /// Matches exactly this character.
+    Char(char),
+    /// Matches any character at all.
+    Any,
+    /// Matches any character in the class.
+    Class(Class),
+    /// Only carries on at the start of the text.
+    Start,
+    /// Only carries on at the end of the text.
+    End,
+    /// Carries on at both places at once, preferring the first.
+    Split(usize, usize),
+    /// Carries on somewhere else.
+    Jump(usize),
+    /// The pattern has matched.
+    Match,
+}
+
+impl Instruction {
+    /// Checks whether this instruction, which has to be one that reads a
+    /// character, accepts `c`.
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        match self {
+            Self::Char(expected) => {
+                *expected == c || ignore_case && expected.to_lowercase().eq(c.to_lowercase())
+            }
+            Self::Any => true,
+            Self::Class(class) => class.contains(c, ignore_case),
+            _ => false,
+        }
+    }
+}
+
+/// Turns `node` into instructions on the end of `program`.
+fn compile(node: &Node, program: &mut Vec<Instruction>) {
+    match node {
+        Node::Empty => {}
+        Node::Char(c) => program.push(Instruction::Char(*c)),
+        Node::Any => program.push(Instruction::Any),
+        Node::Class(class) => program.push(Instruction::Class(class.clone())),
+        Node::Start => program.push(Instruction::Start),
+        Node::End => program.push(Instruction::End),
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program);
+            }
+        }
+        Node::Alternate(branches) => {
+            // Every branch but the last splits off into itself or the rest,
+            // and jumps to the end once it's done.
+            let mut jumps = Vec::new();
+
+            for (i, branch) in branches.iter().enumerate() {
+                if i == branches.len() - 1 {
+                    compile(branch, program);
+                    break;
+                }
+
+                let split = program.len();
+                program.push(Instruction::Split(split + 1, 0));
+                compile(branch, program);
+
+                jumps.push(program.len());
+                program.push(Instruction::Jump(0));
+
+                program[split] = Instruction::Split(split + 1, program.len());
+            }
+
+            let end = program.len();
+
+            for jump in jumps {
+                program[jump] = Instruction::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, program);
+            }
+
+            match max {
+                // Any number more, by looping back round for as long as the
+                // node keeps matching.
+                None => {
+                    let split = program.len();
+                    program.push(Instruction::Split(split + 1, 0));
+                    compile(node, program);
+                    program.push(Instruction::Jump(split));
+
+                    program[split] = Instruction::Split(split + 1, program.len());
+                }
+                // Up to so many more, each of which can be skipped, which
+                // skips all the rest as well.
+                Some(max) => {
+                    let mut splits = Vec::new();
+
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Instruction::Split(program.len() + 1, 0));
+                        compile(node, program);
+                    }
+
+                    let end = program.len();
+
+                    for split in splits {
+                        program[split] = Instruction::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reads a pattern into [`Node`]s.
+struct Parser<'a> {
+    pattern: &'a str,
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+
+        Some(c)
+    }
+
+    fn error(&self, reason: &str) -> RegexError {
+        RegexError {
+            pattern: self.pattern.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Parses branches separated by `|`.
+    fn parse_alternation(&mut self) -> Result<Node, RegexError> {
+        let mut branches = vec![self.parse_concat()?];
+
+        while self.peek() == Some('|') {
+            self.position += 1;
+            branches.push(self.parse_concat()?);
+        }
+
+        Ok(if branches.len() == 1 {
+            branches.remove(0)
+        } else {
+            Node::Alternate(branches)
+        })
+    }
+
+    /// Parses everything up to the end of the current branch.
+    fn parse_concat(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = Vec::new();
+
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_repetitions(atom)?);
+        }
+
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.remove(0),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    /// Parses any `*`, `+`, `?` or `{n,m}` after `node`.
+    fn parse_repetitions(&mut self, mut node: Node) -> Result<Node, RegexError> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('{') => match self.parse_counts()? {
+                    Some(counts) => counts,
+                    // A brace that isn't a repetition is just a brace.
+                    None => return Ok(node),
+                },
+                Some(c @ ('*' | '+' | '?')) => {
+                    self.position += 1;
+
+                    match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    }
+                }
+                _ => return Ok(node),
+            };
+
+            if matches!(node, Node::Start | Node::End | Node::Empty) {
+                return Err(self.error("there's nothing to repeat"));
+            }
+
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            };
+        }
+    }
+
+    /// Parses a `{n}`, `{n,}` or `{n,m}`, returning `None` without moving if
+    /// what's there isn't one of those.
+    fn parse_counts(&mut self) -> Result<Option<(u32, Option<u32>)>, RegexError> {
+        let rest: String = self.chars[self.position..].iter().collect();
+
+        let Some(end) = rest.find('}') else {
+            return Ok(None);
+        };
+
+        let inside = &rest[1..end];
+        let parse = |s: &str| s.parse::<u32>().ok();
+
+        let (min, max) = match inside.split_once(',') {
+            Some((min, "")) => (parse(min), None),
+            Some((min, max)) => match parse(max) {
+                Some(max) => (parse(min), Some(max)),
+                None => return Ok(None),
+            },
+            None => (parse(inside), parse(inside)),
+        };
+
+        let Some(min) = min else {
+            return Ok(None);
+        };
+
+        if max.is_some_and(|max| max < min) {
+            return Err(self.error("a repetition has its numbers the wrong way round"));
+        }
+
+        if max.unwrap_or(min) > MAX_REPETITIONS {
+            return Err(self.error("a repetition repeats too many times"));
+        }
+
+        self.position += rest[..=end].chars().count();
+
+        Ok(Some((min, max)))
+    }
+
+    /// Parses a single character, class, group or assertion.
+    fn parse_atom(&mut self) -> Result<Node, RegexError> {
+        let Some(c) = self.next() else {
+            return Ok(Node::Empty);
+        };
+
+        match c {
+            '(' => {
+                // Groups that don't capture mean the same thing to us, since
+                // we never capture anything anyway.
+                if self.chars[self.position..].starts_with(&['?', ':']) {
+                    self.position += 2;
+                }
+
+                let node = self.parse_alternation()?;
+
+                if self.next() != Some(')') {
+                    return Err(self.error("there's a ( without a matching )"));
+                }
+
+                Ok(node)
+            }
+            '[' => self.parse_class(),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '*' | '+' | '?' => Err(self.error("there's nothing to repeat")),
+            '\\' => {
+                let escaped = self.next().ok_or_else(|| self.error("it ends with a \\"))?;
+
+                let class = |ranges, negated| Node::Class(Class { ranges, negated });
+
+                Ok(match escaped {
+                    'd' => class(Class::digit(), false),
+                    'D' => class(Class::digit(), true),
+                    'w' => class(Class::word(), false),
+                    'W' => class(Class::word(), true),
+                    's' => class(Class::space(), false),
+                    'S' => class(Class::space(), true),
+                    other => Node::Char(unescape(other)),
+                })
+            }
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    /// Parses a class like `[a-z_]`, after its opening bracket.
+    fn parse_class(&mut self) -> Result<Node, RegexError> {
+        let unclosed = |parser: &Self| parser.error("there's a [ without a matching ]");
+
+        let negated = self.peek() == Some('^');
+
+        if negated {
+            self.position += 1;
+        }
+
+        let mut ranges = Vec::new();
+        let mut first = true;
+
+        loop {
+            let c = self.next().ok_or_else(|| unclosed(self))?;
+
+            // A closing bracket straight away is part of the class, since an
+            // empty class would be pointless.
+            if c == ']' && !first {
+                break;
+            }
+
+            first = false;
+
+            let low = match c {
+                '\\' => match self.next().ok_or_else(|| unclosed(self))? {
+                    'd' => {
+                        ranges.extend(Class::digit());
+                        continue;
+                    }
+                    'w' => {
+                        ranges.extend(Class::word());
+                        continue;
+                    }
+                    's' => {
+                        ranges.extend(Class::space());
+                        continue;
+                    }
+                    other => unescape(other),
+                },
+                c => c,
+            };
+
+            // A dash between two characters makes a range, but anywhere else
+            // it's just a dash.
+            let is_range = self.peek() == Some('-')
+                && self
+                    .chars
+                    .get(self.position + 1)
+                    .is_some_and(|&next| next != ']');
+
+            if is_range {
+                self.position += 1;
+
+                let high = match self.next().ok_or_else(|| unclosed(self))? {
+                    '\\' => unescape(self.next().ok_or_else(|| unclosed(self))?),
+                    c => c,
+                };
+
+                if high < low {
+                    return Err(self.error("a range in [] has its ends the wrong way round"));
+                }
+
+                ranges.push((low, high));
+            } else {
+                ranges.push((low, low));
+            }
+        }
+
+        Ok(Node::Class(Class { ranges, negated }))
+    }
+}
+
+/// Works out which character an escape like `\n` or `\.` stands for.
+fn unescape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> Vec<String> {
+        let regex = Regex::new(pattern, false).unwrap();
+
+        regex
+            .find_all(text)
+            .into_iter()
+            .map(|(start, end)| text[start..end].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn matches_each_kind_of_syntax() {
+        let cases = [
+            ("a.c", "abc a-c ac", vec!["abc", "a-c"]),
+            ("[b-d]+", "abcde", vec!["bcd"]),
+            ("[^a ]+", "aa bb a cc", vec!["bb", "cc"]),
+            (r"\d+", "7 of 12", vec!["7", "12"]),
+            (r"\w+", "hi, there", vec!["hi", "there"]),
+            (r"\s", "a b", vec![" "]),
+            ("^ab", "abab", vec!["ab"]),
+            ("ab$", "abab", vec!["ab"]),
+            ("colou?r", "color colour", vec!["color", "colour"]),
+            ("x{2,3}", "x xx xxxx", vec!["xx", "xxx"]),
+            ("x{2}", "xxxxx", vec!["xx", "xx"]),
+            ("cat|dog", "hotdog catalog", vec!["dog", "cat"]),
+            ("(ab)+", "ababa", vec!["abab"]),
+            (r"a\.b", "a.b axb", vec!["a.b"]),
+        ];
+
+        for (pattern, text, expected) in cases {
+            assert_eq!(matches(pattern, text), expected, "{pattern} in {text}");
+        }
+    }
+
+    #[test]
+    fn prefers_the_leftmost_match_then_the_earlier_alternative() {
+        assert_eq!(matches("a+", "baaab"), ["aaa"]);
+        assert_eq!(matches("a|ab", "ab"), ["a"]);
+        assert_eq!(matches("ab|a", "ab"), ["ab"]);
+    }
+
+    #[test]
+    fn empty_matches_move_along() {
+        assert_eq!(Regex::new("x*", false).unwrap().find_all("ab").len(), 3);
+    }
+
+    #[test]
+    fn ranges_are_byte_offsets() {
+        let regex = Regex::new("é+", false).unwrap();
+
+        assert_eq!(regex.find_all("caféé!"), [(3, 7)]);
+    }
+
+    #[test]
+    fn ignores_case_when_asked() {
+        assert!(Regex::new("hello", true).unwrap().is_match("Say HELLO"));
+        assert!(!Regex::new("hello", false).unwrap().is_match("Say HELLO"));
+        assert!(Regex::new("[a-c]", true).unwrap().is_match("B"));
+    }
+
+    #[test]
+    fn doesnt_take_forever_on_nested_repetition() {
+        let text = "a".repeat(50);
+
+        assert!(!Regex::new("(a*)*b", false).unwrap().is_match(&text));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for pattern in ["(ab", "ab)", "[ab", "*a", "a{2,1}", "a{5000}", r"a\"] {
+            assert!(Regex::new(pattern, false).is_err(), "{pattern}");
+        }
+    }
+}
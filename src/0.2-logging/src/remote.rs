@@ -0,0 +1,658 @@
+//! Keeping track of other nests that we share history with.
+//!
+//! A remote is just a name, like `origin`, for another nest, stored in the
+//! nest's config along with where to find it:
+//!
+//! ```text
+//! [remote "origin"]
+//!     url = ../thesis
+//! ```
+//!
+//! The branches we last saw in a remote are kept as refs of their own under
+//! `refs/remotes/<remote>/`, so the `main` branch of `origin` is
+//! `refs/remotes/origin/main`, which can be referred to as just `origin/main`.
+//! These are only ever updated by talking to the remote, never by committing.
+//!
+//! Fetching copies the history of the remote's branches into our nest and
+//! updates our remote branches to match. Pushing does the opposite, copying
+//! the history of one of our branches into the remote and updating its branch
+//! of the same name. See the [`transfer`](crate::transfer) module for how the
+//! history actually gets copied.
+//!
+//! A remote can also be a mirror, set up by `rat clone --mirror`, in which
+//! case fetching makes our branches, tags and notes exactly the same as the
+//! remote's, deleting whatever it doesn't have anymore, rather than keeping
+//! remote branches. Pushing with `--mirror` does the same in the other
+//! direction, which is handy for keeping a backup of a nest somewhere else.
+//!
+//! We can fetch from remotes on the same filesystem or over HTTP, using the
+//! [`transport`](crate::transport) module, but we can only push to remotes on
+//! the same filesystem.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, Config, ConfigFile};
+use crate::error::{RefError, RemoteError, TransportError};
+use crate::lock::NestLock;
+use crate::refs::{self, Head, Ref};
+use crate::transport::{self, LocalTransport, Transport};
+use crate::{graph, hooks, http, notes, resolve, transfer};
+
+/// Lists the name and URL of every remote, sorted by name.
+pub fn list() -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let config = Config::load()?;
+
+    let mut remotes = config
+        .entries()
+        .filter_map(|(key, _)| {
+            let name = key.strip_prefix("remote.")?.strip_suffix(".url")?;
+
+            Some(name.to_string())
+        })
+        .collect::<Vec<_>>();
+
+    remotes.sort();
+    remotes.dedup();
+
+    Ok(remotes
+        .into_iter()
+        .filter_map(|name| {
+            let url = config.get(&format!("remote.{name}.url"))?.to_string();
+
+            Some((name, url))
+        })
+        .collect())
+}
+
+/// Finds the URL of the remote called `name`.
+pub fn url(name: &str) -> Result<String, Box<dyn Error>> {
+    Config::load()?
+        .get(&format!("remote.{name}.url"))
+        .map(str::to_string)
+        .ok_or_else(|| {
+            RemoteError::NotFound {
+                name: name.to_string(),
+            }
+            .into()
+        })
+}
+
+/// Finds the nest directory at `url`, one of the URLs of the remote called
+/// `name`, which has to be on the same filesystem.
+fn local_nest(name: &str, url: &str) -> Result<PathBuf, Box<dyn Error>> {
+    if http::is_url(url) {
+        Err(RemoteError::NotLocal {
+            name: name.to_string(),
+            url: url.to_string(),
+        })?;
+    }
+
+    Ok(
+        crate::find_nest(Path::new(url)).ok_or_else(|| TransportError::NotANest {
+            url: url.to_string(),
+        })?,
+    )
+}
+
+/// Describes how a ref moved, for showing to the user.
+pub fn describe_update(old: Option<&str>, new: &str, from: &str, to: &str) -> String {
+    match old {
+        Some(old) => format!(
+            "    {}..{}  {from} -> {to}",
+            resolve::abbreviate(old),
+            resolve::abbreviate(new)
+        ),
+        None => format!("    [new]  {from} -> {to}"),
+    }
+}
+
+/// Copies every branch of the remote called `name`, along with its history,
+/// into our remote branches for it. Tags we don't have yet are copied too.
+/// If `prune` is set, remote branches for branches the remote doesn't have
+/// anymore are deleted, which otherwise never happens.
+///
+/// Returns a line describing each ref that changed.
+pub fn fetch(name: &str, prune: bool) -> Result<Vec<String>, Box<dyn Error>> {
+    let transport = transport::open(&url(name)?)?;
+
+    if is_mirror(name)? {
+        return fetch_mirror(transport.as_ref());
+    }
+
+    let remote_refs = transport.list_refs()?;
+
+    let tips = remote_refs
+        .iter()
+        .map(|(_, hash)| hash.clone())
+        .collect::<Vec<_>>();
+
+    transfer::copy_objects(transport.as_ref(), crate::nest_dir(), &tips)?;
+
+    let mut updates = Vec::new();
+
+    for (full_name, hash) in &remote_refs {
+        // Tags are meant to mean the same thing everywhere, so unlike
+        // branches, we copy them as they are, but never change one we already
+        // have.
+        let (ref_name, local_name) = match Ref::parse(full_name) {
+            Ref::Tag(tag) => {
+                if refs::read_tag(&tag)?.is_some() {
+                    continue;
+                }
+
+                (tag.clone(), Ref::Tag(tag))
+            }
+            Ref::Branch(branch) => {
+                let local_name = Ref::Remote {
+                    remote: name.to_string(),
+                    branch: branch.clone(),
+                };
+
+                (branch, local_name)
+            }
+            _ => continue,
+        };
+
+        let old = refs::read_ref(&local_name)?;
+
+        if old.as_ref() != Some(hash) {
+            refs::write_ref(&local_name, hash)?;
+
+            updates.push(describe_update(
+                old.as_deref(),
+                hash,
+                &ref_name,
+                &local_name.short_name(),
+            ));
+        }
+    }
+
+    if prune {
+        updates.extend(prune_stale(name, &remote_refs)?);
+    }
+
+    Ok(updates)
+}
+
+/// Checks whether the remote called `name` is a mirror, meaning fetching from
+/// it replaces our refs with its own.
+pub fn is_mirror(name: &str) -> Result<bool, Box<dyn Error>> {
+    Ok(Config::load()?.get(&format!("remote.{name}.mirror")) == Some("true"))
+}
+
+/// Makes every branch, tag and note in our nest exactly the same as in the
+/// nest `transport` reads from, copying whatever history that needs.
+fn fetch_mirror(transport: &dyn Transport) -> Result<Vec<String>, Box<dyn Error>> {
+    let nest = crate::nest_dir();
+    let updates = mirror_updates(&transport.list_refs()?, nest)?;
+
+    let tips: Vec<String> = updates.iter().filter_map(|u| u.new.clone()).collect();
+    transfer::copy_objects(transport, nest, &tips)?;
+
+    let described = apply_mirror(nest, &updates)?;
+
+    if let Some(notes) = transport.read_notes()? {
+        notes::replace_all_in(nest, &notes)?;
+    }
+
+    Ok(described)
+}
+
+/// A ref that has to change to mirror another nest.
+struct MirrorUpdate {
+    name: Ref,
+    old: Option<String>,
+    /// Where the ref should point, or `None` if it has to be deleted.
+    new: Option<String>,
+}
+
+/// Works out how the branches and tags of the nest directory `nest` have to
+/// change to match `source_refs`, the refs of the nest it mirrors.
+fn mirror_updates(
+    source_refs: &[(String, String)],
+    nest: &Path,
+) -> Result<Vec<MirrorUpdate>, Box<dyn Error>> {
+    let mut updates = Vec::new();
+
+    for (full_name, hash) in source_refs {
+        let name = Ref::parse(full_name);
+        let old = refs::read_ref_in(nest, &name)?;
+
+        if old.as_ref() != Some(hash) {
+            updates.push(MirrorUpdate {
+                name,
+                old,
+                new: Some(hash.clone()),
+            });
+        }
+    }
+
+    for (full_name, hash) in LocalTransport::new(nest).list_refs()? {
+        if !source_refs.iter().any(|(source, _)| *source == full_name) {
+            updates.push(MirrorUpdate {
+                name: Ref::parse(&full_name),
+                old: Some(hash),
+                new: None,
+            });
+        }
+    }
+
+    Ok(updates)
+}
+
+/// Makes each of `updates` in the nest directory `nest`, whose objects have
+/// to be there already. Returns a line describing each one.
+fn apply_mirror(nest: &Path, updates: &[MirrorUpdate]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut described = Vec::new();
+
+    for update in updates {
+        let short_name = update.name.short_name();
+
+        match &update.new {
+            Some(hash) => {
+                refs::write_ref_in(nest, &update.name, hash)?;
+                described.push(describe_update(
+                    update.old.as_deref(),
+                    hash,
+                    &short_name,
+                    &short_name,
+                ));
+            }
+            None => {
+                refs::delete_ref_in(nest, &update.name)?;
+                described.push(format!("    [deleted]  {short_name}"));
+            }
+        }
+    }
+
+    Ok(described)
+}
+
+/// Checks whether fetching from the remote called `name` should prune its
+/// remote branches without being asked, which is up to `remote.<name>.prune`,
+/// or `fetch.prune` for every remote.
+pub fn prunes_by_default(name: &str) -> Result<bool, Box<dyn Error>> {
+    let config = Config::load()?;
+    let setting = config
+        .get(&format!("remote.{name}.prune"))
+        .or_else(|| config.get("fetch.prune"));
+
+    Ok(setting == Some("true"))
+}
+
+/// Deletes every remote branch we have for the remote called `name` whose
+/// branch it doesn't have anymore, without fetching anything. Returns a line
+/// describing each one.
+pub fn prune(name: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let transport = transport::open(&url(name)?)?;
+
+    prune_stale(name, &transport.list_refs()?)
+}
+
+/// Deletes the remote branches for the remote called `name` whose branches
+/// aren't among `remote_refs`, the refs it has now.
+fn prune_stale(
+    name: &str,
+    remote_refs: &[(String, String)],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut deleted = Vec::new();
+
+    for branch in refs::list_remote_branches(name)? {
+        let full_name = Ref::Branch(branch.clone()).full_name();
+
+        if remote_refs
+            .iter()
+            .any(|(remote_ref, _)| *remote_ref == full_name)
+        {
+            continue;
+        }
+
+        refs::delete_ref(&Ref::Remote {
+            remote: name.to_string(),
+            branch: branch.clone(),
+        })?;
+        deleted.push(format!("    [deleted]  {name}/{branch}"));
+    }
+
+    Ok(deleted)
+}
+
+/// What happened when pushing to one of a remote's URLs.
+#[derive(Debug)]
+pub struct PushOutcome {
+    pub url: String,
+    /// A line describing each branch that moved, or why the push failed.
+    pub result: Result<Vec<String>, Box<dyn Error>>,
+}
+
+/// Finds every URL the remote called `name` should be pushed to, which are
+/// its `remote.<name>.pushurl` settings, or its URL if it doesn't have any.
+pub fn push_urls(name: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let config = Config::load()?;
+    let push_urls = config.get_all(&format!("remote.{name}.pushurl"));
+
+    if !push_urls.is_empty() {
+        return Ok(push_urls.into_iter().map(str::to_string).collect());
+    }
+
+    Ok(vec![url(name)?])
+}
+
+/// Copies the history of each of our `branches` into the remote called
+/// `name`, and points the remote's branches of the same names at them.
+///
+/// A remote with more than one push URL is pushed to at each of them in
+/// turn, and what happened at each one is returned, since one of them failing
+/// doesn't stop us trying the rest.
+///
+/// We only ever move the remote's branches forwards. If one has commits that
+/// ours doesn't, moving it would lose them, so we refuse and the user has to
+/// fetch and combine them with their own first.
+///
+/// If `verify` is set, the pre-push hook gets the chance to stop each push
+/// before anything is copied.
+pub fn push(
+    name: &str,
+    branches: &[String],
+    verify: bool,
+) -> Result<Vec<PushOutcome>, Box<dyn Error>> {
+    let updates = branches
+        .iter()
+        .map(|branch| {
+            let hash = refs::read_branch(branch)?.ok_or_else(|| RefError::NotFound {
+                kind: "branch",
+                name: branch.clone(),
+            })?;
+
+            Ok((branch.clone(), hash))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+    let mut outcomes = Vec::new();
+
+    for url in push_urls(name)? {
+        let result = push_to(name, &url, &updates, verify);
+
+        // We know exactly where the remote's branches are now, so we might as
+        // well keep our remote branches up to date too.
+        if result.is_ok() {
+            for (branch, hash) in &updates {
+                refs::write_ref(
+                    &Ref::Remote {
+                        remote: name.to_string(),
+                        branch: branch.clone(),
+                    },
+                    hash,
+                )?;
+            }
+        }
+
+        outcomes.push(PushOutcome { url, result });
+    }
+
+    Ok(outcomes)
+}
+
+/// Pushes each of `updates`, a branch and the commit to point it at, to the
+/// nest at `url`, which is one of the URLs of the remote called `name`.
+fn push_to(
+    name: &str,
+    url: &str,
+    updates: &[(String, String)],
+    verify: bool,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let remote_nest = local_nest(name, url)?;
+
+    // The remote's branches could move between checking that these are
+    // fast-forwards and moving them, if somebody committed or pushed there at
+    // the same time, so we hold its lock until we're done.
+    let _lock = NestLock::acquire_in(&remote_nest)?;
+
+    let mut pending = Vec::new();
+
+    for (branch, hash) in updates {
+        let ref_name = Ref::Branch(branch.clone());
+        let old = refs::read_ref_in(&remote_nest, &ref_name)?;
+
+        if old.as_ref() == Some(hash) {
+            continue;
+        }
+
+        if let Some(old) = &old {
+            // If we don't even have the remote's commit, it definitely isn't
+            // part of our history.
+            if !graph::is_ancestor(old, hash)? {
+                Err(RemoteError::NotFastForward {
+                    remote: name.to_string(),
+                    branch: branch.clone(),
+                })?;
+            }
+        }
+
+        // Changing the branch that's checked out in the remote would leave its
+        // working directory and index out of sync with it. Bare nests don't
+        // have either, which is what makes them a good place to push to.
+        if !crate::is_bare(&remote_nest)
+            && refs::read_head_in(&remote_nest)? == Head::Branch(branch.clone())
+        {
+            Err(RemoteError::CheckedOut {
+                remote: name.to_string(),
+                branch: branch.clone(),
+            })?;
+        }
+
+        pending.push((branch, ref_name, hash, old));
+    }
+
+    if pending.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if verify {
+        let lines: Vec<_> = pending
+            .iter()
+            .map(|(_, ref_name, hash, old)| (ref_name, Some(hash.as_str()), old.as_deref()))
+            .collect();
+
+        run_pre_push(name, url, &lines)?;
+    }
+
+    let tips: Vec<String> = pending
+        .iter()
+        .map(|(_, _, hash, _)| (*hash).clone())
+        .collect();
+
+    transfer::copy_objects(&LocalTransport::new(crate::nest_dir()), &remote_nest, &tips)?;
+
+    let mut described = Vec::new();
+
+    for (branch, ref_name, hash, old) in pending {
+        refs::write_ref_in(&remote_nest, &ref_name, hash)?;
+        described.push(describe_update(old.as_deref(), hash, branch, branch));
+    }
+
+    Ok(described)
+}
+
+/// Runs the pre-push hook before pushing to `url`, one of the URLs of the
+/// remote called `name`, telling it about each ref that's about to change:
+/// its name, where we're moving it to, if anywhere, and where it was.
+fn run_pre_push(
+    name: &str,
+    url: &str,
+    updates: &[(&Ref, Option<&str>, Option<&str>)],
+) -> Result<(), Box<dyn Error>> {
+    let lines: String = updates
+        .iter()
+        .map(|(ref_name, new, old)| {
+            format!(
+                "{ref_name} {} {ref_name} {}\n",
+                new.unwrap_or(refs::NO_HASH),
+                old.unwrap_or(refs::NO_HASH)
+            )
+        })
+        .collect();
+
+    Ok(hooks::run("pre-push", &[name, url], &lines)?)
+}
+
+/// Makes every branch, tag and note of the remote called `name` exactly the
+/// same as ours, at each of its push URLs. Unlike a normal push, branches are
+/// moved even when that loses commits, and anything we don't have is deleted.
+///
+/// Our remote branches for it are updated to match our branches afterwards,
+/// as long as one of the pushes worked.
+pub fn push_mirror(name: &str, verify: bool) -> Result<Vec<PushOutcome>, Box<dyn Error>> {
+    let mut outcomes = Vec::new();
+
+    for url in push_urls(name)? {
+        let result = push_mirror_to(name, &url, verify);
+        outcomes.push(PushOutcome { url, result });
+    }
+
+    if outcomes.iter().any(|outcome| outcome.result.is_ok()) {
+        let branches = refs::list_branches()?;
+
+        for branch in refs::list_remote_branches(name)? {
+            if !branches.contains(&branch) {
+                refs::delete_ref(&Ref::Remote {
+                    remote: name.to_string(),
+                    branch,
+                })?;
+            }
+        }
+
+        for branch in branches {
+            if let Some(hash) = refs::read_branch(&branch)? {
+                refs::write_ref(
+                    &Ref::Remote {
+                        remote: name.to_string(),
+                        branch,
+                    },
+                    &hash,
+                )?;
+            }
+        }
+    }
+
+    Ok(outcomes)
+}
+
+/// Mirrors our nest into the nest at `url`, which is one of the URLs of the
+/// remote called `name`.
+fn push_mirror_to(name: &str, url: &str, verify: bool) -> Result<Vec<String>, Box<dyn Error>> {
+    let remote_nest = local_nest(name, url)?;
+    let _lock = NestLock::acquire_in(&remote_nest)?;
+
+    let ours = LocalTransport::new(crate::nest_dir());
+    let updates = mirror_updates(&ours.list_refs()?, &remote_nest)?;
+
+    // Moving or deleting the branch that's checked out in the remote would
+    // leave its working directory out of sync, just like with a normal push.
+    if !crate::is_bare(&remote_nest) {
+        if let Head::Branch(branch) = refs::read_head_in(&remote_nest)? {
+            if updates
+                .iter()
+                .any(|update| update.name == Ref::Branch(branch.clone()))
+            {
+                Err(RemoteError::CheckedOut {
+                    remote: name.to_string(),
+                    branch,
+                })?;
+            }
+        }
+    }
+
+    if verify && !updates.is_empty() {
+        let lines: Vec<_> = updates
+            .iter()
+            .map(|u| (&u.name, u.new.as_deref(), u.old.as_deref()))
+            .collect();
+
+        run_pre_push(name, url, &lines)?;
+    }
+
+    let tips: Vec<String> = updates.iter().filter_map(|u| u.new.clone()).collect();
+    transfer::copy_objects(&ours, &remote_nest, &tips)?;
+
+    let described = apply_mirror(&remote_nest, &updates)?;
+    notes::replace_all_in(&remote_nest, &notes::read_all()?)?;
+
+    Ok(described)
+}
+
+/// Adds a new remote called `name` that lives at `url`.
+pub fn add(name: &str, url: &str) -> Result<(), Box<dyn Error>> {
+    // Remote names end up as part of ref names, so they have to follow the
+    // same rules. They can't contain slashes either, otherwise we couldn't
+    // tell where the remote name ends and the branch name begins.
+    if !refs::is_valid_name(name) || name.contains('/') {
+        Err(RemoteError::InvalidName {
+            name: name.to_string(),
+        })?;
+    }
+
+    if list()?.iter().any(|(existing, _)| existing == name) {
+        Err(RemoteError::AlreadyExists {
+            name: name.to_string(),
+        })?;
+    }
+
+    let path = config::nest_config_path();
+    let mut file = ConfigFile::read(&path)?;
+    file.set(&format!("remote.{name}.url"), url)?;
+    file.write(&path)?;
+
+    Ok(())
+}
+
+/// Changes the URL of the remote called `name` to `url`. If `push` is set, it
+/// changes where the remote is pushed to instead, replacing every push URL it
+/// had, unless `add` is set too, in which case it's pushed to at `url` as well
+/// as everywhere else.
+pub fn set_url(name: &str, url: &str, push: bool, add: bool) -> Result<(), Box<dyn Error>> {
+    // Making sure the remote exists first stops a typo from making a new one
+    // with no URL.
+    self::url(name)?;
+
+    let path = config::nest_config_path();
+    let mut file = ConfigFile::read(&path)?;
+
+    match (push, add) {
+        (false, _) => file.set(&format!("remote.{name}.url"), url)?,
+        (true, false) => {
+            file.unset_all(&format!("remote.{name}.pushurl"))?;
+            file.add(&format!("remote.{name}.pushurl"), url)?;
+        }
+        (true, true) => file.add(&format!("remote.{name}.pushurl"), url)?,
+    }
+
+    file.write(&path)?;
+
+    Ok(())
+}
+
+/// Removes the remote called `name`, along with every remote branch we were
+/// keeping track of for it.
+pub fn remove(name: &str) -> Result<(), Box<dyn Error>> {
+    let path = config::nest_config_path();
+    let mut file = ConfigFile::read(&path)?;
+
+    if !file.remove_section("remote", Some(name)) {
+        Err(RemoteError::NotFound {
+            name: name.to_string(),
+        })?;
+    }
+
+    file.write(&path)?;
+
+    for branch in refs::list_remote_branches(name)? {
+        refs::delete_ref(&Ref::Remote {
+            remote: name.to_string(),
+            branch,
+        })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,256 @@
+//! Spotting files that were moved or copied.
+//!
+//! Snapshots only record which files exist, so moving a file looks just like
+//! deleting it and adding a new one. Like git, we work out afterwards which
+//! new files came from somewhere else, by comparing their contents with the
+//! files that went away. Files with exactly the same contents are taken as
+//! the same file straight away. After that, the most similar pairs are
+//! matched up, as long as they're at least [`SIMILARITY_THRESHOLD`] percent
+//! alike, so a file that was moved and then edited a little still counts.
+//!
+//! Looking for copies works the same way, except that the new file can come
+//! from one that's still there.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+
+use crate::compare::{Change, Entry, Snapshot};
+use crate::objects::{self, ObjectKind};
+use crate::{utils, worktree};
+
+/// How alike two files have to be, as a percentage, to count as the same file.
+pub const SIMILARITY_THRESHOLD: u8 = 50;
+
+/// The most files on either side we'll compare with each other to look for
+/// renames that aren't exact. Every file on one side has to be compared with
+/// every file on the other, which gets slow very quickly.
+const CANDIDATE_LIMIT: usize = 1000;
+
+/// Which kinds of moved files to look for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Detection {
+    /// Don't look at all, so moved files show up as deleted and added.
+    Off,
+    /// Look for files that were renamed.
+    #[default]
+    Renames,
+    /// Look for files that were renamed or copied.
+    Copies,
+}
+
+/// Replaces the deletions and additions in `changes`, which are the changes
+/// from `old` to `new`, with renames and copies wherever they look like one.
+///
+/// `hints` holds renames we've been told about, like by `rat mv`, from each
+/// new path to the old one. They're taken at their word however different the
+/// files are. The files in `new` are read from the working directory if
+/// `new_is_working_directory` is set, since its files are hashed without being
+/// stored.
+pub fn detect(
+    old: &Snapshot,
+    new: &Snapshot,
+    mut changes: BTreeMap<String, Change>,
+    detection: Detection,
+    hints: &BTreeMap<String, String>,
+    new_is_working_directory: bool,
+) -> Result<BTreeMap<String, Change>, Box<dyn Error>> {
+    if detection == Detection::Off {
+        return Ok(changes);
+    }
+
+    let mut deleted: BTreeSet<&String> = paths_with(&changes, Change::Deleted).collect();
+    let mut added: BTreeSet<&String> = paths_with(&changes, Change::Added).collect();
+
+    // Each of these is a new path, the path it came from, and how alike they
+    // are.
+    let mut renames = Vec::new();
+    let mut copies = Vec::new();
+
+    let read_old = |path: &str| read_blob(&old[path]);
+    let read_new = |path: &str| match new_is_working_directory {
+        true => Ok(worktree::read_working_file(path)?),
+        false => read_blob(&new[path]),
+    };
+
+    for (new_path, old_path) in hints {
+        if added.contains(new_path) && deleted.contains(old_path) {
+            let similarity = similarity(&read_old(old_path)?, &read_new(new_path)?);
+
+            renames.push((new_path.clone(), old_path.clone(), similarity));
+            added.remove(new_path);
+            deleted.remove(old_path);
+        }
+    }
+
+    // An empty file has the same hash as every other empty file, so it
+    // doesn't tell us anything about where it came from.
+    let is_empty = |entry: &Entry| entry.hash == objects::hash_object(ObjectKind::Blob, b"");
+
+    let mut deleted_by_hash: HashMap<&str, &String> = HashMap::new();
+
+    for path in &deleted {
+        if !is_empty(&old[*path]) {
+            deleted_by_hash.entry(&old[*path].hash).or_insert(path);
+        }
+    }
+
+    for new_path in added.clone() {
+        if let Some(old_path) = deleted_by_hash.remove(new[new_path].hash.as_str()) {
+            renames.push((new_path.clone(), old_path.clone(), 100));
+            added.remove(new_path);
+            deleted.remove(old_path);
+        }
+    }
+
+    for (new_path, old_path, similarity) in best_matches(&deleted, &added, read_old, read_new)? {
+        renames.push((new_path.clone(), old_path.clone(), similarity));
+        added.remove(new_path);
+    }
+
+    if detection == Detection::Copies {
+        // Any file that's still around can be copied exactly, but only the
+        // ones that changed are worth comparing more closely, just like in
+        // git, since there are usually far too many of the others.
+        let mut kept_by_hash: HashMap<&str, &String> = HashMap::new();
+
+        for (path, entry) in old {
+            if new.contains_key(path) && !is_empty(entry) {
+                kept_by_hash.entry(&entry.hash).or_insert(path);
+            }
+        }
+
+        for new_path in added.clone() {
+            if let Some(old_path) = kept_by_hash.get(new[new_path].hash.as_str()) {
+                copies.push((new_path.clone(), (*old_path).clone(), 100));
+                added.remove(new_path);
+            }
+        }
+
+        let modified: BTreeSet<&String> = paths_with(&changes, Change::Modified).collect();
+
+        for (new_path, old_path, similarity) in best_matches(&modified, &added, read_old, read_new)?
+        {
+            copies.push((new_path.clone(), old_path.clone(), similarity));
+        }
+    }
+
+    for (new_path, from, similarity) in renames {
+        changes.remove(&from);
+        changes.insert(new_path, Change::Renamed { from, similarity });
+    }
+
+    for (new_path, from, similarity) in copies {
+        changes.insert(new_path, Change::Copied { from, similarity });
+    }
+
+    Ok(changes)
+}
+
+/// Lists the paths in `changes` that changed in the way `change` says.
+fn paths_with(
+    changes: &BTreeMap<String, Change>,
+    change: Change,
+) -> impl Iterator<Item = &String> + '_ {
+    changes
+        .iter()
+        .filter(move |(_, other)| **other == change)
+        .map(|(path, _)| path)
+}
+
+/// A file, the file it came from, and how alike they are.
+type Match<'a> = (&'a String, &'a String, u8);
+
+/// Pairs up files from `sources` with files from `targets` that are similar
+/// enough, most similar first, returning each target along with its source
+/// and how alike they are. Each file is only used once.
+fn best_matches<'a>(
+    sources: &BTreeSet<&'a String>,
+    targets: &BTreeSet<&'a String>,
+    read_source: impl Fn(&str) -> Result<Vec<u8>, Box<dyn Error>>,
+    read_target: impl Fn(&str) -> Result<Vec<u8>, Box<dyn Error>>,
+) -> Result<Vec<Match<'a>>, Box<dyn Error>> {
+    if sources.is_empty()
+        || targets.is_empty()
+        || sources.len() > CANDIDATE_LIMIT
+        || targets.len() > CANDIDATE_LIMIT
+    {
+        return Ok(Vec::new());
+    }
+
+    let mut source_files = Vec::new();
+
+    for path in sources {
+        source_files.push((*path, read_source(path)?));
+    }
+
+    let mut candidates = Vec::new();
+
+    for target in targets {
+        let data = read_target(target)?;
+
+        for (source, source_data) in &source_files {
+            let similarity = similarity(source_data, &data);
+
+            if similarity >= SIMILARITY_THRESHOLD {
+                candidates.push((*target, *source, similarity));
+            }
+        }
+    }
+
+    // The sort is stable, so pairs that are just as alike stay in path order,
+    // which keeps the result the same from one run to the next.
+    candidates.sort_by_key(|&(_, _, similarity)| std::cmp::Reverse(similarity));
+
+    let mut used = BTreeSet::new();
+    let mut matches = Vec::new();
+
+    for (target, source, similarity) in candidates {
+        if !used.contains(target) && !used.contains(source) {
+            used.insert(target);
+            used.insert(source);
+            matches.push((target, source, similarity));
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Works out how alike two files are, as the percentage of all of their lines
+/// that they have in common. Binary files are either exactly the same or not
+/// alike at all, since their "lines" don't mean much.
+pub fn similarity(old: &[u8], new: &[u8]) -> u8 {
+    if old == new {
+        return 100;
+    }
+
+    if utils::is_binary(old) || utils::is_binary(new) {
+        return 0;
+    }
+
+    let mut unmatched: HashMap<&[u8], usize> = HashMap::new();
+    let mut total = 0;
+
+    for line in old.split_inclusive(|&byte| byte == b'\n') {
+        *unmatched.entry(line).or_default() += 1;
+        total += 1;
+    }
+
+    let mut common = 0;
+
+    for line in new.split_inclusive(|&byte| byte == b'\n') {
+        total += 1;
+
+        if let Some(count @ 1..) = unmatched.get_mut(line) {
+            *count -= 1;
+            common += 1;
+        }
+    }
+
+    // Each line in common counts once for each file.
+    (200 * common / total) as u8
+}
+
+/// Reads the contents of the blob a snapshot entry points at.
+fn read_blob(entry: &Entry) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(objects::read_object_of_kind(&entry.hash, ObjectKind::Blob)?)
+}
@@ -0,0 +1,3973 @@
+//! The nest as a whole, and everything you can do with it.
+//!
+//! The other modules each look after one piece of the nest, like the object
+//! store or the refs. [`Repository`] ties them together into the operations
+//! rat is actually used for, like committing, checking out and merging, and
+//! returns what happened as plain values rather than text, so that it's just
+//! as useful to other programs as it is to the `rat` command itself.
+//!
+//! Like the rest of rat, a repository always works on the nest in the current
+//! directory.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use crate::bisect::{self, BisectMark, BisectState, BisectStep};
+use crate::cache::StatCache;
+use crate::compare::{self, Change, Entry, FileMode, Snapshot};
+use crate::config::{self, Config, ConfigFile};
+use crate::error::{
+    AddError, BisectError, CheckoutError, CommitError, InitError, MergeError, ObjectError,
+    RefError, StateError,
+};
+use crate::format::{self, Format};
+use crate::hash::{self, HashAlgorithm};
+use crate::index::{self, Index};
+use crate::metadata::{CommitMetadata, Signature, TagMetadata};
+use crate::objects::{self, ObjectKind};
+use crate::patch::{self, FilePatch, PatchContent, PatchHunk};
+use crate::refs::{self, Head, Ref};
+use crate::renames::{self, Detection};
+use crate::resolve::RevisionRange;
+use crate::state::{self, Operation, RebaseAction, RebaseState, RebaseStep};
+use crate::worktree::{
+    self, check_untracked_files, has_uncommitted_changes, restore_snapshot, RestorePlan,
+};
+use crate::{
+    commit_graph, diff, graph, hooks, http, ignore, lfs, logging, merge, nest_dir, nest_path,
+    progress, remote, resolve, signing, submodules, transport, utils, worktrees, RAT_NEST,
+};
+
+/// A handle on the nest in the current directory.
+#[derive(Debug)]
+pub struct Repository {
+    // Nobody outside this module should be able to make one without going
+    // through `open`, `init` or `clone`, which make sure the nest exists.
+    bare: bool,
+}
+
+/// A branch, as listed by [`Repository::branches`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub name: String,
+    /// The hash of the commit the branch points at.
+    pub commit: String,
+    /// Whether HEAD is on this branch.
+    pub current: bool,
+}
+
+/// A tag, as listed by [`Repository::tags`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    /// The hash of the commit the tag is for.
+    pub commit: String,
+    /// Who made the tag and why, if it's annotated.
+    pub annotation: Option<TagMetadata>,
+}
+
+/// A name for a commit based on the nearest tag in its history, as worked out
+/// by [`Repository::describe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Description {
+    pub tag: String,
+    /// How many commits have been made since the tag.
+    pub distance: usize,
+    /// The hash of the commit being described.
+    pub commit: String,
+}
+
+impl Display for Description {
+    /// Formats the description like git does, as `v1.2-14-gabc1234`, where
+    /// the `g` stands for git, or just `v1.2` if the commit is the tagged one.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.distance {
+            0 => write!(f, "{}", self.tag),
+            distance => write!(
+                f,
+                "{}-{distance}-g{}",
+                self.tag,
+                resolve::abbreviate(&self.commit)
+            ),
+        }
+    }
+}
+
+/// Something pointing at a commit, shown next to it in the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoration {
+    /// HEAD, along with the branch it's on, unless it's detached.
+    Head(Option<String>),
+    Branch(String),
+    /// A remote branch, like `origin/main`.
+    RemoteBranch(String),
+    Tag(String),
+}
+
+impl Display for Decoration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Head(Some(branch)) => write!(f, "HEAD -> {branch}"),
+            Self::Head(None) => write!(f, "HEAD"),
+            Self::Branch(name) | Self::RemoteBranch(name) => write!(f, "{name}"),
+            Self::Tag(name) => write!(f, "tag: {name}"),
+        }
+    }
+}
+
+/// What happened when merging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// Everything being merged was already part of HEAD's history.
+    UpToDate,
+    /// HEAD was part of the history being merged, so we just moved forward to
+    /// the commit with this hash.
+    FastForward(String),
+    /// Both sides had commits of their own, so we created a merge commit with
+    /// this hash.
+    Merged(String),
+}
+
+/// When [`Repository::merge`] is allowed to fast-forward instead of making a
+/// merge commit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FastForwardMode {
+    /// Fast-forward whenever we can, and make a merge commit otherwise.
+    #[default]
+    Allowed,
+    /// Only ever fast-forward, refusing if that's not possible.
+    Only,
+    /// Always make a merge commit, even if we could fast-forward, so that the
+    /// history shows where the branch was merged.
+    Never,
+}
+
+/// The branch HEAD starts out on in a new nest, unless we're told otherwise.
+pub const DEFAULT_BRANCH: &str = "main";
+
+/// Where [`Repository::migrate`] keeps the copies made by the first version of
+/// rat once it's turned them into commits, inside the nest.
+pub const MIGRATED_DIR: &str = "migrated";
+
+/// The file the first version of rat kept each commit's message in, inside
+/// its copy of the working directory.
+const OLD_MESSAGE_FILE: &str = ".message";
+
+/// What [`Repository::init`] found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitOutcome {
+    /// There was no nest, so we made one.
+    Created,
+    /// There was a nest already, which we left alone.
+    Reinitialized,
+}
+
+/// What [`Repository::migrate`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrateOutcome {
+    /// The nest was already laid out the newest way, and said so.
+    UpToDate,
+    /// The nest was laid out the newest way, but didn't say so, so all we
+    /// had to do was write its `format` file.
+    Recorded,
+    /// The nest held the snapshots of the first version of rat, which are now
+    /// this many commits.
+    Snapshots { commits: usize },
+}
+
+/// Lets the user edit some text, like a commit message, and gives back what
+/// they wrote. It's given the name of a file in the nest to write the text
+/// to, and what the text should start out as.
+pub type Editor<'a> = dyn FnMut(&str, &str) -> Result<String, Box<dyn Error>> + 'a;
+
+/// What happened when rebasing, as returned by [`Repository::rebase`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RebaseOutcome {
+    /// The branch already has everything from upstream, so nothing changed.
+    UpToDate,
+    /// The branch had nothing of its own, so it was moved forward to
+    /// upstream, which has this hash.
+    FastForward(String),
+    /// The branch's own commits were replayed on top of upstream, and it now
+    /// points at the commit with this hash.
+    Rebased(String),
+}
+
+/// How much of the nest [`Repository::reset`] should change, besides the
+/// current branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Leave the index and the working directory alone, so everything that
+    /// changed since the commit is left staged.
+    Soft,
+    /// Reset the index too, so those changes are left in the working directory
+    /// but aren't staged any more.
+    Mixed,
+    /// Reset the working directory as well, throwing those changes away.
+    Hard,
+}
+
+/// The three snapshots an operation that stopped with conflicts was merging,
+/// as given by [`Repository::conflict_sides`].
+#[derive(Debug, Clone)]
+pub struct ConflictSides {
+    /// What both sides started from.
+    pub base: Snapshot,
+    /// HEAD, which the changes are being made on top of.
+    pub ours: Snapshot,
+    /// The commit whose changes are being brought in.
+    pub theirs: Snapshot,
+}
+
+/// One of the two sides of a conflict, for picking its version of a file with
+/// [`Repository::restore_side`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictSide {
+    Ours,
+    Theirs,
+}
+
+/// One of the two sides [`Repository::diff`] compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffSide<'a> {
+    /// The files in the commit a revision names.
+    Revision(&'a str),
+    /// The files that are staged to be committed.
+    Index,
+    /// The tracked files as they are right now.
+    WorkingDirectory,
+}
+
+/// How the index differs from the last commit, and the working directory from
+/// the index, as found by [`Repository::status`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Status {
+    /// The operation that stopped halfway, if any, along with the commit it
+    /// was working on.
+    pub operation: Option<(Operation, String)>,
+    /// Paths that still have conflicts from that operation.
+    pub conflicts: BTreeSet<String>,
+    /// What the next commit would change if we made it right now.
+    pub staged: BTreeMap<String, Change>,
+    /// Changes to tracked files that haven't been staged yet.
+    pub unstaged: BTreeMap<String, Change>,
+    /// Files in the working directory that aren't being tracked at all.
+    pub untracked: Vec<String>,
+}
+
+impl Status {
+    /// Checks whether there's nothing at all to report.
+    pub fn is_clean(&self) -> bool {
+        self.operation.is_none()
+            && self.staged.is_empty()
+            && self.unstaged.is_empty()
+            && self.untracked.is_empty()
+    }
+}
+
+impl Repository {
+    /// Opens the nest in the current directory, or wherever
+    /// [`set_nest_dir`](crate::set_nest_dir) pointed us.
+    pub fn open() -> Result<Self, InitError> {
+        if !nest_dir().is_dir() {
+            return Err(InitError::NotANest {
+                path: env::current_dir()?,
+            });
+        }
+
+        // Reading or writing a nest laid out differently from what we expect
+        // would make a mess of it, so we stop before either can happen.
+        format::check(nest_dir())?;
+
+        Ok(Self {
+            bare: crate::is_bare(nest_dir()),
+        })
+    }
+
+    /// Initializes a new rat nest in the current directory, with HEAD on
+    /// `initial_branch`, naming its objects with the [`hash`](crate::hash)
+    /// function `algorithm`, or SHA-256 if it isn't given.
+    ///
+    /// A `bare` nest has no working directory, and is meant to be pushed to
+    /// and fetched from rather than worked in. Its contents go straight into
+    /// the nest directory, which is usually the current directory itself
+    /// rather than `.rat`.
+    ///
+    /// Just like in git, running this where there's already a nest is
+    /// perfectly safe. It leaves the nest exactly as it was, and we just say
+    /// so in the outcome, unless it uses a different hash function than
+    /// `algorithm`.
+    pub fn init(
+        bare: bool,
+        initial_branch: &str,
+        algorithm: Option<HashAlgorithm>,
+    ) -> Result<(Self, InitOutcome), InitError> {
+        if nest_path("HEAD").exists() {
+            let repository = Self::open()?;
+            let existing = hash::current();
+
+            if algorithm.is_some_and(|algorithm| algorithm != existing) {
+                Err(InitError::HashMismatch { existing })?;
+            }
+
+            return Ok((repository, InitOutcome::Reinitialized));
+        }
+
+        if !refs::is_valid_name(initial_branch) {
+            Err(RefError::InvalidName {
+                kind: "branch",
+                name: initial_branch.to_string(),
+            })?;
+        }
+
+        fs::create_dir_all(nest_dir())?;
+        format::write(nest_dir(), &Format::new(algorithm.unwrap_or_default()))?;
+        fs::create_dir(nest_path("objects"))?;
+        fs::create_dir_all(nest_path("refs/heads"))?;
+        // Hooks are the user's to add, so this starts out empty.
+        fs::create_dir(nest_path("hooks"))?;
+        // HEAD starts out on the initial branch. The branch itself doesn't
+        // exist until the first commit is made on it, since there's nothing for
+        // it to point to.
+        refs::set_head_branch(initial_branch)?;
+        if bare {
+            fs::write(nest_path("config"), "[core]\n\tbare = true\n")?;
+        } else {
+            // The index starts out empty, since nothing has been staged yet.
+            // Bare nests never stage anything, so they don't need one.
+            fs::write(nest_path("index"), "")?;
+        }
+
+        Ok((Self { bare }, InitOutcome::Created))
+    }
+
+    /// Upgrades the nest in the current directory to the newest
+    /// [`format`](crate::format), in place.
+    ///
+    /// So far, the only upgrade is from the very first version of rat, which
+    /// copied the whole working directory into a `commit-N` directory for
+    /// every commit, with its message in a `.message` file alongside. Each
+    /// copy becomes a commit on the main branch with that message, made by
+    /// whoever is migrating at the time the copy was made. The index is filled
+    /// from the last one, so as far as rat is concerned, the working directory
+    /// is just as it was.
+    ///
+    /// Once everything has been written, the copies and the old `HEAD` are
+    /// moved into [`MIGRATED_DIR`] rather than deleted, so nothing is lost if
+    /// the upgrade turns out to be wrong.
+    pub fn migrate() -> Result<MigrateOutcome, Box<dyn Error>> {
+        if !nest_dir().is_dir() {
+            Err(InitError::NotANest {
+                path: env::current_dir()?,
+            })?;
+        }
+
+        let current = format::read(nest_dir())?;
+
+        if current.version == format::VERSION {
+            if nest_path("format").exists() {
+                return Ok(MigrateOutcome::UpToDate);
+            }
+
+            format::write(nest_dir(), &current)?;
+
+            return Ok(MigrateOutcome::Recorded);
+        }
+
+        // A copy numbered after HEAD is one that was still being made when
+        // rat stopped, so it was never really a commit.
+        let head: i64 = fs::read_to_string(nest_path("HEAD"))?.trim().parse()?;
+        let mut snapshots = Vec::new();
+
+        for entry in fs::read_dir(nest_dir())? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            if let Some(number) = name
+                .strip_prefix("commit-")
+                .and_then(|n| n.parse::<i64>().ok())
+            {
+                if number <= head {
+                    snapshots.push((number, entry.path()));
+                }
+            }
+        }
+
+        snapshots.sort();
+
+        fs::create_dir_all(nest_path("objects"))?;
+        fs::create_dir_all(nest_path("refs/heads"))?;
+        fs::create_dir_all(nest_path("hooks"))?;
+
+        let mut parent = None;
+        let mut snapshot = Snapshot::new();
+
+        for (number, dir) in &snapshots {
+            snapshot = store_directory(dir, "")?;
+
+            // The message was kept in the copy itself, but it was never one
+            // of the user's files, so it goes in the commit instead.
+            snapshot.remove(OLD_MESSAGE_FILE);
+
+            let message = match fs::read_to_string(dir.join(OLD_MESSAGE_FILE)) {
+                Ok(message) if !message.trim().is_empty() => {
+                    format!("{}\n", message.trim_end())
+                }
+                Ok(_) => format!("Commit number {number}\n"),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    format!("Commit number {number}\n")
+                }
+                Err(e) => Err(e)?,
+            };
+
+            // The copy was made when the commit was, so that's the best idea
+            // we have of when it happened.
+            let timestamp = fs::metadata(dir)?
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs() as i64);
+
+            let metadata = CommitMetadata {
+                tree: objects::write_tree(&snapshot)?,
+                parents: parent.into_iter().collect(),
+                author: Signature {
+                    timestamp,
+                    ..identity("AUTHOR")?
+                },
+                committer: Signature {
+                    timestamp,
+                    ..identity("COMMITTER")?
+                },
+                message,
+                signature: None,
+            };
+
+            parent = Some(objects::write_object(
+                ObjectKind::Commit,
+                metadata.serialize().as_bytes(),
+            )?);
+        }
+
+        if let Some(hash) = &parent {
+            refs::write_ref(&Ref::Branch(DEFAULT_BRANCH.to_string()), hash)?;
+        }
+
+        Index { entries: snapshot }.write(nest_path("index"))?;
+
+        // The old HEAD is the only thing that says which copies were really
+        // commits, so it's kept along with them before it's replaced.
+        let backup = nest_path(MIGRATED_DIR);
+        fs::create_dir_all(&backup)?;
+        fs::copy(nest_path("HEAD"), backup.join("HEAD"))?;
+
+        refs::set_head_branch(DEFAULT_BRANCH)?;
+        format::write(nest_dir(), &Format::new(HashAlgorithm::default()))?;
+
+        // Once HEAD is on the branch, the nest is laid out the new way, so
+        // the copies can be moved out of the way.
+        for entry in fs::read_dir(nest_dir())? {
+            let entry = entry?;
+
+            if entry.file_name().to_string_lossy().starts_with("commit-") {
+                fs::rename(entry.path(), backup.join(entry.file_name()))?;
+            }
+        }
+
+        Ok(MigrateOutcome::Snapshots {
+            commits: snapshots.len(),
+        })
+    }
+
+    /// Checks whether the nest is bare, in which case there's no working
+    /// directory, so anything that needs one won't work.
+    pub fn is_bare(&self) -> bool {
+        self.bare
+    }
+
+    /// Copies the nest in the directory `source` into a new directory, along
+    /// with all of its history, moves into it, and returns the new nest along
+    /// with the name of the directory.
+    ///
+    /// The new directory is `destination` if it's given, and otherwise gets
+    /// the same name as `source`. The source nest is set up as a remote called
+    /// `origin`, so its branches end up as remote branches like `origin/main`,
+    /// and we then create a local branch for whichever branch was checked out
+    /// in it.
+    ///
+    /// If `mirror` is set, the copy is a bare nest with exactly the same
+    /// branches, tags and notes as the source instead, and `origin` is set up
+    /// as a mirror, so fetching from it keeps them exactly the same.
+    pub fn clone(
+        source: &str,
+        destination: Option<&str>,
+        mirror: bool,
+    ) -> Result<(Self, String), Box<dyn Error>> {
+        // We're about to move into the new directory, so if the source is a
+        // path, we need to hold on to an absolute path to get back to it.
+        let source_url = if http::is_url(source) {
+            source.to_string()
+        } else {
+            fs::canonicalize(source)
+                .map_err(|e| format!("Failed to find the nest {source}: {e}"))?
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        let transport = transport::open(&source_url)?;
+
+        // Nests served over HTTP are usually named like thesis.rat, in which
+        // case we just want thesis.
+        let destination = match destination {
+            Some(destination) => destination.to_string(),
+            None => source_url
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .map(|name| name.trim_end_matches(".rat"))
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| InitError::NoDirectoryName {
+                    url: source.to_string(),
+                })?
+                .to_string(),
+        };
+
+        // Cloning into an existing directory is fine as long as it's empty,
+        // but we'd rather not mix somebody else's history with whatever else
+        // is there.
+        if fs::read_dir(&destination).is_ok_and(|mut entries| entries.next().is_some()) {
+            Err(InitError::NotEmpty {
+                path: destination.clone().into(),
+            })?;
+        }
+
+        fs::create_dir_all(&destination)?;
+        env::set_current_dir(&destination)?;
+
+        // A mirror is only there to be a copy, so nobody works in it.
+        if mirror {
+            crate::set_nest_dir(".");
+        }
+
+        // Our hashes have to match the source's, or none of its history
+        // would fit.
+        let algorithm = Some(transport.hash_algorithm()?);
+        let (repository, _) = Self::init(mirror, DEFAULT_BRANCH, algorithm)?;
+        remote::add("origin", &source_url)?;
+
+        if mirror {
+            let path = config::nest_config_path();
+            let mut file = ConfigFile::read(&path)?;
+            file.set("remote.origin.mirror", "true")?;
+            file.write(&path)?;
+
+            remote::fetch("origin", false)?;
+
+            if let Head::Branch(branch) = transport.read_head()? {
+                refs::set_head_branch(&branch)?;
+            }
+
+            return Ok((repository, destination));
+        }
+
+        // Fetching copies all of the history, along with the tags, and leaves
+        // us with a remote branch for each branch in the source.
+        remote::fetch("origin", false)?;
+
+        let branches = refs::list_remote_branches("origin")?;
+
+        // We start out on whichever branch the source nest is on. If it's
+        // detached or on a branch with no commits, we settle for main, or
+        // failing that, whichever branch comes first.
+        let default_branch = match transport.read_head()? {
+            Head::Branch(branch) if branches.contains(&branch) => Some(branch),
+            _ => branches
+                .iter()
+                .find(|branch| *branch == DEFAULT_BRANCH)
+                .or(branches.first())
+                .cloned(),
+        };
+
+        // If the source nest doesn't have any commits yet, there's nothing to
+        // check out, and we're left with an empty nest just like after init.
+        if let Some(branch) = default_branch {
+            let hash = refs::read_ref(&Ref::Remote {
+                remote: "origin".to_string(),
+                branch: branch.clone(),
+            })?
+            .ok_or_else(|| RefError::NotFound {
+                kind: "remote branch",
+                name: format!("origin/{branch}"),
+            })?;
+
+            refs::write_ref(&Ref::Branch(branch.clone()), &hash)?;
+            refs::set_head_branch(&branch)?;
+
+            restore_snapshot(
+                &Snapshot::new(),
+                &Snapshot::new(),
+                compare::read_commit(Some(&hash))?,
+            )?;
+        }
+
+        Ok((repository, destination))
+    }
+
+    /// Serves the nest over HTTP on `listener`, so that other nests can clone
+    /// and fetch from it, until the process is stopped.
+    ///
+    /// We serve exactly the files that the HTTP transport downloads, which are
+    /// `HEAD`, the objects, and `info/refs`, which we generate on the fly from
+    /// the branches and tags. Nothing else in the nest is reachable, and
+    /// nothing can be changed, so it's safe to leave running.
+    pub fn serve(&self, listener: TcpListener) {
+        http::serve(listener, |path| {
+            if path == "/info/refs" {
+                let mut listing = String::new();
+
+                for name in refs::list_all_refs()? {
+                    if !matches!(name, Ref::Branch(_) | Ref::Tag(_)) {
+                        continue;
+                    }
+
+                    if let Some(hash) = refs::read_ref(&name)? {
+                        listing.push_str(&format!("{hash} {name}\n"));
+                    }
+                }
+
+                return Ok(Some(listing.into_bytes()));
+            }
+
+            if path == "/HEAD" {
+                return Ok(Some(refs::format_head(&refs::read_head()?).into_bytes()));
+            }
+
+            // Objects and the contents of files kept out of the nest are the
+            // only other things we serve, and we're careful to only accept
+            // something that looks exactly like one of their paths, so nobody
+            // can use .. or anything like it to read other files.
+            let is_hex = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit());
+
+            if let Some((directory, file)) = path
+                .strip_prefix("/lfs/objects/")
+                .and_then(|rest| rest.split_once('/'))
+            {
+                if directory.len() != 2 || !is_hex(directory) || !is_hex(file) {
+                    return Ok(None);
+                }
+
+                let hash = format!("{directory}{file}");
+
+                return match fs::read(lfs::content_path_in(lfs::storage(), &hash)) {
+                    Ok(contents) => Ok(Some(contents)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e.into()),
+                };
+            }
+
+            let Some((directory, file)) = path
+                .strip_prefix("/objects/")
+                .and_then(|rest| rest.split_once('/'))
+            else {
+                return Ok(None);
+            };
+
+            if directory.len() != 2 || !is_hex(directory) || !is_hex(file) {
+                return Ok(None);
+            }
+
+            // The object might be packed, in which case there's no file to
+            // send as it is, so we always send it in the form a loose object
+            // would have.
+            match objects::read_object(&format!("{directory}{file}")) {
+                Ok((kind, data)) => Ok(Some(objects::encode(kind, &data))),
+                Err(ObjectError::NotFound { .. }) => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    /// Stages the current contents of each of `paths` in the index, returning
+    /// how many entries of the index actually changed as a result.
+    ///
+    /// Directories are staged recursively. A path that no longer exists in the
+    /// working directory is removed from the index instead, which is how
+    /// deleting a file gets recorded in the next commit.
+    pub fn add(&self, paths: &[impl AsRef<Path>]) -> Result<usize, AddError> {
+        let index_file = nest_path("index");
+        let mut index = Index::read(&index_file)?;
+
+        // We keep a copy of the original entries around so we can tell the
+        // user how many files were actually affected at the end.
+        let original_entries = index.entries.clone();
+
+        // Staging a file is how the user tells us they've resolved its
+        // conflicts, even if they resolved them by keeping what was already
+        // staged.
+        let mut conflicts = state::read_conflicts()?;
+
+        // Files that haven't changed since we last hashed them are probably
+        // stored already, in which case there's no need to read them again.
+        let mut cache = StatCache::load();
+
+        for path in paths {
+            let path = path.as_ref();
+            let entry_path = utils::normalize_path(path).ok_or_else(|| AddError::OutsideNest {
+                path: path.to_path_buf(),
+            })?;
+
+            if !index::is_valid_path(&entry_path) {
+                Err(AddError::LineBreak {
+                    path: path.to_path_buf(),
+                })?;
+            }
+
+            conflicts.retain(|conflict| {
+                !entry_path.is_empty()
+                    && conflict != &entry_path
+                    && !conflict.starts_with(&format!("{entry_path}/"))
+            });
+
+            // The nest itself is never something we want to commit.
+            if entry_path == RAT_NEST || entry_path.starts_with(&format!("{RAT_NEST}/")) {
+                continue;
+            }
+
+            // Symlinks are staged as links, even ones that point to a
+            // directory, so we mustn't follow them to find out what they are.
+            let file_type = fs::symlink_metadata(path).map(|metadata| metadata.file_type());
+
+            // Another nest is staged as a pointer to the commit it's on, just
+            // like a file, rather than everything inside it.
+            if file_type
+                .as_ref()
+                .is_ok_and(|file_type| !file_type.is_dir())
+                || submodules::is_nest(&entry_path)
+            {
+                // Explicitly adding an ignored file is most likely a mistake,
+                // so we refuse instead of silently staging it.
+                if ignore::is_ignored(&entry_path)? {
+                    Err(AddError::Ignored {
+                        path: path.to_path_buf(),
+                    })?;
+                }
+
+                let (mut entry, stat) = cache.write_file(&entry_path)?;
+                worktree::keep_tracked_mode(&mut entry, index.entries.get(&entry_path));
+
+                cache.insert(entry_path.clone(), stat, &entry);
+                index.stage(entry_path, entry);
+            } else if file_type.is_ok_and(|file_type| file_type.is_dir()) {
+                // We make sure to get rid of anything previously staged inside
+                // this directory first, so that files deleted from it are
+                // unstaged too.
+                index.unstage(&entry_path);
+
+                // Storing the files means hashing them, which is slow enough
+                // for big directories that it's worth doing in parallel.
+                let files = ignore::list_working_files(&entry_path)?;
+                let task = progress::start("Storing files", files.len());
+                let hashes = utils::parallel_map(&files, |file| {
+                    let result = cache.write_file(file);
+                    task.advance(utils::file_size(file));
+                    result
+                });
+                drop(task);
+
+                for (file, result) in files.into_iter().zip(hashes) {
+                    let (mut entry, stat) = result?;
+                    worktree::keep_tracked_mode(&mut entry, original_entries.get(&file));
+
+                    cache.insert(file.clone(), stat, &entry);
+                    index.stage(file, entry);
+                }
+            } else if index.unstage(&entry_path) == 0 {
+                Err(AddError::NoMatch {
+                    path: path.to_path_buf(),
+                })?;
+            }
+        }
+
+        index.write(index_file)?;
+        state::write_conflicts(&conflicts)?;
+
+        // The cache only saves time, so failing to write it isn't worth failing
+        // over.
+        let _ = cache.save();
+
+        // An entry counts as changed if it was added, removed, or its contents
+        // are different from what they were before.
+        let count = original_entries
+            .keys()
+            .chain(index.entries.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|path| original_entries.get(*path) != index.entries.get(*path))
+            .count();
+
+        Ok(count)
+    }
+
+    /// Works out which snapshots the operation in progress was merging when it
+    /// stopped with conflicts, so that each version of a conflicted file can be
+    /// looked at on its own.
+    pub fn conflict_sides(&self) -> Result<ConflictSides, Box<dyn Error>> {
+        let (operation, their_hash) = state::current()?.ok_or(StateError::NothingInProgress)?;
+        let our_hash = refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+
+        let base_hash = match operation {
+            Operation::Merge => graph::merge_base(&our_hash, &their_hash)?,
+            // A cherry-pick, like each step of a rebase, makes the changes
+            // from a commit's parent to the commit itself.
+            Operation::CherryPick | Operation::Rebase => {
+                objects::read_commit(&their_hash)?.parents.first().cloned()
+            }
+        };
+
+        Ok(ConflictSides {
+            base: compare::read_commit(base_hash.as_deref())?,
+            ours: compare::read_commit(Some(&our_hash))?,
+            theirs: compare::read_commit(Some(&their_hash))?,
+        })
+    }
+
+    /// Replaces each conflicted file at `paths` in the working directory with
+    /// its version from `side`, throwing away the conflict markers, and
+    /// returns the files that were replaced. A file that `side` deleted is
+    /// deleted.
+    ///
+    /// The files still count as conflicted until they're staged, so that
+    /// there's a chance to look them over first.
+    pub fn restore_side(
+        &self,
+        paths: &[impl AsRef<Path>],
+        side: ConflictSide,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let conflicts = state::read_conflicts()?;
+        let mut restored = BTreeSet::new();
+
+        for path in paths {
+            let path = path.as_ref();
+            let entry_path = utils::normalize_path(path).ok_or_else(|| AddError::OutsideNest {
+                path: path.to_path_buf(),
+            })?;
+
+            let matching: Vec<&String> = conflicts
+                .iter()
+                .filter(|conflict| {
+                    entry_path.is_empty()
+                        || **conflict == entry_path
+                        || conflict.starts_with(&format!("{entry_path}/"))
+                })
+                .collect();
+
+            if matching.is_empty() {
+                Err(StateError::NoConflicts(path.to_path_buf()))?;
+            }
+
+            restored.extend(matching.into_iter().cloned());
+        }
+
+        let sides = self.conflict_sides()?;
+        let snapshot = match side {
+            ConflictSide::Ours => sides.ours,
+            ConflictSide::Theirs => sides.theirs,
+        };
+
+        for path in &restored {
+            match snapshot.get(path) {
+                Some(entry) => worktree::write_working_file(path, entry)?,
+                None => worktree::remove_working_file(path)?,
+            }
+        }
+
+        Ok(restored.into_iter().collect())
+    }
+
+    /// Works out the changes to the files at `paths` that haven't been staged
+    /// yet and can be staged a hunk at a time, for `rat add -p`.
+    ///
+    /// Only ordinary text files that are already being tracked are included,
+    /// since anything else doesn't have lines to pick between. Each patch has
+    /// at least one hunk.
+    pub fn unstaged_patches(
+        &self,
+        paths: &[impl AsRef<Path>],
+    ) -> Result<Vec<FilePatch>, Box<dyn Error>> {
+        let mut entry_paths = Vec::new();
+
+        for path in paths {
+            let path = path.as_ref();
+
+            entry_paths.push(
+                utils::normalize_path(path).ok_or_else(|| AddError::OutsideNest {
+                    path: path.to_path_buf(),
+                })?,
+            );
+        }
+
+        let mut patches = Vec::new();
+
+        for patch in self.diff(DiffSide::Index, DiffSide::WorkingDirectory, Detection::Off)? {
+            let chosen = entry_paths.iter().any(|entry_path| {
+                entry_path.is_empty()
+                    || patch.path == *entry_path
+                    || patch.path.starts_with(&format!("{entry_path}/"))
+            });
+
+            let ordinary = [&patch.old, &patch.new].iter().all(|entry| {
+                entry
+                    .as_ref()
+                    .is_some_and(|e| matches!(e.mode, FileMode::Regular | FileMode::Executable))
+            });
+
+            let has_hunks =
+                matches!(&patch.content, PatchContent::Text(hunks) if !hunks.is_empty());
+
+            // Files kept out of the nest are stored as a pointer, which it
+            // makes no sense to take only some lines of. Lines that aren't
+            // valid UTF-8 can't be put back together exactly as they were.
+            if !chosen
+                || !ordinary
+                || !has_hunks
+                || lfs::applies_to(&patch.path, utils::file_size(&patch.path))
+                || String::from_utf8(worktree::read_working_file(&patch.path)?).is_err()
+            {
+                continue;
+            }
+
+            patches.push(patch);
+        }
+
+        Ok(patches)
+    }
+
+    /// Stages only `hunks` out of the changes to the file at `path` that
+    /// haven't been staged yet, as given by
+    /// [`unstaged_patches`](Self::unstaged_patches) and perhaps
+    /// [`PatchHunk::split`]. The working directory is left alone, so the rest
+    /// of the changes are still there to be staged later.
+    pub fn stage_hunks(&self, path: &str, hunks: &[PatchHunk]) -> Result<(), Box<dyn Error>> {
+        let index_file = nest_path("index");
+        let mut index = Index::read(&index_file)?;
+
+        let entry = index
+            .entries
+            .get(path)
+            .ok_or_else(|| AddError::NotTracked {
+                path: path.to_string(),
+            })?;
+
+        let staged =
+            String::from_utf8(objects::read_object_of_kind(&entry.hash, ObjectKind::Blob)?)
+                .map_err(|_| format!("The staged version of {path} isn't text."))?;
+
+        // The new version is what was staged, with only the chosen changes
+        // made to it. It keeps the mode that was staged, since a change of mode
+        // isn't part of any hunk.
+        let contents = patch::apply_hunks(&diff::split_lines(&staged), hunks);
+        let entry = Entry {
+            mode: entry.mode,
+            hash: objects::write_object(ObjectKind::Blob, contents.as_bytes())?,
+        };
+
+        index.stage(path.to_string(), entry);
+        index.write(index_file)?;
+
+        Ok(())
+    }
+
+    /// Stops tracking each of `paths`, and deletes them from the working
+    /// directory too unless `cached` is set, returning the paths that were
+    /// removed. Directories are only removed if `recursive` is set.
+    ///
+    /// To avoid losing work, we refuse to remove a file with changes that
+    /// haven't been committed, unless `force` is set. With `cached`, the file
+    /// stays in the working directory, so it only matters if what's staged
+    /// would be lost, which is when it matches neither HEAD nor the working
+    /// directory. Nothing is removed unless every path can be.
+    pub fn remove(
+        &self,
+        paths: &[impl AsRef<Path>],
+        cached: bool,
+        recursive: bool,
+        force: bool,
+    ) -> Result<Vec<String>, AddError> {
+        let index_file = nest_path("index");
+        let mut index = Index::read(&index_file)?;
+        let head_snapshot = compare::read_commit(refs::resolve_head()?.as_deref())?;
+
+        let mut removed = BTreeSet::new();
+
+        for path in paths {
+            let path = path.as_ref();
+            let entry_path = utils::normalize_path(path).ok_or_else(|| AddError::OutsideNest {
+                path: path.to_path_buf(),
+            })?;
+
+            let matching: Vec<&String> = index
+                .entries
+                .keys()
+                .filter(|tracked| {
+                    entry_path.is_empty()
+                        || **tracked == entry_path
+                        || tracked.starts_with(&format!("{entry_path}/"))
+                })
+                .collect();
+
+            if matching.is_empty() {
+                Err(AddError::NoMatch {
+                    path: path.to_path_buf(),
+                })?;
+            }
+
+            if !recursive && matching.iter().any(|tracked| **tracked != entry_path) {
+                Err(AddError::IsDirectory {
+                    path: path.to_path_buf(),
+                })?;
+            }
+
+            removed.extend(matching.into_iter().cloned());
+        }
+
+        if !force {
+            for path in &removed {
+                let staged = &index.entries[path];
+                let committed = head_snapshot.get(path) == Some(staged);
+
+                // A file that's already gone from the working directory can't
+                // lose anything by being deleted from it.
+                let working = match worktree::hash_working_file(path) {
+                    Ok(entry) => Some(entry.hash),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                    Err(e) => Err(e)?,
+                };
+                let saved = working.as_ref().is_none_or(|hash| *hash == staged.hash);
+
+                let loses_work = if cached {
+                    !committed && !saved
+                } else {
+                    !committed || !saved
+                };
+
+                if loses_work {
+                    Err(AddError::HasChanges { path: path.clone() })?;
+                }
+            }
+        }
+
+        for path in &removed {
+            index.entries.remove(path);
+
+            if !cached {
+                worktree::remove_working_file(path).map_err(|e| AddError::Other(Box::new(e)))?;
+            }
+        }
+
+        index.write(&index_file)?;
+
+        // Removing a file is one way of resolving its conflicts, and it can't
+        // have been renamed to anywhere if it's gone.
+        let mut conflicts = state::read_conflicts()?;
+        conflicts.retain(|path| !removed.contains(path));
+        state::write_conflicts(&conflicts)?;
+
+        let mut renames = index::read_renames(nest_path("RENAMES"))?;
+        renames.retain(|new, _| !removed.contains(new));
+        index::write_renames(nest_path("RENAMES"), &renames)?;
+
+        Ok(removed.into_iter().collect())
+    }
+
+    /// Moves the file or directory `source` to `destination`, in both the
+    /// working directory and the index, returning the old and new path of
+    /// every tracked file that moved. If `destination` is a directory that
+    /// already exists, `source` is moved inside it.
+    ///
+    /// The moves are noted down as hints for spotting renames, since
+    /// otherwise they'd look just like deleting one file and adding another.
+    /// We refuse to replace anything already at `destination` unless `force`
+    /// is set.
+    pub fn move_path(
+        &self,
+        source: impl AsRef<Path>,
+        destination: impl AsRef<Path>,
+        force: bool,
+    ) -> Result<Vec<(String, String)>, AddError> {
+        let (source, destination) = (source.as_ref(), destination.as_ref());
+        let normalize = |path: &Path| {
+            utils::normalize_path(path)
+                .filter(|normalized| {
+                    !normalized.is_empty()
+                        && normalized != RAT_NEST
+                        && !normalized.starts_with(&format!("{RAT_NEST}/"))
+                })
+                .ok_or_else(|| AddError::OutsideNest {
+                    path: path.to_path_buf(),
+                })
+        };
+
+        let old_path = normalize(source)?;
+        let mut new_path = normalize(destination)?;
+
+        if !index::is_valid_path(&new_path) {
+            Err(AddError::LineBreak {
+                path: destination.to_path_buf(),
+            })?;
+        }
+
+        if destination.is_dir() {
+            let name = old_path.rsplit('/').next().unwrap_or(&old_path);
+            new_path = format!("{new_path}/{name}");
+        }
+
+        let index_file = nest_path("index");
+        let mut index = Index::read(&index_file)?;
+
+        let moved: Vec<(String, String)> = index
+            .entries
+            .keys()
+            .filter_map(|tracked| {
+                let rest = tracked.strip_prefix(&old_path)?;
+
+                (rest.is_empty() || rest.starts_with('/'))
+                    .then(|| (tracked.clone(), format!("{new_path}{rest}")))
+            })
+            .collect();
+
+        if moved.is_empty() || fs::symlink_metadata(&old_path).is_err() {
+            Err(AddError::NoMatch {
+                path: source.to_path_buf(),
+            })?;
+        }
+
+        if let Ok(metadata) = fs::symlink_metadata(&new_path) {
+            // Replacing a whole directory is never what anybody means.
+            if !force || metadata.is_dir() {
+                Err(AddError::DestinationExists {
+                    path: PathBuf::from(&new_path),
+                })?;
+            }
+        }
+
+        if let Some(parent) = Path::new(&new_path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::rename(&old_path, &new_path)?;
+
+        let mut renames = index::read_renames(nest_path("RENAMES"))?;
+
+        for (old, new) in &moved {
+            let entry = index
+                .entries
+                .remove(old)
+                .expect("the path came from the index");
+            index.stage(new.clone(), entry);
+
+            // A file that's moved twice has still only been renamed once, from
+            // wherever it started.
+            let original = renames.remove(old).unwrap_or_else(|| old.clone());
+
+            if original != *new {
+                renames.insert(new.clone(), original);
+            }
+        }
+
+        index.write(&index_file)?;
+        index::write_renames(nest_path("RENAMES"), &renames)?;
+
+        Ok(moved)
+    }
+
+    /// Lists the files that are `tracked`, along with the untracked ones that
+    /// aren't ignored if `others` is set, and the untracked ones that are if
+    /// `ignored` is set.
+    pub fn list_files(
+        &self,
+        tracked: bool,
+        others: bool,
+        ignored: bool,
+    ) -> Result<BTreeSet<String>, Box<dyn Error>> {
+        let index = Index::read(nest_path("index"))?.entries;
+        let mut files = BTreeSet::new();
+
+        if tracked {
+            files.extend(index.keys().cloned());
+        }
+
+        if others {
+            files.extend(ignore::list_working_files("")?);
+        }
+
+        if ignored {
+            files.extend(ignore::list_ignored_files()?);
+        }
+
+        // Tracked files can turn up as untracked ones too, like when they were
+        // added before being ignored, but they shouldn't unless asked for.
+        if !tracked {
+            files.retain(|path| !index.contains_key(path));
+        }
+
+        Ok(files)
+    }
+
+    /// Finds every file in the working directory that isn't tracked, along
+    /// with every directory with nothing tracked inside if `directories` is
+    /// set, and deletes them if
+    /// `force` is set. Ignored files are left alone unless `include_ignored`
+    /// is set. Either way, returns what was or would be deleted, with a `/` on
+    /// the end of each directory.
+    pub fn clean(
+        &self,
+        directories: bool,
+        include_ignored: bool,
+        force: bool,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let tracked = Index::read(nest_path("index"))?
+            .entries
+            .into_keys()
+            .collect();
+        let untracked = ignore::list_untracked(&tracked, directories, include_ignored)?;
+
+        if force {
+            for path in &untracked {
+                match path.strip_suffix('/') {
+                    Some(dir) => fs::remove_dir_all(dir)?,
+                    None => fs::remove_file(path)?,
+                }
+            }
+        }
+
+        Ok(untracked)
+    }
+
+    /// Commits the contents of the index to the nest, returning the hash of
+    /// the new commit.
+    ///
+    /// The commit's message comes from calling `message`, which only happens
+    /// once the `pre-commit` hook has passed, so that if the message comes
+    /// from the user's editor, they never write one for nothing.
+    ///
+    /// A commit that doesn't change anything is almost always a mistake, like
+    /// forgetting to stage the changes first, so we refuse to make one unless
+    /// `allow_empty` is set.
+    ///
+    /// If `only` is given, only the files at those paths are committed, as
+    /// they are in the index, and everything else is carried over from HEAD
+    /// as it is, staying staged for a later commit.
+    pub fn commit(
+        &self,
+        message: impl FnOnce() -> Result<String, CommitError>,
+        allow_empty: bool,
+        only: Option<&[String]>,
+    ) -> Result<String, CommitError> {
+        // Committing in the middle of something like a cherry-pick would lose
+        // track of where the changes came from.
+        state::ensure_idle()?;
+
+        if !allow_empty && self.changes_to_commit(false, only)?.is_empty() {
+            Err(CommitError::NothingToCommit)?;
+        }
+
+        let snapshot = snapshot_to_commit(only)?;
+        let parents = refs::resolve_head()?.into_iter().collect();
+
+        self.write_commit(message, identity("AUTHOR")?, parents, &snapshot, "commit")
+    }
+
+    /// Replaces the commit HEAD points at with a new one made from the index
+    /// and a message from `message`, returning its hash. The new commit has the same parents
+    /// and author as the old one, so it takes its place in the history as if
+    /// the old one had never been made. Just like with
+    /// [`commit`](Self::commit), the new commit has to change something,
+    /// unless `allow_empty` is set, and `only` limits it to some paths the
+    /// same way.
+    ///
+    /// The old commit isn't deleted, and can still be found in the reflog.
+    pub fn amend(
+        &self,
+        message: impl FnOnce() -> Result<String, CommitError>,
+        allow_empty: bool,
+        only: Option<&[String]>,
+    ) -> Result<String, CommitError> {
+        state::ensure_idle()?;
+
+        if !allow_empty && self.changes_to_commit(true, only)?.is_empty() {
+            Err(CommitError::NothingToCommit)?;
+        }
+
+        let head = refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+        let old = objects::read_commit(&head)?;
+        let snapshot = snapshot_to_commit(only)?;
+
+        self.write_commit(
+            message,
+            old.author,
+            old.parents,
+            &snapshot,
+            "commit (amend)",
+        )
+    }
+
+    /// Works out what committing the index would change compared to the new
+    /// commit's parent, which is HEAD, or HEAD's own parent if `amend` is
+    /// set. The very first commit has nothing before it, so everything that's
+    /// been staged counts as added. With `only`, just the changes to the files
+    /// at those paths count, like for [`commit`](Self::commit).
+    pub fn changes_to_commit(
+        &self,
+        amend: bool,
+        only: Option<&[String]>,
+    ) -> Result<BTreeMap<String, Change>, CommitError> {
+        let head = refs::resolve_head()?;
+
+        let parent = match head {
+            Some(head) if amend => objects::read_commit(&head)?.parents.into_iter().next(),
+            None if amend => Err(RefError::NoCommits)?,
+            head => head,
+        };
+
+        Ok(compare::compare(
+            &compare::read_commit(parent.as_deref())?,
+            &snapshot_to_commit(only)?,
+        ))
+    }
+
+    /// Commits the contents of the index like [`commit`](Self::commit), but
+    /// with the given `author`, which is useful when the changes were
+    /// originally written by somebody else. Any `other_parents` are added
+    /// after HEAD, which is how merge commits get made. `action` says what
+    /// made the commit in the reflog, like `cherry-pick`.
+    fn commit_as(
+        &self,
+        message: &str,
+        author: Signature,
+        other_parents: &[String],
+        action: &str,
+    ) -> Result<String, CommitError> {
+        // The commit we're building on top of becomes the parent of the new
+        // one, unless this is the very first commit, in which case there's
+        // nothing to build on.
+        let parents = refs::resolve_head()?
+            .into_iter()
+            .chain(other_parents.iter().cloned())
+            .collect();
+        let index = Index::read(nest_path("index"))?;
+
+        self.write_commit(
+            || Ok(message.to_string()),
+            author,
+            parents,
+            &index.entries,
+            action,
+        )
+    }
+
+    /// Commits `snapshot`, which is usually the contents of the index, with
+    /// exactly the given `parents`, and moves HEAD to the new commit. The
+    /// commit [`hooks`] run on the way, so every kind of commit gets them.
+    ///
+    /// The `pre-commit` hook runs before `message` is called to come up with
+    /// the message, so nobody writes one in their editor for a commit the hook
+    /// would refuse. Any error from `message` is passed on as it is.
+    fn write_commit<E: From<CommitError>>(
+        &self,
+        message: impl FnOnce() -> Result<String, E>,
+        author: Signature,
+        parents: Vec<String>,
+        snapshot: &Snapshot,
+        action: &str,
+    ) -> Result<String, E> {
+        hooks::pre_commit().map_err(CommitError::from)?;
+
+        // The commit-msg hook can even change the message.
+        let message = hooks::check_message(&message()?).map_err(CommitError::from)?;
+
+        Ok(self.store_commit(message, author, parents, snapshot, action)?)
+    }
+
+    /// Does the rest of [`write_commit`](Self::write_commit) once the hooks
+    /// have passed and the message is final.
+    fn store_commit(
+        &self,
+        message: String,
+        author: Signature,
+        parents: Vec<String>,
+        snapshot: &Snapshot,
+        action: &str,
+    ) -> Result<String, CommitError> {
+        // The blobs were already stored when they were staged, so all we need
+        // to do is build the trees that give them their names. The snapshot
+        // is complete, not just the changes since the last commit, so this is
+        // all we need.
+        let tree = objects::write_tree(snapshot)?;
+
+        let mut metadata = CommitMetadata {
+            tree,
+            parents,
+            author,
+            committer: identity("COMMITTER")?,
+            message: message.clone(),
+            signature: None,
+        };
+
+        if signing::should_sign() {
+            signing::sign(&mut metadata)?;
+        }
+
+        let hash = objects::write_object(ObjectKind::Commit, metadata.serialize().as_bytes())?;
+
+        // Move the current branch (or HEAD itself, if it's detached) to the new
+        // commit that we just created. We only do this at the very end, so that
+        // if anything goes wrong earlier, the branch still points at a complete
+        // commit.
+        let subject = message.lines().next().unwrap_or_default();
+        refs::advance_head(&hash, &format!("{action}: {subject}"))?;
+
+        // The renames are part of the commit now, so they're no longer staged.
+        index::write_renames(nest_path("RENAMES"), &BTreeMap::new())?;
+
+        // The commit has been made by now, so there's nothing for the
+        // post-commit hook to stop, and its failing shouldn't make it look
+        // like the commit did.
+        if let Err(e) = hooks::run("post-commit", &[], "") {
+            logging::warning(format_args!("{e}"));
+        }
+
+        Ok(hash)
+    }
+
+    /// Creates a new branch called `name` pointing at the commit
+    /// `commit_hash`.
+    pub fn branch(&self, name: &str, commit_hash: &str) -> Result<(), RefError> {
+        if !refs::is_valid_name(name) {
+            Err(RefError::InvalidName {
+                kind: "branch",
+                name: name.to_string(),
+            })?;
+        }
+
+        if refs::read_branch(name)?.is_some() {
+            Err(RefError::AlreadyExists {
+                kind: "branch",
+                name: name.to_string(),
+            })?;
+        }
+
+        // Reading the commit makes sure it actually exists before we point a
+        // branch at it.
+        objects::read_commit(commit_hash)?;
+
+        let ref_name = Ref::Branch(name.to_string());
+
+        refs::write_ref(&ref_name, commit_hash)?;
+        refs::append_reflog(
+            &ref_name,
+            None,
+            commit_hash,
+            &format!("branch: Created from {}", resolve::abbreviate(commit_hash)),
+        )?;
+
+        Ok(())
+    }
+
+    /// Lists every branch, sorted by name.
+    pub fn branches(&self) -> Result<Vec<Branch>, RefError> {
+        let head = refs::read_head()?;
+        let mut branches = Vec::new();
+
+        for name in refs::list_branches()? {
+            if let Some(commit) = refs::read_branch(&name)? {
+                branches.push(Branch {
+                    current: head == Head::Branch(name.clone()),
+                    name,
+                    commit,
+                });
+            }
+        }
+
+        Ok(branches)
+    }
+
+    /// Deletes the branch called `name`, returning the commit it pointed to.
+    ///
+    /// Deleting a branch doesn't delete any commits, but if a commit isn't
+    /// part of any other branch, there's no easy way to find it again
+    /// afterwards. To prevent that from happening by accident, we refuse to
+    /// delete a branch whose commits aren't part of the history of HEAD,
+    /// unless `force` is set.
+    pub fn delete_branch(&self, name: &str, force: bool) -> Result<String, RefError> {
+        let hash = refs::read_branch(name)?.ok_or_else(|| RefError::NotFound {
+            kind: "branch",
+            name: name.to_string(),
+        })?;
+
+        if refs::read_head()? == Head::Branch(name.to_string()) {
+            Err(RefError::CurrentBranch {
+                name: name.to_string(),
+            })?;
+        }
+
+        if let Some(worktree) = worktrees::find_checkout(name)? {
+            Err(RefError::CheckedOutElsewhere {
+                name: name.to_string(),
+                path: worktree.path,
+            })?;
+        }
+
+        if !force {
+            let merged = match refs::resolve_head()? {
+                Some(head) => graph::is_ancestor(&hash, &head)?,
+                None => false,
+            };
+
+            if !merged {
+                Err(RefError::NotMerged {
+                    name: name.to_string(),
+                })?;
+            }
+        }
+
+        refs::delete_ref(&Ref::Branch(name.to_string()))?;
+
+        Ok(hash)
+    }
+
+    /// Renames the branch `old_name` to `new_name`, keeping HEAD on it if it
+    /// was the current branch.
+    pub fn rename_branch(&self, old_name: &str, new_name: &str) -> Result<(), RefError> {
+        if !refs::is_valid_name(new_name) {
+            Err(RefError::InvalidName {
+                kind: "branch",
+                name: new_name.to_string(),
+            })?;
+        }
+
+        let hash = refs::read_branch(old_name)?.ok_or_else(|| RefError::NotFound {
+            kind: "branch",
+            name: old_name.to_string(),
+        })?;
+
+        if refs::read_branch(new_name)?.is_some() {
+            Err(RefError::AlreadyExists {
+                kind: "branch",
+                name: new_name.to_string(),
+            })?;
+        }
+
+        // We write the new ref before deleting the old one, so that the commits
+        // are never left without a branch pointing at them. The reflog goes
+        // along with the branch, since it's still the same branch.
+        let old_ref = Ref::Branch(old_name.to_string());
+        let new_ref = Ref::Branch(new_name.to_string());
+
+        refs::write_ref(&new_ref, &hash)?;
+        refs::rename_reflog(&old_ref, &new_ref)?;
+        refs::append_reflog(
+            &new_ref,
+            Some(&hash),
+            &hash,
+            &format!("Branch: renamed {old_ref} to {new_ref}"),
+        )?;
+        refs::delete_ref(&old_ref)?;
+
+        if refs::read_head()? == Head::Branch(old_name.to_string()) {
+            refs::set_head_branch(new_name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lists every tag, sorted by name, along with the commit it's for.
+    pub fn tags(&self) -> Result<Vec<Tag>, RefError> {
+        let mut tags = Vec::new();
+
+        for name in refs::list_tags()? {
+            let Some(hash) = refs::read_tag(&name)? else {
+                continue;
+            };
+
+            // An annotated tag points at a tag object rather than straight at
+            // the commit, so we need to look inside it to find the commit.
+            let annotation = match objects::read_object(&hash)?.0 {
+                ObjectKind::Tag => Some(objects::read_tag(&hash)?),
+                _ => None,
+            };
+
+            tags.push(Tag {
+                name,
+                commit: objects::peel_to_commit(&hash)?,
+                annotation,
+            });
+        }
+
+        Ok(tags)
+    }
+
+    /// Names the commit `commit_hash` after the nearest tag in its history,
+    /// for `rat describe`, or returns `None` if there isn't one. Only
+    /// annotated tags count unless `lightweight` is set, since those are the
+    /// ones usually made for releases.
+    ///
+    /// The nearest tag is the one with the fewest commits between it and
+    /// `commit_hash`, counting every commit in the history of `commit_hash`
+    /// that isn't in the tag's own history.
+    pub fn describe(
+        &self,
+        commit_hash: &str,
+        lightweight: bool,
+    ) -> Result<Option<Description>, RefError> {
+        let mut tagged: HashMap<String, Vec<Tag>> = HashMap::new();
+
+        for tag in self.tags()? {
+            if lightweight || tag.annotation.is_some() {
+                tagged.entry(tag.commit.clone()).or_default().push(tag);
+            }
+        }
+
+        // A tag further back than another one always has more commits since
+        // it, so we only need to look as far as the first tag down each path.
+        let mut seen = HashSet::new();
+        let mut to_visit = vec![commit_hash.to_string()];
+        let mut candidates = Vec::new();
+
+        while let Some(hash) = to_visit.pop() {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+
+            if tagged.contains_key(&hash) {
+                candidates.push(hash);
+            } else {
+                to_visit.extend(objects::read_commit(&hash)?.parents);
+            }
+        }
+
+        let history = graph::reachable(commit_hash)?.len();
+        let mut best = None;
+
+        for candidate in candidates {
+            // The tag's history is all part of ours, so whatever's left over
+            // came since.
+            let distance = history - graph::reachable(&candidate)?.len();
+            let committed = objects::read_commit(&candidate)?.committer.timestamp;
+
+            // When tags are just as near, annotated ones win over lightweight
+            // ones, and then newer ones over older ones.
+            for tag in tagged.remove(&candidate).unwrap_or_default() {
+                let timestamp = match &tag.annotation {
+                    Some(annotation) => annotation.tagger.timestamp,
+                    None => committed,
+                };
+                let rank = (
+                    distance,
+                    tag.annotation.is_none(),
+                    Reverse(timestamp),
+                    tag.name.clone(),
+                );
+
+                if best.as_ref().is_none_or(|(best_rank, _)| rank < *best_rank) {
+                    best = Some((
+                        rank,
+                        Description {
+                            tag: tag.name,
+                            distance,
+                            commit: commit_hash.to_string(),
+                        },
+                    ));
+                }
+            }
+        }
+
+        Ok(best.map(|(_, description)| description))
+    }
+
+    /// Creates a new tag called `name` for the commit `commit_hash`. If
+    /// there's a `message`, the tag is annotated, which means the ref points
+    /// at a tag object holding the message rather than straight at the commit.
+    /// Making one of those is a lot like making a commit, which is why it can
+    /// fail in the same ways.
+    ///
+    /// If `sign` is set, the tag object is [`signing`]ed too, which means there
+    /// has to be a message.
+    pub fn create_tag(
+        &self,
+        name: &str,
+        commit_hash: &str,
+        message: Option<&str>,
+        sign: bool,
+    ) -> Result<(), CommitError> {
+        if !refs::is_valid_name(name) {
+            Err(RefError::InvalidName {
+                kind: "tag",
+                name: name.to_string(),
+            })?;
+        }
+
+        // Unlike branches, tags are meant to stay put forever, so we never
+        // silently move one that already exists.
+        if refs::read_tag(name)?.is_some() {
+            Err(RefError::AlreadyExists {
+                kind: "tag",
+                name: name.to_string(),
+            })?;
+        }
+
+        objects::read_commit(commit_hash)?;
+
+        // A lightweight tag is only a ref, so there's nowhere to put a
+        // signature.
+        if sign && message.is_none() {
+            Err(CommitError::Other(
+                "Only annotated tags can be signed.".into(),
+            ))?;
+        }
+
+        let target = match message {
+            Some(message) => {
+                let mut metadata = TagMetadata {
+                    object: commit_hash.to_string(),
+                    kind: ObjectKind::Commit,
+                    name: name.to_string(),
+                    tagger: identity("COMMITTER")?,
+                    message: message.to_string(),
+                    signature: None,
+                };
+
+                if sign {
+                    signing::sign_tag(&mut metadata)?;
+                }
+
+                objects::write_object(ObjectKind::Tag, metadata.serialize().as_bytes())?
+            }
+            None => commit_hash.to_string(),
+        };
+
+        refs::write_ref(&Ref::Tag(name.to_string()), &target)?;
+
+        Ok(())
+    }
+
+    /// Deletes the tag called `name`, returning what it pointed to.
+    pub fn delete_tag(&self, name: &str) -> Result<String, RefError> {
+        let hash = refs::read_tag(name)?.ok_or_else(|| RefError::NotFound {
+            kind: "tag",
+            name: name.to_string(),
+        })?;
+        refs::delete_ref(&Ref::Tag(name.to_string()))?;
+
+        Ok(hash)
+    }
+
+    /// Creates a new commit that undoes the changes made by the commit
+    /// `commit_hash`, returning the hash of the new commit.
+    ///
+    /// Rather than just restoring the snapshot from before that commit, which
+    /// would also undo everything that came after it, we do a three-way merge
+    /// between HEAD and the commit's parent, using the commit itself as the
+    /// base. That way only the changes the commit made are reversed.
+    pub fn revert(&self, commit_hash: &str) -> Result<String, MergeError> {
+        state::ensure_idle()?;
+
+        let metadata = objects::read_commit(commit_hash)?;
+        let abbreviated_hash = resolve::abbreviate(commit_hash);
+
+        // A merge commit has more than one parent, so it's not clear which
+        // side's changes should be undone.
+        let parent = match &metadata.parents[..] {
+            [] => None,
+            [parent] => Some(parent.as_str()),
+            _ => Err(MergeError::IsMerge {
+                hash: commit_hash.to_string(),
+                action: "reverted",
+            })?,
+        };
+
+        let index = Index::read(nest_path("index"))?;
+        let head_snapshot = compare::read_commit(refs::resolve_head()?.as_deref())?;
+        let working_snapshot = compare::read_working_directory(&index.entries)?;
+
+        if has_uncommitted_changes(&head_snapshot, &index.entries, &working_snapshot) {
+            Err(CheckoutError::UncommittedChanges {
+                action: "reverting",
+            })?;
+        }
+
+        let merged = merge::merge_snapshots(
+            &compare::read_commit(Some(commit_hash))?,
+            &head_snapshot,
+            &compare::read_commit(parent)?,
+            "HEAD",
+            &format!("parent of {abbreviated_hash}"),
+        )?;
+
+        // If the changes since the commit clash with undoing it, we stop before
+        // touching anything, so the user can undo the changes by hand instead.
+        if !merged.conflicts.is_empty() {
+            Err(MergeError::Conflicts {
+                action: format!("Reverting {abbreviated_hash}"),
+                paths: merged.conflicts,
+            })?;
+        }
+
+        check_untracked_files(
+            &index.entries,
+            &working_snapshot,
+            &merged.snapshot,
+            "reverting",
+        )?;
+
+        restore_snapshot(&index.entries, &working_snapshot, merged.snapshot)?;
+
+        // Like git, we refer to the reverted commit by the first line of its
+        // message, and give the full hash so it can always be found again.
+        let subject = metadata.message.lines().next().unwrap_or_default();
+
+        Ok(self.commit(
+            || {
+                Ok(format!(
+                    "Revert \"{subject}\"\n\nThis reverts commit {commit_hash}.\n"
+                ))
+            },
+            false,
+            None,
+        )?)
+    }
+
+    /// Applies the changes made by the commit `commit_hash` on top of HEAD as a
+    /// new commit with the same message and author, returning the hash of the
+    /// new commit.
+    ///
+    /// This is the opposite of [`revert`](Self::revert): we do a three-way
+    /// merge between HEAD and the commit, using the commit's parent as the
+    /// base. If any of the changes conflict, the conflicted files are left in
+    /// the working directory with conflict markers, and the user can finish
+    /// the cherry-pick with [`cherry_pick_continue`](Self::cherry_pick_continue)
+    /// once they've fixed and staged them, or give up with
+    /// [`cherry_pick_abort`](Self::cherry_pick_abort).
+    pub fn cherry_pick(&self, commit_hash: &str) -> Result<String, MergeError> {
+        state::ensure_idle()?;
+
+        let metadata = objects::read_commit(commit_hash)?;
+
+        // A merge commit has more than one parent, so it's not clear which
+        // side's changes should be applied.
+        if metadata.parents.len() > 1 {
+            Err(MergeError::IsMerge {
+                hash: commit_hash.to_string(),
+                action: "cherry-picked",
+            })?;
+        }
+
+        let conflicts = self.apply_commit(commit_hash, &metadata, "cherry-picking")?;
+
+        if conflicts.is_empty() {
+            return Ok(self.commit_as(&metadata.message, metadata.author, &[], "cherry-pick")?);
+        }
+
+        state::start(Operation::CherryPick, commit_hash, &conflicts)?;
+
+        Err(MergeError::Stopped {
+            operation: Operation::CherryPick,
+            hash: commit_hash.to_string(),
+            paths: conflicts,
+        })
+    }
+
+    /// Makes the same changes as the commit `commit_hash`, which has
+    /// `metadata`, on top of HEAD in the index and the working directory,
+    /// without committing them. `action` says what we're doing, like
+    /// `cherry-picking`, in case there are uncommitted changes in the way.
+    ///
+    /// This is a three-way merge between HEAD and the commit, using the
+    /// commit's first parent as the base. The paths that conflict are
+    /// returned, and left in the working directory with conflict markers.
+    fn apply_commit(
+        &self,
+        commit_hash: &str,
+        metadata: &CommitMetadata,
+        action: &'static str,
+    ) -> Result<BTreeSet<String>, MergeError> {
+        let index = Index::read(nest_path("index"))?;
+        let head_snapshot = compare::read_commit(refs::resolve_head()?.as_deref())?;
+        let working_snapshot = compare::read_working_directory(&index.entries)?;
+
+        if has_uncommitted_changes(&head_snapshot, &index.entries, &working_snapshot) {
+            Err(CheckoutError::UncommittedChanges { action })?;
+        }
+
+        let subject = metadata.message.lines().next().unwrap_or_default();
+        let merged = merge::merge_snapshots(
+            &compare::read_commit(metadata.parents.first().map(String::as_str))?,
+            &head_snapshot,
+            &compare::read_commit(Some(commit_hash))?,
+            "HEAD",
+            &format!("{} ({subject})", resolve::abbreviate(commit_hash)),
+        )?;
+
+        check_untracked_files(&index.entries, &working_snapshot, &merged.snapshot, action)?;
+        restore_snapshot(&index.entries, &working_snapshot, merged.snapshot)?;
+
+        unstage_conflicts(&head_snapshot, &merged.conflicts)?;
+
+        Ok(merged.conflicts)
+    }
+
+    /// Finishes a cherry-pick that stopped because of conflicts, once they've
+    /// all been resolved, by committing the index with the original message
+    /// and author.
+    pub fn cherry_pick_continue(&self) -> Result<String, MergeError> {
+        let commit_hash = match state::current()? {
+            Some((Operation::CherryPick, hash)) => hash,
+            _ => Err(StateError::NotInProgress(Operation::CherryPick))?,
+        };
+
+        let conflicts = state::read_conflicts()?;
+
+        if !conflicts.is_empty() {
+            Err(StateError::Unresolved(conflicts))?;
+        }
+
+        let metadata = objects::read_commit(&commit_hash)?;
+        let hash = self.commit_as(&metadata.message, metadata.author, &[], "cherry-pick")?;
+
+        state::finish(Operation::CherryPick)?;
+
+        Ok(hash)
+    }
+
+    /// Gives up on a cherry-pick that stopped because of conflicts, putting the
+    /// index and working directory back the way they were before it started.
+    pub fn cherry_pick_abort(&self) -> Result<(), MergeError> {
+        if !matches!(state::current()?, Some((Operation::CherryPick, _))) {
+            Err(StateError::NotInProgress(Operation::CherryPick))?;
+        }
+
+        // A cherry-pick can only start with no uncommitted changes, and HEAD
+        // doesn't move until it finishes, so HEAD is exactly where we started.
+        let head = refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+
+        self.reset_stopped(&head)
+    }
+
+    /// Resets everything to the commit `hash` after an operation stopped
+    /// because of conflicts, including the conflicted files themselves.
+    fn reset_stopped(&self, hash: &str) -> Result<(), MergeError> {
+        let conflicts = state::read_conflicts()?;
+
+        self.reset(ResetMode::Hard, hash)?;
+
+        // Conflicted files that HEAD didn't have were never staged, so
+        // resetting doesn't know to clean them up.
+        let snapshot = compare::read_commit(Some(hash))?;
+
+        for path in conflicts {
+            if !snapshot.contains_key(&path) {
+                worktree::remove_working_file(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replays every commit on the current branch that isn't in the history
+    /// of `upstream` on top of it, oldest first, as new commits with the same
+    /// changes, messages and authors. Once they've all been replayed, the
+    /// branch is moved to the last of them, so it looks as if the work had
+    /// started from `upstream` all along.
+    ///
+    /// Merge commits are left out, since their changes come along with the
+    /// commits they merged. If replaying a commit causes conflicts, the
+    /// rebase stops with them left in the working directory, and the user can
+    /// carry on with [`rebase_continue`](Self::rebase_continue) once they've
+    /// fixed and staged them, or give up with
+    /// [`rebase_abort`](Self::rebase_abort).
+    ///
+    /// If `interactive` is set, the user gets to `edit` the list of commits
+    /// first, to reorder, reword, squash or drop them. That works even if
+    /// `upstream` is already in the branch's history, which is handy for
+    /// tidying up the last few commits.
+    pub fn rebase(
+        &self,
+        upstream: &str,
+        interactive: bool,
+        edit: &mut Editor,
+    ) -> Result<RebaseOutcome, MergeError> {
+        state::ensure_idle()?;
+
+        let head = refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+
+        let index = Index::read(nest_path("index"))?;
+        let head_snapshot = compare::read_commit(Some(&head))?;
+        let working_snapshot = compare::read_working_directory(&index.entries)?;
+
+        if has_uncommitted_changes(&head_snapshot, &index.entries, &working_snapshot) {
+            Err(CheckoutError::UncommittedChanges { action: "rebasing" })?;
+        }
+
+        if !interactive && graph::is_ancestor(upstream, &head)? {
+            return Ok(RebaseOutcome::UpToDate);
+        }
+
+        let upstream_history = graph::reachable(upstream)?;
+        let upstream_snapshot = compare::read_commit(Some(upstream))?;
+
+        check_untracked_files(
+            &index.entries,
+            &working_snapshot,
+            &upstream_snapshot,
+            "rebasing",
+        )?;
+
+        // If there's nothing of our own to replay, the branch can just move
+        // forward to upstream.
+        if upstream_history.contains(&head) {
+            restore_snapshot(&index.entries, &working_snapshot, upstream_snapshot)?;
+            refs::advance_head(
+                upstream,
+                &format!("rebase: fast-forward to {}", resolve::abbreviate(upstream)),
+            )?;
+
+            return Ok(RebaseOutcome::FastForward(upstream.to_string()));
+        }
+
+        // The log hands out children first, so the oldest commits come last.
+        let mut todo = Vec::new();
+
+        for commit in LogIter::new(vec![head.clone()], upstream_history)? {
+            let (hash, metadata) = commit?;
+
+            if metadata.parents.len() <= 1 {
+                todo.push(RebaseStep {
+                    action: RebaseAction::Pick,
+                    commit: hash,
+                });
+            }
+        }
+
+        todo.reverse();
+
+        if interactive {
+            let text = format_todo(&todo, &head, upstream)?;
+            todo = parse_todo(&edit("REBASE_TODO", &text)?)?;
+
+            if todo.iter().all(|step| step.action == RebaseAction::Drop) {
+                Err(MergeError::NothingToDo)?;
+            }
+        }
+
+        let head_name = match refs::read_head()? {
+            Head::Branch(branch) => Some(branch),
+            Head::Detached(_) => None,
+        };
+
+        let rebase = RebaseState {
+            head_name,
+            orig_head: head,
+            onto: upstream.to_string(),
+            todo,
+        };
+
+        rebase.write()?;
+
+        // Like git, we detach HEAD while replaying, so the branch only moves
+        // once every commit has made it across.
+        restore_snapshot(&index.entries, &working_snapshot, upstream_snapshot)?;
+        refs::set_head_detached(upstream)?;
+        refs::append_reflog(
+            &Ref::Head,
+            Some(&rebase.orig_head),
+            upstream,
+            &format!("rebase (start): checkout {}", resolve::abbreviate(upstream)),
+        )?;
+
+        self.replay(rebase, edit)
+    }
+
+    /// Carries on with a rebase that stopped, once any conflicts have been
+    /// resolved, by finishing the commit it stopped at and then replaying the
+    /// rest. `edit` is used for any messages that still need writing.
+    pub fn rebase_continue(&self, edit: &mut Editor) -> Result<RebaseOutcome, MergeError> {
+        if !matches!(state::current()?, Some((Operation::Rebase, _))) {
+            Err(StateError::NotInProgress(Operation::Rebase))?;
+        }
+
+        let conflicts = state::read_conflicts()?;
+
+        if !conflicts.is_empty() {
+            Err(StateError::Unresolved(conflicts))?;
+        }
+
+        let mut rebase = RebaseState::read()?;
+
+        // The rebase always stops partway through its first step.
+        if let Some(step) = rebase.todo.first().cloned() {
+            self.finish_step(&step, edit)?;
+            rebase.todo.remove(0);
+            rebase.write()?;
+        }
+
+        self.replay(rebase, edit)
+    }
+
+    /// Gives up on a rebase, putting the branch, the index and the working
+    /// directory back the way they were before it started.
+    pub fn rebase_abort(&self) -> Result<(), MergeError> {
+        let rebase = RebaseState::read()?;
+
+        // The branch never moved, so putting HEAD back on it is enough to get
+        // back to where we started.
+        match &rebase.head_name {
+            Some(branch) => refs::set_head_branch(branch)?,
+            None => refs::set_head_detached(&rebase.orig_head)?,
+        }
+
+        self.reset_stopped(&rebase.orig_head)?;
+
+        // Resetting forgets about the rebase too, unless it was interrupted
+        // before it got to its first commit.
+        state::finish(Operation::Rebase)?;
+
+        Ok(())
+    }
+
+    /// Carries out each step left in `rebase` on top of HEAD, and then moves
+    /// the branch being rebased to where HEAD ends up.
+    fn replay(
+        &self,
+        mut rebase: RebaseState,
+        edit: &mut Editor,
+    ) -> Result<RebaseOutcome, MergeError> {
+        while let Some(step) = rebase.todo.first().cloned() {
+            // We record the commit before touching anything, so that if
+            // something goes wrong halfway, the rebase can still be continued
+            // or aborted.
+            state::start(Operation::Rebase, &step.commit, &BTreeSet::new())?;
+
+            let metadata = objects::read_commit(&step.commit)?;
+            let head = refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+
+            match step.action {
+                RebaseAction::Drop => {}
+                // A commit that's already on top of HEAD doesn't need
+                // replaying at all, so we keep it as it is rather than making
+                // a copy. This way, the commits before the first one that
+                // changes keep their hashes.
+                RebaseAction::Pick if metadata.parents.first() == Some(&head) => {
+                    let index = Index::read(nest_path("index"))?;
+                    let working_snapshot = compare::read_working_directory(&index.entries)?;
+                    let commit_snapshot = compare::read_commit(Some(&step.commit))?;
+
+                    check_untracked_files(
+                        &index.entries,
+                        &working_snapshot,
+                        &commit_snapshot,
+                        "rebasing",
+                    )?;
+
+                    restore_snapshot(&index.entries, &working_snapshot, commit_snapshot)?;
+                    refs::advance_head(&step.commit, "rebase (pick): fast-forward")?;
+                }
+                _ => {
+                    let conflicts = self.apply_commit(&step.commit, &metadata, "rebasing")?;
+
+                    if !conflicts.is_empty() {
+                        state::write_conflicts(&conflicts)?;
+
+                        Err(MergeError::Stopped {
+                            operation: Operation::Rebase,
+                            hash: step.commit.clone(),
+                            paths: conflicts,
+                        })?;
+                    }
+
+                    self.finish_step(&step, edit)?;
+                }
+            }
+
+            rebase.todo.remove(0);
+            rebase.write()?;
+        }
+
+        let new_head = refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+
+        if let Some(branch) = &rebase.head_name {
+            let ref_name = Ref::Branch(branch.clone());
+            let message = format!(
+                "rebase (finish): {ref_name} onto {}",
+                resolve::abbreviate(&rebase.onto)
+            );
+
+            refs::write_ref(&ref_name, &new_head)?;
+            refs::append_reflog(&ref_name, Some(&rebase.orig_head), &new_head, &message)?;
+            refs::set_head_branch(branch)?;
+            refs::append_reflog(&Ref::Head, Some(&new_head), &new_head, &message)?;
+        }
+
+        state::finish(Operation::Rebase)?;
+
+        Ok(RebaseOutcome::Rebased(new_head))
+    }
+
+    /// Commits the changes from `step`, which are already in the index, in
+    /// whatever way its action asks for.
+    fn finish_step(&self, step: &RebaseStep, edit: &mut Editor) -> Result<(), MergeError> {
+        let metadata = objects::read_commit(&step.commit)?;
+
+        // An empty message would leave the step unfinished, so the user can
+        // have another go with --continue.
+        let mut edit_message = |message: &str| -> Result<String, MergeError> {
+            let message = edit("COMMIT_EDITMSG", message)?;
+
+            if message.trim().is_empty() {
+                Err(MergeError::EmptyMessage {
+                    operation: Operation::Rebase,
+                })?;
+            }
+
+            Ok(message)
+        };
+
+        match step.action {
+            RebaseAction::Pick => {
+                self.commit_as(&metadata.message, metadata.author, &[], "rebase (pick)")?;
+            }
+            RebaseAction::Reword => {
+                let parents = refs::resolve_head()?.into_iter().collect();
+
+                self.write_commit(
+                    || edit_message(&metadata.message),
+                    metadata.author,
+                    parents,
+                    &Index::read(nest_path("index"))?.entries,
+                    "rebase (reword)",
+                )?;
+            }
+            RebaseAction::Squash => {
+                // The commit before is already on top of HEAD, so we replace
+                // it with one that has both sets of changes, which the index
+                // already holds, and both of their messages.
+                let previous_hash = refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+                let previous = objects::read_commit(&previous_hash)?;
+
+                if previous.parents.is_empty() {
+                    Err(MergeError::InvalidTodo {
+                        line: step.to_string(),
+                        reason: "there's no commit before it to squash into".to_string(),
+                    })?;
+                }
+
+                let message = format!("{}\n\n{}", previous.message.trim_end(), metadata.message);
+
+                self.write_commit(
+                    || edit_message(&message),
+                    previous.author,
+                    previous.parents,
+                    &Index::read(nest_path("index"))?.entries,
+                    "rebase (squash)",
+                )?;
+            }
+            RebaseAction::Drop => {}
+        }
+
+        Ok(())
+    }
+
+    /// Brings the history of the commit `their_hash` into the current branch.
+    /// `label` describes where it came from, like the name of a branch, and is
+    /// used in the message of the merge commit.
+    ///
+    /// If HEAD is already part of their history, there's nothing to combine,
+    /// so by default we just move the current branch forward to their commit,
+    /// which is called a fast-forward. Otherwise, we do a three-way merge using
+    /// the best common ancestor of the two as the base, and create a merge
+    /// commit with both as parents. `fast_forward` can rule out either of
+    /// those.
+    ///
+    /// If any of the changes conflict, the conflicted files are left in the
+    /// working directory with conflict markers, and the user can finish the
+    /// merge with [`merge_continue`](Self::merge_continue) once they've fixed
+    /// and staged them, or give up with [`merge_abort`](Self::merge_abort).
+    pub fn merge(
+        &self,
+        their_hash: &str,
+        label: &str,
+        fast_forward: FastForwardMode,
+    ) -> Result<MergeOutcome, MergeError> {
+        state::ensure_idle()?;
+
+        let head = refs::resolve_head()?;
+
+        let index = Index::read(nest_path("index"))?;
+        let head_snapshot = compare::read_commit(head.as_deref())?;
+        let working_snapshot = compare::read_working_directory(&index.entries)?;
+
+        if has_uncommitted_changes(&head_snapshot, &index.entries, &working_snapshot) {
+            Err(CheckoutError::UncommittedChanges { action: "merging" })?;
+        }
+
+        // A branch with no commits yet has no history to combine with, so it
+        // can always be fast-forwarded.
+        let base = match &head {
+            Some(head) => graph::merge_base(head, their_hash)?,
+            None => None,
+        };
+
+        if head.is_some() && base.as_deref() == Some(their_hash) {
+            return Ok(MergeOutcome::UpToDate);
+        }
+
+        let their_snapshot = compare::read_commit(Some(their_hash))?;
+
+        // Without any history of our own, there's nothing a merge commit could
+        // have as its first parent, so we fast-forward even if we were asked
+        // not to.
+        let can_fast_forward = head.is_none() || base == head;
+
+        if can_fast_forward && (head.is_none() || fast_forward != FastForwardMode::Never) {
+            check_untracked_files(
+                &index.entries,
+                &working_snapshot,
+                &their_snapshot,
+                "merging",
+            )?;
+
+            restore_snapshot(&index.entries, &working_snapshot, their_snapshot)?;
+            refs::advance_head(their_hash, &format!("merge {label}: Fast-forward"))?;
+
+            return Ok(MergeOutcome::FastForward(their_hash.to_string()));
+        }
+
+        if fast_forward == FastForwardMode::Only {
+            Err(MergeError::CannotFastForward {
+                label: label.to_string(),
+            })?;
+        }
+
+        let merged = merge::merge_snapshots(
+            &compare::read_commit(base.as_deref())?,
+            &head_snapshot,
+            &their_snapshot,
+            "HEAD",
+            label,
+        )?;
+
+        check_untracked_files(
+            &index.entries,
+            &working_snapshot,
+            &merged.snapshot,
+            "merging",
+        )?;
+
+        restore_snapshot(&index.entries, &working_snapshot, merged.snapshot)?;
+
+        let message = format!("Merge {label}\n");
+
+        if !merged.conflicts.is_empty() {
+            unstage_conflicts(&head_snapshot, &merged.conflicts)?;
+            state::start(Operation::Merge, their_hash, &merged.conflicts)?;
+            state::write_merge_message(&message)?;
+
+            Err(MergeError::Stopped {
+                operation: Operation::Merge,
+                hash: their_hash.to_string(),
+                paths: merged.conflicts,
+            })?;
+        }
+
+        let hash = self.commit_as(
+            &message,
+            identity("AUTHOR")?,
+            &[their_hash.to_string()],
+            "commit (merge)",
+        )?;
+
+        Ok(MergeOutcome::Merged(hash))
+    }
+
+    /// Finishes a merge that stopped because of conflicts, once they've all
+    /// been resolved, by making the merge commit from the index.
+    pub fn merge_continue(&self) -> Result<String, MergeError> {
+        let their_hash = match state::current()? {
+            Some((Operation::Merge, hash)) => hash,
+            _ => Err(StateError::NotInProgress(Operation::Merge))?,
+        };
+
+        let conflicts = state::read_conflicts()?;
+
+        if !conflicts.is_empty() {
+            Err(StateError::Unresolved(conflicts))?;
+        }
+
+        let message = state::read_merge_message()?;
+        let hash = self.commit_as(
+            &message,
+            identity("AUTHOR")?,
+            &[their_hash],
+            "commit (merge)",
+        )?;
+
+        state::finish(Operation::Merge)?;
+
+        Ok(hash)
+    }
+
+    /// Gives up on a merge that stopped because of conflicts, putting the
+    /// index and working directory back the way they were before it started.
+    pub fn merge_abort(&self) -> Result<(), MergeError> {
+        if !matches!(state::current()?, Some((Operation::Merge, _))) {
+            Err(StateError::NotInProgress(Operation::Merge))?;
+        }
+
+        // A merge can only start with no uncommitted changes, and HEAD doesn't
+        // move until it finishes, so HEAD is exactly where we started.
+        let head = refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+
+        self.reset_stopped(&head)
+    }
+
+    /// Fetches from the remote called `remote` and merges its branch `branch`
+    /// into the current branch, just like [`merge`](Self::merge).
+    pub fn pull(
+        &self,
+        remote: &str,
+        branch: &str,
+        fast_forward: FastForwardMode,
+    ) -> Result<MergeOutcome, MergeError> {
+        remote::fetch(remote, remote::prunes_by_default(remote)?)?;
+
+        let their_hash = refs::read_ref(&Ref::Remote {
+            remote: remote.to_string(),
+            branch: branch.to_string(),
+        })?
+        .ok_or_else(|| RefError::NotFound {
+            kind: "remote branch",
+            name: format!("{remote}/{branch}"),
+        })?;
+
+        self.merge(
+            &their_hash,
+            &format!("branch {branch} of {}", remote::url(remote)?),
+            fast_forward,
+        )
+    }
+
+    /// Moves the current branch, or HEAD itself if it's detached, to the
+    /// commit `commit_hash`, and then resets as much of the nest as `mode`
+    /// asks for.
+    ///
+    /// Unlike [`checkout`](Self::checkout), this never switches branches. It's
+    /// mostly used to undo commits by moving the branch back to an earlier
+    /// one.
+    pub fn reset(&self, mode: ResetMode, commit_hash: &str) -> Result<(), CheckoutError> {
+        let target_snapshot = compare::read_commit(Some(commit_hash))?;
+
+        refs::advance_head(
+            commit_hash,
+            &format!("reset: moving to {}", resolve::abbreviate(commit_hash)),
+        )?;
+
+        // Like git, resetting is also how you give up on an operation that
+        // stopped halfway, so we forget about it.
+        if let Some((operation, _)) = state::current()? {
+            state::finish(operation)?;
+        }
+
+        match mode {
+            ResetMode::Soft => {}
+            ResetMode::Mixed => Index {
+                entries: target_snapshot,
+            }
+            .write(nest_path("index"))?,
+            ResetMode::Hard => {
+                let (tracked, working_snapshot) = self.prepare_hard_reset()?;
+
+                restore_snapshot(&tracked, &working_snapshot, target_snapshot)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Works out which files `rat reset --hard` to the commit `commit_hash`
+    /// would change, without changing anything.
+    pub fn preview_hard_reset(&self, commit_hash: &str) -> Result<RestorePlan, CheckoutError> {
+        let target_snapshot = compare::read_commit(Some(commit_hash))?;
+        let (tracked, working_snapshot) = self.prepare_hard_reset()?;
+
+        Ok(worktree::plan_restore(
+            &tracked,
+            &working_snapshot,
+            &target_snapshot,
+        ))
+    }
+
+    /// Reads what's tracked and what's in the working directory, ready for a
+    /// hard reset.
+    fn prepare_hard_reset(&self) -> Result<(Snapshot, Snapshot), CheckoutError> {
+        // This is the one command that's supposed to throw away work, so
+        // unlike checkout, we don't check for uncommitted changes first.
+        let index = Index::read(nest_path("index"))?;
+        let working_snapshot = compare::read_working_directory(&index.entries)?;
+
+        Ok((index.entries, working_snapshot))
+    }
+
+    /// Brings the files at `paths` back to how they are in the commit
+    /// `source`, in both the index and the working directory, or just the
+    /// index if `staged` is set. HEAD stays where it is. Returns every file
+    /// that was restored.
+    ///
+    /// Files that aren't in `source` at all are taken out of the index, but
+    /// they're left in the working directory, since there's nowhere else to
+    /// get them back from.
+    pub fn restore(
+        &self,
+        paths: &[impl AsRef<Path>],
+        source: &str,
+        staged: bool,
+    ) -> Result<Vec<String>, CheckoutError> {
+        let source_snapshot = compare::read_commit(Some(source))?;
+
+        let index_file = nest_path("index");
+        let mut index = Index::read(&index_file)?;
+
+        let mut restored = BTreeSet::new();
+
+        for path in paths {
+            let path = path.as_ref();
+            let entry_path = utils::normalize_path(path).ok_or_else(|| AddError::OutsideNest {
+                path: path.to_path_buf(),
+            })?;
+
+            let matching: Vec<&String> = source_snapshot
+                .keys()
+                .chain(index.entries.keys())
+                .filter(|tracked| {
+                    entry_path.is_empty()
+                        || **tracked == entry_path
+                        || tracked.starts_with(&format!("{entry_path}/"))
+                })
+                .collect();
+
+            if matching.is_empty() {
+                Err(AddError::NoMatch {
+                    path: path.to_path_buf(),
+                })?;
+            }
+
+            restored.extend(matching.into_iter().cloned());
+        }
+
+        for path in &restored {
+            match source_snapshot.get(path) {
+                Some(entry) => {
+                    if !staged {
+                        worktree::write_working_file(path, entry)?;
+                    }
+
+                    index.entries.insert(path.clone(), entry.clone());
+                }
+                None => {
+                    index.entries.remove(path);
+                }
+            }
+        }
+
+        index.write(&index_file)?;
+
+        Ok(restored.into_iter().collect())
+    }
+
+    /// Switches the working directory and the index to the snapshot in
+    /// `target`, which can either be the name of a branch or any other
+    /// revision, and returns what HEAD now points to.
+    ///
+    /// Checking out a branch makes it the current branch, so new commits get
+    /// added to it. Checking out a commit directly detaches HEAD instead.
+    pub fn checkout(&self, target: &str) -> Result<Head, CheckoutError> {
+        state::ensure_idle()?;
+
+        let (commit_hash, new_head) = checkout_target(target)?;
+        let old_commit = refs::resolve_head()?;
+
+        if let Head::Branch(branch) = &new_head {
+            worktrees::ensure_free(branch)?;
+        }
+
+        // Since we've already made sure everything is committed, anything this
+        // removes is still safe in the history.
+        if let Some((tracked, working_snapshot, target_snapshot)) =
+            self.prepare_checkout(old_commit.as_deref(), &commit_hash)?
+        {
+            restore_snapshot(&tracked, &working_snapshot, target_snapshot)?;
+        }
+
+        let old_head = refs::read_head()?;
+
+        match &new_head {
+            Head::Branch(branch) => refs::set_head_branch(branch)?,
+            Head::Detached(hash) => refs::set_head_detached(hash)?,
+        }
+
+        let describe = |head: &Head| match head {
+            Head::Branch(branch) => branch.clone(),
+            Head::Detached(hash) => resolve::abbreviate(hash),
+        };
+
+        refs::append_reflog(
+            &Ref::Head,
+            old_commit.as_deref(),
+            &commit_hash,
+            &format!(
+                "checkout: moving from {} to {}",
+                describe(&old_head),
+                describe(&new_head)
+            ),
+        )?;
+
+        Ok(new_head)
+    }
+
+    /// Works out which files checking out `target` would change, without
+    /// changing anything. The same checks are made as for
+    /// [`checkout`](Self::checkout), so this fails whenever that would.
+    pub fn preview_checkout(&self, target: &str) -> Result<RestorePlan, CheckoutError> {
+        state::ensure_idle()?;
+
+        let (commit_hash, _) = checkout_target(target)?;
+        let old_commit = refs::resolve_head()?;
+
+        Ok(
+            match self.prepare_checkout(old_commit.as_deref(), &commit_hash)? {
+                Some((tracked, working_snapshot, target_snapshot)) => {
+                    worktree::plan_restore(&tracked, &working_snapshot, &target_snapshot)
+                }
+                None => RestorePlan::default(),
+            },
+        )
+    }
+
+    /// Reads what's tracked, what's in the working directory and what's in
+    /// the commit `commit_hash`, ready to check it out, after making sure that
+    /// doing so won't lose any work. Returns `None` if there's nothing to
+    /// change, since `old_commit` is the same commit.
+    fn prepare_checkout(
+        &self,
+        old_commit: Option<&str>,
+        commit_hash: &str,
+    ) -> Result<Option<(Snapshot, Snapshot, Snapshot)>, CheckoutError> {
+        // Switching to another branch at the same commit, like one that was
+        // just made, doesn't change any files, so any work in progress can
+        // simply come along.
+        if old_commit == Some(commit_hash) {
+            return Ok(None);
+        }
+
+        let index = Index::read(nest_path("index"))?;
+
+        let head_snapshot = compare::read_commit(old_commit)?;
+        let working_snapshot = compare::read_working_directory(&index.entries)?;
+        let target_snapshot = compare::read_commit(Some(commit_hash))?;
+
+        // Checking out overwrites tracked files, so to avoid losing any work
+        // we refuse if there's anything that hasn't been committed yet.
+        if has_uncommitted_changes(&head_snapshot, &index.entries, &working_snapshot) {
+            Err(CheckoutError::UncommittedChanges {
+                action: "checking out",
+            })?;
+        }
+
+        check_untracked_files(
+            &index.entries,
+            &working_snapshot,
+            &target_snapshot,
+            "checking out",
+        )?;
+
+        Ok(Some((index.entries, working_snapshot, target_snapshot)))
+    }
+
+    /// Starts looking for the commit that broke something, given the
+    /// hashes of a `bad` commit and any number of `good` ones if they're
+    /// already known. See [`bisect`] for how it works.
+    ///
+    /// Once there's at least one of each, the first commit to test is checked
+    /// out, detaching HEAD.
+    pub fn bisect_start(
+        &self,
+        bad: Option<&str>,
+        good: &[String],
+    ) -> Result<BisectStep, BisectError> {
+        if BisectState::exists() {
+            Err(BisectError::AlreadyStarted)?;
+        }
+
+        state::ensure_idle()?;
+        refs::resolve_head()?.ok_or(RefError::NoCommits)?;
+
+        let start = match refs::read_head()? {
+            Head::Branch(branch) => branch,
+            Head::Detached(hash) => hash,
+        };
+
+        let state = BisectState {
+            start,
+            bad: bad.map(str::to_string),
+            good: good.to_vec(),
+            skipped: Vec::new(),
+        };
+
+        self.bisect_next(&state)
+    }
+
+    /// Marks the commits with the hashes `commits` as good, bad or skipped in
+    /// the bisect that's going on, and checks out the next one to test. Only
+    /// the newest bad commit matters, so when marking commits as bad, only
+    /// the last one counts.
+    pub fn bisect_mark(
+        &self,
+        mark: BisectMark,
+        commits: &[String],
+    ) -> Result<BisectStep, BisectError> {
+        let mut state = BisectState::read()?;
+
+        match mark {
+            BisectMark::Good => state.good.extend_from_slice(commits),
+            BisectMark::Bad => state.bad = commits.last().cloned().or(state.bad),
+            BisectMark::Skip => state.skipped.extend_from_slice(commits),
+        }
+
+        self.bisect_next(&state)
+    }
+
+    /// Works out where the bisect has got to and saves `state`, checking out
+    /// the next commit to test if there is one. Nothing is saved if `state`
+    /// doesn't make sense, so a mistake can simply be tried again.
+    fn bisect_next(&self, state: &BisectState) -> Result<BisectStep, BisectError> {
+        let step = bisect::next_step(state)?;
+        state.write()?;
+
+        if let BisectStep::Test { commit, .. } = &step {
+            self.checkout(commit)?;
+        }
+
+        Ok(step)
+    }
+
+    /// Finishes the bisect that's going on, going back to wherever HEAD was
+    /// when it started, and returns what HEAD now points to.
+    pub fn bisect_reset(&self) -> Result<Head, BisectError> {
+        let state = BisectState::read()?;
+        let head = self.checkout(&state.start)?;
+
+        BisectState::remove()?;
+
+        Ok(head)
+    }
+
+    /// Walks through every commit reachable from `revision`, or HEAD if it's
+    /// not given, newest first. If there are no commits yet, there's nothing
+    /// to walk through.
+    ///
+    /// `revision` can also be a range like `main..feature`, in which case only
+    /// the commits the range stands for are walked through.
+    pub fn log_iter(&self, revision: Option<&str>) -> Result<LogIter, RefError> {
+        let Some(revision) = revision else {
+            // With nothing else to go on, we start from HEAD.
+            let start = refs::resolve_head()?;
+
+            return Ok(LogIter::new(start.into_iter().collect(), HashSet::new())?);
+        };
+
+        let (starts, excluded) = match RevisionRange::parse(revision) {
+            RevisionRange::Single(revision) => {
+                (vec![resolve::resolve_revision(revision)?], HashSet::new())
+            }
+            RevisionRange::Difference(from, to) => {
+                let excluded = graph::reachable(&resolve::resolve_revision(from)?)?;
+
+                (vec![resolve::resolve_revision(to)?], excluded)
+            }
+            RevisionRange::Symmetric(a, b) => {
+                let (a, b) = (resolve::resolve_revision(a)?, resolve::resolve_revision(b)?);
+
+                // Whatever both sides can reach is exactly what they have in
+                // common, so that's what gets left out.
+                let from_a = graph::reachable(&a)?;
+                let excluded = graph::reachable(&b)?
+                    .into_iter()
+                    .filter(|hash| from_a.contains(hash))
+                    .collect();
+
+                (vec![a, b], excluded)
+            }
+        };
+
+        Ok(LogIter::new(starts, excluded)?)
+    }
+
+    /// Walks through every commit reachable from HEAD or any branch or tag,
+    /// including the branches of remotes, newest first.
+    pub fn log_all(&self) -> Result<LogIter, Box<dyn Error>> {
+        // Everything we'd label in the log is exactly where we need to start.
+        let starts = self.decorations()?.into_keys().collect();
+
+        Ok(LogIter::new(starts, HashSet::new())?)
+    }
+
+    /// Finds the labels to show next to each commit that HEAD, a tag or a
+    /// branch points at, like `HEAD -> main` or `tag: v1.0`, keyed by the hash
+    /// of the commit.
+    pub fn decorations(&self) -> Result<HashMap<String, Vec<Decoration>>, Box<dyn Error>> {
+        let mut decorations: HashMap<String, Vec<Decoration>> = HashMap::new();
+        let head = refs::read_head()?;
+
+        // When we're on a branch, HEAD is shown together with it instead of
+        // separately, so the branch is skipped in the loop further down.
+        match &head {
+            Head::Branch(branch) => {
+                if let Some(hash) = refs::read_branch(branch)? {
+                    decorations
+                        .entry(hash)
+                        .or_default()
+                        .push(Decoration::Head(Some(branch.clone())));
+                }
+            }
+            Head::Detached(hash) => decorations
+                .entry(hash.clone())
+                .or_default()
+                .push(Decoration::Head(None)),
+        }
+
+        for tag in refs::list_tags()? {
+            if let Some(hash) = refs::read_tag(&tag)? {
+                decorations
+                    .entry(objects::peel_to_commit(&hash)?)
+                    .or_default()
+                    .push(Decoration::Tag(tag));
+            }
+        }
+
+        for branch in refs::list_branches()? {
+            if head == Head::Branch(branch.clone()) {
+                continue;
+            }
+
+            if let Some(hash) = refs::read_branch(&branch)? {
+                decorations
+                    .entry(hash)
+                    .or_default()
+                    .push(Decoration::Branch(branch));
+            }
+        }
+
+        for (remote, _) in remote::list()? {
+            for branch in refs::list_remote_branches(&remote)? {
+                let name = Ref::Remote {
+                    remote: remote.clone(),
+                    branch,
+                };
+
+                if let Some(hash) = refs::read_ref(&name)? {
+                    decorations
+                        .entry(hash)
+                        .or_default()
+                        .push(Decoration::RemoteBranch(name.short_name()));
+                }
+            }
+        }
+
+        Ok(decorations)
+    }
+
+    /// Works out how every file changed between `old` and `new`, line by line.
+    /// Untracked files are left out, just like in git. The working directory
+    /// can only be the new side. `detection` says whether to look for files
+    /// that were renamed or copied.
+    pub fn diff(
+        &self,
+        old: DiffSide,
+        new: DiffSide,
+        detection: Detection,
+    ) -> Result<Vec<FilePatch>, Box<dyn Error>> {
+        let index_snapshot = Index::read(nest_path("index"))?.entries;
+
+        let read_side = |side: DiffSide| -> Result<Snapshot, Box<dyn Error>> {
+            match side {
+                // Before the first commit, HEAD doesn't have any files yet.
+                DiffSide::Revision("HEAD") => {
+                    compare::read_commit(refs::resolve_head()?.as_deref())
+                }
+                DiffSide::Revision(revision) => {
+                    compare::read_commit(Some(&resolve::resolve_revision(revision)?))
+                }
+                DiffSide::Index => Ok(index_snapshot.clone()),
+                DiffSide::WorkingDirectory => {
+                    let mut snapshot = compare::read_working_directory(&index_snapshot)?;
+                    snapshot.retain(|path, _| index_snapshot.contains_key(path));
+
+                    Ok(snapshot)
+                }
+            }
+        };
+
+        // The working directory's files are only hashed, not stored, so we
+        // only know how to read them back from the files themselves.
+        if old == DiffSide::WorkingDirectory {
+            Err(
+                "Can't compare the working directory to anything else, only the other way around.",
+            )?;
+        }
+
+        // The renames `rat mv` told us about are only between the last commit
+        // and the index, since that's the only place they're still pending.
+        let hints = match new {
+            DiffSide::Index => index::read_renames(nest_path("RENAMES"))?,
+            _ => BTreeMap::new(),
+        };
+
+        patch::diff_snapshots(
+            &read_side(old)?,
+            &read_side(new)?,
+            new == DiffSide::WorkingDirectory,
+            detection,
+            &hints,
+        )
+    }
+
+    /// Works out how the index differs from the last commit, how the working
+    /// directory differs from the index, and which files aren't tracked at
+    /// all.
+    pub fn status(&self) -> Result<Status, Box<dyn Error>> {
+        let head_snapshot = compare::read_commit(refs::resolve_head()?.as_deref())?;
+        let index_snapshot = Index::read(nest_path("index"))?.entries;
+        let working_snapshot = compare::read_working_directory(&index_snapshot)?;
+
+        // Comparing the index to the working directory tells us what we've
+        // changed but not yet staged. Files that only exist in the working
+        // directory show up as "added" here, but that really means they aren't
+        // being tracked, so we pull those out into their own list.
+        let (untracked, mut unstaged): (BTreeMap<_, _>, BTreeMap<_, _>) =
+            compare::compare(&index_snapshot, &working_snapshot)
+                .into_iter()
+                .partition(|(_, change)| *change == Change::Added);
+
+        // Comparing the last commit to the index tells us what the next
+        // commit would change if we made it right now.
+        let mut staged = renames::detect(
+            &head_snapshot,
+            &index_snapshot,
+            compare::compare(&head_snapshot, &index_snapshot),
+            Detection::Renames,
+            &index::read_renames(nest_path("RENAMES"))?,
+            false,
+        )?;
+
+        // Conflicted files are listed on their own, since they can't be
+        // committed either way until they're resolved.
+        let conflicts = state::read_conflicts()?;
+        staged.retain(|path, _| !conflicts.contains(path));
+        unstaged.retain(|path, _| !conflicts.contains(path));
+
+        Ok(Status {
+            operation: state::current()?,
+            conflicts,
+            staged,
+            unstaged,
+            untracked: untracked.into_keys().collect(),
+        })
+    }
+}
+
+/// The commits in a history, newest first, as walked through by
+/// [`Repository::log_iter`]. Each one comes with its hash.
+///
+/// The shape of the history is worked out up front from the
+/// [`commit_graph`], but each commit is only read in full once it's handed
+/// out, so stopping early means the rest are never read at all.
+#[derive(Debug)]
+pub struct LogIter {
+    /// The parents of every commit we haven't handed out yet.
+    commits: HashMap<String, Vec<String>>,
+    /// How many children of each commit we haven't handed out yet.
+    child_counts: HashMap<String, usize>,
+    /// The commits whose children have all been handed out already.
+    queue: VecDeque<String>,
+}
+
+impl LogIter {
+    /// Prepares to walk through every commit reachable from any of `starts`,
+    /// leaving out the ones in `excluded`.
+    fn new(starts: Vec<String>, excluded: HashSet<String>) -> Result<Self, ObjectError> {
+        let mut log = LogIter {
+            commits: HashMap::new(),
+            child_counts: HashMap::new(),
+            queue: VecDeque::new(),
+        };
+
+        // Since a commit can have several parents, history isn't a simple
+        // chain. To make sure we never show a commit before one of its
+        // children, we first walk the whole history once, counting how many
+        // children each commit has.
+        // Excluded commits are never visited, so their own history is left
+        // out too, and they don't count as anyone's children.
+        let mut to_visit = starts.clone();
+        let mut timestamps = HashMap::new();
+
+        while let Some(hash) = to_visit.pop() {
+            if log.commits.contains_key(&hash) || excluded.contains(&hash) {
+                continue;
+            }
+
+            let commit = commit_graph::read(&hash)?;
+
+            for parent in commit.parents.iter().filter(|p| !excluded.contains(*p)) {
+                *log.child_counts.entry(parent.clone()).or_default() += 1;
+                to_visit.push(parent.clone());
+            }
+
+            timestamps.insert(hash.clone(), commit.timestamp);
+            log.commits.insert(hash, commit.parents);
+        }
+
+        // One starting point can be in the history of another, in which case
+        // it has to wait for its children like any other commit. The rest go
+        // newest first, so that the most recent work is at the top.
+        let mut starts: Vec<_> = starts
+            .into_iter()
+            .filter(|hash| log.commits.contains_key(hash) && !log.child_counts.contains_key(hash))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        starts.sort_by_key(|hash| std::cmp::Reverse(timestamps.get(hash).copied()));
+
+        log.queue = starts.into();
+
+        Ok(log)
+    }
+}
+
+impl Iterator for LogIter {
+    type Item = Result<(String, CommitMetadata), ObjectError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let hash = self.queue.pop_front()?;
+        let parents = self.commits.remove(&hash)?;
+
+        // We only move on to a commit once all of its children have been
+        // handed out. Parents that were excluded are never handed out at all.
+        for parent in parents.iter().filter(|p| self.commits.contains_key(*p)) {
+            let remaining_children = self.child_counts.entry(parent.clone()).or_default();
+            *remaining_children -= 1;
+
+            if *remaining_children == 0 {
+                self.queue.push_back(parent.clone());
+            }
+        }
+
+        Some(objects::read_commit(&hash).map(|metadata| (hash, metadata)))
+    }
+}
+
+/// A commit picked by [`LogFilter::simplify`], along with the parents it
+/// should be drawn with.
+pub type SimplifiedCommit = (String, CommitMetadata, Vec<String>);
+
+/// Which commits to show from a history, as picked by the options to
+/// `rat log`. Every condition that's set has to hold for a commit to be shown.
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    /// The most commits to show.
+    pub max_count: Option<usize>,
+    /// Text that has to appear in the author's name or email.
+    pub author: Option<String>,
+    /// Text that has to appear in the commit message.
+    pub grep: Option<String>,
+    /// The earliest and latest times a commit can have been made, as Unix
+    /// timestamps.
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    /// A file that has to have been changed by the commit, as a path from
+    /// the root of the nest. Like `git log --follow`, once we reach the
+    /// commit that renamed it, older commits are checked for the name it had
+    /// before.
+    pub path: Option<String>,
+}
+
+impl LogFilter {
+    /// Checks whether the commit with `metadata` should be shown. Text is
+    /// matched without caring about case, since that's almost always what
+    /// people want when searching. Commits have to be checked newest first,
+    /// so that the file is followed back through its renames.
+    pub fn matches(&mut self, metadata: &CommitMetadata) -> Result<bool, Box<dyn Error>> {
+        let contains =
+            |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+
+        // Like git, we go by when the commit was made rather than when the
+        // changes were first written, since that's what decides where it
+        // ends up in the history.
+        let time = metadata.committer.timestamp;
+
+        let matches = self.author.as_deref().is_none_or(|author| {
+            contains(&metadata.author.name, author) || contains(&metadata.author.email, author)
+        }) && self
+            .grep
+            .as_deref()
+            .is_none_or(|grep| contains(&metadata.message, grep))
+            && self.since.is_none_or(|since| time >= since)
+            && self.until.is_none_or(|until| time <= until);
+
+        let Some(path) = &self.path else {
+            return Ok(matches);
+        };
+
+        // The file has to be followed through every commit, even ones that
+        // are ruled out, since any of them could have renamed it.
+        let (changed, renamed_from) = follow_file(metadata, path)?;
+
+        if let Some(from) = renamed_from {
+            self.path = Some(from);
+        }
+
+        Ok(matches && changed)
+    }
+
+    /// Narrows `log` down to the commits this filter picks. The commits are
+    /// checked one at a time as the history is walked, so nothing past the
+    /// last one we need is ever looked at.
+    pub fn apply(
+        mut self,
+        log: LogIter,
+    ) -> impl Iterator<Item = Result<(String, CommitMetadata), Box<dyn Error>>> {
+        let max_count = self.max_count.unwrap_or(usize::MAX);
+
+        log.filter_map(move |commit| {
+            let (hash, metadata) = match commit {
+                Ok(commit) => commit,
+                Err(e) => return Some(Err(e.into())),
+            };
+
+            match self.matches(&metadata) {
+                Ok(true) => Some(Ok((hash, metadata))),
+                Ok(false) => None,
+                Err(e) => Some(Err(e)),
+            }
+        })
+        .take(max_count)
+    }
+
+    /// Narrows `log` down to the commits this filter picks, like
+    /// [`LogFilter::apply`], but also works out which of them each one should
+    /// be connected to when drawing the history as a graph. A commit's real
+    /// parents might not be shown, so it's connected to the nearest commits
+    /// in its history that are instead, just like git does.
+    pub fn simplify(mut self, log: LogIter) -> Result<Vec<SimplifiedCommit>, Box<dyn Error>> {
+        let mut commits = Vec::new();
+
+        for commit in log {
+            let (hash, metadata) = commit?;
+            let shown = self.matches(&metadata)?;
+            commits.push((hash, metadata, shown));
+        }
+
+        // The log always has children before their parents, so going through
+        // it backwards means we've always dealt with a commit's parents before
+        // the commit itself. For each commit, we work out the nearest shown
+        // commits, which is either itself or the nearest ones of its parents.
+        let mut nearest: HashMap<String, Vec<String>> = HashMap::new();
+        let mut graph_parents = HashMap::new();
+
+        for (hash, metadata, shown) in commits.iter().rev() {
+            let mut parents = Vec::new();
+
+            // The order matters, since the first parent is the one that
+            // carries on the same line in the graph.
+            for parent in &metadata.parents {
+                for ancestor in nearest.get(parent).into_iter().flatten() {
+                    if !parents.contains(ancestor) {
+                        parents.push(ancestor.clone());
+                    }
+                }
+            }
+
+            if *shown {
+                nearest.insert(hash.clone(), vec![hash.clone()]);
+                graph_parents.insert(hash.clone(), parents);
+            } else {
+                nearest.insert(hash.clone(), parents);
+            }
+        }
+
+        Ok(commits
+            .into_iter()
+            .filter(|(_, _, shown)| *shown)
+            .take(self.max_count.unwrap_or(usize::MAX))
+            .map(|(hash, metadata, _)| {
+                let parents = graph_parents.remove(&hash).unwrap_or_default();
+                (hash, metadata, parents)
+            })
+            .collect())
+    }
+}
+
+/// Makes the index keep what HEAD had, in `head_snapshot`, for each of the
+/// `conflicts`. The working directory has the conflict markers in it, but
+/// they shouldn't end up committed by accident, so they stay out of the index
+/// until the user stages their fixed version.
+fn unstage_conflicts(
+    head_snapshot: &Snapshot,
+    conflicts: &BTreeSet<String>,
+) -> Result<(), Box<dyn Error>> {
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    let mut index = Index::read(nest_path("index"))?;
+
+    for path in conflicts {
+        match head_snapshot.get(path) {
+            Some(hash) => index.stage(path.clone(), hash.clone()),
+            None => {
+                index.unstage(path);
+            }
+        }
+    }
+
+    index.write(nest_path("index"))?;
+
+    Ok(())
+}
+
+/// Describes the steps of a rebase of `head` onto `upstream` for the user to
+/// edit, one per line with the abbreviated hash and the subject of each
+/// commit, followed by instructions.
+fn format_todo(todo: &[RebaseStep], head: &str, upstream: &str) -> Result<String, ObjectError> {
+    let mut text = String::new();
+
+    for step in todo {
+        let metadata = objects::read_commit(&step.commit)?;
+        let subject = metadata.message.lines().next().unwrap_or_default();
+
+        text.push_str(&format!(
+            "{} {} {subject}\n",
+            step.action,
+            resolve::abbreviate(&step.commit)
+        ));
+    }
+
+    text.push_str(&format!(
+        "\n# Rebasing {} onto {}.\n\
+         #\n\
+         # Each line says what to do with a commit:\n\
+         #   pick <commit>   keep it as it is\n\
+         #   reword <commit> keep it, but write a new message for it\n\
+         #   squash <commit> combine it with the commit before, along with both messages\n\
+         #   drop <commit>   leave it out\n\
+         #\n\
+         # The commits are replayed from top to bottom, so they can be reordered\n\
+         # by moving lines around. Removing a line drops that commit, and removing\n\
+         # every line cancels the rebase.\n",
+        resolve::abbreviate(head),
+        resolve::abbreviate(upstream)
+    ));
+
+    Ok(text)
+}
+
+/// Reads back the steps of a rebase after the user has edited them. Each
+/// commit can be given by any unambiguous prefix of its hash, and anything
+/// after the hash, like the subject, is ignored.
+fn parse_todo(text: &str) -> Result<Vec<RebaseStep>, MergeError> {
+    let mut todo = Vec::new();
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let invalid = |reason: &str| MergeError::InvalidTodo {
+            line: line.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let (Some(action), Some(commit)) = (words.next(), words.next()) else {
+            Err(invalid("it needs an action and a commit"))?
+        };
+
+        let action: RebaseAction = action.parse().map_err(|_| invalid("unknown action"))?;
+
+        // Squashing needs a commit before it to squash into.
+        if action == RebaseAction::Squash
+            && todo
+                .iter()
+                .all(|step: &RebaseStep| step.action == RebaseAction::Drop)
+        {
+            Err(invalid("there's no commit before it to squash into"))?;
+        }
+
+        todo.push(RebaseStep {
+            action,
+            commit: resolve::resolve_commit(commit)?,
+        });
+    }
+
+    Ok(todo)
+}
+
+/// Checks whether the commit with `metadata` changed the file at `path`,
+/// meaning it's different from how it was in every one of the commit's
+/// parents. A merge that took the file as it was from one side didn't change
+/// it, since whichever commit changed it on that side did.
+///
+/// If the commit renamed the file to `path`, where it came from is returned
+/// as well, since that's the name to look for in older commits.
+fn follow_file(
+    metadata: &CommitMetadata,
+    path: &str,
+) -> Result<(bool, Option<String>), Box<dyn Error>> {
+    let entry = objects::read_tree_entry(&metadata.tree, path)?;
+
+    // The very first commit changes every file it has.
+    let Some(first_parent) = metadata.parents.first() else {
+        return Ok((entry.is_some(), None));
+    };
+
+    for parent in &metadata.parents {
+        let parent_tree = objects::read_commit(parent)?.tree;
+
+        if objects::read_tree_entry(&parent_tree, path)? == entry {
+            return Ok((false, None));
+        }
+    }
+
+    // Only a file that's new since the first parent can have been renamed,
+    // and working out renames means comparing whole snapshots, so we only
+    // do it then.
+    let first_tree = objects::read_commit(first_parent)?.tree;
+
+    if entry.is_none() || objects::read_tree_entry(&first_tree, path)?.is_some() {
+        return Ok((true, None));
+    }
+
+    let old = objects::read_tree(&first_tree)?;
+    let new = objects::read_tree(&metadata.tree)?;
+    let changes = renames::detect(
+        &old,
+        &new,
+        compare::compare(&old, &new),
+        Detection::Renames,
+        &BTreeMap::new(),
+        false,
+    )?;
+
+    match changes.get(path) {
+        Some(Change::Renamed { from, .. }) => Ok((true, Some(from.clone()))),
+        _ => Ok((true, None)),
+    }
+}
+
+/// Works out who is acting in the given `role`, which is either `AUTHOR` or
+/// `COMMITTER`, and creates a signature for them at the current time.
+///
+/// The name and email come from the `RAT_AUTHOR_NAME` and `RAT_AUTHOR_EMAIL`
+/// environment variables (or their `COMMITTER` equivalents) if they're set,
+/// which is handy for one-off overrides. Otherwise they come from the
+/// `user.name` and `user.email` settings. If those aren't set either, we fall
+/// back to the name of the user that's logged in.
+fn identity(role: &str) -> Result<Signature, CommitError> {
+    let config = Config::load()?;
+
+    // Unix systems store the current username in $USER, while Windows uses
+    // $USERNAME instead.
+    let user = env::var("USER").or_else(|_| env::var("USERNAME")).ok();
+
+    let name = env::var(format!("RAT_{role}_NAME"))
+        .ok()
+        .or_else(|| config.get("user.name").map(str::to_string))
+        .or_else(|| user.clone())
+        .ok_or_else(|| CommitError::NoIdentity {
+            role: role.to_string(),
+            field: "name",
+        })?;
+
+    let email = env::var(format!("RAT_{role}_EMAIL"))
+        .ok()
+        .or_else(|| config.get("user.email").map(str::to_string))
+        .or_else(|| user.map(|user| format!("{user}@localhost")))
+        .ok_or_else(|| CommitError::NoIdentity {
+            role: role.to_string(),
+            field: "email",
+        })?;
+
+    Ok(Signature::now(name, email))
+}
+
+/// Stores every file in `dir` as a blob, returning them as a snapshot with
+/// `prefix` in front of each path.
+fn store_directory(dir: &Path, prefix: &str) -> Result<Snapshot, Box<dyn Error>> {
+    let mut snapshot = Snapshot::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        let metadata = fs::symlink_metadata(&path)?;
+
+        if metadata.is_dir() {
+            snapshot.extend(store_directory(&path, &format!("{name}/"))?);
+        } else if metadata.is_symlink() {
+            let target = fs::read_link(&path)?;
+
+            snapshot.insert(
+                name,
+                Entry {
+                    mode: FileMode::Symlink,
+                    hash: objects::write_object(
+                        ObjectKind::Blob,
+                        target.to_string_lossy().as_bytes(),
+                    )?,
+                },
+            );
+        } else {
+            snapshot.insert(
+                name,
+                Entry {
+                    mode: worktree::mode_of(&metadata),
+                    hash: objects::write_file(&path)?,
+                },
+            );
+        }
+    }
+
+    Ok(snapshot)
+}
+
+/// Works out the snapshot a commit should record, which is everything in the
+/// index. If `only` is given, it's HEAD's snapshot with just the files at
+/// those paths taken from the index instead, whether they're there or not.
+fn snapshot_to_commit(only: Option<&[String]>) -> Result<Snapshot, CommitError> {
+    let index = Index::read(nest_path("index"))?.entries;
+
+    let Some(paths) = only else {
+        return Ok(index);
+    };
+
+    let chosen = |entry_path: &str| {
+        paths.iter().any(|path| {
+            path.is_empty() || entry_path == path || entry_path.starts_with(&format!("{path}/"))
+        })
+    };
+
+    let mut snapshot = compare::read_commit(refs::resolve_head()?.as_deref())?;
+    snapshot.retain(|entry_path, _| !chosen(entry_path));
+    snapshot.extend(
+        index
+            .into_iter()
+            .filter(|(entry_path, _)| chosen(entry_path)),
+    );
+
+    Ok(snapshot)
+}
+
+/// Works out the commit `target` names for a checkout, and what HEAD should
+/// point to afterwards.
+fn checkout_target(target: &str) -> Result<(String, Head), RefError> {
+    // Branch names take priority, since checking out a branch by name is the
+    // only way to end up on it rather than detached at the commit it points
+    // to.
+    match refs::read_branch(target)? {
+        Some(hash) => Ok((hash, Head::Branch(target.to_string()))),
+        None => {
+            let hash = resolve::resolve_revision(target)?;
+
+            Ok((hash.clone(), Head::Detached(hash)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::process;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// A repository always works on the nest in the current directory, which
+    /// the whole process shares, so only one test can be using one at a time.
+    static CURRENT_DIR: Mutex<()> = Mutex::new(());
+
+    /// Runs `test` in a new directory in the temporary directory that no other
+    /// test uses, which is deleted afterwards. The directory doubles as the
+    /// home directory, so nobody's own config gets in the way.
+    fn in_temp_dir(name: &str, test: impl FnOnce(&Path)) {
+        let _guard = CURRENT_DIR.lock().unwrap_or_else(|e| e.into_inner());
+
+        let dir = env::temp_dir().join(format!("rat-test-{}-repository-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let original = env::current_dir().unwrap();
+        env::set_current_dir(&dir).unwrap();
+        env::set_var("HOME", &dir);
+
+        for role in ["AUTHOR", "COMMITTER"] {
+            env::set_var(format!("RAT_{role}_NAME"), "Ada Lovelace");
+            env::set_var(format!("RAT_{role}_EMAIL"), "ada@example.com");
+        }
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| test(&dir)));
+
+        env::set_current_dir(original).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        if let Err(e) = result {
+            panic::resume_unwind(e);
+        }
+    }
+
+    /// Creates a new nest in the directory `name`, and moves into it.
+    fn init_in(name: &str) -> Repository {
+        fs::create_dir_all(name).unwrap();
+        env::set_current_dir(name).unwrap();
+
+        Repository::init(false, DEFAULT_BRANCH, None).unwrap().0
+    }
+
+    /// Makes a commit on the current branch whose tree is exactly `tree`,
+    /// which doesn't have to be anything rat would write itself.
+    fn commit_raw_tree(tree: &str) -> String {
+        let tree = objects::write_object(ObjectKind::Tree, tree.as_bytes()).unwrap();
+        let metadata = CommitMetadata {
+            tree,
+            parents: refs::resolve_head().unwrap().into_iter().collect(),
+            author: identity("AUTHOR").unwrap(),
+            committer: identity("COMMITTER").unwrap(),
+            message: "Crafted by hand\n".to_string(),
+            signature: None,
+        };
+
+        let hash =
+            objects::write_object(ObjectKind::Commit, metadata.serialize().as_bytes()).unwrap();
+        refs::advance_head(&hash, "commit: Crafted by hand").unwrap();
+
+        hash
+    }
+
+    /// Writes the hook `name` as a shell script running `script`.
+    #[cfg(unix)]
+    fn write_hook(name: &str, script: &str) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = nest_path(&format!("hooks/{name}"));
+        fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    /// Writes `contents` to `path`, and commits just that with `path` as the
+    /// message.
+    fn commit_file(repository: &Repository, path: &str, contents: &str) -> String {
+        fs::write(path, contents).unwrap();
+        repository.add(&[path]).unwrap();
+
+        repository
+            .commit(|| Ok(format!("{path}\n")), false, None)
+            .unwrap()
+    }
+
+    /// An editor for rebases that were never meant to ask for one.
+    fn no_editor(_: &str, _: &str) -> Result<String, Box<dyn Error>> {
+        panic!("The editor was opened");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pre_commit_runs_before_the_message_is_written() {
+        in_temp_dir("pre-commit", |_| {
+            let repository = init_in("work");
+            fs::write("a", "a\n").unwrap();
+            repository.add(&["a"]).unwrap();
+
+            write_hook("pre-commit", "exit 1");
+            let mut asked = false;
+            let result = repository.commit(
+                || {
+                    asked = true;
+                    Ok("Add a\n".to_string())
+                },
+                false,
+                None,
+            );
+
+            assert!(matches!(result, Err(CommitError::Hook(_))));
+            assert!(!asked);
+            assert_eq!(refs::resolve_head().unwrap(), None);
+
+            write_hook("pre-commit", "exit 0");
+            repository
+                .commit(|| Ok("Add a\n".to_string()), false, None)
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn clone_refuses_trees_that_would_write_outside_the_clone() {
+        in_temp_dir("clone-escape", |dir| {
+            for (name, tree) in [
+                ("parent", "blob {blob} ../pwned\n"),
+                ("nest", "tree {nest} .rat\n"),
+                ("nested", "tree {inner} a\n"),
+            ] {
+                init_in(&format!("evil-{name}"));
+
+                let blob = objects::write_object(ObjectKind::Blob, b"pwned\n").unwrap();
+                let hooks = objects::write_object(
+                    ObjectKind::Tree,
+                    format!("executable {blob} post-commit\n").as_bytes(),
+                )
+                .unwrap();
+                let nest = objects::write_object(
+                    ObjectKind::Tree,
+                    format!("tree {hooks} hooks\n").as_bytes(),
+                )
+                .unwrap();
+                let inner = objects::write_object(
+                    ObjectKind::Tree,
+                    format!("blob {blob} ../../pwned\n").as_bytes(),
+                )
+                .unwrap();
+
+                commit_raw_tree(
+                    &tree
+                        .replace("{blob}", &blob)
+                        .replace("{nest}", &nest)
+                        .replace("{inner}", &inner),
+                );
+
+                env::set_current_dir(dir).unwrap();
+                let clone = format!("clone-{name}");
+
+                assert!(
+                    Repository::clone(&format!("evil-{name}"), Some(&clone), false).is_err(),
+                    "{name}"
+                );
+
+                env::set_current_dir(dir).unwrap();
+                assert!(!dir.join("pwned").exists(), "{name}");
+                assert!(
+                    !dir.join(&clone).join(".rat/hooks/post-commit").exists(),
+                    "{name}"
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn merge_fast_forwards_and_combines_histories() {
+        in_temp_dir("merge", |_| {
+            let repository = init_in("work");
+            let base = commit_file(&repository, "a", "a\n");
+            repository.branch("topic", &base).unwrap();
+
+            repository.checkout("topic").unwrap();
+            let topic = commit_file(&repository, "t", "t\n");
+            repository.checkout(DEFAULT_BRANCH).unwrap();
+
+            assert!(matches!(
+                repository.merge(&topic, "branch topic", FastForwardMode::Allowed),
+                Ok(MergeOutcome::FastForward(hash)) if hash == topic
+            ));
+            assert_eq!(refs::resolve_head().unwrap().as_ref(), Some(&topic));
+            assert_eq!(fs::read_to_string("t").unwrap(), "t\n");
+
+            repository.checkout("topic").unwrap();
+            let theirs = commit_file(&repository, "u", "u\n");
+            repository.checkout(DEFAULT_BRANCH).unwrap();
+            let ours = commit_file(&repository, "m", "m\n");
+
+            let merge = match repository.merge(&theirs, "branch topic", FastForwardMode::Allowed) {
+                Ok(MergeOutcome::Merged(hash)) => hash,
+                other => panic!("{other:?}"),
+            };
+
+            let commit = objects::read_commit(&merge).unwrap();
+            assert_eq!(commit.parents, [ours, theirs.clone()]);
+            assert_eq!(commit.message, "Merge branch topic\n");
+            assert_eq!(fs::read_to_string("m").unwrap(), "m\n");
+            assert_eq!(fs::read_to_string("u").unwrap(), "u\n");
+
+            assert!(matches!(
+                repository.merge(&theirs, "branch topic", FastForwardMode::Allowed),
+                Ok(MergeOutcome::UpToDate)
+            ));
+        });
+    }
+
+    #[test]
+    fn merge_stops_at_conflicts_until_aborted() {
+        in_temp_dir("merge-conflict", |_| {
+            let repository = init_in("work");
+            let base = commit_file(&repository, "a", "a\n");
+            repository.branch("topic", &base).unwrap();
+
+            repository.checkout("topic").unwrap();
+            let theirs = commit_file(&repository, "a", "theirs\n");
+            repository.checkout(DEFAULT_BRANCH).unwrap();
+            let ours = commit_file(&repository, "a", "ours\n");
+
+            assert!(matches!(
+                repository.merge(&theirs, "branch topic", FastForwardMode::Only),
+                Err(MergeError::CannotFastForward { .. })
+            ));
+
+            match repository.merge(&theirs, "branch topic", FastForwardMode::Allowed) {
+                Err(MergeError::Stopped { paths, .. }) => {
+                    assert_eq!(paths.into_iter().collect::<Vec<_>>(), ["a"]);
+                }
+                other => panic!("{other:?}"),
+            }
+
+            // Another merge can't start until this one is finished with.
+            assert!(repository
+                .merge(&theirs, "branch topic", FastForwardMode::Allowed)
+                .is_err());
+
+            repository.merge_abort().unwrap();
+            assert_eq!(fs::read_to_string("a").unwrap(), "ours\n");
+            assert_eq!(refs::resolve_head().unwrap(), Some(ours));
+            assert!(state::ensure_idle().is_ok());
+        });
+    }
+
+    #[test]
+    fn rebase_replays_commits_onto_upstream() {
+        in_temp_dir("rebase", |_| {
+            let repository = init_in("work");
+            let base = commit_file(&repository, "a", "a\n");
+            repository.branch("topic", &base).unwrap();
+            repository.branch("behind", &base).unwrap();
+            let upstream = commit_file(&repository, "m", "m\n");
+
+            repository.checkout("topic").unwrap();
+            commit_file(&repository, "t", "t\n");
+
+            let rebased = match repository.rebase(&upstream, false, &mut no_editor) {
+                Ok(RebaseOutcome::Rebased(hash)) => hash,
+                other => panic!("{other:?}"),
+            };
+
+            let commit = objects::read_commit(&rebased).unwrap();
+            assert_eq!(commit.parents, [upstream.as_str()]);
+            assert_eq!(commit.message, "t\n");
+            assert_eq!(refs::read_branch("topic").unwrap(), Some(rebased));
+            assert_eq!(fs::read_to_string("m").unwrap(), "m\n");
+            assert_eq!(fs::read_to_string("t").unwrap(), "t\n");
+
+            assert!(matches!(
+                repository.rebase(&upstream, false, &mut no_editor),
+                Ok(RebaseOutcome::UpToDate)
+            ));
+
+            // A branch with nothing of its own just catches up.
+            repository.checkout("behind").unwrap();
+            assert!(matches!(
+                repository.rebase(&upstream, false, &mut no_editor),
+                Ok(RebaseOutcome::FastForward(hash)) if hash == upstream
+            ));
+            assert_eq!(refs::read_branch("behind").unwrap(), Some(upstream));
+        });
+    }
+
+    #[test]
+    fn clone_fetch_and_push_between_nests() {
+        in_temp_dir("remotes", |dir| {
+            let origin = init_in("origin");
+            let first = commit_file(&origin, "a", "a\n");
+            let origin_main = Ref::Remote {
+                remote: "origin".to_string(),
+                branch: DEFAULT_BRANCH.to_string(),
+            };
+
+            env::set_current_dir(dir).unwrap();
+            let (copy, _) = Repository::clone("origin", Some("copy"), false).unwrap();
+            assert_eq!(
+                refs::read_branch(DEFAULT_BRANCH).unwrap(),
+                Some(first.clone())
+            );
+            assert_eq!(refs::read_ref(&origin_main).unwrap(), Some(first.clone()));
+            assert_eq!(fs::read_to_string("a").unwrap(), "a\n");
+
+            env::set_current_dir(dir.join("origin")).unwrap();
+            let second = commit_file(&origin, "b", "b\n");
+
+            // Fetching only moves the remote branch, and leaves ours alone.
+            env::set_current_dir(dir.join("copy")).unwrap();
+            remote::fetch("origin", false).unwrap();
+            assert_eq!(refs::read_ref(&origin_main).unwrap(), Some(second.clone()));
+            assert_eq!(refs::read_branch(DEFAULT_BRANCH).unwrap(), Some(first));
+
+            copy.merge(&second, "origin/main", FastForwardMode::Only)
+                .unwrap();
+            assert_eq!(fs::read_to_string("b").unwrap(), "b\n");
+
+            copy.branch("feature", &second).unwrap();
+            copy.checkout("feature").unwrap();
+            let feature = commit_file(&copy, "f", "f\n");
+
+            let outcomes = remote::push("origin", &["feature".to_string()], true).unwrap();
+            assert!(outcomes.iter().all(|outcome| outcome.result.is_ok()));
+            assert_eq!(
+                refs::read_ref_in(
+                    &dir.join("origin/.rat"),
+                    &Ref::Branch("feature".to_string())
+                )
+                .unwrap(),
+                Some(feature)
+            );
+
+            // The branch that's checked out over there is off limits.
+            copy.checkout(DEFAULT_BRANCH).unwrap();
+            commit_file(&copy, "c", "c\n");
+
+            let outcomes = remote::push("origin", &[DEFAULT_BRANCH.to_string()], true).unwrap();
+            assert!(outcomes.iter().all(|outcome| outcome.result.is_err()));
+            assert_eq!(
+                refs::read_ref_in(
+                    &dir.join("origin/.rat"),
+                    &Ref::Branch(DEFAULT_BRANCH.into())
+                )
+                .unwrap(),
+                Some(second)
+            );
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commit_msg_can_rewrite_or_refuse_the_message() {
+        in_temp_dir("commit-msg", |_| {
+            let repository = init_in("work");
+            write_hook("commit-msg", "echo Rewritten > \"$1\"");
+
+            let hash = commit_file(&repository, "a", "a\n");
+            assert_eq!(objects::read_commit(&hash).unwrap().message, "Rewritten\n");
+
+            write_hook("commit-msg", "exit 1");
+            fs::write("b", "b\n").unwrap();
+            repository.add(&["b"]).unwrap();
+
+            assert!(matches!(
+                repository.commit(|| Ok("Add b\n".to_string()), false, None),
+                Err(CommitError::Hook(_))
+            ));
+            assert_eq!(refs::resolve_head().unwrap(), Some(hash));
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pre_push_can_stop_a_push() {
+        in_temp_dir("pre-push", |dir| {
+            let origin = init_in("origin");
+            commit_file(&origin, "a", "a\n");
+
+            env::set_current_dir(dir).unwrap();
+            let (copy, _) = Repository::clone("origin", Some("copy"), false).unwrap();
+            let head = refs::resolve_head().unwrap().unwrap();
+            copy.branch("feature", &head).unwrap();
+
+            let feature = Ref::Branch("feature".to_string());
+            let origin_nest = dir.join("origin/.rat");
+
+            write_hook("pre-push", "exit 1");
+            let outcomes = remote::push("origin", &["feature".to_string()], true).unwrap();
+            assert!(outcomes.iter().all(|outcome| outcome.result.is_err()));
+            assert_eq!(refs::read_ref_in(&origin_nest, &feature).unwrap(), None);
+
+            // Pushing without verifying skips the hook.
+            let outcomes = remote::push("origin", &["feature".to_string()], false).unwrap();
+            assert!(outcomes.iter().all(|outcome| outcome.result.is_ok()));
+            assert_eq!(
+                refs::read_ref_in(&origin_nest, &feature).unwrap(),
+                Some(head)
+            );
+        });
+    }
+
+    #[test]
+    fn add_refuses_paths_with_line_breaks() {
+        in_temp_dir("add-line-break", |_| {
+            let repository = init_in("work");
+            fs::write("a\nb", "").unwrap();
+            fs::write("c", "").unwrap();
+
+            assert!(matches!(
+                repository.add(&["a\nb"]),
+                Err(AddError::LineBreak { .. })
+            ));
+
+            // Adding everything skips it, and leaves the index readable.
+            assert_eq!(repository.add(&["."]).unwrap(), 1);
+            assert_eq!(
+                Index::read(nest_path("index"))
+                    .unwrap()
+                    .entries
+                    .keys()
+                    .collect::<Vec<_>>(),
+                ["c"]
+            );
+            assert!(repository.status().is_ok());
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn checkout_replaces_symlinks_instead_of_writing_through_them() {
+        in_temp_dir("checkout-symlink", |dir| {
+            init_in("work");
+            fs::create_dir(dir.join("outside")).unwrap();
+
+            let target = objects::write_object(ObjectKind::Blob, b"../outside").unwrap();
+            let blob = objects::write_object(ObjectKind::Blob, b"pwned\n").unwrap();
+            let link = Snapshot::from([(
+                "link".to_string(),
+                Entry {
+                    mode: FileMode::Symlink,
+                    hash: target,
+                },
+            )]);
+            let file = Snapshot::from([(
+                "link/pwned".to_string(),
+                Entry {
+                    mode: FileMode::Regular,
+                    hash: blob,
+                },
+            )]);
+
+            restore_snapshot(&Snapshot::new(), &Snapshot::new(), link.clone()).unwrap();
+            assert!(fs::symlink_metadata("link").unwrap().is_symlink());
+
+            restore_snapshot(&link, &link, file.clone()).unwrap();
+            assert!(!dir.join("outside/pwned").exists());
+            assert_eq!(fs::read("link/pwned").unwrap(), b"pwned\n");
+
+            // An untracked symlink in the way isn't removed as part of the
+            // checkout, but still mustn't be written through.
+            fs::remove_dir_all("link").unwrap();
+            std::os::unix::fs::symlink("../outside", "link").unwrap();
+
+            restore_snapshot(&Snapshot::new(), &Snapshot::new(), file).unwrap();
+            assert!(!dir.join("outside/pwned").exists());
+            assert_eq!(fs::read("link/pwned").unwrap(), b"pwned\n");
+        });
+    }
+}
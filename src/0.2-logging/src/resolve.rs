@@ -0,0 +1,333 @@
+//! Turning what the user typed into the hash of a commit.
+//!
+//! Full SHA-256 hashes are 64 characters long, which is far too much to type.
+//! Luckily, even in a big nest, the first few characters of a hash are almost
+//! always enough to tell it apart from every other object, so just like git,
+//! rat accepts any unambiguous prefix of a hash wherever it expects one.
+//!
+//! On top of that, anywhere rat expects a commit it accepts a *revision*,
+//! which can be any of:
+//!
+//! - `HEAD`, meaning the current commit.
+//! - The name of a tag or a branch, like `v1.0` or `main`, or of a branch in
+//!   a remote, like `origin/main`.
+//! - A full ref name, like `refs/heads/main`.
+//! - A hash, or an unambiguous prefix of one.
+//! - `name@{n}`, meaning where the branch `name` was `n` moves ago, according
+//!   to its reflog. Leaving out the name, as in `@{1}`, means HEAD.
+//!
+//! Any of these can be followed by any number of suffixes that move backwards
+//! through history:
+//!
+//! - `~n` goes back `n` generations, always following the first parent, so
+//!   `HEAD~3` is the great-grandparent of HEAD. Just `~` means `~1`.
+//! - `^n` picks the `n`th parent, which is only interesting for commits with
+//!   more than one. Just `^` means `^1`, and `^0` means the commit itself.
+//!
+//! So `main~2^2` means "the second parent of the grandparent of main".
+//!
+//! Commands that look at a whole history, like `rat log`, also accept a
+//! *range* of revisions:
+//!
+//! - `a..b` means every commit in the history of `b` that isn't in the history
+//!   of `a`, which is what `b` has that `a` doesn't.
+//! - `a...b` means every commit in the history of either one but not both,
+//!   which is what each side has done since they split apart.
+//!
+//! Either side can be left out, in which case it means HEAD, so `..feature`
+//! is the same as `HEAD..feature`.
+//!
+//! The commands that work with objects directly, like `rat cat-file`, can
+//! name any kind of object rather than just commits. On top of revisions,
+//! they accept `revision:path`, meaning the file or directory at `path` in
+//! that commit, and `revision:` on its own means its root directory.
+
+use crate::error::RefError;
+use crate::logging;
+use crate::objects::{self, ObjectKind};
+use crate::refs::{self, Ref};
+
+/// The shortest prefix we accept. Anything shorter is far too likely to be
+/// ambiguous, or to be a typo rather than a hash at all.
+const MIN_PREFIX_LENGTH: usize = 4;
+
+/// The length we abbreviate hashes to when displaying them, unless that would
+/// be ambiguous.
+const DEFAULT_ABBREVIATION_LENGTH: usize = 7;
+
+/// A revision on its own, or a range of them, as described in the module
+/// documentation. Each side is still a revision that needs resolving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevisionRange<'a> {
+    /// A single revision, standing for everything in its history.
+    Single(&'a str),
+    /// `a..b`, everything in the history of `b` but not `a`.
+    Difference(&'a str, &'a str),
+    /// `a...b`, everything in the history of `a` or `b` but not both.
+    Symmetric(&'a str, &'a str),
+}
+
+impl<'a> RevisionRange<'a> {
+    /// Works out which kind of range `revision` is. Anything without `..` in
+    /// it is a single revision.
+    pub fn parse(revision: &'a str) -> Self {
+        let or_head = |side: &'a str| if side.is_empty() { "HEAD" } else { side };
+
+        // Three dots contain two, so they have to be checked for first.
+        if let Some((a, b)) = revision.split_once("...") {
+            Self::Symmetric(or_head(a), or_head(b))
+        } else if let Some((a, b)) = revision.split_once("..") {
+            Self::Difference(or_head(a), or_head(b))
+        } else {
+            Self::Single(revision)
+        }
+    }
+}
+
+/// Finds the commit whose hash starts with `prefix`.
+pub fn resolve_commit(prefix: &str) -> Result<String, RefError> {
+    let unknown = |reason: &str| RefError::UnknownRevision {
+        revision: prefix.to_string(),
+        reason: reason.to_string(),
+    };
+
+    if prefix.len() < MIN_PREFIX_LENGTH || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Err(unknown("it isn't a valid commit hash"))?;
+    }
+
+    // Hashes are always stored in lowercase, but there's no reason to be picky
+    // about how they're typed.
+    let prefix = prefix.to_lowercase();
+
+    let candidates = objects::find_objects_with_prefix(&prefix)?;
+
+    // We're only interested in commits, so other kinds of objects that happen to
+    // share the prefix don't make it ambiguous.
+    let mut commits = Vec::new();
+
+    for candidate in candidates {
+        if objects::read_object(&candidate)?.0 == ObjectKind::Commit {
+            commits.push(candidate);
+        }
+    }
+
+    match &commits[..] {
+        [] => Err(unknown("no commit matches it")),
+        [commit] => Ok(commit.clone()),
+        _ => Err(RefError::AmbiguousRevision {
+            prefix,
+            candidates: commits,
+        }),
+    }
+}
+
+/// Resolves `name` to the hash of any kind of object, as described in the
+/// module documentation. Unlike [`resolve_revision`], the name of an annotated
+/// tag gives the tag object itself, rather than the commit it's for.
+pub fn resolve_object(name: &str) -> Result<String, RefError> {
+    if let Some((revision, path)) = name.split_once(':') {
+        let mut hash = objects::read_commit(&resolve_revision(revision)?)?.tree;
+
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            // Only directories have anything inside them, so anything else
+            // simply won't have an entry with the name we're after.
+            let entries = match objects::read_object(&hash)?.0 {
+                ObjectKind::Tree => objects::read_tree_entries(&hash)?,
+                _ => Vec::new(),
+            };
+
+            hash = entries
+                .into_iter()
+                .find(|entry| entry.name == component)
+                .ok_or_else(|| RefError::UnknownRevision {
+                    revision: name.to_string(),
+                    reason: format!("there's nothing at {path} in {revision}"),
+                })?
+                .hash;
+        }
+
+        return Ok(hash);
+    }
+
+    if name.len() >= MIN_PREFIX_LENGTH && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+        let prefix = name.to_lowercase();
+
+        match &objects::find_objects_with_prefix(&prefix)?[..] {
+            [] => {}
+            [hash] => return Ok(hash.clone()),
+            candidates => Err(RefError::AmbiguousRevision {
+                prefix,
+                candidates: candidates.to_vec(),
+            })?,
+        }
+    }
+
+    if refs::is_valid_name(name) {
+        if let Some(hash) = refs::read_tag(name)? {
+            return Ok(hash);
+        }
+    }
+
+    resolve_revision(name)
+}
+
+/// Shortens `hash` for display, keeping just enough characters that it isn't
+/// ambiguous with any other object. If we can't check, we fall back to the
+/// default length, since a display problem shouldn't stop anything working.
+pub fn abbreviate(hash: &str) -> String {
+    let mut length = DEFAULT_ABBREVIATION_LENGTH.min(hash.len());
+
+    let Ok(others) = objects::find_objects_with_prefix(&hash[..length]) else {
+        return hash[..length].to_string();
+    };
+
+    // Keep adding characters until this is the only object that matches.
+    while length < hash.len()
+        && others
+            .iter()
+            .any(|other| other != hash && other.starts_with(&hash[..length]))
+    {
+        length += 1;
+    }
+
+    hash[..length].to_string()
+}
+
+/// Resolves a revision, in any of the forms described in the module
+/// documentation, to the hash of a commit.
+pub fn resolve_revision(revision: &str) -> Result<String, RefError> {
+    // Everything before the first suffix is the name we start from.
+    let base_end = revision.find(['~', '^']).unwrap_or(revision.len());
+    let (base, mut suffixes) = revision.split_at(base_end);
+
+    let mut hash = resolve_base(base)?;
+
+    while let Some(operator) = suffixes.chars().next() {
+        suffixes = &suffixes[1..];
+
+        // The number after the operator is optional, and defaults to 1.
+        let digits_end = suffixes
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(suffixes.len());
+        let (digits, rest) = suffixes.split_at(digits_end);
+        suffixes = rest;
+
+        let count: usize = if digits.is_empty() {
+            1
+        } else {
+            digits.parse().map_err(|_| RefError::InvalidRevision {
+                revision: revision.to_string(),
+                reason: format!("{digits} is too big"),
+            })?
+        };
+
+        match operator {
+            '~' => {
+                for _ in 0..count {
+                    hash = nth_parent(&hash, 1, revision)?;
+                }
+            }
+            '^' if count == 0 => {}
+            '^' => hash = nth_parent(&hash, count, revision)?,
+            _ => Err(RefError::InvalidRevision {
+                revision: revision.to_string(),
+                reason: format!("{operator} isn't a valid suffix"),
+            })?,
+        }
+    }
+
+    logging::trace(format_args!("Resolved {revision} to {hash}"));
+
+    Ok(hash)
+}
+
+/// Resolves the part of a revision before any suffixes.
+fn resolve_base(base: &str) -> Result<String, RefError> {
+    if base == "HEAD" || base == "@" {
+        return refs::resolve_head()?.ok_or(RefError::NoCommits);
+    }
+
+    if let Some((name, position)) = base.split_once("@{") {
+        return resolve_reflog(base, name, position);
+    }
+
+    // We try the different kinds of refs in the same order as git does, so a
+    // tag takes priority over a branch with the same name. Since ref names
+    // end up as file paths, we make sure they're valid first so that nothing
+    // can sneak out of the refs directory.
+    if refs::is_valid_name(base) {
+        let mut candidates = Vec::new();
+
+        if base.starts_with("refs/") {
+            candidates.push(Ref::parse(base));
+        }
+
+        candidates.push(Ref::Tag(base.to_string()));
+        candidates.push(Ref::Branch(base.to_string()));
+
+        if let Some((remote, branch)) = base.split_once('/') {
+            candidates.push(Ref::Remote {
+                remote: remote.to_string(),
+                branch: branch.to_string(),
+            });
+        }
+
+        for candidate in candidates {
+            // Annotated tags point at a tag object rather than a commit, so we
+            // have to follow it to the commit it's tagging.
+            if let Some(hash) = refs::read_ref(&candidate)? {
+                return Ok(objects::peel_to_commit(&hash)?);
+            }
+        }
+    }
+
+    resolve_commit(base)
+}
+
+/// Resolves `name@{n}` to where the ref `name` was `n` moves ago, where
+/// `position` is the `n}` part.
+fn resolve_reflog(revision: &str, name: &str, position: &str) -> Result<String, RefError> {
+    let invalid = |reason: String| RefError::InvalidRevision {
+        revision: revision.to_string(),
+        reason,
+    };
+
+    let n: usize = position
+        .strip_suffix('}')
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| invalid(format!("{{{position} isn't a position in the reflog")))?;
+
+    let ref_name = refs::reflog_ref_name(name)
+        .ok_or_else(|| invalid(format!("{name} isn't a valid branch name")))?;
+
+    let reflog = refs::read_reflog(&ref_name)?;
+
+    match reflog.get(n) {
+        Some(entry) => Ok(entry.new.clone()),
+        None => Err(invalid(format!(
+            "the reflog of {ref_name} only has {} entries",
+            reflog.len()
+        ))),
+    }
+}
+
+/// Finds the `n`th parent of the commit `hash`, counting from 1.
+fn nth_parent(hash: &str, n: usize, revision: &str) -> Result<String, RefError> {
+    let parents = objects::read_commit(hash)?.parents;
+
+    parents
+        .get(n - 1)
+        .cloned()
+        .ok_or_else(|| RefError::InvalidRevision {
+            revision: revision.to_string(),
+            reason: format!(
+                "commit {} doesn't have {}",
+                abbreviate(hash),
+                if n == 1 {
+                    "a parent".to_string()
+                } else {
+                    format!("{n} parents")
+                }
+            ),
+        })
+}
@@ -0,0 +1,234 @@
+//! Signing commits and tags, so that anyone can check who really made them.
+//!
+//! Anybody can put any name they like in a commit's author and committer, so
+//! on their own they don't prove anything. A signed commit carries a
+//! signature over the rest of its contents, made with its committer's SSH
+//! key, which nobody else can make without that key. Since the signature is
+//! part of the commit, it's covered by the commit's hash too. Annotated tags
+//! can be signed the same way by their tagger, which is how a release can
+//! be shown to really come from whoever made it.
+//!
+//! Like git, we leave the cryptography to `ssh-keygen`, which everybody with
+//! an SSH key already has. These settings control it:
+//!
+//! - `user.signingKey`, the SSH key to sign with. This can be the public key
+//!   if the private key is kept in `ssh-agent`.
+//! - `commit.sign`, which signs every commit when it's `true`. Otherwise,
+//!   only commits made with `rat commit -S` are signed. Tags are only signed
+//!   when made with `rat tag -s`.
+//! - `signing.allowedSigners`, a file listing whose keys we trust, one per
+//!   line as an email followed by the key, like `ada@example.com ssh-ed25519
+//!   AAAA...`. A signature only counts as good if it was made by the key in
+//!   this file for the committer or tagger.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::config::Config;
+use crate::metadata::{CommitMetadata, TagMetadata};
+
+/// What signatures made by rat are for, which ssh-keygen includes in what it
+/// signs, so that a signature made for something else can't be passed off as
+/// one on a commit.
+const NAMESPACE: &str = "rat";
+
+/// Whether every commit should be signed regardless of `commit.sign`, which
+/// `rat commit -S` turns on.
+static ALWAYS: AtomicBool = AtomicBool::new(false);
+
+/// Signs every commit made for the rest of the program.
+pub fn enable() {
+    ALWAYS.store(true, Ordering::Relaxed);
+}
+
+/// Checks whether new commits should be signed.
+pub fn should_sign() -> bool {
+    ALWAYS.load(Ordering::Relaxed)
+        || Config::load()
+            .ok()
+            .is_some_and(|config| config.get("commit.sign") == Some("true"))
+}
+
+/// What checking a commit or tag's signature found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// The committer or tagger's key made the signature, and nothing has
+    /// changed since.
+    Good,
+    /// The signature doesn't match, or wasn't made by a key we trust for the
+    /// committer or tagger.
+    Bad,
+    /// There's no signature.
+    Missing,
+}
+
+impl SignatureStatus {
+    /// The letter git uses for the status in `%G?`.
+    pub fn letter(&self) -> char {
+        match self {
+            Self::Good => 'G',
+            Self::Bad => 'B',
+            Self::Missing => 'N',
+        }
+    }
+}
+
+impl Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Good => "good",
+            Self::Bad => "bad",
+            Self::Missing => "missing",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Signs `metadata` with the key from `user.signingKey`, filling in its
+/// signature.
+pub fn sign(metadata: &mut CommitMetadata) -> Result<(), Box<dyn Error>> {
+    metadata.signature = Some(sign_data(&metadata.signed_data(), "commit")?);
+
+    Ok(())
+}
+
+/// Signs the tag `metadata` with the key from `user.signingKey`, filling in
+/// its signature.
+pub fn sign_tag(metadata: &mut TagMetadata) -> Result<(), Box<dyn Error>> {
+    metadata.signature = Some(sign_data(&metadata.signed_data(), "tag")?);
+
+    Ok(())
+}
+
+/// Makes a signature over `data`, which is the `what` being signed.
+fn sign_data(data: &str, what: &str) -> Result<String, Box<dyn Error>> {
+    let config = Config::load()?;
+    let key = config.get("user.signingKey").ok_or(format!(
+        "Set user.signingKey to the SSH key to sign {what}s with."
+    ))?;
+
+    // With nothing to sign given as a file, ssh-keygen signs whatever it's
+    // given to read, and writes the signature out.
+    let output = run_ssh_keygen(&["-Y", "sign", "-f", key, "-n", NAMESPACE], data.as_bytes())?;
+
+    if !output.status.success() {
+        Err(format!(
+            "Failed to sign the {what} with {key}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))?;
+    }
+
+    Ok(String::from_utf8(output.stdout)?.trim_end().to_string())
+}
+
+/// Checks the signature on `metadata` against the keys in
+/// `signing.allowedSigners`.
+pub fn verify(metadata: &CommitMetadata) -> Result<SignatureStatus, Box<dyn Error>> {
+    verify_data(
+        &metadata.signed_data(),
+        metadata.signature.as_deref(),
+        &metadata.committer.email,
+    )
+}
+
+/// Checks the signature on the tag `metadata` against the keys in
+/// `signing.allowedSigners`.
+pub fn verify_tag(metadata: &TagMetadata) -> Result<SignatureStatus, Box<dyn Error>> {
+    verify_data(
+        &metadata.signed_data(),
+        metadata.signature.as_deref(),
+        &metadata.tagger.email,
+    )
+}
+
+/// Checks that `signature` was made over `data` by the key allowed for
+/// `email`.
+fn verify_data(
+    data: &str,
+    signature: Option<&str>,
+    email: &str,
+) -> Result<SignatureStatus, Box<dyn Error>> {
+    let Some(signature) = signature else {
+        return Ok(SignatureStatus::Missing);
+    };
+
+    let config = Config::load()?;
+    let allowed_signers = config.get("signing.allowedSigners").ok_or(
+        "Set signing.allowedSigners to a file of trusted keys to check signatures against.",
+    )?;
+
+    // Several signatures can be checked at once, so every file needs a name
+    // of its own.
+    static NEXT_TEMPORARY: AtomicUsize = AtomicUsize::new(0);
+
+    // ssh-keygen can only read the signature from a file. It goes in the
+    // nest rather than the shared temporary directory, where somebody else
+    // could have guessed the name and put a file of their own there first.
+    // Even so, we make sure the file is a new one.
+    let signature_path = crate::nest_dir().join(format!(
+        "signature-{}-{}",
+        process::id(),
+        NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let failed = |e: io::Error| format!("Failed to write the signature to check: {e}");
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&signature_path)
+        .map_err(failed)?;
+
+    if let Err(e) = file.write_all(format!("{signature}\n").as_bytes()) {
+        let _ = fs::remove_file(&signature_path);
+        Err(failed(e))?;
+    }
+
+    let output = run_ssh_keygen(
+        &[
+            "-Y",
+            "verify",
+            "-f",
+            allowed_signers,
+            "-I",
+            email,
+            "-n",
+            NAMESPACE,
+            "-s",
+            &signature_path.to_string_lossy(),
+        ],
+        data.as_bytes(),
+    );
+
+    let _ = fs::remove_file(&signature_path);
+
+    Ok(match output?.status.success() {
+        true => SignatureStatus::Good,
+        false => SignatureStatus::Bad,
+    })
+}
+
+/// Runs `ssh-keygen` with `arguments`, giving it `input` to read.
+fn run_ssh_keygen(arguments: &[&str], input: &[u8]) -> Result<process::Output, Box<dyn Error>> {
+    let mut child = Command::new("ssh-keygen")
+        .args(arguments)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run ssh-keygen: {e}"))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(input) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e)?,
+            _ => {}
+        }
+    }
+
+    Ok(child.wait_with_output()?)
+}
@@ -0,0 +1,312 @@
+//! Keeping track of operations that stopped halfway.
+//!
+//! When a command like `cherry-pick` runs into a conflict, it can't finish on
+//! its own, so it leaves the conflicted files in the working directory for the
+//! user to fix and stops. To carry on afterwards, we need to remember what we
+//! were in the middle of, which we do with a few files in the nest:
+//!
+//! - A file named after the operation, like `.rat/CHERRY_PICK_HEAD`, holding
+//!   the hash of the commit it was working on.
+//! - `.rat/CONFLICTS`, listing the paths that still have conflicts, one per
+//!   line. Staging a file with `rat add` marks it as resolved.
+//! - For a merge, `.rat/MERGE_MSG`, holding the message the merge commit will
+//!   get once it's finished.
+//!
+//! A rebase works through a whole list of commits rather than just one, so
+//! it needs to remember a bit more. That lives in `.rat/rebase/`, which holds
+//! the branch being rebased, where it was before, what it's being rebased
+//! onto, and the commits that are still to go. See [`RebaseState`].
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::error::StateError;
+use crate::utils;
+
+/// The operations that can be left in progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    CherryPick,
+    Rebase,
+    Merge,
+}
+
+impl Operation {
+    /// Every operation, so we can check whether any of them are in progress.
+    const ALL: [Self; 3] = [Self::CherryPick, Self::Rebase, Self::Merge];
+
+    /// The file in the nest that records the commit the operation is working
+    /// on.
+    fn path(self) -> PathBuf {
+        let name = match self {
+            Self::CherryPick => "CHERRY_PICK_HEAD",
+            Self::Rebase => "REBASE_HEAD",
+            Self::Merge => "MERGE_HEAD",
+        };
+
+        crate::nest_path(name)
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::CherryPick => "cherry-pick",
+            Self::Rebase => "rebase",
+            Self::Merge => "merge",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+fn conflicts_path() -> PathBuf {
+    crate::nest_path("CONFLICTS")
+}
+
+fn merge_message_path() -> PathBuf {
+    crate::nest_path("MERGE_MSG")
+}
+
+/// Finds the operation that's currently in progress, if any, along with the
+/// hash of the commit it's working on.
+pub fn current() -> Result<Option<(Operation, String)>, io::Error> {
+    for operation in Operation::ALL {
+        match fs::read_to_string(operation.path()) {
+            Ok(hash) => return Ok(Some((operation, hash.trim().to_string()))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Makes sure no operation is in progress, since starting a new one on top of
+/// it would make a mess of both.
+pub fn ensure_idle() -> Result<(), StateError> {
+    match current()? {
+        Some((operation, hash)) => Err(StateError::InProgress { operation, hash }),
+        None => Ok(()),
+    }
+}
+
+/// Records that `operation` stopped while working on the commit `hash`,
+/// leaving `conflicts` to be resolved.
+pub fn start(
+    operation: Operation,
+    hash: &str,
+    conflicts: &BTreeSet<String>,
+) -> Result<(), io::Error> {
+    utils::write_atomically(operation.path(), hash)?;
+    write_conflicts(conflicts)
+}
+
+/// Forgets about the operation in progress, along with any conflicts it left.
+pub fn finish(operation: Operation) -> Result<(), io::Error> {
+    if operation == Operation::Rebase {
+        match fs::remove_dir_all(rebase_dir()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    if operation == Operation::Merge {
+        remove_if_exists(merge_message_path())?;
+    }
+
+    remove_if_exists(operation.path())?;
+    remove_if_exists(conflicts_path())
+}
+
+/// Saves the message for the merge commit of a merge that stopped.
+pub fn write_merge_message(message: &str) -> Result<(), io::Error> {
+    utils::write_atomically(merge_message_path(), message)
+}
+
+/// Reads the message for the merge commit of the merge that's in progress.
+pub fn read_merge_message() -> Result<String, io::Error> {
+    fs::read_to_string(merge_message_path())
+}
+
+fn remove_if_exists(path: PathBuf) -> Result<(), io::Error> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the paths that still have conflicts.
+pub fn read_conflicts() -> Result<BTreeSet<String>, io::Error> {
+    match fs::read_to_string(conflicts_path()) {
+        Ok(contents) => Ok(contents.lines().map(str::to_string).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replaces the list of paths that still have conflicts.
+pub fn write_conflicts(conflicts: &BTreeSet<String>) -> Result<(), io::Error> {
+    if conflicts.is_empty() {
+        return remove_if_exists(conflicts_path());
+    }
+
+    let contents = conflicts
+        .iter()
+        .map(|path| format!("{path}\n"))
+        .collect::<String>();
+
+    utils::write_atomically(conflicts_path(), contents)
+}
+
+fn rebase_dir() -> PathBuf {
+    crate::nest_path("rebase")
+}
+
+/// Everything we need to remember about a rebase for as long as it's going
+/// on, which is kept in `.rat/rebase/`.
+///
+/// While a rebase is going on, HEAD is detached and moves along as each
+/// commit is replayed. The branch itself stays where it was until the very
+/// end, so giving up only has to put HEAD back on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebaseState {
+    /// The branch being rebased, or `None` if HEAD was detached.
+    pub head_name: Option<String>,
+    /// Where HEAD was before the rebase started.
+    pub orig_head: String,
+    /// The commit the branch is being rebased onto.
+    pub onto: String,
+    /// What's still to be done, in order. While the rebase is stopped, the
+    /// first step is the one it stopped at.
+    pub todo: Vec<RebaseStep>,
+}
+
+impl RebaseState {
+    /// Reads the state of the rebase that's going on.
+    pub fn read() -> Result<Self, StateError> {
+        let dir = rebase_dir();
+
+        let read = |name: &str| match fs::read_to_string(dir.join(name)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+
+        let (Some(orig_head), Some(onto), Some(todo)) =
+            (read("orig-head")?, read("onto")?, read("todo")?)
+        else {
+            return Err(StateError::NotInProgress(Operation::Rebase));
+        };
+
+        Ok(Self {
+            head_name: read("head-name")?.map(|name| name.trim().to_string()),
+            orig_head: orig_head.trim().to_string(),
+            onto: onto.trim().to_string(),
+            todo: todo
+                .lines()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|e: String| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        })
+    }
+
+    /// Saves the state, replacing whatever was saved before.
+    pub fn write(&self) -> Result<(), io::Error> {
+        let dir = rebase_dir();
+        fs::create_dir_all(&dir)?;
+
+        match &self.head_name {
+            Some(name) => utils::write_atomically(dir.join("head-name"), name)?,
+            None => remove_if_exists(dir.join("head-name"))?,
+        }
+
+        let todo = self
+            .todo
+            .iter()
+            .map(|step| format!("{step}\n"))
+            .collect::<String>();
+
+        utils::write_atomically(dir.join("orig-head"), &self.orig_head)?;
+        utils::write_atomically(dir.join("onto"), &self.onto)?;
+        utils::write_atomically(dir.join("todo"), todo)
+    }
+}
+
+/// What an interactive rebase can do with each commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebaseAction {
+    /// Replay the commit as it is.
+    Pick,
+    /// Replay the commit, but let the user change its message.
+    Reword,
+    /// Replay the commit, but combine it with the one before it, along with
+    /// both of their messages.
+    Squash,
+    /// Leave the commit out entirely.
+    Drop,
+}
+
+impl Display for RebaseAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Pick => "pick",
+            Self::Reword => "reword",
+            Self::Squash => "squash",
+            Self::Drop => "drop",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for RebaseAction {
+    type Err = String;
+
+    /// Parses an action, which can be shortened to its first letter like in
+    /// git.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pick" | "p" => Ok(Self::Pick),
+            "reword" | "r" => Ok(Self::Reword),
+            "squash" | "s" => Ok(Self::Squash),
+            "drop" | "d" => Ok(Self::Drop),
+            _ => Err(format!("Unknown rebase action {s}.")),
+        }
+    }
+}
+
+/// One line of a rebase's todo list: something to do with a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebaseStep {
+    pub action: RebaseAction,
+    /// The hash of the commit.
+    pub commit: String,
+}
+
+impl Display for RebaseStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.action, self.commit)
+    }
+}
+
+impl FromStr for RebaseStep {
+    type Err = String;
+
+    /// Parses a step in the form it's saved in, which is the action followed
+    /// by the full hash of the commit.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (action, commit) = s
+            .split_once(' ')
+            .ok_or_else(|| format!("Invalid rebase step {s}."))?;
+
+        Ok(Self {
+            action: action.parse()?,
+            commit: commit.trim().to_string(),
+        })
+    }
+}
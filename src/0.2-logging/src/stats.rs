@@ -0,0 +1,167 @@
+//! Counting what's in the nest, and how much room it takes up.
+//!
+//! Every commit is a snapshot of every file, which sounds like it should take
+//! up a lot of space, but since objects are named after their contents, a
+//! file that's the same in two commits is only stored once. The numbers here
+//! make that easy to see: how big the history would be if every commit kept
+//! a copy of every file, next to how big it is with each distinct version
+//! stored once, and how much room the nest really takes up on disk once
+//! [`pack`](crate::pack)s have squeezed those versions down further.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::compare::{self, FileMode};
+use crate::objects::{self, ObjectKind};
+use crate::{pack, progress, refs, utils};
+
+/// How many of the largest files are listed.
+const LARGEST_FILES: usize = 5;
+
+/// Everything we count about the nest.
+#[derive(Debug, Clone, Default)]
+pub struct NestStats {
+    pub commits: usize,
+    pub trees: usize,
+    /// Blobs holding a file's contents. The chunks of a file that's been
+    /// split up are counted in `chunks` instead.
+    pub blobs: usize,
+    /// Pieces of big files that have been split into
+    /// [chunks](crate::chunk), each of them a blob of its own.
+    pub chunks: usize,
+    /// Annotated tags, which are objects of their own. Tags that are only
+    /// refs are counted in `tag_refs` instead.
+    pub tags: usize,
+    /// Objects in files of their own.
+    pub loose_objects: usize,
+    /// Objects in packs. One that's in a pack and loose as well is counted
+    /// both times.
+    pub packed_objects: usize,
+    pub packs: usize,
+    pub branches: usize,
+    pub tag_refs: usize,
+    /// How big every file of every commit in the history adds up to, if each
+    /// commit had a copy of its own.
+    pub history_size: u64,
+    /// How big every distinct version of a file in the history adds up to,
+    /// which is what storing each one once saves us.
+    pub stored_size: u64,
+    /// How much room everything in the nest directory takes up on disk.
+    pub disk_size: u64,
+    /// The biggest files in the history, biggest first.
+    pub largest: Vec<LargeFile>,
+}
+
+/// A version of a file that's in the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargeFile {
+    /// Where it is in the newest commit that has it.
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+}
+
+/// Counts everything in the nest, going through the snapshots of `history`,
+/// which is every commit that should count as part of it, newest first.
+pub fn collect(history: &[String]) -> Result<NestStats, Box<dyn Error>> {
+    let nest = crate::nest_dir();
+
+    let loose = objects::list_loose_objects_in(nest)?;
+    let packed = pack::list_packed_objects_in(nest)?;
+
+    let mut stats = NestStats {
+        loose_objects: loose.len(),
+        packed_objects: packed.len(),
+        packs: pack::list_packs_in(nest)?.len(),
+        branches: refs::list_branches()?.len(),
+        tag_refs: refs::list_tags()?.len(),
+        disk_size: directory_size(nest)?,
+        ..NestStats::default()
+    };
+
+    // An object can be both loose and packed, but it's still only one
+    // object.
+    let hashes: HashSet<&String> = loose.iter().chain(&packed).collect();
+    let mut sizes = HashMap::new();
+    let mut chunks = HashSet::new();
+    let task = progress::start("Counting objects", hashes.len());
+
+    // Only the headers are read, since that's all we need, and reading a
+    // file that's been split into chunks would put the whole thing together.
+    for &hash in &hashes {
+        let header = objects::read_header(hash)?;
+
+        match header.kind {
+            ObjectKind::Commit => stats.commits += 1,
+            ObjectKind::Tree => stats.trees += 1,
+            ObjectKind::Blob => stats.blobs += 1,
+            ObjectKind::Tag => stats.tags += 1,
+        }
+
+        chunks.extend(header.chunks);
+        sizes.insert(hash.as_str(), header.size);
+        task.advance(header.size);
+    }
+
+    drop(task);
+
+    stats.chunks = chunks.iter().filter(|hash| hashes.contains(hash)).count();
+    stats.blobs -= stats.chunks;
+
+    // Going from newest to oldest, the first path we see each version at is
+    // where it is most recently.
+    let mut seen: HashMap<String, String> = HashMap::new();
+
+    for commit in history {
+        for (path, entry) in compare::read_commit(Some(commit))? {
+            if entry.mode == FileMode::Nest {
+                continue;
+            }
+
+            let size = sizes.get(entry.hash.as_str()).copied().unwrap_or(0);
+            stats.history_size += size;
+
+            if let Entry::Vacant(vacant) = seen.entry(entry.hash) {
+                stats.stored_size += size;
+                vacant.insert(path);
+            }
+        }
+    }
+
+    let mut largest: Vec<LargeFile> = seen
+        .into_iter()
+        .map(|(hash, path)| LargeFile {
+            size: sizes.get(hash.as_str()).copied().unwrap_or(0),
+            path,
+            hash,
+        })
+        .collect();
+
+    // Files of the same size are sorted by path, so the list is always the
+    // same.
+    largest.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    largest.truncate(LARGEST_FILES);
+    stats.largest = largest;
+
+    Ok(stats)
+}
+
+/// Adds up the sizes of every file in `directory` and everything in it.
+fn directory_size(directory: &Path) -> io::Result<u64> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+
+        size += match entry.file_type()?.is_dir() {
+            true => directory_size(&entry.path())?,
+            false => utils::file_size(entry.path()),
+        };
+    }
+
+    Ok(size)
+}
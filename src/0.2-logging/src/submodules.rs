@@ -0,0 +1,124 @@
+//! Keeping other nests inside this one, each pinned to a commit.
+//!
+//! A project that depends on another one can keep a copy of it in a
+//! subdirectory without mixing their histories together. The subdirectory is
+//! a nest of its own, and all this nest records about it is which commit it
+//! should be on, as a snapshot entry with the [`Nest`](FileMode::Nest) mode
+//! whose hash is that commit. Its files are never hashed or stored here, so
+//! committing in the outer nest only ever moves the pointer.
+//!
+//! Where each one comes from is kept in `.ratmodules` at the root of the
+//! working directory, in the same format as a config file, so that it's
+//! committed along with everything else:
+//!
+//! ```text
+//! [submodule "vendor/parser"]
+//!     path = vendor/parser
+//!     url = ../parser
+//! ```
+//!
+//! `rat submodule update` clones any of them that are missing and checks out
+//! the commit each is pinned to, which is how a fresh clone gets them.
+
+use std::error::Error;
+use std::io;
+use std::path::Path;
+
+use crate::compare::FileMode;
+use crate::config::ConfigFile;
+use crate::index::Index;
+use crate::refs::{self, Head, Ref};
+use crate::{objects, RAT_NEST};
+
+/// The file listing where each nest inside this one comes from.
+pub const MODULES_FILE: &str = ".ratmodules";
+
+/// A nest kept inside this one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submodule {
+    /// Where it is in the working directory.
+    pub path: String,
+    /// Where it was cloned from.
+    pub url: String,
+    /// The commit it's pinned to in the index, unless it hasn't been staged.
+    pub pinned: Option<String>,
+    /// The commit it's actually on, unless it hasn't been cloned yet.
+    pub checked_out: Option<String>,
+}
+
+/// Checks whether the directory at `path` in the working directory is a nest
+/// of its own, rather than part of this one.
+pub fn is_nest(path: &str) -> bool {
+    !path.is_empty() && Path::new(path).join(RAT_NEST).exists()
+}
+
+/// Finds the commit the nest at `path` in the working directory is on, or
+/// `None` if it isn't a nest or doesn't have any commits yet.
+pub fn head(path: &str) -> io::Result<Option<String>> {
+    if !is_nest(path) {
+        return Ok(None);
+    }
+
+    let Some(nest) = crate::find_nest(Path::new(path)) else {
+        return Ok(None);
+    };
+
+    let read = || match refs::read_head_in(&nest)? {
+        Head::Branch(branch) => refs::read_ref_in(&nest, &Ref::Branch(branch)),
+        Head::Detached(hash) => Ok(Some(hash)),
+    };
+
+    read().map_err(io::Error::other)
+}
+
+/// Checks whether the nest at `path` has the commit `hash`, so that it can be
+/// checked out without fetching first.
+pub fn has_commit(path: &str, hash: &str) -> bool {
+    crate::find_nest(Path::new(path)).is_some_and(|nest| objects::has_object_in(&nest, hash))
+}
+
+/// Lists every nest in `.ratmodules`, sorted by path.
+pub fn list() -> Result<Vec<Submodule>, Box<dyn Error>> {
+    let modules = ConfigFile::read(MODULES_FILE)?;
+    let index = Index::read(crate::nest_path("index"))?.entries;
+
+    let mut submodules: Vec<Submodule> = modules
+        .entries()
+        .filter_map(|(key, path)| {
+            let name = key.strip_prefix("submodule.")?.strip_suffix(".path")?;
+            let url = modules
+                .entries()
+                .find(|(key, _)| *key == format!("submodule.{name}.url"))?
+                .1;
+
+            Some((path.to_string(), url.to_string()))
+        })
+        .map(|(path, url)| {
+            Ok(Submodule {
+                pinned: index
+                    .get(&path)
+                    .filter(|entry| entry.mode == FileMode::Nest)
+                    .map(|entry| entry.hash.clone()),
+                checked_out: head(&path)?,
+                path,
+                url,
+            })
+        })
+        .collect::<io::Result<_>>()?;
+
+    submodules.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(submodules)
+}
+
+/// Records in `.ratmodules` that the nest at `path` comes from `url`. It
+/// still has to be staged, along with `.ratmodules` itself.
+pub fn record(path: &str, url: &str) -> Result<(), Box<dyn Error>> {
+    let mut modules = ConfigFile::read(MODULES_FILE)?;
+
+    modules.set(&format!("submodule.{path}.path"), path)?;
+    modules.set(&format!("submodule.{path}.url"), url)?;
+    modules.write(MODULES_FILE)?;
+
+    Ok(())
+}
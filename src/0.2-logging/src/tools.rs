@@ -0,0 +1,178 @@
+//! Comparing and merging files with programs made for it.
+//!
+//! Reading a patch works for small changes, but for big ones, or for working
+//! through a conflict, a program that shows both versions side by side is a
+//! lot easier. `rat difftool` and `rat mergetool` hand files over to whichever
+//! one the user likes, set with these settings:
+//!
+//! - `diff.tool` and `merge.tool`, naming the program to use for each. Several
+//!   well-known ones, like `meld` and `vimdiff`, work without anything else.
+//! - `difftool.<tool>.cmd` and `mergetool.<tool>.cmd`, the command that runs a
+//!   tool, for tools we don't know about or to run one differently. The
+//!   command is split up the way a shell would, and `$LOCAL`, `$REMOTE`,
+//!   `$BASE` and `$MERGED` in it are replaced with the paths of the files.
+//! - `mergetool.<tool>.trustExitCode`, which when `true` means the tool's exit
+//!   code says whether the merge worked. Otherwise it only counts as having
+//!   worked if the tool exited successfully after changing the file.
+//!
+//! `$LOCAL` is our version of the file and `$REMOTE` is theirs, or for a diff,
+//! the old and new versions. `$BASE` is the version both sides of a merge
+//! started from, and `$MERGED` is the file in the working directory, which the
+//! merge should be saved to.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::Config;
+use crate::utils;
+
+/// What a tool is being used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolKind {
+    Diff,
+    Merge,
+}
+
+impl ToolKind {
+    /// The section settings for tools of this kind are kept in.
+    fn section(self) -> &'static str {
+        match self {
+            Self::Diff => "difftool",
+            Self::Merge => "mergetool",
+        }
+    }
+}
+
+impl Display for ToolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Diff => "diff",
+            Self::Merge => "merge",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// The tools that work without setting a command, with the commands that
+/// compare and merge files with them.
+const KNOWN_TOOLS: &[(&str, &str, &str)] = &[
+    (
+        "meld",
+        "meld $LOCAL $REMOTE",
+        "meld $LOCAL $BASE $REMOTE --output $MERGED",
+    ),
+    (
+        "vimdiff",
+        "vim -d $LOCAL $REMOTE",
+        "vim -d $MERGED $LOCAL $BASE $REMOTE",
+    ),
+    (
+        "nvimdiff",
+        "nvim -d $LOCAL $REMOTE",
+        "nvim -d $MERGED $LOCAL $BASE $REMOTE",
+    ),
+    (
+        "kdiff3",
+        "kdiff3 $LOCAL $REMOTE",
+        "kdiff3 $BASE $LOCAL $REMOTE -o $MERGED",
+    ),
+    (
+        "vscode",
+        "code --wait --diff $LOCAL $REMOTE",
+        "code --wait --merge $REMOTE $LOCAL $BASE $MERGED",
+    ),
+    (
+        "opendiff",
+        "opendiff $LOCAL $REMOTE",
+        "opendiff $LOCAL $REMOTE -ancestor $BASE -merge $MERGED",
+    ),
+];
+
+/// A tool picked by the user's settings, ready to run.
+#[derive(Debug, Clone)]
+pub struct Tool {
+    pub name: String,
+    command: String,
+    trust_exit_code: bool,
+}
+
+impl Tool {
+    /// Looks up the tool the settings say to use for `kind`, or the one named
+    /// `name` if it's given.
+    pub fn configured(kind: ToolKind, name: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let config = Config::load()?;
+
+        let name = match name {
+            Some(name) => name,
+            None => config.get(&format!("{kind}.tool")).ok_or_else(|| {
+                format!("Set {kind}.tool to the program to use, like meld or vimdiff.")
+            })?,
+        };
+
+        let section = kind.section();
+        let known = KNOWN_TOOLS.iter().find(|(known, ..)| *known == name).map(
+            |(_, diff, merge)| match kind {
+                ToolKind::Diff => *diff,
+                ToolKind::Merge => *merge,
+            },
+        );
+
+        let command = config
+            .get(&format!("{section}.{name}.cmd"))
+            .or(known)
+            .ok_or_else(|| {
+                format!(
+                    "rat doesn't know how to run {name}. Set {section}.{name}.cmd to its command."
+                )
+            })?;
+
+        Ok(Self {
+            name: name.to_string(),
+            command: command.to_string(),
+            trust_exit_code: config.get(&format!("{section}.{name}.trustExitCode")) == Some("true"),
+        })
+    }
+
+    /// Runs the tool on `files`, which gives the path to put in place of each
+    /// of `$LOCAL`, `$REMOTE`, `$BASE` and `$MERGED`, and waits for it to
+    /// finish. Returns whether it exited successfully.
+    pub fn run(&self, files: &[(&str, &Path)]) -> Result<bool, Box<dyn Error>> {
+        let words = utils::split_command_line(&self.command).ok_or_else(|| {
+            format!(
+                "The command for {} has a quote that's never closed.",
+                self.name
+            )
+        })?;
+
+        let words: Vec<String> = words
+            .into_iter()
+            .map(|mut word| {
+                for (name, path) in files {
+                    word = word.replace(&format!("${name}"), &path.to_string_lossy());
+                }
+
+                word
+            })
+            .collect();
+
+        let (program, arguments) = words
+            .split_first()
+            .ok_or_else(|| format!("The command for {} is empty.", self.name))?;
+
+        let status = Command::new(program)
+            .args(arguments)
+            .status()
+            .map_err(|e| format!("Failed to run {}: {e}", self.name))?;
+
+        Ok(status.success())
+    }
+
+    /// Works out whether a merge worked, given whether the tool exited
+    /// successfully and whether it changed the merged file.
+    pub fn merged(&self, succeeded: bool, changed: bool) -> bool {
+        succeeded && (self.trust_exit_code || changed)
+    }
+}
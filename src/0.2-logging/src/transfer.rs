@@ -0,0 +1,165 @@
+//! Copying history from one nest to another.
+//!
+//! Since objects are named after their contents, sharing history is just a
+//! matter of copying the objects the other nest doesn't have yet. A commit
+//! depends on its tree and its parents, and a tree depends on everything in
+//! it, so starting from the commits we want to send, we walk through
+//! everything they depend on and stop whenever we reach an object the other
+//! nest already has. Because we always copy an object's dependencies before
+//! the object itself, having a commit means having its entire history, which
+//! is what lets us stop early.
+//!
+//! Files kept out of the nest with [`lfs`](crate::lfs) are only pointers as
+//! far as the history is concerned, so once the objects are copied, we copy
+//! the contents of every pointer among them too.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+
+use crate::error::{ObjectError, TransportError};
+use crate::metadata::{CommitMetadata, TagMetadata};
+use crate::objects::{self, ObjectKind};
+use crate::transport::Transport;
+use crate::{format, lfs, logging};
+
+/// The kind and data of an object we've downloaded but not stored yet.
+type Object = (ObjectKind, Vec<u8>);
+
+/// Copies every object needed by the objects in `tips` from the nest that
+/// `from` reads from to the nest directory `to`, returning how many objects
+/// were copied.
+///
+/// The tips are usually commits, but can be anything a ref points at, like
+/// the tag objects of annotated tags.
+pub fn copy_objects(
+    from: &dyn Transport,
+    to: &Path,
+    tips: &[String],
+) -> Result<usize, Box<dyn Error>> {
+    // The same object has a different hash in each, so there's no way to
+    // tell which objects the other nest already has.
+    let (from_hash, to_hash) = (from.hash_algorithm()?, format::read(to)?.hash);
+
+    if from_hash != to_hash {
+        Err(TransportError::HashMismatch {
+            from: from_hash,
+            to: to_hash,
+        })?;
+    }
+
+    let mut copied = 0;
+    let mut pointers = Vec::new();
+
+    walk_objects(
+        from,
+        tips,
+        |hash| objects::has_object_in(to, hash),
+        |hash, kind, data| {
+            logging::verbose(format_args!("Copied {kind} {hash}"));
+
+            if kind == ObjectKind::Blob {
+                pointers.extend(lfs::Pointer::parse(&data));
+            }
+
+            objects::write_object_in(to, kind, &data)?;
+            copied += 1;
+
+            Ok(())
+        },
+    )?;
+
+    copy_lfs_contents(from, to, &pointers)?;
+
+    Ok(copied)
+}
+
+/// Copies the contents each of `pointers` points to from the nest that `from`
+/// reads from to the store of the nest directory `to`, skipping any that
+/// it already has. Contents the other nest doesn't have either are left out,
+/// and checking them out leaves the pointer there instead.
+fn copy_lfs_contents(
+    from: &dyn Transport,
+    to: &Path,
+    pointers: &[lfs::Pointer],
+) -> Result<(), Box<dyn Error>> {
+    let storage = lfs::storage_in(to);
+
+    for pointer in pointers {
+        if lfs::content_path_in(&storage, &pointer.hash).is_file() {
+            continue;
+        }
+
+        let Some(contents) = from.read_lfs(&pointer.hash)? else {
+            continue;
+        };
+
+        // The contents are named after their hash just like objects, so we
+        // can check they're what the pointer says they are.
+        if lfs::store_in(&storage, contents)? != *pointer {
+            Err(ObjectError::Corrupt {
+                hash: pointer.hash.clone(),
+            })?;
+        }
+
+        logging::verbose(format_args!("Copied the contents of {}", pointer.hash));
+    }
+
+    Ok(())
+}
+
+/// Visits every object needed by the objects in `tips`, reading them through
+/// `from`, and calls `visit` with each one's hash, kind and data. Objects
+/// that `have` says are already taken care of are skipped, along with
+/// everything they depend on.
+///
+/// Every object is visited after everything it depends on, which is what lets
+/// [`copy_objects`] stop early.
+pub fn walk_objects(
+    from: &dyn Transport,
+    tips: &[String],
+    have: impl Fn(&str) -> bool,
+    mut visit: impl FnMut(String, ObjectKind, Vec<u8>) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut seen = HashSet::new();
+
+    // Each entry on the stack is the hash of an object, along with the object
+    // itself once we've downloaded it and pushed its dependencies. When we
+    // come back to it after its dependencies have all been visited, it's safe
+    // to visit it too. Holding on to it means we never download anything
+    // twice.
+    let mut stack: Vec<(String, Option<Object>)> =
+        tips.iter().map(|tip| (tip.clone(), None)).collect();
+
+    while let Some((hash, object)) = stack.pop() {
+        if let Some((kind, data)) = object {
+            visit(hash, kind, data)?;
+
+            continue;
+        }
+
+        if have(&hash) || !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        let (kind, data) = from.read_object(&hash)?;
+
+        let dependencies = match kind {
+            ObjectKind::Blob => Vec::new(),
+            ObjectKind::Tree => objects::tree_children(&data)?,
+            ObjectKind::Commit => {
+                let metadata = CommitMetadata::parse(std::str::from_utf8(&data)?)?;
+
+                let mut dependencies = metadata.parents;
+                dependencies.push(metadata.tree);
+                dependencies
+            }
+            ObjectKind::Tag => vec![TagMetadata::parse(std::str::from_utf8(&data)?)?.object],
+        };
+
+        stack.push((hash, Some((kind, data))));
+        stack.extend(dependencies.into_iter().map(|hash| (hash, None)));
+    }
+
+    Ok(())
+}
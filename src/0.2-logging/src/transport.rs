@@ -0,0 +1,206 @@
+//! Reading from nests that aren't our own.
+//!
+//! Fetching and cloning only ever need to do a few things with the nest on the
+//! other side: list its refs, find out what its `HEAD` points at, and download
+//! objects, along with the contents of any files it keeps out of the nest
+//! with [`lfs`](crate::lfs). A [`Transport`] is anything that can do those, which lets the
+//! rest of rat share the same logic no matter where the other nest lives.
+//!
+//! There are two transports. [`LocalTransport`] reads straight from a nest
+//! directory on the same filesystem. [`HttpTransport`] downloads the same
+//! files from a web server, using the same layout as the nest directory
+//! itself, plus a file called `info/refs` listing every ref with its hash,
+//! since there's no way to list a directory over plain HTTP:
+//!
+//! ```text
+//! 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08 refs/heads/main
+//! 2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae refs/tags/v1.0
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::error::TransportError;
+use crate::format::{self, Format};
+use crate::hash::HashAlgorithm;
+use crate::objects::{self, ObjectKind};
+use crate::refs::{self, Head, Ref};
+use crate::{http, lfs, notes};
+
+/// A way of reading the refs and objects of another nest.
+pub trait Transport {
+    /// Lists every branch and tag as its full ref name and the hash it points
+    /// at, sorted by name.
+    fn list_refs(&self) -> Result<Vec<(String, String)>, Box<dyn Error>>;
+
+    /// Reads what the nest's `HEAD` is pointing at.
+    fn read_head(&self) -> Result<Head, Box<dyn Error>>;
+
+    /// Reads the object with the given hash, returning its kind and data.
+    fn read_object(&self, hash: &str) -> Result<(ObjectKind, Vec<u8>), Box<dyn Error>>;
+
+    /// Finds out which [`hash`](crate::hash) function the nest names its
+    /// objects with.
+    fn hash_algorithm(&self) -> Result<HashAlgorithm, Box<dyn Error>>;
+
+    /// Reads every [`note`](crate::notes) in the nest, by the hash of the
+    /// commit it belongs to, or `None` if there's no way to tell what they
+    /// are from here.
+    fn read_notes(&self) -> Result<Option<HashMap<String, String>>, Box<dyn Error>>;
+
+    /// Opens the contents of a file kept out of the nest, by their SHA-256
+    /// hash, or returns `None` if the nest's store doesn't have them.
+    fn read_lfs(&self, hash: &str) -> Result<Option<Box<dyn Read>>, Box<dyn Error>>;
+}
+
+/// Picks the right transport for `url`, which is either an `http://` URL for a
+/// nest directory or the path of a directory containing a nest.
+pub fn open(url: &str) -> Result<Box<dyn Transport>, Box<dyn Error>> {
+    if http::is_url(url) {
+        Ok(Box::new(HttpTransport::new(url)))
+    } else {
+        let nest = crate::find_nest(Path::new(url)).ok_or_else(|| TransportError::NotANest {
+            url: url.to_string(),
+        })?;
+
+        Ok(Box::new(LocalTransport::new(nest)))
+    }
+}
+
+/// Reads from a nest directory on the same filesystem.
+pub struct LocalTransport {
+    nest: PathBuf,
+}
+
+impl LocalTransport {
+    pub fn new(nest: impl Into<PathBuf>) -> Self {
+        Self { nest: nest.into() }
+    }
+}
+
+impl Transport for LocalTransport {
+    fn list_refs(&self) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let mut refs = Vec::new();
+
+        for name in refs::list_all_refs_in(&self.nest)? {
+            if !matches!(name, Ref::Branch(_) | Ref::Tag(_)) {
+                continue;
+            }
+
+            if let Some(hash) = refs::read_ref_in(&self.nest, &name)? {
+                refs.push((name.full_name(), hash));
+            }
+        }
+
+        Ok(refs)
+    }
+
+    fn read_head(&self) -> Result<Head, Box<dyn Error>> {
+        Ok(refs::read_head_in(&self.nest)?)
+    }
+
+    fn read_object(&self, hash: &str) -> Result<(ObjectKind, Vec<u8>), Box<dyn Error>> {
+        Ok(objects::read_object_in(&self.nest, hash)?)
+    }
+
+    fn hash_algorithm(&self) -> Result<HashAlgorithm, Box<dyn Error>> {
+        Ok(format::read(&self.nest)?.hash)
+    }
+
+    fn read_notes(&self) -> Result<Option<HashMap<String, String>>, Box<dyn Error>> {
+        Ok(Some(notes::read_all_in(&self.nest)?))
+    }
+
+    fn read_lfs(&self, hash: &str) -> Result<Option<Box<dyn Read>>, Box<dyn Error>> {
+        let path = lfs::content_path_in(&lfs::storage_in(&self.nest), hash);
+
+        match File::open(path) {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Downloads from a nest directory served over HTTP.
+pub struct HttpTransport {
+    base_url: String,
+}
+
+impl HttpTransport {
+    pub fn new(url: &str) -> Self {
+        Self {
+            base_url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Downloads the file at `path` inside the nest directory, failing if it
+    /// doesn't exist.
+    fn get(&self, path: &str) -> Result<Vec<u8>, TransportError> {
+        let url = format!("{}/{path}", self.base_url);
+
+        http::get(&url)?.ok_or(TransportError::Missing { url })
+    }
+}
+
+impl Transport for HttpTransport {
+    fn list_refs(&self) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let listing = String::from_utf8(self.get("info/refs")?)?;
+
+        let mut refs = listing
+            .lines()
+            .map(|line| {
+                let (hash, name) =
+                    line.split_once(' ')
+                        .ok_or_else(|| TransportError::InvalidResponse {
+                            url: format!("{}/info/refs", self.base_url),
+                            reason: format!("it has the line {line}"),
+                        })?;
+
+                Ok((name.to_string(), hash.to_string()))
+            })
+            .collect::<Result<Vec<_>, TransportError>>()?;
+
+        refs.sort();
+
+        Ok(refs)
+    }
+
+    fn read_head(&self) -> Result<Head, Box<dyn Error>> {
+        Ok(refs::parse_head(&String::from_utf8(self.get("HEAD")?)?)?)
+    }
+
+    fn read_object(&self, hash: &str) -> Result<(ObjectKind, Vec<u8>), Box<dyn Error>> {
+        let (directory, file) = hash.split_at(2.min(hash.len()));
+
+        // We can't trust what comes over the network any more than what's on
+        // disk, so we check the hash just the same.
+        Ok(objects::decode(
+            hash,
+            &self.get(&format!("objects/{directory}/{file}"))?,
+        )?)
+    }
+
+    fn hash_algorithm(&self) -> Result<HashAlgorithm, Box<dyn Error>> {
+        // Nests from before there was a choice don't have a format file.
+        match http::get(&format!("{}/format", self.base_url))? {
+            Some(format) => Ok(Format::parse(&String::from_utf8(format)?)?.hash),
+            None => Ok(HashAlgorithm::default()),
+        }
+    }
+
+    fn read_notes(&self) -> Result<Option<HashMap<String, String>>, Box<dyn Error>> {
+        // Notes aren't served, since they aren't part of the history.
+        Ok(None)
+    }
+
+    fn read_lfs(&self, hash: &str) -> Result<Option<Box<dyn Read>>, Box<dyn Error>> {
+        let (directory, file) = hash.split_at(2.min(hash.len()));
+        let url = format!("{}/lfs/objects/{directory}/{file}", self.base_url);
+
+        Ok(http::get(&url)?.map(|contents| Box::new(io::Cursor::new(contents)) as Box<dyn Read>))
+    }
+}
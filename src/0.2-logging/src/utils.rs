@@ -4,48 +4,762 @@
 //! May use slightly more advanced Rust concepts. If you're primarily trying to
 //! learn about git, it's not necessary to attempt to read and understand these.
 
-use std::fs;
-use std::io;
-use std::path::Path;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::panic;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
-/// Recursively copies the contents, including subdirectories, of `from` into
-/// `to`. Ignores any paths that match those contained in the `ignore` array.
-pub fn copy_dir_deep(
-    from: impl AsRef<Path>,
-    to: impl AsRef<Path>,
-    ignore: &[impl AsRef<Path>],
-) -> Result<(), io::Error> {
-    let dir_entries = fs::read_dir(&from)?;
+/// Converts a path given on the command line, like `./src//main.rs`, into the
+/// normalized form used inside the nest, like `src/main.rs`. Returns `None` if
+/// the path tries to escape the current directory.
+///
+/// Absolute paths, including Windows ones like `C:\nest\notes.txt` or
+/// `\\server\share\nest\notes.txt`, are fine as long as they lead somewhere
+/// inside the current directory.
+///
+/// An empty string is returned for paths that refer to the current directory
+/// itself, such as `.`.
+pub fn normalize_path(path: impl AsRef<Path>) -> Option<String> {
+    let mut path = path.as_ref().to_path_buf();
 
-    // Ensure the path we intend to transfer to exists.
-    fs::create_dir_all(&to)?;
+    if path.has_root() {
+        path = path
+            .strip_prefix(env::current_dir().ok()?)
+            .ok()?
+            .to_path_buf();
+    }
+
+    let mut parts = Vec::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(parts.join("/"))
+}
+
+/// Replaces the contents of the file at `path` with `contents` in one step,
+/// so that anybody reading it only ever sees the old contents or the new ones.
+/// The same goes for crashes, which is what stops a crash halfway through
+/// moving a branch from leaving it pointing at half a hash.
+///
+/// The trick is to write a temporary file next to it first, make sure that's
+/// really on the disk, then rename it over the top, which filesystems do all at
+/// once. The temporary file's name starts with a dot, which no ref's name can,
+/// so it's never mistaken for one.
+pub fn write_atomically(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    // Several files can be written at once, so every temporary file needs a
+    // name of its own.
+    static NEXT_TEMPORARY: AtomicUsize = AtomicUsize::new(0);
+
+    let path = path.as_ref();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary_path = path.with_file_name(format!(
+        ".{name}.tmp-{}-{}",
+        process::id(),
+        NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written = File::create(&temporary_path).and_then(|mut file| {
+        file.write_all(contents.as_ref())?;
+        file.sync_all()
+    });
+
+    written
+        .and_then(|()| fs::rename(&temporary_path, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temporary_path);
+        })
+}
+
+/// Joins `path`, which uses `/` as the separator like every path inside the
+/// nest, onto `base` using the platform's own separator. That way
+/// `refs/heads/main` ends up as `refs\heads\main` on Windows.
+pub fn join_path(base: impl AsRef<Path>, path: &str) -> PathBuf {
+    let mut joined = base.as_ref().to_path_buf();
+    joined.extend(path.split('/').filter(|part| !part.is_empty()));
+
+    joined
+}
+
+/// Splits a command line like `code --wait "my file"` into the program and its
+/// arguments, the way a shell would, so that settings like `core.editor` can
+/// include arguments. Returns `None` if a quote is never closed.
+///
+/// Words are separated by whitespace. Inside single quotes everything is
+/// taken literally, and inside double quotes a backslash only escapes `"`,
+/// `\`, `$` and `` ` ``. Anywhere else, a backslash escapes whatever comes
+/// after it. We don't try to do anything with variables or globs.
+pub fn split_command_line(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    // Whether there's a word in progress, which isn't the same as `word`
+    // being non-empty, since `""` is an empty word.
+    let mut in_word = false;
+    let mut word = String::new();
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+
+                continue;
+            }
+            '\'' => loop {
+                match chars.next()? {
+                    '\'' => break,
+                    c => word.push(c),
+                }
+            },
+            '"' => loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                        c => {
+                            word.push('\\');
+                            word.push(c);
+                        }
+                    },
+                    c => word.push(c),
+                }
+            },
+            '\\' => word.push(chars.next()?),
+            c => word.push(c),
+        }
+
+        in_word = true;
+    }
+
+    if in_word {
+        words.push(word);
+    }
+
+    Some(words)
+}
+
+/// Checks whether `data` looks like the contents of a binary file rather than
+/// text. Like git, we take a file with a zero byte in it to be binary, since
+/// text practically never has one.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.contains(&0)
+}
+
+/// Gets the size of the file at `path` in bytes, or zero if it can't be
+/// read. Symlinks aren't followed, so a link's size is that of its target
+/// path.
+pub fn file_size(path: impl AsRef<Path>) -> u64 {
+    fs::symlink_metadata(path).map_or(0, |metadata| metadata.len())
+}
+
+/// Formats `bytes` with whichever unit keeps the number short.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} bytes");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Calls `f` on every item in `items`, spread across as many threads as there
+/// are cores, and returns the results in the same order as the items.
+///
+/// The items are handed out one at a time to whichever thread is free, so a
+/// few slow items, like huge files, don't hold up everything else.
+pub fn parallel_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(items.len());
+
+    // Starting threads isn't free, so there's no point for a single item.
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+
+                        let Some(item) = items.get(index) else {
+                            break done;
+                        };
+
+                        done.push((index, f(item)));
+                    }
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            // If a worker panicked, we carry on panicking here, just as if
+            // we'd done the work ourselves.
+            let done = worker.join().unwrap_or_else(|e| panic::resume_unwind(e));
+
+            for (index, result) in done {
+                results[index] = Some(result);
+            }
+        }
+    });
+
+    // Every index was handed out exactly once, so every result is there.
+    results.into_iter().flatten().collect()
+}
+
+/// When to color output meant for a terminal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when the output is going straight to a terminal, and the user
+    /// hasn't asked for no color with the `NO_COLOR` environment variable.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!("{s} isn't one of auto, always or never.")),
+        }
+    }
+}
+
+/// The colors output can be painted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Magenta,
+    Cyan,
+}
+
+/// How to format output meant for a terminal, as worked out by
+/// [`terminal_format`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TerminalFormat {
+    /// Whether to use colors, which are written as ANSI escape sequences that
+    /// only make sense to a terminal.
+    pub color: bool,
+}
+
+impl TerminalFormat {
+    /// Paints `text` with `color`, if we're using colors at all.
+    pub fn paint(&self, text: &str, color: Color) -> String {
+        if !self.color {
+            return text.to_string();
+        }
+
+        let code = match color {
+            Color::Red => 31,
+            Color::Green => 32,
+            Color::Yellow => 33,
+            Color::Magenta => 35,
+            Color::Cyan => 36,
+        };
+
+        // The escape sequence at the end resets the color, so that it doesn't
+        // leak into whatever comes next.
+        format!("\x1b[{code}m{text}\x1b[0m")
+    }
+}
+
+/// Works out how to format output going to standard output, given the user's
+/// `choice` of when to use colors.
+///
+/// Escape sequences are just noise in a file or another program's input, so
+/// by default we only use colors when standard output is a terminal. Following
+/// <https://no-color.org>, setting `NO_COLOR` to anything turns them off too,
+/// unless they're asked for explicitly, and so does a terminal that says it's
+/// too dumb for them.
+pub fn terminal_format(choice: ColorChoice) -> TerminalFormat {
+    let color = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            io::stdout().is_terminal()
+                && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && env::var_os("TERM").is_none_or(|term| term != "dumb")
+        }
+    };
+
+    TerminalFormat { color }
+}
+
+/// The round constants used by SHA-256, which are the first 32 bits of the
+/// fractional parts of the cube roots of the first 64 primes.
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// A minimal implementation of the SHA-256 hash function, as described in
+/// [FIPS 180-4](https://csrc.nist.gov/publications/detail/fips/180/4/final).
+///
+/// Data can be fed in with any number of calls to [`Sha256::update`], and the
+/// final hash is produced by [`Sha256::finalize`].
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// Data that hasn't been processed yet, since SHA-256 works on 64-byte
+    /// blocks at a time.
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self {
+            // The first 32 bits of the fractional parts of the square roots of
+            // the first 8 primes.
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds more data into the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+
+        // Top up any partially filled block from a previous call first.
+        if !self.buffer.is_empty() {
+            let needed = (64 - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..needed]);
+            data = &data[needed..];
+
+            if self.buffer.len() < 64 {
+                return;
+            }
+
+            let block = std::mem::take(&mut self.buffer);
+            self.process_block(&block);
+        }
+
+        let mut blocks = data.chunks_exact(64);
+
+        for block in &mut blocks {
+            self.process_block(block);
+        }
+
+        self.buffer.extend_from_slice(blocks.remainder());
+    }
+
+    /// Finishes the hash, returning the 32-byte digest.
+    pub fn finalize(mut self) -> [u8; 32] {
+        let bit_length = self.length.wrapping_mul(8);
+
+        // The message is padded with a single 1 bit, then enough zeroes to
+        // leave exactly 8 bytes at the end of the final block, which hold the
+        // length of the message in bits.
+        let mut padding = vec![0x80];
+        let padded_length = (self.buffer.len() + 1 + 8).div_ceil(64) * 64;
+        padding.resize(padded_length - self.buffer.len() - 8, 0);
+        padding.extend_from_slice(&bit_length.to_be_bytes());
+
+        // We don't want update to count the padding as part of the length, but
+        // we've already captured it above, so that doesn't matter anymore.
+        self.update(&padding);
+
+        let mut digest = [0; 32];
+
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
 
-    for dir_entry_result in dir_entries {
-        let dir_entry = dir_entry_result?;
+        digest
+    }
 
-        let entry_name = dir_entry.file_name();
+    fn process_block(&mut self, block: &[u8]) {
+        let mut schedule = [0u32; 64];
 
-        // Remember that ignore is not an array of Paths, it's an array of types
-        // that implement AsRef<Path>, so using the contains method directly
-        // here doesn't work.
-        if ignore
-            .iter()
-            .any(|ignore_path| ignore_path.as_ref() == entry_name)
-        {
-            continue;
+        for (word, bytes) in schedule.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
         }
 
-        let final_destination = to.as_ref().join(entry_name);
+        for i in 16..64 {
+            let s0 = schedule[i - 15].rotate_right(7)
+                ^ schedule[i - 15].rotate_right(18)
+                ^ (schedule[i - 15] >> 3);
+            let s1 = schedule[i - 2].rotate_right(17)
+                ^ schedule[i - 2].rotate_right(19)
+                ^ (schedule[i - 2] >> 10);
 
-        // We only check for files, and assume that anything that's not a file
-        // is a directory. For our purposes, symlinks don't exist, because they
-        // introduce a ton of complexity that isn't really relevant.
-        if dir_entry.file_type()?.is_file() {
-            fs::copy(dir_entry.path(), final_destination)?;
-        } else {
-            copy_dir_deep(dir_entry.path(), final_destination, ignore)?;
+            schedule[i] = schedule[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(schedule[i]);
+
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
         }
     }
+}
+
+/// Lets data be fed into the hash by anything that writes, like `io::copy`.
+impl io::Write for Sha256 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Parses a size like `512`, `64k` or `1m`, in bytes, the way settings
+/// that take a size spell it.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim().to_ascii_lowercase();
+
+    let (number, multiplier) = match size.as_bytes().last()? {
+        b'k' => (&size[..size.len() - 1], 1 << 10),
+        b'm' => (&size[..size.len() - 1], 1 << 20),
+        b'g' => (&size[..size.len() - 1], 1 << 30),
+        _ => (size.as_str(), 1),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Encodes `bytes` as a lowercase hexadecimal string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Decodes a hexadecimal string back into bytes, or `None` if it isn't one.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Finds the offset of the local timezone from UTC, in minutes.
+///
+/// The standard library doesn't know anything about timezones, and working it
+/// out ourselves means parsing the system's timezone database. Instead, we ask
+/// the `date` command, which is available on practically every Unix system. If
+/// that doesn't work, we fall back to UTC.
+pub fn local_utc_offset() -> i32 {
+    std::process::Command::new("date")
+        .arg("+%z")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|offset| parse_utc_offset(offset.trim()))
+        .unwrap_or(0)
+}
+
+/// Parses a timezone offset like `+0530` or `-0800` into minutes.
+pub fn parse_utc_offset(offset: &str) -> Option<i32> {
+    let (sign, digits) = match offset.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Formats an offset in minutes as `+hhmm` or `-hhmm`.
+pub fn format_utc_offset(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+
+    format!("{sign}{:02}{:02}", offset / 60, offset % 60)
+}
+
+/// Formats a Unix timestamp, shifted into the timezone `utc_offset` minutes
+/// away from UTC, like `Thu Nov 14 22:13:20 2023 +0100`.
+pub fn format_timestamp(timestamp: i64, utc_offset: i32) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let local = timestamp + i64::from(utc_offset) * 60;
 
-    Ok(())
+    let days = local.div_euclid(86400);
+    let seconds_of_day = local.rem_euclid(86400);
+
+    // The epoch was a Thursday, which is why the weekdays above start there.
+    let weekday = WEEKDAYS[days.rem_euclid(7) as usize];
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{weekday} {} {day} {:02}:{:02}:{:02} {year} {}",
+        MONTHS[month as usize - 1],
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        format_utc_offset(utc_offset)
+    )
+}
+
+/// Formats just the day of a Unix timestamp, shifted into the timezone
+/// `utc_offset` minutes away from UTC, like `2023-11-14`.
+pub fn format_short_date(timestamp: i64, utc_offset: i32) -> String {
+    let local = timestamp + i64::from(utc_offset) * 60;
+    let (year, month, day) = civil_from_days(local.div_euclid(86400));
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Parses a date given on the command line into a Unix timestamp, for options
+/// like `rat log --since`. Dates can be written as:
+///
+/// - `2023-11-14`, which means midnight at the start of that day,
+/// - `2023-11-14 22:13:20`, or the same with a `T` in the middle,
+/// - `3 days ago`, or any other number of seconds, minutes, hours, days,
+///   weeks, months or years,
+/// - `@1700000000`, which is a Unix timestamp itself.
+///
+/// Dates without a timezone are in the local one, `utc_offset` minutes away
+/// from UTC, and relative ones count back from `now`.
+pub fn parse_date(date: &str, now: i64, utc_offset: i32) -> Option<i64> {
+    let date = date.trim();
+
+    if let Some(timestamp) = date.strip_prefix('@') {
+        return timestamp.parse().ok();
+    }
+
+    if let Some(amount) = date.strip_suffix(" ago") {
+        let (count, unit) = amount.trim().split_once(' ')?;
+        let count: i64 = count.parse().ok()?;
+
+        // Months and years aren't all the same length, so like git we just
+        // use their average.
+        let seconds = match unit.trim().trim_end_matches('s') {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            "month" => 30 * 24 * 60 * 60,
+            "year" => 365 * 24 * 60 * 60,
+            _ => return None,
+        };
+
+        return Some(now - count.checked_mul(seconds)?);
+    }
+
+    let (day, time) = match date.split_once([' ', 'T']) {
+        Some((day, time)) => (day, Some(time)),
+        None => (date, None),
+    };
+
+    let mut parts = day.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let seconds_of_day = match time {
+        Some(time) => {
+            let mut parts = time.splitn(3, ':');
+            let hours: i64 = parts.next()?.parse().ok()?;
+            let minutes: i64 = parts.next()?.parse().ok()?;
+            let seconds: i64 = parts.next().map_or(Some(0), |s| s.parse().ok())?;
+
+            if hours > 23 || minutes > 59 || seconds > 60 {
+                return None;
+            }
+
+            hours * 3600 + minutes * 60 + seconds
+        }
+        None => 0,
+    };
+
+    let local = days_from_civil(year, month, day) * 86400 + seconds_of_day;
+
+    Some(local - i64::from(utc_offset) * 60)
+}
+
+/// Converts a year, month, and day into a number of days since the Unix
+/// epoch, the opposite of [`civil_from_days`], using the same algorithm.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Converts a number of days since the Unix epoch into a year, month, and day
+/// in the proleptic Gregorian calendar. This is Howard Hinnant's algorithm
+/// from <https://howardhinnant.github.io/date_algorithms.html>.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    // Shift the epoch to March 1st of year 0, so that leap days fall at the
+    // very end of each year, then split into 400-year eras.
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256(data: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        to_hex(&hasher.finalize())
+    }
+
+    #[test]
+    fn sha256_matches_known_hashes() {
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Long enough that the padding needs a block of its own.
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn sha256_gives_the_same_hash_however_the_data_is_split() {
+        let data: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+        let mut hasher = Sha256::new();
+
+        for piece in data.chunks(37) {
+            hasher.update(piece);
+        }
+
+        assert_eq!(to_hex(&hasher.finalize()), sha256(&data));
+        assert_eq!(
+            sha256(&data),
+            "69dbee893909fa17d1be397e0c07691336fe42049c29d403467d3d4a1fc3b5a1"
+        );
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let bytes = [0x00, 0x7f, 0x80, 0xff];
+
+        assert_eq!(to_hex(&bytes), "007f80ff");
+        assert_eq!(from_hex("007f80ff").as_deref(), Some(&bytes[..]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
 }
@@ -0,0 +1,460 @@
+//! Keeping the working directory and the index in step with snapshots.
+//!
+//! Checking out, resetting, merging and friends all come down to the same
+//! thing: making the files on disk and the index match a snapshot, without
+//! losing anything the user hasn't committed yet. These are the pieces they
+//! share.
+
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::compare::{self, Change, Entry, FileMode, Snapshot};
+use crate::config::Config;
+use crate::error::{CheckoutError, ObjectError};
+use crate::index::Index;
+use crate::lfs;
+use crate::objects::{self, ObjectKind};
+use crate::{logging, progress, submodules, utils};
+
+/// Works out whether symlinks should be kept as links, which is what the
+/// `core.symlinks` setting controls.
+///
+/// When it's turned off, rat follows symlinks in the working directory and
+/// records whatever they point to instead, and checks symlinks out as plain
+/// files holding their target. That's for platforms like Windows, where
+/// making a symlink usually needs special permissions, so it's off there
+/// unless it's been turned on.
+pub fn symlinks_enabled() -> bool {
+    // Every file we look at needs to know, so we only read the config once.
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+
+    *ENABLED.get_or_init(|| {
+        Config::load()
+            .ok()
+            .and_then(|config| config.get("core.symlinks").map(str::to_string))
+            .map_or(cfg!(unix), |value| value != "false")
+    })
+}
+
+/// Hashes the file at `path` in the working directory as it would be stored,
+/// without storing it.
+pub fn hash_working_file(path: &str) -> io::Result<Entry> {
+    if let Some(entry) = nest_entry(path)? {
+        return Ok(entry);
+    }
+
+    match read_symlink(path)? {
+        Some(target) => Ok(Entry {
+            mode: FileMode::Symlink,
+            hash: objects::hash_object(ObjectKind::Blob, target.as_bytes()),
+        }),
+        None if kept_out_of_nest(path)? => Ok(Entry {
+            mode: file_mode(path)?,
+            hash: objects::hash_object(ObjectKind::Blob, &lfs::pointer_for(path)?.to_bytes()),
+        }),
+        None => Ok(Entry {
+            mode: file_mode(path)?,
+            hash: objects::hash_file(Path::new(path))?,
+        }),
+    }
+}
+
+/// Reads the file at `path` in the working directory as it would be stored,
+/// which for a symlink means the path it points to, and for a file kept out
+/// of the nest means its pointer.
+pub fn read_working_file(path: &str) -> io::Result<Vec<u8>> {
+    match read_symlink(path)? {
+        Some(target) => Ok(target.into_bytes()),
+        None if kept_out_of_nest(path)? => Ok(lfs::pointer_for(path)?.to_bytes()),
+        None => fs::read(path),
+    }
+}
+
+/// Checks whether the ordinary file at `path` should be kept out of the nest,
+/// with only a pointer to it stored. See the [`lfs`] module.
+fn kept_out_of_nest(path: &str) -> io::Result<bool> {
+    Ok(lfs::applies_to(path, fs::metadata(path)?.len()))
+}
+
+/// Makes the entry for the nest at `path` in the working directory, pointing
+/// at the commit it's on, if that's what's there.
+fn nest_entry(path: &str) -> io::Result<Option<Entry>> {
+    if !submodules::is_nest(path) {
+        return Ok(None);
+    }
+
+    let hash = submodules::head(path)?.ok_or_else(|| {
+        io::Error::other(format!("The nest in {path} doesn't have any commits yet."))
+    })?;
+
+    Ok(Some(Entry {
+        mode: FileMode::Nest,
+        hash,
+    }))
+}
+
+/// Stores the file at `path` in the working directory as a blob. Another nest
+/// isn't stored at all, since all we keep of it is the commit it's on.
+pub fn store_working_file(path: &str) -> Result<Entry, ObjectError> {
+    if let Some(entry) = nest_entry(path).map_err(|source| ObjectError::Io {
+        path: path.into(),
+        source,
+    })? {
+        return Ok(entry);
+    }
+
+    let target = read_symlink(path).map_err(|source| ObjectError::Io {
+        path: path.into(),
+        source,
+    })?;
+
+    let io_error = |source| ObjectError::Io {
+        path: path.into(),
+        source,
+    };
+
+    match target {
+        Some(target) => Ok(Entry {
+            mode: FileMode::Symlink,
+            hash: objects::write_object(ObjectKind::Blob, target.as_bytes())?,
+        }),
+        None if kept_out_of_nest(path).map_err(io_error)? => Ok(Entry {
+            mode: file_mode(path).map_err(io_error)?,
+            hash: objects::write_object(
+                ObjectKind::Blob,
+                &lfs::store(path).map_err(io_error)?.to_bytes(),
+            )?,
+        }),
+        None => Ok(Entry {
+            mode: file_mode(path).map_err(io_error)?,
+            hash: objects::write_file(Path::new(path))?,
+        }),
+    }
+}
+
+/// Platforms without an executable bit make every file look ordinary, so
+/// rather than losing the bit on files that had it, we keep the mode they were
+/// `tracked` with.
+pub fn keep_tracked_mode(entry: &mut Entry, tracked: Option<&Entry>) {
+    if cfg!(unix) || entry.mode != FileMode::Regular {
+        return;
+    }
+
+    if let Some(tracked) = tracked.filter(|tracked| tracked.mode == FileMode::Executable) {
+        entry.mode = tracked.mode;
+    }
+}
+
+/// Works out the mode of the ordinary file at `path`.
+fn file_mode(path: &str) -> io::Result<FileMode> {
+    Ok(mode_of(&fs::metadata(path)?))
+}
+
+/// Works out whether an ordinary file with `metadata` is executable, which on
+/// Unix means anyone at all can run it, just like git decides.
+#[cfg(unix)]
+pub fn mode_of(metadata: &Metadata) -> FileMode {
+    use std::os::unix::fs::PermissionsExt;
+
+    if metadata.permissions().mode() & 0o111 != 0 {
+        FileMode::Executable
+    } else {
+        FileMode::Regular
+    }
+}
+
+/// Other platforms don't have an executable bit, so every file is ordinary.
+#[cfg(not(unix))]
+pub fn mode_of(_metadata: &Metadata) -> FileMode {
+    FileMode::Regular
+}
+
+/// Makes the file at `path` executable by anyone who can read it, or by nobody,
+/// depending on `mode`.
+#[cfg(unix)]
+fn set_file_mode(path: &str, mode: FileMode) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = fs::metadata(path)?.permissions();
+    let current = permissions.mode();
+
+    let new = match mode {
+        // Each read bit is two places to the left of its executable bit.
+        FileMode::Executable => current | ((current & 0o444) >> 2),
+        _ => current & !0o111,
+    };
+
+    if new != current {
+        permissions.set_mode(new);
+        fs::set_permissions(path, permissions)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(_path: &str, _mode: FileMode) -> io::Result<()> {
+    Ok(())
+}
+
+/// Reads where the file at `path` points to if it's a symlink we should keep
+/// as one, or returns `None` if it should be treated as an ordinary file.
+fn read_symlink(path: &str) -> io::Result<Option<String>> {
+    if !fs::symlink_metadata(path)?.is_symlink() {
+        return Ok(None);
+    }
+
+    // Following a link to a directory could lead us in circles, so those are
+    // always kept as links, as are links that point nowhere.
+    if !symlinks_enabled() && Path::new(path).is_file() {
+        return Ok(None);
+    }
+
+    Ok(Some(fs::read_link(path)?.to_string_lossy().into_owned()))
+}
+
+/// Checks whether the index or any tracked file in the working directory differ
+/// from the last commit. Untracked files don't count, since nothing rat does
+/// to the working directory ever touches them.
+pub fn has_uncommitted_changes(
+    head_snapshot: &Snapshot,
+    index_snapshot: &Snapshot,
+    working_snapshot: &Snapshot,
+) -> bool {
+    let has_staged_changes = !compare::compare(head_snapshot, index_snapshot).is_empty();
+    let has_unstaged_changes = compare::compare(index_snapshot, working_snapshot)
+        .values()
+        .any(|change| *change != Change::Added);
+
+    has_staged_changes || has_unstaged_changes
+}
+
+/// Makes sure that restoring `target_snapshot` wouldn't overwrite any untracked
+/// file, which is the one time we'd otherwise touch one. `action` describes
+/// what we're doing for the error message, like `checking out`.
+pub fn check_untracked_files(
+    index_snapshot: &Snapshot,
+    working_snapshot: &Snapshot,
+    target_snapshot: &Snapshot,
+    action: &'static str,
+) -> Result<(), CheckoutError> {
+    for (path, entry) in target_snapshot {
+        if !index_snapshot.contains_key(path)
+            && working_snapshot.get(path).is_some_and(|e| e != entry)
+        {
+            Err(CheckoutError::WouldOverwrite {
+                action,
+                path: path.clone(),
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The files [`restore_snapshot`] changes in the working directory.
+#[derive(Debug, Default)]
+pub struct RestorePlan {
+    /// Files that don't exist yet and will be written.
+    pub created: Vec<String>,
+    /// Files that exist with different contents or modes, and will be
+    /// replaced.
+    pub overwritten: Vec<String>,
+    /// Tracked files that aren't in the target, and will be deleted.
+    pub removed: Vec<String>,
+}
+
+impl RestorePlan {
+    /// Checks whether nothing in the working directory would change.
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.overwritten.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Works out which files [`restore_snapshot`] would change, given the same
+/// snapshots, without touching any of them.
+pub fn plan_restore(
+    tracked: &Snapshot,
+    working_snapshot: &Snapshot,
+    target_snapshot: &Snapshot,
+) -> RestorePlan {
+    let mut plan = RestorePlan::default();
+
+    for (path, entry) in target_snapshot {
+        match working_snapshot.get(path) {
+            Some(working) if working == entry => {}
+            Some(_) => plan.overwritten.push(path.clone()),
+            None => plan.created.push(path.clone()),
+        }
+    }
+
+    // Anything we're tracking right now that doesn't exist in the target has to
+    // go, otherwise the working directory wouldn't match the snapshot.
+    plan.removed = tracked
+        .keys()
+        .filter(|path| !target_snapshot.contains_key(*path))
+        .cloned()
+        .collect();
+
+    plan
+}
+
+/// Makes the working directory and the index match `target_snapshot`.
+///
+/// `tracked` is what the index currently contains and `working_snapshot` is
+/// what the working directory currently contains. Files that already have the
+/// right contents are left alone, and untracked files are never removed.
+/// [`plan_restore`] says exactly which files will change beforehand.
+pub fn restore_snapshot(
+    tracked: &Snapshot,
+    working_snapshot: &Snapshot,
+    target_snapshot: Snapshot,
+) -> Result<(), CheckoutError> {
+    let plan = plan_restore(tracked, working_snapshot, &target_snapshot);
+    let written: Vec<&String> = plan.created.iter().chain(&plan.overwritten).collect();
+
+    // Removing files first makes room for directories of the same name, and
+    // the other way around, since a file and a directory can't share a path.
+    for path in &plan.removed {
+        logging::verbose(format_args!("Removed {path}"));
+        remove_working_file(path)?;
+    }
+
+    let task = progress::start("Checking out files", written.len());
+
+    for path in written {
+        logging::verbose(format_args!("Wrote {path}"));
+        write_working_file(path, &target_snapshot[path])?;
+        task.advance(utils::file_size(path));
+    }
+
+    drop(task);
+
+    // The index should now describe exactly what we just restored, so that the
+    // next commit starts from there.
+    Index {
+        entries: target_snapshot,
+    }
+    .write(crate::nest_path("index"))?;
+
+    Ok(())
+}
+
+/// Writes `entry` into the working directory at `path`, creating any
+/// directories it needs.
+///
+/// Another nest only gets an empty directory to go in, if it isn't there
+/// already, since its files come from cloning it with `rat submodule update`.
+pub fn write_working_file(path: &str, entry: &Entry) -> Result<(), CheckoutError> {
+    if entry.mode == FileMode::Nest {
+        fs::create_dir_all(path)?;
+        return Ok(());
+    }
+
+    // A symlink where we need a directory would have us write through it to
+    // wherever it points, which could be outside the working directory, so it
+    // has to make way for a real directory first.
+    let ancestors: Vec<&Path> = Path::new(path).ancestors().skip(1).collect();
+
+    for ancestor in ancestors.into_iter().rev() {
+        if fs::symlink_metadata(ancestor).is_ok_and(|metadata| metadata.is_symlink()) {
+            fs::remove_file(ancestor)?;
+        }
+    }
+
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let data = objects::read_object_of_kind(&entry.hash, ObjectKind::Blob)?;
+    let is_symlink = entry.mode == FileMode::Symlink && symlinks_enabled();
+
+    // Writing to a symlink would write to whatever it points to instead, and
+    // a symlink can't be made over the top of an existing file, so either way
+    // the old one has to go first.
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_symlink() || is_symlink => fs::remove_file(path)?,
+        _ => {}
+    }
+
+    if is_symlink {
+        create_symlink(&String::from_utf8_lossy(&data), path)?;
+    } else {
+        // A pointer to a file kept out of the nest gets replaced by the real
+        // contents, as long as we have them. Otherwise the pointer is the
+        // best we can do.
+        match lfs::Pointer::parse(&data) {
+            Some(pointer) if pointer.has_content() => {
+                fs::copy(pointer.content_path(), path)?;
+            }
+            Some(_) => {
+                logging::warning(format_args!(
+                    "The contents of {path} aren't in the nest, so it's been left as a pointer."
+                ));
+                fs::write(path, data)?;
+            }
+            None => fs::write(path, data)?,
+        }
+
+        // Writing to an existing file keeps its permissions, so it might need
+        // its executable bit adding or taking away either way.
+        set_file_mode(path, entry.mode)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &str) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &str, path: &str) -> io::Result<()> {
+    // Windows needs to know up front whether the link is to a directory.
+    if Path::new(path).with_file_name(target).is_dir() {
+        std::os::windows::fs::symlink_dir(target, path)
+    } else {
+        std::os::windows::fs::symlink_file(target, path)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(target: &str, path: &str) -> io::Result<()> {
+    // Without symlinks, the next best thing is what core.symlinks = false
+    // would have given us.
+    fs::write(path, target)
+}
+
+/// Deletes the file at `path` from the working directory, along with any of
+/// its parent directories that are left empty as a result. Rat only tracks
+/// files, so an empty directory would otherwise just linger forever.
+pub fn remove_working_file(path: &str) -> Result<(), CheckoutError> {
+    match fs::remove_file(path) {
+        Ok(()) => {}
+        // If it's already gone, that's exactly what we wanted anyway.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        // Another nest has history of its own that isn't ours to throw away,
+        // so it's left where it is.
+        Err(_) if submodules::is_nest(path) => return Ok(()),
+        Err(e) => Err(e)?,
+    }
+
+    for parent in Path::new(path).ancestors().skip(1) {
+        // The last ancestor is the empty path, which is the root of the nest
+        // itself, and we never want to remove that.
+        if parent.as_os_str().is_empty() {
+            break;
+        }
+
+        // remove_dir only succeeds on empty directories, so we stop at the
+        // first one that still has something in it.
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
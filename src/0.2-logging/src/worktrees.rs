@@ -0,0 +1,329 @@
+//! Working on several branches at once, each in a directory of its own.
+//!
+//! Switching branches means stashing or committing whatever's half done, which
+//! gets old quickly when a fix is needed on one branch in the middle of work
+//! on another. Instead, a nest can have more than one working directory, all
+//! sharing the same objects, branches and config, but each with its own
+//! `HEAD`, index and operation in progress.
+//!
+//! The working directory the nest was made in is the main one. Each linked
+//! one made by `rat worktree add` has a directory of its own files in the
+//! nest, `.rat/worktrees/<name>/`, holding:
+//!
+//! - `HEAD`, the index and everything else from [`is_own_file`].
+//! - `commondir`, the path of the nest it shares everything else with.
+//! - `workdir`, the path of the working directory itself.
+//!
+//! In place of a `.rat` directory, the linked working directory has a `.rat`
+//! file pointing back at those, so that rat knows where to look:
+//!
+//! ```text
+//! nest: /home/me/thesis/.rat/worktrees/hotfix
+//! ```
+//!
+//! Having the same branch checked out in two places at once would leave one
+//! of them behind every time the other committed, so a branch can only be
+//! checked out in one of them at a time.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::compare::{self, Snapshot};
+use crate::error::RefError;
+use crate::index::Index;
+use crate::refs::{self, Head};
+use crate::{logging, progress, utils, worktree, RAT_NEST};
+
+/// A working directory of the nest.
+#[derive(Debug, Clone)]
+pub struct Worktree {
+    /// The name of its directory in `.rat/worktrees`, or `None` for the main
+    /// working directory.
+    pub name: Option<String>,
+    /// Where it is.
+    pub path: PathBuf,
+    /// What its `HEAD` points at.
+    pub head: Head,
+    /// The commit its `HEAD` resolves to, if there is one yet.
+    pub commit: Option<String>,
+    /// Whether it's the one we're in.
+    pub current: bool,
+    /// Whether its directory has gone missing, in which case it can be
+    /// removed without losing anything.
+    pub missing: bool,
+    /// Where its own files, like `HEAD`, are kept.
+    nest: PathBuf,
+}
+
+/// Checks whether the file `name` inside the nest belongs to one working
+/// directory, rather than being shared by all of them. That's the index and
+/// the cache that goes with it, along with `HEAD`, its reflog and every file
+/// about an operation in progress, which are the ones named in capitals.
+///
+/// The lock is the one exception, since it has to keep every working
+/// directory from changing the refs they share at the same time.
+pub fn is_own_file(name: &str) -> bool {
+    match name {
+        "index" | "cache" | "rebase" | "logs/HEAD" => true,
+        "LOCK" => false,
+        name => !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c == '_'),
+    }
+}
+
+/// Reads the `.rat` file of a linked working directory at `path`, returning
+/// the directory of its own files and the nest it shares everything else
+/// with, or `None` if it doesn't lead anywhere.
+pub fn read_link(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let own = PathBuf::from(
+        fs::read_to_string(path)
+            .ok()?
+            .trim()
+            .strip_prefix("nest: ")?,
+    );
+    let shared = PathBuf::from(fs::read_to_string(own.join("commondir")).ok()?.trim());
+
+    Some((own, shared))
+}
+
+/// Finds where the linked working directories keep their own files.
+fn worktrees_dir() -> PathBuf {
+    crate::nest_dir().join("worktrees")
+}
+
+/// Lists every working directory of the nest, starting with the main one.
+pub fn list() -> Result<Vec<Worktree>, RefError> {
+    let nest = crate::nest_dir();
+    let ours = crate::worktree_nest().unwrap_or(nest);
+
+    // The main working directory is the one the nest is in, unless the nest
+    // is bare, in which case there isn't one and we list the nest instead.
+    let main_path = match crate::is_bare(nest) {
+        true => nest.to_path_buf(),
+        false => nest.join(".."),
+    };
+
+    let mut worktrees = vec![read(None, nest, main_path, ours)?];
+
+    let entries = match fs::read_dir(worktrees_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(worktrees),
+        Err(e) => Err(e)?,
+    };
+
+    let mut linked = Vec::new();
+
+    for entry in entries {
+        let own = entry?.path();
+        let name = own
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let path = PathBuf::from(fs::read_to_string(own.join("workdir"))?.trim());
+
+        linked.push(read(Some(name), &own, path, ours)?);
+    }
+
+    linked.sort_by(|a, b| a.name.cmp(&b.name));
+    worktrees.extend(linked);
+
+    Ok(worktrees)
+}
+
+/// Reads what's checked out in the working directory at `path`, whose own
+/// files are in `own`.
+fn read(
+    name: Option<String>,
+    own: &Path,
+    path: PathBuf,
+    ours: &Path,
+) -> Result<Worktree, RefError> {
+    let head = refs::read_head_in(own)?;
+    let commit = match &head {
+        Head::Branch(branch) => refs::read_branch(branch)?,
+        Head::Detached(hash) => Some(hash.clone()),
+    };
+
+    Ok(Worktree {
+        name,
+        missing: !path.is_dir(),
+        path: fs::canonicalize(&path).unwrap_or(path),
+        current: same_file(own, ours),
+        head,
+        commit,
+        nest: own.to_path_buf(),
+    })
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Finds the working directory that has `branch` checked out, if any does.
+pub fn find_checkout(branch: &str) -> Result<Option<Worktree>, RefError> {
+    Ok(list()?
+        .into_iter()
+        .find(|worktree| worktree.head == Head::Branch(branch.to_string())))
+}
+
+/// Makes sure `branch` isn't checked out in any working directory other than
+/// the one we're in, so that it can be checked out here.
+pub fn ensure_free(branch: &str) -> Result<(), RefError> {
+    match find_checkout(branch)? {
+        Some(worktree) if !worktree.current => Err(RefError::CheckedOutElsewhere {
+            name: branch.to_string(),
+            path: worktree.path,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Makes a new working directory at `path` with `branch` checked out in it,
+/// which has to be somewhere new or empty.
+pub fn add(path: &Path, branch: &str) -> Result<Worktree, Box<dyn Error>> {
+    let commit = refs::read_branch(branch)?.ok_or_else(|| RefError::NotFound {
+        kind: "branch",
+        name: branch.to_string(),
+    })?;
+
+    if let Some(worktree) = find_checkout(branch)? {
+        Err(RefError::CheckedOutElsewhere {
+            name: branch.to_string(),
+            path: worktree.path,
+        })?;
+    }
+
+    if fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some()) {
+        Err(format!(
+            "{} already exists and isn't empty.",
+            path.display()
+        ))?;
+    }
+
+    fs::create_dir_all(path)?;
+    let path = fs::canonicalize(path)?;
+    let shared = fs::canonicalize(crate::nest_dir())?;
+
+    // The directory is named after the working directory, with a number on
+    // the end if that's taken already.
+    let base_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .filter(|name| refs::is_valid_name(name))
+        .unwrap_or_else(|| "worktree".to_string());
+
+    let mut name = base_name.clone();
+    let mut number = 1;
+
+    while worktrees_dir().join(&name).exists() {
+        name = format!("{base_name}{number}");
+        number += 1;
+    }
+
+    let own = shared.join("worktrees").join(&name);
+    fs::create_dir_all(&own)?;
+
+    utils::write_atomically(
+        own.join("HEAD"),
+        refs::format_head(&Head::Branch(branch.to_string())),
+    )?;
+    utils::write_atomically(own.join("commondir"), shared.to_string_lossy().as_bytes())?;
+    utils::write_atomically(own.join("workdir"), path.to_string_lossy().as_bytes())?;
+    utils::write_atomically(
+        path.join(RAT_NEST),
+        format!("nest: {}\n", own.to_string_lossy()),
+    )?;
+
+    let snapshot = compare::read_commit(Some(&commit))?;
+    let task = progress::start("Checking out files", snapshot.len());
+
+    for (file, entry) in &snapshot {
+        logging::verbose(format_args!("Wrote {file}"));
+
+        let full_path = utils::join_path(&path, file);
+        worktree::write_working_file(&full_path.to_string_lossy(), entry)?;
+        task.advance(utils::file_size(&full_path));
+    }
+
+    drop(task);
+
+    Index { entries: snapshot }.write(own.join("index"))?;
+
+    Ok(Worktree {
+        name: Some(name),
+        path,
+        head: Head::Branch(branch.to_string()),
+        commit: Some(commit),
+        current: false,
+        missing: false,
+        nest: own,
+    })
+}
+
+/// Removes the linked working directory at `path`, along with everything in
+/// it. Unless `force` is set, we refuse if it has changes that haven't been
+/// committed, since they'd be gone for good.
+pub fn remove(path: &Path, force: bool) -> Result<Worktree, Box<dyn Error>> {
+    let wanted = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    let worktree = list()?
+        .into_iter()
+        .find(|worktree| worktree.path == wanted || worktree.name.as_deref() == path.to_str())
+        .ok_or_else(|| format!("{} isn't a working directory of this nest.", path.display()))?;
+
+    if worktree.name.is_none() {
+        Err("The main working directory can't be removed.")?;
+    }
+
+    if worktree.current {
+        Err("Can't remove the working directory you're in.")?;
+    }
+
+    if !worktree.missing {
+        if !force && has_uncommitted_changes(&worktree)? {
+            Err(format!(
+                "{} has changes that haven't been committed. Use --force to remove it anyway.",
+                worktree.path.display()
+            ))?;
+        }
+
+        fs::remove_dir_all(&worktree.path)?;
+    }
+
+    fs::remove_dir_all(&worktree.nest)?;
+
+    Ok(worktree)
+}
+
+/// Checks whether the index or any tracked file of `worktree` differ from its
+/// last commit, the same way [`worktree::has_uncommitted_changes`] does for
+/// the one we're in.
+fn has_uncommitted_changes(worktree: &Worktree) -> Result<bool, Box<dyn Error>> {
+    let head_snapshot = compare::read_commit(worktree.commit.as_deref())?;
+    let index_snapshot = Index::read(worktree.nest.join("index"))?.entries;
+
+    // Files are hashed by their paths in the working directory, which need
+    // to be relative to it for things like .ratattributes to match them.
+    let started_in = env::current_dir()?;
+    env::set_current_dir(&worktree.path)?;
+
+    let working_snapshot: io::Result<Snapshot> = index_snapshot
+        .keys()
+        .filter(|file| fs::symlink_metadata(file).is_ok())
+        .map(|file| Ok((file.clone(), worktree::hash_working_file(file)?)))
+        .collect();
+
+    env::set_current_dir(started_in)?;
+
+    Ok(worktree::has_uncommitted_changes(
+        &head_snapshot,
+        &index_snapshot,
+        &working_snapshot?,
+    ))
+}
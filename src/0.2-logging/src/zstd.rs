@@ -0,0 +1,912 @@
+//! Zstandard, a newer compression format than [`deflate`](crate::deflate),
+//! which can look much further back for repeats and is quicker to
+//! decompress.
+//!
+//! A zstd frame is a small header followed by blocks of up to 128 KiB each.
+//! A block can be stored as it is, be a single byte repeated, or be
+//! compressed. A compressed block is split into its literals, which are the
+//! bytes that aren't part of a repeat, and its sequences, each of which says
+//! how many literals come next, and then how far back the repeat after them
+//! is and how long it is. The sequences are written with finite state
+//! entropy coding, a cousin of arithmetic coding that zstd uses in place of
+//! Huffman codes.
+//!
+//! Like our DEFLATE, we keep things simple by storing the literals as they
+//! are and always using the tables the format defines for the sequences,
+//! rather than working out the best ones for each block. Unlike DEFLATE,
+//! nothing but rat ever reads what we write here, so decompressing only
+//! understands as much of the format as we use. Anything else, like
+//! Huffman-coded literals, is treated as invalid.
+
+use std::sync::OnceLock;
+
+/// What every zstd frame starts with.
+const MAGIC: u32 = 0xfd2f_b528;
+
+/// The level we compress at unless asked otherwise, which is zstd's default
+/// too.
+pub const DEFAULT_LEVEL: u32 = 3;
+
+/// The highest level there is.
+pub const MAX_LEVEL: u32 = 19;
+
+/// The most a block can hold once it's decompressed.
+const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+/// How far back a repeat can be. The format allows much further, but looking
+/// further takes more memory.
+const WINDOW_SIZE: usize = 8 * 1024 * 1024;
+
+/// The shortest repeat worth writing.
+const MIN_MATCH: usize = 3;
+
+/// How many different hashes of three bytes there are.
+const HASH_SIZE: usize = 1 << 17;
+
+/// The first literal length for each code, and how many extra bits follow it
+/// to say exactly which length it is.
+const LITERAL_LENGTHS: [(u32, u8); 36] = [
+    (0, 0),
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 0),
+    (12, 0),
+    (13, 0),
+    (14, 0),
+    (15, 0),
+    (16, 1),
+    (18, 1),
+    (20, 1),
+    (22, 1),
+    (24, 2),
+    (28, 2),
+    (32, 3),
+    (40, 3),
+    (48, 4),
+    (64, 6),
+    (128, 7),
+    (256, 8),
+    (512, 9),
+    (1024, 10),
+    (2048, 11),
+    (4096, 12),
+    (8192, 13),
+    (16384, 14),
+    (32768, 15),
+    (65536, 16),
+];
+
+/// The same for match lengths.
+const MATCH_LENGTHS: [(u32, u8); 53] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 0),
+    (12, 0),
+    (13, 0),
+    (14, 0),
+    (15, 0),
+    (16, 0),
+    (17, 0),
+    (18, 0),
+    (19, 0),
+    (20, 0),
+    (21, 0),
+    (22, 0),
+    (23, 0),
+    (24, 0),
+    (25, 0),
+    (26, 0),
+    (27, 0),
+    (28, 0),
+    (29, 0),
+    (30, 0),
+    (31, 0),
+    (32, 0),
+    (33, 0),
+    (34, 0),
+    (35, 1),
+    (37, 1),
+    (39, 1),
+    (41, 1),
+    (43, 2),
+    (47, 2),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 5),
+    (131, 7),
+    (259, 8),
+    (515, 9),
+    (1027, 10),
+    (2051, 11),
+    (4099, 12),
+    (8195, 13),
+    (16387, 14),
+    (32771, 15),
+    (65539, 16),
+];
+
+/// The longest repeat a single sequence can describe.
+const MAX_MATCH: usize = 65539 + 0xffff;
+
+/// How often each literal length code is expected to come up, out of 64,
+/// which is what the format defines for when a block doesn't bring its own
+/// table. -1 means less than once.
+const LITERAL_LENGTH_DISTRIBUTION: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+
+/// The same for match length codes, out of 64.
+const MATCH_LENGTH_DISTRIBUTION: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+
+/// The same for offset codes, out of 32.
+const OFFSET_DISTRIBUTION: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+/// One state of a finite state entropy table. Reading a code in this state
+/// gives `symbol`, and the next state is `baseline` plus the next `bits`
+/// bits.
+#[derive(Debug, Clone, Copy, Default)]
+struct Cell {
+    symbol: u8,
+    bits: u8,
+    baseline: u16,
+}
+
+/// A finite state entropy table, with `1 << accuracy` states.
+#[derive(Debug)]
+struct Table {
+    accuracy: u32,
+    cells: Vec<Cell>,
+    /// For each symbol and each state we might need to get to next, the
+    /// state that gives that symbol and can get there. Only writing needs
+    /// this.
+    states: Vec<Vec<u16>>,
+}
+
+impl Table {
+    /// Builds the table for `distribution`, which says how many of the states
+    /// each symbol gets, in the way the format lays out.
+    fn new(distribution: &[i16], accuracy: u32) -> Self {
+        let size = 1 << accuracy;
+        let mut cells = vec![Cell::default(); size];
+
+        // Symbols that come up less than once get a state each at the end,
+        // and the rest are spread out over what's left.
+        let mut high = size - 1;
+
+        for (symbol, &count) in distribution.iter().enumerate() {
+            if count == -1 {
+                cells[high].symbol = symbol as u8;
+                high -= 1;
+            }
+        }
+
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut position = 0;
+
+        for (symbol, &count) in distribution.iter().enumerate() {
+            for _ in 0..count.max(0) {
+                cells[position].symbol = symbol as u8;
+
+                position = (position + step) & (size - 1);
+
+                while position > high {
+                    position = (position + step) & (size - 1);
+                }
+            }
+        }
+
+        // Each symbol's states share out the whole table between them, so
+        // that whichever state comes next, one of them can lead there.
+        let mut next: Vec<usize> = distribution
+            .iter()
+            .map(|&count| count.max(1) as usize)
+            .collect();
+
+        for cell in &mut cells {
+            let state = next[usize::from(cell.symbol)];
+            next[usize::from(cell.symbol)] += 1;
+
+            let bits = accuracy - state.ilog2();
+            cell.bits = bits as u8;
+            cell.baseline = ((state << bits) - size) as u16;
+        }
+
+        let mut states = vec![vec![0; size]; distribution.len()];
+
+        for (index, cell) in cells.iter().enumerate() {
+            let start = usize::from(cell.baseline);
+
+            for target in &mut states[usize::from(cell.symbol)][start..start + (1 << cell.bits)] {
+                *target = index as u16;
+            }
+        }
+
+        Self {
+            accuracy,
+            cells,
+            states,
+        }
+    }
+}
+
+fn literal_length_table() -> &'static Table {
+    static TABLE: OnceLock<Table> = OnceLock::new();
+    TABLE.get_or_init(|| Table::new(&LITERAL_LENGTH_DISTRIBUTION, 6))
+}
+
+fn match_length_table() -> &'static Table {
+    static TABLE: OnceLock<Table> = OnceLock::new();
+    TABLE.get_or_init(|| Table::new(&MATCH_LENGTH_DISTRIBUTION, 6))
+}
+
+fn offset_table() -> &'static Table {
+    static TABLE: OnceLock<Table> = OnceLock::new();
+    TABLE.get_or_init(|| Table::new(&OFFSET_DISTRIBUTION, 5))
+}
+
+/// Finds the code for `value` in one of the length tables, along with the
+/// extra bits that go with it and how many there are.
+fn length_code(bases: &[(u32, u8)], value: u32) -> (u8, u32, u8) {
+    // Each table is sorted, so the code we want is the last one that starts
+    // at or before the number we're writing.
+    let code = bases.partition_point(|&(base, _)| base <= value) - 1;
+    let (base, bits) = bases[code];
+
+    (code as u8, value - base, bits)
+}
+
+/// Finds the code for a repeat `distance` bytes back, along with its extra
+/// bits and how many there are. The three smallest codes mean a repeat at
+/// the same distance as a recent one, which we never use, so we skip past
+/// them.
+fn offset_code(distance: usize) -> (u8, u32, u8) {
+    let value = distance as u32 + 3;
+    let code = value.ilog2();
+
+    (code as u8, value - (1 << code), code as u8)
+}
+
+/// A repeat of `length` bytes from `distance` bytes back, after `literals`
+/// bytes that aren't part of any repeat.
+#[derive(Debug, Clone, Copy)]
+struct Sequence {
+    literals: usize,
+    distance: usize,
+    length: usize,
+}
+
+/// Writes bits into bytes starting from the lowest bit.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest `count` bits of `value`.
+    fn write(&mut self, value: u32, count: u8) {
+        self.bits |= u64::from(value) << self.count;
+        self.count += u32::from(count);
+
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Ends with a single set bit, so that reading backwards can tell where
+    /// the bits start.
+    fn finish(mut self) -> Vec<u8> {
+        self.write(1, 1);
+
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+
+        self.out
+    }
+}
+
+/// Where each hash of three bytes has been seen within the last
+/// [`WINDOW_SIZE`] bytes.
+struct Chains {
+    /// The last place each hash was seen, or `usize::MAX` if it hasn't been.
+    head: Vec<usize>,
+    /// For each place in the window, the place before it with the same hash.
+    previous: Vec<usize>,
+}
+
+impl Chains {
+    fn hash_at(data: &[u8], position: usize) -> usize {
+        let bytes = &data[position..position + MIN_MATCH];
+        let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+
+        (value.wrapping_mul(2_654_435_761) >> 15) as usize % HASH_SIZE
+    }
+
+    fn insert(&mut self, data: &[u8], position: usize) {
+        if position + MIN_MATCH <= data.len() {
+            let hash = Self::hash_at(data, position);
+            self.previous[position % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = position;
+        }
+    }
+
+    /// Finds the longest repeat of what's at `position`, no longer than
+    /// `longest`, trying at most `max_chain` earlier places.
+    fn find(
+        &self,
+        data: &[u8],
+        position: usize,
+        longest: usize,
+        max_chain: usize,
+    ) -> Option<(usize, usize)> {
+        if longest < MIN_MATCH {
+            return None;
+        }
+
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[Self::hash_at(data, position)];
+        let mut chain = 0;
+
+        while candidate != usize::MAX && position - candidate < WINDOW_SIZE && chain < max_chain {
+            let length = data[candidate..]
+                .iter()
+                .zip(&data[position..position + longest])
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            if length >= MIN_MATCH && best.is_none_or(|(best, _)| length > best) {
+                best = Some((length, position - candidate));
+
+                if length == longest {
+                    break;
+                }
+            }
+
+            candidate = self.previous[candidate % WINDOW_SIZE];
+            chain += 1;
+        }
+
+        best
+    }
+}
+
+/// Compresses `data` into a zstd frame at `level`, from 1 up to
+/// [`MAX_LEVEL`]. Higher levels look harder for repeats.
+pub fn compress(data: &[u8], level: u32) -> Vec<u8> {
+    let mut out = MAGIC.to_le_bytes().to_vec();
+
+    // The whole frame is one segment, so its size doubles as the size of
+    // the window, and takes up as few bytes as it can.
+    let size = data.len() as u64;
+    let (size_flag, size_bytes) = match size {
+        0..=0xff => (0, size.to_le_bytes()[..1].to_vec()),
+        0x100..=0x100ff => (1, (size - 0x100).to_le_bytes()[..2].to_vec()),
+        0x10100..=0xffff_ffff => (2, size.to_le_bytes()[..4].to_vec()),
+        _ => (3, size.to_le_bytes().to_vec()),
+    };
+
+    out.push(size_flag << 6 | 1 << 5);
+    out.extend(size_bytes);
+
+    let max_chain = 1 << (level.clamp(1, MAX_LEVEL) / 2 + 2);
+
+    let mut chains = Chains {
+        head: vec![usize::MAX; HASH_SIZE],
+        previous: vec![usize::MAX; data.len().min(WINDOW_SIZE)],
+    };
+
+    let mut block_start = 0;
+
+    // Even nothing at all needs a block to say so.
+    loop {
+        let block_end = (block_start + MAX_BLOCK_SIZE).min(data.len());
+        let last = block_end == data.len();
+
+        let mut sequences = Vec::new();
+        let mut literals = Vec::new();
+        let mut pending = 0;
+        let mut position = block_start;
+
+        while position < block_end {
+            let longest = (block_end - position).min(MAX_MATCH);
+
+            match chains.find(data, position, longest, max_chain) {
+                Some((length, distance)) => {
+                    sequences.push(Sequence {
+                        literals: pending,
+                        distance,
+                        length,
+                    });
+                    pending = 0;
+
+                    for skipped in position..position + length {
+                        chains.insert(data, skipped);
+                    }
+
+                    position += length;
+                }
+                None => {
+                    literals.push(data[position]);
+                    chains.insert(data, position);
+                    pending += 1;
+                    position += 1;
+                }
+            }
+        }
+
+        let block = &data[block_start..block_end];
+        let compressed = compress_block(&literals, &sequences);
+
+        // Compressing doesn't always help, in which case the block is better
+        // off as it is.
+        let (kind, contents) = match compressed.len() < block.len() {
+            true => (2, &compressed[..]),
+            false => (0, block),
+        };
+
+        let header = u32::from(last) | kind << 1 | (contents.len() as u32) << 3;
+        out.extend_from_slice(&header.to_le_bytes()[..3]);
+        out.extend_from_slice(contents);
+
+        if last {
+            return out;
+        }
+
+        block_start = block_end;
+    }
+}
+
+/// Writes the contents of a compressed block with `literals` and
+/// `sequences`.
+fn compress_block(literals: &[u8], sequences: &[Sequence]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    // The literals are stored as they are, after a header giving their
+    // length in as few bytes as it fits in.
+    let length = literals.len();
+
+    match length {
+        0..=31 => out.push((length << 3) as u8),
+        32..=4095 => out.extend_from_slice(&[(0b0100 | length << 4) as u8, (length >> 4) as u8]),
+        _ => out.extend_from_slice(&[
+            (0b1100 | length << 4) as u8,
+            (length >> 4) as u8,
+            (length >> 12) as u8,
+        ]),
+    }
+
+    out.extend_from_slice(literals);
+
+    let count = sequences.len();
+
+    match count {
+        0 => {
+            out.push(0);
+            return out;
+        }
+        1..=127 => out.push(count as u8),
+        128..=0x7eff => out.extend_from_slice(&[(count >> 8) as u8 + 128, count as u8]),
+        _ => {
+            out.push(255);
+            out.extend_from_slice(&((count - 0x7f00) as u16).to_le_bytes());
+        }
+    }
+
+    // Every table is the predefined one.
+    out.push(0);
+
+    let codes: Vec<_> = sequences
+        .iter()
+        .map(|sequence| {
+            (
+                length_code(&LITERAL_LENGTHS, sequence.literals as u32),
+                length_code(&MATCH_LENGTHS, sequence.length as u32),
+                offset_code(sequence.distance),
+            )
+        })
+        .collect();
+
+    let tables = [literal_length_table(), match_length_table(), offset_table()];
+
+    // The sequences are read backwards from the end, so we write them
+    // starting from the last one, and the states we end up in are where
+    // reading starts.
+    let mut writer = BitWriter {
+        out: Vec::new(),
+        bits: 0,
+        count: 0,
+    };
+
+    let (literal_length, match_length, offset) = codes[count - 1];
+    let mut states = [literal_length.0, match_length.0, offset.0]
+        .iter()
+        .zip(tables)
+        .map(|(&symbol, table)| table.states[usize::from(symbol)][0])
+        .collect::<Vec<_>>();
+
+    for (_, extra, bits) in [literal_length, match_length, offset] {
+        writer.write(extra, bits);
+    }
+
+    for &(literal_length, match_length, offset) in codes[..count - 1].iter().rev() {
+        let symbols = [literal_length.0, match_length.0, offset.0];
+
+        // States move on in the order offset, match length, literal length,
+        // which is the opposite of the order they're read in.
+        for which in [2, 1, 0] {
+            let table = tables[which];
+            let next = states[which];
+            let state = table.states[usize::from(symbols[which])][usize::from(next)];
+            let cell = table.cells[usize::from(state)];
+
+            writer.write(u32::from(next - cell.baseline), cell.bits);
+            states[which] = state;
+        }
+
+        for (_, extra, bits) in [literal_length, match_length, offset] {
+            writer.write(extra, bits);
+        }
+    }
+
+    for which in [1, 2, 0] {
+        writer.write(u32::from(states[which]), tables[which].accuracy as u8);
+    }
+
+    out.extend(writer.finish());
+
+    out
+}
+
+/// Reads bits backwards from the end of a block's sequences, which is how
+/// they were written.
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// How many bits are left to read.
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    /// Starts reading `data`, just below the set bit that marks where the
+    /// bits start.
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let last = *data.last()?;
+
+        if last == 0 {
+            return None;
+        }
+
+        Some(Self {
+            data,
+            position: (data.len() - 1) * 8 + last.ilog2() as usize,
+        })
+    }
+
+    fn read(&mut self, count: u32) -> Option<u32> {
+        let count = count as usize;
+
+        if count > self.position {
+            return None;
+        }
+
+        let mut value = 0;
+
+        for _ in 0..count {
+            self.position -= 1;
+            let bit = self.data[self.position / 8] >> (self.position % 8) & 1;
+            value = value << 1 | u32::from(bit);
+        }
+
+        Some(value)
+    }
+}
+
+/// Decompresses a zstd frame written by [`compress`], returning `None` if it
+/// isn't valid or uses parts of the format we don't.
+pub fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    if u32::from_le_bytes(data.get(..4)?.try_into().ok()?) != MAGIC {
+        return None;
+    }
+
+    let descriptor = *data.get(4)?;
+    let single_segment = descriptor & 0x20 != 0;
+    let has_checksum = descriptor & 0x04 != 0;
+
+    // Dictionaries are for compressing lots of small things the same way,
+    // which we never do.
+    if descriptor & 0x03 != 0 || descriptor & 0x08 != 0 {
+        return None;
+    }
+
+    let mut position = 5;
+
+    if !single_segment {
+        position += 1;
+    }
+
+    let size_length = match descriptor >> 6 {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+
+    let size = data.get(position..position + size_length)?;
+    let mut size_bytes = [0; 8];
+    size_bytes[..size_length].copy_from_slice(size);
+    let size = u64::from_le_bytes(size_bytes) + if size_length == 2 { 0x100 } else { 0 };
+    position += size_length;
+
+    let expected = (size_length > 0).then_some(size as usize);
+    let mut out = Vec::with_capacity(expected.unwrap_or(0).min(WINDOW_SIZE));
+
+    loop {
+        let header = data.get(position..position + 3)?;
+        let header = u32::from(header[0]) | u32::from(header[1]) << 8 | u32::from(header[2]) << 16;
+        let last = header & 1 != 0;
+        let length = (header >> 3) as usize;
+        position += 3;
+
+        position += match header >> 1 & 0b11 {
+            0 => {
+                out.extend_from_slice(data.get(position..position + length)?);
+                length
+            }
+            1 => {
+                // A repeated byte only stores the byte once, and the length
+                // is how many times it repeats.
+                out.resize(out.len() + length, *data.get(position)?);
+                1
+            }
+            2 => {
+                decompress_block(data.get(position..position + length)?, &mut out)?;
+                length
+            }
+            _ => return None,
+        };
+
+        if last {
+            break;
+        }
+    }
+
+    // We check the data's hash ourselves, so the checksum is just skipped.
+    if has_checksum {
+        data.get(position..position + 4)?;
+    }
+
+    match expected {
+        Some(expected) if expected != out.len() => None,
+        _ => Some(out),
+    }
+}
+
+/// Decompresses the contents of a compressed block onto the end of `out`.
+fn decompress_block(data: &[u8], out: &mut Vec<u8>) -> Option<()> {
+    let first = *data.first()?;
+
+    // Only literals stored as they are or as one repeated byte are
+    // understood.
+    let kind = first & 0b11;
+
+    if kind > 1 {
+        return None;
+    }
+
+    let (literal_count, mut position) = match first >> 2 & 0b11 {
+        0 | 2 => (usize::from(first >> 3), 1),
+        1 => (usize::from(first >> 4) | usize::from(*data.get(1)?) << 4, 2),
+        _ => (
+            usize::from(first >> 4)
+                | usize::from(*data.get(1)?) << 4
+                | usize::from(*data.get(2)?) << 12,
+            3,
+        ),
+    };
+
+    let literals = match kind {
+        0 => {
+            let literals = data.get(position..position + literal_count)?.to_vec();
+            position += literal_count;
+            literals
+        }
+        _ => {
+            let literals = vec![*data.get(position)?; literal_count];
+            position += 1;
+            literals
+        }
+    };
+
+    let count = match *data.get(position)? {
+        0 => {
+            out.extend_from_slice(&literals);
+            return Some(());
+        }
+        byte @ 1..=127 => {
+            position += 1;
+            usize::from(byte)
+        }
+        255 => {
+            let count = u16::from_le_bytes(data.get(position + 1..position + 3)?.try_into().ok()?);
+            position += 3;
+            usize::from(count) + 0x7f00
+        }
+        byte => {
+            let count = (usize::from(byte) - 128) << 8 | usize::from(*data.get(position + 1)?);
+            position += 2;
+            count
+        }
+    };
+
+    // Tables of the block's own aren't understood.
+    if *data.get(position)? != 0 {
+        return None;
+    }
+
+    let mut reader = BitReader::new(data.get(position + 1..)?)?;
+    let tables = [literal_length_table(), offset_table(), match_length_table()];
+    let mut states = [0; 3];
+
+    for (state, table) in states.iter_mut().zip(tables) {
+        *state = reader.read(table.accuracy)? as usize;
+    }
+
+    let mut used = 0;
+
+    for i in 0..count {
+        let cells = [0, 1, 2].map(|which| tables[which].cells[states[which]]);
+        let [literal_length, offset, match_length] = cells.map(|cell| cell.symbol);
+
+        let offset = u32::from(offset);
+        let (offset_base, offset_bits) = (1_u32.checked_shl(offset)?, offset);
+        let offset_value = offset_base + reader.read(offset_bits)?;
+
+        let (base, bits) = *MATCH_LENGTHS.get(usize::from(match_length))?;
+        let length = (base + reader.read(u32::from(bits))?) as usize;
+
+        let (base, bits) = *LITERAL_LENGTHS.get(usize::from(literal_length))?;
+        let literal_length = (base + reader.read(u32::from(bits))?) as usize;
+
+        // Repeating a recent distance is never written.
+        if offset_value <= 3 {
+            return None;
+        }
+
+        let distance = offset_value as usize - 3;
+
+        out.extend_from_slice(literals.get(used..used + literal_length)?);
+        used += literal_length;
+
+        if distance > out.len() {
+            return None;
+        }
+
+        // The repeat can overlap what it's writing, so it has to be copied
+        // a byte at a time.
+        let start = out.len() - distance;
+
+        for i in start..start + length {
+            out.push(out[i]);
+        }
+
+        if i + 1 < count {
+            for which in [0, 2, 1] {
+                let cell = cells[which];
+                states[which] =
+                    usize::from(cell.baseline) + reader.read(u32::from(cell.bits))? as usize;
+            }
+        }
+    }
+
+    if reader.position != 0 {
+        return None;
+    }
+
+    out.extend_from_slice(literals.get(used..)?);
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decompresses_what_zstd_itself_writes() {
+        // Made by the zstd command, which stores literals this short as they
+        // are and uses the predefined tables for so few sequences.
+        let frame = [
+            0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x58, 0x65, 0x00, 0x00, 0x30, 0x68, 0x65, 0x6c, 0x6c,
+            0x6f, 0x20, 0x01, 0x00, 0x99, 0x4b, 0x11, 0x17, 0x5e, 0xae, 0x0d,
+        ];
+
+        assert_eq!(
+            decompress(&frame).as_deref(),
+            Some(&b"hello hello hello hello"[..])
+        );
+
+        let frame = [
+            0x28, 0xb5, 0x2f, 0xfd, 0x00, 0x68, 0xc5, 0x00, 0x00, 0x80, 0x61, 0x62, 0x63, 0x20,
+            0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x20, 0x02, 0x00,
+            0x2f, 0xe6, 0x5b, 0xb8, 0x21,
+        ];
+
+        assert_eq!(
+            decompress(&frame).as_deref(),
+            Some(&b"abcabcabcabc hello world hello world"[..])
+        );
+    }
+
+    #[test]
+    fn round_trips_at_every_level() {
+        let mut data = Vec::new();
+
+        for i in 0..500 {
+            data.extend_from_slice(format!("entry {} is {}\n", i % 23, i * 7).as_bytes());
+        }
+
+        for level in 1..=MAX_LEVEL {
+            assert_eq!(
+                decompress(&compress(&data, level)),
+                Some(data.clone()),
+                "level {level}"
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_more_than_one_block() {
+        // Bigger than a block, and with nothing to find, so that at least one
+        // block has to be stored as it is.
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..300_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+
+        assert_eq!(decompress(&compress(&data, DEFAULT_LEVEL)), Some(data));
+    }
+
+    #[test]
+    fn round_trips_repeated_and_empty_data() {
+        for data in [Vec::new(), vec![b'x'; 200_000]] {
+            let compressed = compress(&data, DEFAULT_LEVEL);
+
+            assert!(compressed.len() < 100);
+            assert_eq!(decompress(&compressed), Some(data));
+        }
+    }
+
+    #[test]
+    fn rejects_anything_that_isnt_a_frame() {
+        assert_eq!(decompress(b"blob 5\0hello"), None);
+        assert_eq!(decompress(&compress(b"hello", 1)[..6]), None);
+    }
+}